@@ -29,6 +29,8 @@ pub struct PluginSystemConfig {
     pub bundled_plugins_dir: Option<PathBuf>,
     /// Ignore filename used for path restrictions (for example `.squidignore`).
     pub ignore_file_name: String,
+    /// Offers plugins that declare `security.network` when true.
+    pub allow_network: bool,
 }
 
 impl Default for PluginSystemConfig {
@@ -43,6 +45,7 @@ impl Default for PluginSystemConfig {
             max_memory_mb: 128,
             bundled_plugins_dir: None,
             ignore_file_name: ".squidignore".to_string(),
+            allow_network: true,
         }
     }
 }
@@ -113,6 +116,7 @@ impl PluginManager {
 
         Ok(plugins
             .into_iter()
+            .filter(|plugin| self.config.allow_network || !plugin.security.network)
             .map(|plugin| PluginToolDefinition {
                 name: plugin.tool_name(),
                 description: plugin.description,
@@ -160,6 +164,14 @@ impl PluginManager {
                 .ok_or(format!("Plugin '{}' not found", plugin_id))?
         };
 
+        if metadata.security.network && !self.config.allow_network {
+            return Err(format!(
+                "Plugin '{}' needs network access, which is disabled",
+                plugin_id
+            )
+            .into());
+        }
+
         let input_validator = SchemaValidator::new(&metadata.input_schema)?;
         if let Err(errors) = input_validator.validate(input) {
             return Ok(json!({
@@ -89,10 +89,11 @@ impl PluginRegistry {
                         let is_bundled = self.is_bundled_dir(plugin_dir);
 
                         if let Some(existing) = plugins_map.get(&metadata.id) {
-                            if !metadata.is_global && !is_bundled {
-                            } else if metadata.is_global && !existing.is_global {
-                                continue;
-                            } else if is_bundled && (!existing.is_global || metadata.is_global) {
+                            let is_workspace = !metadata.is_global && !is_bundled;
+                            let skip_global = metadata.is_global && !existing.is_global;
+                            let skip_bundled =
+                                is_bundled && (!existing.is_global || metadata.is_global);
+                            if !is_workspace && (skip_global || skip_bundled) {
                                 continue;
                             }
                         }
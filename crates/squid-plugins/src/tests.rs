@@ -176,6 +176,7 @@ fn manager_initializes_and_executes_plugin_from_bundled_dir() {
         max_memory_mb: 64,
         bundled_plugins_dir: Some(bundled_dir),
         ignore_file_name: ".squidignore".to_string(),
+        allow_network: true,
     };
 
     let manager = PluginManager::new(Arc::new(config));
@@ -201,3 +202,45 @@ fn manager_initializes_and_executes_plugin_from_bundled_dir() {
 
     fs::remove_dir_all(&root).expect("cleanup temp tree");
 }
+
+#[test]
+fn manager_hides_network_plugins_when_network_is_disallowed() {
+    let root = create_temp_dir("manager_offline");
+    let bundled_dir = root.join("bundled_plugins");
+    fs::create_dir_all(&bundled_dir).expect("create bundled dir");
+
+    write_plugin(&bundled_dir, "echo", "echo plugin", "input.message");
+    write_plugin(&bundled_dir, "fetch", "fetch plugin", "input.message");
+    let metadata_path = bundled_dir.join("fetch_dir").join("plugin.json");
+    let mut metadata: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&metadata_path).expect("read metadata"))
+            .expect("parse metadata");
+    metadata["security"]["network"] = json!(true);
+    fs::write(&metadata_path, metadata.to_string()).expect("write metadata");
+
+    let config = PluginSystemConfig {
+        load_global: false,
+        load_workspace: false,
+        working_dir: root.clone(),
+        bundled_plugins_dir: Some(bundled_dir),
+        allow_network: false,
+        ..Default::default()
+    };
+    let manager = PluginManager::new(Arc::new(config));
+    manager.initialize().expect("initialize manager");
+
+    let tools = manager.get_plugin_tools().expect("get tools");
+    assert_eq!(tools.len(), 1);
+    assert_eq!(tools[0].name, "plugin:echo");
+
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("create tokio runtime");
+    let error = rt
+        .block_on(manager.execute_plugin_tool("plugin:fetch", &json!({ "message": "hello" })))
+        .expect_err("network plugin should be refused");
+    assert!(error.to_string().contains("network access"));
+
+    fs::remove_dir_all(&root).expect("cleanup temp tree");
+}
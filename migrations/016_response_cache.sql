@@ -0,0 +1,16 @@
+-- Migration 016: Response cache for identical ask/review requests
+-- Keyed by a SHA-256 hash of (model, system prompt, user message, tool schema).
+-- Stores the final response text and the token usage of the original request.
+
+CREATE TABLE IF NOT EXISTS response_cache (
+    cache_key TEXT PRIMARY KEY,
+    model TEXT NOT NULL,
+    response TEXT NOT NULL,
+    input_tokens INTEGER DEFAULT 0,
+    output_tokens INTEGER DEFAULT 0,
+    reasoning_tokens INTEGER DEFAULT 0,
+    cache_tokens INTEGER DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_response_cache_created_at ON response_cache(created_at);
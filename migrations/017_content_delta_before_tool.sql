@@ -0,0 +1,6 @@
+-- Incremental content split markers
+-- Version: 017
+-- Description: Stores only the content produced since the previous tool step instead of a
+-- cumulative snapshot. Older rows keep content_before_tool and are converted on read.
+
+ALTER TABLE thinking_steps ADD COLUMN content_delta_before_tool TEXT;
@@ -0,0 +1,6 @@
+-- Session working directory
+-- Version: 018
+-- Description: Lets each web session run tools in its own directory. NULL means the
+-- server's startup working directory.
+
+ALTER TABLE sessions ADD COLUMN working_dir TEXT;
@@ -0,0 +1,13 @@
+-- Message ordering and parentage
+-- Version: 019
+-- Description: Orders messages by an explicit position instead of their timestamp, which
+-- collides when two messages are saved in the same second. parent_message_id links a message
+-- to the one it answers or follows, for branching conversations.
+
+ALTER TABLE messages ADD COLUMN position INTEGER;
+ALTER TABLE messages ADD COLUMN parent_message_id INTEGER REFERENCES messages(id) ON DELETE SET NULL;
+
+-- Insertion order is the best record of the original order
+UPDATE messages SET position = rowid WHERE position IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_messages_session_position ON messages(session_id, position);
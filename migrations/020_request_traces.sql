@@ -0,0 +1,18 @@
+-- Migration 020: Captured provider requests for debugging
+-- Stores a redacted copy of each request sent to the model (messages, tool schemas, parameters)
+-- and a summary of the response. Only written when debug capture is enabled; rows expire after
+-- debug.trace_retention_days.
+
+CREATE TABLE IF NOT EXISTS request_traces (
+    id TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    message_id INTEGER REFERENCES messages(id) ON DELETE SET NULL,
+    model TEXT NOT NULL,
+    request TEXT NOT NULL,
+    response TEXT,
+    truncated INTEGER NOT NULL DEFAULT 0,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_request_traces_session ON request_traces(session_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_request_traces_created_at ON request_traces(created_at);
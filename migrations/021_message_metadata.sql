@@ -0,0 +1,6 @@
+-- Message metadata
+-- Version: 021
+-- Description: JSON details about how a message was generated, such as the reasoning
+-- settings sent to the model. NULL for messages saved before this migration.
+
+ALTER TABLE messages ADD COLUMN metadata TEXT;
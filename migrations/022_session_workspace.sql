@@ -0,0 +1,6 @@
+-- Session workspace
+-- Version: 022
+-- Description: Records which configured workspace a session belongs to, so the session
+-- list can be filtered per project. NULL means no workspace.
+
+ALTER TABLE sessions ADD COLUMN workspace TEXT;
@@ -0,0 +1,31 @@
+-- Source origin
+-- Version: 023
+-- Description: Records whether a source is an attachment, a RAG chunk or tool output, and
+-- removes sources repeated within a message. Builds on the content deduplication from 006.
+
+ALTER TABLE sources ADD COLUMN origin TEXT NOT NULL DEFAULT 'attachment';
+
+-- Assistant sources that no user message in the session attached came from RAG
+UPDATE sources
+SET origin = 'rag'
+WHERE message_id IN (SELECT id FROM messages WHERE role = 'assistant')
+  AND NOT EXISTS (
+      SELECT 1
+      FROM sources attached
+      JOIN messages user_message ON user_message.id = attached.message_id
+      JOIN messages answer ON answer.id = sources.message_id
+      WHERE user_message.session_id = answer.session_id
+        AND user_message.role = 'user'
+        AND attached.title = sources.title
+        AND attached.content_id IS sources.content_id
+        AND attached.content IS sources.content
+  );
+
+-- Keep one row per title and content within a message; the cleanup trigger from 006
+-- removes file contents no longer referenced
+DELETE FROM sources
+WHERE id NOT IN (
+    SELECT MIN(id)
+    FROM sources
+    GROUP BY message_id, title, content_id, content
+);
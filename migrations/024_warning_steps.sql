@@ -0,0 +1,35 @@
+-- Warning thinking steps
+-- Version: 024
+-- Description: Allows 'warning' thinking steps, which record tool limits hit during a turn.
+-- SQLite can't change a CHECK constraint, so the table is rebuilt with the same rows.
+
+CREATE TABLE thinking_steps_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    message_id INTEGER NOT NULL,
+    step_order INTEGER NOT NULL,
+    step_type TEXT NOT NULL CHECK(step_type IN ('reasoning', 'tool', 'warning')),
+    content TEXT,
+    tool_name TEXT,
+    tool_arguments TEXT,
+    tool_result TEXT,
+    tool_error TEXT,
+    created_at INTEGER NOT NULL,
+    content_before_tool TEXT,
+    content_delta_before_tool TEXT,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
+
+INSERT INTO thinking_steps_new (
+    id, message_id, step_order, step_type, content, tool_name, tool_arguments,
+    tool_result, tool_error, created_at, content_before_tool, content_delta_before_tool
+)
+SELECT
+    id, message_id, step_order, step_type, content, tool_name, tool_arguments,
+    tool_result, tool_error, created_at, content_before_tool, content_delta_before_tool
+FROM thinking_steps;
+
+DROP TABLE thinking_steps;
+ALTER TABLE thinking_steps_new RENAME TO thinking_steps;
+
+CREATE INDEX idx_thinking_steps_message_id ON thinking_steps(message_id);
+CREATE INDEX idx_thinking_steps_order ON thinking_steps(message_id, step_order);
@@ -0,0 +1,7 @@
+-- Session models
+-- Version: 025
+-- Description: Records every model that answered in a session (models_used, a JSON array in
+-- order of first use) and an optional model the session is locked to (locked_model).
+
+ALTER TABLE sessions ADD COLUMN models_used TEXT;
+ALTER TABLE sessions ADD COLUMN locked_model TEXT;
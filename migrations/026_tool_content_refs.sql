@@ -0,0 +1,48 @@
+-- Tool content references
+-- Version: 026
+-- Description: Large tool arguments (e.g. write_file content) are stored once in file_contents
+-- and referenced from stream events and thinking steps. tool_content_refs keeps that content
+-- alive while its session exists; file contents are removed once neither a source nor a
+-- session references them. A content ref in tool arguments only resolves for the session and
+-- tool call it was stored for.
+
+CREATE TABLE IF NOT EXISTS tool_content_refs (
+    session_id TEXT NOT NULL,
+    tool_call_id TEXT NOT NULL,
+    content_id INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (session_id, tool_call_id, content_id),
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE,
+    FOREIGN KEY (content_id) REFERENCES file_contents(id) ON DELETE RESTRICT
+);
+
+CREATE INDEX IF NOT EXISTS idx_tool_content_refs_content_id ON tool_content_refs(content_id);
+
+DROP TRIGGER IF EXISTS cleanup_orphaned_contents;
+
+CREATE TRIGGER IF NOT EXISTS cleanup_orphaned_contents
+AFTER DELETE ON sources
+WHEN OLD.content_id IS NOT NULL
+BEGIN
+    DELETE FROM file_contents
+    WHERE id = OLD.content_id
+    AND NOT EXISTS (
+        SELECT 1 FROM sources WHERE content_id = OLD.content_id
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM tool_content_refs WHERE content_id = OLD.content_id
+    );
+END;
+
+CREATE TRIGGER IF NOT EXISTS cleanup_orphaned_tool_contents
+AFTER DELETE ON tool_content_refs
+BEGIN
+    DELETE FROM file_contents
+    WHERE id = OLD.content_id
+    AND NOT EXISTS (
+        SELECT 1 FROM sources WHERE content_id = OLD.content_id
+    )
+    AND NOT EXISTS (
+        SELECT 1 FROM tool_content_refs WHERE content_id = OLD.content_id
+    );
+END;
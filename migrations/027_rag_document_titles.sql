@@ -0,0 +1,7 @@
+-- RAG document titles
+-- Version: 027
+-- Description: Stores a human-friendly title per indexed document (markdown H1 or front-matter
+-- title, module doc comment for source files). Documents indexed before this migration have no
+-- title and are shown by filename until they are re-indexed.
+
+ALTER TABLE rag_documents ADD COLUMN title TEXT;
@@ -0,0 +1,7 @@
+-- Message finish reason
+-- Version: 028
+-- Description: Records why the model stopped generating an assistant message (end_turn, length,
+-- content_filter, tool_calls, other), so truncated answers can be told apart from complete
+-- ones. Messages saved before this migration have no finish reason.
+
+ALTER TABLE messages ADD COLUMN finish_reason TEXT;
@@ -0,0 +1,26 @@
+-- Idempotency keys
+-- Version: 029
+-- Description: Client-generated keys sent with chat requests (Idempotency-Key header or
+-- client_message_id). A retried request with a known key replays the stored answer instead of
+-- generating a new one. Keys expire after server.idempotency_ttl_seconds; the user message a key
+-- produced keeps it in messages.idempotency_key.
+
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    request_id TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'in_flight',  -- 'in_flight' or 'completed'
+    user_message_id INTEGER,
+    assistant_message_id INTEGER,
+    created_at INTEGER NOT NULL,
+    -- Tokens the answered turn used, so a replay reports that turn rather than the session
+    input_tokens INTEGER,
+    output_tokens INTEGER,
+    reasoning_tokens INTEGER,
+    cache_tokens INTEGER,
+    FOREIGN KEY (session_id) REFERENCES sessions(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys(created_at);
+
+ALTER TABLE messages ADD COLUMN idempotency_key TEXT;
@@ -0,0 +1,13 @@
+-- Message embeddings
+-- Version: 030
+-- Description: Embeddings of chat messages for the "relevant" history strategy
+-- (context.strategy). Each message is embedded once with the RAG embedder; the model is stored
+-- so switching embedding models computes them again.
+
+CREATE TABLE IF NOT EXISTS message_embeddings (
+    message_id INTEGER PRIMARY KEY,
+    model TEXT NOT NULL,
+    embedding BLOB NOT NULL,  -- little-endian f32 values
+    created_at INTEGER NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE
+);
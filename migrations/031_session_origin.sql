@@ -0,0 +1,11 @@
+-- Session origin
+-- Version: 031
+-- Description: Who started a session over the web API and from where: the client address
+-- (truncated when privacy.redact_ips is set), its User-Agent and the X-Squid-User header.
+-- NULL for sessions started before this migration or from the CLI.
+
+ALTER TABLE sessions ADD COLUMN origin_ip TEXT;
+ALTER TABLE sessions ADD COLUMN origin_user_agent TEXT;
+ALTER TABLE sessions ADD COLUMN origin_user TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_sessions_origin_user ON sessions(origin_user);
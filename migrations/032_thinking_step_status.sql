@@ -0,0 +1,7 @@
+-- Thinking step status
+-- Version: 032
+-- Description: Why a tool step didn't run: denied_by_config, rejected_by_user,
+-- approval_timeout or blocked_dangerous. NULL for tools that ran and for steps saved before
+-- this migration.
+
+ALTER TABLE thinking_steps ADD COLUMN status TEXT;
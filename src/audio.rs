@@ -18,12 +18,6 @@ use crate::config;
 pub struct TranscribeAudioRequest {
     pub audio_base64: String,
     pub mime_type: String,
-    #[serde(default = "default_agent_for_transcription")]
-    pub agent_id: String,
-}
-
-fn default_agent_for_transcription() -> String {
-    "default".to_string()
 }
 
 #[derive(Debug, Serialize)]
@@ -0,0 +1,288 @@
+//! Project files attached automatically to new sessions
+//!
+//! `context.auto_include` lists globs resolved against the project root, e.g.
+//! `["ARCHITECTURE.md", "docs/api/*.md"]`. When a session starts, the matched files are attached
+//! to its first user message as `auto` sources, so they are sent once and stay in the history
+//! like attachments. Files the path validator rejects or that exceed `context.max_file_bytes`
+//! are skipped; when the rest needs more than `context.budget` of the context window, the
+//! largest are left out with a warning.
+
+use ignore::WalkBuilder;
+use ignore::overrides::OverrideBuilder;
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, ContextConfig};
+use crate::tokens;
+use crate::validate::PathValidator;
+
+/// Files to attach to a new session's first message
+#[derive(Debug, Default)]
+pub struct AutoIncluded {
+    /// `(path relative to the project root, content)` pairs, in path order
+    pub files: Vec<(String, String)>,
+    /// Files left out to stay within the budget, for the user to see
+    pub warnings: Vec<String>,
+}
+
+/// The configured files for a new session answered by `model`
+pub fn load(config: &Config, model: &str, context_window: u32) -> AutoIncluded {
+    if config.context.auto_include.is_empty() {
+        return AutoIncluded::default();
+    }
+    let root = config.project_root();
+    let validator = PathValidator::cached_for_working_dir(&root, config.security.respect_gitignore);
+    load_from(&root, &config.context, &validator, model, context_window)
+}
+
+/// Read the files in `root` matching `settings.auto_include`, within the size and token limits
+pub fn load_from(
+    root: &Path,
+    settings: &ContextConfig,
+    validator: &PathValidator,
+    model: &str,
+    context_window: u32,
+) -> AutoIncluded {
+    let mut files = Vec::new();
+    for path in resolve(root, &settings.auto_include) {
+        let name = relative_name(root, &path);
+        if let Err(e) = validator.validate(&path) {
+            warn!("Not auto-including {}: {}", name, e);
+            continue;
+        }
+        match std::fs::metadata(&path) {
+            Ok(metadata) if metadata.len() as usize > settings.max_file_bytes => {
+                warn!(
+                    "Not auto-including {}: larger than {} bytes",
+                    name, settings.max_file_bytes
+                );
+                continue;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                warn!("Failed to read {}: {}", name, e);
+                continue;
+            }
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(content) => files.push((name, content)),
+            Err(e) => warn!("Not auto-including {}: {}", name, e),
+        }
+    }
+
+    let budget = (context_window as f64 * settings.budget.clamp(0.0, 1.0)) as i64;
+    let (files, dropped) = fit_budget(files, model, budget);
+    let warnings = if dropped.is_empty() {
+        Vec::new()
+    } else {
+        let message = format!(
+            "Auto-included files exceed the {}-token budget (context.budget), left out: {}",
+            budget,
+            dropped.join(", ")
+        );
+        warn!("{}", message);
+        vec![message]
+    };
+    debug!("Auto-including {} file(s)", files.len());
+    AutoIncluded { files, warnings }
+}
+
+/// Files under `root` matching any of `patterns`, sorted and without duplicates
+///
+/// Patterns use .gitignore syntax anchored at `root`, so `ARCHITECTURE.md` only matches the
+/// file at the top and `*` stays within one directory; `!pattern` excludes matches again.
+fn resolve(root: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let mut builder = OverrideBuilder::new(root);
+    for pattern in patterns {
+        let (negated, glob) = match pattern.trim().strip_prefix('!') {
+            Some(glob) => ("!", glob),
+            None => ("", pattern.trim()),
+        };
+        let anchored = format!("{}/{}", negated, glob.trim_start_matches('/'));
+        if let Err(e) = builder.add(&anchored) {
+            warn!("Invalid context.auto_include pattern '{}': {}", pattern, e);
+        }
+    }
+    let overrides = match builder.build() {
+        Ok(overrides) => overrides,
+        Err(e) => {
+            warn!("Invalid context.auto_include patterns: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut paths: Vec<PathBuf> = WalkBuilder::new(root)
+        .hidden(false)
+        .overrides(overrides)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_some_and(|kind| kind.is_file()))
+        .map(|entry| entry.into_path())
+        .collect();
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Drop the largest files until the rest fits in `budget` tokens, returning the dropped names
+fn fit_budget(
+    files: Vec<(String, String)>,
+    model: &str,
+    budget: i64,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut sized: Vec<(i64, (String, String))> = files
+        .into_iter()
+        .map(|file| (tokens::estimate_message_tokens(model, &file.1), file))
+        .collect();
+    let mut total: i64 = sized.iter().map(|(size, _)| size).sum();
+
+    let mut by_size: Vec<usize> = (0..sized.len()).collect();
+    by_size.sort_by_key(|&index| std::cmp::Reverse(sized[index].0));
+    let mut dropped = Vec::new();
+    for index in by_size {
+        if total <= budget {
+            break;
+        }
+        total -= sized[index].0;
+        dropped.push(index);
+    }
+
+    let mut dropped_names = Vec::new();
+    let mut index = 0;
+    sized.retain(|(size, (name, _))| {
+        let keep = !dropped.contains(&index);
+        if !keep {
+            dropped_names.push(format!("{} (~{} tokens)", name, size));
+        }
+        index += 1;
+        keep
+    });
+    (
+        sized.into_iter().map(|(_, file)| file).collect(),
+        dropped_names,
+    )
+}
+
+/// `path` relative to `root` with `/` separators, as shown to the model and the user
+fn relative_name(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Note placed before auto-included files in a user message, naming them
+pub fn note(names: &[&str]) -> String {
+    format!(
+        "The following project files were included automatically (context.auto_include): {}\n\n",
+        names.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("ARCHITECTURE.md"), "# Architecture\n").unwrap();
+        std::fs::create_dir_all(root.join("docs/api")).unwrap();
+        std::fs::write(root.join("docs/api/chat.md"), "Chat API\n").unwrap();
+        std::fs::write(root.join("docs/api/rag.md"), "RAG API\n").unwrap();
+        std::fs::write(root.join("docs/api/notes.txt"), "Not markdown\n").unwrap();
+        std::fs::create_dir_all(root.join("nested")).unwrap();
+        std::fs::write(root.join("nested/ARCHITECTURE.md"), "Nested\n").unwrap();
+        (dir, root)
+    }
+
+    fn settings(patterns: &[&str]) -> ContextConfig {
+        ContextConfig {
+            auto_include: patterns.iter().map(|p| p.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_globs_resolve_against_the_project_root() {
+        let (_dir, root) = project();
+        let validator = PathValidator::for_working_dir(&root);
+
+        let included = load_from(
+            &root,
+            &settings(&["ARCHITECTURE.md", "docs/api/*.md", "missing.md"]),
+            &validator,
+            "gpt-4",
+            128_000,
+        );
+        let names: Vec<&str> = included.files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            ["ARCHITECTURE.md", "docs/api/chat.md", "docs/api/rag.md"]
+        );
+        assert_eq!(included.files[0].1, "# Architecture\n");
+        assert!(included.warnings.is_empty());
+
+        // Negated patterns exclude matches again; ** reaches nested directories
+        let included = load_from(
+            &root,
+            &settings(&["**/*.md", "!docs/api/rag.md"]),
+            &validator,
+            "gpt-4",
+            128_000,
+        );
+        let names: Vec<&str> = included.files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "ARCHITECTURE.md",
+                "docs/api/chat.md",
+                "nested/ARCHITECTURE.md"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_squidignored_and_oversized_files_are_skipped() {
+        let (_dir, root) = project();
+        std::fs::write(root.join(".squidignore"), "rag.md\n").unwrap();
+        std::fs::write(root.join("big.md"), "x".repeat(2000)).unwrap();
+        let validator = PathValidator::for_working_dir(&root);
+
+        let settings = ContextConfig {
+            max_file_bytes: 1000,
+            ..settings(&["*.md", "docs/api/*.md"])
+        };
+        let included = load_from(&root, &settings, &validator, "gpt-4", 128_000);
+        let names: Vec<&str> = included.files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["ARCHITECTURE.md", "docs/api/chat.md"]);
+    }
+
+    #[test]
+    fn test_largest_files_are_dropped_to_fit_the_budget() {
+        let (_dir, root) = project();
+        std::fs::write(
+            root.join("schema.sql"),
+            "create table t (id int);\n".repeat(200),
+        )
+        .unwrap();
+        let validator = PathValidator::for_working_dir(&root);
+
+        // A quarter of 4000 tokens holds the small files but not the schema
+        let included = load_from(
+            &root,
+            &settings(&["ARCHITECTURE.md", "schema.sql", "docs/api/chat.md"]),
+            &validator,
+            "gpt-4",
+            4000,
+        );
+        let names: Vec<&str> = included.files.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["ARCHITECTURE.md", "docs/api/chat.md"]);
+        assert_eq!(included.warnings.len(), 1);
+        assert!(included.warnings[0].contains("1000-token budget"));
+        assert!(included.warnings[0].contains("schema.sql (~"));
+    }
+}
@@ -160,13 +160,13 @@ pub fn get_bundled_plugins_dir() -> Option<PathBuf> {
     }
 
     // Dev build fallback
-    if let Ok(exe_path) = std::env::current_exe() {
-        if let Some(parent) = exe_path.parent() {
-            let dev_plugins = parent.join("plugins");
-            if dev_plugins.exists() && is_in_target_dir(&dev_plugins) {
-                debug!("Using dev bundled plugins dir: {:?}", dev_plugins);
-                return Some(dev_plugins);
-            }
+    if let Ok(exe_path) = std::env::current_exe()
+        && let Some(parent) = exe_path.parent()
+    {
+        let dev_plugins = parent.join("plugins");
+        if dev_plugins.exists() && is_in_target_dir(&dev_plugins) {
+            debug!("Using dev bundled plugins dir: {:?}", dev_plugins);
+            return Some(dev_plugins);
         }
     }
 
@@ -185,13 +185,13 @@ pub fn get_bundled_agents_dir() -> Option<PathBuf> {
     }
 
     // Dev build fallback
-    if let Ok(exe_path) = std::env::current_exe() {
-        if let Some(parent) = exe_path.parent() {
-            let dev_agents = parent.join("agents");
-            if dev_agents.exists() && is_in_target_dir(&dev_agents) {
-                debug!("Using dev bundled agents dir: {:?}", dev_agents);
-                return Some(dev_agents);
-            }
+    if let Ok(exe_path) = std::env::current_exe()
+        && let Some(parent) = exe_path.parent()
+    {
+        let dev_agents = parent.join("agents");
+        if dev_agents.exists() && is_in_target_dir(&dev_agents) {
+            debug!("Using dev bundled agents dir: {:?}", dev_agents);
+            return Some(dev_agents);
         }
     }
 
@@ -0,0 +1,1006 @@
+//! State of one chat turn, kept apart from the I/O that drives it
+//!
+//! [`ChatTurn`] is fed what the provider streams and decides what the client sees and which
+//! tool calls run; [`TurnTranscript`] follows the resulting events and builds the answer that
+//! is saved. Neither does any I/O: requests, approvals and persistence stay in
+//! [`crate::api`], and tools run through a [`ToolExecutor`], so both can be driven from tests
+//! with scripted input.
+
+use async_openai::types::chat::ChatCompletionMessageToolCall;
+use futures::Stream;
+use log::warn;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::Path;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use crate::api::{Source, StreamEvent, StreamWarningCode};
+use crate::config::{self, Config};
+use crate::policy::SkipStatus;
+use crate::providers::{self, ProviderEvent, StopReason};
+use crate::rag::RagSystem;
+use crate::trace::ResponseSummary;
+use crate::{sanitize, session, tokens, tools};
+
+/// What the model is told about tool calls skipped by a [`ToolLoopGuard`] limit
+const TOOL_LIMIT_RESULT: &str =
+    "Tool limit reached for this turn. Answer the user with the information you already have.";
+
+/// Content events between live usage estimates
+pub(crate) const LIVE_USAGE_EVERY_EVENTS: usize = 20;
+
+/// Longest time between live usage estimates while content streams
+const LIVE_USAGE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Progress of a tool run in the chat stream
+pub(crate) enum ToolRunStep {
+    Output(tools::ToolOutputChunk),
+    Finished(Value),
+}
+
+/// Runs the tools of a chat turn; the part of a turn with side effects
+pub(crate) trait ToolExecutor: Sync {
+    /// Run `name`, reporting its output lines as they are produced and then its result
+    fn run<'a>(
+        &'a self,
+        name: &'a str,
+        args: &'a Value,
+        working_dir: Option<&'a Path>,
+    ) -> Pin<Box<dyn Stream<Item = ToolRunStep> + Send + 'a>>;
+}
+
+/// Runs tools in this process, with the server's configuration
+pub(crate) struct LocalTools<'a> {
+    pub config: &'a Config,
+    pub rag: Option<&'a RagSystem>,
+}
+
+impl ToolExecutor for LocalTools<'_> {
+    fn run<'a>(
+        &'a self,
+        name: &'a str,
+        args: &'a Value,
+        working_dir: Option<&'a Path>,
+    ) -> Pin<Box<dyn Stream<Item = ToolRunStep> + Send + 'a>> {
+        let (config, rag) = (self.config, self.rag);
+        Box::pin(async_stream::stream! {
+            let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+            let execution = tools::execute_tool_direct(
+                name,
+                args,
+                config,
+                working_dir,
+                rag,
+                Some(tools::ToolOutput::channel(sender)),
+            );
+            tokio::pin!(execution);
+
+            let result = loop {
+                let step = tokio::select! {
+                    biased;
+                    Some(chunk) = receiver.recv() => ToolRunStep::Output(chunk),
+                    result = &mut execution => ToolRunStep::Finished(result),
+                };
+                match step {
+                    ToolRunStep::Output(chunk) => yield ToolRunStep::Output(chunk),
+                    ToolRunStep::Finished(result) => break result,
+                }
+            };
+
+            // Lines sent right before the tool returned
+            while let Ok(chunk) = receiver.try_recv() {
+                yield ToolRunStep::Output(chunk);
+            }
+            yield ToolRunStep::Finished(result);
+        })
+    }
+}
+
+/// Running estimate of the output tokens the provider hasn't reported yet
+///
+/// Re-tokenizes the unreported text on every estimate, which is why it's opt-in
+/// (`server.live_usage_estimates`).
+struct LiveUsageEstimate {
+    text: String,
+    events: usize,
+    last_sent: Instant,
+}
+
+impl LiveUsageEstimate {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            events: 0,
+            last_sent: Instant::now(),
+        }
+    }
+
+    /// Add streamed content; returns the output token estimate when one is due
+    fn push(&mut self, text: &str, model: &str) -> Option<i64> {
+        self.text.push_str(text);
+        self.events += 1;
+        if self.events < LIVE_USAGE_EVERY_EVENTS && self.last_sent.elapsed() < LIVE_USAGE_INTERVAL {
+            return None;
+        }
+        self.events = 0;
+        self.last_sent = Instant::now();
+        Some(tokens::estimate_message_tokens(model, &self.text))
+    }
+
+    /// The provider reported usage for everything streamed so far
+    fn reset(&mut self) {
+        self.text.clear();
+        self.events = 0;
+    }
+}
+
+/// Per-turn limits on tool calls the model makes
+///
+/// Bounds the number of tool rounds and the total time tools run, and answers repeated calls
+/// with identical arguments from the earlier result instead of running the tool again.
+struct ToolLoopGuard {
+    max_rounds: usize,
+    time_budget: Option<Duration>,
+    rounds: usize,
+    tool_time: Duration,
+    results: HashMap<(String, String), Value>,
+    /// Set once a limit was hit; later requests of the turn are sent without tools
+    exhausted: bool,
+}
+
+impl ToolLoopGuard {
+    fn new(config: &config::ToolsConfig) -> Self {
+        Self {
+            max_rounds: config.max_rounds,
+            time_budget: config.time_budget(),
+            rounds: 0,
+            tool_time: Duration::ZERO,
+            results: HashMap::new(),
+            exhausted: false,
+        }
+    }
+
+    /// Count a new round of tool calls; returns a warning when it goes over `max_rounds`
+    fn start_round(&mut self) -> Option<String> {
+        if self.max_rounds > 0 && self.rounds >= self.max_rounds {
+            self.exhausted = true;
+            return Some(format!(
+                "Stopped after {} rounds of tool calls; the model was asked to answer with what it has",
+                self.max_rounds
+            ));
+        }
+        self.rounds += 1;
+        None
+    }
+
+    /// Result of an earlier call to `name` with the same arguments in this turn
+    fn previous(&self, name: &str, args: &Value) -> Option<&Value> {
+        self.results.get(&(name.to_string(), args.to_string()))
+    }
+
+    /// Remember a tool's result and run time; returns a warning when the time budget runs out
+    fn record(
+        &mut self,
+        name: &str,
+        args: &Value,
+        result: &Value,
+        elapsed: Duration,
+    ) -> Option<String> {
+        self.results
+            .insert((name.to_string(), args.to_string()), result.clone());
+        self.tool_time += elapsed;
+        let budget = self.time_budget?;
+        if self.exhausted || self.tool_time < budget {
+            return None;
+        }
+        self.exhausted = true;
+        Some(format!(
+            "Tools used up this turn's {}s time budget; remaining tool calls were skipped",
+            budget.as_secs()
+        ))
+    }
+}
+
+/// Tool message for a call answered from an earlier identical call
+fn duplicate_tool_result(previous: &Value) -> Value {
+    json!({
+        "note": "You already called this tool with the same arguments in this turn. This is the earlier result; don't call it again.",
+        "result": previous,
+    })
+}
+
+/// How the turn goes on once a provider response has ended
+#[derive(Debug)]
+pub(crate) enum ResponseEnd {
+    /// The model answered, or kept calling tools after it was told to stop
+    Answered,
+    /// Run these calls and send their results back; `warning` is set when the round limit was
+    /// reached, in which case every call is answered with the limit instead of running
+    ToolRound {
+        calls: Vec<ChatCompletionMessageToolCall>,
+        warning: Option<String>,
+    },
+}
+
+/// First look at one of the model's tool calls
+#[derive(Debug)]
+pub(crate) enum CallCheck {
+    /// Answer the model with this tool message without running anything
+    Answer(String),
+    /// Check the call against the policy; these are its parsed arguments
+    Run(Value),
+}
+
+/// Provider-facing state of a chat turn
+///
+/// Holds the tool calls of the current response, the turn's tool limits and what the request
+/// trace records about each response. Each `on_*` method takes one piece of a provider
+/// response and returns the events to send.
+pub(crate) struct ChatTurn {
+    model_id: String,
+    /// Tool calls of the current response, assembled from their deltas
+    tool_calls: Vec<ChatCompletionMessageToolCall>,
+    stop_reason: Option<StopReason>,
+    summary: ResponseSummary,
+    /// Kept across tool rounds until the provider reports usage
+    live_usage: Option<LiveUsageEstimate>,
+    guard: ToolLoopGuard,
+}
+
+impl ChatTurn {
+    pub fn new(model_id: &str, config: &Config) -> Self {
+        Self {
+            model_id: model_id.to_string(),
+            tool_calls: Vec::new(),
+            stop_reason: None,
+            summary: ResponseSummary::default(),
+            live_usage: config
+                .server
+                .live_usage_estimates
+                .then(LiveUsageEstimate::new),
+            guard: ToolLoopGuard::new(&config.tools),
+        }
+    }
+
+    /// Set once a tool limit was hit; the model then has to answer without tools
+    pub fn tools_exhausted(&self) -> bool {
+        self.guard.exhausted
+    }
+
+    /// Forget the previous response before the next request is sent
+    pub fn begin_response(&mut self) {
+        self.tool_calls.clear();
+        self.stop_reason = None;
+        self.summary = ResponseSummary::default();
+    }
+
+    pub fn on_provider_event(&mut self, event: ProviderEvent) -> Vec<StreamEvent> {
+        match event {
+            ProviderEvent::Content(text) => self.on_content_delta(text),
+            ProviderEvent::ToolCallDelta {
+                index,
+                id,
+                name,
+                arguments,
+            } => self.on_tool_call_delta(index, id, name, arguments),
+            ProviderEvent::Usage {
+                input_tokens,
+                output_tokens,
+            } => self.on_usage(input_tokens, output_tokens),
+            ProviderEvent::Stop(reason) => self.on_finish_reason(reason),
+        }
+    }
+
+    pub fn on_content_delta(&mut self, text: String) -> Vec<StreamEvent> {
+        self.summary.content_chars += text.chars().count();
+        let estimate = self
+            .live_usage
+            .as_mut()
+            .and_then(|live| live.push(&text, &self.model_id));
+        let mut events = vec![StreamEvent::Content { text }];
+        if let Some(output_tokens) = estimate {
+            events.push(StreamEvent::Usage {
+                input_tokens: 0,
+                output_tokens,
+                reasoning_tokens: 0,
+                cache_tokens: 0,
+                estimated: true,
+            });
+        }
+        events
+    }
+
+    /// Tool calls are only reported once the response ends, see [`ChatTurn::end_response`]
+    pub fn on_tool_call_delta(
+        &mut self,
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: Option<String>,
+    ) -> Vec<StreamEvent> {
+        providers::apply_tool_call_delta(&mut self.tool_calls, index, id, name, arguments);
+        Vec::new()
+    }
+
+    pub fn on_usage(&mut self, input_tokens: i64, output_tokens: i64) -> Vec<StreamEvent> {
+        if let Some(live) = self.live_usage.as_mut() {
+            live.reset();
+        }
+        self.summary.input_tokens = Some(input_tokens);
+        self.summary.output_tokens = Some(output_tokens);
+        vec![StreamEvent::Usage {
+            input_tokens,
+            output_tokens,
+            reasoning_tokens: 0, // Not reported by the providers' streaming APIs
+            cache_tokens: 0,
+            estimated: false,
+        }]
+    }
+
+    /// The reason is reported with the turn's `done` event, sent by the caller
+    pub fn on_finish_reason(&mut self, reason: StopReason) -> Vec<StreamEvent> {
+        self.stop_reason = Some(reason);
+        Vec::new()
+    }
+
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    /// What the request trace records about a response that failed part way
+    pub fn partial_summary(&self) -> ResponseSummary {
+        self.summary.clone()
+    }
+
+    /// What the request trace records about a response that ended
+    pub fn response_summary(&self) -> ResponseSummary {
+        ResponseSummary {
+            stop_reason: self.stop_reason.map(|reason| reason.as_str().to_string()),
+            tool_calls: self
+                .tool_calls
+                .iter()
+                .map(|tc| tc.function.name.clone())
+                .collect(),
+            ..self.summary.clone()
+        }
+    }
+
+    /// Decide whether the response ends the turn or starts a round of tool calls
+    pub fn end_response(&mut self) -> ResponseEnd {
+        // Without tool calls the answer is complete
+        if self.stop_reason != Some(StopReason::ToolCalls) || self.tool_calls.is_empty() {
+            return ResponseEnd::Answered;
+        }
+        if self.guard.exhausted {
+            warn!("Model kept calling tools after the tool limit was reached; ending the turn");
+            return ResponseEnd::Answered;
+        }
+        let warning = self.guard.start_round();
+        ResponseEnd::ToolRound {
+            calls: self.tool_calls.clone(),
+            warning,
+        }
+    }
+
+    pub fn check_call(&self, tool_call: &ChatCompletionMessageToolCall) -> CallCheck {
+        // Every call still needs an answer once a limit was hit
+        if self.guard.exhausted {
+            return CallCheck::Answer(
+                json!({"error": TOOL_LIMIT_RESULT, "skipped": true}).to_string(),
+            );
+        }
+        match tool_call.function.arguments.parse() {
+            Ok(args) => CallCheck::Run(args),
+            Err(e) => CallCheck::Answer(
+                json!({"error": format!("Failed to parse tool arguments: {}", e)}).to_string(),
+            ),
+        }
+    }
+
+    /// Answer a repeated call with the result of the earlier identical one
+    ///
+    /// Returns the events to send and the tool message, or `None` if the call is new.
+    pub fn reuse_previous(
+        &self,
+        name: &str,
+        args: &Value,
+        event_args: &Value,
+    ) -> Option<(Vec<StreamEvent>, String)> {
+        let previous = self.guard.previous(name, args)?;
+        let message = format!(
+            "'{}' was called again with the same arguments; the earlier result was reused",
+            name
+        );
+        warn!("{}", message);
+        let events = vec![
+            StreamEvent::Warning {
+                message,
+                code: StreamWarningCode::DuplicateToolCall,
+            },
+            StreamEvent::ToolInvocationCompleted {
+                name: name.to_string(),
+                arguments: event_args.clone(),
+                result: Some(previous.to_string()),
+                error: None,
+                status: None,
+            },
+        ];
+        let tool_message =
+            sanitize::wrap_tool_result(name, &duplicate_tool_result(previous).to_string());
+        Some((events, tool_message))
+    }
+
+    /// A tool ran: returns the events to send and the tool message for the model
+    pub fn on_tool_finished(
+        &mut self,
+        name: &str,
+        args: &Value,
+        event_args: &Value,
+        result: &Value,
+        elapsed: Duration,
+    ) -> (Vec<StreamEvent>, String) {
+        let mut events = Vec::new();
+        if let Some(message) = self.guard.record(name, args, result, elapsed) {
+            warn!("{}", message);
+            events.push(StreamEvent::Warning {
+                message,
+                code: StreamWarningCode::ToolTimeBudgetExceeded,
+            });
+        }
+        events.push(StreamEvent::ToolInvocationCompleted {
+            name: name.to_string(),
+            arguments: event_args.clone(),
+            result: Some(result.to_string()),
+            error: None,
+            status: None,
+        });
+        let doc_sources = tools::doc_sources(name, result);
+        if !doc_sources.is_empty() {
+            events.push(StreamEvent::Sources {
+                sources: doc_sources.iter().map(Source::from).collect(),
+            });
+        }
+        (
+            events,
+            sanitize::wrap_tool_result(name, &result.to_string()),
+        )
+    }
+}
+
+/// A tool call that was answered without running, and why
+pub(crate) struct SkippedCall {
+    pub error: String,
+    pub status: SkipStatus,
+    /// What the model is told instead of a result
+    pub tool_message: Value,
+}
+
+impl SkippedCall {
+    pub fn event(&self, name: &str, event_args: &Value) -> StreamEvent {
+        StreamEvent::ToolInvocationCompleted {
+            name: name.to_string(),
+            arguments: event_args.clone(),
+            result: None,
+            error: Some(self.error.clone()),
+            status: Some(self.status),
+        }
+    }
+}
+
+/// Finds the `<think>` blocks of streamed content as they close
+#[derive(Debug, Default)]
+pub(crate) struct ThinkTagParser {
+    /// Byte offset just past the last closed block
+    closed_until: usize,
+}
+
+impl ThinkTagParser {
+    const OPEN: &'static str = "<think>";
+    const CLOSE: &'static str = "</think>";
+
+    /// Text of the blocks closed in `content` since the previous call
+    ///
+    /// `content` is everything streamed so far. A block is returned once its `</think>` has
+    /// arrived; an unterminated block waits for more content. Tags don't nest: the first
+    /// `</think>` closes the block, so an inner `<think>` is part of its text.
+    pub fn closed_blocks(&mut self, content: &str) -> Vec<String> {
+        let mut blocks = Vec::new();
+        while let Some(relative_start) = content[self.closed_until..].find(Self::OPEN) {
+            let start = self.closed_until + relative_start;
+            let Some(relative_end) = content[start..].find(Self::CLOSE) else {
+                break; // Incomplete block, wait for more content
+            };
+            let end = start + relative_end;
+            blocks.push(content[start + Self::OPEN.len()..end].to_string());
+            self.closed_until = end + Self::CLOSE.len();
+        }
+        blocks
+    }
+}
+
+/// What a chat turn has produced so far, built from the events sent to the client
+///
+/// Collects the answer's content, its reasoning, tool and warning steps in the order they
+/// happened, the token usage the provider reported and the turn's sources.
+pub(crate) struct TurnTranscript {
+    content: String,
+    steps: Vec<session::ThinkingStep>,
+    content_deltas: session::ContentDeltaTracker,
+    /// `None` when reasoning was turned off, so `<think>` is left in the content
+    think_tags: Option<ThinkTagParser>,
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    pub reasoning_tokens: i64,
+    pub cache_tokens: i64,
+    /// Set once the provider reported usage; otherwise it's estimated when the turn ends
+    pub received_usage: bool,
+    pub trace_ids: Vec<String>,
+    pub sources: Vec<session::Source>,
+    pub finish_reason: Option<StopReason>,
+    pub context_breakdown: Option<Vec<crate::tokens::ContextCategory>>,
+}
+
+impl TurnTranscript {
+    /// `sources` are the attachments and document chunks sent with the question
+    pub fn new(sources: Vec<session::Source>, parse_think_tags: bool) -> Self {
+        Self {
+            content: String::new(),
+            steps: Vec::new(),
+            content_deltas: session::ContentDeltaTracker::new(),
+            think_tags: parse_think_tags.then(ThinkTagParser::default),
+            input_tokens: 0,
+            output_tokens: 0,
+            reasoning_tokens: 0,
+            cache_tokens: 0,
+            received_usage: false,
+            trace_ids: Vec::new(),
+            sources,
+            finish_reason: None,
+            context_breakdown: None,
+        }
+    }
+
+    /// Everything streamed so far, tags included
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Record an event; returns the event to send on, or `None` for the turn's `done`,
+    /// which is sent once the answer is saved
+    pub fn observe(&mut self, event: StreamEvent) -> Option<StreamEvent> {
+        match event {
+            StreamEvent::Done { finish_reason, .. } => {
+                self.finish_reason = finish_reason;
+                return None;
+            }
+            // Documents found by search_docs join the turn's sources; the client gets the full list
+            StreamEvent::Sources { sources } => {
+                let found: Vec<session::Source> =
+                    sources.into_iter().map(session::Source::from).collect();
+                self.sources = session::merge_turn_sources(&self.sources, &found);
+                return Some(StreamEvent::Sources {
+                    sources: self.sources.iter().map(Source::from).collect(),
+                });
+            }
+            StreamEvent::Content { ref text } => self.on_content(text),
+            StreamEvent::TraceId { ref trace_id } => self.trace_ids.push(trace_id.clone()),
+            StreamEvent::ContextBreakdown { ref categories } => {
+                self.context_breakdown = Some(categories.clone())
+            }
+            // Keep tool limit warnings with the answer, in order
+            StreamEvent::Warning { ref message, .. } => {
+                let step = session::ThinkingStep::warning(self.next_step_order(), message);
+                self.steps.push(step);
+            }
+            // Only reported usage counts; live estimates are just forwarded
+            StreamEvent::Usage {
+                input_tokens,
+                output_tokens,
+                reasoning_tokens,
+                cache_tokens,
+                estimated: false,
+            } => {
+                self.input_tokens += input_tokens;
+                self.output_tokens += output_tokens;
+                self.reasoning_tokens += reasoning_tokens;
+                self.cache_tokens += cache_tokens;
+                self.received_usage = true;
+            }
+            // Added right away, so a tool lands right after the reasoning that led to it
+            StreamEvent::ToolInvocationCompleted {
+                ref name,
+                ref arguments,
+                ref result,
+                ref error,
+                status,
+            } => {
+                // Capture only the content produced since the previous tool
+                let content_delta = self.content_deltas.take_delta(&self.content);
+                let step = session::ThinkingStep {
+                    id: None,
+                    step_type: "tool".to_string(),
+                    step_order: self.next_step_order(),
+                    content: None,
+                    tool_name: Some(name.clone()),
+                    tool_arguments: Some(arguments.clone()),
+                    tool_result: result.clone(),
+                    tool_error: error.clone(),
+                    content_delta_before_tool: content_delta,
+                    status: status.map(|status| status.as_str().to_string()),
+                };
+                self.steps.push(step);
+            }
+            _ => {}
+        }
+        Some(event)
+    }
+
+    /// Each closed `<think>` block becomes its own reasoning step
+    fn on_content(&mut self, text: &str) {
+        self.content.push_str(text);
+        let Some(parser) = self.think_tags.as_mut() else {
+            return;
+        };
+        for block in parser.closed_blocks(&self.content) {
+            if block.trim().is_empty() {
+                continue;
+            }
+            let step = session::ThinkingStep {
+                id: None,
+                step_type: "reasoning".to_string(),
+                step_order: self.next_step_order(),
+                content: Some(block),
+                tool_name: None,
+                tool_arguments: None,
+                tool_result: None,
+                tool_error: None,
+                content_delta_before_tool: None,
+                status: None,
+            };
+            self.steps.push(step);
+        }
+    }
+
+    fn next_step_order(&self) -> i32 {
+        self.steps.len() as i32
+    }
+
+    /// The answer to save: content without tags, and the steps if there were any
+    pub fn answer(&mut self) -> (String, Option<Vec<session::ThinkingStep>>) {
+        let content = crate::api::sanitize_assistant_content(&self.content)
+            .trim()
+            .to_string();
+        let steps = std::mem::take(&mut self.steps);
+        (content, (!steps.is_empty()).then_some(steps))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blocks(chunks: &[&str]) -> Vec<Vec<String>> {
+        let mut parser = ThinkTagParser::default();
+        let mut content = String::new();
+        chunks
+            .iter()
+            .map(|chunk| {
+                content.push_str(chunk);
+                parser.closed_blocks(&content)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_think_block_in_one_chunk() {
+        assert_eq!(blocks(&["<think>plan</think>answer"]), [vec!["plan"]]);
+        assert_eq!(blocks(&["no tags here"]), [Vec::<String>::new()]);
+    }
+
+    #[test]
+    fn test_think_tags_split_across_chunks() {
+        assert_eq!(
+            blocks(&["<thi", "nk>first", " half</th", "ink>answer"]),
+            [vec![], vec![], vec![], vec!["first half".to_string()]]
+        );
+        // The closing tag split right before its last character
+        assert_eq!(
+            blocks(&["<think>a</think", ">"]),
+            [vec![], vec!["a".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_several_think_blocks_come_out_in_order() {
+        assert_eq!(
+            blocks(&[
+                "<think>one</think>x<think>two</think>y<think>thr",
+                "ee</think>"
+            ]),
+            [
+                vec!["one".to_string(), "two".to_string()],
+                vec!["three".to_string()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_think_block_waits() {
+        assert_eq!(
+            blocks(&["<think>still going", " and going"]),
+            [Vec::<String>::new(), vec![]]
+        );
+        // A later block can't close before the open one
+        assert_eq!(
+            blocks(&["<think>a", "<think>b</think>"]),
+            [vec![], vec!["a<think>b".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_nested_think_tags_close_at_the_first_end_tag() {
+        assert_eq!(
+            blocks(&["<think>outer <think>inner</think> rest</think>after"]),
+            [vec!["outer <think>inner".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_stray_and_empty_think_tags() {
+        // A closing tag without an opening one is left alone
+        assert_eq!(
+            blocks(&["text</think><think></think>"]),
+            [vec![String::new()]]
+        );
+        // Blocks already returned are not returned again
+        assert_eq!(
+            blocks(&["<think>a</think>", " more"]),
+            [vec!["a".to_string()], vec![]]
+        );
+    }
+
+    fn config() -> Config {
+        Config::default()
+    }
+
+    fn turn_with_call(id: &str, name: &str, arguments: &str) -> ChatTurn {
+        let mut turn = ChatTurn::new("test-model", &config());
+        turn.on_tool_call_delta(
+            0,
+            Some(id.to_string()),
+            Some(name.to_string()),
+            Some(arguments.to_string()),
+        );
+        turn
+    }
+
+    #[test]
+    fn test_response_without_tool_calls_ends_the_turn() {
+        let mut turn = ChatTurn::new("test-model", &config());
+        let events = turn.on_content_delta("Hello".to_string());
+        assert!(matches!(&events[..], [StreamEvent::Content { text }] if text == "Hello"));
+        assert!(turn.on_usage(10, 2).len() == 1);
+        assert!(turn.on_finish_reason(StopReason::EndTurn).is_empty());
+        assert!(matches!(turn.end_response(), ResponseEnd::Answered));
+
+        let summary = turn.response_summary();
+        assert_eq!(summary.stop_reason.as_deref(), Some("end_turn"));
+        assert_eq!((summary.input_tokens, summary.content_chars), (Some(10), 5));
+    }
+
+    #[test]
+    fn test_tool_call_deltas_become_a_tool_round() {
+        let mut turn = turn_with_call("call_1", "now", "{\"timezone\":");
+        turn.on_tool_call_delta(0, None, None, Some("\"UTC\"}".to_string()));
+        turn.on_finish_reason(StopReason::ToolCalls);
+        let ResponseEnd::ToolRound { calls, warning } = turn.end_response() else {
+            panic!("expected a tool round");
+        };
+        assert!(warning.is_none());
+        assert_eq!(calls[0].function.name, "now");
+        assert!(matches!(
+            turn.check_call(&calls[0]),
+            CallCheck::Run(args) if args == json!({"timezone": "UTC"})
+        ));
+
+        // The next response starts empty
+        turn.begin_response();
+        assert!(turn.stop_reason().is_none());
+        assert!(turn.response_summary().tool_calls.is_empty());
+    }
+
+    #[test]
+    fn test_broken_arguments_are_answered_without_running() {
+        let mut turn = turn_with_call("call_1", "read_file", "{not json");
+        turn.on_finish_reason(StopReason::ToolCalls);
+        let ResponseEnd::ToolRound { calls, .. } = turn.end_response() else {
+            panic!("expected a tool round");
+        };
+        let CallCheck::Answer(message) = turn.check_call(&calls[0]) else {
+            panic!("expected an answer");
+        };
+        assert!(message.contains("Failed to parse tool arguments"));
+    }
+
+    #[test]
+    fn test_repeated_call_reuses_the_earlier_result() {
+        let mut turn = ChatTurn::new("test-model", &config());
+        let args = json!({"path": "a.rs"});
+        assert!(turn.reuse_previous("read_file", &args, &args).is_none());
+
+        let (events, message) = turn.on_tool_finished(
+            "read_file",
+            &args,
+            &args,
+            &json!({"content": "fn a() {}"}),
+            Duration::from_millis(5),
+        );
+        assert!(matches!(
+            &events[..],
+            [StreamEvent::ToolInvocationCompleted { .. }]
+        ));
+        assert!(message.contains("fn a() {}"));
+
+        let (events, message) = turn.reuse_previous("read_file", &args, &args).unwrap();
+        assert!(matches!(
+            &events[..],
+            [
+                StreamEvent::Warning {
+                    code: StreamWarningCode::DuplicateToolCall,
+                    ..
+                },
+                StreamEvent::ToolInvocationCompleted { .. }
+            ]
+        ));
+        assert!(message.contains("You already called this tool"));
+    }
+
+    #[test]
+    fn test_round_limit_answers_the_remaining_calls() {
+        let mut config = config();
+        config.tools.max_rounds = 1;
+        let mut turn = ChatTurn::new("test-model", &config);
+        for round in 0..2 {
+            turn.begin_response();
+            turn.on_tool_call_delta(
+                0,
+                Some("call".into()),
+                Some("now".into()),
+                Some("{}".into()),
+            );
+            turn.on_finish_reason(StopReason::ToolCalls);
+            let ResponseEnd::ToolRound { calls, warning } = turn.end_response() else {
+                panic!("expected a tool round");
+            };
+            assert_eq!(warning.is_some(), round == 1);
+            assert_eq!(
+                matches!(turn.check_call(&calls[0]), CallCheck::Answer(_)),
+                round == 1
+            );
+        }
+        // Calling tools again after the limit ends the turn
+        turn.begin_response();
+        turn.on_tool_call_delta(
+            0,
+            Some("call".into()),
+            Some("now".into()),
+            Some("{}".into()),
+        );
+        turn.on_finish_reason(StopReason::ToolCalls);
+        assert!(turn.tools_exhausted());
+        assert!(matches!(turn.end_response(), ResponseEnd::Answered));
+    }
+
+    #[test]
+    fn test_tool_time_budget() {
+        let mut guard = ToolLoopGuard::new(&config::ToolsConfig {
+            time_budget_secs: 5,
+            ..Default::default()
+        });
+        assert!(guard.start_round().is_none());
+        let args = json!({"command": "make"});
+        assert!(
+            guard
+                .record(
+                    "bash",
+                    &args,
+                    &json!({"content": "ok"}),
+                    Duration::from_secs(3)
+                )
+                .is_none()
+        );
+        assert!(!guard.exhausted);
+        let warning = guard.record(
+            "bash",
+            &json!({"command": "make test"}),
+            &json!({"content": "ok"}),
+            Duration::from_secs(3),
+        );
+        assert!(warning.unwrap().contains("5s time budget"));
+        assert!(guard.exhausted);
+        assert_eq!(
+            guard.previous("bash", &args),
+            Some(&json!({"content": "ok"}))
+        );
+
+        // Zero turns the limits off
+        let mut unlimited = ToolLoopGuard::new(&config::ToolsConfig {
+            max_rounds: 0,
+            time_budget_secs: 0,
+            ..Default::default()
+        });
+        for _ in 0..100 {
+            assert!(unlimited.start_round().is_none());
+        }
+        assert!(
+            unlimited
+                .record("bash", &args, &Value::Null, Duration::from_secs(3600))
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_transcript_orders_reasoning_tools_and_warnings() {
+        let mut transcript = TurnTranscript::new(Vec::new(), true);
+        for event in [
+            StreamEvent::Content {
+                text: "<think>look first</think>Checking. ".to_string(),
+            },
+            StreamEvent::ToolInvocationCompleted {
+                name: "bash".to_string(),
+                arguments: json!({"command": "ls"}),
+                result: None,
+                error: Some("Bash commands not allowed".to_string()),
+                status: Some(SkipStatus::DeniedByConfig),
+            },
+            StreamEvent::Warning {
+                message: "Stopped".to_string(),
+                code: StreamWarningCode::ToolRoundsExceeded,
+            },
+            StreamEvent::Content {
+                text: "Done.".to_string(),
+            },
+            StreamEvent::Usage {
+                input_tokens: 10,
+                output_tokens: 3,
+                reasoning_tokens: 0,
+                cache_tokens: 0,
+                estimated: true,
+            },
+        ] {
+            assert!(transcript.observe(event).is_some());
+        }
+        assert!(
+            transcript
+                .observe(StreamEvent::Done {
+                    finish_reason: Some(StopReason::EndTurn),
+                    message_id: None,
+                })
+                .is_none()
+        );
+
+        // Estimates aren't usage
+        assert!(!transcript.received_usage);
+        assert_eq!(transcript.finish_reason, Some(StopReason::EndTurn));
+        let (content, steps) = transcript.answer();
+        assert_eq!(content, "Checking. Done.");
+        let steps = steps.unwrap();
+        let kinds: Vec<_> = steps.iter().map(|step| step.step_type.as_str()).collect();
+        assert_eq!(kinds, ["reasoning", "tool", "warning"]);
+        assert_eq!(steps[1].status.as_deref(), Some("denied_by_config"));
+        assert_eq!(
+            steps[1].content_delta_before_tool.as_deref(),
+            Some("<think>look first</think>Checking. ")
+        );
+    }
+
+    #[test]
+    fn test_transcript_keeps_think_tags_when_reasoning_is_off() {
+        let mut transcript = TurnTranscript::new(Vec::new(), false);
+        transcript.observe(StreamEvent::Content {
+            text: "<think>x</think>y".to_string(),
+        });
+        assert!(transcript.answer().1.is_none());
+    }
+}
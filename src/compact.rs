@@ -0,0 +1,470 @@
+//! Explicit compaction of long sessions
+//!
+//! `squid sessions compact` and `POST /api/sessions/{id}/compact` ask the session's model to
+//! summarize all but the latest messages, then replace those messages in the database with a
+//! single summary message. Unlike trimming the history sent to the model, this deletes the old
+//! messages, their sources and thinking steps for good.
+
+use actix_web::{HttpResponse, web};
+use async_openai::types::chat::{
+    ChatCompletionRequestMessage, ChatCompletionRequestSystemMessage,
+    ChatCompletionRequestUserMessage,
+};
+use futures::StreamExt;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::api;
+use crate::config::Config;
+use crate::db::CompactionStats;
+use crate::llm;
+use crate::providers::{self, ChatParams, ProviderEvent, ToolChoice};
+use crate::session::{ChatMessage, ChatSession, MessageMetadata, SessionManager};
+
+/// Most recent messages kept verbatim when no count is given
+pub const DEFAULT_KEEP: usize = 4;
+
+const SUMMARY_PROMPT: &str = "You compact long conversations. Summarize the conversation you are given so it can \
+replace the original messages: keep decisions, conclusions, open questions, file names, code identifiers and \
+any facts the user stated about themselves or their project. Leave out greetings and repetition. Write the \
+summary as plain Markdown without addressing the user.";
+
+/// Result of compacting a session
+#[derive(Debug, Serialize)]
+pub struct CompactionReport {
+    pub session_id: String,
+    /// The new summary message, `None` when there was nothing to compact
+    pub summary_message_id: Option<i64>,
+    pub messages_kept: usize,
+    #[serde(flatten)]
+    pub stats: CompactionStats,
+}
+
+/// Why a session wasn't compacted
+#[derive(Debug, PartialEq)]
+pub enum CompactError {
+    NotFound(String),
+    /// A chat turn or another compaction is running on the session
+    Busy(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for CompactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactError::NotFound(id) => write!(f, "Session '{}' not found", id),
+            CompactError::Busy(message) | CompactError::Failed(message) => {
+                write!(f, "{}", message)
+            }
+        }
+    }
+}
+
+/// Summarize all but the last `keep` messages of a session and replace them with the summary
+///
+/// The summary is generated before anything is changed and saved in one transaction, so a
+/// failure leaves the session as it was.
+pub async fn run(
+    config: &Config,
+    session_manager: &SessionManager,
+    session_id: &str,
+    keep: usize,
+) -> Result<CompactionReport, CompactError> {
+    let _lock = session_manager
+        .begin_compaction(session_id)
+        .map_err(|e| CompactError::Busy(e.to_string()))?;
+    let session = session_manager
+        .get_session(session_id)
+        .ok_or_else(|| CompactError::NotFound(session_id.to_string()))?;
+
+    let split = session.messages.len().saturating_sub(keep);
+    let (old, kept) = session.messages.split_at(split);
+    let mut report = CompactionReport {
+        session_id: session_id.to_string(),
+        summary_message_id: None,
+        messages_kept: kept.len(),
+        stats: CompactionStats::default(),
+    };
+    // Replacing a single message with its summary wouldn't save anything
+    if old.len() < 2 {
+        report.messages_kept = session.messages.len();
+        return Ok(report);
+    }
+    let message_ids = old
+        .iter()
+        .map(|message| message.id)
+        .collect::<Option<Vec<i64>>>()
+        .ok_or_else(|| CompactError::Failed("Session has unsaved messages".to_string()))?;
+
+    let model = summary_model(config, &session).ok_or_else(|| {
+        CompactError::Failed("No agent is configured to write the summary".to_string())
+    })?;
+    let summary = summarize(config, &model, old)
+        .await
+        .map_err(CompactError::Failed)?;
+
+    let message = ChatMessage {
+        id: None,
+        parent_message_id: None,
+        role: "assistant".to_string(),
+        content: summary,
+        sources: Vec::new(),
+        timestamp: chrono::Utc::now().timestamp(),
+        thinking_steps: None,
+        metadata: MessageMetadata::for_answer(&model, &config.api_url, Default::default()).map(
+            |metadata| MessageMetadata {
+                summary_of: Some(old.len()),
+                ..metadata
+            },
+        ),
+        finish_reason: None,
+    };
+    let (summary_id, stats) = session_manager
+        .replace_with_summary(session_id, &message_ids, &message)
+        .map_err(|e| CompactError::Failed(e.to_string()))?;
+    info!(
+        "Compacted session {}: {} message(s) replaced, {} byte(s) reclaimed",
+        session_id, stats.messages_removed, stats.bytes_reclaimed
+    );
+
+    report.summary_message_id = Some(summary_id);
+    report.stats = stats;
+    Ok(report)
+}
+
+/// Model of the session's agent, falling back to the default agent
+fn summary_model(config: &Config, session: &ChatSession) -> Option<String> {
+    session
+        .agent_id
+        .as_deref()
+        .and_then(|id| config.get_agent(id))
+        .or_else(|| config.get_agent(&config.agents.default_agent))
+        .map(|agent| agent.model.clone())
+}
+
+/// Plain-text transcript of `messages`, without reasoning blocks or source contents
+fn transcript(messages: &[ChatMessage]) -> String {
+    let mut transcript = String::new();
+    for message in messages {
+        let speaker = if message
+            .metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.summary_of.is_some())
+        {
+            "Summary of earlier messages"
+        } else if message.role == "user" {
+            "User"
+        } else {
+            "Assistant"
+        };
+        transcript.push_str(&format!(
+            "{}:\n{}\n",
+            speaker,
+            llm::strip_reasoning_blocks(&message.content).trim()
+        ));
+
+        let titles: Vec<&str> = message
+            .sources
+            .iter()
+            .map(|source| source.title.as_str())
+            .collect();
+        if !titles.is_empty() {
+            transcript.push_str(&format!("(sources: {})\n", titles.join(", ")));
+        }
+        let tools: Vec<&str> = message
+            .thinking_steps
+            .iter()
+            .flatten()
+            .filter_map(|step| step.tool_name.as_deref())
+            .collect();
+        if !tools.is_empty() {
+            transcript.push_str(&format!("(tools used: {})\n", tools.join(", ")));
+        }
+        transcript.push('\n');
+    }
+    transcript
+}
+
+/// Ask `model` for a summary of `messages`
+async fn summarize(
+    config: &Config,
+    model: &str,
+    messages: &[ChatMessage],
+) -> Result<String, String> {
+    let request: Vec<ChatCompletionRequestMessage> = vec![
+        ChatCompletionRequestSystemMessage {
+            content: SUMMARY_PROMPT.into(),
+            ..Default::default()
+        }
+        .into(),
+        ChatCompletionRequestUserMessage {
+            content: transcript(messages).into(),
+            ..Default::default()
+        }
+        .into(),
+    ];
+    let params = ChatParams {
+        model,
+        reasoning: Default::default(),
+        tool_choice: ToolChoice::None,
+    };
+
+    let provider = providers::from_config(config);
+    let mut stream = provider
+        .stream_chat(&request, &[], &params)
+        .await
+        .map_err(|e| format!("Failed to summarize the session: {}", e))?;
+    let mut summary = String::new();
+    while let Some(event) = stream.next().await {
+        if let ProviderEvent::Content(text) =
+            event.map_err(|e| format!("Failed to summarize the session: {}", e))?
+        {
+            summary.push_str(&text);
+        }
+    }
+
+    let summary = llm::strip_reasoning_blocks(&summary).trim().to_string();
+    if summary.is_empty() {
+        return Err("The model returned an empty summary".to_string());
+    }
+    Ok(summary)
+}
+
+/// Optional body of `POST /api/sessions/{id}/compact`
+#[derive(Debug, Default, Deserialize)]
+pub struct CompactSessionRequest {
+    /// Most recent messages to keep verbatim
+    pub keep: Option<usize>,
+}
+
+/// Compact a session, responding with what was removed
+pub async fn compact_session(
+    session_id: web::Path<String>,
+    body: Option<web::Json<CompactSessionRequest>>,
+    app_config: web::Data<Arc<Config>>,
+    session_manager: web::Data<Arc<SessionManager>>,
+) -> HttpResponse {
+    let keep = body.and_then(|body| body.keep).unwrap_or(DEFAULT_KEEP);
+    match run(&app_config, &session_manager, &session_id, keep).await {
+        Ok(report) => {
+            api::broadcast_session_update_for_session(&session_manager, &session_id);
+            HttpResponse::Ok().json(report)
+        }
+        Err(e @ CompactError::NotFound(_)) => {
+            HttpResponse::NotFound().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        Err(e @ CompactError::Busy(_)) => {
+            HttpResponse::Conflict().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        Err(e) => {
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": e.to_string() }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::session::{FileAttachment, ThinkingStep};
+    use serde_json::json;
+
+    fn answer(text: &str) -> String {
+        format!(
+            "data: {}\n\ndata: [DONE]\n\n",
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 1,
+                "model": "test-model",
+                "choices": [{"index": 0, "delta": {"role": "assistant", "content": text}, "finish_reason": "stop"}]
+            })
+        )
+    }
+
+    fn config_for(api_url: String) -> Config {
+        let mut config = Config {
+            api_url,
+            ..Default::default()
+        };
+        config.agents.default_agent = "assistant".to_string();
+        config.agents.agents.insert(
+            "assistant".to_string(),
+            crate::agent::AgentConfig {
+                name: "Assistant".to_string(),
+                enabled: true,
+                description: String::new(),
+                model: "test-model".to_string(),
+                prompt: None,
+                pricing_model: None,
+                context_window: None,
+                permissions: Default::default(),
+                use_tools: false,
+                suggestions: vec![],
+            },
+        );
+        config
+    }
+
+    fn attachment(filename: &str, content: String) -> FileAttachment {
+        FileAttachment {
+            filename: filename.to_string(),
+            content,
+            for_review: false,
+        }
+    }
+
+    /// A session of three exchanges; `shared.txt` is attached to the first and the last
+    fn session_with_history(manager: &SessionManager) -> String {
+        let id = manager.create_session();
+        let shared = "shared notes\n".repeat(200);
+        let turns = [
+            (
+                "Read these",
+                vec![
+                    attachment("big.log", "line of a large log\n".repeat(2000)),
+                    attachment("shared.txt", shared.clone()),
+                ],
+            ),
+            ("What failed?", Vec::new()),
+            ("And now?", vec![attachment("shared.txt", shared)]),
+        ];
+        for (question, files) in turns {
+            manager
+                .add_user_message(&id, question.to_string(), files)
+                .unwrap();
+            let steps = vec![ThinkingStep {
+                id: None,
+                step_type: "tool".to_string(),
+                step_order: 0,
+                content: None,
+                tool_name: Some("read_file".to_string()),
+                tool_arguments: Some(json!({"path": "big.log"})),
+                tool_result: Some("log contents".to_string()),
+                tool_error: None,
+                content_delta_before_tool: None,
+                status: None,
+            }];
+            manager
+                .add_assistant_message(
+                    &id,
+                    format!("Answer to {}", question),
+                    Vec::new(),
+                    Some(steps),
+                    MessageMetadata::for_answer(
+                        "test-model",
+                        "http://localhost",
+                        Default::default(),
+                    ),
+                )
+                .unwrap();
+        }
+        id
+    }
+
+    #[tokio::test]
+    async fn test_compaction_replaces_old_messages_and_reclaims_their_bytes() {
+        let (url, requests) = providers::test_support::serve_sequence(
+            "text/event-stream",
+            vec![answer("The user shared a large log; the build failed.")],
+        )
+        .await;
+        let config = config_for(url);
+        let manager = SessionManager::new(Database::new(":memory:").unwrap());
+        let id = session_with_history(&manager);
+
+        let report = run(&config, &manager, &id, 2).await.unwrap();
+
+        assert_eq!(report.messages_kept, 2);
+        assert_eq!(report.stats.messages_removed, 4);
+        assert_eq!(report.stats.sources_removed, 2);
+        assert_eq!(report.stats.thinking_steps_removed, 2);
+        // The large log goes, the file the kept question attaches stays
+        assert_eq!(report.stats.file_contents_removed, 1);
+        assert!(report.stats.bytes_reclaimed > 100);
+
+        let prompt = &requests.await.unwrap()[0];
+        assert!(prompt.contains("What failed?"));
+        assert!(prompt.contains("big.log"));
+        assert!(!prompt.contains("line of a large log"));
+
+        let session = manager.get_session(&id).unwrap();
+        let contents: Vec<_> = session
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                "The user shared a large log; the build failed.",
+                "And now?",
+                "Answer to And now?"
+            ]
+        );
+        assert_eq!(
+            session.messages[0].id, report.summary_message_id,
+            "the summary is saved"
+        );
+        let summary_of = |index: usize| {
+            session.messages[index]
+                .metadata
+                .as_ref()
+                .and_then(|metadata| metadata.summary_of)
+        };
+        assert_eq!(summary_of(0), Some(4));
+        // The kept messages now follow the summary
+        assert_eq!(session.messages[0].parent_message_id, None);
+        assert_eq!(
+            session.messages[1].parent_message_id,
+            report.summary_message_id
+        );
+        assert_eq!(
+            session.messages[2].parent_message_id,
+            session.messages[1].id
+        );
+        assert_eq!(summary_of(2), None);
+        assert_eq!(session.messages[0].role, "assistant");
+        assert_eq!(session.messages[1].sources.len(), 1);
+        assert!(
+            session.messages[1].sources[0]
+                .content
+                .starts_with("shared notes")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compaction_waits_for_chat_turns() {
+        let config = config_for("http://127.0.0.1:1".to_string());
+        let manager = SessionManager::new(Database::new(":memory:").unwrap());
+        let id = session_with_history(&manager);
+
+        let turn = manager.begin_turn(&id, "turn").unwrap();
+        let error = run(&config, &manager, &id, 2).await.unwrap_err();
+        assert!(matches!(error, CompactError::Busy(_)), "{:?}", error);
+        drop(turn);
+
+        let compacting = manager.begin_compaction(&id).unwrap();
+        assert!(manager.begin_turn(&id, "turn").is_err());
+        drop(compacting);
+        assert!(manager.begin_turn(&id, "turn").is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failed_summary_leaves_the_session_unchanged() {
+        let config = config_for("http://127.0.0.1:1".to_string());
+        let manager = SessionManager::new(Database::new(":memory:").unwrap());
+        let id = session_with_history(&manager);
+
+        let error = run(&config, &manager, &id, 2).await.unwrap_err();
+        assert!(matches!(error, CompactError::Failed(_)), "{:?}", error);
+
+        let session = manager.get_session(&id).unwrap();
+        assert_eq!(session.messages.len(), 6);
+        assert_eq!(session.messages[0].sources.len(), 2);
+
+        let error = run(&config, &manager, "missing", 2).await.unwrap_err();
+        assert_eq!(error, CompactError::NotFound("missing".to_string()));
+    }
+}
@@ -1,11 +1,13 @@
 use log::{debug, info, warn};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use crate::agent::{
     AgentConfig, AgentPermissions, AgentsConfig, get_agents_dir, load_agents_from_dir,
 };
+use crate::error::SquidError;
 
 /// RAG (Retrieval-Augmented Generation) configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,6 +21,14 @@ pub struct RagConfig {
     /// Embedding API URL (typically same as api_url)
     #[serde(default = "default_embedding_url")]
     pub embedding_url: String,
+    /// Embedding API format (`openai` or `ollama` for the native `/api/embeddings`)
+    #[serde(default)]
+    pub embedding_provider: ProviderKind,
+    /// API key for the embedding service (falls back to the `EMBEDDING_API_KEY` env variable)
+    ///
+    /// Read from squid.config.json but never written back, like the legacy `api_key`.
+    #[serde(default, skip_serializing)]
+    pub embedding_api_key: Option<String>,
     /// Chunk size in tokens for document splitting
     #[serde(default = "default_chunk_size")]
     pub chunk_size: usize,
@@ -28,7 +38,13 @@ pub struct RagConfig {
     /// Number of top results to retrieve
     #[serde(default = "default_top_k")]
     pub top_k: usize,
-    /// Documents directory path (relative to current working directory)
+    /// Join retrieved chunks that are neighbors in the same document into one block
+    #[serde(default = "default_merge_adjacent")]
+    pub merge_adjacent: bool,
+    /// Reorder results by blending vector distance with query term overlap
+    #[serde(default)]
+    pub rerank: bool,
+    /// Documents directory path (relative to the project root)
     #[serde(default = "default_documents_path")]
     pub documents_path: String,
 }
@@ -57,6 +73,10 @@ fn default_top_k() -> usize {
     5
 }
 
+fn default_merge_adjacent() -> bool {
+    true
+}
+
 fn default_documents_path() -> String {
     "documents".to_string()
 }
@@ -67,14 +87,28 @@ impl Default for RagConfig {
             enabled: default_rag_enabled(),
             embedding_model: default_embedding_model(),
             embedding_url: default_embedding_url(),
+            embedding_provider: ProviderKind::default(),
+            embedding_api_key: None,
             chunk_size: default_chunk_size(),
             chunk_overlap: default_chunk_overlap(),
             top_k: default_top_k(),
+            merge_adjacent: default_merge_adjacent(),
+            rerank: false,
             documents_path: default_documents_path(),
         }
     }
 }
 
+impl RagConfig {
+    /// Embedding API key: `rag.embedding_api_key`, then `EMBEDDING_API_KEY`, else "not-needed"
+    pub fn embedding_api_key(&self) -> String {
+        self.embedding_api_key
+            .clone()
+            .or_else(|| std::env::var("EMBEDDING_API_KEY").ok())
+            .unwrap_or_else(|| "not-needed".to_string())
+    }
+}
+
 /// Plugin system configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PluginsConfig {
@@ -206,20 +240,159 @@ pub struct ServerConfig {
     /// When false, server binds to 127.0.0.1 (localhost only)
     #[serde(default = "default_allow_network")]
     pub allow_network: bool,
+    /// How long the provider's model list is cached by `/api/models`, in seconds
+    #[serde(default = "default_models_cache_ttl_seconds")]
+    pub models_cache_ttl_seconds: u64,
+    /// How long in-flight requests get to finish after Ctrl+C/SIGTERM, in seconds
+    #[serde(default = "default_shutdown_grace_seconds")]
+    pub shutdown_grace_seconds: u64,
+    /// Serve the Web UI from this directory instead of the embedded build
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub static_dir: Option<String>,
+    /// Maximum size of a JSON request body, in bytes
+    #[serde(default = "default_max_json_bytes")]
+    pub max_json_bytes: usize,
+    /// Maximum total size of the files attached to one chat request, in bytes
+    #[serde(default = "default_max_attachment_bytes")]
+    pub max_attachment_bytes: usize,
+    /// Maximum number of files attached to one chat request
+    #[serde(default = "default_max_attachments")]
+    pub max_attachments: usize,
+    /// How long streamed answer text is buffered before it's sent, in milliseconds (0 sends every delta)
+    #[serde(default = "default_stream_flush_ms")]
+    pub stream_flush_ms: u64,
+    /// Longest chat stream event sent as one SSE line, in bytes; longer events are split into
+    /// `chunked` events (0 never splits)
+    #[serde(default = "default_sse_max_event_bytes")]
+    pub sse_max_event_bytes: usize,
+    /// Send estimated `usage` events while an answer streams, before the provider reports usage
+    #[serde(default)]
+    pub live_usage_estimates: bool,
+    /// How long a chat request's idempotency key replays its answer, in seconds
+    #[serde(default = "default_idempotency_ttl_seconds")]
+    pub idempotency_ttl_seconds: u64,
+    /// Per-IP rate limits for `/api/chat` and `/api/rag/*`
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
 }
 
 fn default_allow_network() -> bool {
     false
 }
 
+fn default_models_cache_ttl_seconds() -> u64 {
+    60
+}
+
+fn default_shutdown_grace_seconds() -> u64 {
+    10
+}
+
+fn default_max_json_bytes() -> usize {
+    32 * 1024 * 1024
+}
+
+fn default_max_attachment_bytes() -> usize {
+    15 * 1024 * 1024
+}
+
+fn default_max_attachments() -> usize {
+    20
+}
+
+fn default_stream_flush_ms() -> u64 {
+    50
+}
+
+fn default_sse_max_event_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_idempotency_ttl_seconds() -> u64 {
+    24 * 60 * 60
+}
+
 impl Default for ServerConfig {
     fn default() -> Self {
         Self {
             allow_network: default_allow_network(),
+            models_cache_ttl_seconds: default_models_cache_ttl_seconds(),
+            shutdown_grace_seconds: default_shutdown_grace_seconds(),
+            static_dir: None,
+            max_json_bytes: default_max_json_bytes(),
+            max_attachment_bytes: default_max_attachment_bytes(),
+            max_attachments: default_max_attachments(),
+            stream_flush_ms: default_stream_flush_ms(),
+            sse_max_event_bytes: default_sse_max_event_bytes(),
+            live_usage_estimates: false,
+            idempotency_ttl_seconds: default_idempotency_ttl_seconds(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// JSON body limit actually enforced
+    ///
+    /// Raised above `max_json_bytes` when needed so a chat request within the attachment limits
+    /// isn't rejected for its size: escaping can double file contents, plus room for the rest
+    /// of the request.
+    pub fn json_body_limit(&self) -> usize {
+        self.max_json_bytes.max(
+            self.max_attachment_bytes
+                .saturating_mul(2)
+                .saturating_add(1024 * 1024),
+        )
+    }
+}
+
+/// Per-IP token-bucket rate limiting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Apply limits; when unset, limits apply only if `allow_network` is true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    /// Sustained `/api/chat` requests per minute per IP (0 = unlimited)
+    #[serde(default = "default_chat_per_minute")]
+    pub chat_per_minute: u32,
+    /// Sustained `/api/rag/*` requests per minute per IP (0 = unlimited)
+    #[serde(default = "default_rag_per_minute")]
+    pub rag_per_minute: u32,
+    /// Requests allowed at once before the per-minute rate applies
+    #[serde(default = "default_rate_limit_burst")]
+    pub burst: u32,
+}
+
+fn default_chat_per_minute() -> u32 {
+    10
+}
+
+fn default_rag_per_minute() -> u32 {
+    30
+}
+
+fn default_rate_limit_burst() -> u32 {
+    3
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: None,
+            chat_per_minute: default_chat_per_minute(),
+            rag_per_minute: default_rag_per_minute(),
+            burst: default_rate_limit_burst(),
         }
     }
 }
 
+impl RateLimitConfig {
+    /// Whether limits apply, given whether the server is reachable from the network
+    pub fn is_enabled(&self, allow_network: bool) -> bool {
+        self.enabled.unwrap_or(allow_network)
+    }
+}
+
 /// Background jobs configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JobsConfig {
@@ -280,232 +453,1075 @@ impl Default for JobsConfig {
     }
 }
 
-/// Configuration for squid CLI
-///
-/// This configuration is typically stored in `squid.config.json` in the project directory.
-///
-/// **Fields:**
-/// - `api_url`: Base URL for the LLM API (e.g., `http://127.0.0.1:1234/v1`)
-/// - `api_key`: Optional API key (use `None` for local models)
-/// - `context_window`: Maximum context window size in tokens (e.g., `32768` for Qwen2.5-Coder)
-/// - `log_level`: Console logging verbosity (`error`, `warn`, `info`, `debug`, `trace`)
-/// - `db_log_level`: Database logging verbosity (`error`, `warn`, `info`, `debug`, `trace`)
-/// - `version`: Config file version (matches app version when created)
-/// - `working_dir`: Working directory for file operations (default: `.`)
-///
-/// **Best Practices:**
-/// - Commit `squid.config.json` to your repository to share project settings with your team
-/// - Keep sensitive API keys in `.env` file (which is gitignored)
-/// - Use `api_key: None` in config file for local models (LM Studio, Ollama)
-/// - For cloud services (OpenAI, etc.), omit `api_key` from config and set it via `.env`
-/// - Default `log_level` is `error` (minimal console noise)
-/// - Default `db_log_level` is `debug` (capture detailed logs in database)
-/// - Use `.squidignore` file for project-wide ignore patterns
-///
-/// **Configuration Priority:**
-/// 1. `squid.config.json` (if exists) - project settings
-/// 2. `.env` variables (fallback) - sensitive credentials and overrides
+/// Response cache configuration (CLI `ask`/`review` only)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Config {
-    pub api_url: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub api_key: Option<String>,
-    #[serde(default = "default_context_window")]
-    pub context_window: u32,
-    #[serde(default = "default_log_level")]
-    pub log_level: String,
-    #[serde(default = "default_db_log_level")]
-    pub db_log_level: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub version: Option<String>,
-    #[serde(default = "default_database_path")]
-    pub database_path: String,
-    #[serde(default = "default_working_dir")]
-    pub working_dir: String,
-    #[serde(default)]
-    pub rag: RagConfig,
-    #[serde(default)]
-    pub plugins: PluginsConfig,
-    #[serde(default)]
-    pub server: ServerConfig,
-    #[serde(default)]
-    pub web: WebConfig,
-    #[serde(default)]
-    pub audio: AudioConfig,
-    #[serde(default)]
-    pub jobs: JobsConfig,
-    /// Default agent ID (agents are loaded from files, not from config)
-    #[serde(default = "default_agent_id")]
-    pub default_agent: String,
+pub struct CacheConfig {
+    /// Reuse responses for identical ask/review requests
+    #[serde(default = "default_cache_enabled")]
+    pub enabled: bool,
+    /// How long cached responses stay valid, in seconds
+    #[serde(default = "default_cache_ttl_seconds")]
+    pub ttl_seconds: i64,
+}
 
-    // Non-serialized fields
-    #[serde(skip)]
-    pub agents: AgentsConfig,
-    #[serde(skip)]
-    pub config_dir: Option<PathBuf>,
+fn default_cache_enabled() -> bool {
+    false
 }
 
-fn default_agent_id() -> String {
-    "general-assistant".to_string()
+fn default_cache_ttl_seconds() -> i64 {
+    86400 // 24 hours
 }
 
-fn default_context_window() -> u32 {
-    // Default to 8192 tokens (common for many local models)
-    // Users should override this based on their model's actual context window
-    8192
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_cache_enabled(),
+            ttl_seconds: default_cache_ttl_seconds(),
+        }
+    }
 }
 
-fn default_log_level() -> String {
-    "error".to_string()
+/// Database log storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    /// Log retention period in days, enforced periodically by `squid serve` (0 = keep forever)
+    #[serde(default = "default_log_retention_days")]
+    pub retention_days: i64,
 }
 
-fn default_db_log_level() -> String {
-    "debug".to_string()
+fn default_log_retention_days() -> i64 {
+    0
 }
 
-fn default_database_path() -> String {
-    "squid.db".to_string()
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: default_log_retention_days(),
+        }
+    }
 }
 
-fn default_working_dir() -> String {
-    "./workspace".to_string()
+/// Chat session storage configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionsConfig {
+    /// Delete sessions not updated for this many days, enforced periodically by `squid serve`
+    /// (0 = keep forever)
+    #[serde(default = "default_session_retention_days")]
+    pub retention_days: i64,
 }
 
-impl Default for Config {
+fn default_session_retention_days() -> i64 {
+    0
+}
+
+impl Default for SessionsConfig {
     fn default() -> Self {
         Self {
-            api_url: "http://127.0.0.1:1234/v1".to_string(),
-            api_key: None,
-            context_window: default_context_window(),
-            log_level: default_log_level(),
-            db_log_level: default_db_log_level(),
-            version: None,
-            database_path: default_database_path(),
-            working_dir: default_working_dir(),
-            rag: RagConfig::default(),
-            plugins: PluginsConfig::default(),
-            server: ServerConfig::default(),
-            web: WebConfig::default(),
-            audio: AudioConfig::default(),
-            jobs: JobsConfig::default(),
-            default_agent: default_agent_id(),
-            agents: AgentsConfig::default(),
-            config_dir: None,
+            retention_days: default_session_retention_days(),
         }
     }
 }
 
-impl Config {
-    /// Load configuration from squid.config.json if it exists, otherwise from environment variables
-    pub fn load() -> Self {
-        // Search for config file in current directory and parent directories
-        let config_path =
-            Self::find_config_file().unwrap_or_else(|| PathBuf::from("squid.config.json"));
-
-        let mut config = if config_path.exists() {
-            debug!("Loading configuration from squid.config.json");
-            match fs::read_to_string(&config_path) {
-                Ok(content) => match serde_json::from_str::<Config>(&content) {
-                    Ok(mut config) => {
-                        // Check version and warn if outdated
-                        if let Some(warning) = config.version_warning() {
-                            eprintln!("\n{}\n", warning);
-                        }
+impl SessionsConfig {
+    /// Maximum session age in seconds, or `None` when sessions are kept forever
+    pub fn retention_seconds(&self) -> Option<i64> {
+        (self.retention_days > 0).then(|| self.retention_days * 24 * 60 * 60)
+    }
+}
 
-                        // Resolve database_path relative to config file directory
-                        let db_path = PathBuf::from(&config.database_path);
-                        if db_path.is_relative() {
-                            // Get the directory containing the config file
-                            let config_dir = config_path
-                                .parent()
-                                .unwrap_or_else(|| std::path::Path::new("."));
-
-                            // Resolve database path relative to config directory
-                            let absolute_db_path = config_dir.join(&config.database_path);
-
-                            // Convert to string, using the original if conversion fails
-                            if let Some(path_str) = absolute_db_path.to_str() {
-                                config.database_path = path_str.to_string();
-                                debug!("Resolved database path to: {}", config.database_path);
-                            }
-                        }
+/// Tool execution configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolsConfig {
+    /// How long the web UI waits for the user to approve or reject a tool call
+    #[serde(default = "default_approval_timeout_secs")]
+    pub approval_timeout_secs: u64,
+    /// Rounds of tool calls per user turn before the model is told to answer (0 = unlimited)
+    #[serde(default = "default_max_tool_rounds")]
+    pub max_rounds: usize,
+    /// Total time tools may run per user turn, in seconds (0 = unlimited)
+    #[serde(default = "default_tool_time_budget_secs")]
+    pub time_budget_secs: u64,
+    /// Most matches `grep` and `find_files` return, whatever `max_results` the model asks for
+    #[serde(default = "default_tool_max_results")]
+    pub max_results: usize,
+    /// Settings for the bash tool
+    #[serde(default)]
+    pub bash: BashConfig,
+    /// Markdown file with extra prose per tool, under `## <tool name>` headings, appended to the
+    /// generated tool documentation (relative to the project root)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes_file: Option<String>,
+}
 
-                        config
-                    }
-                    Err(e) => {
-                        debug!("Failed to parse squid.config.json: {}", e);
-                        Self::default()
-                    }
-                },
-                Err(e) => {
-                    debug!("Failed to read squid.config.json: {}", e);
-                    Self::default()
-                }
-            }
-        } else {
-            debug!("No squid.config.json found, using defaults");
-            Self::default()
-        };
+/// Bash tool configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BashConfig {
+    /// Environment variables passed to commands besides PATH, HOME, LANG and TERM
+    ///
+    /// Names ending in `_KEY`, `_TOKEN` or `_SECRET` are never passed, even when listed here.
+    /// Ignored in the restricted sandbox.
+    #[serde(default)]
+    pub env_passthrough: Vec<String>,
+    /// How commands are isolated from the machine they run on
+    #[serde(default)]
+    pub sandbox: SandboxMode,
+    /// Hard wall-clock limit for sandboxed commands in seconds, regardless of the tool timeout
+    #[serde(default = "default_sandbox_time_limit_secs")]
+    pub sandbox_time_limit_secs: u64,
+}
 
-        // Environment variables override config file settings
-        debug!("Applying environment variable overrides");
+fn default_sandbox_time_limit_secs() -> u64 {
+    60
+}
 
-        if let Ok(api_url) = std::env::var("API_URL") {
-            debug!("Overriding API_URL from environment");
-            config.api_url = api_url;
+impl Default for BashConfig {
+    fn default() -> Self {
+        Self {
+            env_passthrough: Vec::new(),
+            sandbox: SandboxMode::default(),
+            sandbox_time_limit_secs: default_sandbox_time_limit_secs(),
         }
+    }
+}
 
-        if let Ok(api_key) = std::env::var("API_KEY") {
-            debug!("Overriding API_KEY from environment");
-            config.api_key = Some(api_key);
-        }
+/// Isolation of bash tool commands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxMode {
+    /// Run commands directly in the working directory
+    #[default]
+    None,
+    /// Run commands with a stripped environment and a read-only working directory
+    /// (see `crate::sandbox`)
+    Restricted,
+}
 
-        if let Ok(context_window) = std::env::var("SQUID_CONTEXT_WINDOW")
-            && let Ok(window) = context_window.parse()
-        {
-            debug!("Overriding SQUID_CONTEXT_WINDOW from environment");
-            config.context_window = window;
-        }
+fn default_approval_timeout_secs() -> u64 {
+    300
+}
 
-        if let Ok(log_level) = std::env::var("SQUID_LOG_LEVEL") {
-            debug!("Overriding SQUID_LOG_LEVEL from environment");
-            config.log_level = log_level;
-        }
+fn default_max_tool_rounds() -> usize {
+    8
+}
 
-        if let Ok(db_log_level) = std::env::var("SQUID_DB_LOG_LEVEL") {
-            debug!("Overriding SQUID_DB_LOG_LEVEL from environment");
-            config.db_log_level = db_log_level;
-        }
+fn default_tool_time_budget_secs() -> u64 {
+    300
+}
 
-        if let Ok(db_path) = std::env::var("SQUID_DATABASE_PATH") {
-            debug!("Overriding SQUID_DATABASE_PATH from environment");
-            config.database_path = db_path;
-        }
+fn default_tool_max_results() -> usize {
+    200
+}
 
-        if let Ok(working_dir) = std::env::var("SQUID_WORKING_DIR") {
-            debug!("Overriding SQUID_WORKING_DIR from environment");
-            config.working_dir = working_dir;
-        }
+impl ToolsConfig {
+    /// Approval wait timeout (at least one second)
+    pub fn approval_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.approval_timeout_secs.max(1))
+    }
 
-        // RAG configuration overrides
-        if let Ok(rag_enabled) = std::env::var("SQUID_RAG_ENABLED")
-            && let Ok(enabled) = rag_enabled.parse()
-        {
-            debug!("Overriding SQUID_RAG_ENABLED from environment");
-            config.rag.enabled = enabled;
-        }
+    /// Per-turn tool execution budget, or `None` when unlimited
+    pub fn time_budget(&self) -> Option<std::time::Duration> {
+        (self.time_budget_secs > 0).then(|| std::time::Duration::from_secs(self.time_budget_secs))
+    }
 
-        if let Ok(embedding_model) = std::env::var("SQUID_EMBEDDING_MODEL") {
-            debug!("Overriding SQUID_EMBEDDING_MODEL from environment");
-            config.rag.embedding_model = embedding_model;
-        }
+    /// `max_results` of a search tool call, `default` when unset, within 1..=`max_results`
+    pub fn search_limit(&self, args: &serde_json::Value, default: usize) -> usize {
+        let limit = self.max_results.max(1);
+        args["max_results"]
+            .as_u64()
+            .map_or(default, |requested| requested.min(limit as u64) as usize)
+            .clamp(1, limit)
+    }
 
-        if let Ok(embedding_url) = std::env::var("SQUID_EMBEDDING_URL") {
-            debug!("Overriding SQUID_EMBEDDING_URL from environment");
-            config.rag.embedding_url = embedding_url;
+    /// How often expired approvals are swept: half the timeout, between 1 and 60 seconds
+    pub fn approval_cleanup_interval(&self) -> std::time::Duration {
+        std::time::Duration::from_secs((self.approval_timeout_secs / 2).clamp(1, 60))
+    }
+}
+
+impl Default for ToolsConfig {
+    fn default() -> Self {
+        Self {
+            approval_timeout_secs: default_approval_timeout_secs(),
+            max_rounds: default_max_tool_rounds(),
+            time_budget_secs: default_tool_time_budget_secs(),
+            max_results: default_tool_max_results(),
+            bash: BashConfig::default(),
+            notes_file: None,
         }
+    }
+}
 
-        if let Ok(chunk_size) = std::env::var("SQUID_RAG_CHUNK_SIZE")
+/// Debugging aids for the web chat
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DebugConfig {
+    /// Store every provider request (redacted) in `request_traces` for the prompt inspector
+    #[serde(default)]
+    pub capture_requests: bool,
+    /// Delete captured requests after this many days, enforced hourly by `squid serve`
+    /// (0 = keep forever)
+    #[serde(default = "default_trace_retention_days")]
+    pub trace_retention_days: i64,
+    /// Long strings in a captured request are shortened to keep it under this size
+    #[serde(default = "default_max_trace_bytes")]
+    pub max_trace_bytes: usize,
+}
+
+fn default_trace_retention_days() -> i64 {
+    7
+}
+
+fn default_max_trace_bytes() -> usize {
+    256 * 1024
+}
+
+impl Default for DebugConfig {
+    fn default() -> Self {
+        Self {
+            capture_requests: false,
+            trace_retention_days: default_trace_retention_days(),
+            max_trace_bytes: default_max_trace_bytes(),
+        }
+    }
+}
+
+impl DebugConfig {
+    /// Maximum age of captured requests in seconds, or `None` when they are kept forever
+    pub fn trace_retention_seconds(&self) -> Option<i64> {
+        (self.trace_retention_days > 0).then(|| self.trace_retention_days * 24 * 60 * 60)
+    }
+}
+
+/// How much a reasoning model thinks before answering
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReasoningEffort {
+    Low,
+    Medium,
+    High,
+}
+
+impl ReasoningEffort {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReasoningEffort::Low => "low",
+            ReasoningEffort::Medium => "medium",
+            ReasoningEffort::High => "high",
+        }
+    }
+}
+
+/// Reasoning controls for models that take them (o-series `reasoning_effort`, Qwen3 `enable_thinking`)
+///
+/// Unset fields leave the model's own default in place.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReasoningConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub effort: Option<ReasoningEffort>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
+
+impl ReasoningConfig {
+    /// Fields set in `self` win over `defaults`
+    pub fn or(self, defaults: ReasoningConfig) -> Self {
+        Self {
+            effort: self.effort.or(defaults.effort),
+            enabled: self.enabled.or(defaults.enabled),
+        }
+    }
+
+    /// Whether reasoning was explicitly turned off
+    pub fn is_disabled(&self) -> bool {
+        self.enabled == Some(false)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.effort.is_none() && self.enabled.is_none()
+    }
+}
+
+/// Defaults for generation parameters sent with every chat request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GenerationConfig {
+    /// Overridden per request by the `reasoning` field of a chat request
+    #[serde(default, skip_serializing_if = "ReasoningConfig::is_empty")]
+    pub reasoning: ReasoningConfig,
+}
+
+/// Longest an outbound HTTP connection may take in offline mode
+pub const OFFLINE_HTTP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Outbound network behaviour
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Air-gapped mode: only the configured provider is contacted, and unreachable servers
+    /// fail after [`OFFLINE_HTTP_TIMEOUT`] instead of hanging
+    #[serde(default)]
+    pub offline: bool,
+}
+
+impl NetworkConfig {
+    /// HTTP client for the provider and embedding servers
+    ///
+    /// Offline, connecting gives up after [`OFFLINE_HTTP_TIMEOUT`]; responses are not limited,
+    /// since a local model may take a while to answer.
+    pub fn http_client(&self) -> reqwest::Client {
+        let mut builder = reqwest::Client::builder();
+        if self.offline {
+            builder = builder.connect_timeout(OFFLINE_HTTP_TIMEOUT);
+        }
+        builder.build().unwrap_or_default()
+    }
+}
+
+/// Config file names looked up in each directory, in this order; the first one found is used
+pub const CONFIG_FILE_NAMES: &[&str] = &[
+    "squid.config.json",
+    "squid.config.toml",
+    "squid.config.yaml",
+    "squid.config.yml",
+];
+
+/// Config file names looked up in the global config directory, in this order
+pub const GLOBAL_CONFIG_FILE_NAMES: &[&str] =
+    &["config.json", "config.toml", "config.yaml", "config.yml"];
+
+/// Format of a config file, chosen by its extension
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// The file name `squid init` creates for this format
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "squid.config.json",
+            ConfigFormat::Toml => "squid.config.toml",
+            ConfigFormat::Yaml => "squid.config.yaml",
+        }
+    }
+
+    /// The file name `squid init --global` creates for this format
+    pub fn global_file_name(self) -> &'static str {
+        match self {
+            ConfigFormat::Json => "config.json",
+            ConfigFormat::Toml => "config.toml",
+            ConfigFormat::Yaml => "config.yaml",
+        }
+    }
+
+    /// The format of a config file; unknown extensions are read as JSON
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml" | "yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
+    }
+
+    pub fn parse<T: serde::de::DeserializeOwned>(self, content: &str) -> Result<T, SquidError> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::from_str(content)?,
+            ConfigFormat::Toml => toml::from_str(content)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(content)?,
+        })
+    }
+
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<String, SquidError> {
+        Ok(match self {
+            ConfigFormat::Json => serde_json::to_string_pretty(value)?,
+            ConfigFormat::Toml => toml::to_string_pretty(value)?,
+            ConfigFormat::Yaml => serde_yaml::to_string(value)?,
+        })
+    }
+}
+
+impl std::fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigFormat::Json => "json",
+            ConfigFormat::Toml => "toml",
+            ConfigFormat::Yaml => "yaml",
+        })
+    }
+}
+
+/// Overrides for the model metadata shipped with squid (tool support, reasoning, context length)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelsConfig {
+    /// JSON file in the `model-metadata.json` format, relative to the project root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata_path: Option<String>,
+    /// Entries applied after those in `metadata_path`, for one-off tweaks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<crate::models::ModelOverride>,
+}
+
+/// A named project root served by the web UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorkspaceConfig {
+    pub name: String,
+    /// `~` is the home directory; relative paths resolve against the project root
+    pub path: String,
+}
+
+/// A prompt `squid serve` runs on a schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleConfig {
+    pub name: String,
+    /// Cron expression with seconds: `sec min hour day month weekday`, e.g. `0 0 8 * * Mon-Fri`
+    pub cron: String,
+    /// Prompt sent as the user message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    /// File holding the prompt, relative to the project root; used when `prompt` is unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_file: Option<String>,
+    /// Agent that answers (default: `default_agent`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+    /// Replaces the agent's allow list for scheduled runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<String>>,
+    /// Whether each run starts a new session or continues the schedule's latest one
+    #[serde(default)]
+    pub session: ScheduleSessionMode,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScheduleSessionMode {
+    #[default]
+    New,
+    Append,
+}
+
+/// A conversation sessions can start from, with `squid new` or the web UI
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TemplateConfig {
+    pub name: String,
+    /// Shown next to the name when picking a template
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// System prompt of the session's turns, in place of the agent's prompt
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+    /// Bundled prompt used when `system_prompt` is unset: `ask`, `code-review` or `review-<language>`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_name: Option<String>,
+    /// First user message; `{{name}}` placeholders are filled from the variables given
+    pub initial_user_message: String,
+    /// Globs of project files attached to the first message, added to `context.auto_include`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_include: Vec<String>,
+    /// Agent that answers (default: `default_agent`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub agent: Option<String>,
+}
+
+/// Project instructions read from `SQUID.md` (or `AGENTS.md`) in the project root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectInstructionsConfig {
+    /// Append the instructions to system prompts
+    #[serde(default = "default_project_instructions_enabled")]
+    pub enabled: bool,
+    /// Longer files are truncated to this many tokens
+    #[serde(default = "default_project_instructions_max_tokens")]
+    pub max_tokens: usize,
+}
+
+fn default_project_instructions_enabled() -> bool {
+    true
+}
+
+fn default_project_instructions_max_tokens() -> usize {
+    8000
+}
+
+impl Default for ProjectInstructionsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_project_instructions_enabled(),
+            max_tokens: default_project_instructions_max_tokens(),
+        }
+    }
+}
+
+/// What the environment section of system prompts says about where the assistant works
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EnvContextConfig {
+    /// The directory tools run in
+    #[serde(default = "default_env_context_field")]
+    pub working_dir: bool,
+    /// The current git branch, when the directory is inside a repository
+    #[serde(default = "default_env_context_field")]
+    pub git_branch: bool,
+    /// The short SHA of HEAD
+    #[serde(default = "default_env_context_field")]
+    pub git_commit: bool,
+    /// Whether the working tree has uncommitted changes
+    #[serde(default = "default_env_context_field")]
+    pub git_status: bool,
+}
+
+fn default_env_context_field() -> bool {
+    true
+}
+
+impl Default for EnvContextConfig {
+    fn default() -> Self {
+        Self {
+            working_dir: default_env_context_field(),
+            git_branch: default_env_context_field(),
+            git_commit: default_env_context_field(),
+            git_status: default_env_context_field(),
+        }
+    }
+}
+
+/// Project files attached to the first message of every new session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContextConfig {
+    /// Globs resolved against the project root, e.g. `["ARCHITECTURE.md", "docs/api/*.md"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auto_include: Vec<String>,
+    /// Larger matched files are skipped, in bytes
+    #[serde(default = "default_auto_include_max_file_bytes")]
+    pub max_file_bytes: usize,
+    /// Share of the model's context window the files may use together; the largest files are
+    /// left out until the rest fits
+    #[serde(default = "default_auto_include_budget")]
+    pub budget: f64,
+    /// Which earlier turns of a web session are sent with a new message
+    #[serde(default)]
+    pub strategy: HistoryStrategy,
+    /// Latest turns always sent with the `recent` and `relevant` strategies
+    #[serde(default = "default_recent_turns")]
+    pub recent_turns: usize,
+    /// Share of the model's context window the earlier turns may use with `relevant`
+    #[serde(default = "default_history_budget")]
+    pub history_budget: f64,
+}
+
+/// How the history of a session is replayed (see `crate::history`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryStrategy {
+    /// Every earlier message, in order
+    #[default]
+    Full,
+    /// Only the latest `context.recent_turns` turns
+    Recent,
+    /// The latest turns plus the older turns most similar to the new message
+    Relevant,
+}
+
+fn default_auto_include_max_file_bytes() -> usize {
+    256 * 1024
+}
+
+fn default_auto_include_budget() -> f64 {
+    0.25
+}
+
+fn default_recent_turns() -> usize {
+    4
+}
+
+fn default_history_budget() -> f64 {
+    0.5
+}
+
+impl Default for ContextConfig {
+    fn default() -> Self {
+        Self {
+            auto_include: Vec::new(),
+            max_file_bytes: default_auto_include_max_file_bytes(),
+            budget: default_auto_include_budget(),
+            strategy: HistoryStrategy::default(),
+            recent_turns: default_recent_turns(),
+            history_budget: default_history_budget(),
+        }
+    }
+}
+
+/// Webhooks called when chat events happen (e.g. a Slack or ntfy URL)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationsConfig {
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+}
+
+/// A webhook URL and the events it receives
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    /// Receives a JSON POST per event; treated as a secret and never logged in full
+    pub url: String,
+    /// Events to send (empty = all events)
+    #[serde(default)]
+    pub events: Vec<NotificationEvent>,
+}
+
+impl WebhookConfig {
+    pub fn wants(&self, event: NotificationEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Chat events that can trigger a webhook
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    /// A tool call is waiting for approval
+    ApprovalRequested,
+    /// The assistant finished answering
+    TurnCompleted,
+    /// A chat turn failed
+    Error,
+}
+
+impl NotificationEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NotificationEvent::ApprovalRequested => "approval_requested",
+            NotificationEvent::TurnCompleted => "turn_completed",
+            NotificationEvent::Error => "error",
+        }
+    }
+}
+
+/// Path security configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Honor .gitignore files and .git/info/exclude in addition to .squidignore
+    #[serde(default = "default_respect_gitignore")]
+    pub respect_gitignore: bool,
+    /// Ask before `read_file` and `grep` calls outside the allow list instead of refusing them
+    #[serde(default)]
+    pub ask_for_reads: bool,
+}
+
+fn default_respect_gitignore() -> bool {
+    true
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            respect_gitignore: default_respect_gitignore(),
+            ask_for_reads: false,
+        }
+    }
+}
+
+/// What the server keeps about who sent a request
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PrivacyConfig {
+    /// Store client addresses truncated to their network (/24 for IPv4, /48 for IPv6)
+    #[serde(default)]
+    pub redact_ips: bool,
+}
+
+/// Wire format used to talk to the LLM API
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI-compatible `/chat/completions` (LM Studio, OpenAI, Ollama's `/v1`, ...)
+    #[default]
+    OpenAi,
+    /// Ollama's native `/api/chat`
+    Ollama,
+}
+
+impl ProviderKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::Ollama => "ollama",
+        }
+    }
+}
+
+impl std::str::FromStr for ProviderKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "openai" => Ok(Self::OpenAi),
+            "ollama" => Ok(Self::Ollama),
+            other => Err(format!(
+                "Unknown provider kind '{}' (expected 'openai' or 'ollama')",
+                other
+            )),
+        }
+    }
+}
+
+/// Configuration for squid CLI
+///
+/// This configuration is typically stored in `squid.config.json` in the project directory.
+///
+/// **Fields:**
+/// - `api_url`: Base URL for the LLM API (e.g., `http://127.0.0.1:1234/v1`)
+/// - `api_key`: Legacy API key field, read for compatibility but never saved (use `.env` or the keychain)
+/// - `provider_kind`: API wire format (`openai` or `ollama`, default: `openai`)
+/// - `context_window`: Maximum context window size in tokens (e.g., `32768` for Qwen2.5-Coder)
+/// - `log_level`: Console logging verbosity (`error`, `warn`, `info`, `debug`, `trace`)
+/// - `db_log_level`: Database logging verbosity (`error`, `warn`, `info`, `debug`, `trace`)
+/// - `version`: Config file version (matches app version when created)
+/// - `working_dir`: Working directory for file operations (default: `.`)
+///
+/// **Best Practices:**
+/// - Commit `squid.config.json` to your repository to share project settings with your team
+/// - Keep sensitive API keys in `.env` file (which is gitignored)
+/// - API keys are never written to `squid.config.json`; `squid config set-key` stores them in
+///   `.env` or the OS keychain
+/// - Default `log_level` is `error` (minimal console noise)
+/// - Default `db_log_level` is `debug` (capture detailed logs in database)
+/// - Use `.squidignore` file for project-wide ignore patterns
+///
+/// **Configuration Priority:**
+/// 1. `squid.config.json` (if exists) - project settings
+/// 2. `.env` variables (fallback) - sensitive credentials and overrides
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    pub api_url: String,
+    /// Legacy: keys in squid.config.json are still read but never written back
+    #[serde(default, skip_serializing)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub provider_kind: ProviderKind,
+    #[serde(default = "default_context_window")]
+    pub context_window: u32,
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    #[serde(default = "default_db_log_level")]
+    pub db_log_level: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default = "default_database_path")]
+    pub database_path: String,
+    #[serde(default = "default_working_dir")]
+    pub working_dir: String,
+    #[serde(default)]
+    pub rag: RagConfig,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub web: WebConfig,
+    #[serde(default)]
+    pub audio: AudioConfig,
+    #[serde(default)]
+    pub jobs: JobsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+    #[serde(default)]
+    pub debug: DebugConfig,
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    #[serde(default)]
+    pub generation: GenerationConfig,
+    #[serde(default)]
+    pub project_instructions: ProjectInstructionsConfig,
+    #[serde(default)]
+    pub env_context: EnvContextConfig,
+    #[serde(default)]
+    pub context: ContextConfig,
+    /// Values for `{{name}}` placeholders in system prompts, agent prompts and SQUID.md
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub prompt_vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub models: ModelsConfig,
+    /// Named project roots the web UI can switch between
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub workspaces: Vec<WorkspaceConfig>,
+    /// Prompts run on a schedule while `squid serve` is running
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleConfig>,
+    /// Conversations new sessions can start from
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<TemplateConfig>,
+    /// Default agent ID (agents are loaded from files, not from config)
+    #[serde(default = "default_agent_id")]
+    pub default_agent: String,
+    /// Tool permissions saved from approval prompts, merged into each agent's allow list
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub agent_permissions: HashMap<String, AgentPermissions>,
+    /// Keys this version doesn't know, kept so saving doesn't drop settings of newer versions
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+
+    // Non-serialized fields
+    #[serde(skip)]
+    pub agents: AgentsConfig,
+    /// Project root: directory containing the found config file, else the CWD
+    #[serde(skip)]
+    pub config_dir: Option<PathBuf>,
+    /// Format of the loaded config file, used when no file exists yet to save to
+    #[serde(skip)]
+    pub config_format: ConfigFormat,
+    /// Config file the settings were read from, project or global
+    #[serde(skip)]
+    pub config_file: Option<PathBuf>,
+    /// Scenario file answered from instead of the model (`squid serve --mock-llm`)
+    #[serde(skip)]
+    pub mock_llm: Option<PathBuf>,
+}
+
+fn default_agent_id() -> String {
+    "general-assistant".to_string()
+}
+
+fn default_context_window() -> u32 {
+    // Default to 8192 tokens (common for many local models)
+    // Users should override this based on their model's actual context window
+    8192
+}
+
+fn default_log_level() -> String {
+    "error".to_string()
+}
+
+fn default_db_log_level() -> String {
+    "debug".to_string()
+}
+
+fn default_database_path() -> String {
+    "squid.db".to_string()
+}
+
+fn default_working_dir() -> String {
+    "./workspace".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_url: "http://127.0.0.1:1234/v1".to_string(),
+            api_key: None,
+            provider_kind: ProviderKind::default(),
+            context_window: default_context_window(),
+            log_level: default_log_level(),
+            db_log_level: default_db_log_level(),
+            version: None,
+            database_path: default_database_path(),
+            working_dir: default_working_dir(),
+            rag: RagConfig::default(),
+            plugins: PluginsConfig::default(),
+            server: ServerConfig::default(),
+            web: WebConfig::default(),
+            audio: AudioConfig::default(),
+            jobs: JobsConfig::default(),
+            cache: CacheConfig::default(),
+            logging: LoggingConfig::default(),
+            sessions: SessionsConfig::default(),
+            security: SecurityConfig::default(),
+            privacy: PrivacyConfig::default(),
+            tools: ToolsConfig::default(),
+            debug: DebugConfig::default(),
+            notifications: NotificationsConfig::default(),
+            generation: GenerationConfig::default(),
+            project_instructions: ProjectInstructionsConfig::default(),
+            env_context: EnvContextConfig::default(),
+            context: ContextConfig::default(),
+            prompt_vars: BTreeMap::new(),
+            network: NetworkConfig::default(),
+            models: ModelsConfig::default(),
+            workspaces: Vec::new(),
+            schedules: Vec::new(),
+            templates: Vec::new(),
+            default_agent: default_agent_id(),
+            agent_permissions: HashMap::new(),
+            extra: serde_json::Map::new(),
+            agents: AgentsConfig::default(),
+            config_dir: None,
+            config_format: ConfigFormat::default(),
+            config_file: None,
+            mock_llm: None,
+        }
+    }
+}
+
+impl Config {
+    /// Load configuration from the project's config file if it exists, else from the global
+    /// config file, otherwise from environment variables
+    pub fn load() -> Self {
+        let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        Self::load_from(&current_dir)
+    }
+
+    /// Load configuration, searching for a config file from `start_dir` upwards
+    pub fn load_from(start_dir: &Path) -> Self {
+        Self::load_with_globals(
+            start_dir,
+            Self::global_config_dir().as_deref(),
+            Self::global_data_dir().as_deref(),
+        )
+    }
+
+    /// Load configuration with explicit global config and data directories
+    ///
+    /// A project config wins; the global config is only read when no project config is found.
+    /// Outside a project, a relative `database_path` lives in the global data directory.
+    fn load_with_globals(
+        start_dir: &Path,
+        global_config_dir: Option<&Path>,
+        global_data_dir: Option<&Path>,
+    ) -> Self {
+        // Search for config file in the start directory and parent directories
+        let project_config = Self::find_config_file_from(start_dir);
+        let in_project = project_config.is_some();
+        let config_path = project_config
+            .or_else(|| global_config_dir.and_then(Self::global_config_file_in))
+            .unwrap_or_else(|| start_dir.join(ConfigFormat::default().file_name()));
+        let format = ConfigFormat::from_path(&config_path);
+
+        let mut config = if config_path.exists() {
+            debug!("Loading configuration from {:?}", config_path);
+            match fs::read_to_string(&config_path) {
+                Ok(content) => match format.parse::<Config>(&content) {
+                    Ok(mut config) => {
+                        config.config_format = format;
+                        config.config_file = Some(config_path.clone());
+
+                        // Check version and warn if outdated
+                        if let Some(warning) = config.version_warning() {
+                            eprintln!("\n{}\n", warning);
+                        }
+
+                        if config.api_key.is_some() {
+                            eprintln!(
+                                "\n⚠️  {:?} contains a plain-text `api_key`.\n   \
+                                 Move it to .env or the OS keychain with `squid config set-key`; \
+                                 it will be dropped from the file the next time the config is saved.\n",
+                                config_path
+                            );
+                        }
+
+                        config
+                    }
+                    Err(e) => {
+                        debug!("Failed to parse {:?}: {}", config_path, e);
+                        Self::default()
+                    }
+                },
+                Err(e) => {
+                    debug!("Failed to read {:?}: {}", config_path, e);
+                    Self::default()
+                }
+            }
+        } else {
+            debug!("No config file found, using defaults");
+            Self::default()
+        };
+
+        // Resolve database_path relative to the project, or to the global data directory
+        let db_path = PathBuf::from(&config.database_path);
+        if db_path.is_relative() {
+            let data_dir = if in_project {
+                config_path.parent().map(Path::to_path_buf)
+            } else {
+                global_data_dir.map(Path::to_path_buf)
+            };
+            if let Some(data_dir) = data_dir {
+                if !in_project && let Err(e) = fs::create_dir_all(&data_dir) {
+                    debug!("Failed to create data directory {:?}: {}", data_dir, e);
+                }
+
+                // Convert to string, using the original if conversion fails
+                if let Some(path_str) = data_dir.join(&db_path).to_str() {
+                    config.database_path = path_str.to_string();
+                    debug!("Resolved database path to: {}", config.database_path);
+                }
+            }
+        }
+
+        // Environment variables override config file settings
+        debug!("Applying environment variable overrides");
+
+        if let Ok(api_url) = std::env::var("API_URL") {
+            debug!("Overriding API_URL from environment");
+            config.api_url = api_url;
+        }
+
+        if let Ok(provider_kind) = std::env::var("SQUID_PROVIDER_KIND")
+            && let Ok(kind) = provider_kind.parse()
+        {
+            debug!("Overriding SQUID_PROVIDER_KIND from environment");
+            config.provider_kind = kind;
+        }
+
+        if let Ok(context_window) = std::env::var("SQUID_CONTEXT_WINDOW")
+            && let Ok(window) = context_window.parse()
+        {
+            debug!("Overriding SQUID_CONTEXT_WINDOW from environment");
+            config.context_window = window;
+        }
+
+        if let Ok(log_level) = std::env::var("SQUID_LOG_LEVEL") {
+            debug!("Overriding SQUID_LOG_LEVEL from environment");
+            config.log_level = log_level;
+        }
+
+        if let Ok(db_log_level) = std::env::var("SQUID_DB_LOG_LEVEL") {
+            debug!("Overriding SQUID_DB_LOG_LEVEL from environment");
+            config.db_log_level = db_log_level;
+        }
+
+        if let Ok(db_path) = std::env::var("SQUID_DATABASE_PATH") {
+            debug!("Overriding SQUID_DATABASE_PATH from environment");
+            config.database_path = db_path;
+        }
+
+        if let Ok(working_dir) = std::env::var("SQUID_WORKING_DIR") {
+            debug!("Overriding SQUID_WORKING_DIR from environment");
+            config.working_dir = working_dir;
+        }
+
+        // RAG configuration overrides
+        if let Ok(rag_enabled) = std::env::var("SQUID_RAG_ENABLED")
+            && let Ok(enabled) = rag_enabled.parse()
+        {
+            debug!("Overriding SQUID_RAG_ENABLED from environment");
+            config.rag.enabled = enabled;
+        }
+
+        if let Ok(embedding_model) = std::env::var("SQUID_EMBEDDING_MODEL") {
+            debug!("Overriding SQUID_EMBEDDING_MODEL from environment");
+            config.rag.embedding_model = embedding_model;
+        }
+
+        if let Ok(embedding_url) = std::env::var("SQUID_EMBEDDING_URL") {
+            debug!("Overriding SQUID_EMBEDDING_URL from environment");
+            config.rag.embedding_url = embedding_url;
+        }
+
+        if let Ok(embedding_provider) = std::env::var("SQUID_EMBEDDING_PROVIDER")
+            && let Ok(kind) = embedding_provider.parse()
+        {
+            debug!("Overriding SQUID_EMBEDDING_PROVIDER from environment");
+            config.rag.embedding_provider = kind;
+        }
+
+        if let Ok(chunk_size) = std::env::var("SQUID_RAG_CHUNK_SIZE")
             && let Ok(size) = chunk_size.parse()
         {
             debug!("Overriding SQUID_RAG_CHUNK_SIZE from environment");
@@ -526,6 +1542,20 @@ impl Config {
             config.rag.top_k = k;
         }
 
+        if let Ok(merge) = std::env::var("SQUID_RAG_MERGE_ADJACENT")
+            && let Ok(merge) = merge.parse()
+        {
+            debug!("Overriding SQUID_RAG_MERGE_ADJACENT from environment");
+            config.rag.merge_adjacent = merge;
+        }
+
+        if let Ok(rerank) = std::env::var("SQUID_RAG_RERANK")
+            && let Ok(rerank) = rerank.parse()
+        {
+            debug!("Overriding SQUID_RAG_RERANK from environment");
+            config.rag.rerank = rerank;
+        }
+
         if let Ok(docs_path) = std::env::var("SQUID_RAG_DOCUMENTS_PATH") {
             debug!("Overriding SQUID_RAG_DOCUMENTS_PATH from environment");
             config.rag.documents_path = docs_path;
@@ -539,6 +1569,95 @@ impl Config {
             config.server.allow_network = enabled;
         }
 
+        if let Ok(ttl) = std::env::var("SQUID_SERVER_MODELS_CACHE_TTL_SECONDS")
+            && let Ok(seconds) = ttl.parse()
+        {
+            debug!("Overriding SQUID_SERVER_MODELS_CACHE_TTL_SECONDS from environment");
+            config.server.models_cache_ttl_seconds = seconds;
+        }
+
+        if let Ok(grace) = std::env::var("SQUID_SERVER_SHUTDOWN_GRACE_SECONDS")
+            && let Ok(seconds) = grace.parse()
+        {
+            debug!("Overriding SQUID_SERVER_SHUTDOWN_GRACE_SECONDS from environment");
+            config.server.shutdown_grace_seconds = seconds;
+        }
+
+        if let Ok(static_dir) = std::env::var("SQUID_SERVER_STATIC_DIR") {
+            debug!("Overriding SQUID_SERVER_STATIC_DIR from environment");
+            config.server.static_dir = Some(static_dir);
+        }
+
+        if let Ok(max_json_bytes) = std::env::var("SQUID_SERVER_MAX_JSON_BYTES")
+            && let Ok(bytes) = max_json_bytes.parse()
+        {
+            debug!("Overriding SQUID_SERVER_MAX_JSON_BYTES from environment");
+            config.server.max_json_bytes = bytes;
+        }
+
+        if let Ok(max_attachment_bytes) = std::env::var("SQUID_SERVER_MAX_ATTACHMENT_BYTES")
+            && let Ok(bytes) = max_attachment_bytes.parse()
+        {
+            debug!("Overriding SQUID_SERVER_MAX_ATTACHMENT_BYTES from environment");
+            config.server.max_attachment_bytes = bytes;
+        }
+
+        if let Ok(max_attachments) = std::env::var("SQUID_SERVER_MAX_ATTACHMENTS")
+            && let Ok(count) = max_attachments.parse()
+        {
+            debug!("Overriding SQUID_SERVER_MAX_ATTACHMENTS from environment");
+            config.server.max_attachments = count;
+        }
+
+        if let Ok(stream_flush_ms) = std::env::var("SQUID_SERVER_STREAM_FLUSH_MS")
+            && let Ok(ms) = stream_flush_ms.parse()
+        {
+            debug!("Overriding SQUID_SERVER_STREAM_FLUSH_MS from environment");
+            config.server.stream_flush_ms = ms;
+        }
+
+        if let Ok(max_event_bytes) = std::env::var("SQUID_SERVER_SSE_MAX_EVENT_BYTES")
+            && let Ok(bytes) = max_event_bytes.parse()
+        {
+            debug!("Overriding SQUID_SERVER_SSE_MAX_EVENT_BYTES from environment");
+            config.server.sse_max_event_bytes = bytes;
+        }
+
+        if let Ok(ttl) = std::env::var("SQUID_SERVER_IDEMPOTENCY_TTL_SECONDS")
+            && let Ok(seconds) = ttl.parse()
+        {
+            debug!("Overriding SQUID_SERVER_IDEMPOTENCY_TTL_SECONDS from environment");
+            config.server.idempotency_ttl_seconds = seconds;
+        }
+
+        if let Ok(rate_limit) = std::env::var("SQUID_SERVER_RATE_LIMIT_ENABLED")
+            && let Ok(enabled) = rate_limit.parse()
+        {
+            debug!("Overriding SQUID_SERVER_RATE_LIMIT_ENABLED from environment");
+            config.server.rate_limit.enabled = Some(enabled);
+        }
+
+        if let Ok(chat_per_minute) = std::env::var("SQUID_SERVER_RATE_LIMIT_CHAT_PER_MINUTE")
+            && let Ok(limit) = chat_per_minute.parse()
+        {
+            debug!("Overriding SQUID_SERVER_RATE_LIMIT_CHAT_PER_MINUTE from environment");
+            config.server.rate_limit.chat_per_minute = limit;
+        }
+
+        if let Ok(rag_per_minute) = std::env::var("SQUID_SERVER_RATE_LIMIT_RAG_PER_MINUTE")
+            && let Ok(limit) = rag_per_minute.parse()
+        {
+            debug!("Overriding SQUID_SERVER_RATE_LIMIT_RAG_PER_MINUTE from environment");
+            config.server.rate_limit.rag_per_minute = limit;
+        }
+
+        if let Ok(burst) = std::env::var("SQUID_SERVER_RATE_LIMIT_BURST")
+            && let Ok(burst) = burst.parse()
+        {
+            debug!("Overriding SQUID_SERVER_RATE_LIMIT_BURST from environment");
+            config.server.rate_limit.burst = burst;
+        }
+
         // Web client configuration overrides
         if let Ok(sounds) = std::env::var("SQUID_WEB_SOUNDS")
             && let Ok(enabled) = sounds.parse()
@@ -547,6 +1666,29 @@ impl Config {
             config.web.sounds = enabled;
         }
 
+        // Project instructions overrides
+        if let Ok(enabled) = std::env::var("SQUID_PROJECT_INSTRUCTIONS")
+            && let Ok(enabled) = enabled.parse()
+        {
+            debug!("Overriding SQUID_PROJECT_INSTRUCTIONS from environment");
+            config.project_instructions.enabled = enabled;
+        }
+
+        if let Ok(max_tokens) = std::env::var("SQUID_PROJECT_INSTRUCTIONS_MAX_TOKENS")
+            && let Ok(max_tokens) = max_tokens.parse()
+        {
+            debug!("Overriding SQUID_PROJECT_INSTRUCTIONS_MAX_TOKENS from environment");
+            config.project_instructions.max_tokens = max_tokens;
+        }
+
+        // Network overrides
+        if let Ok(offline) = std::env::var("SQUID_OFFLINE")
+            && let Ok(offline) = offline.parse()
+        {
+            debug!("Overriding SQUID_OFFLINE from environment");
+            config.network.offline = offline;
+        }
+
         // Audio transcription configuration overrides
         if let Ok(audio_image) = std::env::var("SQUID_AUDIO_IMAGE") {
             debug!("Overriding SQUID_AUDIO_IMAGE from environment");
@@ -600,8 +1742,101 @@ impl Config {
             config.jobs.default_retries = retry_count;
         }
 
-        // Store config directory for agents loading
-        config.config_dir = config_path.parent().map(|p| p.to_path_buf());
+        // Response cache configuration overrides
+        if let Ok(cache_enabled) = std::env::var("SQUID_CACHE_ENABLED")
+            && let Ok(enabled) = cache_enabled.parse()
+        {
+            debug!("Overriding SQUID_CACHE_ENABLED from environment");
+            config.cache.enabled = enabled;
+        }
+
+        if let Ok(cache_ttl) = std::env::var("SQUID_CACHE_TTL_SECONDS")
+            && let Ok(ttl) = cache_ttl.parse()
+        {
+            debug!("Overriding SQUID_CACHE_TTL_SECONDS from environment");
+            config.cache.ttl_seconds = ttl;
+        }
+
+        if let Ok(retention) = std::env::var("SQUID_LOG_RETENTION_DAYS")
+            && let Ok(days) = retention.parse()
+        {
+            debug!("Overriding SQUID_LOG_RETENTION_DAYS from environment");
+            config.logging.retention_days = days;
+        }
+
+        if let Ok(retention) = std::env::var("SQUID_SESSION_RETENTION_DAYS")
+            && let Ok(days) = retention.parse()
+        {
+            debug!("Overriding SQUID_SESSION_RETENTION_DAYS from environment");
+            config.sessions.retention_days = days;
+        }
+
+        if let Ok(respect) = std::env::var("SQUID_SECURITY_RESPECT_GITIGNORE")
+            && let Ok(enabled) = respect.parse()
+        {
+            debug!("Overriding SQUID_SECURITY_RESPECT_GITIGNORE from environment");
+            config.security.respect_gitignore = enabled;
+        }
+
+        if let Ok(ask) = std::env::var("SQUID_SECURITY_ASK_FOR_READS")
+            && let Ok(enabled) = ask.parse()
+        {
+            debug!("Overriding SQUID_SECURITY_ASK_FOR_READS from environment");
+            config.security.ask_for_reads = enabled;
+        }
+
+        if let Ok(redact) = std::env::var("SQUID_PRIVACY_REDACT_IPS")
+            && let Ok(enabled) = redact.parse()
+        {
+            debug!("Overriding SQUID_PRIVACY_REDACT_IPS from environment");
+            config.privacy.redact_ips = enabled;
+        }
+
+        if let Ok(timeout) = std::env::var("SQUID_TOOLS_APPROVAL_TIMEOUT_SECS")
+            && let Ok(secs) = timeout.parse()
+        {
+            debug!("Overriding SQUID_TOOLS_APPROVAL_TIMEOUT_SECS from environment");
+            config.tools.approval_timeout_secs = secs;
+        }
+
+        if let Ok(mode) = std::env::var("SQUID_TOOLS_BASH_SANDBOX")
+            && let Ok(mode) = serde_json::from_value(serde_json::Value::String(mode))
+        {
+            debug!("Overriding SQUID_TOOLS_BASH_SANDBOX from environment");
+            config.tools.bash.sandbox = mode;
+        }
+
+        if let Ok(capture) = std::env::var("SQUID_DEBUG_CAPTURE_REQUESTS")
+            && let Ok(enabled) = capture.parse()
+        {
+            debug!("Overriding SQUID_DEBUG_CAPTURE_REQUESTS from environment");
+            config.debug.capture_requests = enabled;
+        }
+
+        if let Ok(effort) = std::env::var("SQUID_REASONING_EFFORT")
+            && let Ok(effort) = serde_json::from_value(serde_json::Value::String(effort))
+        {
+            debug!("Overriding SQUID_REASONING_EFFORT from environment");
+            config.generation.reasoning.effort = Some(effort);
+        }
+
+        if let Ok(enabled) = std::env::var("SQUID_REASONING_ENABLED")
+            && let Ok(enabled) = enabled.parse()
+        {
+            debug!("Overriding SQUID_REASONING_ENABLED from environment");
+            config.generation.reasoning.enabled = Some(enabled);
+        }
+
+        // Store the project root for agents loading and project-relative paths; outside a
+        // project that is the start directory, even when the global config was read
+        config.config_dir = Some(if in_project {
+            config_path
+                .parent()
+                .map(|p| p.to_path_buf())
+                .unwrap_or_else(|| start_dir.to_path_buf())
+        } else {
+            start_dir.to_path_buf()
+        });
 
         config
     }
@@ -609,7 +1844,7 @@ impl Config {
     /// Load agents from the agents directory
     pub fn load_agents(&mut self) {
         let agents_dir = get_agents_dir(self.config_dir.as_deref());
-        let agents = load_agents_from_dir(&agents_dir);
+        let mut agents = load_agents_from_dir(&agents_dir);
 
         if agents.is_empty() {
             warn!(
@@ -620,6 +1855,22 @@ impl Config {
             info!("Loaded {} agents from {:?}", agents.len(), agents_dir);
         }
 
+        // Merge permissions saved from approval prompts
+        for (agent_id, permissions) in &self.agent_permissions {
+            if let Some(agent) = agents.get_mut(agent_id) {
+                for tool in &permissions.allow {
+                    if !agent.permissions.allow.contains(tool) {
+                        agent.permissions.allow.push(tool.clone());
+                    }
+                }
+            } else {
+                debug!(
+                    "Saved permissions reference unknown agent '{}', skipping",
+                    agent_id
+                );
+            }
+        }
+
         self.agents = AgentsConfig {
             agents,
             default_agent: self.default_agent.clone(),
@@ -631,13 +1882,45 @@ impl Config {
         Self::find_config_file().is_some()
     }
 
-    /// Search for squid.config.json in current directory and parent directories
-    fn find_config_file() -> Option<PathBuf> {
-        let mut current_dir = std::env::current_dir().ok()?;
+    /// Search for a config file in current directory and parent directories
+    pub fn find_config_file() -> Option<PathBuf> {
+        Self::find_config_file_from(&std::env::current_dir().ok()?)
+    }
+
+    /// The config file in `dir`, by [`CONFIG_FILE_NAMES`] order
+    pub fn config_file_in(dir: &Path) -> Option<PathBuf> {
+        CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// The global config file in `dir`, by [`GLOBAL_CONFIG_FILE_NAMES`] order
+    pub fn global_config_file_in(dir: &Path) -> Option<PathBuf> {
+        GLOBAL_CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    }
+
+    /// Directory of the global config: `$XDG_CONFIG_HOME/squid`, else the platform config
+    /// directory (`~/.config/squid`, `~/Library/Application Support/squid`, `%APPDATA%\squid`)
+    pub fn global_config_dir() -> Option<PathBuf> {
+        xdg_dir(std::env::var_os("XDG_CONFIG_HOME"), dirs::config_dir())
+    }
+
+    /// Directory for the database outside a project: `$XDG_DATA_HOME/squid`, else the platform
+    /// data directory (`~/.local/share/squid` on Linux)
+    pub fn global_data_dir() -> Option<PathBuf> {
+        xdg_dir(std::env::var_os("XDG_DATA_HOME"), dirs::data_dir())
+    }
+
+    /// Search for a config file in `start_dir` and its parent directories
+    fn find_config_file_from(start_dir: &Path) -> Option<PathBuf> {
+        let mut current_dir = start_dir.to_path_buf();
 
         loop {
-            let config_path = current_dir.join("squid.config.json");
-            if config_path.exists() {
+            if let Some(config_path) = Self::config_file_in(&current_dir) {
                 debug!("Found config file at: {:?}", config_path);
                 return Some(config_path);
             }
@@ -652,25 +1935,69 @@ impl Config {
         None
     }
 
-    /// Search for squid.db in current directory and parent directories
-    fn find_database_file() -> Option<PathBuf> {
-        let mut current_dir = std::env::current_dir().ok()?;
+    /// Resolve the project root from the current directory
+    /// (directory containing the nearest squid.config.json, else the CWD)
+    pub fn find_project_root() -> PathBuf {
+        let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        Self::find_project_root_from(&current_dir)
+    }
 
-        loop {
-            let db_path = current_dir.join("squid.db");
-            if db_path.exists() {
-                debug!("Found database file at: {:?}", db_path);
-                return Some(db_path);
-            }
+    /// Resolve the project root starting the config search at `start_dir`
+    pub fn find_project_root_from(start_dir: &Path) -> PathBuf {
+        Self::find_config_file_from(start_dir)
+            .and_then(|path| path.parent().map(|p| p.to_path_buf()))
+            .unwrap_or_else(|| start_dir.to_path_buf())
+    }
 
-            // Try parent directory
-            if !current_dir.pop() {
-                // Reached root, no database found
-                break;
+    /// Get the resolved project root for this configuration
+    pub fn project_root(&self) -> PathBuf {
+        self.config_dir
+            .clone()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or_else(Self::find_project_root)
+    }
+
+    /// Resolve a path relative to the project root (absolute paths are returned as-is)
+    pub fn resolve_path(&self, path: &str) -> PathBuf {
+        let path = PathBuf::from(path);
+        if path.is_absolute() {
+            path
+        } else {
+            self.project_root().join(path)
+        }
+    }
+
+    /// Look up a configured workspace by name
+    pub fn get_workspace(&self, name: &str) -> Option<&WorkspaceConfig> {
+        self.workspaces
+            .iter()
+            .find(|workspace| workspace.name == name)
+    }
+
+    /// Look up a configured schedule by name
+    pub fn get_schedule(&self, name: &str) -> Option<&ScheduleConfig> {
+        self.schedules.iter().find(|schedule| schedule.name == name)
+    }
+
+    /// Look up a configured conversation template by name
+    pub fn get_template(&self, name: &str) -> Option<&TemplateConfig> {
+        self.templates.iter().find(|template| template.name == name)
+    }
+
+    /// Directory of a workspace with `~` expanded, resolved against the project root
+    pub fn workspace_path(&self, workspace: &WorkspaceConfig) -> PathBuf {
+        let home = dirs::home_dir();
+        match (workspace.path.strip_prefix('~'), home) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with('/') => {
+                home.join(rest.trim_start_matches('/'))
             }
+            _ => self.resolve_path(&workspace.path),
         }
+    }
 
-        None
+    /// Get the RAG documents directory resolved against the project root
+    pub fn documents_dir(&self) -> PathBuf {
+        self.resolve_path(&self.rag.documents_path)
     }
 
     /// Get the current application version
@@ -700,94 +2027,573 @@ impl Config {
         }
     }
 
-    /// Save configuration to squid.config.json in the specified directory
-    pub fn save_to_dir(&self, dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
-        let config_path = dir.join("squid.config.json");
+    /// The file settings changes are saved to: the one the config was loaded from, else the
+    /// project's config file in any supported format
+    fn writable_config_file(&self) -> Result<PathBuf, SquidError> {
+        self.config_file
+            .clone()
+            .filter(|path| path.is_file())
+            .or_else(|| Self::config_file_in(&self.project_root()))
+            .ok_or_else(|| {
+                SquidError::Config(format!(
+                    "No config file ({}) found in {}. Run 'squid init' first.",
+                    CONFIG_FILE_NAMES.join(", "),
+                    self.project_root().display()
+                ))
+            })
+    }
+
+    /// Save configuration to the config file in the specified directory, returning its path
+    ///
+    /// An existing config file is rewritten in its own format; otherwise a new file is created
+    /// in `config_format`.
+    pub fn save_to_dir(&self, dir: &Path) -> Result<PathBuf, SquidError> {
+        let config_path =
+            Self::config_file_in(dir).unwrap_or_else(|| dir.join(self.config_format.file_name()));
+        self.save_to_file(config_path)
+    }
+
+    /// Save configuration as the global config file in `dir`, returning its path
+    pub fn save_global_to_dir(&self, dir: &Path) -> Result<PathBuf, SquidError> {
+        let config_path = Self::global_config_file_in(dir)
+            .unwrap_or_else(|| dir.join(self.config_format.global_file_name()));
+        self.save_to_file(config_path)
+    }
+
+    fn save_to_file(&self, config_path: PathBuf) -> Result<PathBuf, SquidError> {
+        // Create a copy with the current version set
+        let mut config_to_save = self.clone();
+        config_to_save.version = Some(Self::app_version());
+
+        let content = ConfigFormat::from_path(&config_path).serialize(&config_to_save)?;
+        let _guard = lock_config_writes();
+        write_atomically(&config_path, &content)?;
+        info!("Configuration saved to {:?}", config_path);
+        Ok(config_path)
+    }
+
+    /// Get API key with fallback to "not-needed" for local models
+    ///
+    /// Lookup order: OS keychain, `API_KEY` environment variable (including `.env`), then the
+    /// legacy `api_key` field of squid.config.json.
+    pub fn get_api_key(&self) -> String {
+        crate::secrets::keychain_api_key()
+            .or_else(|| std::env::var("API_KEY").ok())
+            .or_else(|| self.api_key.clone())
+            .unwrap_or_else(|| "not-needed".to_string())
+    }
+
+    /// Get agent configuration by ID
+    pub fn get_agent(&self, agent_id: &str) -> Option<&AgentConfig> {
+        self.agents.agents.get(agent_id)
+    }
+
+    /// Context window for an agent: the model metadata's, else its `context_window`, else the global one
+    ///
+    /// Chat requests also consult the provider's model list, through [`crate::models::ModelCache`].
+    pub fn agent_context_window(&self, agent: &AgentConfig) -> u32 {
+        crate::models::resolve_context_window(
+            crate::models::context_length(&agent.model),
+            None,
+            self.configured_context_window(agent),
+        )
+        .tokens
+    }
+
+    /// Context window the config sets for an agent: its `context_window`, else the global one
+    pub fn configured_context_window(&self, agent: &AgentConfig) -> u32 {
+        agent.context_window.unwrap_or(self.context_window)
+    }
+
+    /// Get agent permissions by ID
+    pub fn get_agent_permissions(&self, agent_id: &str) -> Option<&AgentPermissions> {
+        self.get_agent(agent_id).map(|a| &a.permissions)
+    }
+
+    /// Add a tool to an agent's allow list
+    /// Note: This modifies the in-memory config only.
+    /// Call `persist_tool_permission()` to also save it to squid.config.json.
+    pub fn allow_tool_for_agent(
+        &mut self,
+        agent_id: &str,
+        tool_name: &str,
+    ) -> Result<(), SquidError> {
+        let agent = self
+            .agents
+            .agents
+            .get_mut(agent_id)
+            .ok_or_else(|| SquidError::Config(format!("Agent '{}' not found", agent_id)))?;
+
+        // Add to allow list if not already present
+        if !agent.permissions.allow.iter().any(|t| t == tool_name) {
+            agent.permissions.allow.push(tool_name.to_string());
+        }
+
+        apply_permission_change(
+            &mut self.agent_permissions,
+            agent_id,
+            tool_name,
+            PermissionChange::Allow,
+        );
+        Ok(())
+    }
+
+    /// Remove a tool from an agent's allow list
+    /// Note: This modifies the in-memory config only.
+    /// Call `persist_tool_permission()` to also save it to squid.config.json.
+    pub fn deny_tool_for_agent(
+        &mut self,
+        agent_id: &str,
+        tool_name: &str,
+    ) -> Result<(), SquidError> {
+        let agent = self
+            .agents
+            .agents
+            .get_mut(agent_id)
+            .ok_or_else(|| SquidError::Config(format!("Agent '{}' not found", agent_id)))?;
+
+        // Remove from allow list if present
+        agent.permissions.allow.retain(|t| t != tool_name);
+
+        apply_permission_change(
+            &mut self.agent_permissions,
+            agent_id,
+            tool_name,
+            PermissionChange::Deny,
+        );
+        Ok(())
+    }
+
+    /// Apply a permission change in memory and save it to the config file it was loaded from
+    ///
+    /// The file is re-read under a process-wide lock and only this one entry is added or
+    /// removed, so concurrent approvals and manual edits to the file are not overwritten. Only
+    /// the `agent_permissions` section is rewritten; other settings are left untouched, and TOML
+    /// files keep their comments.
+    pub fn persist_tool_permission(
+        &mut self,
+        agent_id: &str,
+        tool_name: &str,
+        change: PermissionChange,
+    ) -> Result<PathBuf, SquidError> {
+        match change {
+            PermissionChange::Allow => self.allow_tool_for_agent(agent_id, tool_name)?,
+            PermissionChange::Deny => self.deny_tool_for_agent(agent_id, tool_name)?,
+        }
+
+        let config_path = self.writable_config_file()?;
+        let format = ConfigFormat::from_path(&config_path);
+
+        let _guard = lock_config_writes();
+        let content = fs::read_to_string(&config_path)?;
+        let mut value: serde_json::Value = format.parse(&content)?;
+        let object = value.as_object_mut().ok_or_else(|| {
+            SquidError::Config("The config file must contain a table of settings".to_string())
+        })?;
+
+        let mut saved: HashMap<String, AgentPermissions> = match object.get("agent_permissions") {
+            Some(section) => serde_json::from_value(section.clone())?,
+            None => HashMap::new(),
+        };
+        apply_permission_change(&mut saved, agent_id, tool_name, change);
+
+        let updated = if format == ConfigFormat::Toml {
+            toml_with_permissions(&content, agent_id, saved.get(agent_id))?
+        } else {
+            if saved.is_empty() {
+                object.remove("agent_permissions");
+            } else {
+                object.insert(
+                    "agent_permissions".to_string(),
+                    serde_json::to_value(&saved)?,
+                );
+            }
+            format.serialize(&value)?
+        };
+
+        write_atomically(&config_path, &updated)?;
+        self.agent_permissions = saved;
+        info!("Agent permissions saved to {:?}", config_path);
+        Ok(config_path)
+    }
+
+    /// Effective value of a dot-separated `key`, or the whole config when `None`
+    ///
+    /// API keys, tokens and secrets are redacted.
+    pub fn get_value(&self, key: Option<&str>) -> Result<serde_json::Value, SquidError> {
+        let mut value = serde_json::to_value(self)?;
+        if let Some(api_key) = &self.api_key {
+            value["api_key"] = serde_json::Value::String(api_key.clone());
+        }
+        if let Some(key) = &self.rag.embedding_api_key {
+            value["rag"]["embedding_api_key"] = serde_json::Value::String(key.clone());
+        }
+        redact_secrets(&mut value);
+        let Some(key) = key else {
+            return Ok(value);
+        };
+        if key == "api_key" {
+            return value.get("api_key").cloned().ok_or_else(|| {
+                SquidError::Config("api_key is not set in the config file".to_string())
+            });
+        }
+        value_at(&value, &self.key_path(key))
+            .cloned()
+            .ok_or_else(|| SquidError::Config(format!("Unknown config key '{}'", key)))
+    }
+
+    /// Change one setting in the config file it was loaded from, returning the file's path
+    ///
+    /// The new file must still load as a valid config and the key must be one squid knows;
+    /// otherwise nothing is written. Values are parsed by the type of the current setting, so
+    /// `8080` stays a string for string settings. TOML files keep their comments.
+    pub fn change_value(&self, key: &str, change: ValueChange) -> Result<PathBuf, SquidError> {
+        if key == "api_key" {
+            return Err(SquidError::Config(
+                "Use `squid config set-key` to store the API key".to_string(),
+            ));
+        }
+        let path = self.key_path(key);
+        if path.iter().any(|segment| segment.is_empty()) {
+            return Err(SquidError::Config(format!("Invalid config key '{}'", key)));
+        }
+
+        let config_path = self.writable_config_file()?;
+        let format = ConfigFormat::from_path(&config_path);
+
+        let _guard = lock_config_writes();
+        let content = fs::read_to_string(&config_path)?;
+        let mut file: serde_json::Value = format.parse(&content)?;
+        let effective = serde_json::to_value(self)?;
+
+        let new_value = match change {
+            // Prompt variables are text even when they look like numbers
+            ValueChange::Set(raw) if path[0] == "prompt_vars" => {
+                Some(serde_json::Value::String(raw))
+            }
+            ValueChange::Set(raw) => Some(parse_setting(&raw, value_at(&effective, &path))),
+            ValueChange::Append(raw) => {
+                let mut list = match value_at(&file, &path).or_else(|| value_at(&effective, &path))
+                {
+                    Some(serde_json::Value::Array(list)) => list.clone(),
+                    Some(_) => return Err(SquidError::Config(format!("'{}' is not a list", key))),
+                    None => Vec::new(),
+                };
+                let entry = parse_setting(
+                    &raw,
+                    Some(
+                        list.first()
+                            .unwrap_or(&serde_json::Value::String(String::new())),
+                    ),
+                );
+                if !list.contains(&entry) {
+                    list.push(entry);
+                }
+                Some(serde_json::Value::Array(list))
+            }
+            ValueChange::Unset => None,
+        };
+
+        match &new_value {
+            Some(value) => set_value_at(&mut file, &path, value.clone())?,
+            None => {
+                if !remove_value_at(&mut file, &path) {
+                    return if value_at(&effective, &path).is_some() {
+                        Ok(config_path)
+                    } else {
+                        Err(SquidError::Config(format!("Unknown config key '{}'", key)))
+                    };
+                }
+            }
+        }
+
+        // The result must load, and a set key must be one the config actually reads
+        let parsed: Config = serde_json::from_value(file.clone())
+            .map_err(|e| format!("Invalid value for '{}': {}", key, e))?;
+        if new_value.is_some() {
+            let known = !parsed.extra.contains_key(&path[0])
+                && value_at(&serde_json::to_value(&parsed)?, &path) == value_at(&file, &path);
+            if !known {
+                return Err(SquidError::Config(format!("Unknown config key '{}'", key)));
+            }
+        }
+
+        let updated = if format == ConfigFormat::Toml {
+            toml_with_value(&content, &path, new_value.as_ref())?
+        } else {
+            format.serialize(&file)?
+        };
+        write_atomically(&config_path, &updated)?;
+        info!("Config key '{}' saved to {:?}", key, config_path);
+        Ok(config_path)
+    }
+
+    /// Split a dot-separated key, expanding `permissions.*` to the default agent's saved permissions
+    fn key_path(&self, key: &str) -> Vec<String> {
+        let mut path: Vec<String> = key.split('.').map(str::to_string).collect();
+        if path[0] == "permissions" {
+            path.splice(
+                0..1,
+                ["agent_permissions".to_string(), self.default_agent.clone()],
+            );
+        }
+        path
+    }
+}
 
-        // Create a copy with the current version set
-        let mut config_to_save = self.clone();
-        config_to_save.version = Some(Self::app_version());
+/// A change made by `squid config set` or `squid config unset`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueChange {
+    /// Replace the value
+    Set(String),
+    /// Add an entry to a list unless it's already there
+    Append(String),
+    /// Remove the key from the file so its default applies
+    Unset,
+}
 
-        let json = serde_json::to_string_pretty(&config_to_save)?;
-        fs::write(&config_path, json)?;
-        info!("Configuration saved to {:?}", config_path);
-        Ok(())
+/// Parse a command-line value like the setting it replaces: raw text for strings, JSON otherwise
+fn parse_setting(raw: &str, current: Option<&serde_json::Value>) -> serde_json::Value {
+    match current {
+        Some(serde_json::Value::String(_)) => serde_json::Value::String(raw.to_string()),
+        _ => {
+            serde_json::from_str(raw).unwrap_or_else(|_| serde_json::Value::String(raw.to_string()))
+        }
     }
+}
 
-    /// Get API key with fallback to "not-needed" for local models
-    pub fn get_api_key(&self) -> String {
-        self.api_key
-            .clone()
-            .or_else(|| std::env::var("API_KEY").ok())
-            .unwrap_or_else(|| "not-needed".to_string())
-    }
+fn value_at<'a>(value: &'a serde_json::Value, path: &[String]) -> Option<&'a serde_json::Value> {
+    path.iter()
+        .try_fold(value, |value, segment| value.get(segment))
+}
 
-    /// Get agent configuration by ID
-    pub fn get_agent(&self, agent_id: &str) -> Option<&AgentConfig> {
-        self.agents.agents.get(agent_id)
+/// Set the value at `path`, creating tables along the way
+fn set_value_at(
+    value: &mut serde_json::Value,
+    path: &[String],
+    new_value: serde_json::Value,
+) -> Result<(), SquidError> {
+    let (last, parents) = path
+        .split_last()
+        .ok_or_else(|| SquidError::Config("Empty config key".to_string()))?;
+    let mut table = value.as_object_mut().ok_or_else(|| {
+        SquidError::Config("The config file must contain a table of settings".to_string())
+    })?;
+    for segment in parents {
+        table = table
+            .entry(segment.as_str())
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
+            .as_object_mut()
+            .ok_or_else(|| SquidError::Config(format!("'{}' is not a table", segment)))?;
     }
+    table.insert(last.clone(), new_value);
+    Ok(())
+}
 
-    /// Get agent permissions by ID
-    pub fn get_agent_permissions(&self, agent_id: &str) -> Option<&AgentPermissions> {
-        self.get_agent(agent_id).map(|a| &a.permissions)
+/// Remove the value at `path`, returning whether it was there
+fn remove_value_at(value: &mut serde_json::Value, path: &[String]) -> bool {
+    let Some((last, parents)) = path.split_last() else {
+        return false;
+    };
+    let mut table = value;
+    for segment in parents {
+        match table.get_mut(segment) {
+            Some(next) => table = next,
+            None => return false,
+        }
     }
+    table
+        .as_object_mut()
+        .is_some_and(|table| table.remove(last).is_some())
+}
 
-    /// Get the default agent configuration
-    pub fn get_default_agent(&self) -> Option<&AgentConfig> {
-        self.get_agent(&self.agents.default_agent)
+/// Replace secret-looking values (`*_key`, `*_token`, `*secret`) with a placeholder
+fn redact_secrets(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(table) => {
+            for (name, value) in table.iter_mut() {
+                let secret = name.ends_with("api_key")
+                    || name.ends_with("_token")
+                    || name.ends_with("secret");
+                if secret && !value.is_null() {
+                    *value = serde_json::Value::String("<redacted>".to_string());
+                } else {
+                    redact_secrets(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
     }
+}
 
-    /// Add a tool to an agent's allow list
-    /// Note: This modifies the in-memory config only.
-    /// To persist changes, update the agent's .md file directly.
-    pub fn allow_tool_for_agent(
-        &mut self,
-        agent_id: &str,
-        tool_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let tool_str = tool_name.to_string();
-
-        let agent = self
-            .agents
-            .agents
-            .get_mut(agent_id)
-            .ok_or_else(|| format!("Agent '{}' not found", agent_id))?;
+/// Set or remove one value in a TOML config, keeping its comments and layout
+fn toml_with_value(
+    content: &str,
+    path: &[String],
+    value: Option<&serde_json::Value>,
+) -> Result<String, SquidError> {
+    let mut document: toml_edit::DocumentMut = content.parse()?;
+    let (last, parents) = path
+        .split_last()
+        .ok_or_else(|| SquidError::Config("Empty config key".to_string()))?;
+    let mut table: &mut dyn toml_edit::TableLike = document.as_table_mut();
+    for segment in parents {
+        let item = match value {
+            Some(_) => table.entry(segment).or_insert_with(|| {
+                // Only the innermost table gets a header
+                let mut table = toml_edit::Table::new();
+                table.set_implicit(true);
+                toml_edit::Item::Table(table)
+            }),
+            None => match table.get_mut(segment) {
+                Some(item) => item,
+                None => return Ok(content.to_string()),
+            },
+        };
+        table = item
+            .as_table_like_mut()
+            .ok_or_else(|| SquidError::Config(format!("'{}' is not a table", segment)))?;
+    }
 
-        // Add to allow list if not already present
-        if !agent.permissions.allow.contains(&tool_str) {
-            agent.permissions.allow.push(tool_str);
+    match value {
+        Some(value) => {
+            let value: toml_edit::Value = toml::Value::try_from(value)?.to_string().parse()?;
+            // Assigning in place keeps comments attached to the key
+            match table.get_mut(last) {
+                Some(item) => *item = toml_edit::value(value),
+                None => {
+                    table.insert(last, toml_edit::value(value));
+                }
+            }
         }
+        None => {
+            table.remove(last);
+        }
+    }
+    Ok(document.to_string())
+}
 
-        Ok(())
+/// Replace one agent's saved permissions in a TOML config, keeping its comments and layout
+fn toml_with_permissions(
+    content: &str,
+    agent_id: &str,
+    permissions: Option<&AgentPermissions>,
+) -> Result<String, SquidError> {
+    let mut document: toml_edit::DocumentMut = content.parse()?;
+    let section = document
+        .entry("agent_permissions")
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .ok_or_else(|| SquidError::Config("`agent_permissions` must be a table".to_string()))?;
+
+    match permissions {
+        Some(permissions) => {
+            let allow: toml_edit::Array = permissions.allow.iter().map(String::as_str).collect();
+            let agent = section
+                .entry(agent_id)
+                .or_insert_with(toml_edit::table)
+                .as_table_like_mut()
+                .ok_or_else(|| {
+                    SquidError::Config(format!("`agent_permissions.{}` must be a table", agent_id))
+                })?;
+            // Assigning in place keeps comments attached to the key
+            match agent.get_mut("allow") {
+                Some(item) => *item = toml_edit::value(allow),
+                None => {
+                    agent.insert("allow", toml_edit::value(allow));
+                }
+            }
+        }
+        None => {
+            section.remove(agent_id);
+        }
     }
+    if section.is_empty() {
+        document.remove("agent_permissions");
+    }
+    Ok(document.to_string())
+}
 
-    /// Remove a tool from an agent's allow list
-    /// Note: This modifies the in-memory config only.
-    /// To persist changes, update the agent's .md file directly.
-    pub fn deny_tool_for_agent(
-        &mut self,
-        agent_id: &str,
-        tool_name: &str,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        let tool_str = tool_name.to_string();
+/// A change to an agent's saved tool permissions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PermissionChange {
+    Allow,
+    Deny,
+}
 
-        let agent = self
-            .agents
-            .agents
-            .get_mut(agent_id)
-            .ok_or_else(|| format!("Agent '{}' not found", agent_id))?;
+fn apply_permission_change(
+    saved: &mut HashMap<String, AgentPermissions>,
+    agent_id: &str,
+    tool_name: &str,
+    change: PermissionChange,
+) {
+    match change {
+        PermissionChange::Allow => {
+            let permissions = saved.entry(agent_id.to_string()).or_default();
+            if !permissions.allow.iter().any(|t| t == tool_name) {
+                permissions.allow.push(tool_name.to_string());
+            }
+        }
+        PermissionChange::Deny => {
+            if let Some(permissions) = saved.get_mut(agent_id) {
+                permissions.allow.retain(|t| t != tool_name);
+                if permissions.allow.is_empty() {
+                    saved.remove(agent_id);
+                }
+            }
+        }
+    }
+}
 
-        // Remove from allow list if present
-        agent.permissions.allow.retain(|t| t != &tool_str);
+/// Serializes config file writes within this process
+fn lock_config_writes() -> std::sync::MutexGuard<'static, ()> {
+    static CONFIG_WRITE_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    CONFIG_WRITE_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
 
-        Ok(())
+/// Write via a temporary file in the same directory and a rename, so readers never see a
+/// partially written file
+fn write_atomically(path: &Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let mut file = tempfile::NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+    file.write_all(contents.as_bytes())?;
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(file.path(), metadata.permissions())?;
     }
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// `squid` under an XDG base directory, which must be absolute to count, else under `fallback`
+fn xdg_dir(xdg_value: Option<std::ffi::OsString>, fallback: Option<PathBuf>) -> Option<PathBuf> {
+    xdg_value
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or(fallback)
+        .map(|dir| dir.join("squid"))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_json_body_limit_fits_attachment_limit() {
+        let server = ServerConfig::default();
+        assert_eq!(server.json_body_limit(), server.max_json_bytes);
+
+        let server = ServerConfig {
+            max_attachment_bytes: 40 * 1024 * 1024,
+            ..Default::default()
+        };
+        assert_eq!(server.json_body_limit(), 81 * 1024 * 1024);
+    }
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
@@ -798,7 +2604,7 @@ mod tests {
         assert_eq!(config.version, None);
         assert_eq!(config.database_path, "squid.db");
         assert_eq!(config.working_dir, "./workspace");
-        assert_eq!(config.rag.enabled, true);
+        assert!(config.rag.enabled);
         assert_eq!(
             config.rag.embedding_model,
             "text-embedding-nomic-embed-text-v1.5"
@@ -809,6 +2615,66 @@ mod tests {
         assert_eq!(config.audio.language, "");
     }
 
+    #[test]
+    fn test_approval_timeout_and_cleanup_interval() {
+        let tools = ToolsConfig::default();
+        assert_eq!(tools.approval_timeout().as_secs(), 300);
+        assert_eq!(tools.approval_cleanup_interval().as_secs(), 60);
+
+        let tools = ToolsConfig {
+            approval_timeout_secs: 10,
+            ..Default::default()
+        };
+        assert_eq!(tools.approval_cleanup_interval().as_secs(), 5);
+
+        let tools = ToolsConfig {
+            approval_timeout_secs: 0,
+            ..Default::default()
+        };
+        assert_eq!(tools.approval_timeout().as_secs(), 1);
+        assert_eq!(tools.approval_cleanup_interval().as_secs(), 1);
+    }
+
+    #[test]
+    fn test_search_limit_is_clamped() {
+        let tools = ToolsConfig::default();
+        assert_eq!(tools.search_limit(&serde_json::json!({}), 50), 50);
+        assert_eq!(
+            tools.search_limit(&serde_json::json!({"max_results": 5}), 50),
+            5
+        );
+        assert_eq!(
+            tools.search_limit(&serde_json::json!({"max_results": u64::MAX}), 50),
+            200
+        );
+        assert_eq!(
+            tools.search_limit(&serde_json::json!({"max_results": 0}), 50),
+            1
+        );
+        // Negative values are not u64, so the default applies instead of wrapping around
+        assert_eq!(
+            tools.search_limit(&serde_json::json!({"max_results": -1}), 50),
+            50
+        );
+
+        let tools = ToolsConfig {
+            max_results: 10,
+            ..Default::default()
+        };
+        assert_eq!(tools.search_limit(&serde_json::json!({}), 50), 10);
+    }
+
+    #[test]
+    fn test_session_retention_zero_keeps_sessions() {
+        assert_eq!(SessionsConfig::default().retention_seconds(), None);
+
+        let sessions = SessionsConfig { retention_days: 0 };
+        assert_eq!(sessions.retention_seconds(), None);
+
+        let sessions = SessionsConfig { retention_days: 2 };
+        assert_eq!(sessions.retention_seconds(), Some(2 * 86400));
+    }
+
     #[test]
     fn test_get_api_key_fallback() {
         let config = Config::default();
@@ -824,6 +2690,53 @@ mod tests {
         assert_eq!(config.get_api_key(), "test-key");
     }
 
+    #[test]
+    fn test_save_never_writes_api_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            api_key: Some("sk-secret".to_string()),
+            ..Default::default()
+        };
+        config.save_to_dir(dir.path()).unwrap();
+
+        let saved = fs::read_to_string(dir.path().join("squid.config.json")).unwrap();
+        assert!(!saved.contains("api_key"));
+        assert!(!saved.contains("sk-secret"));
+
+        // Legacy files with a key still load
+        let legacy = saved.replacen('{', "{\n  \"api_key\": \"sk-legacy\",", 1);
+        fs::write(dir.path().join("squid.config.json"), legacy).unwrap();
+        let loaded = Config::load_from(dir.path());
+        assert_eq!(loaded.api_key.as_deref(), Some("sk-legacy"));
+
+        loaded.save_to_dir(dir.path()).unwrap();
+        let resaved = fs::read_to_string(dir.path().join("squid.config.json")).unwrap();
+        assert!(!resaved.contains("sk-legacy"));
+    }
+
+    #[test]
+    fn test_save_never_writes_embedding_api_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.rag.embedding_api_key = Some("sk-embed".to_string());
+        config.save_to_dir(dir.path()).unwrap();
+
+        let saved = fs::read_to_string(dir.path().join("squid.config.json")).unwrap();
+        assert!(!saved.contains("embedding_api_key"));
+        assert!(!saved.contains("sk-embed"));
+
+        // A key written by hand still loads, and survives a save only in memory
+        let mut file: serde_json::Value = serde_json::from_str(&saved).unwrap();
+        file["rag"]["embedding_api_key"] = "sk-hand".into();
+        fs::write(dir.path().join("squid.config.json"), file.to_string()).unwrap();
+        let loaded = Config::load_from(dir.path());
+        assert_eq!(loaded.rag.embedding_api_key.as_deref(), Some("sk-hand"));
+
+        loaded.save_to_dir(dir.path()).unwrap();
+        let resaved = fs::read_to_string(dir.path().join("squid.config.json")).unwrap();
+        assert!(!resaved.contains("sk-hand"));
+    }
+
     #[test]
     fn test_app_version() {
         let version = Config::app_version();
@@ -864,4 +2777,541 @@ mod tests {
         assert!(warning.is_some());
         assert!(warning.unwrap().contains("0.1.0"));
     }
+
+    /// Creates `project/` with a config file and one agent, plus an empty `project/src/`
+    fn create_test_project() -> tempfile::TempDir {
+        let temp = tempfile::tempdir().unwrap();
+        let project = temp.path();
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::create_dir_all(project.join("agents")).unwrap();
+        fs::write(
+            project.join("squid.config.json"),
+            r#"{"api_url": "http://127.0.0.1:1234/v1", "rag": {"documents_path": "docs"}}"#,
+        )
+        .unwrap();
+        fs::write(
+            project.join("agents").join("tester.md"),
+            "---\nname: Tester\nmodel: test-model\npermissions:\n  - read_file\n---\nTest prompt",
+        )
+        .unwrap();
+        temp
+    }
+
+    #[test]
+    fn test_project_root_from_subdirectory() {
+        let temp = create_test_project();
+        let project = temp.path();
+
+        let config = Config::load_from(&project.join("src"));
+        assert_eq!(config.project_root(), project);
+        assert_eq!(config.documents_dir(), project.join("docs"));
+        assert_eq!(
+            Config::find_project_root_from(&project.join("src")),
+            project
+        );
+    }
+
+    #[test]
+    fn test_xdg_dir() {
+        let fallback = Some(PathBuf::from("/home/user/.config"));
+        assert_eq!(
+            xdg_dir(Some("/tmp/xdg".into()), fallback.clone()),
+            Some(PathBuf::from("/tmp/xdg/squid"))
+        );
+        // Relative and empty values are ignored, as the XDG spec requires
+        assert_eq!(
+            xdg_dir(Some("relative".into()), fallback.clone()),
+            Some(PathBuf::from("/home/user/.config/squid"))
+        );
+        assert_eq!(
+            xdg_dir(Some("".into()), fallback.clone()),
+            Some(PathBuf::from("/home/user/.config/squid"))
+        );
+        assert_eq!(xdg_dir(None, None), None);
+    }
+
+    #[test]
+    fn test_global_config_outside_project() {
+        let temp = tempfile::tempdir().unwrap();
+        let config_home = temp.path().join("config").join("squid");
+        let data_home = temp.path().join("data").join("squid");
+        let work = temp.path().join("work");
+        fs::create_dir_all(&config_home).unwrap();
+        fs::create_dir_all(&work).unwrap();
+        fs::write(
+            config_home.join("config.json"),
+            r#"{"api_url": "http://global:1234/v1", "log_level": "warn"}"#,
+        )
+        .unwrap();
+
+        let config = Config::load_with_globals(&work, Some(&config_home), Some(&data_home));
+        assert_eq!(config.api_url, "http://global:1234/v1");
+        assert_eq!(config.log_level, "warn");
+        // Project-relative paths still resolve against the directory squid runs in
+        assert_eq!(config.project_root(), work);
+        assert_eq!(
+            PathBuf::from(&config.database_path),
+            data_home.join("squid.db")
+        );
+        assert!(data_home.is_dir());
+        assert!(!work.join("squid.db").exists());
+    }
+
+    #[test]
+    fn test_settings_are_saved_to_the_file_they_came_from() {
+        let temp = tempfile::tempdir().unwrap();
+        let config_home = temp.path().join("config");
+        let work = temp.path().join("work");
+        fs::create_dir_all(&config_home).unwrap();
+        fs::create_dir_all(&work).unwrap();
+        fs::write(
+            config_home.join("config.toml"),
+            "api_url = \"http://global:1234/v1\"\nlog_level = \"warn\"\n",
+        )
+        .unwrap();
+
+        let config = Config::load_with_globals(&work, Some(&config_home), None);
+        let saved = config
+            .change_value("log_level", ValueChange::Set("debug".to_string()))
+            .unwrap();
+        assert_eq!(saved, config_home.join("config.toml"));
+        assert!(
+            fs::read_to_string(&saved)
+                .unwrap()
+                .contains("log_level = \"debug\"")
+        );
+
+        // Without any config file, the error names every supported format
+        let config = Config::load_with_globals(&work, None, None);
+        let err = config
+            .change_value("log_level", ValueChange::Set("debug".to_string()))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("squid.config.toml"), "{}", err);
+    }
+
+    #[test]
+    fn test_project_config_wins_over_global() {
+        let temp = create_test_project();
+        let project = temp.path();
+        let globals = tempfile::tempdir().unwrap();
+        let config_home = globals.path().join("config");
+        let data_home = globals.path().join("data");
+        fs::create_dir_all(&config_home).unwrap();
+        fs::write(
+            config_home.join("config.json"),
+            r#"{"api_url": "http://global:1234/v1", "log_level": "warn"}"#,
+        )
+        .unwrap();
+
+        let config =
+            Config::load_with_globals(&project.join("src"), Some(&config_home), Some(&data_home));
+        assert_eq!(config.api_url, "http://127.0.0.1:1234/v1");
+        // Nothing is merged from the global file
+        assert_eq!(config.log_level, default_log_level());
+        assert_eq!(
+            PathBuf::from(&config.database_path),
+            project.join("squid.db")
+        );
+        assert!(!data_home.exists());
+    }
+
+    #[test]
+    fn test_defaults_outside_project_use_data_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let data_home = temp.path().join("data");
+        let empty_config_home = temp.path().join("config");
+
+        let config =
+            Config::load_with_globals(temp.path(), Some(&empty_config_home), Some(&data_home));
+        assert_eq!(config.api_url, Config::default().api_url);
+        assert_eq!(
+            PathBuf::from(&config.database_path),
+            data_home.join("squid.db")
+        );
+    }
+
+    #[test]
+    fn test_save_global_config() {
+        let temp = tempfile::tempdir().unwrap();
+        let config = Config {
+            api_url: "http://global:1234/v1".to_string(),
+            ..Config::default()
+        };
+
+        let path = config.save_global_to_dir(temp.path()).unwrap();
+        assert_eq!(path, temp.path().join("config.json"));
+        assert_eq!(Config::global_config_file_in(temp.path()), Some(path));
+        assert_eq!(Config::config_file_in(temp.path()), None);
+    }
+
+    #[test]
+    fn test_workspace_paths() {
+        let temp = create_test_project();
+        let project = temp.path();
+        std::fs::write(
+            project.join("squid.config.json"),
+            r#"{"api_url": "http://127.0.0.1:1234/v1", "workspaces": [{"name": "docs", "path": "docs"}, {"name": "home", "path": "~/code"}]}"#,
+        )
+        .unwrap();
+
+        let config = Config::load_from(project);
+        let docs = config.get_workspace("docs").unwrap();
+        assert_eq!(config.workspace_path(docs), project.join("docs"));
+        let home = config.get_workspace("home").unwrap();
+        assert_eq!(
+            config.workspace_path(home),
+            dirs::home_dir().unwrap().join("code")
+        );
+        assert!(config.get_workspace("missing").is_none());
+    }
+
+    #[test]
+    fn test_project_root_without_config_uses_start_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        assert_eq!(Config::find_project_root_from(temp.path()), temp.path());
+    }
+
+    #[test]
+    fn test_allow_tool_saves_to_project_config() {
+        let temp = create_test_project();
+        let project = temp.path();
+
+        let mut config = Config::load_from(&project.join("src"));
+        config.load_agents();
+        let saved_path = config
+            .persist_tool_permission("tester", "bash:ls", PermissionChange::Allow)
+            .unwrap();
+
+        assert_eq!(saved_path, project.join("squid.config.json"));
+        assert!(!project.join("src").join("squid.config.json").exists());
+
+        // Reloading from the subdirectory merges the saved permission
+        let mut reloaded = Config::load_from(&project.join("src"));
+        reloaded.load_agents();
+        let permissions = reloaded.get_agent_permissions("tester").unwrap();
+        assert!(permissions.allow.contains(&"read_file".to_string()));
+        assert!(permissions.allow.contains(&"bash:ls".to_string()));
+        assert_eq!(reloaded.rag.documents_path, "docs");
+
+        // Denying removes the saved entry again
+        reloaded
+            .persist_tool_permission("tester", "bash:ls", PermissionChange::Deny)
+            .unwrap();
+        let content = fs::read_to_string(project.join("squid.config.json")).unwrap();
+        assert!(!content.contains("agent_permissions"));
+    }
+
+    #[test]
+    fn test_concurrent_permission_saves_both_survive() {
+        let temp = create_test_project();
+        let project = temp.path().to_path_buf();
+
+        // Both configs are loaded before either saves, like two pending approvals
+        let configs: Vec<Config> = (0..2)
+            .map(|_| {
+                let mut config = Config::load_from(&project);
+                config.load_agents();
+                config
+            })
+            .collect();
+        let handles: Vec<_> = configs
+            .into_iter()
+            .zip(["bash:ls", "grep"])
+            .map(|(mut config, tool)| {
+                std::thread::spawn(move || {
+                    config
+                        .persist_tool_permission("tester", tool, PermissionChange::Allow)
+                        .unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let reloaded = Config::load_from(&project);
+        let saved = &reloaded.agent_permissions["tester"].allow;
+        assert!(saved.contains(&"bash:ls".to_string()));
+        assert!(saved.contains(&"grep".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_fields_survive_save() {
+        let temp = create_test_project();
+        let project = temp.path();
+        fs::write(
+            project.join("squid.config.json"),
+            r#"{"api_url": "http://127.0.0.1:1234/v1", "future_setting": {"enabled": true}}"#,
+        )
+        .unwrap();
+
+        let mut config = Config::load_from(project);
+        config.load_agents();
+        config.save_to_dir(project).unwrap();
+        config
+            .persist_tool_permission("tester", "grep", PermissionChange::Allow)
+            .unwrap();
+
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(project.join("squid.config.json")).unwrap())
+                .unwrap();
+        assert_eq!(saved["future_setting"]["enabled"], true);
+        assert_eq!(saved["agent_permissions"]["tester"]["allow"][0], "grep");
+        assert!(saved.get("extra").is_none());
+    }
+
+    #[test]
+    fn test_config_formats_round_trip() {
+        for format in [ConfigFormat::Json, ConfigFormat::Toml, ConfigFormat::Yaml] {
+            let temp = create_test_project();
+            let project = temp.path();
+            fs::remove_file(project.join("squid.config.json")).unwrap();
+
+            let mut config = Config {
+                api_url: "http://127.0.0.1:1234/v1".to_string(),
+                config_format: format,
+                ..Default::default()
+            };
+            config.rag.documents_path = "docs".to_string();
+            config.rag.top_k = 7;
+            config.agent_permissions.insert(
+                "tester".to_string(),
+                AgentPermissions {
+                    allow: vec!["grep".to_string(), "bash:ls".to_string()],
+                },
+            );
+            let saved_path = config.save_to_dir(project).unwrap();
+            assert_eq!(saved_path, project.join(format.file_name()), "{}", format);
+            assert!(!project.join("squid.config.json").exists() || format == ConfigFormat::Json);
+
+            let mut loaded = Config::load_from(&project.join("src"));
+            assert_eq!(loaded.config_format, format);
+            assert_eq!(loaded.api_url, "http://127.0.0.1:1234/v1");
+            assert_eq!(loaded.rag.documents_path, "docs");
+            assert_eq!(loaded.rag.top_k, 7);
+            assert_eq!(
+                loaded.agent_permissions["tester"].allow,
+                vec!["grep".to_string(), "bash:ls".to_string()]
+            );
+
+            // Permissions are written back to the same file, in its format
+            loaded.load_agents();
+            let path = loaded
+                .persist_tool_permission("tester", "read_file", PermissionChange::Allow)
+                .unwrap();
+            assert_eq!(path, saved_path);
+            loaded
+                .persist_tool_permission("tester", "grep", PermissionChange::Deny)
+                .unwrap();
+            let reloaded = Config::load_from(project);
+            assert_eq!(
+                reloaded.agent_permissions["tester"].allow,
+                vec!["bash:ls".to_string(), "read_file".to_string()],
+                "{}",
+                format
+            );
+            assert_eq!(reloaded.rag.top_k, 7);
+            assert_eq!(
+                fs::read_dir(project)
+                    .unwrap()
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry
+                        .file_name()
+                        .to_string_lossy()
+                        .starts_with("squid.config"))
+                    .count(),
+                1
+            );
+        }
+    }
+
+    #[test]
+    fn test_toml_permission_save_keeps_comments() {
+        let temp = create_test_project();
+        let project = temp.path();
+        fs::remove_file(project.join("squid.config.json")).unwrap();
+        fs::write(
+            project.join("squid.config.toml"),
+            "# Local LM Studio\napi_url = \"http://127.0.0.1:1234/v1\"\n\n[agent_permissions.tester]\n# ls is needed for the release checklist\nallow = [\"bash:ls\"]\n",
+        )
+        .unwrap();
+
+        let mut config = Config::load_from(project);
+        config.load_agents();
+        config
+            .persist_tool_permission("tester", "grep", PermissionChange::Allow)
+            .unwrap();
+        let content = fs::read_to_string(project.join("squid.config.toml")).unwrap();
+        assert!(content.contains("# Local LM Studio"));
+        assert!(content.contains("# ls is needed for the release checklist"));
+        assert!(content.contains(r#"allow = ["bash:ls", "grep"]"#));
+
+        config
+            .persist_tool_permission("tester", "bash:ls", PermissionChange::Deny)
+            .unwrap();
+        config
+            .persist_tool_permission("tester", "grep", PermissionChange::Deny)
+            .unwrap();
+        let content = fs::read_to_string(project.join("squid.config.toml")).unwrap();
+        assert!(!content.contains("agent_permissions"));
+        assert!(content.starts_with("# Local LM Studio"));
+    }
+
+    #[test]
+    fn test_config_set_get_unset_round_trip() {
+        let temp = create_test_project();
+        let project = temp.path();
+        let config = Config::load_from(project);
+
+        config
+            .change_value("rag.chunk_size", ValueChange::Set("256".to_string()))
+            .unwrap();
+        config
+            .change_value("rag.documents_path", ValueChange::Set("2024".to_string()))
+            .unwrap();
+        for _ in 0..2 {
+            config
+                .change_value(
+                    "agent_permissions.tester.allow",
+                    ValueChange::Append("bash:ls".to_string()),
+                )
+                .unwrap();
+        }
+
+        config
+            .change_value("prompt_vars.team", ValueChange::Set("Platform".to_string()))
+            .unwrap();
+        config
+            .change_value("prompt_vars.since", ValueChange::Set("2019".to_string()))
+            .unwrap();
+
+        let reloaded = Config::load_from(project);
+        assert_eq!(reloaded.rag.chunk_size, 256);
+        assert_eq!(reloaded.rag.documents_path, "2024");
+        assert_eq!(reloaded.agent_permissions["tester"].allow, vec!["bash:ls"]);
+        assert_eq!(reloaded.prompt_vars["team"], "Platform");
+        assert_eq!(reloaded.prompt_vars["since"], "2019");
+        assert_eq!(
+            reloaded.get_value(Some("rag.chunk_size")).unwrap(),
+            serde_json::json!(256)
+        );
+
+        reloaded
+            .change_value("rag.chunk_size", ValueChange::Unset)
+            .unwrap();
+        let reloaded = Config::load_from(project);
+        assert_eq!(reloaded.rag.chunk_size, 512);
+        assert_eq!(reloaded.rag.documents_path, "2024");
+    }
+
+    #[test]
+    fn test_config_set_rejects_unknown_keys_and_bad_values() {
+        let temp = create_test_project();
+        let project = temp.path();
+        let config = Config::load_from(project);
+        let before = fs::read_to_string(project.join("squid.config.json")).unwrap();
+
+        for (key, value) in [
+            ("rag.chunk_sise", "256"),
+            ("no_such_setting", "1"),
+            ("rag.chunk_size", "lots"),
+            ("rag.documents_path.nested", "x"),
+        ] {
+            assert!(
+                config
+                    .change_value(key, ValueChange::Set(value.to_string()))
+                    .is_err(),
+                "{} = {}",
+                key,
+                value
+            );
+        }
+        assert!(
+            config
+                .change_value("rag.chunk_size", ValueChange::Append("1".to_string()))
+                .is_err()
+        );
+        assert!(
+            config
+                .change_value("api_key", ValueChange::Set("sk-1".to_string()))
+                .is_err()
+        );
+        assert!(config.get_value(Some("rag.chunk_sise")).is_err());
+        assert_eq!(
+            fs::read_to_string(project.join("squid.config.json")).unwrap(),
+            before
+        );
+    }
+
+    #[test]
+    fn test_config_get_redacts_secrets() {
+        let mut config = Config {
+            api_key: Some("sk-legacy".to_string()),
+            ..Default::default()
+        };
+        config.rag.embedding_api_key = Some("sk-embed".to_string());
+
+        let value = config.get_value(None).unwrap();
+        assert_eq!(value["api_key"], "<redacted>");
+        assert_eq!(value["rag"]["embedding_api_key"], "<redacted>");
+        assert!(!value.to_string().contains("sk-"));
+    }
+
+    #[test]
+    fn test_config_set_keeps_toml_comments() {
+        let temp = create_test_project();
+        let project = temp.path();
+        fs::remove_file(project.join("squid.config.json")).unwrap();
+        fs::write(
+            project.join("squid.config.toml"),
+            "# Local LM Studio\napi_url = \"http://127.0.0.1:1234/v1\"\n\n[rag]\n# small chunks for notes\nchunk_size = 300\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from(project);
+        config
+            .change_value("rag.chunk_size", ValueChange::Set("256".to_string()))
+            .unwrap();
+        config
+            .change_value(
+                "tools.bash.env_passthrough",
+                ValueChange::Append("CARGO_HOME".to_string()),
+            )
+            .unwrap();
+        let content = fs::read_to_string(project.join("squid.config.toml")).unwrap();
+        assert!(content.contains("# small chunks for notes\nchunk_size = 256"));
+        assert!(content.contains("[tools.bash]\nenv_passthrough = [\"CARGO_HOME\"]"));
+        assert!(!content.contains("[tools]\n"));
+
+        let reloaded = Config::load_from(project);
+        assert_eq!(reloaded.rag.chunk_size, 256);
+        assert_eq!(reloaded.tools.bash.env_passthrough, vec!["CARGO_HOME"]);
+    }
+
+    #[test]
+    fn test_config_file_priority() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        assert_eq!(Config::config_file_in(dir), None);
+        fs::write(dir.join("squid.config.yaml"), "api_url: http://a/v1\n").unwrap();
+        fs::write(dir.join("squid.config.toml"), "api_url = \"http://b/v1\"\n").unwrap();
+        assert_eq!(
+            Config::config_file_in(dir),
+            Some(dir.join("squid.config.toml"))
+        );
+        fs::write(
+            dir.join("squid.config.json"),
+            r#"{"api_url": "http://c/v1"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            Config::config_file_in(dir),
+            Some(dir.join("squid.config.json"))
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("squid.config.yml")),
+            ConfigFormat::Yaml
+        );
+    }
 }
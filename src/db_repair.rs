@@ -0,0 +1,357 @@
+//! Startup diagnosis and recovery of a damaged session database
+//!
+//! A database that SQLite reports as corrupt (or as not a database at all) is moved aside and
+//! replaced by a fresh one, into which every row that can still be read is copied table by
+//! table. A table that fails part way keeps the rows read before the damage. A database locked
+//! by another process is only reported, with the processes holding it when they can be found.
+
+use log::{info, warn};
+use rusqlite::types::Value;
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+use std::path::{Path, PathBuf};
+
+use crate::db::Database;
+use crate::error::{Result, SquidError};
+
+/// Why the database couldn't be opened, when it's something the user can act on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenProblem {
+    /// The file is damaged or isn't an SQLite database
+    Corrupt,
+    /// Another process holds a lock on the file
+    Locked { holders: Vec<u32> },
+}
+
+/// What [`repair`] salvaged from a damaged database
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Where the damaged file was moved
+    pub backup: PathBuf,
+    pub sessions: usize,
+    pub messages: usize,
+    /// Rows copied per table, in copy order
+    pub tables: Vec<(String, usize)>,
+    /// Tables that could not be read completely, with the error that stopped them
+    pub damaged: Vec<(String, String)>,
+}
+
+/// Classify an error from [`Database::new`] on the file at `path`
+pub fn diagnose(error: &SquidError, path: &Path) -> Option<OpenProblem> {
+    let SquidError::Database(rusqlite::Error::SqliteFailure(failure, _)) = error else {
+        return None;
+    };
+    match failure.code {
+        ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase => Some(OpenProblem::Corrupt),
+        ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked => Some(OpenProblem::Locked {
+            holders: lock_holders(path),
+        }),
+        _ => None,
+    }
+}
+
+/// The message printed for a problem found while opening `path`
+pub fn describe(problem: &OpenProblem, path: &Path) -> String {
+    match problem {
+        OpenProblem::Corrupt => format!(
+            "The database {} is damaged or is not a squid database.\n    Run `squid serve --repair` to move it aside and copy what can still be read into a new one.",
+            path.display()
+        ),
+        OpenProblem::Locked { holders } if holders.is_empty() => format!(
+            "The database {} is locked by another process, probably another squid instance.\n    Stop it or pass a different --db.",
+            path.display()
+        ),
+        OpenProblem::Locked { holders } => format!(
+            "The database {} is locked by another process (PID {}), probably another squid instance.\n    Stop it or pass a different --db.",
+            path.display(),
+            holders
+                .iter()
+                .map(u32::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Move the damaged database at `path` aside and rebuild it from the rows still readable
+pub fn repair(path: &Path) -> Result<RepairReport> {
+    let backup = corrupt_backup_path(path);
+    std::fs::rename(path, &backup)?;
+    // The journal belongs to the damaged file; a fresh database must not replay it
+    for suffix in ["-wal", "-shm", "-journal"] {
+        let journal = PathBuf::from(format!("{}{}", path.display(), suffix));
+        if journal.exists() {
+            std::fs::rename(&journal, format!("{}{}", backup.display(), suffix))?;
+        }
+    }
+    warn!("Moved damaged database to {}", backup.display());
+
+    // Creates the current schema, so rows only need to be copied
+    drop(Database::new(path)?);
+    let conn = Connection::open(path)?;
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+
+    let mut report = RepairReport {
+        backup: backup.clone(),
+        ..Default::default()
+    };
+    let old = match Connection::open_with_flags(&backup, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        Ok(old) => old,
+        Err(e) => {
+            report.damaged.push(("*".to_string(), e.to_string()));
+            return Ok(report);
+        }
+    };
+    let tables = match copyable_tables(&old) {
+        Ok(tables) => tables,
+        Err(e) => {
+            // Without a readable schema nothing can be located
+            report
+                .damaged
+                .push(("sqlite_master".to_string(), e.to_string()));
+            return Ok(report);
+        }
+    };
+
+    for table in tables {
+        let Some(columns) = shared_columns(&old, &conn, &table) else {
+            continue;
+        };
+        let (copied, error) = copy_rows(&old, &conn, &table, &columns);
+        info!("Recovered {} row(s) of {}", copied, table);
+        match table.as_str() {
+            "sessions" => report.sessions = copied,
+            "messages" => report.messages = copied,
+            _ => {}
+        }
+        report.tables.push((table.clone(), copied));
+        if let Some(error) = error {
+            report.damaged.push((table, error));
+        }
+    }
+    Ok(report)
+}
+
+/// `<path>.corrupt.<unix time>`, next to the database
+fn corrupt_backup_path(path: &Path) -> PathBuf {
+    PathBuf::from(format!(
+        "{}.corrupt.{}",
+        path.display(),
+        chrono::Utc::now().timestamp()
+    ))
+}
+
+/// Ordinary tables of the damaged database worth copying
+///
+/// Migration bookkeeping comes from the fresh schema. Vector tables are skipped with their
+/// shadow tables: embeddings are rebuilt by indexing the documents again.
+fn copyable_tables(old: &Connection) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = old.prepare("SELECT name, type, sql FROM sqlite_master ORDER BY rowid")?;
+    let entries: Vec<(String, String, Option<String>)> = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let virtual_tables: Vec<&str> = entries
+        .iter()
+        .filter(|(_, _, sql)| {
+            sql.as_deref()
+                .is_some_and(|sql| sql.to_uppercase().starts_with("CREATE VIRTUAL"))
+        })
+        .map(|(name, _, _)| name.as_str())
+        .collect();
+
+    Ok(entries
+        .iter()
+        .filter(|(name, kind, _)| {
+            kind == "table"
+                && !name.starts_with("sqlite_")
+                && name != "schema_migrations"
+                && !virtual_tables
+                    .iter()
+                    .any(|table| name == table || name.starts_with(&format!("{}_", table)))
+        })
+        .map(|(name, _, _)| name.clone())
+        .collect())
+}
+
+/// Columns `table` has in both databases, or `None` if the fresh schema doesn't have it
+fn shared_columns(old: &Connection, new: &Connection, table: &str) -> Option<Vec<String>> {
+    let new_columns = table_columns(new, table).ok()?;
+    if new_columns.is_empty() {
+        return None;
+    }
+    let old_columns = table_columns(old, table).ok()?;
+    Some(
+        new_columns
+            .into_iter()
+            .filter(|column| old_columns.contains(column))
+            .collect(),
+    )
+}
+
+fn table_columns(conn: &Connection, table: &str) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", quote(table)))?;
+    stmt.query_map([], |row| row.get(1))?.collect()
+}
+
+/// Copy rows one at a time until the table ends or a read fails
+///
+/// The copy of a table is one transaction: an interrupted repair leaves no half-copied table,
+/// and rows read before a failure are committed as what could be recovered. Returns the number
+/// of rows copied and the error that stopped the copy, if any.
+fn copy_rows(
+    old: &Connection,
+    new: &Connection,
+    table: &str,
+    columns: &[String],
+) -> (usize, Option<String>) {
+    let column_list = columns
+        .iter()
+        .map(|column| quote(column))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let placeholders = vec!["?"; columns.len()].join(", ");
+    let select = format!("SELECT {} FROM {}", column_list, quote(table));
+    let insert = format!(
+        "INSERT OR IGNORE INTO {} ({}) VALUES ({})",
+        quote(table),
+        column_list,
+        placeholders
+    );
+
+    let tx = match new.unchecked_transaction() {
+        Ok(tx) => tx,
+        Err(e) => return (0, Some(e.to_string())),
+    };
+    let result = (|| -> rusqlite::Result<usize> {
+        let mut read = old.prepare(&select)?;
+        let mut write = tx.prepare(&insert)?;
+        let mut rows = read.query([])?;
+        let mut copied = 0;
+        while let Some(row) = rows.next()? {
+            let values = (0..columns.len())
+                .map(|index| row.get::<_, Value>(index))
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            copied += write.execute(rusqlite::params_from_iter(values))?;
+        }
+        Ok(copied)
+    })();
+    let (copied, error) = match result {
+        Ok(copied) => (copied, None),
+        Err(e) => {
+            let copied = tx
+                .query_row(
+                    &format!("SELECT COUNT(*) FROM {}", quote(table)),
+                    [],
+                    |row| row.get::<_, i64>(0),
+                )
+                .unwrap_or(0) as usize;
+            warn!(
+                "Stopped recovering {} after {} row(s): {}",
+                table, copied, e
+            );
+            (copied, Some(e.to_string()))
+        }
+    };
+    match tx.commit() {
+        Ok(()) => (copied, error),
+        Err(e) => (0, Some(e.to_string())),
+    }
+}
+
+fn quote(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
+/// Other processes with the database open, found through `/proc` where it exists
+fn lock_holders(path: &Path) -> Vec<u32> {
+    let Ok(target) = path.canonicalize() else {
+        return Vec::new();
+    };
+    let Ok(processes) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let own = std::process::id();
+    let mut holders: Vec<u32> = processes
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse::<u32>().ok())
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            std::fs::read_dir(format!("/proc/{}/fd", pid)).is_ok_and(|fds| {
+                fds.flatten()
+                    .any(|fd| std::fs::read_link(fd.path()).is_ok_and(|link| link == target))
+            })
+        })
+        .collect();
+    holders.sort_unstable();
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn database_with_a_conversation(path: &Path) {
+        let manager = crate::session::SessionManager::new(Database::new(path).unwrap());
+        let session_id = manager.create_session();
+        manager
+            .add_user_message(&session_id, "Hello".to_string(), vec![])
+            .unwrap();
+        manager
+            .add_assistant_message(&session_id, "Hi there".to_string(), vec![], None, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_truncated_database_is_detected_and_backed_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("squid.db");
+        database_with_a_conversation(&path);
+        let original = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &original[..original.len().min(2048)]).unwrap();
+
+        let error = Database::new(&path).err().unwrap();
+        assert_eq!(diagnose(&error, &path), Some(OpenProblem::Corrupt));
+
+        let report = repair(&path).unwrap();
+        assert_eq!(
+            std::fs::read(&report.backup).unwrap(),
+            &original[..original.len().min(2048)]
+        );
+        assert!(report.backup.starts_with(dir.path()));
+        // The fresh database opens and works
+        let manager = crate::session::SessionManager::new(Database::new(&path).unwrap());
+        assert!(!manager.create_session().is_empty());
+    }
+
+    #[test]
+    fn test_repair_copies_every_readable_row() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("squid.db");
+        database_with_a_conversation(&path);
+
+        let report = repair(&path).unwrap();
+        assert_eq!((report.sessions, report.messages), (1, 2));
+        assert!(report.damaged.is_empty(), "{:?}", report.damaged);
+        assert!(
+            report
+                .tables
+                .iter()
+                .all(|(table, _)| !table.starts_with("rag_embeddings"))
+        );
+
+        let manager = crate::session::SessionManager::new(Database::new(&path).unwrap());
+        let ids = manager.list_sessions();
+        assert_eq!(ids.len(), 1);
+        let session = manager.get_session(&ids[0]).unwrap();
+        assert_eq!(session.messages[1].content, "Hi there");
+    }
+
+    #[test]
+    fn test_other_errors_are_not_diagnosed() {
+        let error = SquidError::Other("boom".to_string());
+        assert_eq!(diagnose(&error, Path::new("squid.db")), None);
+        let locked = OpenProblem::Locked {
+            holders: vec![4242],
+        };
+        assert!(describe(&locked, Path::new("squid.db")).contains("PID 4242"));
+    }
+}
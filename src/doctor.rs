@@ -1,9 +1,9 @@
 use log::debug;
-use serde::Deserialize;
 use std::fmt;
 use std::path::Path;
 
 use crate::config::Config;
+use crate::models::{ModelsResponse, extract_connection_error, fetch_models, models_url};
 
 /// Result of a single doctor check
 #[derive(Debug)]
@@ -286,11 +286,7 @@ impl Check for ApiConnectivityCheck {
         let api_url = &config.api_url;
 
         // Try to fetch the models endpoint
-        let models_url = if api_url.ends_with('/') {
-            format!("{}models", api_url)
-        } else {
-            format!("{}/models", api_url)
-        };
+        let models_url = models_url(api_url);
 
         debug!("Testing API connectivity: {}", models_url);
 
@@ -346,8 +342,17 @@ impl Check for AgentModelsCheck {
 
     async fn run(&self, config: &Config) -> CheckResult {
         // First, fetch available models from API
-        let available_models = match fetch_api_models(&config.api_url).await {
-            Ok(models) => models,
+        let available_models = match fetch_models(
+            &config.api_url,
+            None,
+            config
+                .network
+                .offline
+                .then_some(crate::config::OFFLINE_HTTP_TIMEOUT),
+        )
+        .await
+        {
+            Ok(models) => models.into_iter().map(|m| m.id).collect::<Vec<_>>(),
             Err(e) => {
                 return CheckResult::fail(format!("Cannot fetch models from API: {}", e));
             }
@@ -507,55 +512,6 @@ impl Check for WorkingDirectoryCheck {
 // Helper Functions
 // ============================================================================
 
-#[derive(Debug, Deserialize)]
-struct ModelsResponse {
-    data: Vec<ModelInfo>,
-}
-
-#[derive(Debug, Deserialize)]
-struct ModelInfo {
-    id: String,
-}
-
-/// Fetch available models from the API
-async fn fetch_api_models(api_url: &str) -> Result<Vec<String>, String> {
-    let models_url = if api_url.ends_with('/') {
-        format!("{}models", api_url)
-    } else {
-        format!("{}/models", api_url)
-    };
-
-    debug!("Fetching models from: {}", models_url);
-
-    let response = reqwest::get(&models_url)
-        .await
-        .map_err(|e| extract_connection_error(&e))?;
-
-    if !response.status().is_success() {
-        return Err(format!("HTTP {}", response.status()));
-    }
-
-    let models_response: ModelsResponse = response
-        .json()
-        .await
-        .map_err(|e| format!("Failed to parse response: {}", e))?;
-
-    Ok(models_response.data.iter().map(|m| m.id.clone()).collect())
-}
-
-/// Extract user-friendly connection error message
-fn extract_connection_error(error: &reqwest::Error) -> String {
-    if error.is_connect() {
-        "Connection refused - is the API server running?".to_string()
-    } else if error.is_timeout() {
-        "Connection timed out - check if the API server is running and accessible".to_string()
-    } else if error.is_request() {
-        format!("Request error: {}", error)
-    } else {
-        error.to_string()
-    }
-}
-
 /// Check if a directory is writable
 fn is_dir_writable(dir: &Path) -> bool {
     let test_file = dir.join(".squid_doctor_test");
@@ -0,0 +1,332 @@
+//! Where the assistant is working, appended to system prompts
+//!
+//! Template variables cover the platform and time, but not the project: without this section
+//! the model keeps asking which repository it is in. The section names the working directory
+//! and, inside a git repository, the branch, short HEAD SHA and whether the tree is dirty.
+//!
+//! Branch and commit are read straight from `.git`. Dirty status needs `git status`, which is
+//! given [`GIT_STATUS_TIMEOUT`] and left out when it takes longer or git isn't installed. The
+//! result is cached per directory, so only the first request in a directory pays for it.
+
+use log::debug;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, EnvContextConfig};
+
+/// Longest `git status` may take before dirty status is left out
+const GIT_STATUS_TIMEOUT: Duration = Duration::from_millis(50);
+
+/// How long a probe is reused for the same directory
+const GIT_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Length of the abbreviated HEAD SHA
+const SHORT_SHA_LEN: usize = 7;
+
+/// Git state of a working directory
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GitContext {
+    /// Root of the working tree
+    pub root: PathBuf,
+    /// Checked-out branch; `None` when HEAD is detached
+    pub branch: Option<String>,
+    /// Abbreviated HEAD SHA; `None` in a repository without commits
+    pub commit: Option<String>,
+    /// Uncommitted changes, if `git status` answered in time
+    pub dirty: Option<bool>,
+}
+
+/// The last probe: the directory it was for, when it ran and what it found
+type GitCache = Option<(PathBuf, Instant, Option<GitContext>)>;
+
+static GIT_CACHE: Mutex<GitCache> = Mutex::new(None);
+
+/// Git state of `dir`, reusing the last probe while the directory stays the same
+pub fn git_context(dir: &Path) -> Option<GitContext> {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let mut cache = GIT_CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((cached_dir, probed_at, context)) = cache.as_ref()
+        && *cached_dir == dir
+        && probed_at.elapsed() < GIT_CACHE_TTL
+    {
+        return context.clone();
+    }
+
+    let started = Instant::now();
+    let context = probe_git(&dir, GIT_STATUS_TIMEOUT);
+    debug!("Probed git context of {:?} in {:?}", dir, started.elapsed());
+    *cache = Some((dir, Instant::now(), context.clone()));
+    context
+}
+
+/// Read the git state of `dir` without the cache
+fn probe_git(dir: &Path, status_timeout: Duration) -> Option<GitContext> {
+    let (root, git_dir) = find_git_dir(dir)?;
+    let head = std::fs::read_to_string(git_dir.join("HEAD")).ok()?;
+    let head = head.trim();
+
+    let (branch, sha) = match head.strip_prefix("ref: ") {
+        Some(reference) => (
+            Some(
+                reference
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(reference)
+                    .to_string(),
+            ),
+            resolve_ref(&git_dir, reference),
+        ),
+        None => (None, Some(head.to_string())),
+    };
+    let commit = sha
+        .filter(|sha| sha.len() >= SHORT_SHA_LEN)
+        .map(|sha| sha[..SHORT_SHA_LEN].to_string());
+    let dirty = is_dirty(&root, status_timeout);
+
+    Some(GitContext {
+        root,
+        branch,
+        commit,
+        dirty,
+    })
+}
+
+/// The working tree root and git directory containing `dir`
+///
+/// In a linked worktree or submodule `.git` is a file pointing at the real git directory.
+fn find_git_dir(dir: &Path) -> Option<(PathBuf, PathBuf)> {
+    dir.ancestors().find_map(|ancestor| {
+        let dot_git = ancestor.join(".git");
+        if dot_git.is_dir() {
+            return Some((ancestor.to_path_buf(), dot_git));
+        }
+        let pointer = std::fs::read_to_string(&dot_git).ok()?;
+        let git_dir = pointer.trim().strip_prefix("gitdir:")?.trim();
+        Some((ancestor.to_path_buf(), ancestor.join(git_dir)))
+    })
+}
+
+/// SHA a ref points to, from its loose file or `packed-refs`
+fn resolve_ref(git_dir: &Path, reference: &str) -> Option<String> {
+    // Branches of a linked worktree live in the main repository's git directory
+    let common_dir = std::fs::read_to_string(git_dir.join("commondir"))
+        .map(|common| git_dir.join(common.trim()))
+        .unwrap_or_else(|_| git_dir.to_path_buf());
+
+    [git_dir, common_dir.as_path()].iter().find_map(|dir| {
+        if let Ok(sha) = std::fs::read_to_string(dir.join(reference)) {
+            return Some(sha.trim().to_string());
+        }
+        let packed = std::fs::read_to_string(dir.join("packed-refs")).ok()?;
+        packed.lines().find_map(|line| {
+            let (sha, name) = line.split_once(' ')?;
+            (name == reference).then(|| sha.to_string())
+        })
+    })
+}
+
+/// Whether `git status` reports changes in `root`, if it answers within `timeout`
+fn is_dirty(root: &Path, timeout: Duration) -> Option<bool> {
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(root)
+        .args(["status", "--porcelain"])
+        // Don't take the index lock just to look
+        .env("GIT_OPTIONAL_LOCKS", "0")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    // Any output means changes, so one byte is enough and a big diff can't fill the pipe
+    let mut stdout = child.stdout.take()?;
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let mut byte = [0u8; 1];
+        let _ = sender.send(stdout.read(&mut byte).ok());
+    });
+
+    let dirty = match receiver.recv_timeout(timeout) {
+        Ok(Some(0)) => child
+            .wait()
+            .ok()
+            .filter(|status| status.success())
+            .map(|_| false),
+        Ok(Some(_)) => Some(true),
+        Ok(None) | Err(_) => None,
+    };
+    let _ = child.kill();
+    let _ = child.wait();
+    dirty
+}
+
+/// The environment section for a prompt whose tools run in `dir`, or `None` if it's empty
+pub fn get_env_context(settings: &EnvContextConfig, dir: &Path) -> Option<String> {
+    let mut lines = Vec::new();
+    if settings.working_dir {
+        let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+        lines.push(format!("- Working directory: {}", dir.display()));
+    }
+
+    if (settings.git_branch || settings.git_commit || settings.git_status)
+        && let Some(git) = git_context(dir)
+    {
+        if settings.git_branch {
+            let branch = git.branch.as_deref().unwrap_or("(detached HEAD)");
+            lines.push(format!("- Git branch: {}", branch));
+        }
+        if settings.git_commit
+            && let Some(commit) = &git.commit
+        {
+            lines.push(format!("- Git commit: {}", commit));
+        }
+        if settings.git_status
+            && let Some(dirty) = git.dirty
+        {
+            let status = if dirty {
+                "uncommitted changes"
+            } else {
+                "clean"
+            };
+            lines.push(format!("- Git status: {}", status));
+        }
+    }
+
+    if lines.is_empty() {
+        return None;
+    }
+    Some(format!("\n\n## Environment\n\n{}\n", lines.join("\n")))
+}
+
+/// Append the environment section to a rendered system prompt
+///
+/// `working_dir` is the session's directory; tools run in the current directory without one.
+/// The git probe runs on a blocking thread, so a slow `git status` never stalls the runtime.
+pub async fn append_to_prompt(
+    system_prompt: &mut String,
+    config: &Config,
+    working_dir: Option<&Path>,
+) {
+    let dir = match working_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => match std::env::current_dir() {
+            Ok(dir) => dir,
+            Err(_) => return,
+        },
+    };
+    let settings = config.env_context.clone();
+    let section = tokio::task::spawn_blocking(move || get_env_context(&settings, &dir)).await;
+    if let Ok(Some(section)) = section {
+        system_prompt.push_str(&section);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run git in `dir` with a throwaway identity
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    fn repo_fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        git(dir.path(), &["init", "-q", "-b", "feature/env"]);
+        std::fs::write(dir.path().join("README.md"), "hello\n").unwrap();
+        git(dir.path(), &["add", "README.md"]);
+        git(dir.path(), &["commit", "-q", "-m", "Initial commit"]);
+        dir
+    }
+
+    #[test]
+    fn test_branch_commit_and_dirty_status() {
+        let dir = repo_fixture();
+        let root = dir.path().canonicalize().unwrap();
+        let nested = root.join("src/nested");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        let clean = probe_git(&nested, Duration::from_secs(5)).unwrap();
+        assert_eq!(clean.root, root);
+        assert_eq!(clean.branch.as_deref(), Some("feature/env"));
+        assert_eq!(clean.commit.as_ref().map(String::len), Some(SHORT_SHA_LEN));
+        assert_eq!(clean.dirty, Some(false));
+
+        std::fs::write(root.join("README.md"), "changed\n").unwrap();
+        let dirty = probe_git(&root, Duration::from_secs(5)).unwrap();
+        assert_eq!(dirty.commit, clean.commit);
+        assert_eq!(dirty.dirty, Some(true));
+    }
+
+    #[test]
+    fn test_detached_head_and_packed_refs() {
+        let dir = tempfile::tempdir().unwrap();
+        let git_dir = dir.path().join(".git");
+        std::fs::create_dir_all(&git_dir).unwrap();
+        let sha = "0123456789abcdef0123456789abcdef01234567";
+
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(
+            git_dir.join("packed-refs"),
+            format!("# pack-refs with: peeled\n{} refs/heads/main\n", sha),
+        )
+        .unwrap();
+        let packed = probe_git(dir.path(), Duration::ZERO).unwrap();
+        assert_eq!(packed.branch.as_deref(), Some("main"));
+        assert_eq!(packed.commit.as_deref(), Some("0123456"));
+
+        std::fs::write(git_dir.join("HEAD"), format!("{}\n", sha)).unwrap();
+        let detached = probe_git(dir.path(), Duration::ZERO).unwrap();
+        assert_eq!(detached.branch, None);
+        assert_eq!(detached.commit.as_deref(), Some("0123456"));
+    }
+
+    #[test]
+    fn test_env_context_fields_are_toggleable() {
+        let dir = repo_fixture();
+        let root = dir.path().canonicalize().unwrap();
+
+        let section = get_env_context(&EnvContextConfig::default(), &root).unwrap();
+        assert!(section.starts_with("\n\n## Environment\n\n"));
+        assert!(section.contains(&format!("- Working directory: {}", root.display())));
+        assert!(section.contains("- Git branch: feature/env\n"));
+        assert!(section.contains("- Git commit: "));
+
+        let settings = EnvContextConfig {
+            working_dir: false,
+            git_branch: true,
+            git_commit: false,
+            git_status: false,
+        };
+        assert_eq!(
+            get_env_context(&settings, &root).as_deref(),
+            Some("\n\n## Environment\n\n- Git branch: feature/env\n")
+        );
+
+        let outside = tempfile::tempdir().unwrap();
+        assert_eq!(get_env_context(&settings, outside.path()), None);
+    }
+
+    #[tokio::test]
+    async fn test_append_to_prompt_probes_off_the_runtime() {
+        let dir = repo_fixture();
+        let root = dir.path().canonicalize().unwrap();
+
+        let mut prompt = "You are helpful.".to_string();
+        append_to_prompt(&mut prompt, &Config::default(), Some(&root)).await;
+        assert!(prompt.starts_with("You are helpful.\n\n## Environment\n\n"));
+        assert!(prompt.contains("- Git branch: feature/env\n"));
+    }
+}
@@ -0,0 +1,247 @@
+//! Errors shared across modules
+//!
+//! Configuration, storage, sessions, the LLM client and tools report a [`SquidError`] whose
+//! variant says what went wrong, so callers can decide whether to retry and each front end can
+//! pick a status in one place: `api.rs` maps variants to HTTP statuses and stream error codes,
+//! `main.rs` to exit codes.
+
+use async_openai::error::OpenAIError;
+use thiserror::Error;
+
+use crate::providers::ProviderError;
+use crate::validate::PathValidationError;
+
+#[derive(Error, Debug)]
+pub enum SquidError {
+    /// The configuration is missing, unreadable or invalid
+    #[error("{0}")]
+    Config(String),
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    /// The model provider failed or couldn't be reached
+    #[error("{message}")]
+    Provider {
+        /// HTTP status of the provider's response, if it sent one
+        status: Option<u16>,
+        message: String,
+        /// Trying the same request again later may succeed (rate limits, outages, timeouts)
+        retriable: bool,
+    },
+    /// The user or a policy didn't allow a tool call
+    #[error("{0}")]
+    ToolDenied(String),
+    #[error(transparent)]
+    PathValidation(#[from] PathValidationError),
+    #[error("{0}")]
+    Rag(String),
+    #[error("Session not found")]
+    SessionNotFound(String),
+    /// The session only continues with the model that started it
+    #[error(
+        "Session is locked to model '{locked}' and can't be continued with '{requested}'; pick an agent using '{locked}' or clear the session's locked_model"
+    )]
+    ModelLocked { locked: String, requested: String },
+    /// Another operation holds the session (e.g. compaction during a chat turn)
+    #[error("{0}")]
+    SessionBusy(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Serialization(#[from] serde_json::Error),
+    /// Any other failure, described for the user
+    #[error("{0}")]
+    Other(String),
+}
+
+pub type Result<T, E = SquidError> = std::result::Result<T, E>;
+
+impl SquidError {
+    /// Whether the same request may succeed if tried again later
+    pub fn is_retriable(&self) -> bool {
+        match self {
+            SquidError::Provider { retriable, .. } => *retriable,
+            SquidError::SessionBusy(_) => true,
+            SquidError::Database(rusqlite::Error::SqliteFailure(error, _)) => matches!(
+                error.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            ),
+            _ => false,
+        }
+    }
+
+    /// A provider failure; `status` decides whether it is worth retrying
+    pub fn provider(status: Option<u16>, message: impl Into<String>) -> Self {
+        SquidError::Provider {
+            status,
+            message: message.into(),
+            retriable: status.is_none_or(|status| status == 429 || status >= 500),
+        }
+    }
+}
+
+impl From<String> for SquidError {
+    fn from(message: String) -> Self {
+        SquidError::Other(message)
+    }
+}
+
+impl From<&str> for SquidError {
+    fn from(message: &str) -> Self {
+        SquidError::Other(message.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for SquidError {
+    fn from(error: tokio::task::JoinError) -> Self {
+        SquidError::Other(format!("Background task failed: {}", error))
+    }
+}
+
+/// Config files that don't parse or can't be written back are configuration errors
+macro_rules! config_errors {
+    ($($error:ty),*) => {
+        $(impl From<$error> for SquidError {
+            fn from(error: $error) -> Self {
+                SquidError::Config(error.to_string())
+            }
+        })*
+    };
+}
+
+config_errors!(
+    toml::de::Error,
+    toml::ser::Error,
+    serde_yaml::Error,
+    toml_edit::TomlError
+);
+
+impl From<ProviderError> for SquidError {
+    /// Classify an error reported by a provider client
+    fn from(error: ProviderError) -> Self {
+        let message = error.to_string();
+        if let Some(error) = error.downcast_ref::<reqwest::Error>() {
+            return reqwest_error(error, message);
+        }
+        if let Some(error) = error.downcast_ref::<OpenAIError>() {
+            return openai_error(error, message);
+        }
+        // Clients that format the status into the message, e.g. "(HTTP 503)"
+        match http_status_in(&message) {
+            Some(status) => SquidError::provider(Some(status), message),
+            None => SquidError::Provider {
+                status: None,
+                message,
+                retriable: false,
+            },
+        }
+    }
+}
+
+impl From<OpenAIError> for SquidError {
+    fn from(error: OpenAIError) -> Self {
+        let message = error.to_string();
+        openai_error(&error, message)
+    }
+}
+
+fn openai_error(error: &OpenAIError, message: String) -> SquidError {
+    match error {
+        OpenAIError::Reqwest(error) => reqwest_error(error, message),
+        OpenAIError::ApiError(api_error) => {
+            let kind = api_error.r#type.as_deref().unwrap_or_default();
+            let code = api_error.code.as_deref().unwrap_or_default();
+            SquidError::Provider {
+                status: None,
+                message,
+                retriable: kind == "server_error"
+                    || kind == "rate_limit_error"
+                    || code == "rate_limit_exceeded",
+            }
+        }
+        // The connection dropped mid-stream
+        OpenAIError::StreamError(_) => SquidError::provider(None, message),
+        _ => SquidError::Provider {
+            status: None,
+            message,
+            retriable: false,
+        },
+    }
+}
+
+impl From<reqwest::Error> for SquidError {
+    fn from(error: reqwest::Error) -> Self {
+        let message = error.to_string();
+        reqwest_error(&error, message)
+    }
+}
+
+fn reqwest_error(error: &reqwest::Error, message: String) -> SquidError {
+    match error.status() {
+        Some(status) => SquidError::provider(Some(status.as_u16()), message),
+        // No response at all: refused connections and timeouts are worth another try
+        None => SquidError::Provider {
+            status: None,
+            message,
+            retriable: error.is_connect() || error.is_timeout(),
+        },
+    }
+}
+
+/// The status in an `HTTP 503`-style fragment of an error message
+fn http_status_in(message: &str) -> Option<u16> {
+    let rest = &message[message.find("HTTP ")? + "HTTP ".len()..];
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    digits
+        .parse()
+        .ok()
+        .filter(|status| (100..600).contains(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_errors_are_classified_by_status() {
+        let rate_limited: ProviderError = "Ollama request failed (HTTP 429): slow down".into();
+        let error = SquidError::from(rate_limited);
+        assert!(matches!(
+            error,
+            SquidError::Provider {
+                status: Some(429),
+                retriable: true,
+                ..
+            }
+        ));
+        assert_eq!(
+            error.to_string(),
+            "Ollama request failed (HTTP 429): slow down"
+        );
+
+        let bad_request: ProviderError = "Ollama request failed (HTTP 400): bad model".into();
+        assert!(!SquidError::from(bad_request).is_retriable());
+
+        let unknown: ProviderError = "Ollama error: model not found".into();
+        assert!(matches!(
+            SquidError::from(unknown),
+            SquidError::Provider {
+                status: None,
+                retriable: false,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_busy_sessions_and_databases_are_retriable() {
+        assert!(SquidError::SessionBusy("compacting".to_string()).is_retriable());
+        assert!(!SquidError::SessionNotFound("abc".to_string()).is_retriable());
+
+        let locked = rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        );
+        assert!(SquidError::from(locked).is_retriable());
+        assert!(!SquidError::from(rusqlite::Error::QueryReturnedNoRows).is_retriable());
+    }
+}
@@ -0,0 +1,416 @@
+//! Which earlier turns of a session are replayed with a new message
+//!
+//! `context.strategy` decides: `full` sends every earlier message, `recent` only the latest
+//! `context.recent_turns` turns, and `relevant` adds the older turns most similar to the new
+//! message as long as they fit in `context.history_budget` of the context window. A turn is a
+//! user message with the answers that follow it, so tool calls stay with their results, and a
+//! compaction summary is always kept. Messages are embedded once with the RAG embedder and cached
+//! in `message_embeddings`; without an embedder, or when embedding fails, `relevant` falls back
+//! to `recent`. Older turns sent out of order are labeled with their time so the model sees the
+//! gap.
+
+use log::{debug, warn};
+use std::ops::Range;
+
+use crate::config::{ContextConfig, HistoryStrategy};
+use crate::rag::RagEmbedder;
+use crate::session::{ChatMessage, SessionManager};
+use crate::tokens;
+
+/// Longest part of a message that is embedded, in characters
+const MAX_EMBEDDED_CHARS: usize = 4000;
+
+/// Earlier messages to send, in order, with a new message asking `question`
+pub async fn select(
+    history: &[ChatMessage],
+    question: &str,
+    settings: &ContextConfig,
+    embedder: Option<&RagEmbedder>,
+    session_manager: &SessionManager,
+    model: &str,
+    context_window: u32,
+) -> Vec<ChatMessage> {
+    let turns = turns(history);
+    if settings.strategy == HistoryStrategy::Full || turns.len() <= settings.recent_turns {
+        return history.to_vec();
+    }
+
+    if settings.strategy == HistoryStrategy::Relevant {
+        let older = turns.len() - settings.recent_turns;
+        match embedder {
+            Some(embedder) => {
+                match similarities(
+                    history,
+                    &turns[..older],
+                    question,
+                    embedder,
+                    session_manager,
+                )
+                .await
+                {
+                    Ok(scores) => {
+                        let budget = (context_window as f64
+                            * settings.history_budget.clamp(0.0, 1.0))
+                            as i64;
+                        return pick(
+                            history,
+                            &turns,
+                            settings.recent_turns,
+                            &scores,
+                            model,
+                            budget,
+                        );
+                    }
+                    Err(e) => warn!("Failed to embed history, sending the recent turns: {}", e),
+                }
+            }
+            None => {
+                debug!("No embedder for the relevant history strategy, sending the recent turns")
+            }
+        }
+    }
+    pick(history, &turns, settings.recent_turns, &[], model, 0)
+}
+
+/// Index ranges of the turns in `history`; messages before the first user message form a turn
+fn turns(history: &[ChatMessage]) -> Vec<Range<usize>> {
+    let mut turns: Vec<Range<usize>> = Vec::new();
+    for (index, message) in history.iter().enumerate() {
+        match turns.last_mut() {
+            Some(turn) if message.role != "user" => turn.end = index + 1,
+            _ => turns.push(index..index + 1),
+        }
+    }
+    turns
+}
+
+/// How similar each of the `older` turns is to `question`: its best matching message
+async fn similarities(
+    history: &[ChatMessage],
+    older: &[Range<usize>],
+    question: &str,
+    embedder: &RagEmbedder,
+    session_manager: &SessionManager,
+) -> anyhow::Result<Vec<f32>> {
+    let query = embedder.embed_text(question).await?;
+    let model = embedder.model();
+    let end = older.last().map_or(0, |turn| turn.end);
+    let ids: Vec<i64> = history[..end].iter().filter_map(|m| m.id).collect();
+    let mut cached = session_manager.message_embeddings(&ids, model);
+
+    let mut scores = Vec::with_capacity(older.len());
+    for turn in older {
+        let mut best = f32::MIN;
+        for message in &history[turn.clone()] {
+            if message.content.trim().is_empty() {
+                continue;
+            }
+            let embedding = match message.id.and_then(|id| cached.remove(&id)) {
+                Some(embedding) => embedding,
+                None => {
+                    let text = crate::text::truncate(&message.content, MAX_EMBEDDED_CHARS);
+                    let embedding = embedder.embed_text(&text).await?;
+                    if let Some(id) = message.id {
+                        session_manager.save_message_embedding(id, model, &embedding);
+                    }
+                    embedding
+                }
+            };
+            best = best.max(cosine_similarity(&query, &embedding));
+        }
+        scores.push(best);
+    }
+    Ok(scores)
+}
+
+/// The last `recent` turns and summaries, plus the best scoring older turns that fit in `budget`
+///
+/// `scores` are for the turns before the recent ones; with none, only those are kept.
+fn pick(
+    history: &[ChatMessage],
+    turns: &[Range<usize>],
+    recent: usize,
+    scores: &[f32],
+    model: &str,
+    budget: i64,
+) -> Vec<ChatMessage> {
+    let turn_tokens = |turn: &Range<usize>| -> i64 {
+        history[turn.clone()]
+            .iter()
+            .map(|message| tokens::estimate_message_tokens(model, &message.content))
+            .sum()
+    };
+    let first_recent = turns.len().saturating_sub(recent);
+    let mut kept = vec![false; turns.len()];
+    let mut recalled = vec![false; turns.len()];
+    let mut used = 0;
+    for (index, turn) in turns.iter().enumerate() {
+        let summary = history[turn.clone()].iter().any(|message| {
+            message
+                .metadata
+                .as_ref()
+                .is_some_and(|m| m.summary_of.is_some())
+        });
+        if index >= first_recent || summary {
+            kept[index] = true;
+            used += turn_tokens(turn);
+        }
+    }
+
+    let mut candidates: Vec<usize> = (0..first_recent.min(scores.len()))
+        .filter(|&index| !kept[index])
+        .collect();
+    candidates.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+    for index in candidates {
+        let size = turn_tokens(&turns[index]);
+        if used + size <= budget {
+            used += size;
+            kept[index] = true;
+            recalled[index] = true;
+        }
+    }
+    debug!(
+        "Sending {} of {} earlier turns ({} recalled by relevance)",
+        kept.iter().filter(|&&kept| kept).count(),
+        turns.len(),
+        recalled.iter().filter(|&&recalled| recalled).count()
+    );
+
+    let mut messages = Vec::new();
+    for (index, turn) in turns.iter().enumerate() {
+        if !kept[index] {
+            continue;
+        }
+        for message in &history[turn.clone()] {
+            messages.push(if recalled[index] {
+                labeled(message)
+            } else {
+                message.clone()
+            });
+        }
+    }
+    messages
+}
+
+/// `message` prefixed with when it was written, for turns sent out of order
+fn labeled(message: &ChatMessage) -> ChatMessage {
+    let time = chrono::DateTime::from_timestamp(message.timestamp, 0)
+        .map(|time| time.format("%Y-%m-%d %H:%M UTC").to_string())
+        .unwrap_or_else(|| "an unknown time".to_string());
+    ChatMessage {
+        content: format!(
+            "[From earlier in this conversation, {}]\n{}",
+            time, message.content
+        ),
+        ..message.clone()
+    }
+}
+
+fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norms = norm(a) * norm(b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{ProviderKind, RagConfig};
+    use crate::session::MessageMetadata;
+
+    const MODEL: &str = "gpt-4";
+
+    fn message(role: &str, content: &str, timestamp: i64) -> ChatMessage {
+        ChatMessage {
+            id: None,
+            parent_message_id: None,
+            role: role.to_string(),
+            content: content.to_string(),
+            sources: Vec::new(),
+            timestamp,
+            thinking_steps: None,
+            metadata: None,
+            finish_reason: None,
+        }
+    }
+
+    /// Turns about migrations, cooking and the latest question, oldest first
+    fn conversation() -> Vec<(&'static str, &'static str)> {
+        vec![
+            (
+                "How do I add a column in a SQLite migration?",
+                "Use ALTER TABLE ... ADD COLUMN in a new migration file.",
+            ),
+            (
+                "What's a good pasta recipe?",
+                "Try aglio e olio: garlic, olive oil and chili.",
+            ),
+            (
+                "Can you review this function?",
+                "It looks fine, but the loop could use an iterator.",
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_turns_group_answers_with_their_question() {
+        let history = vec![
+            ChatMessage {
+                metadata: Some(MessageMetadata {
+                    summary_of: Some(4),
+                    ..Default::default()
+                }),
+                ..message("assistant", "Summary", 1)
+            },
+            message("user", "a", 2),
+            message("assistant", "b", 3),
+            message("assistant", "c", 4),
+            message("user", "d", 5),
+        ];
+        assert_eq!(turns(&history), vec![0..1, 1..4, 4..5]);
+
+        // The summary stays even when only the latest turn is kept
+        let kept = pick(&history, &turns(&history), 1, &[], MODEL, 0);
+        let contents: Vec<&str> = kept.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["Summary", "d"]);
+    }
+
+    #[test]
+    fn test_pick_recalls_the_best_scoring_turn_that_fits() {
+        let mut history = Vec::new();
+        for (index, (question, answer)) in conversation().into_iter().enumerate() {
+            history.push(message(
+                "user",
+                question,
+                1_760_000_000 + index as i64 * 3600,
+            ));
+            history.push(message(
+                "assistant",
+                answer,
+                1_760_000_060 + index as i64 * 3600,
+            ));
+        }
+        let turns = turns(&history);
+        let size = |range: Range<usize>| -> i64 {
+            history[range]
+                .iter()
+                .map(|m| tokens::estimate_message_tokens(MODEL, &m.content))
+                .sum()
+        };
+        // Room for the latest turn and one more
+        let budget = size(4..6) + size(0..2).max(size(2..4));
+
+        let kept = pick(&history, &turns, 1, &[0.9, 0.1], MODEL, budget);
+        assert_eq!(kept.len(), 4);
+        assert_eq!(
+            kept[0].content,
+            "[From earlier in this conversation, 2025-10-09 08:53 UTC]\nHow do I add a column in a SQLite migration?"
+        );
+        assert!(kept[1].content.starts_with("[From earlier"));
+        assert_eq!(kept[2].content, "Can you review this function?");
+
+        // Recent keeps only the latest turns
+        let kept = pick(&history, &turns, 1, &[], MODEL, budget);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].content, "Can you review this function?");
+    }
+
+    #[tokio::test]
+    async fn test_relevant_history_embeds_messages_once_and_picks_the_related_turn() {
+        // The question, then the four older messages, then only the question again
+        let embedding =
+            |values: [f32; 2]| format!("{{\"embedding\": [{}, {}]}}", values[0], values[1]);
+        let (url, requests) = crate::providers::test_support::serve_sequence(
+            "application/json",
+            vec![
+                embedding([1.0, 0.0]),
+                embedding([0.9, 0.1]),
+                embedding([0.8, 0.2]),
+                embedding([0.0, 1.0]),
+                embedding([0.1, 0.9]),
+                embedding([1.0, 0.0]),
+            ],
+        )
+        .await;
+        let embedder = RagEmbedder::new(
+            &RagConfig {
+                embedding_url: url,
+                embedding_provider: ProviderKind::Ollama,
+                ..Default::default()
+            },
+            reqwest::Client::new(),
+        )
+        .unwrap();
+
+        let session_manager = SessionManager::new(crate::db::Database::new(":memory:").unwrap());
+        let session_id = session_manager.create_session();
+        for (question, answer) in conversation() {
+            session_manager
+                .add_user_message(&session_id, question.to_string(), Vec::new())
+                .unwrap();
+            session_manager
+                .add_assistant_message(&session_id, answer.to_string(), Vec::new(), None, None)
+                .unwrap();
+        }
+        let history = session_manager.get_session(&session_id).unwrap().messages;
+        let settings = ContextConfig {
+            strategy: HistoryStrategy::Relevant,
+            recent_turns: 1,
+            history_budget: 1.0,
+            ..Default::default()
+        };
+        // Enough for the latest turn and one of the older ones
+        let context_window = history
+            .iter()
+            .map(|m| tokens::estimate_message_tokens(MODEL, &m.content))
+            .sum::<i64>() as u32
+            - 5;
+
+        let question = "Which migration adds the index?";
+        let kept = select(
+            &history,
+            question,
+            &settings,
+            Some(&embedder),
+            &session_manager,
+            MODEL,
+            context_window,
+        )
+        .await;
+        let contents: Vec<&str> = kept.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents.len(), 4);
+        assert!(contents[0].ends_with("How do I add a column in a SQLite migration?"));
+        assert!(!contents.iter().any(|c| c.contains("pasta")));
+
+        // Stored embeddings are reused: only the new question is embedded
+        let again = select(
+            &history,
+            question,
+            &settings,
+            Some(&embedder),
+            &session_manager,
+            MODEL,
+            context_window,
+        )
+        .await;
+        assert_eq!(again.len(), 4);
+        assert_eq!(requests.await.unwrap().len(), 6);
+
+        // Without an embedder only the recent turns are sent
+        let recent = select(
+            &history,
+            question,
+            &settings,
+            None,
+            &session_manager,
+            MODEL,
+            context_window,
+        )
+        .await;
+        assert_eq!(recent.len(), 2);
+    }
+}
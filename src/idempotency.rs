@@ -0,0 +1,188 @@
+//! Client-generated idempotency keys for chat requests
+//!
+//! A client retrying `POST /api/chat` after a dropped connection sends the same key in the
+//! `Idempotency-Key` header (or as `client_message_id`). The first request claims the key; a retry
+//! while it still streams gets `409` with that request's id, and a retry after it saved its answer
+//! gets the answer replayed instead of a new generation. A request that ends without an answer
+//! releases its key, so its retry runs normally. A key belongs to the session its request ran
+//! in; sending it for another session is refused.
+
+use actix_web::{HttpRequest, HttpResponse};
+use std::sync::Arc;
+
+use crate::api::StreamEvent;
+use crate::db::{IdempotencyRecord, TurnUsage};
+use crate::replay;
+use crate::session::SessionManager;
+use crate::stream_events;
+
+/// Request header carrying the key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+/// Response header set when a stored answer is replayed instead of generated
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// Response header with the id of the chat request that claimed the key
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest accepted key
+const MAX_KEY_LENGTH: usize = 255;
+
+/// The idempotency key of a chat request, if it sent one; the header wins over the body field
+pub fn request_key(
+    req: &HttpRequest,
+    client_message_id: Option<&str>,
+) -> Result<Option<String>, String> {
+    let header = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| format!("{} must be visible ASCII", IDEMPOTENCY_KEY_HEADER))?,
+        ),
+        None => None,
+    };
+    let Some(key) = header.or(client_message_id).map(str::trim) else {
+        return Ok(None);
+    };
+    if key.is_empty() {
+        return Ok(None);
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(format!(
+            "Idempotency key is longer than {} characters",
+            MAX_KEY_LENGTH
+        ));
+    }
+    Ok(Some(key.to_string()))
+}
+
+/// Response for a key an earlier request already used, or `None` when this request should run
+///
+/// `session_id` is the session the request names, if any. An answer that can't be replayed any
+/// more (its message was deleted) releases the key.
+pub fn existing_response(
+    key: &str,
+    record: &IdempotencyRecord,
+    session_id: Option<&str>,
+    session_manager: &SessionManager,
+    max_event_bytes: usize,
+) -> Option<HttpResponse> {
+    if session_id.is_some_and(|session_id| session_id != record.session_id) {
+        return Some(HttpResponse::UnprocessableEntity().json(serde_json::json!({
+            "error": "This idempotency key was already used in another session",
+            "code": "idempotency_key_reused",
+        })));
+    }
+    let Some(message_id) = record.assistant_message_id else {
+        return Some(
+            HttpResponse::Conflict()
+                .insert_header((REQUEST_ID_HEADER, record.request_id.as_str()))
+                .json(serde_json::json!({
+                    "error": "A request with this idempotency key is still streaming",
+                    "code": "request_in_flight",
+                    "request_id": record.request_id,
+                })),
+        );
+    };
+
+    let events = session_manager
+        .get_session(&record.session_id)
+        .and_then(|session| {
+            let index = session
+                .messages
+                .iter()
+                .position(|message| message.id == Some(message_id))?;
+            replay::replay_events(&session, Some(index), false).ok()
+        });
+    let Some(mut events) = events else {
+        session_manager.release_idempotency_key(key);
+        return None;
+    };
+    // Clients add each turn's usage to their totals, so the session total would count twice
+    events.retain_mut(|event| match event {
+        StreamEvent::Usage {
+            input_tokens,
+            output_tokens,
+            reasoning_tokens,
+            cache_tokens,
+            ..
+        } => match record.usage {
+            Some(usage) => {
+                *input_tokens = usage.input_tokens;
+                *output_tokens = usage.output_tokens;
+                *reasoning_tokens = usage.reasoning_tokens;
+                *cache_tokens = usage.cache_tokens;
+                true
+            }
+            None => false,
+        },
+        _ => true,
+    });
+
+    let frames = events
+        .into_iter()
+        .enumerate()
+        .map(|(index, event)| {
+            Ok::<_, actix_web::Error>(stream_events::chunked_frames(
+                &event.to_json(),
+                index as u64 + 1,
+                max_event_bytes,
+            ))
+        })
+        .collect::<Vec<_>>();
+    Some(
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .insert_header(("X-Accel-Buffering", "no"))
+            .insert_header((REPLAYED_HEADER, "true"))
+            .insert_header((REQUEST_ID_HEADER, record.request_id.as_str()))
+            .streaming(futures::stream::iter(frames)),
+    )
+}
+
+/// A claimed key, held for the lifetime of its chat stream
+///
+/// Dropping it before [`KeyClaim::complete`] releases the key, including when the client
+/// disconnects mid-stream.
+pub struct KeyClaim {
+    key: String,
+    session_manager: Arc<SessionManager>,
+    user_message_id: Option<i64>,
+    completed: bool,
+}
+
+impl KeyClaim {
+    pub fn new(key: String, session_manager: Arc<SessionManager>) -> Self {
+        Self {
+            key,
+            session_manager,
+            user_message_id: None,
+            completed: false,
+        }
+    }
+
+    /// Remember the user message the request saved, to tag it with the key
+    pub fn set_user_message(&mut self, message_id: Option<i64>) {
+        self.user_message_id = message_id;
+    }
+
+    /// Record the saved answer and the tokens its turn used, so retries replay it
+    pub fn complete(mut self, assistant_message_id: i64, usage: TurnUsage) {
+        self.session_manager.complete_idempotency_key(
+            &self.key,
+            self.user_message_id,
+            assistant_message_id,
+            &usage,
+        );
+        self.completed = true;
+    }
+}
+
+impl Drop for KeyClaim {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.session_manager.release_idempotency_key(&self.key);
+        }
+    }
+}
@@ -0,0 +1,804 @@
+//! Import conversations exported from ChatGPT, Claude or squid
+//!
+//! Each export is a JSON array of conversations. Conversations are parsed one at a time so a
+//! malformed entry is skipped with a warning instead of aborting the whole import. Content squid
+//! can't represent (tool calls, tool output, images) is kept as short text notes.
+
+use crate::error::{Result, SquidError};
+use clap::ValueEnum;
+use indicatif::ProgressBar;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use crate::db::Database;
+use crate::session::{
+    ChatMessage, ChatSession, ImportSource, SessionMetadata, Source, SourceOrigin,
+};
+
+/// Sessions written per database transaction
+pub const IMPORT_BATCH_SIZE: usize = 50;
+
+/// Export format accepted by `squid sessions import`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+    /// `conversations.json` from a ChatGPT data export
+    Chatgpt,
+    /// `conversations.json` from a Claude data export
+    Claude,
+    /// Sessions serialized by squid
+    Squid,
+}
+
+impl ImportFormat {
+    /// Name recorded in the session metadata
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImportFormat::Chatgpt => "chatgpt",
+            ImportFormat::Claude => "claude",
+            ImportFormat::Squid => "squid",
+        }
+    }
+}
+
+/// Sessions parsed from an export, plus what had to be skipped
+#[derive(Debug, Default)]
+pub struct ParsedExport {
+    pub sessions: Vec<ChatSession>,
+    pub warnings: Vec<String>,
+}
+
+/// Outcome of writing parsed sessions to the database
+#[derive(Debug, Default, PartialEq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Conversations already imported earlier (or repeated in the export)
+    pub duplicates: usize,
+}
+
+/// Parse an export into sessions tagged with their source conversation id
+///
+/// Fails only if the input isn't a JSON conversation list; bad entries become warnings.
+pub fn parse_export(format: ImportFormat, json: &str) -> Result<ParsedExport, String> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid JSON export: {}", e))?;
+    let entries = match value {
+        Value::Array(entries) => entries,
+        // A single squid session may be exported on its own
+        entry @ Value::Object(_) if format == ImportFormat::Squid => vec![entry],
+        _ => return Err("Expected a JSON array of conversations".to_string()),
+    };
+
+    let mut parsed = ParsedExport::default();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let result = match format {
+            ImportFormat::Chatgpt => parse_chatgpt(entry, &mut parsed.warnings),
+            ImportFormat::Claude => parse_claude(entry, &mut parsed.warnings),
+            ImportFormat::Squid => parse_squid(entry),
+        };
+        match result {
+            Ok((conversation_id, mut session)) => {
+                if session.messages.is_empty() {
+                    parsed.warnings.push(format!(
+                        "Skipping conversation #{} ({}): no messages",
+                        index + 1,
+                        conversation_id
+                    ));
+                    continue;
+                }
+                session.metadata = Some(SessionMetadata {
+                    imported_from: Some(ImportSource {
+                        format: format.as_str().to_string(),
+                        conversation_id,
+                    }),
+                    ..Default::default()
+                });
+                parsed.sessions.push(session);
+            }
+            Err(e) => parsed
+                .warnings
+                .push(format!("Skipping conversation #{}: {}", index + 1, e)),
+        }
+    }
+
+    Ok(parsed)
+}
+
+/// Save sessions in batches, skipping conversations that were imported before
+pub fn write_sessions(
+    db: &Database,
+    format: ImportFormat,
+    sessions: &[ChatSession],
+    progress: &ProgressBar,
+) -> Result<ImportSummary> {
+    let mut seen = db.imported_conversation_ids(format.as_str())?;
+    let mut summary = ImportSummary::default();
+
+    for batch in sessions.chunks(IMPORT_BATCH_SIZE) {
+        db.in_transaction(|db| {
+            for session in batch {
+                progress.inc(1);
+                let Some(conversation_id) = session
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.imported_from.as_ref())
+                    .map(|source| source.conversation_id.clone())
+                else {
+                    continue;
+                };
+                if !seen.insert(conversation_id) {
+                    summary.duplicates += 1;
+                    continue;
+                }
+
+                db.save_session(session)?;
+                for message in &session.messages {
+                    db.save_message(&session.id, message)?;
+                }
+                // Saving messages bumps `updated_at`; keep the exported value
+                db.save_session(session)?;
+                summary.imported += 1;
+            }
+            Ok(())
+        })?;
+    }
+
+    Ok(summary)
+}
+
+/// Build a session from parsed messages, merging consecutive messages from the same role
+fn build_session(
+    title: Option<String>,
+    created_at: Option<i64>,
+    updated_at: Option<i64>,
+    messages: Vec<ChatMessage>,
+) -> ChatSession {
+    let mut merged: Vec<ChatMessage> = Vec::new();
+    for message in messages {
+        if message.content.trim().is_empty() && message.sources.is_empty() {
+            continue;
+        }
+        match merged.last_mut() {
+            Some(previous) if previous.role == message.role => {
+                previous.content.push_str("\n\n");
+                previous.content.push_str(&message.content);
+                previous.sources.extend(message.sources);
+            }
+            _ => merged.push(message),
+        }
+    }
+
+    let mut session = ChatSession::new();
+    let first = merged.first().map(|message| message.timestamp);
+    let last = merged.last().map(|message| message.timestamp);
+    session.created_at = created_at.or(first).unwrap_or(session.created_at);
+    session.updated_at = updated_at
+        .or(last)
+        .unwrap_or(session.created_at)
+        .max(session.created_at);
+    session.title = title.filter(|title| !title.trim().is_empty());
+    session.messages = merged;
+    session
+}
+
+fn text_message(role: &str, content: String, timestamp: i64) -> ChatMessage {
+    ChatMessage {
+        id: None,
+        parent_message_id: None,
+        role: role.to_string(),
+        content,
+        sources: Vec::new(),
+        timestamp,
+        thinking_steps: None,
+        metadata: None,
+        finish_reason: None,
+    }
+}
+
+fn fenced(text: &str, language: &str) -> String {
+    format!("```{}\n{}\n```", language, text.trim_end())
+}
+
+// ChatGPT: conversations are trees of nodes; the visible thread runs from `current_node` up to
+// the root.
+
+#[derive(Deserialize)]
+struct ChatGptConversation {
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    conversation_id: Option<String>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    update_time: Option<f64>,
+    #[serde(default)]
+    current_node: Option<String>,
+    mapping: std::collections::HashMap<String, ChatGptNode>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptNode {
+    #[serde(default)]
+    message: Option<ChatGptMessage>,
+    #[serde(default)]
+    parent: Option<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptMessage {
+    author: ChatGptAuthor,
+    #[serde(default)]
+    create_time: Option<f64>,
+    #[serde(default)]
+    content: Value,
+    #[serde(default)]
+    recipient: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatGptAuthor {
+    role: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+fn chatgpt_cycle() -> SquidError {
+    SquidError::Other("conversation tree contains a cycle".to_string())
+}
+
+/// Node a conversation ends at: its current node, or the end of the newest branch when that's
+/// missing
+fn chatgpt_leaf(conversation: &ChatGptConversation) -> Result<Option<String>> {
+    if let Some(id) = &conversation.current_node
+        && conversation.mapping.contains_key(id)
+    {
+        return Ok(Some(id.clone()));
+    }
+
+    let Some(mut id) = conversation
+        .mapping
+        .iter()
+        .find(|(_, node)| node.parent.is_none())
+        .map(|(id, _)| id.clone())
+    else {
+        return Ok(None);
+    };
+    let mut visited = HashSet::from([id.clone()]);
+    while let Some(next) = conversation
+        .mapping
+        .get(&id)
+        .and_then(|node| node.children.last())
+    {
+        if !visited.insert(next.clone()) {
+            return Err(chatgpt_cycle());
+        }
+        id = next.clone();
+    }
+    Ok(Some(id))
+}
+
+fn parse_chatgpt(entry: Value, warnings: &mut Vec<String>) -> Result<(String, ChatSession)> {
+    let conversation: ChatGptConversation = serde_json::from_value(entry)?;
+    let conversation_id = conversation
+        .conversation_id
+        .clone()
+        .or_else(|| conversation.id.clone())
+        .ok_or_else(|| SquidError::Other("missing conversation id".to_string()))?;
+    let created_at = conversation.create_time.map(|t| t as i64);
+
+    // Walk up from the leaf, guarding against cycles in a corrupted mapping
+    let mut path = Vec::new();
+    let mut visited = HashSet::new();
+    let mut next = chatgpt_leaf(&conversation)?;
+    while let Some(id) = next {
+        if !visited.insert(id.clone()) {
+            return Err(chatgpt_cycle());
+        }
+        let Some(node) = conversation.mapping.get(&id) else {
+            break;
+        };
+        if let Some(message) = &node.message {
+            path.push(message);
+        }
+        next = node.parent.clone();
+    }
+    path.reverse();
+
+    let mut messages = Vec::new();
+    for message in path {
+        let timestamp = message
+            .create_time
+            .map(|t| t as i64)
+            .or(created_at)
+            .unwrap_or_default();
+        let text = chatgpt_content_text(&message.content);
+        let recipient = message.recipient.as_deref().unwrap_or("all");
+        match message.author.role.as_str() {
+            "user" => messages.push(text_message("user", text, timestamp)),
+            "assistant" if recipient == "all" => {
+                messages.push(text_message("assistant", text, timestamp))
+            }
+            "assistant" => messages.push(text_message(
+                "assistant",
+                format!("[Tool call: {}]\n{}", recipient, text),
+                timestamp,
+            )),
+            "tool" => {
+                let name = message.author.name.as_deref().unwrap_or("tool");
+                messages.push(text_message(
+                    "assistant",
+                    format!("[Tool result from {}]\n{}", name, text),
+                    timestamp,
+                ));
+            }
+            "system" => {}
+            other => warnings.push(format!(
+                "Conversation {}: skipping message with unknown role '{}'",
+                conversation_id, other
+            )),
+        }
+    }
+
+    let session = build_session(
+        conversation.title,
+        created_at,
+        conversation.update_time.map(|t| t as i64),
+        messages,
+    );
+    Ok((conversation_id, session))
+}
+
+/// Text of a ChatGPT message `content`, with non-text parts replaced by notes
+fn chatgpt_content_text(content: &Value) -> String {
+    let content_type = content
+        .get("content_type")
+        .and_then(Value::as_str)
+        .unwrap_or("text");
+    match content_type {
+        "text" | "multimodal_text" => content
+            .get("parts")
+            .and_then(Value::as_array)
+            .map(|parts| {
+                parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        Value::String(text) if !text.trim().is_empty() => Some(text.clone()),
+                        Value::String(_) => None,
+                        Value::Object(_) => {
+                            let kind = part
+                                .get("content_type")
+                                .and_then(Value::as_str)
+                                .unwrap_or("attachment");
+                            Some(if kind.contains("image") {
+                                "[Image omitted]".to_string()
+                            } else {
+                                format!("[{} omitted]", kind)
+                            })
+                        }
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            })
+            .unwrap_or_default(),
+        "code" => {
+            let language = content
+                .get("language")
+                .and_then(Value::as_str)
+                .filter(|language| *language != "unknown")
+                .unwrap_or("");
+            fenced(
+                content.get("text").and_then(Value::as_str).unwrap_or(""),
+                language,
+            )
+        }
+        "execution_output" => fenced(
+            content.get("text").and_then(Value::as_str).unwrap_or(""),
+            "",
+        ),
+        // Custom instructions aren't part of the conversation
+        "user_editable_context" => String::new(),
+        other => content
+            .get("text")
+            .or_else(|| content.get("result"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("[{} content omitted]", other)),
+    }
+}
+
+// Claude: a flat list of messages whose `content` blocks carry text, tool use and images.
+
+#[derive(Deserialize)]
+struct ClaudeConversation {
+    uuid: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    updated_at: Option<String>,
+    chat_messages: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct ClaudeMessage {
+    sender: String,
+    #[serde(default)]
+    text: String,
+    #[serde(default)]
+    created_at: Option<String>,
+    #[serde(default)]
+    content: Vec<Value>,
+    #[serde(default)]
+    attachments: Vec<ClaudeAttachment>,
+    #[serde(default)]
+    files: Vec<ClaudeFile>,
+}
+
+#[derive(Deserialize)]
+struct ClaudeAttachment {
+    #[serde(default)]
+    file_name: String,
+    #[serde(default)]
+    extracted_content: String,
+}
+
+#[derive(Deserialize)]
+struct ClaudeFile {
+    #[serde(default)]
+    file_name: String,
+}
+
+fn parse_rfc3339(timestamp: Option<&str>) -> Option<i64> {
+    chrono::DateTime::parse_from_rfc3339(timestamp?)
+        .ok()
+        .map(|t| t.timestamp())
+}
+
+fn parse_claude(entry: Value, warnings: &mut Vec<String>) -> Result<(String, ChatSession)> {
+    let conversation: ClaudeConversation = serde_json::from_value(entry)?;
+    let created_at = parse_rfc3339(conversation.created_at.as_deref());
+
+    let mut messages = Vec::new();
+    for (index, raw) in conversation.chat_messages.into_iter().enumerate() {
+        let message: ClaudeMessage = match serde_json::from_value(raw) {
+            Ok(message) => message,
+            Err(e) => {
+                warnings.push(format!(
+                    "Conversation {}: skipping message #{}: {}",
+                    conversation.uuid,
+                    index + 1,
+                    e
+                ));
+                continue;
+            }
+        };
+        let role = match message.sender.as_str() {
+            "human" => "user",
+            "assistant" => "assistant",
+            other => {
+                warnings.push(format!(
+                    "Conversation {}: skipping message with unknown sender '{}'",
+                    conversation.uuid, other
+                ));
+                continue;
+            }
+        };
+        let timestamp = parse_rfc3339(message.created_at.as_deref())
+            .or(created_at)
+            .unwrap_or_default();
+
+        let mut parts: Vec<String> = message
+            .files
+            .iter()
+            .map(|file| format!("[Attached file omitted: {}]", file.file_name))
+            .collect();
+        let content = claude_content_text(&message.content);
+        parts.push(if content.trim().is_empty() {
+            message.text
+        } else {
+            content
+        });
+
+        let mut chat_message = text_message(role, parts.join("\n\n"), timestamp);
+        chat_message.sources = message
+            .attachments
+            .into_iter()
+            .filter(|attachment| !attachment.extracted_content.is_empty())
+            .map(|attachment| {
+                Source::new(
+                    attachment.file_name,
+                    attachment.extracted_content,
+                    SourceOrigin::Attachment,
+                )
+            })
+            .collect();
+        messages.push(chat_message);
+    }
+
+    let session = build_session(
+        conversation.name,
+        created_at,
+        parse_rfc3339(conversation.updated_at.as_deref()),
+        messages,
+    );
+    Ok((conversation.uuid, session))
+}
+
+/// Text of Claude `content` blocks, with tool use and media replaced by notes
+fn claude_content_text(blocks: &[Value]) -> String {
+    blocks
+        .iter()
+        .filter_map(|block| {
+            let text = |block: &Value| {
+                block
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .map(str::to_string)
+            };
+            let name = block.get("name").and_then(Value::as_str).unwrap_or("tool");
+            match block.get("type").and_then(Value::as_str).unwrap_or("") {
+                "text" => text(block),
+                // Extended thinking isn't part of the visible answer
+                "thinking" => None,
+                "tool_use" => {
+                    let input = block.get("input").cloned().unwrap_or(Value::Null);
+                    let input = serde_json::to_string_pretty(&input).unwrap_or_default();
+                    Some(format!("[Tool call: {}]\n{}", name, fenced(&input, "json")))
+                }
+                "tool_result" => {
+                    let output = match block.get("content") {
+                        Some(Value::Array(items)) => {
+                            items.iter().filter_map(text).collect::<Vec<_>>().join("\n")
+                        }
+                        Some(Value::String(output)) => output.clone(),
+                        _ => String::new(),
+                    };
+                    Some(format!(
+                        "[Tool result from {}]\n{}",
+                        name,
+                        fenced(&output, "")
+                    ))
+                }
+                "image" => Some("[Image omitted]".to_string()),
+                other => Some(format!("[{} content omitted]", other)),
+            }
+        })
+        .filter(|part| !part.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+// squid: sessions in the same shape they're stored in, re-keyed so they never clash with
+// existing sessions.
+
+fn parse_squid(entry: Value) -> Result<(String, ChatSession)> {
+    let mut session: ChatSession = serde_json::from_value(entry)?;
+    let conversation_id = std::mem::replace(&mut session.id, Uuid::new_v4().to_string());
+    session.is_readonly = false;
+    for message in &mut session.messages {
+        message.id = None;
+        message.parent_message_id = None;
+        if let Some(steps) = &mut message.thinking_steps {
+            for step in steps {
+                step.id = None;
+            }
+        }
+    }
+    let messages = std::mem::take(&mut session.messages);
+    let mut imported = build_session(
+        session.title.take(),
+        Some(session.created_at),
+        Some(session.updated_at),
+        messages,
+    );
+    imported.id = session.id;
+    imported.agent_id = session.agent_id;
+    imported.token_usage = session.token_usage;
+    imported.cost_usd = session.cost_usd;
+    imported.working_dir = session.working_dir;
+    imported.workspace = session.workspace;
+    Ok((conversation_id, imported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHATGPT_EXPORT: &str = include_str!("../tests/fixtures/import/chatgpt.json");
+    const CLAUDE_EXPORT: &str = include_str!("../tests/fixtures/import/claude.json");
+
+    fn source_id(session: &ChatSession) -> &str {
+        &session
+            .metadata
+            .as_ref()
+            .unwrap()
+            .imported_from
+            .as_ref()
+            .unwrap()
+            .conversation_id
+    }
+
+    #[test]
+    fn test_parse_chatgpt_export() {
+        let parsed = parse_export(ImportFormat::Chatgpt, CHATGPT_EXPORT).unwrap();
+
+        // Missing mapping, a non-object entry and an empty conversation are skipped
+        assert_eq!(parsed.sessions.len(), 2);
+        assert_eq!(parsed.warnings.len(), 3, "{:?}", parsed.warnings);
+        assert!(parsed.warnings[0].starts_with("Skipping conversation #2"));
+        assert!(parsed.warnings[2].contains("no messages"));
+
+        let session = &parsed.sessions[0];
+        assert_eq!(source_id(session), "chatgpt-conv-1");
+        assert_eq!(session.title.as_deref(), Some("Rust lifetimes"));
+        assert_eq!(session.created_at, 1700000000);
+        assert_eq!(session.updated_at, 1700000300);
+
+        // The system message and the abandoned branch are dropped; the tool call, its output
+        // and the answer merge into one assistant message
+        let roles: Vec<_> = session.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user"]);
+        assert_eq!(
+            session.messages[0].content,
+            "[Image omitted]\n\nWhat does this borrow checker error mean?"
+        );
+        assert_eq!(session.messages[0].timestamp, 1700000010);
+        let answer = &session.messages[1].content;
+        assert!(answer.starts_with("[Tool call: python]\n```python\nprint(1 + 1)\n```"));
+        assert!(answer.contains("[Tool result from python]\n```\n2\n```"));
+        assert!(answer.ends_with("The reference outlives the value it points to."));
+        assert!(!answer.contains("abandoned"));
+
+        // Without `current_node` the thread is followed from the root
+        let second = &parsed.sessions[1];
+        assert_eq!(source_id(second), "chatgpt-conv-2");
+        assert_eq!(second.title, None);
+        assert_eq!(second.messages.len(), 2);
+        assert_eq!(second.messages[1].content, "Hi there");
+    }
+
+    #[test]
+    fn test_parse_claude_export() {
+        let parsed = parse_export(ImportFormat::Claude, CLAUDE_EXPORT).unwrap();
+
+        // Unknown sender (a message), missing chat_messages and a number are skipped
+        assert_eq!(parsed.sessions.len(), 2);
+        assert_eq!(parsed.warnings.len(), 3, "{:?}", parsed.warnings);
+        assert!(parsed.warnings[0].contains("unknown sender 'narrator'"));
+
+        let session = &parsed.sessions[0];
+        assert_eq!(source_id(session), "claude-conv-1");
+        assert_eq!(session.title.as_deref(), Some("Parsing YAML"));
+        assert_eq!(session.created_at, 1714557600);
+        assert_eq!(session.updated_at, 1714557900);
+        assert_eq!(session.messages.len(), 2);
+
+        let question = &session.messages[0];
+        assert_eq!(question.role, "user");
+        assert_eq!(
+            question.content,
+            "[Attached file omitted: screenshot.png]\n\nWhy does this config fail to load?"
+        );
+        assert_eq!(question.sources.len(), 1);
+        assert_eq!(question.sources[0].title, "config.yaml");
+        assert_eq!(question.sources[0].content, "key: [unclosed");
+
+        let answer = &session.messages[1].content;
+        assert!(!answer.contains("Look at the brackets"));
+        assert!(answer.contains("[Tool call: web_search]"));
+        assert!(answer.contains("\"query\": \"yaml flow sequence\""));
+        assert!(answer.contains("Flow sequences need a closing bracket."));
+        assert!(answer.ends_with("The flow sequence is never closed."));
+
+        // Empty names become untitled; unparseable dates fall back to the messages
+        let second = &parsed.sessions[1];
+        assert_eq!(second.title, None);
+        assert_eq!(second.updated_at, 1714636805);
+        assert_eq!(second.messages[0].content, "Plain text only");
+    }
+
+    #[test]
+    fn test_cyclic_chatgpt_mapping_is_skipped() {
+        let message = |text: &str| serde_json::json!({"author": {"role": "user"}, "content": {"parts": [text]}});
+        // The current node is reachable from its own ancestors, through a node without a message
+        let upward = serde_json::json!({
+            "conversation_id": "up",
+            "current_node": "a",
+            "mapping": {
+                "a": {"message": message("hi"), "parent": "b", "children": []},
+                "b": {"parent": "a", "children": ["a"]},
+            },
+        });
+        // No current node, and the newest branch loops back to the root
+        let downward = serde_json::json!({
+            "conversation_id": "down",
+            "mapping": {
+                "root": {"children": ["a"]},
+                "a": {"parent": "root", "children": ["b"]},
+                "b": {"parent": "a", "children": ["a"]},
+            },
+        });
+        let json = serde_json::json!([upward, downward]).to_string();
+
+        let parsed = parse_export(ImportFormat::Chatgpt, &json).unwrap();
+        assert!(parsed.sessions.is_empty());
+        assert_eq!(parsed.warnings.len(), 2);
+        assert!(
+            parsed
+                .warnings
+                .iter()
+                .all(|warning| warning.contains("contains a cycle"))
+        );
+    }
+
+    #[test]
+    fn test_parse_export_rejects_non_list() {
+        assert!(parse_export(ImportFormat::Chatgpt, "{not json").is_err());
+        assert!(parse_export(ImportFormat::Claude, r#"{"uuid": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_squid_round_trip_gets_new_id() {
+        let mut original = ChatSession::new();
+        original.title = Some("Exported".to_string());
+        original.add_message("user".to_string(), "Hi".to_string(), vec![]);
+        original.add_message("assistant".to_string(), "Hello".to_string(), vec![]);
+        let json = serde_json::to_string(&original).unwrap();
+
+        let parsed = parse_export(ImportFormat::Squid, &json).unwrap();
+        assert!(parsed.warnings.is_empty());
+        let session = &parsed.sessions[0];
+        assert_ne!(session.id, original.id);
+        assert_eq!(source_id(session), original.id);
+        assert_eq!(session.title.as_deref(), Some("Exported"));
+        assert_eq!(session.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_write_sessions_is_idempotent() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let db = Database::new(temp_dir.path().join("test.db")).unwrap();
+        let progress = ProgressBar::hidden();
+
+        let parsed = parse_export(ImportFormat::Claude, CLAUDE_EXPORT).unwrap();
+        let summary =
+            write_sessions(&db, ImportFormat::Claude, &parsed.sessions, &progress).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 2,
+                duplicates: 0
+            }
+        );
+
+        let stored = db.load_session(&parsed.sessions[0].id).unwrap().unwrap();
+        assert_eq!(stored.metadata, parsed.sessions[0].metadata);
+        assert_eq!(stored.updated_at, 1714557900);
+        assert_eq!(stored.messages.len(), 2);
+        assert_eq!(stored.messages[0].sources[0].title, "config.yaml");
+
+        // Parsing again creates fresh session ids, but the source ids match
+        let reparsed = parse_export(ImportFormat::Claude, CLAUDE_EXPORT).unwrap();
+        let summary =
+            write_sessions(&db, ImportFormat::Claude, &reparsed.sessions, &progress).unwrap();
+        assert_eq!(
+            summary,
+            ImportSummary {
+                imported: 0,
+                duplicates: 2
+            }
+        );
+        assert_eq!(db.list_sessions().unwrap().len(), 2);
+
+        // The same id from a different format is a different conversation
+        assert!(db.imported_conversation_ids("chatgpt").unwrap().is_empty());
+    }
+}
@@ -1,14 +1,44 @@
 use log::{error, info, warn};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::output::CommandResult;
+use crate::secrets::{self, KeyStorage};
 
 const SQUIDIGNORE_TEMPLATE: &str = include_str!("../.squidignore.template");
 
-pub async fn run(
-    dir: &PathBuf,
-    url: &Option<String>,
-    api_key: &Option<String>,
-    log_level: &Option<String>,
-) {
+/// Model used by the bundled agent templates
+const DEFAULT_AGENT_MODEL: &str = "qwen3.5-4b";
+/// Context window used by the bundled agent templates and the global fallback
+const DEFAULT_CONTEXT_WINDOW: u32 = 32768;
+/// How long to wait for the provider's `/models` endpoint before falling back to text input
+const MODEL_PROBE_TIMEOUT: Duration = Duration::from_secs(3);
+const MANUAL_MODEL_ENTRY: &str = "✎ Enter a model id manually";
+const INIT_CANCELLED: &str = "Configuration initialization cancelled";
+
+/// Answers given on the command line; each one skips its interactive prompt
+#[derive(Debug, Default)]
+pub struct InitOptions {
+    pub url: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+    pub log_level: Option<String>,
+    pub format: Option<crate::config::ConfigFormat>,
+    /// Leave an existing .squidignore as it is
+    pub no_ignore_merge: bool,
+}
+
+/// Initialize a project in `dir`, or the global config when `global` is set (`dir` is then the
+/// global config directory)
+pub async fn run(dir: &PathBuf, global: bool, options: &InitOptions) -> CommandResult {
+    let InitOptions {
+        url,
+        api_key,
+        model,
+        log_level,
+        format,
+        no_ignore_merge,
+    } = options;
     info!("Initializing squid configuration in {:?}...", dir);
 
     // Create directory if it doesn't exist
@@ -16,16 +46,34 @@ pub async fn run(
         && let Err(e) = std::fs::create_dir_all(dir)
     {
         error!("Failed to create directory {:?}: {}", dir, e);
-        return;
+        return Err(format!("Failed to create directory {:?} - {}", dir, e).into());
     }
 
     // Try to load existing config, otherwise use defaults
-    let config_path = dir.join("squid.config.json");
-    let config_existed = config_path.exists();
-    let existing_config = if config_existed {
+    let existing_path = if global {
+        crate::config::Config::global_config_file_in(dir)
+    } else {
+        crate::config::Config::config_file_in(dir)
+    };
+    // An existing file keeps its format so settings never end up split across two files
+    let format = match (&existing_path, *format) {
+        (Some(path), Some(requested))
+            if crate::config::ConfigFormat::from_path(path) != requested =>
+        {
+            return Err(format!(
+                "{} already exists; remove it to switch the config to {}",
+                path.display(),
+                requested
+            )
+            .into());
+        }
+        (Some(path), _) => crate::config::ConfigFormat::from_path(path),
+        (None, requested) => requested.unwrap_or_default(),
+    };
+    let existing_config = if let Some(config_path) = &existing_path {
         println!("Found existing configuration, using current values as defaults...\n");
-        match std::fs::read_to_string(&config_path) {
-            Ok(content) => match serde_json::from_str::<crate::config::Config>(&content) {
+        match std::fs::read_to_string(config_path) {
+            Ok(content) => match format.parse::<crate::config::Config>(&content) {
                 Ok(cfg) => Some(cfg),
                 Err(e) => {
                     info!("Failed to parse existing config: {}", e);
@@ -57,14 +105,11 @@ pub async fn run(
             Ok(u) => u,
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         }
     };
 
-    // Use default context window (32768) for agents
-    let _final_context_window = 32768u32;
-
     let final_api_key = if api_key.is_some() {
         api_key.clone()
     } else {
@@ -72,13 +117,54 @@ pub async fn run(
             .with_help_message("API key if required (leave empty for local models)")
             .prompt_skippable()
         {
-            Ok(key) => key.filter(|k| !k.is_empty()),
+            // Keep a key from a legacy config so it can be moved out of the file
+            Ok(key) => key
+                .filter(|k| !k.is_empty())
+                .or_else(|| default_config.api_key.clone()),
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
+            }
+        }
+    };
+
+    // Keys are never saved in squid.config.json; ask where to keep it instead
+    let key_storage =
+        if final_api_key.is_some() && api_key.is_none() && secrets::keychain_available() {
+            let options = vec![".env file", "OS keychain"];
+            match inquire::Select::new("Where should the API key be stored?", options)
+                .with_help_message("The key is never written to squid.config.json")
+                .prompt()
+            {
+                Ok("OS keychain") => KeyStorage::Keychain,
+                Ok(_) => KeyStorage::DotEnv,
+                Err(_) => {
+                    error!("Configuration initialization cancelled or failed");
+                    return Err(INIT_CANCELLED.into());
+                }
+            }
+        } else {
+            KeyStorage::DotEnv
+        };
+
+    // Default agents are only generated on first project init, so the model only matters then
+    let agents_dir = dir.join("agents");
+    let create_agents = !global && !agents_dir.exists();
+
+    let (final_model, detected_context_window) = if let Some(m) = model {
+        (m.clone(), None)
+    } else if create_agents {
+        match prompt_model(&final_url, final_api_key.as_deref()).await {
+            Some(choice) => choice,
+            None => {
+                error!("Configuration initialization cancelled or failed");
+                return Err(INIT_CANCELLED.into());
             }
         }
+    } else {
+        (DEFAULT_AGENT_MODEL.to_string(), None)
     };
+    let final_context_window = detected_context_window.unwrap_or(DEFAULT_CONTEXT_WINDOW);
 
     let final_log_level = if let Some(level) = log_level {
         level.clone()
@@ -98,7 +184,7 @@ pub async fn run(
             Ok(level) => level.to_string(),
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         }
     };
@@ -112,7 +198,7 @@ pub async fn run(
         Ok(enabled) => enabled,
         Err(_) => {
             error!("Configuration initialization cancelled or failed");
-            return;
+            return Err(INIT_CANCELLED.into());
         }
     };
 
@@ -139,7 +225,7 @@ pub async fn run(
             Ok(url) => url,
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         };
 
@@ -153,7 +239,7 @@ pub async fn run(
             Ok(model) => model,
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         };
 
@@ -165,18 +251,22 @@ pub async fn run(
             Ok(path) => path,
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         };
 
         crate::config::RagConfig {
             enabled: true,
             embedding_url,
+            embedding_provider: default_config.rag.embedding_provider,
+            embedding_api_key: default_config.rag.embedding_api_key.clone(),
             embedding_model,
             documents_path,
             chunk_size: default_config.rag.chunk_size,
             chunk_overlap: default_config.rag.chunk_overlap,
             top_k: default_config.rag.top_k,
+            merge_adjacent: default_config.rag.merge_adjacent,
+            rerank: default_config.rag.rerank,
         }
     } else {
         crate::config::RagConfig {
@@ -186,7 +276,7 @@ pub async fn run(
     };
 
     // Ask about setting up demo documents
-    let setup_demo_docs = if enable_rag {
+    let setup_demo_docs = if enable_rag && !global {
         match inquire::Confirm::new("Setup demo documents for RAG?")
             .with_default(true)
             .with_help_message(
@@ -197,7 +287,7 @@ pub async fn run(
             Ok(setup) => setup,
             Err(_) => {
                 error!("Configuration initialization cancelled or failed");
-                return;
+                return Err(INIT_CANCELLED.into());
             }
         }
     } else {
@@ -205,24 +295,24 @@ pub async fn run(
     };
 
     // Create agents directory and default agent files
-    let agents_dir = dir.join("agents");
-    if !agents_dir.exists() {
+    if create_agents {
         if let Err(e) = std::fs::create_dir_all(&agents_dir) {
             warn!("Failed to create agents directory: {}", e);
         } else {
             info!("Created agents directory at {:?}", agents_dir);
             // Create default agent files
-            create_default_agent_files(&agents_dir);
+            create_default_agent_files(&agents_dir, &final_model, final_context_window);
         }
-    } else {
+    } else if !global {
         info!("Agents directory already exists, skipping creation");
         println!("\n✓ Using existing agents directory");
     }
 
     let config = crate::config::Config {
         api_url: final_url,
-        api_key: final_api_key,
-        context_window: 32768, // Global default fallback
+        api_key: None,
+        provider_kind: crate::config::ProviderKind::default(),
+        context_window: final_context_window, // Global default fallback
         log_level: final_log_level,
         db_log_level: crate::config::Config::default().db_log_level,
         version: None, // Will be set automatically by save_to_dir()
@@ -234,22 +324,52 @@ pub async fn run(
         web: crate::config::WebConfig::default(),
         audio: crate::config::AudioConfig::default(),
         jobs: crate::config::JobsConfig::default(),
+        cache: crate::config::CacheConfig::default(),
+        logging: crate::config::LoggingConfig::default(),
+        sessions: crate::config::SessionsConfig::default(),
+        security: crate::config::SecurityConfig::default(),
+        privacy: crate::config::PrivacyConfig::default(),
+        tools: crate::config::ToolsConfig::default(),
+        debug: crate::config::DebugConfig::default(),
+        notifications: default_config.notifications.clone(),
+        generation: default_config.generation.clone(),
+        project_instructions: default_config.project_instructions.clone(),
+        env_context: default_config.env_context.clone(),
+        context: default_config.context.clone(),
+        prompt_vars: default_config.prompt_vars.clone(),
+        network: default_config.network.clone(),
+        models: default_config.models.clone(),
+        workspaces: default_config.workspaces.clone(),
+        schedules: default_config.schedules.clone(),
+        templates: default_config.templates.clone(),
         default_agent: "general-assistant".to_string(),
+        agent_permissions: default_config.agent_permissions.clone(),
+        extra: default_config.extra.clone(),
         agents: crate::agent::AgentsConfig::default(),
         config_dir: Some(dir.clone()),
+        config_format: format,
+        config_file: None,
+        mock_llm: None,
     };
 
-    match config.save_to_dir(dir) {
-        Ok(_) => {
-            let config_path = dir.join("squid.config.json");
+    let saved = if global {
+        config.save_global_to_dir(dir)
+    } else {
+        config.save_to_dir(dir)
+    };
+    match saved {
+        Ok(config_path) => {
             info!("✓ Configuration saved to {:?}", config_path);
             println!("\n✅ Configuration saved to: {:?}", config_path);
             println!("\nSettings:");
             println!("  API URL: {}", config.api_url);
-            if config.api_key.is_some() {
-                println!("  API Key: [configured]");
-            } else {
-                println!("  API Key: [not set]");
+            match (&final_api_key, key_storage) {
+                (Some(_), KeyStorage::DotEnv) => println!("  API Key: [stored in .env]"),
+                (Some(_), KeyStorage::Keychain) => println!("  API Key: [stored in OS keychain]"),
+                (None, _) => println!("  API Key: [not set]"),
+            }
+            if create_agents {
+                println!("  Model: {}", final_model);
             }
             println!("  Context Window: {} tokens", config.context_window);
             println!("  Log Level: {}", config.log_level);
@@ -258,6 +378,17 @@ pub async fn run(
                 if config.rag.enabled { "yes" } else { "no" }
             );
 
+            if global {
+                if let Some(key) = &final_api_key
+                    && let Err(e) = store_api_key(dir, key, key_storage)
+                {
+                    println!("\n⚠ {}", e);
+                }
+                println!("\nThis config is used in any directory without a squid.config.json.");
+                println!("Run 'squid init' in a project to give it its own settings.");
+                return Ok(());
+            }
+
             println!("\nDefault agents available (in agents/ folder):");
             println!("  • general-assistant (default) - Full-featured coding assistant");
             println!("  • code-reviewer - Read-only code review specialist");
@@ -290,9 +421,17 @@ pub async fn run(
                         println!("\n⚠ Could not create .squidignore: {}", e);
                     }
                 }
-            } else {
+            } else if *no_ignore_merge {
                 info!(".squidignore already exists, skipping creation");
                 println!("\n✓ Using existing .squidignore file");
+            } else {
+                update_squidignore(&squidignore_path);
+            }
+
+            if let Some(key) = &final_api_key
+                && let Err(e) = store_api_key(dir, key, key_storage)
+            {
+                println!("\n⚠ {}", e);
             }
 
             // Setup demo documents if requested
@@ -352,12 +491,201 @@ pub async fn run(
         }
         Err(e) => {
             error!("Failed to save configuration: {}", e);
+            return Err(format!("Failed to save configuration - {}", e).into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Append the template patterns an existing `.squidignore` lacks, and say which
+fn update_squidignore(path: &Path) {
+    let existing = match std::fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(e) => {
+            warn!("Failed to read .squidignore: {}", e);
+            println!("\n⚠ Could not read .squidignore: {}", e);
+            return;
+        }
+    };
+    let Some((merged, added)) = merge_squidignore(&existing, SQUIDIGNORE_TEMPLATE) else {
+        info!(".squidignore already has every default pattern");
+        println!("\n✓ Using existing .squidignore file");
+        return;
+    };
+    match std::fs::write(path, merged) {
+        Ok(_) => {
+            info!("Added {} default pattern(s) to {:?}", added.len(), path);
+            println!(
+                "\n✓ Added {} new default pattern(s) to .squidignore: {}",
+                added.len(),
+                added.join(", ")
+            );
+            println!("  Run with --no-ignore-merge to leave the file as it is");
+        }
+        Err(e) => {
+            warn!("Failed to update .squidignore: {}", e);
+            println!("\n⚠ Could not update .squidignore: {}", e);
+        }
+    }
+}
+
+/// `existing` with the template patterns it lacks appended in a marked block, and those patterns
+///
+/// Only pattern lines count: comments and blank lines are ignored on both sides. A negation
+/// (`!*.pem`) counts as the user's decision about its pattern, so that pattern isn't added back
+/// after it. User lines are never removed or reordered. `None` when nothing is missing.
+fn merge_squidignore(existing: &str, template: &str) -> Option<(String, Vec<String>)> {
+    let patterns = |content: &str| -> Vec<String> {
+        content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()
+    };
+    let present = patterns(existing);
+    let mut added: Vec<String> = Vec::new();
+    for pattern in patterns(template) {
+        let decided = present
+            .iter()
+            .any(|line| *line == pattern || line.strip_prefix('!') == Some(pattern.as_str()));
+        if !decided && !added.contains(&pattern) {
+            added.push(pattern);
         }
     }
+    if added.is_empty() {
+        return None;
+    }
+
+    let mut merged = existing.to_string();
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+    if !merged.is_empty() {
+        merged.push('\n');
+    }
+    merged.push_str(&format!(
+        "# added by squid init v{}\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    for pattern in &added {
+        merged.push_str(pattern);
+        merged.push('\n');
+    }
+    Some((merged, added))
+}
+
+/// Save the API key to `.env` or the OS keychain and report the result
+pub fn store_api_key(dir: &Path, key: &str, storage: KeyStorage) -> Result<(), String> {
+    match storage {
+        KeyStorage::Keychain => match secrets::store_in_keychain(key) {
+            Ok(()) => {
+                info!("Stored API key in OS keychain");
+                println!("\n✓ Stored API key in the OS keychain");
+            }
+            Err(e) => {
+                warn!("{}", e);
+                return Err(format!(
+                    "{}\n  Use `squid config set-key` to store it in .env instead",
+                    e
+                ));
+            }
+        },
+        KeyStorage::DotEnv => match secrets::write_env_key(dir, key) {
+            Ok(env_path) => {
+                info!("Stored API key in {:?}", env_path);
+                println!("\n✓ Stored API key in {:?}", env_path);
+                match secrets::ensure_env_ignored(dir) {
+                    Ok(changed) => {
+                        for path in changed {
+                            println!("  Added .env to {:?}", path);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to update ignore files: {}", e);
+                        println!("  ⚠ Could not add .env to ignore files: {}", e);
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("Failed to write .env: {}", e);
+                return Err(format!("Could not write API key to .env: {}", e));
+            }
+        },
+    }
+    Ok(())
+}
+
+/// Probe the provider for available models and let the user pick one
+///
+/// Falls back to a free-text prompt when the endpoint is unreachable or returns no models.
+/// Returns `None` if the prompt was cancelled.
+async fn prompt_model(api_url: &str, api_key: Option<&str>) -> Option<(String, Option<u32>)> {
+    match crate::models::fetch_models(api_url, api_key, Some(MODEL_PROBE_TIMEOUT)).await {
+        Ok(models) if !models.is_empty() => {
+            let mut options: Vec<String> = models.iter().map(|m| m.id.clone()).collect();
+            let cursor = options
+                .iter()
+                .position(|m| m == DEFAULT_AGENT_MODEL)
+                .unwrap_or(0);
+            options.push(MANUAL_MODEL_ENTRY.to_string());
+
+            let choice = inquire::Select::new("Model:", options)
+                .with_help_message("Models reported by the API (used by the default agents)")
+                .with_starting_cursor(cursor)
+                .prompt()
+                .ok()?;
+
+            if choice != MANUAL_MODEL_ENTRY {
+                let context_window = detected_context_window(&models, &choice);
+                return Some((choice, context_window));
+            }
+        }
+        Ok(_) => {
+            info!("API returned no models, falling back to manual model entry");
+            println!("⚠ The API did not list any models, please enter the model id manually");
+        }
+        Err(e) => {
+            warn!("Failed to fetch models from {}: {}", api_url, e);
+            println!(
+                "⚠ Could not reach {} ({}), please enter the model id manually",
+                crate::models::models_url(api_url),
+                e
+            );
+        }
+    }
+
+    let model = inquire::Text::new("Model:")
+        .with_default(DEFAULT_AGENT_MODEL)
+        .with_help_message("Model id used by the default agents (e.g., qwen3.5-4b)")
+        .prompt()
+        .ok()?;
+    let context_window = detected_context_window(&[], &model);
+    Some((model, context_window))
+}
+
+/// Context window to pre-fill for `model`: the model metadata's, else the one the provider listed
+fn detected_context_window(models: &[crate::models::ModelInfo], model: &str) -> Option<u32> {
+    crate::models::context_length(model)
+        .or_else(|| crate::models::context_length_for(models, model))
+}
+
+/// Substitute the chosen model and context window into a bundled agent template
+fn apply_agent_model(template: &str, model: &str, context_window: u32) -> String {
+    template
+        .replace(
+            &format!("model: {}", DEFAULT_AGENT_MODEL),
+            &format!("model: {}", model),
+        )
+        .replace(
+            &format!("context_window: {}", DEFAULT_CONTEXT_WINDOW),
+            &format!("context_window: {}", context_window),
+        )
 }
 
 /// Create default agent files in the agents directory
-fn create_default_agent_files(agents_dir: &std::path::Path) {
+fn create_default_agent_files(agents_dir: &std::path::Path, model: &str, context_window: u32) {
     let agents = vec![
         (
             "general-assistant.md",
@@ -372,7 +700,10 @@ permissions:
   - now
   - read_file
   - write_file
+  - apply_patch
   - grep
+  - find_files
+  - search_docs
   - bash:ls
   - bash:pwd
   - bash:git
@@ -400,6 +731,8 @@ permissions:
   - now
   - read_file
   - grep
+  - find_files
+  - search_docs
 suggestions:
   - Review this file for security vulnerabilities
   - What are the biggest code quality issues here?
@@ -481,7 +814,10 @@ Thou art William Shakespeare, the immortal Bard of Avon ✍️. Speak always in
             continue;
         }
 
-        match std::fs::write(&file_path, content) {
+        match std::fs::write(
+            &file_path,
+            apply_agent_model(content, model, context_window),
+        ) {
             Ok(_) => {
                 info!("Created default agent file: {}", filename);
                 success_count += 1;
@@ -500,3 +836,78 @@ Thou art William Shakespeare, the immortal Bard of Avon ✍️. Speak always in
         println!("⚠ Failed to create {} agent file(s)", fail_count);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_agent_model() {
+        let template = "---\nname: Test\nmodel: qwen3.5-4b\ncontext_window: 32768\n---\n";
+        let result = apply_agent_model(template, "llama-3.1-8b", 131072);
+        assert!(result.contains("model: llama-3.1-8b"));
+        assert!(result.contains("context_window: 131072"));
+
+        // Agents with their own model/context window are left alone
+        let light = "model: gemma-4-e2b-it\ncontext_window: 8192\n";
+        assert_eq!(apply_agent_model(light, "llama-3.1-8b", 131072), light);
+    }
+
+    #[test]
+    fn test_detected_context_window_prefers_embedded_metadata() {
+        let listed = [crate::models::ModelInfo {
+            id: "qwen/qwen3-8b".to_string(),
+            context_length: Some(40960),
+        }];
+        // Bundled entry, matched through the provider prefix
+        assert_eq!(
+            detected_context_window(&listed, "qwen/qwen3-8b"),
+            Some(32768)
+        );
+        assert_eq!(detected_context_window(&[], "gpt-oss-20b"), Some(131072));
+
+        // Unknown models fall back to what the provider listed
+        let listed = [crate::models::ModelInfo {
+            id: "my-finetune".to_string(),
+            context_length: Some(16384),
+        }];
+        assert_eq!(detected_context_window(&listed, "my-finetune"), Some(16384));
+        assert_eq!(detected_context_window(&[], "my-finetune"), None);
+    }
+
+    const TEMPLATE: &str = "# Secrets\n.env\n*.pem\n\n# Build\ntarget/\n*.pem\n.aws/\n";
+
+    #[test]
+    fn test_squidignore_merge_appends_only_missing_defaults() {
+        let existing = "# mine\n  target/  \nfixtures/big.bin\n.env";
+        let (merged, added) = merge_squidignore(existing, TEMPLATE).unwrap();
+        // The template's duplicate is added once; user lines stay first and untouched
+        assert_eq!(added, vec!["*.pem", ".aws/"]);
+        assert!(merged.starts_with(existing));
+        assert!(merged.ends_with(&format!(
+            "\n\n# added by squid init v{}\n*.pem\n.aws/\n",
+            env!("CARGO_PKG_VERSION")
+        )));
+
+        // A second run finds nothing missing
+        assert_eq!(merge_squidignore(&merged, TEMPLATE), None);
+    }
+
+    #[test]
+    fn test_squidignore_merge_respects_negations() {
+        let existing = "!*.pem\n!.env\n";
+        let (merged, added) = merge_squidignore(existing, TEMPLATE).unwrap();
+        assert_eq!(added, vec!["target/", ".aws/"]);
+        assert!(!merged.lines().any(|line| line == "*.pem" || line == ".env"));
+    }
+
+    #[test]
+    fn test_squidignore_merge_ignores_commented_defaults() {
+        // A commented-out default isn't a pattern, so it's still missing
+        let (_, added) = merge_squidignore("# .env\n", TEMPLATE).unwrap();
+        assert_eq!(added, vec![".env", "*.pem", "target/", ".aws/"]);
+
+        let (merged, _) = merge_squidignore("", TEMPLATE).unwrap();
+        assert!(merged.starts_with("# added by squid init"));
+    }
+}
@@ -0,0 +1,244 @@
+//! Project instructions appended to system prompts
+//!
+//! A `SQUID.md` in the project root (the directory holding `squid.config.json`), or an
+//! `AGENTS.md` when there is none, tells the model about the project's conventions. It is read
+//! on every request so edits apply without restarting the server.
+
+use log::{debug, warn};
+use std::path::{Path, PathBuf};
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::template::TemplateRenderer;
+
+/// Files looked up in the project root, in order of preference
+pub const INSTRUCTION_FILES: &[&str] = &["SQUID.md", "AGENTS.md"];
+
+/// Instructions read from a project file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProjectInstructions {
+    pub path: PathBuf,
+    pub content: String,
+    /// The file was longer than `project_instructions.max_tokens`
+    pub truncated: bool,
+}
+
+impl ProjectInstructions {
+    /// Name of the instructions file, e.g. `SQUID.md`
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+
+    /// The instructions as a delimited section of a system prompt
+    fn prompt_section(&self) -> String {
+        let file_name = self.file_name();
+        format!(
+            "\n\n## Project Instructions\n\nThe project provides the following instructions in `{}`. Follow them unless they conflict with the rules above.\n\n<project_instructions>\n{}\n</project_instructions>\n",
+            file_name, self.content
+        )
+    }
+}
+
+/// Instructions for the configured project, unless disabled
+pub fn load(config: &Config) -> Option<ProjectInstructions> {
+    if !config.project_instructions.enabled {
+        return None;
+    }
+    load_from(
+        &config.project_root(),
+        config.project_instructions.max_tokens,
+    )
+}
+
+/// Read the first non-empty instructions file in `root`, truncated to `max_tokens`
+pub fn load_from(root: &Path, max_tokens: usize) -> Option<ProjectInstructions> {
+    for name in INSTRUCTION_FILES {
+        let path = root.join(name);
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                warn!("Failed to read project instructions {:?}: {}", path, e);
+                continue;
+            }
+        };
+        if content.trim().is_empty() {
+            continue;
+        }
+
+        let (content, truncated) = truncate_to_tokens(content.trim(), max_tokens);
+        if truncated {
+            warn!(
+                "Project instructions {:?} exceed {} tokens and were truncated",
+                path, max_tokens
+            );
+        }
+        debug!("Loaded project instructions from {:?}", path);
+        return Some(ProjectInstructions {
+            path,
+            content,
+            truncated,
+        });
+    }
+    None
+}
+
+/// Append the project instructions, if any, to a rendered system prompt
+///
+/// `SQUID.md` may use the same `{{variables}}` as the prompt, rendered with `renderer`.
+/// `AGENTS.md` is shared with other tools, which may use braces differently, so it is kept as is.
+pub fn append_to_prompt(
+    system_prompt: &mut String,
+    config: &Config,
+    renderer: &TemplateRenderer,
+) -> Result<()> {
+    if let Some(mut instructions) = load(config) {
+        let file_name = instructions.file_name();
+        if file_name == INSTRUCTION_FILES[0] {
+            instructions.content = renderer.render_prompt(&file_name, &instructions.content)?;
+        }
+        system_prompt.push_str(&instructions.prompt_section());
+    }
+    Ok(())
+}
+
+/// Cut `text` to at most `max_tokens` tokens, returning whether anything was removed
+fn truncate_to_tokens(text: &str, max_tokens: usize) -> (String, bool) {
+    // Every token covers at least one byte, so short text needs no tokenizer
+    if text.len() <= max_tokens {
+        return (text.to_string(), false);
+    }
+    let bpe = match tiktoken_rs::cl100k_base() {
+        Ok(bpe) => bpe,
+        Err(e) => {
+            warn!(
+                "Failed to load tokenizer, estimating instruction length: {}",
+                e
+            );
+            // Same fallback as token estimates elsewhere: about 4 bytes per token
+            let limit = max_tokens.saturating_mul(4);
+            if text.len() <= limit {
+                return (text.to_string(), false);
+            }
+            let end = text.floor_char_boundary(limit);
+            return (format!("{}\n\n[... truncated]", &text[..end]), true);
+        }
+    };
+
+    let tokens = bpe.encode_ordinary(text);
+    if tokens.len() <= max_tokens {
+        return (text.to_string(), false);
+    }
+    // A cut can split a multi-byte character; back off until the prefix decodes
+    let kept = (0..4)
+        .filter_map(|back| {
+            let end = max_tokens.checked_sub(back)?;
+            bpe.decode(&tokens[..end]).ok()
+        })
+        .next()
+        .unwrap_or_default();
+    (format!("{}\n\n[... truncated]", kept.trim_end()), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_found_at_root_from_subdirectory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        std::fs::write(root.join("squid.config.json"), "{}").unwrap();
+        std::fs::write(root.join("SQUID.md"), "Use tabs.\n").unwrap();
+        let subdir = root.join("src/nested");
+        std::fs::create_dir_all(&subdir).unwrap();
+
+        let config = Config::load_from(&subdir);
+        let instructions = load(&config).unwrap();
+        assert_eq!(instructions.path, root.join("SQUID.md"));
+        assert_eq!(instructions.content, "Use tabs.");
+        assert!(!instructions.truncated);
+
+        let renderer = TemplateRenderer::for_prompt(&config, "test-model");
+        let mut prompt = "System prompt".to_string();
+        append_to_prompt(&mut prompt, &config, &renderer).unwrap();
+        assert!(prompt.starts_with("System prompt\n\n## Project Instructions"));
+        assert!(prompt.contains("`SQUID.md`"));
+        assert!(prompt.contains("<project_instructions>\nUse tabs.\n</project_instructions>"));
+
+        // Placeholders resolve from prompt_vars; undefined ones name the file
+        std::fs::write(root.join("SQUID.md"), "Team: {{team}}. {{missing}}").unwrap();
+        let mut config = config;
+        config
+            .prompt_vars
+            .insert("team".to_string(), "Platform".to_string());
+        let renderer = TemplateRenderer::for_prompt(&config, "test-model");
+        let error = append_to_prompt(&mut String::new(), &config, &renderer).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("SQUID.md uses undefined variables: missing.")
+        );
+        std::fs::write(root.join("SQUID.md"), "Team: {{team}}.").unwrap();
+        let mut prompt = String::new();
+        append_to_prompt(&mut prompt, &config, &renderer).unwrap();
+        assert!(prompt.contains("Team: Platform."));
+
+        // AGENTS.md is shared with other tools and kept verbatim
+        std::fs::remove_file(root.join("SQUID.md")).unwrap();
+        std::fs::write(
+            root.join("AGENTS.md"),
+            "Agent prompts start with {{persona}}.",
+        )
+        .unwrap();
+        let mut prompt = String::new();
+        append_to_prompt(&mut prompt, &config, &renderer).unwrap();
+        assert!(prompt.contains("Agent prompts start with {{persona}}."));
+
+        // Disabled (e.g. --no-project-instructions)
+        config.project_instructions.enabled = false;
+        assert!(load(&config).is_none());
+    }
+
+    #[test]
+    fn test_absent_file_changes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("squid.config.json"), "{}").unwrap();
+        // An empty SQUID.md counts as absent
+        std::fs::write(dir.path().join("SQUID.md"), "  \n").unwrap();
+
+        let config = Config::load_from(dir.path());
+        let mut prompt = "System prompt".to_string();
+        let renderer = TemplateRenderer::for_prompt(&config, "test-model");
+        append_to_prompt(&mut prompt, &config, &renderer).unwrap();
+        assert_eq!(prompt, "System prompt");
+    }
+
+    #[test]
+    fn test_falls_back_to_agents_md() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("AGENTS.md"), "Run cargo fmt.").unwrap();
+        let instructions = load_from(dir.path(), 8000).unwrap();
+        assert_eq!(instructions.path, dir.path().join("AGENTS.md"));
+
+        std::fs::write(dir.path().join("SQUID.md"), "Prefer this.").unwrap();
+        let instructions = load_from(dir.path(), 8000).unwrap();
+        assert_eq!(instructions.content, "Prefer this.");
+    }
+
+    #[test]
+    fn test_long_instructions_are_truncated() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("SQUID.md"), "word ".repeat(500)).unwrap();
+
+        let instructions = load_from(dir.path(), 50).unwrap();
+        assert!(instructions.truncated);
+        assert!(instructions.content.ends_with("[... truncated]"));
+        let kept = instructions.content.trim_end_matches("\n\n[... truncated]");
+        assert!(kept.split_whitespace().count() <= 50);
+        assert!(kept.starts_with("word word"));
+    }
+}
@@ -144,11 +144,27 @@ async fn execute_job_from_request(
     };
 
     // Render template variables
-    let renderer = template::TemplateRenderer::new();
-    let system_message = renderer.render_string(&system_prompt).unwrap_or_else(|e| {
-        warn!("Failed to render system prompt: {}", e);
-        system_prompt.clone()
-    });
+    let renderer = template::TemplateRenderer::for_prompt(&app_config, &agent.model);
+    let system_message = match renderer.render_prompt("System prompt", &system_prompt) {
+        Ok(message) => message,
+        Err(e) => {
+            let error_msg = e.to_string();
+            error!("{}", error_msg);
+            db.update_job_result(job_id, "failed", None, Some(&error_msg))
+                .map_err(|e| e.to_string())?;
+
+            let _ = sse_tx.send(JobStatusEvent {
+                job_id,
+                job_name: job_name.clone(),
+                status: "failed".to_string(),
+                result: None,
+                error: Some(error_msg.clone()),
+                timestamp: Utc::now().timestamp(),
+            });
+
+            return Err(error_msg);
+        }
+    };
 
     // Create session (unique per execution)
     let started_at = Utc::now();
@@ -196,15 +212,24 @@ async fn execute_job_from_request(
     };
 
     // Build the LLM query params
+    let files: Vec<(&str, &str)> = job_payload
+        .file_path
+        .as_deref()
+        .zip(file_content.as_deref())
+        .into_iter()
+        .collect();
     let query_params = llm::LlmQueryParams {
         question: &job_payload.message,
-        file_content: file_content.as_deref(),
-        file_path: job_payload.file_path.as_deref(),
+        files: &files,
+        auto_included: &[],
+        context: None,
         system_prompt: Some(&system_message),
         model: &agent.model,
         app_config: &app_config,
         session: Some(&mut chat_session),
         db: Some(&*db),
+        use_cache: false,
+        tool_choice: Default::default(),
     };
 
     // Execute the LLM call with timeout (if specified)
@@ -227,7 +252,9 @@ async fn execute_job_from_request(
     };
 
     // Convert error to String immediately to avoid Send issues
-    let llm_result_str = llm_result.map_err(|e| e.to_string());
+    let llm_result_str = llm_result
+        .map(|result| result.answer)
+        .map_err(|e| e.to_string());
 
     match llm_result_str {
         Ok(response) => {
@@ -271,18 +298,19 @@ async fn execute_job_from_request(
             .unwrap_or_default();
 
             // Record this execution in job_executions table
-            if let Err(e) = db.create_job_execution(
+            if let Err(e) = db.create_job_execution(&db::JobExecution {
+                id: None,
                 job_id,
-                Some(&session_id),
-                "completed",
-                Some(&result_json),
-                None,
-                &started_at.to_rfc3339(),
-                Some(&completed_at.to_rfc3339()),
-                Some(duration_ms),
-                Some(total_tokens),
-                Some(chat_session.cost_usd),
-            ) {
+                session_id: Some(session_id.clone()),
+                status: "completed".to_string(),
+                result: Some(result_json.clone()),
+                error_message: None,
+                started_at: started_at.to_rfc3339(),
+                completed_at: Some(completed_at.to_rfc3339()),
+                duration_ms: Some(duration_ms),
+                tokens_used: Some(total_tokens),
+                cost_usd: Some(chat_session.cost_usd),
+            }) {
                 error!("Failed to record job execution: {}", e);
                 // Don't fail the job if execution recording fails
             }
@@ -307,15 +335,9 @@ async fn execute_job_from_request(
                 .messages
                 .iter()
                 .find(|msg| msg.role == "user")
-                .map(|msg| {
-                    let content = &msg.content;
-                    if content.len() > 100 {
-                        format!("{}...", &content[..100])
-                    } else {
-                        content.clone()
-                    }
-                });
+                .map(|msg| crate::text::truncate(&msg.content, 100));
 
+            let last_message = chat_session.messages.last().map(crate::db::LastMessage::of);
             let session_item = api::SessionListItem {
                 session_id: chat_session.id.clone(),
                 message_count: chat_session.messages.len(),
@@ -335,10 +357,22 @@ async fn execute_job_from_request(
                 },
                 cost_usd: chat_session.cost_usd,
                 is_readonly: chat_session.is_readonly,
+                working_dir: chat_session.working_dir.clone(),
+                workspace: chat_session.workspace.clone(),
+                schedule: None,
+                models_used: chat_session.models_used.clone(),
+                locked_model: chat_session.locked_model.clone(),
+                last_message_role: last_message.as_ref().map(|last| last.role.clone()),
+                last_message_at: last_message.as_ref().map(|last| last.timestamp),
+                status: api::SessionStatus::of(
+                    false,
+                    false,
+                    last_message.as_ref().is_some_and(|last| last.errored),
+                ),
             };
 
             api::broadcast_session_update(api::SessionUpdateEvent::Update {
-                session: session_item,
+                session: Box::new(session_item),
             });
 
             Ok(response)
@@ -402,18 +436,19 @@ async fn execute_job_from_request(
                         + chat_session.token_usage.reasoning_tokens;
 
                     // Record failed execution in job_executions table
-                    if let Err(e) = db.create_job_execution(
+                    if let Err(e) = db.create_job_execution(&db::JobExecution {
+                        id: None,
                         job_id,
-                        Some(&session_id),
-                        "failed",
-                        None,
-                        Some(&error_msg),
-                        &started_at.to_rfc3339(),
-                        Some(&completed_at.to_rfc3339()),
-                        Some(duration_ms),
-                        Some(total_tokens),
-                        Some(chat_session.cost_usd),
-                    ) {
+                        session_id: Some(session_id.clone()),
+                        status: "failed".to_string(),
+                        result: None,
+                        error_message: Some(error_msg.clone()),
+                        started_at: started_at.to_rfc3339(),
+                        completed_at: Some(completed_at.to_rfc3339()),
+                        duration_ms: Some(duration_ms),
+                        tokens_used: Some(total_tokens),
+                        cost_usd: Some(chat_session.cost_usd),
+                    }) {
                         error!("Failed to record job execution: {}", e);
                     }
 
@@ -1042,9 +1077,8 @@ mod tests {
         // This test verifies error handling, actual failure is environment-dependent
         let result = validate_file_path("test.txt");
         // Should either succeed (if in workspace) or fail gracefully with error message
-        match result {
-            Ok(_) => assert!(true),
-            Err(e) => assert!(e.contains("Failed to") || e.contains("Access denied")),
+        if let Err(e) = result {
+            assert!(e.contains("Failed to") || e.contains("Access denied"));
         }
     }
 
@@ -1077,7 +1111,7 @@ mod tests {
 
         assert_eq!(req.timeout_seconds, 0);
         // In execute_job_from_request, timeout_seconds > 0 triggers timeout wrapper
-        assert!(!(req.timeout_seconds > 0));
+        assert!(req.timeout_seconds <= 0);
     }
 
     #[test]
@@ -555,6 +555,110 @@ pub async fn trigger_job(path: web::Path<i64>) -> HttpResponse {
     }
 }
 
+/// Get execution history for a specific job
+pub async fn get_job_executions(
+    path: web::Path<i64>,
+    query: web::Query<std::collections::HashMap<String, String>>,
+) -> HttpResponse {
+    let db = get_db();
+    let job_id = path.into_inner();
+
+    // Get limit from query parameter (default to 50)
+    let limit = query
+        .get("limit")
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or(50);
+
+    match db.get_job_executions(job_id, Some(limit)) {
+        Ok(executions) => {
+            let response: Vec<JobExecutionResponse> =
+                executions.iter().map(JobExecutionResponse::from).collect();
+            HttpResponse::Ok().json(response)
+        }
+        Err(e) => {
+            // If table doesn't exist yet, return empty array instead of error
+            let error_msg = e.to_string();
+            if error_msg.contains("no such table: job_executions") {
+                warn!("job_executions table not yet created - returning empty array");
+                HttpResponse::Ok().json(Vec::<JobExecutionResponse>::new())
+            } else {
+                error!("Failed to get job executions for job {}: {}", job_id, e);
+                HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to get job executions: {}", e)
+                }))
+            }
+        }
+    }
+}
+
+/// Get a single execution by ID
+pub async fn get_job_execution(path: web::Path<i64>) -> HttpResponse {
+    let db = get_db();
+    let execution_id = path.into_inner();
+
+    match db.get_job_execution(execution_id) {
+        Ok(Some(execution)) => HttpResponse::Ok().json(JobExecutionResponse::from(&execution)),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Execution {} not found", execution_id)
+        })),
+        Err(e) => {
+            // If table doesn't exist yet, return 404
+            let error_msg = e.to_string();
+            if error_msg.contains("no such table: job_executions") {
+                warn!("job_executions table not yet created");
+                HttpResponse::NotFound().json(serde_json::json!({
+                    "error": format!("Execution {} not found", execution_id)
+                }))
+            } else {
+                error!("Failed to get job execution {}: {}", execution_id, e);
+                HttpResponse::InternalServerError().json(serde_json::json!({
+                    "error": format!("Failed to get job execution: {}", e)
+                }))
+            }
+        }
+    }
+}
+
+/// Server-Sent Events endpoint for job status updates
+pub async fn job_events() -> HttpResponse {
+    if let Some(tx) = JOB_UPDATE_BROADCASTER.get() {
+        let rx = tx.subscribe();
+        let stream = BroadcastStream::new(rx)
+            .filter_map(|result| match result {
+                Ok(event) => Some(event),
+                Err(e) => {
+                    warn!("Job SSE broadcast receive error: {}", e);
+                    None
+                }
+            })
+            .map(|event| {
+                let data = serde_json::to_string(&event).unwrap_or_else(|e| {
+                    error!("Failed to serialize job event: {}", e);
+                    String::from("{}")
+                });
+                Ok::<_, actix_web::Error>(web::Bytes::from(format!(
+                    "event: job_update\ndata: {}\n\n",
+                    data
+                )))
+            })
+            .throttle(Duration::from_millis(100)); // Limit to 10 updates/sec
+
+        HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+            .insert_header((header::CACHE_CONTROL, "no-cache"))
+            .insert_header((header::CONNECTION, "keep-alive"))
+            .streaming(Box::pin(stream)
+                as Pin<
+                    Box<dyn Stream<Item = Result<web::Bytes, actix_web::Error>>>,
+                >)
+    } else {
+        warn!("Job update broadcaster not initialized");
+        HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "error": "Job update broadcaster not available"
+        }))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -828,107 +932,3 @@ mod tests {
         // trigger_job endpoint should reject "once" jobs
     }
 }
-
-/// Get execution history for a specific job
-pub async fn get_job_executions(
-    path: web::Path<i64>,
-    query: web::Query<std::collections::HashMap<String, String>>,
-) -> HttpResponse {
-    let db = get_db();
-    let job_id = path.into_inner();
-
-    // Get limit from query parameter (default to 50)
-    let limit = query
-        .get("limit")
-        .and_then(|s| s.parse::<i64>().ok())
-        .unwrap_or(50);
-
-    match db.get_job_executions(job_id, Some(limit)) {
-        Ok(executions) => {
-            let response: Vec<JobExecutionResponse> =
-                executions.iter().map(JobExecutionResponse::from).collect();
-            HttpResponse::Ok().json(response)
-        }
-        Err(e) => {
-            // If table doesn't exist yet, return empty array instead of error
-            let error_msg = e.to_string();
-            if error_msg.contains("no such table: job_executions") {
-                warn!("job_executions table not yet created - returning empty array");
-                HttpResponse::Ok().json(Vec::<JobExecutionResponse>::new())
-            } else {
-                error!("Failed to get job executions for job {}: {}", job_id, e);
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to get job executions: {}", e)
-                }))
-            }
-        }
-    }
-}
-
-/// Get a single execution by ID
-pub async fn get_job_execution(path: web::Path<i64>) -> HttpResponse {
-    let db = get_db();
-    let execution_id = path.into_inner();
-
-    match db.get_job_execution(execution_id) {
-        Ok(Some(execution)) => HttpResponse::Ok().json(JobExecutionResponse::from(&execution)),
-        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
-            "error": format!("Execution {} not found", execution_id)
-        })),
-        Err(e) => {
-            // If table doesn't exist yet, return 404
-            let error_msg = e.to_string();
-            if error_msg.contains("no such table: job_executions") {
-                warn!("job_executions table not yet created");
-                HttpResponse::NotFound().json(serde_json::json!({
-                    "error": format!("Execution {} not found", execution_id)
-                }))
-            } else {
-                error!("Failed to get job execution {}: {}", execution_id, e);
-                HttpResponse::InternalServerError().json(serde_json::json!({
-                    "error": format!("Failed to get job execution: {}", e)
-                }))
-            }
-        }
-    }
-}
-
-/// Server-Sent Events endpoint for job status updates
-pub async fn job_events() -> HttpResponse {
-    if let Some(tx) = JOB_UPDATE_BROADCASTER.get() {
-        let rx = tx.subscribe();
-        let stream = BroadcastStream::new(rx)
-            .filter_map(|result| match result {
-                Ok(event) => Some(event),
-                Err(e) => {
-                    warn!("Job SSE broadcast receive error: {}", e);
-                    None
-                }
-            })
-            .map(|event| {
-                let data = serde_json::to_string(&event).unwrap_or_else(|e| {
-                    error!("Failed to serialize job event: {}", e);
-                    String::from("{}")
-                });
-                Ok::<_, actix_web::Error>(web::Bytes::from(format!(
-                    "event: job_update\ndata: {}\n\n",
-                    data
-                )))
-            })
-            .throttle(Duration::from_millis(100)); // Limit to 10 updates/sec
-
-        HttpResponse::Ok()
-            .insert_header((header::CONTENT_TYPE, "text/event-stream"))
-            .insert_header((header::CACHE_CONTROL, "no-cache"))
-            .insert_header((header::CONNECTION, "keep-alive"))
-            .streaming(Box::pin(stream)
-                as Pin<
-                    Box<dyn Stream<Item = Result<web::Bytes, actix_web::Error>>>,
-                >)
-    } else {
-        warn!("Job update broadcaster not initialized");
-        HttpResponse::ServiceUnavailable().json(serde_json::json!({
-            "error": "Job update broadcaster not available"
-        }))
-    }
-}
@@ -5,22 +5,31 @@ use async_openai::{
         ChatCompletionMessageToolCall, ChatCompletionMessageToolCalls,
         ChatCompletionRequestAssistantMessage, ChatCompletionRequestMessage,
         ChatCompletionRequestSystemMessage, ChatCompletionRequestToolMessage,
-        ChatCompletionRequestUserMessage, ChatCompletionStreamOptions,
-        CreateChatCompletionRequestArgs, FinishReason,
+        ChatCompletionRequestUserMessage, ChatCompletionTools, CompletionUsage,
+        CreateChatCompletionRequestArgs,
     },
 };
 use futures::StreamExt;
-use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::io::{self, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use crate::config;
-use crate::session::{ChatSession, Source, ThinkingStep};
+use crate::error::SquidError;
+use crate::output::{self, CommandError, CommandResult, print_json, status};
+use crate::providers::{self, ChatParams, ProviderEvent, StopReason, ToolChoice};
+use crate::sanitize;
+use crate::session::{ChatMessage, ChatSession, Source, SourceOrigin, ThinkingStep};
 use crate::template;
+use crate::tokens;
 use crate::tools;
-use crate::{db, rag, validate};
+use crate::{auto_include, db, envinfo, instructions, rag, validate};
+
+/// Name under which retrieved RAG context is shown to the model
+const RAG_CONTEXT_NAME: &str = "retrieved documents";
 
 // Prompt constants
 const PERSONA: &str = include_str!("./assets/persona.md");
@@ -45,41 +54,89 @@ const CODE_REVIEW_YAML_PROMPT: &str = include_str!("./assets/review-yaml.md");
 pub struct AskCommandOptions<'a> {
     pub message: Option<&'a str>,
     pub no_stream: bool,
-    pub file: Option<&'a Path>,
+    pub files: &'a [PathBuf],
     pub prompt: Option<&'a Path>,
     pub agent: Option<&'a str>,
     pub rag_flag: bool,
     pub no_rag_flag: bool,
+    pub cache_flag: bool,
+    /// Continue this saved session
+    pub session: Option<&'a str>,
+    /// Continue the most recently updated session
+    pub continue_session: bool,
+    /// Print an [`AskResult`] as JSON instead of streaming text
+    pub json_output: bool,
+    /// `--no-tools` or `--tool <name>`
+    pub tool_choice: ToolChoice,
+    /// Skip the `context.auto_include` files for a new session
+    pub no_auto_context: bool,
+}
+
+/// Options for the review command
+pub struct ReviewCommandOptions<'a> {
+    pub message: Option<&'a str>,
+    pub no_stream: bool,
+    pub agent: Option<&'a str>,
+    pub rag_flag: bool,
+    pub no_rag_flag: bool,
+    pub cache_flag: bool,
+    /// Review each file in its own request instead of one combined review
+    pub separate: bool,
+    /// Print an [`AskResult`] as JSON instead of streaming text
+    pub json_output: bool,
+    /// Prefix file lines with their numbers and ask for findings by line
+    pub line_numbers: bool,
+    /// Questions sent as further turns in the review session
+    pub follow_ups: &'a [String],
 }
 
 /// Parameters for LLM query functions
 pub struct LlmQueryParams<'a> {
     pub question: &'a str,
-    pub file_content: Option<&'a str>,
-    pub file_path: Option<&'a str>,
+    /// Files to include as `(name, content)` pairs
+    pub files: &'a [(&'a str, &'a str)],
+    /// Project files from `context.auto_include`, sent ahead of `files` with a note naming them
+    pub auto_included: &'a [(&'a str, &'a str)],
+    /// Retrieved RAG context, sent ahead of the files
+    pub context: Option<&'a str>,
     pub system_prompt: Option<&'a str>,
     pub model: &'a str,
     pub app_config: &'a config::Config,
     pub session: Option<&'a mut ChatSession>,
     pub db: Option<&'a db::Database>,
+    /// Consult and populate the response cache (requires `db`)
+    pub use_cache: bool,
+    /// Tool use for the first request of the turn
+    pub tool_choice: ToolChoice,
 }
 
-/// Combines persona and task-specific prompt into a complete system prompt
-/// Renders templates with secure context variables
-pub fn combine_prompts(task_prompt: &str) -> String {
-    let renderer = template::TemplateRenderer::new();
-
-    let persona = renderer.render_string(PERSONA).unwrap_or_else(|e| {
-        log::warn!("Failed to render persona template: {}", e);
-        PERSONA.to_string()
-    });
+impl LlmQueryParams<'_> {
+    /// The configured reasoning settings this model can be sent
+    fn reasoning(&self) -> config::ReasoningConfig {
+        providers::supported_reasoning(self.model, self.app_config.generation.reasoning)
+    }
 
-    let task = renderer.render_string(task_prompt).unwrap_or_else(|e| {
-        log::warn!("Failed to render task prompt template: {}", e);
-        task_prompt.to_string()
-    });
+    /// The user message for this query, with RAG context and files inlined
+    fn user_message(&self) -> String {
+        let mut files = Vec::with_capacity(self.auto_included.len() + self.files.len() + 1);
+        if let Some(context) = self.context {
+            files.push((RAG_CONTEXT_NAME, context));
+        }
+        files.extend_from_slice(self.auto_included);
+        files.extend_from_slice(self.files);
+        format!(
+            "{}{}",
+            auto_included_note(self.auto_included),
+            compose_user_message(self.question, &files)
+        )
+    }
+}
 
-    format!("{}\n\n{}", persona, task)
+/// Combines persona and task-specific prompt into a complete system prompt
+///
+/// Template variables are left in place and rendered with the rest of the system prompt.
+pub fn combine_prompts(task_prompt: &str) -> String {
+    sanitize::with_untrusted_content_policy(&format!("{}\n\n{}", PERSONA, task_prompt))
 }
 
 /// Strip <think>...</think> blocks from content
@@ -104,142 +161,674 @@ pub fn strip_reasoning_blocks(content: &str) -> String {
     result.trim().to_string()
 }
 
-/// Composes the user message with optional file content
+/// Replay stored conversation history as chat completion messages
+///
+/// Reasoning blocks are stripped from assistant messages, and tool invocations recorded in
+/// thinking steps are reconstructed as tool calls followed by their (wrapped) results.
+pub fn history_messages(history: &[ChatMessage]) -> Vec<ChatCompletionRequestMessage> {
+    let mut messages: Vec<ChatCompletionRequestMessage> = Vec::new();
+
+    for msg in history {
+        if msg.role == "user" {
+            messages.push(
+                ChatCompletionRequestUserMessage {
+                    content: msg.content.clone().into(),
+                    ..Default::default()
+                }
+                .into(),
+            );
+        } else if msg.role == "assistant" {
+            // Check if this message has tool invocations in thinking steps
+            if let Some(thinking_steps) = &msg.thinking_steps {
+                let tool_steps: Vec<_> = thinking_steps
+                    .iter()
+                    .filter(|s| s.step_type == "tool")
+                    .collect();
+
+                if !tool_steps.is_empty() {
+                    // Reconstruct tool calls from thinking steps
+                    let assistant_tool_calls: Vec<ChatCompletionMessageToolCalls> = tool_steps
+                        .iter()
+                        .enumerate()
+                        .map(|(idx, step)| {
+                            let mut tool_call = ChatCompletionMessageToolCall {
+                                id: format!("call_{}", idx),
+                                function: Default::default(),
+                            };
+                            tool_call.function.name = step.tool_name.clone().unwrap_or_default();
+                            tool_call.function.arguments =
+                                serde_json::to_string(&step.tool_arguments).unwrap_or_default();
+                            ChatCompletionMessageToolCalls::Function(tool_call)
+                        })
+                        .collect();
+
+                    // Add assistant message with tool calls
+                    let filtered_content = strip_reasoning_blocks(&msg.content);
+                    messages.push(
+                        ChatCompletionRequestAssistantMessage {
+                            content: if filtered_content.is_empty() {
+                                None
+                            } else {
+                                Some(filtered_content.into())
+                            },
+                            tool_calls: Some(assistant_tool_calls),
+                            ..Default::default()
+                        }
+                        .into(),
+                    );
+
+                    // Add tool result messages
+                    for (idx, step) in tool_steps.iter().enumerate() {
+                        let result_content = if let Some(error) = &step.tool_error {
+                            serde_json::json!({"error": error}).to_string()
+                        } else if let Some(result) = &step.tool_result {
+                            sanitize::wrap_tool_result(
+                                step.tool_name.as_deref().unwrap_or("tool"),
+                                result,
+                            )
+                        } else {
+                            serde_json::json!({"message": "Tool executed"}).to_string()
+                        };
+
+                        messages.push(
+                            ChatCompletionRequestToolMessage {
+                                content: result_content.into(),
+                                tool_call_id: format!("call_{}", idx),
+                            }
+                            .into(),
+                        );
+                    }
+                } else {
+                    // No tools, just add normal assistant message
+                    let filtered_content = strip_reasoning_blocks(&msg.content);
+                    messages.push(
+                        ChatCompletionRequestAssistantMessage {
+                            content: Some(filtered_content.into()),
+                            ..Default::default()
+                        }
+                        .into(),
+                    );
+                }
+            } else {
+                // No thinking steps, just add normal assistant message
+                let filtered_content = strip_reasoning_blocks(&msg.content);
+                messages.push(
+                    ChatCompletionRequestAssistantMessage {
+                        content: Some(filtered_content.into()),
+                        ..Default::default()
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+
+    messages
+}
+
+/// Stored history as the CLI sends it, with the files of each user message inlined again
+///
+/// Files are only stored as sources, so without this a continued session loses them.
+fn session_history(session: &ChatSession) -> Vec<ChatCompletionRequestMessage> {
+    let history: Vec<ChatMessage> = session
+        .messages
+        .iter()
+        .map(|msg| {
+            if msg.role != "user" || msg.sources.is_empty() {
+                return msg.clone();
+            }
+            let files: Vec<(&str, &str)> = msg
+                .sources
+                .iter()
+                .map(|source| (source.title.as_str(), source.content.as_str()))
+                .collect();
+            let auto_included: Vec<(&str, &str)> = msg
+                .sources
+                .iter()
+                .filter(|source| source.origin == SourceOrigin::Auto)
+                .map(|source| (source.title.as_str(), source.content.as_str()))
+                .collect();
+            ChatMessage {
+                content: format!(
+                    "{}{}",
+                    auto_included_note(&auto_included),
+                    compose_user_message(&msg.content, &files)
+                ),
+                ..msg.clone()
+            }
+        })
+        .collect();
+    history_messages(&history)
+}
+
+/// The note naming auto-included files, or nothing when there are none
+fn auto_included_note(files: &[(&str, &str)]) -> String {
+    if files.is_empty() {
+        return String::new();
+    }
+    let names: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+    auto_include::note(&names)
+}
+
+/// Composes the user message from `(name, content)` file pairs and the question
 /// Uses template rendering for variable substitution
-fn compose_user_message(
-    question: &str,
-    file_content: Option<&str>,
-    file_path: Option<&str>,
-) -> String {
+fn compose_user_message(question: &str, files: &[(&str, &str)]) -> String {
     let renderer = template::TemplateRenderer::new();
 
-    if let Some(content) = file_content {
-        let file_info = if let Some(path) = file_path {
-            format!("the file '{}'", path)
-        } else {
-            "the file".to_string()
-        };
+    let mut template = String::new();
+    for (name, content) in files {
+        template.push_str(&file_context(name, content));
+    }
+    template.push_str(&format!("User query: {}", question));
 
-        let template = format!(
-            "Here is the content of {}:\n\n```\n{}\n```\n\nUser query: {}",
-            file_info, content, question
-        );
+    renderer.render_string(&template).unwrap_or(template)
+}
+
+/// A file's content in a fenced, untrusted block, as sent to the model
+///
+/// The fence is tagged with the file's language and made longer than any backtick run inside
+/// the content, so code blocks in markdown files don't close it early.
+pub fn file_context(name: &str, content: &str) -> String {
+    let language = detect_language(Path::new(name)).or_else(|| {
+        Path::new(name)
+            .extension()
+            .is_none()
+            .then(|| shebang_language(content))
+            .flatten()
+    });
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let min_len = if language == Some("markdown") { 4 } else { 3 };
+    let fence = "`".repeat((longest_run + 1).max(min_len));
+    let block = format!(
+        "{}{}\n{}\n{}",
+        fence,
+        language.unwrap_or_default(),
+        content,
+        fence
+    );
+    format!(
+        "Here is the content of '{}':\n\n{}\n\n",
+        name,
+        sanitize::wrap_untrusted(&format!("file:{}", name), &block)
+    )
+}
+
+/// Language of a script from its `#!` line, e.g. `#!/usr/bin/env python3`
+fn shebang_language(content: &str) -> Option<&'static str> {
+    let shebang = content.lines().next()?.strip_prefix("#!")?;
+    let mut words = shebang.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+    let name = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    let language = match name {
+        "sh" | "bash" | "zsh" | "dash" | "ksh" | "fish" => "shell",
+        "python" => "python",
+        "node" | "deno" | "bun" => "javascript",
+        "ruby" => "ruby",
+        "perl" => "perl",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Appended to review prompts when files are sent with [`number_lines`]
+pub const LINE_NUMBER_INSTRUCTIONS: &str = "\n\n## Line Numbers\n\nEach line of the files under review is prefixed with its 1-based line number and ` | `, which are not part of the file. Reference every finding by its exact line or range (e.g. `line 42` or `lines 42-48`), and leave the prefixes out when quoting code.\n";
+
+/// Prefix each line with its 1-based number so findings can cite exact lines
+///
+/// Line endings, including `\r\n` and a missing final newline, are kept as they are.
+pub fn number_lines(content: &str) -> String {
+    let width = content.lines().count().max(1).to_string().len();
+    content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| format!("{:>width$} | {}", index + 1, line))
+        .collect()
+}
+
+/// Check that `files` fit in the context window together
+///
+/// On failure the error lists each file's estimated size and the largest files to drop.
+pub fn check_file_budget(
+    model: &str,
+    files: &[(&str, &str)],
+    context_window: u32,
+) -> Result<(), String> {
+    let mut sizes: Vec<(&str, i64)> = files
+        .iter()
+        .map(|(name, content)| (*name, tokens::estimate_message_tokens(model, content)))
+        .collect();
+    let total: i64 = sizes.iter().map(|(_, size)| size).sum();
+    let budget = context_window as i64;
+    if total <= budget {
+        return Ok(());
+    }
+
+    sizes.sort_by_key(|(_, size)| std::cmp::Reverse(*size));
+    let mut message = format!(
+        "The files need about {} tokens, more than the {}-token context window:",
+        total, context_window
+    );
+    for (name, size) in &sizes {
+        message.push_str(&format!("\n  {} (~{} tokens)", name, size));
+    }
 
-        renderer.render_string(&template).unwrap_or(template)
+    // Drop the largest files until the rest fits
+    let mut remaining = total;
+    let mut drop = Vec::new();
+    for (name, size) in &sizes {
+        if remaining <= budget {
+            break;
+        }
+        remaining -= size;
+        drop.push(*name);
+    }
+    if drop.len() < sizes.len() {
+        message.push_str(&format!("\nDrop {} to fit.", drop.join(", ")));
     } else {
-        let template = format!("User query: {}", question);
-        renderer.render_string(&template).unwrap_or(template)
+        message.push_str("\nUse a model with a larger context window, or fewer or smaller files.");
     }
+    Err(message)
 }
 
 pub fn get_ask_prompt() -> &'static str {
     ASK_PROMPT
 }
 
-/// Returns the appropriate code review prompt based on file extension
-pub fn get_review_prompt_for_file(file_path: &Path) -> &'static str {
+/// Bundled task prompts by the name templates refer to them with (`prompt_name`)
+pub const BUNDLED_PROMPTS: &[(&str, &str)] = &[
+    ("ask", ASK_PROMPT),
+    ("code-review", CODE_REVIEW_PROMPT),
+    ("review-rust", CODE_REVIEW_RUST_PROMPT),
+    ("review-typescript", CODE_REVIEW_TYPESCRIPT_PROMPT),
+    ("review-html", CODE_REVIEW_HTML_PROMPT),
+    ("review-css", CODE_REVIEW_CSS_PROMPT),
+    ("review-python", CODE_REVIEW_PYTHON_PROMPT),
+    ("review-sql", CODE_REVIEW_SQL_PROMPT),
+    ("review-shell", CODE_REVIEW_SHELL_PROMPT),
+    ("review-docker", CODE_REVIEW_DOCKER_PROMPT),
+    ("review-go", CODE_REVIEW_GO_PROMPT),
+    ("review-java", CODE_REVIEW_JAVA_PROMPT),
+    ("review-json", CODE_REVIEW_JSON_PROMPT),
+    ("review-makefile", CODE_REVIEW_MAKEFILE_PROMPT),
+    ("review-markdown", CODE_REVIEW_MARKDOWN_PROMPT),
+    ("review-yaml", CODE_REVIEW_YAML_PROMPT),
+];
+
+/// The bundled task prompt called `name`
+pub fn bundled_prompt(name: &str) -> Option<&'static str> {
+    BUNDLED_PROMPTS
+        .iter()
+        .find(|(bundled, _)| *bundled == name)
+        .map(|(_, prompt)| *prompt)
+}
+
+/// Detects the language of a file from its name or extension
+///
+/// Returns `None` for files without a recognized language.
+pub fn detect_language(file_path: &Path) -> Option<&'static str> {
     // Check for files without extensions first (Dockerfile, Makefile, etc.)
     if let Some(file_name) = file_path.file_name().and_then(|n| n.to_str()) {
         let lower_name = file_name.to_lowercase();
         if lower_name == "dockerfile" || lower_name.starts_with("dockerfile.") {
-            return CODE_REVIEW_DOCKER_PROMPT;
+            return Some("dockerfile");
         }
         if lower_name == "makefile" || lower_name.starts_with("makefile.") {
-            return CODE_REVIEW_MAKEFILE_PROMPT;
+            return Some("makefile");
         }
     }
 
     // Check by file extension
-    if let Some(extension) = file_path.extension() {
-        match extension.to_str() {
-            Some("rs") => CODE_REVIEW_RUST_PROMPT,
-            Some("ts") | Some("tsx") | Some("js") | Some("jsx") | Some("mjs") | Some("cjs") => {
-                CODE_REVIEW_TYPESCRIPT_PROMPT
-            }
-            Some("html") | Some("htm") => CODE_REVIEW_HTML_PROMPT,
-            Some("css") | Some("scss") | Some("sass") | Some("less") => CODE_REVIEW_CSS_PROMPT,
-            Some("py") | Some("pyw") | Some("pyi") => CODE_REVIEW_PYTHON_PROMPT,
-            Some("sql") | Some("ddl") | Some("dml") => CODE_REVIEW_SQL_PROMPT,
-            Some("sh") | Some("bash") | Some("zsh") | Some("fish") => CODE_REVIEW_SHELL_PROMPT,
-            Some("go") => CODE_REVIEW_GO_PROMPT,
-            Some("java") => CODE_REVIEW_JAVA_PROMPT,
-            Some("json") => CODE_REVIEW_JSON_PROMPT,
-            Some("yaml") | Some("yml") => CODE_REVIEW_YAML_PROMPT,
-            Some("md") | Some("markdown") => CODE_REVIEW_MARKDOWN_PROMPT,
-            _ => CODE_REVIEW_PROMPT,
+    let language = match file_path.extension()?.to_str()? {
+        "rs" => "rust",
+        "ts" | "tsx" => "typescript",
+        "js" | "jsx" | "mjs" | "cjs" => "javascript",
+        "html" | "htm" => "html",
+        "css" => "css",
+        "scss" | "sass" => "scss",
+        "less" => "less",
+        "py" | "pyw" | "pyi" => "python",
+        "sql" | "ddl" | "dml" => "sql",
+        "sh" | "bash" | "zsh" | "fish" => "shell",
+        "go" => "go",
+        "java" => "java",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "md" | "markdown" => "markdown",
+        "toml" => "toml",
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Returns the appropriate code review prompt based on file extension
+pub fn get_review_prompt_for_file(file_path: &Path) -> &'static str {
+    match detect_language(file_path) {
+        Some("dockerfile") => CODE_REVIEW_DOCKER_PROMPT,
+        Some("makefile") => CODE_REVIEW_MAKEFILE_PROMPT,
+        Some("rust") => CODE_REVIEW_RUST_PROMPT,
+        Some("typescript") | Some("javascript") => CODE_REVIEW_TYPESCRIPT_PROMPT,
+        Some("html") => CODE_REVIEW_HTML_PROMPT,
+        Some("css") | Some("scss") | Some("less") => CODE_REVIEW_CSS_PROMPT,
+        Some("python") => CODE_REVIEW_PYTHON_PROMPT,
+        Some("sql") => CODE_REVIEW_SQL_PROMPT,
+        Some("shell") => CODE_REVIEW_SHELL_PROMPT,
+        Some("go") => CODE_REVIEW_GO_PROMPT,
+        Some("java") => CODE_REVIEW_JAVA_PROMPT,
+        Some("json") => CODE_REVIEW_JSON_PROMPT,
+        Some("yaml") => CODE_REVIEW_YAML_PROMPT,
+        Some("markdown") => CODE_REVIEW_MARKDOWN_PROMPT,
+        _ => CODE_REVIEW_PROMPT,
+    }
+}
+
+/// Computes the response cache key for a request.
+/// The key covers the model, the rendered system prompt, the composed user message
+/// (including any file content) and the tool schema offered to the model.
+pub fn response_cache_key(model: &str, system_message: &str, user_message: &str) -> String {
+    let tool_schema = serde_json::to_string(&tools::get_tools()).unwrap_or_default();
+
+    let mut hasher = Sha256::new();
+    for part in [model, system_message, user_message, &tool_schema] {
+        hasher.update(part.as_bytes());
+        hasher.update([0u8]);
+    }
+    let digest = hasher.finalize();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Looks up a non-expired cached response for the given key
+fn lookup_cached_response(
+    params: &LlmQueryParams<'_>,
+    cache_key: &str,
+) -> Option<db::CachedResponse> {
+    let database = params.db?;
+    match database.get_cached_response(cache_key, params.app_config.cache.ttl_seconds) {
+        Ok(Some(cached)) => {
+            info!("Response cache hit ({})", &cache_key[..12]);
+            Some(cached)
+        }
+        Ok(None) => {
+            debug!("Response cache miss ({})", &cache_key[..12]);
+            None
+        }
+        Err(e) => {
+            warn!("Failed to read response cache: {}", e);
+            None
         }
-    } else {
-        CODE_REVIEW_PROMPT
     }
 }
 
-/// Sends a streaming request to the LLM and handles tool calls
-/// Optionally saves the conversation to a session if session_id and db are provided
-pub async fn ask_llm_streaming(
+/// Stores a completed response in the cache
+fn store_cached_response(
+    params: &LlmQueryParams<'_>,
+    cache_key: &str,
+    cached: &db::CachedResponse,
+) {
+    if let Some(database) = params.db
+        && let Err(e) = database.save_cached_response(cache_key, params.model, cached)
+    {
+        warn!("Failed to write response cache: {}", e);
+    }
+}
+
+/// Saves the user question and assistant answer to the session, if one was provided
+///
+/// `sources` are documents the model looked up with tools while answering.
+fn save_exchange(
     params: LlmQueryParams<'_>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    debug!("Using API URL: {}", params.app_config.api_url);
-    debug!("Using Model: {}", params.model);
+    answer: &str,
+    thinking_steps: Option<Vec<ThinkingStep>>,
+    sources: Vec<Source>,
+    usage: db::TurnUsage,
+) {
+    let reasoning = params.reasoning();
+    let (Some(sess), Some(database)) = (params.session, params.db) else {
+        return;
+    };
 
-    let config = OpenAIConfig::new()
-        .with_api_base(&params.app_config.api_url)
-        .with_api_key(params.app_config.get_api_key());
+    sess.note_model(params.model);
+
+    // Save session metadata FIRST (before messages, due to foreign key constraint)
+    if sess.title.is_none() {
+        // Generate title from first user message
+        let title = crate::text::truncate(params.question, 100);
+        sess.title = Some(title);
+    }
+
+    if let Err(e) = database.save_session(sess) {
+        debug!("Failed to save session: {}", e);
+    } else {
+        debug!("Session saved successfully");
+    }
+
+    // Save user message
+    let mut user_msg = crate::session::ChatMessage {
+        id: None,
+        parent_message_id: sess.messages.last().and_then(|msg| msg.id),
+        role: "user".to_string(),
+        content: params.question.to_string(),
+        sources: params
+            .auto_included
+            .iter()
+            .map(|(name, content)| Source::new(*name, *content, SourceOrigin::Auto))
+            .chain(
+                params
+                    .files
+                    .iter()
+                    .map(|(name, content)| Source::new(*name, *content, SourceOrigin::Attachment)),
+            )
+            .collect(),
+        timestamp: chrono::Utc::now().timestamp(),
+        thinking_steps: None,
+        metadata: None,
+        finish_reason: None,
+    };
+
+    let user_msg_id = match database.save_message(&sess.id, &user_msg) {
+        Ok(id) => {
+            debug!("User message saved successfully to session");
+            Some(id)
+        }
+        Err(e) => {
+            debug!("Failed to save user message: {}", e);
+            None
+        }
+    };
+
+    user_msg.id = user_msg_id;
+
+    // Save assistant message with thinking steps
+    let mut assistant_msg = crate::session::ChatMessage {
+        id: None,
+        parent_message_id: user_msg_id,
+        role: "assistant".to_string(),
+        content: answer.to_string(),
+        sources,
+        timestamp: chrono::Utc::now().timestamp(),
+        thinking_steps,
+        metadata: crate::session::MessageMetadata::for_answer(
+            params.model,
+            &params.app_config.api_url,
+            reasoning,
+        ),
+        finish_reason: None,
+    };
+
+    match database.save_message(&sess.id, &assistant_msg) {
+        Ok(id) => {
+            debug!("Assistant message saved successfully");
+            assistant_msg.id = Some(id);
+        }
+        Err(e) => debug!("Failed to save assistant message: {}", e),
+    }
+
+    // Keep the in-memory session in step, so a further turn sees this exchange as history
+    sess.messages.push(user_msg);
+    sess.messages.push(assistant_msg);
 
-    let client = Client::with_config(config);
+    // Update session token usage
+    sess.add_tokens(
+        usage.input_tokens,
+        usage.output_tokens,
+        usage.reasoning_tokens,
+        usage.cache_tokens,
+    );
+    if let Err(e) = database.save_session(sess) {
+        debug!("Failed to update session: {}", e);
+    }
+}
+
+/// What an ask request is built from, and the tokens each part takes
+struct AskContext {
+    system_message: String,
+    user_message: String,
+    tool_definitions: Vec<ChatCompletionTools>,
+    /// Earlier messages when continuing a saved session
+    history: Vec<ChatCompletionRequestMessage>,
+    tally: tokens::ContextTally,
+}
 
-    let user_message = compose_user_message(params.question, params.file_content, params.file_path);
+/// Assemble the system message, tools and history of an ask request, part by part
+async fn ask_context(params: &LlmQueryParams<'_>) -> Result<AskContext, SquidError> {
+    let mut tally = tokens::ContextTally::new(params.model);
+    let user_message = params.user_message();
+    // The question closes the message; the files and documents before it count as attachments
+    let query_start = user_message.rfind("User query: ").unwrap_or(0);
+    tally.add("attachments", &user_message[..query_start]);
+    tally.add("question", &user_message[query_start..]);
 
     let default_prompt = combine_prompts(ASK_PROMPT);
     let system_prompt_str = params.system_prompt.unwrap_or(&default_prompt);
 
     // Render template variables in system message
-    let renderer = template::TemplateRenderer::new();
-    let system_message = renderer
-        .render_string(system_prompt_str)
-        .unwrap_or_else(|e| {
-            log::warn!("Failed to render system prompt template: {}", e);
-            system_prompt_str.to_string()
-        });
+    let renderer = template::TemplateRenderer::for_prompt(params.app_config, params.model);
+    let mut system_message = renderer.render_prompt("System prompt", system_prompt_str)?;
+    tally.add("system_prompt", &system_message);
+    let start = system_message.len();
+    instructions::append_to_prompt(&mut system_message, params.app_config, &renderer)?;
+    tally.add("project_instructions", &system_message[start..]);
+    let start = system_message.len();
+    envinfo::append_to_prompt(&mut system_message, params.app_config, None).await;
+    tally.add("environment", &system_message[start..]);
+    let start = system_message.len();
+    let mut tool_definitions = tools::tools_for_model(
+        params.model,
+        params.tool_choice.allows_tools(),
+        &mut system_message,
+    );
+    tools::retain_doc_search(&mut tool_definitions, params.app_config.rag.enabled);
+    tools::append_tool_docs(&mut system_message, &tool_definitions, params.app_config);
+    tally.add("tools", &system_message[start..]);
+    if !tool_definitions.is_empty() {
+        tally.add_beside_messages(
+            "tools",
+            &serde_json::to_string(&tool_definitions).unwrap_or_default(),
+        );
+    }
 
     debug!("System message:\n{}", system_message);
     debug!("User message:\n{}", user_message);
 
-    let initial_messages = vec![
+    let history = params
+        .session
+        .as_deref()
+        .map(session_history)
+        .unwrap_or_default();
+    let (tool_results, earlier_turns): (Vec<_>, Vec<_>) = history
+        .iter()
+        .cloned()
+        .partition(|msg| matches!(msg, ChatCompletionRequestMessage::Tool(_)));
+    tally.add_messages("history", &earlier_turns);
+    tally.add_messages("tool_results", &tool_results);
+
+    Ok(AskContext {
+        system_message,
+        user_message,
+        tool_definitions,
+        history,
+        tally,
+    })
+}
+
+/// With --verbose, show what the request's context is made of
+fn print_context_breakdown(tally: tokens::ContextTally, messages: &[ChatCompletionRequestMessage]) {
+    if log::log_enabled!(log::Level::Debug) {
+        status!(
+            "Context breakdown (estimated tokens):\n{}",
+            tokens::format_breakdown(&tally.finish(messages))
+        );
+    }
+}
+
+/// Sends a streaming request to the LLM and handles tool calls
+/// Optionally saves the conversation to a session if session_id and db are provided
+pub async fn ask_llm_streaming(params: LlmQueryParams<'_>) -> Result<String, SquidError> {
+    debug!("Using API URL: {}", params.app_config.api_url);
+    debug!("Using Model: {}", params.model);
+
+    let provider = providers::from_config(params.app_config);
+    let chat_params = ChatParams {
+        model: params.model,
+        reasoning: params.reasoning(),
+        tool_choice: params.tool_choice.clone(),
+    };
+    // Only an off switch the model actually received keeps <think> blocks in the answer
+    let parse_reasoning = !chat_params.reasoning.is_disabled();
+
+    let AskContext {
+        system_message,
+        user_message,
+        tool_definitions,
+        history,
+        tally,
+    } = ask_context(&params).await?;
+
+    // The cache key doesn't cover history or tool choice, so those requests bypass the cache
+    let cache_key =
+        (params.use_cache && history.is_empty() && params.tool_choice == ToolChoice::Auto)
+            .then(|| response_cache_key(params.model, &system_message, &user_message));
+    if let Some(key) = cache_key.as_deref()
+        && let Some(cached) = lookup_cached_response(&params, key)
+    {
+        // Replay the cached text as a single chunk
+        print_answer(&cached.response);
+        status!("♻️  Cached response (no tokens used)");
+        save_exchange(
+            params,
+            &cached.response,
+            None,
+            Vec::new(),
+            db::TurnUsage::default(),
+        );
+        return Ok(cached.response);
+    }
+
+    let mut initial_messages: Vec<ChatCompletionRequestMessage> = vec![
         ChatCompletionRequestSystemMessage {
             content: system_message.into(),
             ..Default::default()
         }
         .into(),
+    ];
+    initial_messages.extend(history);
+    initial_messages.push(
         ChatCompletionRequestUserMessage {
             content: user_message.into(),
             ..Default::default()
         }
         .into(),
-    ];
-
-    let request = CreateChatCompletionRequestArgs::default()
-        .model(params.model)
-        .messages(initial_messages.clone())
-        .tools(tools::get_tools())
-        .stream_options(ChatCompletionStreamOptions {
-            include_usage: Some(true),
-            include_obfuscation: None,
-        })
-        .build()?;
-
-    debug!("Sending streaming request...");
+    );
+    print_context_breakdown(tally, &initial_messages);
 
     // Show spinner while waiting for the first response
-    let spinner = ProgressBar::new_spinner();
-    spinner.set_style(
-        ProgressStyle::default_spinner()
-            .template("{spinner:.cyan} {msg}")
-            .unwrap(),
-    );
-    spinner.set_message("Waiting for squid...");
-    spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+    let spinner = output::spinner("Waiting for squid...");
 
-    let mut stream = client.chat().create_stream(request).await?;
+    let mut stream = provider
+        .stream_chat(&initial_messages, &tool_definitions, &chat_params)
+        .await?;
     let mut tool_calls: Vec<ChatCompletionMessageToolCall> = Vec::new();
     let mut execution_handles = Vec::new();
     let mut lock = io::stdout().lock();
@@ -256,35 +845,28 @@ pub async fn ask_llm_streaming(
     let total_cache_tokens = 0i64;
 
     while let Some(result) = stream.next().await {
-        let response = result?;
-
-        // Log token usage statistics from streaming response (only present in final chunk)
-        if let Some(usage) = &response.usage {
-            writeln!(lock)?; // Add newline before logging token stats
-            debug!(
-                "Token usage - Prompt: {}, Completion: {}, Total: {}",
-                usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
-            );
-
-            total_input_tokens = usage.prompt_tokens as i64;
-            total_output_tokens = usage.completion_tokens as i64;
-            // reasoning_tokens and cache_tokens are not directly available in CompletionUsage
-            // They may be in completion_tokens_details depending on the API provider
+        match result? {
+            ProviderEvent::Usage {
+                input_tokens,
+                output_tokens,
+            } => {
+                writeln!(lock)?; // Add newline before logging token stats
+                debug!(
+                    "Token usage - Prompt: {}, Completion: {}",
+                    input_tokens, output_tokens
+                );
 
-            if let Some(prompt_details) = &usage.prompt_tokens_details
-                && let Some(cached) = prompt_details.cached_tokens
-            {
-                debug!("Cached tokens: {}", cached);
+                total_input_tokens = input_tokens;
+                total_output_tokens = output_tokens;
             }
-        }
-
-        for choice in response.choices {
-            if let Some(content) = &choice.delta.content {
+            ProviderEvent::Content(content) => {
                 // Clear spinner and write prompt on first content
                 if spinner_active {
                     spinner.finish_and_clear();
-                    writeln!(lock)?;
-                    write!(lock, "🦑: ")?;
+                    if !output::is_quiet() {
+                        writeln!(lock)?;
+                        write!(lock, "🦑: ")?;
+                    }
                     spinner_active = false;
                 }
 
@@ -295,16 +877,18 @@ pub async fn ask_llm_streaming(
                     content.as_str()
                 };
                 write!(lock, "{}", content_to_write)?;
-                accumulated_content.push_str(content);
+                accumulated_content.push_str(&content);
 
                 // Check for <think>...</think> blocks in the content
-                while let Some(think_start) = accumulated_content.find("<think>") {
+                while parse_reasoning && let Some(think_start) = accumulated_content.find("<think>")
+                {
                     if let Some(think_end) = accumulated_content.find("</think>") {
                         if think_end > think_start {
                             let reasoning_text =
                                 accumulated_content[think_start + 7..think_end].to_string();
                             if !reasoning_text.trim().is_empty() {
                                 thinking_steps.push(ThinkingStep {
+                                    id: None,
                                     step_type: "reasoning".to_string(),
                                     step_order,
                                     content: Some(reasoning_text),
@@ -312,7 +896,8 @@ pub async fn ask_llm_streaming(
                                     tool_arguments: None,
                                     tool_result: None,
                                     tool_error: None,
-                                    content_before_tool: None,
+                                    content_delta_before_tool: None,
+                                    status: None,
                                 });
                                 step_order += 1;
                             }
@@ -330,42 +915,22 @@ pub async fn ask_llm_streaming(
                     }
                 }
             }
-
-            if let Some(tool_call_chunks) = choice.delta.tool_calls {
-                for chunk in tool_call_chunks {
-                    let index = chunk.index as usize;
-
-                    while tool_calls.len() <= index {
-                        tool_calls.push(ChatCompletionMessageToolCall {
-                            id: String::new(),
-                            function: Default::default(),
-                        });
-                    }
-
-                    let tool_call = &mut tool_calls[index];
-                    if let Some(id) = chunk.id {
-                        tool_call.id = id;
-                    }
-                    if let Some(function_chunk) = chunk.function {
-                        if let Some(name) = function_chunk.name {
-                            tool_call.function.name = name;
-                        }
-                        if let Some(arguments) = function_chunk.arguments {
-                            tool_call
-                                .function
-                                .arguments
-                                .push_str(&arguments.to_string());
-                        }
-                    }
-                }
+            ProviderEvent::ToolCallDelta {
+                index,
+                id,
+                name,
+                arguments,
+            } => {
+                providers::apply_tool_call_delta(&mut tool_calls, index, id, name, arguments);
             }
-
-            if matches!(choice.finish_reason, Some(FinishReason::ToolCalls)) {
+            ProviderEvent::Stop(StopReason::ToolCalls) => {
                 // Clear spinner if still active (tool calls without content)
                 if spinner_active {
                     spinner.finish_and_clear();
-                    writeln!(lock)?;
-                    write!(lock, "🦑: ")?;
+                    if !output::is_quiet() {
+                        writeln!(lock)?;
+                        write!(lock, "🦑: ")?;
+                    }
                     spinner_active = false;
                 }
 
@@ -378,20 +943,21 @@ pub async fn ask_llm_streaming(
                     let handle = tokio::spawn(async move {
                         let result: serde_json::Value =
                             tools::call_tool(&name, &args, None, &config_clone).await;
-                        (tool_call_id, result)
+                        (tool_call_id, name, result)
                     });
                     execution_handles.push(handle);
                 }
             }
+            ProviderEvent::Stop(_) => {}
         }
         lock.flush()?;
     }
 
+    let mut doc_sources = Vec::new();
     if !execution_handles.is_empty() {
         let mut tool_responses = Vec::new();
         for handle in execution_handles {
-            let (tool_call_id, response) = handle.await?;
-            tool_responses.push((tool_call_id, response));
+            tool_responses.push(handle.await?);
         }
 
         let mut messages: Vec<ChatCompletionRequestMessage> = initial_messages;
@@ -408,52 +974,36 @@ pub async fn ask_llm_streaming(
             .into(),
         );
 
-        for (tool_call_id, response) in tool_responses {
+        for (tool_call_id, name, response) in tool_responses {
+            doc_sources.extend(tools::doc_sources(&name, &response));
             messages.push(
                 ChatCompletionRequestToolMessage {
-                    content: response.to_string().into(),
+                    content: sanitize::wrap_tool_result(&name, &response.to_string()).into(),
                     tool_call_id,
                 }
                 .into(),
             );
         }
 
-        let follow_up_request = CreateChatCompletionRequestArgs::default()
-            .model(params.model)
-            .messages(messages)
-            .stream_options(ChatCompletionStreamOptions {
-                include_usage: Some(true),
-                include_obfuscation: None,
-            })
-            .build()?;
-
-        let mut follow_up_stream = client.chat().create_stream(follow_up_request).await?;
+        let mut follow_up_stream = provider.stream_chat(&messages, &[], &chat_params).await?;
         let mut first_followup_content = true;
 
         while let Some(result) = follow_up_stream.next().await {
-            let response = result?;
-
-            // Log token usage statistics from follow-up streaming response (only present in final chunk)
-            if let Some(usage) = &response.usage {
-                writeln!(lock)?; // Add newline before logging token stats
-                debug!(
-                    "Follow-up token usage - Prompt: {}, Completion: {}, Total: {}",
-                    usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
-                );
-
-                total_input_tokens += usage.prompt_tokens as i64;
-                total_output_tokens += usage.completion_tokens as i64;
-                // reasoning_tokens and cache_tokens are not directly available in CompletionUsage
+            match result? {
+                ProviderEvent::Usage {
+                    input_tokens,
+                    output_tokens,
+                } => {
+                    writeln!(lock)?; // Add newline before logging token stats
+                    debug!(
+                        "Follow-up token usage - Prompt: {}, Completion: {}",
+                        input_tokens, output_tokens
+                    );
 
-                if let Some(prompt_details) = &usage.prompt_tokens_details
-                    && let Some(cached) = prompt_details.cached_tokens
-                {
-                    debug!("Follow-up cached tokens: {}", cached);
+                    total_input_tokens += input_tokens;
+                    total_output_tokens += output_tokens;
                 }
-            }
-
-            for choice in response.choices {
-                if let Some(content) = &choice.delta.content {
+                ProviderEvent::Content(content) => {
                     let content_to_write = if first_followup_content {
                         first_followup_content = false;
                         content.trim_start()
@@ -461,8 +1011,9 @@ pub async fn ask_llm_streaming(
                         content.as_str()
                     };
                     write!(lock, "{}", content_to_write)?;
-                    accumulated_content.push_str(content);
+                    accumulated_content.push_str(&content);
                 }
+                ProviderEvent::ToolCallDelta { .. } | ProviderEvent::Stop(_) => {}
             }
             lock.flush()?;
         }
@@ -470,135 +1021,188 @@ pub async fn ask_llm_streaming(
 
     writeln!(lock)?;
 
-    // Save to session if provided
-    if let Some(sess) = params.session
-        && let Some(database) = params.db
-    {
-        // Save session metadata FIRST (before messages, due to foreign key constraint)
-        if sess.title.is_none() {
-            // Generate title from first user message
-            let title = if params.question.len() > 100 {
-                format!("{}...", &params.question[..97])
-            } else {
-                params.question.to_string()
-            };
-            sess.title = Some(title);
-        }
-
-        if let Err(e) = database.save_session(sess) {
-            debug!("Failed to save session: {}", e);
-        } else {
-            debug!("Session saved successfully");
-        }
+    let answer = accumulated_content.trim().to_string();
 
-        // Save user message
-        let user_msg = crate::session::ChatMessage {
-            role: "user".to_string(),
-            content: params.question.to_string(),
-            sources: if let Some(path) = params.file_path {
-                if let Some(content) = params.file_content {
-                    vec![Source {
-                        title: path.to_string(),
-                        content: content.to_string(),
-                    }]
-                } else {
-                    vec![]
-                }
-            } else {
-                vec![]
+    // Responses that involved tool calls are not cached since tools may have side effects
+    if let Some(key) = cache_key.as_deref()
+        && tool_calls.is_empty()
+    {
+        store_cached_response(
+            &params,
+            key,
+            &db::CachedResponse {
+                response: answer.clone(),
+                input_tokens: total_input_tokens,
+                output_tokens: total_output_tokens,
+                reasoning_tokens: total_reasoning_tokens,
+                cache_tokens: total_cache_tokens,
             },
-            timestamp: chrono::Utc::now().timestamp(),
-            thinking_steps: None,
-        };
-
-        if let Err(e) = database.save_message(&sess.id, &user_msg) {
-            debug!("Failed to save user message: {}", e);
-        } else {
-            debug!("User message saved successfully to session");
-        }
+        );
+    }
 
-        // Save assistant message
-        let thinking_steps_opt = if thinking_steps.is_empty() {
-            None
-        } else {
-            Some(thinking_steps)
-        };
-        let assistant_msg = crate::session::ChatMessage {
-            role: "assistant".to_string(),
-            content: accumulated_content.trim().to_string(),
-            sources: vec![],
-            timestamp: chrono::Utc::now().timestamp(),
-            thinking_steps: thinking_steps_opt,
-        };
+    let thinking_steps_opt = if thinking_steps.is_empty() {
+        None
+    } else {
+        Some(thinking_steps)
+    };
+    save_exchange(
+        params,
+        &answer,
+        thinking_steps_opt,
+        doc_sources,
+        db::TurnUsage {
+            input_tokens: total_input_tokens,
+            output_tokens: total_output_tokens,
+            reasoning_tokens: total_reasoning_tokens,
+            cache_tokens: total_cache_tokens,
+        },
+    );
 
-        if let Err(e) = database.save_message(&sess.id, &assistant_msg) {
-            debug!("Failed to save assistant message: {}", e);
-        } else {
-            debug!("Assistant message saved successfully");
-        }
+    Ok(answer)
+}
 
-        // Update session token usage
-        sess.add_tokens(
-            total_input_tokens,
-            total_output_tokens,
-            total_reasoning_tokens,
-            total_cache_tokens,
-        );
-        if let Err(e) = database.save_session(sess) {
-            debug!("Failed to update session: {}", e);
-        }
-    }
+/// A tool call made while answering, with its result
+#[derive(Debug, Clone, Serialize)]
+pub struct ToolInvocation {
+    pub id: String,
+    pub name: String,
+    pub arguments: serde_json::Value,
+    pub result: serde_json::Value,
+}
 
-    Ok(accumulated_content.trim().to_string())
+/// Token usage across all requests made for one answer
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AskUsage {
+    pub input_tokens: i64,
+    pub output_tokens: i64,
+    /// Some requests were counted locally because the provider didn't report usage
+    pub estimated: bool,
+}
+
+impl AskUsage {
+    /// Add one request's usage, estimating it when the provider sent none
+    fn add(
+        &mut self,
+        reported: Option<&CompletionUsage>,
+        model: &str,
+        messages: &[ChatCompletionRequestMessage],
+        output: &str,
+    ) {
+        match reported {
+            Some(usage) => {
+                debug!(
+                    "Token usage - Prompt: {}, Completion: {}, Total: {}",
+                    usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
+                );
+                if let Some(prompt_details) = &usage.prompt_tokens_details
+                    && let Some(cached) = prompt_details.cached_tokens
+                {
+                    debug!("Cached tokens: {}", cached);
+                }
+                self.input_tokens += usage.prompt_tokens as i64;
+                self.output_tokens += usage.completion_tokens as i64;
+            }
+            None => {
+                debug!("No token usage in response, estimating");
+                self.input_tokens += tokens::estimate_tokens(model, messages).0;
+                self.output_tokens += tokens::estimate_message_tokens(model, output);
+                self.estimated = true;
+            }
+        }
+    }
+}
+
+/// Result of a non-streaming request
+#[derive(Debug, Clone, Serialize)]
+pub struct AskResult {
+    pub answer: String,
+    pub model: String,
+    pub tool_calls: Vec<ToolInvocation>,
+    pub usage: AskUsage,
+    pub elapsed_ms: u64,
+    /// Served from the response cache without calling the model
+    pub cached: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
 }
 
 /// Sends a non-streaming request to the LLM and handles tool calls
 /// Optionally saves the conversation to a session if session_id and db are provided
-pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::error::Error>> {
+pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<AskResult, SquidError> {
     debug!("Using API URL: {}", params.app_config.api_url);
     debug!("Using Model: {}", params.model);
 
+    let started_at = std::time::Instant::now();
+    let session_id = params.session.as_deref().map(|session| session.id.clone());
+    let finish = |answer: String, tool_calls, usage, cached| AskResult {
+        answer,
+        model: params.model.to_string(),
+        tool_calls,
+        usage,
+        elapsed_ms: started_at.elapsed().as_millis() as u64,
+        cached,
+        session_id: session_id.clone(),
+    };
+
     let config = OpenAIConfig::new()
         .with_api_base(&params.app_config.api_url)
         .with_api_key(params.app_config.get_api_key());
 
-    let client = Client::with_config(config);
-
-    let user_message = compose_user_message(params.question, params.file_content, params.file_path);
-
-    let default_prompt = combine_prompts(ASK_PROMPT);
-    let system_prompt_str = params.system_prompt.unwrap_or(&default_prompt);
-
-    // Render template variables in system message
-    let renderer = template::TemplateRenderer::new();
-    let system_message = renderer
-        .render_string(system_prompt_str)
-        .unwrap_or_else(|e| {
-            log::warn!("Failed to render system prompt template: {}", e);
-            system_prompt_str.to_string()
-        });
-
-    debug!("System message:\n{}", system_message);
-    debug!("User message:\n{}", user_message);
+    let http_client = params.app_config.network.http_client();
+    let client = Client::with_config(config).with_http_client(http_client.clone());
+
+    let AskContext {
+        system_message,
+        user_message,
+        tool_definitions,
+        history,
+        tally,
+    } = ask_context(&params).await?;
+
+    // The cache key doesn't cover history or tool choice, so those requests bypass the cache
+    let cache_key =
+        (params.use_cache && history.is_empty() && params.tool_choice == ToolChoice::Auto)
+            .then(|| response_cache_key(params.model, &system_message, &user_message));
+    if let Some(key) = cache_key.as_deref()
+        && let Some(cached) = lookup_cached_response(&params, key)
+    {
+        let result = finish(cached.response, Vec::new(), AskUsage::default(), true);
+        save_exchange(
+            params,
+            &result.answer,
+            None,
+            Vec::new(),
+            db::TurnUsage::default(),
+        );
+        return Ok(result);
+    }
 
-    let initial_messages = vec![
+    let mut initial_messages: Vec<ChatCompletionRequestMessage> = vec![
         ChatCompletionRequestSystemMessage {
             content: system_message.into(),
             ..Default::default()
         }
         .into(),
+    ];
+    initial_messages.extend(history);
+    initial_messages.push(
         ChatCompletionRequestUserMessage {
             content: user_message.into(),
             ..Default::default()
         }
         .into(),
-    ];
+    );
+    print_context_breakdown(tally, &initial_messages);
 
-    let request = CreateChatCompletionRequestArgs::default()
+    let mut request_builder = CreateChatCompletionRequestArgs::default();
+    request_builder
         .model(params.model)
-        .messages(initial_messages.clone())
-        .tools(tools::get_tools())
-        .build()?;
+        .messages(initial_messages.clone());
+    let has_tools = !tool_definitions.is_empty();
+    if has_tools {
+        request_builder.tools(tool_definitions);
+    }
+    let request = request_builder.build()?;
 
     debug!("Sending request...");
 
@@ -608,12 +1212,12 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
         params.app_config.api_url.trim_end_matches('/')
     );
     let mut raw_body = serde_json::to_value(&request).unwrap_or_default();
+    providers::apply_reasoning(&mut raw_body, &params.reasoning());
+    if has_tools {
+        providers::apply_tool_choice(&mut raw_body, &params.tool_choice);
+    }
 
-    // Add reasoning effort control (low = faster, less tokens; high = more thorough)
-    // Default to "low" for better performance in background jobs
-    raw_body["reasoning"] = serde_json::json!({ "effort": "low" });
-
-    let raw_resp = reqwest::Client::new()
+    let raw_resp = http_client
         .post(&raw_url)
         .header(
             "Authorization",
@@ -622,7 +1226,17 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
         .header("Content-Type", "application/json")
         .json(&raw_body)
         .send()
-        .await?;
+        .await
+        .map_err(|e| {
+            let api_url = &params.app_config.api_url;
+            if params.app_config.network.offline
+                && let Some(message) = providers::offline_unreachable_error(api_url, &e)
+            {
+                SquidError::provider(None, message)
+            } else {
+                SquidError::from(e)
+            }
+        })?;
 
     let raw_json: serde_json::Value = raw_resp.json().await?;
 
@@ -656,28 +1270,6 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
     let response: async_openai::types::chat::CreateChatCompletionResponse =
         serde_json::from_value(raw_json)?;
 
-    // Log token usage statistics
-    let mut total_input_tokens = 0i64;
-    let mut total_output_tokens = 0i64;
-    let total_reasoning_tokens = 0i64;
-    let total_cache_tokens = 0i64;
-
-    if let Some(usage) = &response.usage {
-        debug!(
-            "Token usage - Prompt: {}, Completion: {}, Total: {}",
-            usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
-        );
-
-        total_input_tokens = usage.prompt_tokens as i64;
-        total_output_tokens = usage.completion_tokens as i64;
-        // reasoning_tokens and cache_tokens are not directly available in CompletionUsage
-
-        if let Some(prompt_details) = &usage.prompt_tokens_details
-            && let Some(cached) = prompt_details.cached_tokens
-        {
-            debug!("Cached tokens: {}", cached);
-        }
-    }
     let response_message = response
         .choices
         .first()
@@ -685,6 +1277,28 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
         .message
         .clone();
 
+    // reasoning_tokens and cache_tokens are not directly available in CompletionUsage
+    let mut usage = AskUsage::default();
+    let total_reasoning_tokens = 0i64;
+    let total_cache_tokens = 0i64;
+    let first_output = response_message
+        .tool_calls
+        .iter()
+        .flatten()
+        .filter_map(|tool_call| match tool_call {
+            ChatCompletionMessageToolCalls::Function(tc) => Some(tc.function.arguments.as_str()),
+            _ => None,
+        })
+        .chain(response_message.content.as_deref())
+        .collect::<Vec<_>>()
+        .join("\n");
+    usage.add(
+        response.usage.as_ref(),
+        params.model,
+        &initial_messages,
+        &first_output,
+    );
+
     if let Some(tool_calls) = response_message.tool_calls {
         let mut handles = Vec::new();
         for tool_call in &tool_calls {
@@ -726,42 +1340,40 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
             .into(),
         );
 
+        let mut invocations = Vec::new();
         for (tool_call, response_content) in function_responses {
             if let ChatCompletionMessageToolCalls::Function(tc) = &tool_call {
                 messages.push(
                     ChatCompletionRequestToolMessage {
-                        content: response_content.to_string().into(),
+                        content: sanitize::wrap_tool_result(
+                            &tc.function.name,
+                            &response_content.to_string(),
+                        )
+                        .into(),
                         tool_call_id: tc.id.clone(),
                     }
                     .into(),
                 );
+                invocations.push(ToolInvocation {
+                    id: tc.id.clone(),
+                    name: tc.function.name.clone(),
+                    arguments: serde_json::from_str(&tc.function.arguments).unwrap_or_else(|_| {
+                        serde_json::Value::String(tc.function.arguments.clone())
+                    }),
+                    result: response_content,
+                });
             }
         }
 
         let follow_up_request = CreateChatCompletionRequestArgs::default()
             .model(params.model)
-            .messages(messages)
+            .messages(messages.clone())
             .build()?;
+        let mut follow_up_body = serde_json::to_value(&follow_up_request)?;
+        providers::apply_reasoning(&mut follow_up_body, &params.reasoning());
 
-        let final_response = client.chat().create(follow_up_request).await?;
-
-        // Log token usage statistics for follow-up request
-        if let Some(usage) = &final_response.usage {
-            debug!(
-                "Follow-up token usage - Prompt: {}, Completion: {}, Total: {}",
-                usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
-            );
-
-            total_input_tokens += usage.prompt_tokens as i64;
-            total_output_tokens += usage.completion_tokens as i64;
-            // reasoning_tokens and cache_tokens are not directly available in CompletionUsage
-
-            if let Some(prompt_details) = &usage.prompt_tokens_details
-                && let Some(cached) = prompt_details.cached_tokens
-            {
-                debug!("Follow-up cached tokens: {}", cached);
-            }
-        }
+        let final_response: async_openai::types::chat::CreateChatCompletionResponse =
+            client.chat().create_byot(follow_up_body).await?;
 
         let answer = final_response
             .choices
@@ -770,193 +1382,242 @@ pub async fn ask_llm(params: LlmQueryParams<'_>) -> Result<String, Box<dyn std::
             .ok_or("No response from LLM")?;
 
         let answer_str = answer.to_string();
+        usage.add(
+            final_response.usage.as_ref(),
+            params.model,
+            &messages,
+            &answer_str,
+        );
 
-        // Save to session if provided
-        if let Some(sess) = params.session
-            && let Some(database) = params.db
-        {
-            // Save session metadata FIRST (before messages, due to foreign key constraint)
-            if sess.title.is_none() {
-                let title = if params.question.len() > 100 {
-                    format!("{}...", &params.question[..97])
-                } else {
-                    params.question.to_string()
-                };
-                sess.title = Some(title);
-            }
+        // Build thinking steps from reasoning content
+        let thinking_steps = reasoning_content.as_ref().map(|reasoning| {
+            vec![crate::session::ThinkingStep {
+                id: None,
+                step_order: 0,
+                step_type: "reasoning".to_string(),
+                content: Some(reasoning.clone()),
+                tool_name: None,
+                tool_arguments: None,
+                tool_result: None,
+                tool_error: None,
+                content_delta_before_tool: None,
+                status: None,
+            }]
+        });
 
-            if let Err(e) = database.save_session(sess) {
-                debug!("Failed to save session: {}", e);
-            } else {
-                debug!("Session saved successfully");
-            }
+        let doc_sources = invocations
+            .iter()
+            .flat_map(|invocation| tools::doc_sources(&invocation.name, &invocation.result))
+            .collect();
+        let result = finish(answer_str, invocations, usage, false);
+        save_exchange(
+            params,
+            &result.answer,
+            thinking_steps,
+            doc_sources,
+            db::TurnUsage {
+                input_tokens: result.usage.input_tokens,
+                output_tokens: result.usage.output_tokens,
+                reasoning_tokens: total_reasoning_tokens,
+                cache_tokens: total_cache_tokens,
+            },
+        );
 
-            // Save user message
-            let user_msg = crate::session::ChatMessage {
-                role: "user".to_string(),
-                content: params.question.to_string(),
-                sources: if let Some(path) = params.file_path {
-                    if let Some(content) = params.file_content {
-                        vec![Source {
-                            title: path.to_string(),
-                            content: content.to_string(),
-                        }]
-                    } else {
-                        vec![]
-                    }
-                } else {
-                    vec![]
-                },
-                timestamp: chrono::Utc::now().timestamp(),
-                thinking_steps: None,
-            };
+        return Ok(result);
+    }
 
-            if let Err(e) = database.save_message(&sess.id, &user_msg) {
-                debug!("Failed to save user message: {}", e);
-            } else {
-                debug!("User message saved successfully to session");
-            }
+    let answer = response_message.content.ok_or("No response from LLM")?;
+    let answer_str = answer.to_string();
 
-            // Build thinking steps from reasoning content
-            let thinking_steps = reasoning_content.as_ref().map(|reasoning| {
-                vec![crate::session::ThinkingStep {
-                    step_order: 0,
-                    step_type: "reasoning".to_string(),
-                    content: Some(reasoning.clone()),
-                    tool_name: None,
-                    tool_arguments: None,
-                    tool_result: None,
-                    tool_error: None,
-                    content_before_tool: None,
-                }]
-            });
+    if let Some(key) = cache_key.as_deref() {
+        store_cached_response(
+            &params,
+            key,
+            &db::CachedResponse {
+                response: answer_str.clone(),
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+                reasoning_tokens: total_reasoning_tokens,
+                cache_tokens: total_cache_tokens,
+            },
+        );
+    }
 
-            // Save assistant message with thinking steps
-            let assistant_msg = crate::session::ChatMessage {
-                role: "assistant".to_string(),
-                content: answer_str.clone(),
-                sources: vec![],
-                timestamp: chrono::Utc::now().timestamp(),
-                thinking_steps,
-            };
+    // Build thinking steps from reasoning content
+    let thinking_steps = reasoning_content.as_ref().map(|reasoning| {
+        info!("Saving {} thinking step(s) for message", 1);
+        vec![crate::session::ThinkingStep {
+            id: None,
+            step_order: 0,
+            step_type: "reasoning".to_string(),
+            content: Some(reasoning.clone()),
+            tool_name: None,
+            tool_arguments: None,
+            tool_result: None,
+            tool_error: None,
+            content_delta_before_tool: None,
+            status: None,
+        }]
+    });
 
-            if let Err(e) = database.save_message(&sess.id, &assistant_msg) {
-                debug!("Failed to save assistant message: {}", e);
-            } else {
-                debug!("Assistant message saved successfully");
-            }
+    let result = finish(answer_str, Vec::new(), usage, false);
+    save_exchange(
+        params,
+        &result.answer,
+        thinking_steps,
+        Vec::new(),
+        db::TurnUsage {
+            input_tokens: result.usage.input_tokens,
+            output_tokens: result.usage.output_tokens,
+            reasoning_tokens: total_reasoning_tokens,
+            cache_tokens: total_cache_tokens,
+        },
+    );
 
-            // Update session token usage
-            sess.add_tokens(
-                total_input_tokens,
-                total_output_tokens,
-                total_reasoning_tokens,
-                total_cache_tokens,
-            );
-            if let Err(e) = database.save_session(sess) {
-                debug!("Failed to update session: {}", e);
-            }
-        }
+    Ok(result)
+}
 
-        return Ok(answer_str);
+/// Print an answer to stdout; `--quiet` drops the squid prefix so only the answer remains
+fn print_answer(answer: &str) {
+    if output::is_quiet() {
+        println!("{}", answer);
+    } else {
+        println!("\n🦑: {}", answer);
     }
+}
 
-    let answer = response_message.content.ok_or("No response from LLM")?;
-    let answer_str = answer.to_string();
-
-    // Save to session if provided (for simple responses without tool calls)
-    if let Some(sess) = params.session
-        && let Some(database) = params.db
-    {
-        // Save session metadata FIRST (before messages, due to foreign key constraint)
-        if sess.title.is_none() {
-            let title = if params.question.len() > 100 {
-                format!("{}...", &params.question[..97])
-            } else {
-                params.question.to_string()
-            };
-            sess.title = Some(title);
+/// Print the outcome of a non-streaming request
+fn print_ask_result(result: Result<AskResult, SquidError>, json_output: bool) -> CommandResult {
+    match result {
+        Ok(result) if json_output => print_json(&result)?,
+        Ok(result) => {
+            if result.cached {
+                status!("♻️  Cached response (no tokens used)");
+            }
+            print_answer(&result.answer);
         }
-
-        if let Err(e) = database.save_session(sess) {
-            debug!("Failed to save session: {}", e);
-        } else {
-            debug!("Session saved successfully");
+        Err(e) if json_output => return Err(json_error(e)),
+        Err(e) => {
+            error!("Failed to get response: {}", e);
+            return Err(CommandError::failed("Failed to get a response", e));
         }
+    }
+    Ok(())
+}
 
-        // Save user message
-        let user_msg = crate::session::ChatMessage {
-            role: "user".to_string(),
-            content: params.question.to_string(),
-            sources: if let Some(path) = params.file_path {
-                if let Some(content) = params.file_content {
-                    vec![Source {
-                        title: path.to_string(),
-                        content: content.to_string(),
-                    }]
-                } else {
-                    vec![]
-                }
-            } else {
-                vec![]
-            },
-            timestamp: chrono::Utc::now().timestamp(),
-            thinking_steps: None,
-        };
+/// Report a failed request as `{"error": ...}` on stdout for JSON output
+fn json_error(e: SquidError) -> CommandError {
+    error!("Failed to get response: {}", e);
+    println!("{}", serde_json::json!({ "error": e.to_string() }));
+    CommandError::Reported
+}
 
-        if let Err(e) = database.save_message(&sess.id, &user_msg) {
-            debug!("Failed to save user message: {}", e);
-        } else {
-            debug!("User message saved successfully to session");
+/// Describe why a file given on the command line couldn't be read
+fn file_read_error(e: &std::io::Error, what: &str) -> String {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => format!(
+            "I can't find that {}. Please check the path and try again.",
+            what
+        ),
+        std::io::ErrorKind::PermissionDenied => {
+            format!("I don't have permission to read that {}.", what)
         }
+        _ => format!("I couldn't read that {} - {}", what, e),
+    }
+}
 
-        // Build thinking steps from reasoning content
-        let thinking_steps = reasoning_content.as_ref().map(|reasoning| {
-            info!("Saving {} thinking step(s) for message", 1);
-            vec![crate::session::ThinkingStep {
-                step_order: 0,
-                step_type: "reasoning".to_string(),
-                content: Some(reasoning.clone()),
-                tool_name: None,
-                tool_arguments: None,
-                tool_result: None,
-                tool_error: None,
-                content_before_tool: None,
-            }]
-        });
-
-        // Save assistant message with thinking steps
-        let assistant_msg = crate::session::ChatMessage {
-            role: "assistant".to_string(),
-            content: answer_str.clone(),
-            sources: vec![],
-            timestamp: chrono::Utc::now().timestamp(),
-            thinking_steps,
-        };
+/// Describe why a file given on the command line was rejected by the path validator
+fn path_validation_error(e: validate::PathValidationError) -> String {
+    match e {
+        validate::PathValidationError::PathIgnored(_) => {
+            "I can't access that file - it's in your .squidignore list.".to_string()
+        }
+        validate::PathValidationError::PathNotAllowed(_) => {
+            "I can't access that file - it's outside the project directory or in a protected system location.".to_string()
+        }
+        e => {
+            debug!("Path validation failed: {}", e);
+            format!("I can't access that file - {}", e)
+        }
+    }
+}
 
-        if let Err(e) = database.save_message(&sess.id, &assistant_msg) {
-            debug!("Failed to save assistant message: {}", e);
-        } else {
-            debug!("Assistant message saved successfully");
+/// Validate and read files given on the command line, as `(path, content)` pairs
+fn read_input_files(
+    paths: &[PathBuf],
+    app_config: &config::Config,
+) -> Result<Vec<(String, String)>, CommandError> {
+    let validator =
+        validate::PathValidator::cached_for_project(app_config.security.respect_gitignore);
+
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Err(e) = validator.validate(path) {
+            return Err(format!("{}: {}", path.display(), path_validation_error(e)).into());
+        }
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                info!(
+                    "Read file content from {} ({} bytes)",
+                    path.display(),
+                    content.len()
+                );
+                files.push((path.display().to_string(), content));
+            }
+            Err(e) => {
+                debug!("Failed to read file {}: {}", path.display(), e);
+                return Err(format!("{}: {}", path.display(), file_read_error(&e, "file")).into());
+            }
         }
+    }
+    Ok(files)
+}
 
-        // Update session token usage
-        sess.add_tokens(
-            total_input_tokens,
-            total_output_tokens,
-            total_reasoning_tokens,
-            total_cache_tokens,
-        );
-        if let Err(e) = database.save_session(sess) {
-            debug!("Failed to update session: {}", e);
+/// Borrow owned `(name, content)` pairs for [`LlmQueryParams::files`]
+fn file_pairs(files: &[(String, String)]) -> Vec<(&str, &str)> {
+    files
+        .iter()
+        .map(|(name, content)| (name.as_str(), content.as_str()))
+        .collect()
+}
+
+/// The model and context window of an agent
+fn resolve_agent_model(
+    agent_id: &str,
+    app_config: &config::Config,
+) -> Result<(String, u32), CommandError> {
+    match app_config.get_agent(agent_id) {
+        Some(agent_config) => {
+            info!(
+                "Using agent '{}' with model '{}'",
+                agent_id, agent_config.model
+            );
+            let context_window = app_config.agent_context_window(agent_config);
+            Ok((agent_config.model.clone(), context_window))
         }
+        None => Err(agent_not_found(agent_id, app_config)),
     }
+}
 
-    Ok(answer_str)
+/// Error for an unknown agent, listing the configured ones
+fn agent_not_found(agent_id: &str, app_config: &config::Config) -> CommandError {
+    error!("Agent '{}' not found", agent_id);
+    format!(
+        "Configuration error - agent '{}' not found\nAvailable agents: {}",
+        agent_id,
+        app_config
+            .agents
+            .agents
+            .keys()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .into()
 }
 
 /// Initialize RAG system if needed based on config and CLI flags
-async fn initialize_rag_if_needed(
+pub(crate) async fn initialize_rag_if_needed(
     config_enabled: bool,
     rag_flag: bool,
     no_rag_flag: bool,
@@ -975,7 +1636,13 @@ async fn initialize_rag_if_needed(
     }
 
     match db::Database::new(&app_config.database_path) {
-        Ok(db) => match rag::RagSystem::new(Arc::new(db), &app_config.rag).await {
+        Ok(db) => match rag::RagSystem::new(
+            Arc::new(db),
+            &app_config.rag,
+            app_config.network.http_client(),
+        )
+        .await
+        {
             Ok(system) => Some(Arc::new(system)),
             Err(e) => {
                 warn!("RAG initialization failed: {}", e);
@@ -989,13 +1656,45 @@ async fn initialize_rag_if_needed(
     }
 }
 
+/// Load the session `ask --session`/`--continue` should append to
+///
+/// Uses `session_id` when given, otherwise the most recently updated session.
+fn load_session_to_continue(
+    database: &db::Database,
+    session_id: Option<&str>,
+) -> Result<ChatSession, String> {
+    let session_id = match session_id {
+        Some(id) => id.to_string(),
+        None => database
+            .list_sessions()
+            .map_err(|e| format!("Failed to list sessions: {}", e))?
+            .into_iter()
+            .next()
+            .ok_or("There are no saved sessions to continue")?,
+    };
+
+    let session = database
+        .load_session(&session_id)
+        .map_err(|e| format!("Failed to load session '{}': {}", session_id, e))?
+        .ok_or_else(|| format!("Session '{}' not found", session_id))?;
+
+    if session.is_readonly {
+        return Err(format!(
+            "Session '{}' is read-only and can't be continued",
+            session_id
+        ));
+    }
+
+    Ok(session)
+}
+
 /// Handles the `ask` command: resolves file content, custom prompt, RAG context,
 /// and agent model, then dispatches to the LLM (streaming or non-streaming).
 pub async fn run_ask_command(
     question: &str,
     options: AskCommandOptions<'_>,
     app_config: &config::Config,
-) {
+) -> CommandResult {
     let full_question = if let Some(m) = options.message {
         format!("{} {}", question, m)
     } else {
@@ -1004,49 +1703,7 @@ pub async fn run_ask_command(
 
     info!("Q: {}", full_question);
 
-    let file_content = if let Some(file_path) = options.file {
-        let ignore_patterns = validate::PathValidator::load_ignore_patterns();
-        let validator = validate::PathValidator::with_ignore_file(Some(ignore_patterns));
-
-        match validator.validate(file_path) {
-            Ok(_) => match std::fs::read_to_string(file_path) {
-                Ok(content) => {
-                    info!("Read file content ({} bytes)", content.len());
-                    Some(content)
-                }
-                Err(e) => {
-                    if e.kind() == std::io::ErrorKind::NotFound {
-                        println!(
-                            "🦑: I can't find that file. Please check the path and try again."
-                        );
-                    } else if e.kind() == std::io::ErrorKind::PermissionDenied {
-                        println!("🦑: I don't have permission to read that file.");
-                    } else {
-                        println!("🦑: I couldn't read that file - {}", e);
-                    }
-                    debug!("Failed to read file {}: {}", file_path.display(), e);
-                    return;
-                }
-            },
-            Err(validate::PathValidationError::PathIgnored(_)) => {
-                println!("🦑: I can't access that file - it's in your .squidignore list.");
-                return;
-            }
-            Err(validate::PathValidationError::PathNotAllowed(_)) => {
-                println!(
-                    "🦑: I can't access that file - it's outside the project directory or in a protected system location."
-                );
-                return;
-            }
-            Err(e) => {
-                debug!("Path validation failed: {}", e);
-                println!("🦑: I can't access that file - {}", e);
-                return;
-            }
-        }
-    } else {
-        None
-    };
+    let file_contents = read_input_files(options.files, app_config)?;
 
     let custom_prompt = if let Some(prompt_path) = options.prompt {
         match std::fs::read_to_string(prompt_path) {
@@ -1055,21 +1712,17 @@ pub async fn run_ask_command(
                 Some(content)
             }
             Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    println!(
-                        "🦑: I can't find that custom prompt file. Please check the path and try again."
-                    );
-                } else if e.kind() == std::io::ErrorKind::PermissionDenied {
-                    println!("🦑: I don't have permission to read that prompt file.");
-                } else {
-                    println!("🦑: I couldn't read that prompt file - {}", e);
-                }
                 debug!(
                     "Failed to read custom prompt file {}: {}",
                     prompt_path.display(),
                     e
                 );
-                return;
+                let what = if e.kind() == std::io::ErrorKind::NotFound {
+                    "custom prompt file"
+                } else {
+                    "prompt file"
+                };
+                return Err(file_read_error(&e, what).into());
             }
         }
     } else {
@@ -1085,7 +1738,7 @@ pub async fn run_ask_command(
     .await;
 
     let rag_context = if let Some(ref system) = rag_system {
-        println!("🦑: Using RAG for enhanced context...");
+        status!("🦑: Using RAG for enhanced context...");
         match system.query.execute(&full_question).await {
             Ok(context) if !context.is_empty() => {
                 debug!("RAG retrieved {} bytes of context", context.len());
@@ -1097,7 +1750,7 @@ pub async fn run_ask_command(
             }
             Err(e) => {
                 warn!("RAG query failed: {}", e);
-                println!("🦑: RAG query failed, continuing without RAG context");
+                status!("🦑: RAG query failed, continuing without RAG context");
                 None
             }
         }
@@ -1105,43 +1758,7 @@ pub async fn run_ask_command(
         None
     };
 
-    let enhanced_file_content = match (rag_context, file_content) {
-        (Some(rag), Some(file)) => Some(format!("{}\n\n# Provided File:\n\n{}", rag, file)),
-        (Some(rag), None) => Some(rag),
-        (None, file_opt) => file_opt,
-    };
-
-    let agent_id = options
-        .agent
-        .unwrap_or(app_config.agents.default_agent.as_str());
-    let model = match app_config.get_agent(agent_id) {
-        Some(agent_config) => {
-            info!(
-                "Using agent '{}' with model '{}'",
-                agent_id, agent_config.model
-            );
-            agent_config.model.clone()
-        }
-        None => {
-            error!("Agent '{}' not found", agent_id);
-            println!("🦑: Configuration error - agent '{}' not found", agent_id);
-            println!(
-                "Available agents: {}",
-                app_config
-                    .agents
-                    .agents
-                    .keys()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            );
-            return;
-        }
-    };
-
-    // Create session and open database for saving conversation
-    let mut session = ChatSession::new();
-    session.set_model(model.clone());
+    // Open the database for saving the conversation (and loading it when continuing)
     let db = match db::Database::new(&app_config.database_path) {
         Ok(db) => Some(db),
         Err(e) => {
@@ -1150,219 +1767,394 @@ pub async fn run_ask_command(
         }
     };
 
-    if options.no_stream {
-        match ask_llm(LlmQueryParams {
+    let existing_session = if options.session.is_some() || options.continue_session {
+        let Some(database) = db.as_ref() else {
+            return Err("I can't continue a session - the database is unavailable.".into());
+        };
+        match load_session_to_continue(database, options.session) {
+            Ok(session) => {
+                info!(
+                    "Continuing session {} ({} messages)",
+                    session.id,
+                    session.messages.len()
+                );
+                Some(session)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    } else {
+        None
+    };
+
+    // A continued session keeps its agent unless --agent overrides it
+    let session_agent = existing_session
+        .as_ref()
+        .and_then(|s| s.agent_id.clone())
+        .filter(|id| app_config.get_agent(id).is_some());
+    let agent_id = options
+        .agent
+        .map(str::to_string)
+        .or(session_agent)
+        .unwrap_or_else(|| app_config.agents.default_agent.clone());
+    let agent_id = agent_id.as_str();
+    let (model, context_window) = resolve_agent_model(agent_id, app_config)?;
+
+    if let Some(name) = options.tool_choice.forced_tool() {
+        if !crate::models::supports_tools(&model) {
+            return Err(format!(
+                "Can't force tool '{}': model '{}' doesn't support tools",
+                name, model
+            )
+            .into());
+        }
+        crate::policy::check_forced_tool(name, agent_id, app_config)?;
+    }
+    info!("Tool choice: {}", options.tool_choice);
+
+    let files = file_pairs(&file_contents);
+    check_file_budget(&model, &files, context_window)?;
+
+    if let Some(session) = &existing_session {
+        session.check_model_lock(&model)?;
+    }
+
+    // A new session starts with the project files listed in context.auto_include
+    let auto_included = if existing_session.is_none() && !options.no_auto_context {
+        auto_include::load(app_config, &model, context_window)
+    } else {
+        auto_include::AutoIncluded::default()
+    };
+    for warning in &auto_included.warnings {
+        status!("⚠ {}", warning);
+    }
+    let auto_included = file_pairs(&auto_included.files);
+
+    let mut session = existing_session.unwrap_or_else(|| {
+        let mut session = ChatSession::new();
+        session.set_agent(agent_id.to_string());
+        session
+    });
+
+    let use_cache = options.cache_flag || app_config.cache.enabled;
+
+    if options.no_stream || options.json_output {
+        let result = ask_llm(LlmQueryParams {
             question: &full_question,
-            file_content: enhanced_file_content.as_deref(),
-            file_path: options.file.and_then(|p| p.to_str()),
+            files: &files,
+            auto_included: &auto_included,
+            context: rag_context.as_deref(),
             system_prompt: custom_prompt.as_deref(),
             model: &model,
             app_config,
             session: Some(&mut session),
             db: db.as_ref(),
+            use_cache,
+            tool_choice: options.tool_choice,
         })
-        .await
-        {
-            Ok(response) => println!("\n🦑: {}", response),
-            Err(e) => error!("Failed to get response: {}", e),
-        }
+        .await;
+        print_ask_result(result, options.json_output)?;
     } else if let Err(e) = ask_llm_streaming(LlmQueryParams {
         question: &full_question,
-        file_content: enhanced_file_content.as_deref(),
-        file_path: options.file.and_then(|p| p.to_str()),
+        files: &files,
+        auto_included: &auto_included,
+        context: rag_context.as_deref(),
         system_prompt: custom_prompt.as_deref(),
         model: &model,
         app_config,
         session: Some(&mut session),
         db: db.as_ref(),
+        use_cache,
+        tool_choice: options.tool_choice,
     })
     .await
     {
         error!("Failed to get response: {}", e);
+        return Err(CommandError::failed("Failed to get a response", e));
     }
 
-    println!("💾 Session saved");
+    if db.is_some() && !options.json_output {
+        status!("💾 Session saved: {}", session.id);
+        status!(
+            "   Continue with: squid ask --session {} \"<question>\"",
+            session.id
+        );
+    }
+    Ok(())
 }
 
-/// Handles the `review` command: validates and reads the file, initialises RAG,
+/// Handles the `review` command: validates and reads the files, initialises RAG,
 /// selects the language-specific prompt, and dispatches to the LLM.
+///
+/// All files go into one combined review unless `separate` is set.
 pub async fn run_review_command(
-    file: &Path,
-    message: Option<&str>,
-    no_stream: bool,
-    agent: Option<&str>,
-    rag_flag: bool,
-    no_rag_flag: bool,
+    files: &[PathBuf],
+    options: ReviewCommandOptions<'_>,
     app_config: &config::Config,
-) {
-    info!("Reviewing file: {:?}", file);
+) -> CommandResult {
+    info!("Reviewing files: {:?}", files);
 
-    let ignore_patterns = validate::PathValidator::load_ignore_patterns();
-    let validator = validate::PathValidator::with_ignore_file(Some(ignore_patterns));
-
-    let file_content = match validator.validate(file) {
-        Ok(_) => match std::fs::read_to_string(file) {
-            Ok(content) => {
-                info!("Read file content ({} bytes)", content.len());
-                content
-            }
-            Err(e) => {
-                if e.kind() == std::io::ErrorKind::NotFound {
-                    println!("🦑: I can't find that file. Please check the path and try again.");
-                } else if e.kind() == std::io::ErrorKind::PermissionDenied {
-                    println!("🦑: I don't have permission to read that file.");
-                } else {
-                    println!("🦑: I couldn't read that file - {}", e);
-                }
-                debug!("Failed to read file {}: {}", file.display(), e);
-                return;
-            }
-        },
-        Err(validate::PathValidationError::PathIgnored(_)) => {
-            println!("🦑: I can't access that file - it's in your .squidignore list.");
-            return;
+    let mut file_contents = read_input_files(files, app_config)?;
+    if options.line_numbers {
+        for (_, content) in &mut file_contents {
+            *content = number_lines(content);
         }
-        Err(validate::PathValidationError::PathNotAllowed(_)) => {
-            println!(
-                "🦑: I can't access that file - it's outside the project directory or in a protected system location."
-            );
-            return;
+    }
+
+    let agent_id = options
+        .agent
+        .unwrap_or(app_config.agents.default_agent.as_str());
+    let (model, context_window) = resolve_agent_model(agent_id, app_config)?;
+
+    // Check sizes up front so a large file doesn't fail the run halfway through
+    let pairs = file_pairs(&file_contents);
+    if options.separate {
+        for pair in &pairs {
+            check_file_budget(&model, std::slice::from_ref(pair), context_window)?;
         }
+    } else {
+        check_file_budget(&model, &pairs, context_window)?;
+    }
+
+    let rag_system = initialize_rag_if_needed(
+        app_config.rag.enabled,
+        options.rag_flag,
+        options.no_rag_flag,
+        app_config,
+    )
+    .await;
+
+    // Open database for saving conversations
+    let db = match db::Database::new(&app_config.database_path) {
+        Ok(db) => Some(db),
         Err(e) => {
-            debug!("Path validation failed: {}", e);
-            println!("🦑: I can't access that file - {}", e);
-            return;
+            warn!("Failed to open database for session saving: {}", e);
+            None
         }
     };
 
-    let review_prompt = get_review_prompt_for_file(file);
-    let combined_review_prompt = combine_prompts(review_prompt);
-    debug!("Using review prompt for file type");
-
-    let question = if let Some(msg) = message {
-        format!("Please review this code. {}", msg)
-    } else {
-        "Please review this code.".to_string()
+    let request = ReviewRequest {
+        options: &options,
+        agent_id,
+        model: &model,
+        rag_system: rag_system.as_deref(),
+        db: db.as_ref(),
+        app_config,
     };
 
-    let rag_system =
-        initialize_rag_if_needed(app_config.rag.enabled, rag_flag, no_rag_flag, app_config).await;
-
-    let rag_context = if let Some(ref system) = rag_system {
-        println!("🦑: Using RAG for enhanced context...");
-        let file_extension = file
-            .extension()
-            .and_then(|e| e.to_str())
-            .unwrap_or("unknown");
-        let review_query = format!(
-            "code review best practices and common issues for {} files{}",
-            file_extension,
-            message.map(|m| format!(": {}", m)).unwrap_or_default()
-        );
+    if !options.separate {
+        if let Some(result) = request.run(files, &pairs).await? {
+            print_json(&result)?;
+        }
+        return Ok(());
+    }
 
-        match system.query.execute(&review_query).await {
-            Ok(context) if !context.is_empty() => {
-                debug!(
-                    "RAG retrieved {} bytes of context for review",
-                    context.len()
-                );
-                Some(context)
-            }
-            Ok(_) => {
-                debug!("RAG returned empty context");
-                None
-            }
-            Err(e) => {
-                warn!("RAG query failed: {}", e);
-                println!("🦑: RAG query failed, continuing without RAG context");
-                None
-            }
+    let mut results = Vec::new();
+    for (path, pair) in files.iter().zip(&pairs) {
+        if !options.json_output {
+            println!("\n## {}", pair.0);
         }
-    } else {
-        None
-    };
+        if let Some(result) = request
+            .run(std::slice::from_ref(path), std::slice::from_ref(pair))
+            .await?
+        {
+            results.push(FileReview {
+                file: pair.0.to_string(),
+                result,
+            });
+        }
+    }
+    if options.json_output {
+        print_json(&results)?;
+    }
+    Ok(())
+}
 
-    let enhanced_content = if let Some(rag) = rag_context {
-        format!("{}\n\n# Code to Review:\n\n{}", rag, file_content)
-    } else {
-        file_content
-    };
+/// One file's result in `review --separate --output json`
+#[derive(Serialize)]
+struct FileReview {
+    file: String,
+    #[serde(flatten)]
+    result: ReviewResult,
+}
 
-    let agent_id = agent.unwrap_or(app_config.agents.default_agent.as_str());
-    let model = match app_config.get_agent(agent_id) {
-        Some(agent_config) => {
-            info!(
-                "Using agent '{}' with model '{}'",
-                agent_id, agent_config.model
-            );
-            agent_config.model.clone()
+/// A review in `review --output json`, with the answers to `--follow-up` questions
+#[derive(Serialize)]
+struct ReviewResult {
+    #[serde(flatten)]
+    review: AskResult,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    follow_ups: Vec<AskResult>,
+}
+
+/// Settings shared by the review requests of one `review` command
+struct ReviewRequest<'a> {
+    options: &'a ReviewCommandOptions<'a>,
+    agent_id: &'a str,
+    model: &'a str,
+    rag_system: Option<&'a rag::RagSystem>,
+    db: Option<&'a db::Database>,
+    app_config: &'a config::Config,
+}
+
+impl ReviewRequest<'_> {
+    /// Review `files` in a new session, then ask the `--follow-up` questions in it
+    ///
+    /// With JSON output the result is returned for the caller to print; otherwise it is
+    /// printed here and `None` is returned.
+    async fn run(
+        &self,
+        paths: &[PathBuf],
+        files: &[(&str, &str)],
+    ) -> Result<Option<ReviewResult>, CommandError> {
+        let message = self.options.message;
+        let mut combined_review_prompt = combine_prompts(review_prompt_for_files(paths));
+        if self.options.line_numbers {
+            combined_review_prompt.push_str(LINE_NUMBER_INSTRUCTIONS);
         }
-        None => {
-            error!("Agent '{}' not found", agent_id);
-            println!("🦑: Configuration error - agent '{}' not found", agent_id);
-            println!(
-                "Available agents: {}",
-                app_config
-                    .agents
-                    .agents
-                    .keys()
-                    .map(|s| s.as_str())
-                    .collect::<Vec<_>>()
-                    .join(", ")
+        debug!("Using review prompt for file type");
+
+        let question = if let Some(msg) = message {
+            format!("Please review this code. {}", msg)
+        } else {
+            "Please review this code.".to_string()
+        };
+
+        let rag_context = if let Some(system) = self.rag_system {
+            status!("🦑: Using RAG for enhanced context...");
+            let mut extensions: Vec<&str> = paths
+                .iter()
+                .map(|path| {
+                    path.extension()
+                        .and_then(|e| e.to_str())
+                        .unwrap_or("unknown")
+                })
+                .collect();
+            extensions.sort_unstable();
+            extensions.dedup();
+            let review_query = format!(
+                "code review best practices and common issues for {} files{}",
+                extensions.join(", "),
+                message.map(|m| format!(": {}", m)).unwrap_or_default()
             );
-            return;
-        }
-    };
 
-    // Create session and open database for saving conversation
-    let mut session = ChatSession::new();
-    session.set_model(model.clone());
-    let db = match db::Database::new(&app_config.database_path) {
-        Ok(db) => Some(db),
-        Err(e) => {
-            warn!("Failed to open database for session saving: {}", e);
+            match system.query.execute(&review_query).await {
+                Ok(context) if !context.is_empty() => {
+                    debug!(
+                        "RAG retrieved {} bytes of context for review",
+                        context.len()
+                    );
+                    Some(context)
+                }
+                Ok(_) => {
+                    debug!("RAG returned empty context");
+                    None
+                }
+                Err(e) => {
+                    warn!("RAG query failed: {}", e);
+                    status!("🦑: RAG query failed, continuing without RAG context");
+                    None
+                }
+            }
+        } else {
             None
+        };
+
+        let mut session = ChatSession::new();
+        session.set_agent(self.agent_id.to_string());
+        let names: Vec<&str> = files.iter().map(|(name, _)| *name).collect();
+        session.title = Some(crate::text::truncate(
+            &format!("Review: {}", names.join(", ")),
+            100,
+        ));
+        let use_cache = self.options.cache_flag || self.app_config.cache.enabled;
+
+        let mut results = Vec::new();
+        let turns = std::iter::once((question.as_str(), files, rag_context.as_deref())).chain(
+            self.options
+                .follow_ups
+                .iter()
+                .map(|follow_up| (follow_up.as_str(), &[][..], None)),
+        );
+        for (turn, (question, files, context)) in turns.enumerate() {
+            let params = LlmQueryParams {
+                question,
+                files,
+                auto_included: &[],
+                context,
+                system_prompt: Some(&combined_review_prompt),
+                model: self.model,
+                app_config: self.app_config,
+                session: Some(&mut session),
+                db: self.db,
+                use_cache,
+                tool_choice: ToolChoice::Auto,
+            };
+
+            if self.options.json_output {
+                results.push(ask_llm(params).await.map_err(json_error)?);
+                continue;
+            }
+
+            if turn > 0 {
+                println!("\n## {}", question);
+            }
+            if self.options.no_stream {
+                print_ask_result(ask_llm(params).await, false)?;
+            } else if let Err(e) = ask_llm_streaming(params).await {
+                error!("Failed to get review: {}", e);
+                return Err(format!("Failed to get a review - {}", e).into());
+            }
         }
-    };
 
-    if no_stream {
-        match ask_llm(LlmQueryParams {
-            question: &question,
-            file_content: Some(&enhanced_content),
-            file_path: file.to_str(),
-            system_prompt: Some(&combined_review_prompt),
-            model: &model,
-            app_config,
-            session: Some(&mut session),
-            db: db.as_ref(),
-        })
-        .await
-        {
-            Ok(response) => println!("\n🦑: {}", response),
-            Err(e) => error!("Failed to get review: {}", e),
+        if self.options.json_output {
+            let mut results = results.into_iter();
+            return Ok(results.next().map(|review| ReviewResult {
+                review,
+                follow_ups: results.collect(),
+            }));
         }
-    } else if let Err(e) = ask_llm_streaming(LlmQueryParams {
-        question: &question,
-        file_content: Some(&enhanced_content),
-        file_path: file.to_str(),
-        system_prompt: Some(&combined_review_prompt),
-        model: &model,
-        app_config,
-        session: Some(&mut session),
-        db: db.as_ref(),
-    })
-    .await
-    {
-        error!("Failed to get review: {}", e);
+
+        if self.db.is_some() {
+            status!("💾 Session saved: {}", session.id);
+            status!(
+                "   Continue with: squid ask --session {} \"<question>\"",
+                session.id
+            );
+        }
+        Ok(None)
     }
+}
 
-    println!("💾 Session saved");
+/// The language-specific review prompt when all files share one, otherwise the generic prompt
+fn review_prompt_for_files(paths: &[PathBuf]) -> &'static str {
+    let mut prompts = paths.iter().map(|path| get_review_prompt_for_file(path));
+    let first = prompts.next().unwrap_or(CODE_REVIEW_PROMPT);
+    if prompts.all(|prompt| prompt == first) {
+        first
+    } else {
+        CODE_REVIEW_PROMPT
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_number_lines() {
+        assert_eq!(number_lines("fn main() {}\n"), "1 | fn main() {}\n");
+        // A final line without newline is numbered and left without one
+        assert_eq!(number_lines("a\nb"), "1 | a\n2 | b");
+        // CRLF endings are kept and not counted as extra lines
+        assert_eq!(number_lines("a\r\n\r\nb\r\n"), "1 | a\r\n2 | \r\n3 | b\r\n");
+        assert_eq!(number_lines(""), "");
+
+        // Numbers are right-aligned to the widest one
+        let numbered = number_lines(&"x\n".repeat(10));
+        assert!(numbered.starts_with(" 1 | x\n 2 | x\n"));
+        assert!(numbered.ends_with("10 | x\n"));
+    }
+
     #[test]
     fn test_strip_reasoning_blocks_single() {
         let content = "Hello <think>internal reasoning here</think> world!";
@@ -1405,4 +2197,475 @@ mod tests {
         let result = strip_reasoning_blocks(content);
         assert_eq!(result, "Text before\n\nText after");
     }
+
+    #[tokio::test]
+    async fn test_ask_llm_result_reports_tool_calls_and_usage() {
+        let tool_call_response = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "tool_calls",
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": { "name": "read_file", "arguments": "{\"path\":\"Cargo.toml\"}" }
+                    }]
+                }
+            }],
+            "usage": { "prompt_tokens": 50, "completion_tokens": 5, "total_tokens": 55 }
+        });
+        // No usage in the follow-up response, so it gets estimated
+        let answer_response = serde_json::json!({
+            "id": "chatcmpl-2",
+            "object": "chat.completion",
+            "created": 1700000001,
+            "model": "test-model",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": { "role": "assistant", "content": "The crate is squid." }
+            }]
+        });
+        let (url, requests) = crate::providers::test_support::serve_sequence(
+            "application/json",
+            vec![tool_call_response.to_string(), answer_response.to_string()],
+        )
+        .await;
+
+        let app_config = config::Config {
+            api_url: url,
+            ..Default::default()
+        };
+        let result = ask_llm(LlmQueryParams {
+            question: "What is this crate called?",
+            files: &[],
+            auto_included: &[],
+            context: None,
+            system_prompt: None,
+            model: "test-model",
+            app_config: &app_config,
+            session: None,
+            db: None,
+            use_cache: false,
+            tool_choice: ToolChoice::Auto,
+        })
+        .await
+        .unwrap();
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["answer"], "The crate is squid.");
+        assert_eq!(json["model"], "test-model");
+        assert_eq!(json["cached"], false);
+        assert!(json["elapsed_ms"].is_u64());
+        assert!(json.get("session_id").is_none());
+
+        let tool_calls = json["tool_calls"].as_array().unwrap();
+        assert_eq!(tool_calls.len(), 1);
+        assert_eq!(tool_calls[0]["id"], "call_1");
+        assert_eq!(tool_calls[0]["name"], "read_file");
+        assert_eq!(tool_calls[0]["arguments"]["path"], "Cargo.toml");
+        assert!(tool_calls[0]["result"].is_object());
+
+        let usage = &json["usage"];
+        assert!(usage["input_tokens"].as_i64().unwrap() > 50);
+        assert!(usage["output_tokens"].as_i64().unwrap() > 5);
+        assert_eq!(usage["estimated"], true);
+
+        // The tool result was sent back with the follow-up request
+        let requests = requests.await.unwrap();
+        assert!(requests[1].contains("\"tool_call_id\":\"call_1\""));
+    }
+
+    #[tokio::test]
+    async fn test_ask_llm_omits_tools_for_unsupported_model() {
+        let response = serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 1700000000,
+            "model": "phi-2",
+            "choices": [{
+                "index": 0,
+                "finish_reason": "stop",
+                "message": { "role": "assistant", "content": "Hello." }
+            }]
+        });
+        let (url, request) =
+            crate::providers::test_support::serve_once("application/json", response.to_string())
+                .await;
+
+        let app_config = config::Config {
+            api_url: url,
+            ..Default::default()
+        };
+        let result = ask_llm(LlmQueryParams {
+            question: "Hi",
+            files: &[("main.rs", "fn main() {}")],
+            auto_included: &[],
+            context: None,
+            system_prompt: None,
+            model: "phi-2",
+            app_config: &app_config,
+            session: None,
+            db: None,
+            use_cache: false,
+            tool_choice: ToolChoice::Auto,
+        })
+        .await
+        .unwrap();
+        assert_eq!(result.answer, "Hello.");
+
+        let body: serde_json::Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert!(body.get("tools").is_none());
+        let system = body["messages"][0]["content"].as_str().unwrap();
+        assert!(system.contains("Tools are not available with this model"));
+        // Attached files are sent as usual
+        let user = body["messages"][1]["content"].as_str().unwrap();
+        assert!(user.contains("fn main() {}"));
+    }
+
+    fn cache_test_params<'a>(
+        app_config: &'a config::Config,
+        database: &'a db::Database,
+    ) -> LlmQueryParams<'a> {
+        LlmQueryParams {
+            question: "Please review this code.",
+            files: &[],
+            auto_included: &[],
+            context: None,
+            system_prompt: None,
+            model: "test-model",
+            app_config,
+            session: None,
+            db: Some(database),
+            use_cache: true,
+            tool_choice: ToolChoice::Auto,
+        }
+    }
+
+    #[test]
+    fn test_compose_user_message_fences_each_file() {
+        let message = compose_user_message(
+            "How do these fit together?",
+            &[
+                ("src/lib.h", "int add(int a, int b);"),
+                ("src/lib.c", "int add(int a, int b) { return a + b; }"),
+            ],
+        );
+
+        let header = message.find("Here is the content of 'src/lib.h'").unwrap();
+        let source = message.find("Here is the content of 'src/lib.c'").unwrap();
+        let query = message
+            .find("User query: How do these fit together?")
+            .unwrap();
+        assert!(header < source && source < query);
+        assert!(message.contains("```\nint add(int a, int b);\n```"));
+        assert_eq!(message.matches("source=\"file:src/").count(), 2);
+
+        // Without files only the query is sent
+        assert_eq!(compose_user_message("Hi", &[]), "User query: Hi");
+    }
+
+    #[test]
+    fn test_file_context_fences_by_language() {
+        let rust = file_context("src/main.rs", "fn main() {}");
+        assert!(rust.contains("```rust\nfn main() {}\n```"));
+
+        let python = file_context("tools/run.py", "print('hi')");
+        assert!(python.contains("```python\nprint('hi')\n```"));
+
+        // Code blocks inside markdown don't close the outer fence
+        let readme = "# Usage\n\n```bash\ncargo run\n```";
+        let markdown = file_context("README.md", readme);
+        assert!(markdown.contains(&format!("````markdown\n{}\n````", readme)));
+        let nested = file_context("docs/fences.md", "`````\nquoted\n`````");
+        assert!(nested.contains("``````markdown\n`````"));
+
+        // Files without an extension are recognized by their shebang
+        let script = file_context("bin/deploy", "#!/usr/bin/env python3\nimport os");
+        assert!(script.contains("```python\n#!/usr/bin/env python3"));
+        assert!(file_context("bin/setup", "#!/bin/bash -e\nset -u").contains("```shell\n"));
+        assert!(file_context("LICENSE", "MIT License").contains("```\nMIT License\n```"));
+
+        // Both the CLI and the Web UI compose attachments with this helper
+        let message = compose_user_message("Explain", &[("src/main.rs", "fn main() {}")]);
+        assert!(message.starts_with(&rust));
+    }
+
+    #[test]
+    fn test_check_file_budget_lists_files_to_drop() {
+        let big = "word ".repeat(4000);
+        let medium = "word ".repeat(1000);
+        let files = [
+            ("small.rs", "fn a() {}"),
+            ("big.rs", big.as_str()),
+            ("medium.rs", medium.as_str()),
+        ];
+
+        assert!(check_file_budget("gpt-4", &files, 100_000).is_ok());
+
+        let error = check_file_budget("gpt-4", &files, 2_000).unwrap_err();
+        assert!(error.contains("big.rs (~"));
+        assert!(error.contains("Drop big.rs to fit."));
+
+        let error = check_file_budget("gpt-4", &files, 500).unwrap_err();
+        assert!(error.contains("Drop big.rs, medium.rs to fit."));
+
+        let error = check_file_budget("gpt-4", &files[1..2], 500).unwrap_err();
+        assert!(error.contains("larger context window"));
+    }
+
+    #[test]
+    fn test_review_prompt_for_mixed_files_is_generic() {
+        let rust = [PathBuf::from("a.rs"), PathBuf::from("b.rs")];
+        assert_eq!(review_prompt_for_files(&rust), CODE_REVIEW_RUST_PROMPT);
+
+        let mixed = [PathBuf::from("a.rs"), PathBuf::from("b.py")];
+        assert_eq!(review_prompt_for_files(&mixed), CODE_REVIEW_PROMPT);
+    }
+
+    #[test]
+    fn test_response_cache_hit_on_identical_request() {
+        let app_config = config::Config::default();
+        let database = db::Database::new(":memory:").unwrap();
+        let params = cache_test_params(&app_config, &database);
+
+        let user_message =
+            compose_user_message("Please review this code.", &[("main.rs", "fn main() {}")]);
+        let key = response_cache_key("test-model", "system", &user_message);
+        assert!(lookup_cached_response(&params, &key).is_none());
+
+        store_cached_response(
+            &params,
+            &key,
+            &db::CachedResponse {
+                response: "Looks good".to_string(),
+                input_tokens: 100,
+                output_tokens: 20,
+                reasoning_tokens: 0,
+                cache_tokens: 0,
+            },
+        );
+
+        // Second identical invocation produces the same key and hits the cache
+        let same_message =
+            compose_user_message("Please review this code.", &[("main.rs", "fn main() {}")]);
+        let same_key = response_cache_key("test-model", "system", &same_message);
+        assert_eq!(key, same_key);
+        let cached = lookup_cached_response(&params, &same_key).unwrap();
+        assert_eq!(cached.response, "Looks good");
+        assert_eq!(cached.input_tokens, 100);
+    }
+
+    #[test]
+    fn test_response_cache_miss_on_changed_file() {
+        let app_config = config::Config::default();
+        let database = db::Database::new(":memory:").unwrap();
+        let params = cache_test_params(&app_config, &database);
+
+        let original =
+            compose_user_message("Please review this code.", &[("main.rs", "fn main() {}")]);
+        let key = response_cache_key("test-model", "system", &original);
+        store_cached_response(
+            &params,
+            &key,
+            &db::CachedResponse {
+                response: "Looks good".to_string(),
+                input_tokens: 100,
+                output_tokens: 20,
+                reasoning_tokens: 0,
+                cache_tokens: 0,
+            },
+        );
+
+        let changed = compose_user_message(
+            "Please review this code.",
+            &[("main.rs", "fn main() { panic!() }")],
+        );
+        let changed_key = response_cache_key("test-model", "system", &changed);
+        assert_ne!(key, changed_key);
+        assert!(lookup_cached_response(&params, &changed_key).is_none());
+
+        // A different model also misses
+        let other_model_key = response_cache_key("other-model", "system", &original);
+        assert!(lookup_cached_response(&params, &other_model_key).is_none());
+    }
+
+    fn session_with_messages(database: &db::Database, messages: &[(&str, &str)]) -> ChatSession {
+        let session = ChatSession::new();
+        database.save_session(&session).unwrap();
+        for (role, content) in messages {
+            database
+                .save_message(
+                    &session.id,
+                    &ChatMessage {
+                        id: None,
+                        parent_message_id: None,
+                        role: role.to_string(),
+                        content: content.to_string(),
+                        sources: vec![],
+                        timestamp: chrono::Utc::now().timestamp(),
+                        thinking_steps: None,
+                        metadata: None,
+                        finish_reason: None,
+                    },
+                )
+                .unwrap();
+        }
+        session
+    }
+
+    #[test]
+    fn test_load_session_to_continue() {
+        let database = db::Database::new(":memory:").unwrap();
+        assert!(load_session_to_continue(&database, None).is_err());
+
+        let session = session_with_messages(
+            &database,
+            &[
+                ("user", "What is 2+2?"),
+                ("assistant", "<think>easy</think>4"),
+            ],
+        );
+
+        let loaded = load_session_to_continue(&database, Some(&session.id)).unwrap();
+        assert_eq!(loaded.messages.len(), 2);
+
+        // --continue picks the most recently updated session
+        let latest = load_session_to_continue(&database, None).unwrap();
+        assert_eq!(latest.id, session.id);
+
+        let err = load_session_to_continue(&database, Some("missing")).unwrap_err();
+        assert_eq!(err, "Session 'missing' not found");
+
+        // History is replayed without reasoning blocks
+        let history = history_messages(&loaded.messages);
+        assert_eq!(history.len(), 2);
+        let json = serde_json::to_value(&history).unwrap();
+        assert_eq!(json[0]["role"], "user");
+        assert_eq!(json[1]["content"], "4");
+    }
+
+    #[tokio::test]
+    async fn test_review_follow_up_continues_the_review_session() {
+        let completion = |content: &str| {
+            serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 1700000000,
+                "model": "test-model",
+                "choices": [{
+                    "index": 0,
+                    "finish_reason": "stop",
+                    "message": { "role": "assistant", "content": content }
+                }]
+            })
+            .to_string()
+        };
+        let (url, requests) = crate::providers::test_support::serve_sequence(
+            "application/json",
+            vec![
+                completion("1. `main` never returns an error."),
+                completion("Finding 1 means errors are swallowed."),
+            ],
+        )
+        .await;
+
+        let app_config = config::Config {
+            api_url: url,
+            ..Default::default()
+        };
+        let database = db::Database::new(":memory:").unwrap();
+        let file_content = "fn main() {\n    let _ = std::fs::read(\"missing\");\n}\n";
+        let follow_ups = vec!["Explain finding 1".to_string()];
+        let options = ReviewCommandOptions {
+            message: None,
+            no_stream: true,
+            agent: None,
+            rag_flag: false,
+            no_rag_flag: true,
+            cache_flag: false,
+            separate: false,
+            json_output: true,
+            line_numbers: false,
+            follow_ups: &follow_ups,
+        };
+        let request = ReviewRequest {
+            options: &options,
+            agent_id: "general-assistant",
+            model: "test-model",
+            rag_system: None,
+            db: Some(&database),
+            app_config: &app_config,
+        };
+        let result = request
+            .run(
+                &[PathBuf::from("src/main.rs")],
+                &[("src/main.rs", file_content)],
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["answer"], "1. `main` never returns an error.");
+        assert_eq!(
+            json["follow_ups"][0]["answer"],
+            "Finding 1 means errors are swallowed."
+        );
+        assert_eq!(json["follow_ups"][0]["session_id"], json["session_id"]);
+
+        let sessions = database.list_sessions().unwrap();
+        assert_eq!(sessions.len(), 1);
+        let session = database.load_session(&sessions[0]).unwrap().unwrap();
+        assert_eq!(session.title.as_deref(), Some("Review: src/main.rs"));
+        assert_eq!(session.agent_id.as_deref(), Some("general-assistant"));
+
+        let roles: Vec<&str> = session.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant", "user", "assistant"]);
+        let review = &session.messages[0];
+        assert_eq!(review.sources.len(), 1);
+        assert_eq!(review.sources[0].title, "src/main.rs");
+        assert_eq!(review.sources[0].content, file_content);
+        assert_eq!(
+            session.messages[1].content,
+            "1. `main` never returns an error."
+        );
+        assert_eq!(session.messages[2].content, "Explain finding 1");
+        assert!(session.messages[2].sources.is_empty());
+        assert_eq!(
+            session.messages[2].parent_message_id,
+            session.messages[1].id
+        );
+        assert_eq!(
+            session.messages[3].content,
+            "Finding 1 means errors are swallowed."
+        );
+
+        // The follow-up is sent with the review prompt and the reviewed file in its history
+        let requests = requests.await.unwrap();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value = serde_json::from_str(&requests[1]).unwrap();
+        let messages = body["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 4);
+        let first: serde_json::Value = serde_json::from_str(&requests[0]).unwrap();
+        assert_eq!(messages[0], first["messages"][0]);
+        assert!(
+            messages[1]["content"]
+                .as_str()
+                .unwrap()
+                .contains(file_content)
+        );
+        assert_eq!(messages[2]["content"], "1. `main` never returns an error.");
+        assert!(
+            messages[3]["content"]
+                .as_str()
+                .unwrap()
+                .ends_with("User query: Explain finding 1")
+        );
+    }
 }
@@ -184,19 +184,189 @@ pub fn query_logs(
     Ok(logs)
 }
 
+/// Number of rows removed per DELETE statement when purging logs
+const LOG_DELETE_BATCH_SIZE: usize = 5000;
+
+/// Number of rows read per query when exporting logs
+pub const LOG_EXPORT_BATCH_SIZE: usize = 1000;
+
+/// Delete logs older than `cutoff_time` in batches so other writers are never blocked for long
+fn delete_logs_before(
+    conn: &Connection,
+    cutoff_time: i64,
+    batch_size: usize,
+) -> Result<usize, rusqlite::Error> {
+    let mut total = 0;
+    loop {
+        let deleted = conn.execute(
+            "DELETE FROM logs WHERE id IN (SELECT id FROM logs WHERE timestamp < ?1 LIMIT ?2)",
+            params![cutoff_time, batch_size as i64],
+        )?;
+        total += deleted;
+        if deleted < batch_size {
+            return Ok(total);
+        }
+    }
+}
+
 /// Clean up old logs from database
+///
+/// Rows are deleted in batches, then free pages are returned to the file system with an
+/// incremental vacuum (`Database::new` switches the database to `auto_vacuum = INCREMENTAL`).
 pub fn cleanup_old_logs(db_path: &str, max_age_seconds: i64) -> Result<usize, rusqlite::Error> {
     let conn = Connection::open(db_path)?;
+    conn.busy_timeout(std::time::Duration::from_secs(5))?;
     let cutoff_time = chrono::Utc::now().timestamp() - max_age_seconds;
 
-    let deleted = conn.execute(
-        "DELETE FROM logs WHERE timestamp < ?1",
-        params![cutoff_time],
-    )?;
+    let deleted = delete_logs_before(&conn, cutoff_time, LOG_DELETE_BATCH_SIZE)?;
+    if deleted > 0 {
+        // Each step of the pragma frees one page, so run it to completion
+        let mut stmt = conn.prepare("PRAGMA incremental_vacuum")?;
+        let mut rows = stmt.query([])?;
+        while rows.next()?.is_some() {}
+    }
 
     Ok(deleted)
 }
 
+/// Parse a log age like `14d`, `12h` or `2w` into seconds (a bare number means days)
+pub fn parse_log_age(age: &str) -> Result<i64, String> {
+    let age = age.trim();
+    let (value, unit_seconds) = match age.char_indices().last() {
+        Some((i, 'h')) => (&age[..i], 3600),
+        Some((i, 'd')) => (&age[..i], 86400),
+        Some((i, 'w')) => (&age[..i], 7 * 86400),
+        _ => (age, 86400),
+    };
+
+    value
+        .parse::<i64>()
+        .ok()
+        .filter(|v| *v >= 0)
+        .and_then(|v| v.checked_mul(unit_seconds))
+        .ok_or_else(|| {
+            format!(
+                "Invalid age '{}'. Use a number followed by h, d or w, e.g. '14d'",
+                age
+            )
+        })
+}
+
+/// Query logs with an id greater than `after_id`, oldest first
+///
+/// Used to page through the whole table without loading it into memory.
+pub fn query_logs_after(
+    db_path: &str,
+    after_id: i64,
+    limit: usize,
+    level_filter: Option<&str>,
+    session_id: Option<&str>,
+) -> Result<Vec<LogEntry>, rusqlite::Error> {
+    let conn = Connection::open(db_path)?;
+
+    let mut query = String::from(
+        "SELECT id, timestamp, level, target, message, session_id FROM logs WHERE id > ?",
+    );
+    let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(after_id)];
+
+    if let Some(level) = level_filter {
+        query.push_str(" AND level = ?");
+        params.push(Box::new(level.to_string()));
+    }
+
+    if let Some(sid) = session_id {
+        query.push_str(" AND session_id = ?");
+        params.push(Box::new(sid.to_string()));
+    }
+
+    query.push_str(" ORDER BY id ASC LIMIT ?");
+    params.push(Box::new(limit as i64));
+
+    let mut stmt = conn.prepare(&query)?;
+    let param_refs: Vec<&dyn rusqlite::ToSql> = params.iter().map(|p| p.as_ref()).collect();
+
+    stmt.query_map(param_refs.as_slice(), |row| {
+        Ok(LogEntry {
+            id: row.get(0)?,
+            timestamp: row.get(1)?,
+            level: row.get(2)?,
+            target: row.get(3)?,
+            message: row.get(4)?,
+            session_id: row.get(5)?,
+        })
+    })?
+    .collect()
+}
+
+/// Output format for log exports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogExportFormat {
+    Ndjson,
+    Csv,
+}
+
+impl LogExportFormat {
+    /// Parse a format name (`ndjson` or `csv`)
+    pub fn parse(format: &str) -> Option<Self> {
+        match format.to_lowercase().as_str() {
+            "ndjson" | "jsonl" => Some(Self::Ndjson),
+            "csv" => Some(Self::Csv),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Ndjson => "application/x-ndjson",
+            Self::Csv => "text/csv; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Ndjson => "ndjson",
+            Self::Csv => "csv",
+        }
+    }
+
+    /// Line written before the first entry, if the format has one
+    pub fn header(&self) -> Option<&'static str> {
+        match self {
+            Self::Ndjson => None,
+            Self::Csv => Some("id,timestamp,level,target,message,session_id\n"),
+        }
+    }
+
+    /// Serialize a single entry as one line (including the trailing newline)
+    pub fn format_entry(&self, entry: &LogEntry) -> String {
+        match self {
+            Self::Ndjson => {
+                let mut line = serde_json::to_string(entry).unwrap_or_else(|_| "{}".to_string());
+                line.push('\n');
+                line
+            }
+            Self::Csv => format!(
+                "{},{},{},{},{},{}\n",
+                entry.id,
+                entry.timestamp,
+                csv_field(&entry.level),
+                csv_field(&entry.target),
+                csv_field(&entry.message),
+                csv_field(entry.session_id.as_deref().unwrap_or(""))
+            ),
+        }
+    }
+}
+
+/// Quote a CSV field when it contains separators, quotes or line breaks
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
 /// Clear all logs from database
 pub fn reset_logs(db_path: &str) -> Result<usize, rusqlite::Error> {
     let conn = Connection::open(db_path)?;
@@ -207,7 +377,7 @@ pub fn reset_logs(db_path: &str) -> Result<usize, rusqlite::Error> {
 }
 
 /// A log entry from the database
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, serde::Serialize)]
 pub struct LogEntry {
     pub id: i64,
     pub timestamp: i64,
@@ -419,4 +589,131 @@ mod tests {
         // Cleanup
         let _ = std::fs::remove_file(&db_file);
     }
+
+    fn logs_table(conn: &Connection) {
+        conn.execute_batch(
+            "CREATE TABLE logs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                level TEXT NOT NULL,
+                target TEXT NOT NULL,
+                message TEXT NOT NULL,
+                session_id TEXT
+            );",
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_retention_deletes_only_old_logs_in_batches() {
+        let conn = Connection::open_in_memory().unwrap();
+        logs_table(&conn);
+        for timestamp in [100, 200, 300, 400, 500, 1000, 2000] {
+            conn.execute(
+                "INSERT INTO logs (timestamp, level, target, message) VALUES (?1, 'info', 'squid', 'm')",
+                params![timestamp],
+            )
+            .unwrap();
+        }
+
+        // Batch size smaller than the number of matching rows forces several DELETEs
+        let deleted = delete_logs_before(&conn, 1000, 2).unwrap();
+        assert_eq!(deleted, 5);
+
+        let remaining: Vec<i64> = conn
+            .prepare("SELECT timestamp FROM logs ORDER BY timestamp")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(remaining, vec![1000, 2000]);
+
+        assert_eq!(delete_logs_before(&conn, 1000, 2).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_cleanup_reclaims_free_pages() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("squid.db");
+        crate::db::Database::new(&path).unwrap();
+        let path = path.to_str().unwrap();
+
+        let conn = Connection::open(path).unwrap();
+        let message = "x".repeat(1000);
+        for _ in 0..500 {
+            conn.execute(
+                "INSERT INTO logs (timestamp, level, target, message) VALUES (1, 'info', 'squid', ?1)",
+                params![message],
+            )
+            .unwrap();
+        }
+        let pages = |conn: &Connection| -> i64 {
+            conn.query_row("PRAGMA page_count", [], |row| row.get(0))
+                .unwrap()
+        };
+        let before = pages(&conn);
+
+        assert_eq!(cleanup_old_logs(path, 60).unwrap(), 500);
+
+        let free: i64 = conn
+            .query_row("PRAGMA freelist_count", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(free, 0);
+        assert!(pages(&conn) < before);
+    }
+
+    #[test]
+    fn test_parse_log_age() {
+        assert_eq!(parse_log_age("14d"), Ok(14 * 86400));
+        assert_eq!(parse_log_age("12h"), Ok(12 * 3600));
+        assert_eq!(parse_log_age("2w"), Ok(14 * 86400));
+        assert_eq!(parse_log_age("30"), Ok(30 * 86400));
+        assert!(parse_log_age("d").is_err());
+        assert!(parse_log_age("-1d").is_err());
+        assert!(parse_log_age("soon").is_err());
+    }
+
+    fn sample_entry() -> LogEntry {
+        LogEntry {
+            id: 7,
+            timestamp: 1707654321,
+            level: "warn".to_string(),
+            target: "squid_rs::api".to_string(),
+            message: "Tool \"bash\" failed,\nretrying".to_string(),
+            session_id: None,
+        }
+    }
+
+    #[test]
+    fn test_ndjson_export_shape() {
+        let line = LogExportFormat::Ndjson.format_entry(&sample_entry());
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.matches('\n').count(), 1);
+
+        let value: serde_json::Value = serde_json::from_str(line.trim_end()).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "id": 7,
+                "timestamp": 1707654321,
+                "level": "warn",
+                "target": "squid_rs::api",
+                "message": "Tool \"bash\" failed,\nretrying",
+                "session_id": null
+            })
+        );
+        assert!(LogExportFormat::Ndjson.header().is_none());
+    }
+
+    #[test]
+    fn test_csv_export_escapes_fields() {
+        let row = LogExportFormat::Csv.format_entry(&sample_entry());
+        assert_eq!(
+            row,
+            "7,1707654321,warn,squid_rs::api,\"Tool \"\"bash\"\" failed,\nretrying\",\n"
+        );
+        assert_eq!(LogExportFormat::parse("CSV"), Some(LogExportFormat::Csv));
+        assert_eq!(LogExportFormat::parse("xml"), None);
+    }
 }
@@ -1,30 +1,63 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use dotenvy::dotenv;
 use log::error;
 use std::path::PathBuf;
+use std::process::ExitCode;
 use std::sync::Arc;
 use tabled::{Table, Tabled};
 
+use crate::error::SquidError;
+use crate::output::{CommandError, CommandResult, status};
+
 mod agent;
 mod api;
 mod audio;
+mod auto_include;
 mod bundled;
+mod chat_turn;
+mod compact;
 mod config;
 mod db;
+mod db_repair;
 mod doctor;
+mod envinfo;
+mod error;
+mod history;
+mod idempotency;
+mod import;
 mod init;
+mod instructions;
 mod jobs;
 mod jobs_api;
 mod llm;
 mod logger;
+mod models;
+mod offload;
+mod output;
+mod patch;
 mod plugins;
+mod policy;
+mod providers;
 mod rag;
+mod rate_limit;
+mod replay;
+mod safe_write;
+mod sandbox;
+mod sanitize;
+mod schedules;
+mod secrets;
 mod server;
 mod session;
+mod session_templates;
+mod shutdown;
+mod stream_events;
 mod template;
+mod text;
 mod tokens;
 mod tools;
+mod trace;
 mod validate;
+mod webhooks;
 mod workspace;
 
 #[derive(Parser)]
@@ -34,6 +67,18 @@ mod workspace;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Only print the answer and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Show debug logs for this invocation (overrides log_level)
+    #[arg(short, long, global = true)]
+    verbose: bool,
+    /// Don't add SQUID.md or AGENTS.md from the project root to system prompts
+    #[arg(long, global = true)]
+    no_project_instructions: bool,
+    /// Only contact the configured provider, and fail fast when it's unreachable
+    #[arg(long, global = true)]
+    offline: bool,
 }
 
 #[derive(Subcommand)]
@@ -49,9 +94,26 @@ enum Commands {
         /// API Key (skips interactive prompt if provided)
         #[arg(long)]
         key: Option<String>,
+        /// Model for the default agents (skips model discovery if provided)
+        #[arg(long)]
+        model: Option<String>,
         /// Log Level (skips interactive prompt if provided)
         #[arg(long)]
         log_level: Option<String>,
+        /// Config file format for a new project (defaults to json)
+        #[arg(long, value_enum)]
+        format: Option<config::ConfigFormat>,
+        /// Write the global config used outside projects instead of a project config
+        #[arg(long, conflicts_with = "dir")]
+        global: bool,
+        /// Leave an existing .squidignore as it is instead of adding new default patterns
+        #[arg(long)]
+        no_ignore_merge: bool,
+    },
+    /// Manage project configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
     },
     /// Ask a question to the LLM
     Ask {
@@ -63,9 +125,9 @@ enum Commands {
         /// Disable streaming (return complete response at once)
         #[arg(long)]
         no_stream: bool,
-        /// Optional file to provide context
+        /// File to provide context (repeat for several files)
         #[arg(short, long)]
-        file: Option<PathBuf>,
+        file: Vec<PathBuf>,
         /// Optional custom system prompt file
         #[arg(short, long)]
         prompt: Option<PathBuf>,
@@ -78,11 +140,33 @@ enum Commands {
         /// Disable RAG (overrides config setting)
         #[arg(long, conflicts_with = "rag")]
         no_rag: bool,
+        /// Reuse cached responses for identical requests (overrides config setting)
+        #[arg(long)]
+        cache: bool,
+        /// Continue a saved session by ID
+        #[arg(long)]
+        session: Option<String>,
+        /// Continue the most recently updated session
+        #[arg(long = "continue", conflicts_with = "session")]
+        continue_session: bool,
+        /// Output format; `json` prints the answer, tool calls and token usage (implies --no-stream)
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+        /// Answer without tools (no tool schemas are sent)
+        #[arg(long)]
+        no_tools: bool,
+        /// Make the model call this tool first (e.g. read_file)
+        #[arg(long, value_name = "NAME", conflicts_with = "no_tools")]
+        tool: Option<String>,
+        /// Don't attach the files listed in context.auto_include to a new session
+        #[arg(long)]
+        no_auto_context: bool,
     },
     /// Review code from a file
     Review {
-        /// Path to the file to review
-        file: PathBuf,
+        /// Paths of the files to review
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
         /// Optional additional message or specific question about the code
         #[arg(short, long)]
         message: Option<String>,
@@ -98,6 +182,25 @@ enum Commands {
         /// Disable RAG (overrides config setting)
         #[arg(long, conflicts_with = "rag")]
         no_rag: bool,
+        /// Reuse cached responses for identical requests (overrides config setting)
+        #[arg(long)]
+        cache: bool,
+        /// Review each file separately instead of in one combined review
+        #[arg(long)]
+        separate: bool,
+        /// Number the lines of each file so findings cite exact lines
+        #[arg(long, default_value_t = true, action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "true", value_name = "BOOL")]
+        line_numbers: bool,
+        /// Ask a question about the review in the same session (repeat for several)
+        #[arg(
+            long = "follow-up",
+            value_name = "QUESTION",
+            conflicts_with = "separate"
+        )]
+        follow_up: Vec<String>,
+        /// Output format; `json` prints the answer, tool calls and token usage (implies --no-stream)
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
     },
     /// Start a web server for the Squid Web UI
     Serve {
@@ -110,6 +213,15 @@ enum Commands {
         /// Custom working directory for the server
         #[arg(long)]
         dir: Option<PathBuf>,
+        /// Serve the Web UI from this directory instead of the embedded build
+        #[arg(long)]
+        static_dir: Option<PathBuf>,
+        /// Answer chats from a JSON scenario file instead of the model, for frontend development
+        #[arg(long, value_name = "SCENARIO")]
+        mock_llm: Option<PathBuf>,
+        /// If the database is damaged, move it aside and copy what can still be read into a new one
+        #[arg(long)]
+        repair: bool,
     },
     /// View application logs from the database
     Logs {
@@ -126,12 +238,86 @@ enum Commands {
         #[command(subcommand)]
         command: JobCommands,
     },
+    /// Start a session from a conversation template, to continue in the web UI
+    New {
+        /// Name of a template under `templates` in the config
+        #[arg(long)]
+        template: String,
+        /// Value of a placeholder in the template's first message, as NAME=VALUE (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = session_templates::parse_var)]
+        vars: Vec<(String, String)>,
+    },
+    /// Manage saved chat sessions
+    Sessions {
+        #[command(subcommand)]
+        command: SessionCommands,
+    },
+    /// Manage the response cache for ask/review
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+    /// Show usage statistics
+    Stats {
+        #[command(subcommand)]
+        command: StatsCommands,
+    },
+    /// Inspect the session database
+    Db {
+        #[command(subcommand)]
+        command: DbCommands,
+    },
+    /// Explain whether tools may access a path, and which rule decided
+    CheckPath {
+        /// Path to check, relative to the current directory or absolute
+        path: PathBuf,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Clean up bundled assets extracted from the binary
     Cleanup,
     /// Run diagnostic checks to verify configuration and setup
     Doctor,
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Store the API key in .env (or the OS keychain) instead of squid.config.json
+    SetKey {
+        /// API key (prompted without echo if omitted)
+        key: Option<String>,
+        /// Store the key in the OS keychain (requires the `keyring` build feature)
+        #[arg(long)]
+        keychain: bool,
+    },
+    /// Print a setting (e.g. `rag.chunk_size`), or the whole config, with secrets redacted
+    Get {
+        /// Dot-separated key; `permissions.allow` is the default agent's saved allow list
+        key: Option<String>,
+    },
+    /// Change a setting in the config file; `key+=value` adds an entry to a list
+    Set {
+        /// Dot-separated key, or `key+=value` to append (e.g. `permissions.allow+=bash:ls`)
+        key: String,
+        /// New value, parsed as JSON unless the setting is a string
+        value: Option<String>,
+    },
+    /// Remove a setting from the config file so its default applies
+    Unset {
+        /// Dot-separated key
+        key: String,
+    },
+    /// Print the path of the config file in use
+    Path,
+}
+
 #[derive(Subcommand)]
 enum LogCommands {
     /// Display logs from the database
@@ -148,11 +334,14 @@ enum LogCommands {
     },
     /// Clear all logs from the database
     Reset,
-    /// Remove logs older than a specified number of days
+    /// Remove logs older than a given age (e.g. `--older-than 14d` or `--max-age-days 7`)
     Cleanup {
         /// Maximum age of logs to keep (in days)
-        #[arg(short, long, default_value = "30")]
-        max_age_days: u32,
+        #[arg(short, long, conflicts_with = "older_than")]
+        max_age_days: Option<u32>,
+        /// Maximum age of logs to keep: a number followed by h, d or w (e.g. 12h, 14d, 2w)
+        #[arg(long)]
+        older_than: Option<String>,
     },
 }
 
@@ -163,17 +352,134 @@ enum RagCommands {
         /// Custom documents directory (defaults to ./documents)
         #[arg(long)]
         dir: Option<PathBuf>,
+        /// Print the indexing statistics as JSON
+        #[arg(long)]
+        json: bool,
+        /// Exit with an error if any file failed to index
+        #[arg(long)]
+        strict: bool,
     },
     /// List indexed documents
-    List,
+    List {
+        /// Print the documents as JSON
+        #[arg(long)]
+        json: bool,
+    },
     /// Rebuild the entire RAG index
     Rebuild {
         /// Custom documents directory (defaults to ./documents)
         #[arg(long)]
         dir: Option<PathBuf>,
+        /// Print the indexing statistics as JSON
+        #[arg(long)]
+        json: bool,
+        /// Exit with an error if any file failed to index
+        #[arg(long)]
+        strict: bool,
     },
     /// Show RAG statistics
-    Stats,
+    Stats {
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+impl RagCommands {
+    /// Whether the result should be printed as JSON
+    fn json(&self) -> bool {
+        match self {
+            RagCommands::Init { json, .. }
+            | RagCommands::List { json }
+            | RagCommands::Rebuild { json, .. }
+            | RagCommands::Stats { json } => *json,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum SessionCommands {
+    /// Delete sessions not updated for a given age (e.g. `--older-than 30d`)
+    Prune {
+        /// Minimum age of sessions to delete: a number followed by h, d or w (e.g. 12h, 30d, 2w)
+        #[arg(long, default_value = "30d")]
+        older_than: String,
+        /// List the sessions that would be deleted without deleting them
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip confirmation prompt
+        #[arg(short = 'y', long)]
+        yes: bool,
+    },
+    /// Delete sessions by id or by filter; filters combine, and a session must match all of them
+    Rm {
+        /// Session IDs to delete
+        ids: Vec<String>,
+        /// Only sessions not updated for this long: a number followed by h, d or w (e.g. 30d)
+        #[arg(long)]
+        older_than: Option<String>,
+        /// Only sessions whose title contains this text
+        #[arg(long)]
+        title_contains: Option<String>,
+        /// Only sessions without a title
+        #[arg(long)]
+        untitled: bool,
+        /// Only sessions with at most this many messages
+        #[arg(long)]
+        max_messages: Option<i64>,
+        /// Delete every session when no ids or filters are given
+        #[arg(long)]
+        all: bool,
+        /// List the sessions that would be deleted without deleting them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Show a session's details and which model, provider and squid version wrote each answer
+    Show {
+        /// Session ID
+        id: String,
+    },
+    /// Summarize all but the latest messages of a session and delete them permanently
+    Compact {
+        /// Session ID
+        id: String,
+        /// Number of most recent messages to keep verbatim
+        #[arg(long, default_value_t = compact::DEFAULT_KEEP)]
+        keep: usize,
+    },
+    /// Import conversations from a ChatGPT, Claude or squid export
+    Import {
+        /// Export format of the file
+        #[arg(long, value_enum)]
+        format: import::ImportFormat,
+        /// Path to the export (e.g. `conversations.json`)
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Remove all cached responses
+    Clear,
+}
+
+#[derive(Subcommand)]
+enum StatsCommands {
+    /// How often each tool was called, failed or denied, with bash broken down by command
+    Tools {
+        /// How many days back to count
+        #[arg(long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
+        days: u32,
+        /// Print the statistics as JSON
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommands {
+    /// Show applied migrations, missing columns, and the latest pre-migration backup
+    Check,
 }
 
 #[derive(Subcommand)]
@@ -252,7 +558,7 @@ fn check_config_or_suggest_init() -> bool {
     if !config::Config::config_file_exists() {
         // Check if essential environment variables are set
         if std::env::var("API_URL").is_ok() {
-            eprintln!("ℹ️  Using configuration from environment variables (.env file)");
+            status!("ℹ️  Using configuration from environment variables (.env file)");
             return true;
         }
 
@@ -270,11 +576,160 @@ fn check_config_or_suggest_init() -> bool {
     true
 }
 
+/// Report a failed `rag` command; with `--json` the error is printed as `{"error": ...}` on stdout
+fn rag_error(json: bool, message: String) -> CommandError {
+    if json {
+        println!("{}", serde_json::json!({ "error": message }));
+        CommandError::Reported
+    } else {
+        message.into()
+    }
+}
+
+/// Print a session's metadata and the origin of each answer, without the transcript
+fn print_session_details(session: &session::ChatSession) {
+    let time = |timestamp: i64| {
+        chrono::DateTime::from_timestamp(timestamp, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default()
+    };
+
+    println!("🦑: Session Details\n");
+    println!("  ID: {}", session.id);
+    println!(
+        "  Title: {}",
+        session.title.as_deref().unwrap_or("(untitled)")
+    );
+    if let Some(agent_id) = &session.agent_id {
+        println!("  Agent: {}", agent_id);
+    }
+    if !session.models_used.is_empty() {
+        println!("  Models: {}", session.models_used.join(", "));
+    }
+    if let Some(model) = &session.locked_model {
+        println!("  Locked to model: {}", model);
+    }
+    println!("  Created: {}", time(session.created_at));
+    println!("  Updated: {}", time(session.updated_at));
+    if let Some(workspace) = &session.workspace {
+        println!("  Workspace: {}", workspace);
+    }
+    if let Some(dir) = &session.working_dir {
+        println!("  Working directory: {}", dir);
+    }
+    if let Some(source) = session
+        .metadata
+        .as_ref()
+        .and_then(|metadata| metadata.imported_from.as_ref())
+    {
+        println!(
+            "  Imported from: {} ({})",
+            source.format, source.conversation_id
+        );
+    }
+    let answers: Vec<&session::ChatMessage> = session
+        .messages
+        .iter()
+        .filter(|message| message.role == "assistant")
+        .collect();
+    println!(
+        "  Messages: {} ({} answers)",
+        session.messages.len(),
+        answers.len()
+    );
+    println!(
+        "  Tokens: {} ({} in, {} out)",
+        session.token_usage.total_tokens,
+        session.token_usage.input_tokens,
+        session.token_usage.output_tokens
+    );
+    println!("  Cost: ${:.4}", session.cost_usd);
+
+    if answers.is_empty() {
+        return;
+    }
+    // Answers saved before this was recorded show dashes
+    println!("\n  Answers:");
+    for (index, answer) in answers.iter().enumerate() {
+        let metadata = answer.metadata.clone().unwrap_or_default();
+        println!(
+            "    {:>3}. {}  model: {}  provider: {}  squid: {}",
+            index + 1,
+            time(answer.timestamp),
+            metadata.model.as_deref().unwrap_or("-"),
+            metadata.provider_host.as_deref().unwrap_or("-"),
+            metadata.squid_version.as_deref().unwrap_or("-")
+        );
+    }
+}
+
+/// Print tool statistics as an aligned table, most used first
+fn print_tool_stats(rows: &[db::ToolStatsRow]) {
+    let width = rows
+        .iter()
+        .map(|row| row.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!(
+        "  {:<width$}  {:>6}  {:>6}  {:>6}  {:>6}  {:>10}",
+        "Name", "Calls", "OK", "Errors", "Denied", "Avg result"
+    );
+    for row in rows {
+        println!(
+            "  {:<width$}  {:>6}  {:>6}  {:>6}  {:>6}  {:>10}",
+            row.name,
+            row.invocations,
+            row.successes,
+            row.errors,
+            row.denied,
+            format!("{:.0} B", row.avg_result_bytes)
+        );
+    }
+}
+
+/// Print the result of `rag init`/`rag rebuild`; with `strict`, failed files fail the command
+fn print_index_stats(
+    stats: &rag::IndexStats,
+    heading: &str,
+    json: bool,
+    strict: bool,
+) -> CommandResult {
+    if json {
+        output::print_json(stats)?;
+    } else {
+        println!("🦑: {}", heading);
+        println!("    Files found: {}", stats.files_found);
+        println!("    Files processed: {}", stats.files_processed);
+        if stats.files_failed > 0 {
+            println!("    Files failed: {}", stats.files_failed);
+        }
+        println!("    Total chunks: {}", stats.total_chunks);
+        println!("    Total embeddings: {}", stats.total_embeddings);
+    }
+
+    if strict && stats.files_failed > 0 {
+        // The JSON result already reports the failures
+        if json {
+            return Err(CommandError::Reported);
+        }
+        return Err(format!("{} file(s) failed to index", stats.files_failed).into());
+    }
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
     dotenv().ok();
+    // Outside a project, the API key may live next to the global config
+    if config::Config::find_config_file().is_none()
+        && let Some(dir) = config::Config::global_config_dir()
+    {
+        dotenvy::from_path(dir.join(".env")).ok();
+    }
 
     let cli = Cli::parse();
+    output::set_quiet(cli.quiet);
 
     // Load config early to initialize logger with correct log level
     // For init command, we'll use defaults since config doesn't exist yet
@@ -283,6 +738,21 @@ async fn main() {
     } else {
         config::Config::load()
     };
+    if cli.no_project_instructions {
+        app_config.project_instructions.enabled = false;
+    }
+    if cli.offline {
+        app_config.network.offline = true;
+    }
+
+    // --verbose and --quiet override the configured console log level for this invocation
+    let log_level = if cli.verbose {
+        "debug".to_string()
+    } else if cli.quiet {
+        "error".to_string()
+    } else {
+        app_config.log_level.clone()
+    };
 
     // Initialize logger with database support only for serve command
     // Other commands use stdout-only logging
@@ -305,30 +775,160 @@ async fn main() {
             }
         };
 
-        logger::init_with_db(
-            Some(&app_config.log_level),
-            Some(db_path_buf),
-            Some(db_level),
-        );
+        logger::init_with_db(Some(&log_level), Some(db_path_buf), Some(db_level));
 
         // Load agents from files after logger is initialized (so logs are visible)
         app_config.load_agents();
     } else {
-        logger::init(Some(&app_config.log_level));
+        logger::init(Some(&log_level));
 
         // Load agents for non-serve commands as well
         app_config.load_agents();
     }
+    models::load_metadata(&app_config);
+
+    match run(&cli, &app_config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(CommandError::Reported) => ExitCode::FAILURE,
+        Err(failure) => {
+            eprintln!("🦑: {}", failure);
+            match &failure {
+                CommandError::Failed { error, .. } => {
+                    if let Some(hint) = error_hint(error) {
+                        eprintln!("   {}", hint);
+                    }
+                    error_exit_code(error)
+                }
+                _ => ExitCode::FAILURE,
+            }
+        }
+    }
+}
 
+/// Exit code for a typed error, following the BSD `sysexits.h` conventions
+fn error_exit_code(error: &SquidError) -> ExitCode {
+    let code = match error {
+        SquidError::Config(_) => 78,
+        SquidError::Provider { .. } => 69,
+        SquidError::ToolDenied(_) | SquidError::PathValidation(_) => 77,
+        SquidError::SessionNotFound(_) => 66,
+        SquidError::ModelLocked { .. } => 65,
+        SquidError::SessionBusy(_) => 75,
+        SquidError::Database(_) | SquidError::Io(_) => 74,
+        SquidError::Rag(_) | SquidError::Serialization(_) | SquidError::Other(_) => 1,
+    };
+    ExitCode::from(code)
+}
+
+/// What the user can do about a typed error, if there is something obvious
+fn error_hint(error: &SquidError) -> Option<&'static str> {
+    match error {
+        SquidError::Config(_) => Some("Run `squid doctor` to check your configuration."),
+        SquidError::Provider { .. } if error.is_retriable() => {
+            Some("The provider is unreachable or busy; try again shortly.")
+        }
+        SquidError::Provider { .. } => {
+            Some("Check `api_url`, the model name and your API key with `squid doctor`.")
+        }
+        SquidError::SessionBusy(_) => Some("Wait for the running turn or compaction to finish."),
+        SquidError::Database(_) if error.is_retriable() => {
+            Some("Another squid process is using the database; try again shortly.")
+        }
+        SquidError::Database(_) => Some("Check `database_path` and that the file is writable."),
+        _ => None,
+    }
+}
+
+/// Run the selected command
+async fn run(cli: &Cli, app_config: &config::Config) -> CommandResult {
     match &cli.command {
         Commands::Init {
             dir,
             url,
             key: api_key,
+            model,
             log_level,
+            format,
+            global,
+            no_ignore_merge,
         } => {
-            init::run(dir, url, api_key, log_level).await;
+            let dir = if *global {
+                config::Config::global_config_dir()
+                    .ok_or("Could not determine the global config directory")?
+            } else {
+                dir.clone()
+            };
+            let options = init::InitOptions {
+                url: url.clone(),
+                api_key: api_key.clone(),
+                model: model.clone(),
+                log_level: log_level.clone(),
+                format: *format,
+                no_ignore_merge: *no_ignore_merge,
+            };
+            init::run(&dir, *global, &options).await?;
         }
+        Commands::Config { command } => match command {
+            ConfigCommands::SetKey { key, keychain } => {
+                let key = match key {
+                    Some(key) => key.clone(),
+                    None => match inquire::Password::new("API Key:")
+                        .without_confirmation()
+                        .prompt()
+                    {
+                        Ok(key) => key,
+                        Err(_) => return Err("Cancelled".into()),
+                    },
+                };
+                if key.trim().is_empty() {
+                    return Err("API key can't be empty".into());
+                }
+
+                let storage = if *keychain {
+                    secrets::KeyStorage::Keychain
+                } else {
+                    secrets::KeyStorage::DotEnv
+                };
+                init::store_api_key(&app_config.project_root(), key.trim(), storage)?;
+            }
+            ConfigCommands::Get { key } => match app_config
+                .get_value(key.as_deref())
+                .map_err(|e| e.to_string())?
+            {
+                serde_json::Value::String(value) => println!("{}", value),
+                value => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&value).unwrap_or_default()
+                ),
+            },
+            ConfigCommands::Set { key, value } => {
+                let (key, change) = match (key.split_once("+="), value) {
+                    (Some((key, entry)), None) => {
+                        (key, config::ValueChange::Append(entry.to_string()))
+                    }
+                    (None, Some(value)) => (key.as_str(), config::ValueChange::Set(value.clone())),
+                    _ => {
+                        return Err(
+                            "Usage: squid config set <key> <value> or <key>+=<value>".into()
+                        );
+                    }
+                };
+                let path = app_config
+                    .change_value(key, change)
+                    .map_err(|e| e.to_string())?;
+                println!("✓ Updated {} in {}", key, path.display());
+            }
+            ConfigCommands::Unset { key } => {
+                let path = app_config
+                    .change_value(key, config::ValueChange::Unset)
+                    .map_err(|e| e.to_string())?;
+                println!("✓ {} reset to its default in {}", key, path.display());
+            }
+            ConfigCommands::Path => match config::Config::find_config_file() {
+                Some(path) => println!("{}", path.display()),
+                None => return Err("No config file found. Run 'squid init' first.".into()),
+            },
+        },
         Commands::Ask {
             question,
             message,
@@ -338,53 +938,100 @@ async fn main() {
             agent,
             rag,
             no_rag,
+            cache,
+            session,
+            continue_session,
+            output,
+            no_tools,
+            tool,
+            no_auto_context,
         } => {
             if !check_config_or_suggest_init() {
-                return;
+                return Err(CommandError::Reported);
             }
+            let tool_choice = match tool {
+                Some(name) => providers::ToolChoice::Tool(name.clone()),
+                None if *no_tools => providers::ToolChoice::None,
+                None => providers::ToolChoice::Auto,
+            };
             llm::run_ask_command(
                 question,
                 llm::AskCommandOptions {
                     message: message.as_deref(),
                     no_stream: *no_stream,
-                    file: file.as_deref(),
+                    files: file,
                     prompt: prompt.as_deref(),
                     agent: agent.as_deref(),
                     rag_flag: *rag,
                     no_rag_flag: *no_rag,
+                    cache_flag: *cache,
+                    session: session.as_deref(),
+                    continue_session: *continue_session,
+                    json_output: *output == OutputFormat::Json,
+                    tool_choice,
+                    no_auto_context: *no_auto_context,
                 },
-                &app_config,
+                app_config,
             )
-            .await;
+            .await?;
         }
         Commands::Review {
-            file,
+            files,
             message,
             no_stream,
             agent,
             rag,
             no_rag,
+            cache,
+            separate,
+            output,
+            line_numbers,
+            follow_up,
         } => {
             if !check_config_or_suggest_init() {
-                return;
+                return Err(CommandError::Reported);
             }
             llm::run_review_command(
-                file,
-                message.as_deref(),
-                *no_stream,
-                agent.as_deref(),
-                *rag,
-                *no_rag,
-                &app_config,
+                files,
+                llm::ReviewCommandOptions {
+                    message: message.as_deref(),
+                    no_stream: *no_stream,
+                    agent: agent.as_deref(),
+                    rag_flag: *rag,
+                    no_rag_flag: *no_rag,
+                    cache_flag: *cache,
+                    separate: *separate,
+                    json_output: *output == OutputFormat::Json,
+                    line_numbers: *line_numbers,
+                    follow_ups: follow_up,
+                },
+                app_config,
             )
-            .await;
+            .await?;
         }
-        Commands::Serve { port, db, dir } => {
+        Commands::Serve {
+            port,
+            db,
+            dir,
+            static_dir,
+            mock_llm,
+            repair,
+        } => {
             if !check_config_or_suggest_init() {
-                return;
+                return Err(CommandError::Reported);
             }
 
-            server::start_server(*port, db.clone(), dir.clone(), app_config.clone()).await;
+            let mut app_config = app_config.clone();
+            app_config.mock_llm = mock_llm.clone();
+            server::start_server(
+                *port,
+                db.clone(),
+                dir.clone(),
+                static_dir.clone(),
+                *repair,
+                app_config,
+            )
+            .await?;
         }
         Commands::Logs { command } => {
             let db_path = &app_config.database_path;
@@ -395,7 +1042,7 @@ async fn main() {
                     level,
                     session_id,
                 } => {
-                    println!("🦑: Fetching logs from database: {}", db_path);
+                    status!("🦑: Fetching logs from database: {}", db_path);
 
                     match logger::query_logs(
                         db_path,
@@ -426,14 +1073,16 @@ async fn main() {
                         }
                         Err(e) => {
                             error!("Failed to query logs: {}", e);
-                            println!("🦑: Failed to read logs from database - {}", e);
-                            println!("    Database path: {}", db_path);
-                            println!("    Make sure the database exists and is not corrupted.");
+                            return Err(format!(
+                                "Failed to read logs from database - {}\n    Database path: {}\n    Make sure the database exists and is not corrupted.",
+                                e, db_path
+                            )
+                            .into());
                         }
                     }
                 }
                 LogCommands::Reset => {
-                    println!("🦑: Clearing all logs from database: {}", db_path);
+                    status!("🦑: Clearing all logs from database: {}", db_path);
 
                     match logger::reset_logs(db_path) {
                         Ok(count) => {
@@ -441,16 +1090,28 @@ async fn main() {
                         }
                         Err(e) => {
                             error!("Failed to reset logs: {}", e);
-                            println!("🦑: Failed to clear logs from database - {}", e);
-                            println!("    Database path: {}", db_path);
+                            return Err(format!(
+                                "Failed to clear logs from database - {}\n    Database path: {}",
+                                e, db_path
+                            )
+                            .into());
                         }
                     }
                 }
-                LogCommands::Cleanup { max_age_days } => {
-                    let max_age_seconds = (*max_age_days as i64) * 24 * 60 * 60;
-                    println!(
-                        "🦑: Removing logs older than {} day(s) from database: {}",
-                        max_age_days, db_path
+                LogCommands::Cleanup {
+                    max_age_days,
+                    older_than,
+                } => {
+                    let older_than = match (max_age_days, older_than) {
+                        (Some(days), _) => format!("{}d", days),
+                        (None, Some(age)) => age.clone(),
+                        (None, None) => "30d".to_string(),
+                    };
+                    let max_age_seconds = logger::parse_log_age(&older_than)?;
+                    status!(
+                        "🦑: Removing logs older than {} from database: {}",
+                        older_than,
+                        db_path
                     );
 
                     match logger::cleanup_old_logs(db_path, max_age_seconds) {
@@ -459,201 +1120,227 @@ async fn main() {
                         }
                         Err(e) => {
                             error!("Failed to clean up logs: {}", e);
-                            println!("🦑: Failed to remove old logs from database - {}", e);
-                            println!("    Database path: {}", db_path);
+                            return Err(format!(
+                                "Failed to remove old logs from database - {}\n    Database path: {}",
+                                e, db_path
+                            )
+                            .into());
                         }
                     }
                 }
             }
         }
         Commands::Rag { command } => {
+            let json = command.json();
+            if json {
+                // Only the JSON result goes to stdout; keep banners and spinners out of the way
+                output::set_quiet(true);
+            }
+
             let db_path = &app_config.database_path;
             let db = match db::Database::new(db_path) {
                 Ok(db) => Arc::new(db),
                 Err(e) => {
                     error!("Failed to open database: {}", e);
-                    println!("🦑: Failed to open database - {}", e);
-                    return;
+                    return Err(rag_error(json, format!("Failed to open database - {}", e)));
                 }
             };
 
             let rag_config = &app_config.rag;
 
             if !rag_config.enabled {
-                println!("🦑: RAG is disabled in configuration");
-                println!(
-                    "    Set 'rag.enabled = true' in squid.config.json to enable RAG features"
-                );
-                return;
+                return Err(rag_error(
+                    json,
+                    "RAG is disabled in configuration\n    Set 'rag.enabled = true' in squid.config.json to enable RAG features"
+                        .to_string(),
+                ));
             }
 
-            let rag_system = match rag::RagSystem::new(db.clone(), rag_config).await {
-                Ok(system) => system,
-                Err(e) => {
-                    error!("Failed to initialize RAG system: {}", e);
-                    println!("🦑: Failed to initialize RAG system - {}", e);
-                    return;
-                }
-            };
+            let rag_system =
+                match rag::RagSystem::new(db.clone(), rag_config, app_config.network.http_client())
+                    .await
+                {
+                    Ok(system) => system,
+                    Err(e) => {
+                        error!("Failed to initialize RAG system: {}", e);
+                        return Err(rag_error(
+                            json,
+                            format!("Failed to initialize RAG system - {}", e),
+                        ));
+                    }
+                };
 
             match command {
-                RagCommands::Init { dir } => {
-                    let documents_path = dir
-                        .clone()
-                        .unwrap_or_else(|| PathBuf::from(&rag_config.documents_path));
+                RagCommands::Init { dir, strict, .. } => {
+                    let documents_path = dir.clone().unwrap_or_else(|| app_config.documents_dir());
 
                     if !documents_path.exists() {
-                        println!(
-                            "🦑: Documents directory not found: {}",
-                            documents_path.display()
-                        );
-                        println!("    Create the directory and add documents to index");
-                        return;
+                        return Err(rag_error(
+                            json,
+                            format!(
+                                "Documents directory not found: {}\n    Create the directory and add documents to index",
+                                documents_path.display()
+                            ),
+                        ));
                     }
 
                     // Test embedding service connection with a simple request
-                    println!("🦑: Testing embedding service connection...");
-                    match rag_system.indexer.embedder.embed_text("test").await {
+                    status!("🦑: Testing embedding service connection...");
+                    match rag_system.indexer.embedder.check_connection().await {
                         Ok(_) => {
-                            println!("✓ Embedding service is accessible");
+                            status!("✓ Embedding service is accessible");
+                        }
+                        Err(e) if json => {
+                            return Err(rag_error(
+                                json,
+                                format!("Embedding service connection failed - {}", e),
+                            ));
                         }
                         Err(e) => {
-                            println!("✗ Embedding service connection failed:");
-                            println!("    {}", e);
-                            println!("\nTroubleshooting:");
-                            println!(
+                            eprintln!("✗ Embedding service connection failed:");
+                            eprintln!("    {}", e);
+                            eprintln!("\nTroubleshooting:");
+                            eprintln!(
                                 "  1. Check if embedding service is running at: {}",
                                 rag_config.embedding_url
                             );
-                            println!(
+                            eprintln!(
                                 "  2. Verify the embedding model '{}' is loaded",
                                 rag_config.embedding_model
                             );
-                            println!("  3. For Ollama: run 'ollama pull nomic-embed-text'");
-                            println!("  4. For LM Studio: ensure an embedding model is loaded");
-                            println!("\nUpdate config with: squid init");
-                            return;
+                            eprintln!(
+                                "  3. For Ollama: run 'ollama pull nomic-embed-text' and set rag.embedding_provider to \"ollama\" (or use the /v1 URL)"
+                            );
+                            eprintln!("  4. For LM Studio: ensure an embedding model is loaded");
+                            eprintln!("\nUpdate config with: squid init");
+                            return Err(CommandError::Reported);
                         }
                     }
 
-                    println!(
+                    status!(
                         "🦑: Scanning documents directory: {}",
                         documents_path.display()
                     );
 
-                    let pb = indicatif::ProgressBar::new_spinner();
-                    pb.set_message("Indexing documents...");
-                    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+                    let pb = output::spinner("Indexing documents...");
+                    let result = rag_system.indexer.scan_and_index(&documents_path).await;
+                    pb.finish_and_clear();
 
-                    match rag_system.indexer.scan_and_index(&documents_path).await {
+                    match result {
                         Ok(stats) => {
-                            pb.finish_and_clear();
-                            println!("🦑: Indexing complete!");
-                            println!("    Files found: {}", stats.files_found);
-                            println!("    Files processed: {}", stats.files_processed);
-                            if stats.files_failed > 0 {
-                                println!("    Files failed: {}", stats.files_failed);
-                            }
-                            println!("    Total chunks: {}", stats.total_chunks);
-                            println!("    Total embeddings: {}", stats.total_embeddings);
+                            print_index_stats(&stats, "Indexing complete!", json, *strict)?
                         }
                         Err(e) => {
-                            pb.finish_and_clear();
                             error!("Failed to index documents: {}", e);
-                            println!("🦑: Failed to index documents - {}", e);
+                            return Err(rag_error(
+                                json,
+                                format!("Failed to index documents - {}", e),
+                            ));
                         }
                     }
                 }
-                RagCommands::List => match rag_system.indexer.list_documents() {
+                RagCommands::List { .. } => match rag_system.indexer.list_documents() {
                     Ok(docs) => {
-                        if docs.is_empty() {
+                        let list = rag::DocumentList::from(docs);
+                        if json {
+                            output::print_json(&list)?;
+                        } else if list.documents.is_empty() {
                             println!("🦑: No documents indexed");
                             println!("    Run 'squid rag init' to index documents");
                         } else {
                             println!("🦑: Indexed documents:\n");
-                            for doc in &docs {
+                            for doc in &list.documents {
                                 let updated = chrono::DateTime::from_timestamp(doc.updated_at, 0)
                                     .map(|dt| dt.format("%Y-%m-%d %H:%M:%S").to_string())
                                     .unwrap_or_else(|| "unknown".to_string());
+                                let name = if doc.title == doc.filename {
+                                    doc.filename.clone()
+                                } else {
+                                    format!("{} — {}", doc.filename, doc.title)
+                                };
                                 println!(
-                                    "  {} ({} bytes, updated: {})",
-                                    doc.filename, doc.file_size, updated
+                                    "  {} ({} bytes, {} chunks, {} embeddings, updated: {})",
+                                    name,
+                                    doc.file_size,
+                                    doc.chunk_count,
+                                    doc.embedding_count,
+                                    updated
                                 );
                             }
-                            println!("\nTotal: {} documents", docs.len());
+                            println!("\nTotal: {} documents", list.total);
                         }
                     }
                     Err(e) => {
                         error!("Failed to list documents: {}", e);
-                        println!("🦑: Failed to list documents - {}", e);
+                        return Err(rag_error(json, format!("Failed to list documents - {}", e)));
                     }
                 },
-                RagCommands::Rebuild { dir } => {
-                    let documents_path = dir
-                        .clone()
-                        .unwrap_or_else(|| PathBuf::from(&rag_config.documents_path));
+                RagCommands::Rebuild { dir, strict, .. } => {
+                    let documents_path = dir.clone().unwrap_or_else(|| app_config.documents_dir());
 
                     if !documents_path.exists() {
-                        println!(
-                            "🦑: Documents directory not found: {}",
-                            documents_path.display()
-                        );
-                        return;
+                        return Err(rag_error(
+                            json,
+                            format!(
+                                "Documents directory not found: {}",
+                                documents_path.display()
+                            ),
+                        ));
                     }
 
-                    println!("🦑: Rebuilding RAG index...");
+                    status!("🦑: Rebuilding RAG index...");
 
-                    let pb = indicatif::ProgressBar::new_spinner();
-                    pb.set_message("Rebuilding index...");
-                    pb.enable_steady_tick(std::time::Duration::from_millis(100));
+                    let pb = output::spinner("Rebuilding index...");
+                    let result = rag_system.indexer.rebuild(&documents_path).await;
+                    pb.finish_and_clear();
 
-                    match rag_system.indexer.rebuild(&documents_path).await {
-                        Ok(stats) => {
-                            pb.finish_and_clear();
-                            println!("🦑: Rebuild complete!");
-                            println!("    Files found: {}", stats.files_found);
-                            println!("    Files processed: {}", stats.files_processed);
-                            if stats.files_failed > 0 {
-                                println!("    Files failed: {}", stats.files_failed);
-                            }
-                            println!("    Total chunks: {}", stats.total_chunks);
-                            println!("    Total embeddings: {}", stats.total_embeddings);
-                        }
+                    match result {
+                        Ok(stats) => print_index_stats(&stats, "Rebuild complete!", json, *strict)?,
                         Err(e) => {
-                            pb.finish_and_clear();
                             error!("Failed to rebuild index: {}", e);
-                            println!("🦑: Failed to rebuild index - {}", e);
+                            return Err(rag_error(
+                                json,
+                                format!("Failed to rebuild index - {}", e),
+                            ));
                         }
                     }
                 }
-                RagCommands::Stats => match rag_system.indexer.get_stats() {
+                RagCommands::Stats { .. } => match rag_system.indexer.get_stats() {
                     Ok((doc_count, chunk_count, embedding_count)) => {
-                        println!("🦑: RAG Statistics:\n");
-                        println!("  Documents: {}", doc_count);
-                        println!("  Chunks: {}", chunk_count);
-                        println!("  Embeddings: {}", embedding_count);
-                        if doc_count > 0 {
-                            let avg_chunks = chunk_count as f64 / doc_count as f64;
-                            println!("  Average chunks per document: {:.1}", avg_chunks);
+                        let stats = rag::RagStats::new(doc_count, chunk_count, embedding_count);
+                        if json {
+                            output::print_json(&stats)?;
+                        } else {
+                            println!("🦑: RAG Statistics:\n");
+                            println!("  Documents: {}", stats.doc_count);
+                            println!("  Chunks: {}", stats.chunk_count);
+                            println!("  Embeddings: {}", stats.embedding_count);
+                            if stats.doc_count > 0 {
+                                println!(
+                                    "  Average chunks per document: {:.1}",
+                                    stats.avg_chunks_per_doc
+                                );
+                            }
                         }
                     }
                     Err(e) => {
                         error!("Failed to get stats: {}", e);
-                        println!("🦑: Failed to get statistics - {}", e);
+                        return Err(rag_error(json, format!("Failed to get statistics - {}", e)));
                     }
                 },
             }
         }
         Commands::Jobs { command } => {
             if !check_config_or_suggest_init() {
-                return;
+                return Err(CommandError::Reported);
             }
 
             // Check if jobs are enabled
             if !app_config.jobs.enabled {
-                println!("🦑: Background jobs are disabled in configuration");
-                println!("    Set 'jobs.enabled = true' in squid.config.json to enable jobs");
-                return;
+                return Err(
+                    "Background jobs are disabled in configuration\n    Set 'jobs.enabled = true' in squid.config.json to enable jobs"
+                        .into(),
+                );
             }
 
             let db_path = &app_config.database_path;
@@ -661,8 +1348,7 @@ async fn main() {
                 Ok(db) => Arc::new(db),
                 Err(e) => {
                     error!("Failed to open database: {}", e);
-                    println!("🦑: Failed to open database - {}", e);
-                    return;
+                    return Err(format!("Failed to open database - {}", e).into());
                 }
             };
 
@@ -671,14 +1357,14 @@ async fn main() {
                     status,
                     schedule_type,
                 } => {
-                    println!("🦑: Fetching jobs from database...\n");
+                    status!("🦑: Fetching jobs from database...\n");
 
                     match db.get_all_jobs() {
                         Ok(jobs) => {
                             if jobs.is_empty() {
                                 println!("No jobs found.");
                                 println!("\nCreate a job with: squid jobs create");
-                                return;
+                                return Ok(());
                             }
 
                             // Filter jobs
@@ -697,7 +1383,7 @@ async fn main() {
 
                             if filtered_jobs.is_empty() {
                                 println!("No jobs match the specified filters.");
-                                return;
+                                return Ok(());
                             }
 
                             // Create table rows
@@ -729,24 +1415,14 @@ async fn main() {
                                     let schedule = job
                                         .cron_expression
                                         .as_ref()
-                                        .map(|s| {
-                                            // Truncate long cron expressions
-                                            if s.len() > 20 {
-                                                format!("{}...", &s[..17])
-                                            } else {
-                                                s.clone()
-                                            }
-                                        })
+                                        // Truncate long cron expressions
+                                        .map(|s| text::truncate(s, 20))
                                         .unwrap_or_else(|| "-".to_string());
 
                                     JobRow {
                                         active,
                                         id: id.to_string(),
-                                        name: if job.name.len() > 30 {
-                                            format!("{}...", &job.name[..27])
-                                        } else {
-                                            job.name.clone()
-                                        },
+                                        name: text::truncate(&job.name, 30),
                                         job_type: job.schedule_type.clone(),
                                         status: job.status.clone(),
                                         schedule,
@@ -763,7 +1439,7 @@ async fn main() {
                         }
                         Err(e) => {
                             error!("Failed to list jobs: {}", e);
-                            println!("🦑: Failed to list jobs - {}", e);
+                            return Err(format!("Failed to list jobs - {}", e).into());
                         }
                     }
                 }
@@ -828,11 +1504,11 @@ async fn main() {
                         }
                     }
                     Ok(None) => {
-                        println!("🦑: Job {} not found", id);
+                        return Err(format!("Job {} not found", id).into());
                     }
                     Err(e) => {
                         error!("Failed to get job: {}", e);
-                        println!("🦑: Failed to get job - {}", e);
+                        return Err(format!("Failed to get job - {}", e).into());
                     }
                 },
                 JobCommands::Create {
@@ -851,9 +1527,10 @@ async fn main() {
                         app_config.agents.agents.keys().cloned().collect();
 
                     if available_agents.is_empty() {
-                        println!("🦑: No agents found in configuration");
-                        println!("    Create agents in the agents/ directory");
-                        return;
+                        return Err(
+                            "No agents found in configuration\n    Create agents in the agents/ directory"
+                                .into(),
+                        );
                     }
 
                     // Interactive mode: prompt for job name if not provided
@@ -866,8 +1543,7 @@ async fn main() {
                         {
                             Ok(name) => name,
                             Err(_) => {
-                                println!("Job creation cancelled.");
-                                return;
+                                return Err("Job creation cancelled".into());
                             }
                         }
                     };
@@ -876,16 +1552,17 @@ async fn main() {
                     let selected_agent = if let Some(agent_id) = agent {
                         // Validate provided agent exists
                         if !available_agents.contains(agent_id) {
-                            println!("🦑: Agent '{}' not found", agent_id);
-                            println!("\nAvailable agents:");
+                            let mut message =
+                                format!("Agent '{}' not found\n\nAvailable agents:", agent_id);
                             for id in &available_agents {
                                 if let Some(agent_config) = app_config.get_agent(id) {
-                                    println!("  - {} ({})", id, agent_config.name);
+                                    message
+                                        .push_str(&format!("\n  - {} ({})", id, agent_config.name));
                                 } else {
-                                    println!("  - {}", id);
+                                    message.push_str(&format!("\n  - {}", id));
                                 }
                             }
-                            return;
+                            return Err(message.into());
                         }
                         agent_id.clone()
                     } else {
@@ -911,8 +1588,7 @@ async fn main() {
                                     .position(|opt| opt == &selection)
                                     .unwrap(),
                                 Err(_) => {
-                                    println!("Job creation cancelled.");
-                                    return;
+                                    return Err("Job creation cancelled".into());
                                 }
                             };
 
@@ -929,8 +1605,7 @@ async fn main() {
                         {
                             Ok(msg) => msg,
                             Err(_) => {
-                                println!("Job creation cancelled.");
-                                return;
+                                return Err("Job creation cancelled".into());
                             }
                         }
                     };
@@ -946,8 +1621,7 @@ async fn main() {
                         {
                             Ok(selection) => selection.to_string(),
                             Err(_) => {
-                                println!("Job creation cancelled.");
-                                return;
+                                return Err("Job creation cancelled".into());
                             }
                         }
                     };
@@ -963,8 +1637,7 @@ async fn main() {
                             {
                                 Ok(expr) => Some(expr),
                                 Err(_) => {
-                                    println!("Job creation cancelled.");
-                                    return;
+                                    return Err("Job creation cancelled".into());
                                 }
                             }
                         }
@@ -1028,7 +1701,7 @@ async fn main() {
                         }
                         Err(e) => {
                             error!("Failed to create job: {}", e);
-                            println!("🦑: Failed to create job - {}", e);
+                            return Err(format!("Failed to create job - {}", e).into());
                         }
                     }
                 }
@@ -1052,7 +1725,7 @@ async fn main() {
 
                                 if !response.trim().eq_ignore_ascii_case("y") {
                                     println!("Cancelled.");
-                                    return;
+                                    return Ok(());
                                 }
                             }
 
@@ -1062,16 +1735,16 @@ async fn main() {
                                 }
                                 Err(e) => {
                                     error!("Failed to delete job: {}", e);
-                                    println!("🦑: Failed to delete job - {}", e);
+                                    return Err(format!("Failed to delete job - {}", e).into());
                                 }
                             }
                         }
                         Ok(None) => {
-                            println!("🦑: Job {} not found", id);
+                            return Err(format!("Job {} not found", id).into());
                         }
                         Err(e) => {
                             error!("Failed to get job: {}", e);
-                            println!("🦑: Failed to get job - {}", e);
+                            return Err(format!("Failed to get job - {}", e).into());
                         }
                     }
                 }
@@ -1083,8 +1756,11 @@ async fn main() {
                     }
                     Err(e) => {
                         error!("Failed to pause job: {}", e);
-                        println!("🦑: Failed to pause job - {}", e);
-                        println!("    Note: Only cron jobs can be paused");
+                        return Err(format!(
+                            "Failed to pause job - {}\n    Note: Only cron jobs can be paused",
+                            e
+                        )
+                        .into());
                     }
                 },
                 JobCommands::Resume { id } => match db.resume_job(*id) {
@@ -1094,8 +1770,11 @@ async fn main() {
                     }
                     Err(e) => {
                         error!("Failed to resume job: {}", e);
-                        println!("🦑: Failed to resume job - {}", e);
-                        println!("    Note: Only cron jobs can be resumed");
+                        return Err(format!(
+                            "Failed to resume job - {}\n    Note: Only cron jobs can be resumed",
+                            e
+                        )
+                        .into());
                     }
                 },
                 JobCommands::Trigger { id } => {
@@ -1103,12 +1782,11 @@ async fn main() {
                     match db.get_job_by_id(*id) {
                         Ok(Some(job)) => {
                             if job.schedule_type != "cron" {
-                                println!(
-                                    "🦑: Job {} is not a cron job (type: {})",
+                                return Err(format!(
+                                    "Job {} is not a cron job (type: {})\n    Only cron jobs can be manually triggered",
                                     id, job.schedule_type
-                                );
-                                println!("    Only cron jobs can be manually triggered");
-                                return;
+                                )
+                                .into());
                             }
 
                             println!("🦑: Triggering job {} manually...", id);
@@ -1128,42 +1806,503 @@ async fn main() {
                                 }
                                 Err(e) => {
                                     error!("Failed to queue job: {}", e);
-                                    println!("🦑: Failed to queue job - {}", e);
+                                    return Err(format!("Failed to queue job - {}", e).into());
                                 }
                             }
                         }
                         Ok(None) => {
-                            println!("🦑: Job {} not found", id);
+                            return Err(format!("Job {} not found", id).into());
                         }
                         Err(e) => {
                             error!("Failed to get job: {}", e);
-                            println!("🦑: Failed to get job - {}", e);
+                            return Err(format!("Failed to get job - {}", e).into());
                         }
                     }
                 }
             }
         }
+        Commands::Cache { command } => match command {
+            CacheCommands::Clear => {
+                let db_path = &app_config.database_path;
+                match db::Database::new(db_path) {
+                    Ok(db) => match db.clear_response_cache() {
+                        Ok(count) => {
+                            println!("✓ Cleared {} cached response(s).", count);
+                        }
+                        Err(e) => {
+                            error!("Failed to clear response cache: {}", e);
+                            return Err(format!("Failed to clear response cache - {}", e).into());
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to open database: {}", e);
+                        return Err(format!(
+                            "Failed to open database - {}\n    Database path: {}",
+                            e, db_path
+                        )
+                        .into());
+                    }
+                }
+            }
+        },
+        Commands::Stats { command } => match command {
+            StatsCommands::Tools { days, json } => {
+                let db_path = &app_config.database_path;
+                let db = match db::Database::new(db_path) {
+                    Ok(db) => db,
+                    Err(e) => {
+                        error!("Failed to open database: {}", e);
+                        return Err(format!(
+                            "Failed to open database - {}\n    Database path: {}",
+                            e, db_path
+                        )
+                        .into());
+                    }
+                };
+
+                let since = chrono::Utc::now().timestamp() - i64::from(*days) * 86_400;
+                let tools = db.get_tool_stats(since)?;
+                let bash_commands = db.get_bash_command_stats(since)?;
+                if *json {
+                    output::print_json(&serde_json::json!({
+                        "days": days,
+                        "since": since,
+                        "tools": tools,
+                        "bash_commands": bash_commands,
+                    }))?;
+                } else if tools.is_empty() {
+                    println!("No tool calls in the last {} day(s).", days);
+                } else {
+                    println!("Tool usage in the last {} day(s):\n", days);
+                    print_tool_stats(&tools);
+                    if !bash_commands.is_empty() {
+                        println!("\nBash commands:\n");
+                        print_tool_stats(&bash_commands);
+                    }
+                }
+            }
+        },
+        Commands::New { template, vars } => {
+            let Some(template) = app_config.get_template(template) else {
+                let names: Vec<&str> = app_config
+                    .templates
+                    .iter()
+                    .map(|template| template.name.as_str())
+                    .collect();
+                return Err(if names.is_empty() {
+                    format!(
+                        "Template '{}' not found; no templates are configured under `templates`",
+                        template
+                    )
+                } else {
+                    format!(
+                        "Template '{}' not found; configured templates: {}",
+                        template,
+                        names.join(", ")
+                    )
+                }
+                .into());
+            };
+            let db_path = &app_config.database_path;
+            let db = db::Database::new(db_path).map_err(|e| {
+                error!("Failed to open database: {}", e);
+                format!(
+                    "Failed to open database - {}\n    Database path: {}",
+                    e, db_path
+                )
+            })?;
+            let session_manager = session::SessionManager::new(db);
+            let vars = vars.iter().cloned().collect();
+            let seeded = session_templates::seed(app_config, &session_manager, template, &vars)
+                .map_err(|e| e.to_string())?;
+            for warning in &seeded.warnings {
+                status!("⚠ {}", warning);
+            }
+            println!(
+                "✓ Started session {} from template '{}'.",
+                seeded.session_id, template.name
+            );
+            status!("🦑: Open it in the web UI (`squid serve`) to get the first answer.");
+        }
+        Commands::Sessions { command } => match command {
+            SessionCommands::Prune {
+                older_than,
+                dry_run,
+                yes,
+            } => {
+                let max_age_seconds = logger::parse_log_age(older_than)?;
+                let db_path = &app_config.database_path;
+                let db = match db::Database::new(db_path) {
+                    Ok(db) => db,
+                    Err(e) => {
+                        error!("Failed to open database: {}", e);
+                        return Err(format!(
+                            "Failed to open database - {}\n    Database path: {}",
+                            e, db_path
+                        )
+                        .into());
+                    }
+                };
+
+                if *dry_run {
+                    match db.find_old_sessions(max_age_seconds) {
+                        Ok(sessions) => {
+                            println!(
+                                "🦑: {} session(s) older than {} would be deleted:",
+                                sessions.len(),
+                                older_than
+                            );
+                            for session in &sessions {
+                                let updated_at =
+                                    chrono::DateTime::from_timestamp(session.updated_at, 0)
+                                        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                                        .unwrap_or_default();
+                                let title = session.title.as_deref().unwrap_or("(untitled)");
+                                println!(
+                                    "  {}  {}  {}",
+                                    session.id,
+                                    updated_at,
+                                    text::truncate(title, 50)
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            error!("Failed to list old sessions: {}", e);
+                            return Err(format!("Failed to list old sessions - {}", e).into());
+                        }
+                    }
+                    return Ok(());
+                }
+
+                if !yes {
+                    let count = match db.find_old_sessions(max_age_seconds) {
+                        Ok(sessions) => sessions.len(),
+                        Err(e) => {
+                            error!("Failed to list old sessions: {}", e);
+                            return Err(format!("Failed to list old sessions - {}", e).into());
+                        }
+                    };
+                    if count == 0 {
+                        println!("🦑: No sessions older than {}.", older_than);
+                        return Ok(());
+                    }
+                    print!(
+                        "🦑: About to delete {} session(s) older than {} with their messages.\nAre you sure? (y/N): ",
+                        count, older_than
+                    );
+
+                    use std::io::{self, Write};
+                    io::stdout().flush().unwrap();
+
+                    let mut response = String::new();
+                    io::stdin().read_line(&mut response).unwrap();
+
+                    if !response.trim().eq_ignore_ascii_case("y") {
+                        println!("Cancelled.");
+                        return Ok(());
+                    }
+                }
+
+                status!(
+                    "🦑: Removing sessions older than {} from database: {}",
+                    older_than,
+                    db_path
+                );
+                match db.cleanup_old_sessions(max_age_seconds) {
+                    Ok(count) => {
+                        println!("✓ Successfully removed {} old session(s).", count);
+                    }
+                    Err(e) => {
+                        error!("Failed to prune sessions: {}", e);
+                        return Err(format!(
+                            "Failed to remove old sessions - {}\n    Database path: {}",
+                            e, db_path
+                        )
+                        .into());
+                    }
+                }
+            }
+            SessionCommands::Rm {
+                ids,
+                older_than,
+                title_contains,
+                untitled,
+                max_messages,
+                all,
+                dry_run,
+            } => {
+                let filter = db::SessionFilter {
+                    ids: ids.clone(),
+                    older_than_seconds: older_than
+                        .as_deref()
+                        .map(logger::parse_log_age)
+                        .transpose()?,
+                    title_contains: title_contains.clone(),
+                    untitled_only: *untitled,
+                    max_messages: *max_messages,
+                };
+                if filter.is_empty() && !*all {
+                    return Err(
+                        "No session ids or filters given; pass --all to delete every session"
+                            .into(),
+                    );
+                }
+
+                let db_path = &app_config.database_path;
+                let db = db::Database::new(db_path).map_err(|e| {
+                    error!("Failed to open database: {}", e);
+                    format!(
+                        "Failed to open database - {}\n    Database path: {}",
+                        e, db_path
+                    )
+                })?;
+
+                if *dry_run {
+                    let sessions = db.find_sessions(&filter).map_err(|e| {
+                        error!("Failed to list sessions: {}", e);
+                        format!("Failed to list sessions - {}", e)
+                    })?;
+                    println!("🦑: {} session(s) would be deleted:", sessions.len());
+                    for session in &sessions {
+                        let updated_at = chrono::DateTime::from_timestamp(session.updated_at, 0)
+                            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                            .unwrap_or_default();
+                        let title = session.title.as_deref().unwrap_or("(untitled)");
+                        println!(
+                            "  {}  {}  {}",
+                            session.id,
+                            updated_at,
+                            text::truncate(title, 50)
+                        );
+                    }
+                    return Ok(());
+                }
+
+                match db.delete_sessions(&filter) {
+                    Ok(deleted) => {
+                        println!("✓ Successfully removed {} session(s).", deleted.len());
+                    }
+                    Err(e) => {
+                        error!("Failed to delete sessions: {}", e);
+                        return Err(format!(
+                            "Failed to delete sessions - {}\n    Database path: {}",
+                            e, db_path
+                        )
+                        .into());
+                    }
+                }
+            }
+            SessionCommands::Show { id } => {
+                let db_path = &app_config.database_path;
+                let db = db::Database::new(db_path).map_err(|e| {
+                    error!("Failed to open database: {}", e);
+                    format!(
+                        "Failed to open database - {}\n    Database path: {}",
+                        e, db_path
+                    )
+                })?;
+                let session = match db.load_session(id) {
+                    Ok(Some(session)) => session,
+                    Ok(None) => return Err(format!("Session '{}' not found", id).into()),
+                    Err(e) => return Err(format!("Failed to load session - {}", e).into()),
+                };
+                print_session_details(&session);
+            }
+            SessionCommands::Compact { id, keep } => {
+                let db_path = &app_config.database_path;
+                let db = db::Database::new(db_path).map_err(|e| {
+                    error!("Failed to open database: {}", e);
+                    format!(
+                        "Failed to open database - {}\n    Database path: {}",
+                        e, db_path
+                    )
+                })?;
+                let session_manager = session::SessionManager::new(db);
+                let report = compact::run(app_config, &session_manager, id, *keep)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                match report.summary_message_id {
+                    Some(_) => println!(
+                        "✓ Replaced {} message(s) with a summary, kept {} ({} byte(s) reclaimed).",
+                        report.stats.messages_removed,
+                        report.messages_kept,
+                        report.stats.bytes_reclaimed
+                    ),
+                    None => println!(
+                        "🦑: Nothing to compact: session has {} message(s).",
+                        report.messages_kept
+                    ),
+                }
+            }
+            SessionCommands::Import { format, path } => {
+                let json = std::fs::read_to_string(path).map_err(|e| {
+                    format!("Failed to read export file {} - {}", path.display(), e)
+                })?;
+                let parsed = import::parse_export(*format, &json)?;
+                for warning in &parsed.warnings {
+                    status!("⚠ {}", warning);
+                }
+
+                let db_path = &app_config.database_path;
+                let db = db::Database::new(db_path).map_err(|e| {
+                    error!("Failed to open database: {}", e);
+                    format!(
+                        "Failed to open database - {}\n    Database path: {}",
+                        e, db_path
+                    )
+                })?;
+
+                let progress =
+                    output::progress_bar(parsed.sessions.len() as u64, "🦑: Importing sessions");
+                let summary = import::write_sessions(&db, *format, &parsed.sessions, &progress)
+                    .map_err(|e| {
+                        error!("Failed to import sessions: {}", e);
+                        format!("Failed to import sessions - {}", e)
+                    })?;
+                progress.finish_and_clear();
+
+                println!(
+                    "✓ Imported {} session(s), skipped {} already imported ({} warning(s)).",
+                    summary.imported,
+                    summary.duplicates,
+                    parsed.warnings.len()
+                );
+            }
+        },
+        Commands::Db { command } => match command {
+            DbCommands::Check => {
+                let db_path = &app_config.database_path;
+                if !std::path::Path::new(db_path).exists() {
+                    return Err(format!("No database found at {}", db_path).into());
+                }
+
+                // Inspect the schema as-is; opening normally would run pending migrations
+                let report =
+                    match db::Database::open_existing(db_path).and_then(|db| db.schema_report()) {
+                        Ok(report) => report,
+                        Err(e) => {
+                            error!("Failed to inspect database: {}", e);
+                            return Err(format!(
+                                "Failed to inspect database - {}\n    Database path: {}",
+                                e, db_path
+                            )
+                            .into());
+                        }
+                    };
+
+                println!("Database: {}\n", db_path);
+                println!("Applied migrations ({}):", report.applied.len());
+                for migration in &report.applied {
+                    let applied_at = chrono::DateTime::from_timestamp(migration.applied_at, 0)
+                        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "  ✓ {:03} {} ({})",
+                        migration.version, migration.name, applied_at
+                    );
+                }
+
+                if !report.pending.is_empty() {
+                    println!("\nPending migrations (run on next start):");
+                    for (version, name) in &report.pending {
+                        println!("  • {:03} {}", version, name);
+                    }
+                }
+
+                if report.missing_columns.is_empty() {
+                    println!("\nMissing columns: none");
+                } else {
+                    println!("\nMissing columns:");
+                    for missing in &report.missing_columns {
+                        println!(
+                            "  ✗ {}.{} (migration {:03})",
+                            missing.table, missing.column, missing.version
+                        );
+                    }
+                }
+
+                match &report.backup {
+                    Some(path) => println!("\nBackup: {}", path.display()),
+                    None => println!("\nBackup: none"),
+                }
+
+                if !report.missing_columns.is_empty() {
+                    println!();
+                    return Err(
+                        "The schema is incomplete. Restore a backup or recreate the database."
+                            .into(),
+                    );
+                }
+            }
+        },
+        Commands::CheckPath { path, json } => {
+            let validator =
+                validate::PathValidator::cached_for_project(app_config.security.respect_gitignore);
+            let report = validator.explain(path);
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&report).unwrap_or_default()
+                );
+            } else {
+                println!(
+                    "Path: {}",
+                    report.resolved.as_ref().unwrap_or(&report.path).display()
+                );
+                for check in &report.checks {
+                    // Where the deciding rule came from, e.g. `*.env` in .squidignore:3
+                    let origin = match (&check.source, check.line) {
+                        (Some(source), Some(line)) => {
+                            Some(format!("{}:{}", source.display(), line))
+                        }
+                        (Some(source), None) => Some(source.display().to_string()),
+                        _ => None,
+                    };
+                    let rule = match (&check.pattern, origin) {
+                        (Some(pattern), Some(origin)) => format!(" (`{}` in {})", pattern, origin),
+                        (Some(pattern), None) => format!(" (`{}`)", pattern),
+                        (None, Some(origin)) => format!(" ({})", origin),
+                        (None, None) => String::new(),
+                    };
+                    println!(
+                        "  {} {}: {}{}",
+                        if check.passed { "✓" } else { "✗" },
+                        check.rule.as_str(),
+                        check.detail,
+                        rule
+                    );
+                }
+                match report.denied_by() {
+                    Some(check) => println!("\nDenied by {}", check.rule.as_str()),
+                    None => println!("\nAllowed"),
+                }
+            }
+            if !report.allowed {
+                return Err(CommandError::Reported);
+            }
+        }
         Commands::Cleanup => match bundled::cleanup_bundled_assets() {
             Ok(()) => {
                 println!("✅ Bundled assets cleaned up successfully");
                 println!("✅ Removed extracted plugins and agents from data directory");
             }
             Err(e) => {
-                eprintln!("❌ Failed to clean bundled assets: {e}");
-                std::process::exit(1);
+                return Err(format!("Failed to clean bundled assets - {}", e).into());
             }
         },
         Commands::Doctor => {
             if !check_config_or_suggest_init() {
-                return;
+                return Err(CommandError::Reported);
             }
 
             let doctor = doctor::Doctor::new();
-            let all_passed = doctor.run(&app_config).await;
+            let all_passed = doctor.run(app_config).await;
 
             if !all_passed {
-                std::process::exit(1);
+                return Err(CommandError::Reported);
             }
         }
     }
+
+    Ok(())
 }
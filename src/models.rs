@@ -0,0 +1,899 @@
+use crate::config::Config;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+
+/// Timeout for `/models` requests made by the web server
+pub const MODELS_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Known model capabilities shipped with squid
+const MODEL_METADATA: &str = include_str!("./assets/model-metadata.json");
+
+/// Response body of the OpenAI-compatible `/models` endpoint
+#[derive(Debug, Deserialize)]
+pub struct ModelsResponse {
+    pub data: Vec<ModelInfo>,
+}
+
+/// A single model entry returned by the provider
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelInfo {
+    pub id: String,
+    /// Context length, when the provider reports one (OpenRouter, LM Studio and others)
+    #[serde(default, alias = "max_context_length", alias = "context_window")]
+    pub context_length: Option<u32>,
+}
+
+/// Build the `/models` URL for an API base URL
+pub fn models_url(api_url: &str) -> String {
+    if api_url.ends_with('/') {
+        format!("{}models", api_url)
+    } else {
+        format!("{}/models", api_url)
+    }
+}
+
+/// Fetch available models from the API
+///
+/// A `timeout` keeps interactive callers from hanging on unreachable endpoints.
+pub async fn fetch_models(
+    api_url: &str,
+    api_key: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<Vec<ModelInfo>, String> {
+    let url = models_url(api_url);
+    debug!("Fetching models from: {}", url);
+
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    let client = builder
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    let mut request = client.get(&url);
+    if let Some(key) = api_key.filter(|k| !k.is_empty()) {
+        request = request.bearer_auth(key);
+    }
+
+    let response = request
+        .send()
+        .await
+        .map_err(|e| extract_connection_error(&e))?;
+
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+
+    let models_response: ModelsResponse = response
+        .json()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))?;
+
+    Ok(models_response.data)
+}
+
+/// Look up the context length reported for a model
+pub fn context_length_for(models: &[ModelInfo], model_id: &str) -> Option<u32> {
+    models
+        .iter()
+        .find(|m| m.id == model_id)
+        .and_then(|m| m.context_length)
+}
+
+/// When the model list was fetched, and the list itself
+type ModelCacheEntry = Option<(Instant, Vec<ModelInfo>)>;
+
+/// In-process cache of the provider's model list, shared through app data
+#[derive(Clone)]
+pub struct ModelCache {
+    entry: Arc<RwLock<ModelCacheEntry>>,
+    ttl: Duration,
+}
+
+/// Models served by the cache, and whether they are left over from an earlier request
+#[derive(Debug)]
+pub struct CachedModels {
+    pub models: Vec<ModelInfo>,
+    pub stale: bool,
+}
+
+impl ModelCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entry: Arc::new(RwLock::new(None)),
+            ttl,
+        }
+    }
+
+    /// Return cached models while they are fresh, otherwise call `fetch`
+    ///
+    /// `refresh` bypasses a fresh cache. When `fetch` fails, the last known list is returned
+    /// with `stale: true`; the error is only returned if nothing was ever cached.
+    pub async fn get<F, Fut>(&self, refresh: bool, fetch: F) -> Result<CachedModels, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Vec<ModelInfo>, String>>,
+    {
+        if !refresh
+            && let Some((fetched_at, models)) = &*self.entry.read().unwrap()
+            && fetched_at.elapsed() < self.ttl
+        {
+            return Ok(CachedModels {
+                models: models.clone(),
+                stale: false,
+            });
+        }
+
+        match fetch().await {
+            Ok(models) => {
+                *self.entry.write().unwrap() = Some((Instant::now(), models.clone()));
+                Ok(CachedModels {
+                    models,
+                    stale: false,
+                })
+            }
+            Err(e) => match &*self.entry.read().unwrap() {
+                Some((_, models)) => {
+                    warn!("Failed to fetch models, serving cached list: {}", e);
+                    Ok(CachedModels {
+                        models: models.clone(),
+                        stale: true,
+                    })
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Context length the last fetched list reports for `model_id`, however old the list is
+    ///
+    /// Never calls the provider: a chat request shouldn't wait on `/models`.
+    pub fn context_length(&self, model_id: &str) -> Option<u32> {
+        self.entry
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|(_, models)| context_length_for(models, model_id))
+    }
+}
+
+/// Normalize a model id for fuzzy matching
+///
+/// Lowercases and drops the provider prefix (`qwen/`), `.gguf` extension and tag (`:latest`),
+/// so `Qwen/Qwen3.5-4B-GGUF:latest` and `qwen3.5-4b-gguf` compare equal.
+pub fn normalize_model_id(id: &str) -> String {
+    let id = id.trim().to_lowercase();
+    let id = id.rsplit('/').next().unwrap_or_default();
+    let id = id.split(':').next().unwrap_or_default();
+    let id = id.strip_suffix(".gguf").unwrap_or(id);
+    id.replace(['_', ' '], "-")
+}
+
+/// Capabilities of a known model, from the embedded `model-metadata.json` and user overrides
+#[derive(Debug, Clone, Deserialize)]
+pub struct ModelMetadata {
+    pub id: String,
+    /// Other names the model is published under
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Whether the model handles function calling; tools are not sent to models without it
+    #[serde(default = "default_supports_tools")]
+    pub supports_tools: bool,
+    /// How the model's reasoning is controlled, if it can be
+    #[serde(default)]
+    pub reasoning: Option<ReasoningControl>,
+    /// Context window in tokens, used when an agent doesn't set `context_window`
+    #[serde(default)]
+    pub context_length: Option<u32>,
+    /// Model id the Web UI prices usage with, when an agent doesn't set `pricing_model`
+    #[serde(default)]
+    pub pricing_model: Option<String>,
+}
+
+/// A user entry from `models.metadata_path` or `models.overrides`
+///
+/// Same fields as `model-metadata.json`; fields left out keep the value of the entry it overrides.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelOverride {
+    /// Id or alias of the entry to change, or the id of a new entry
+    pub id: String,
+    /// Added to the entry's aliases
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supports_tools: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<ReasoningControl>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing_model: Option<String>,
+}
+
+/// Request field a reasoning model is steered with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReasoningControl {
+    /// `reasoning_effort` (OpenAI o-series, gpt-oss)
+    Effort,
+    /// The `enable_thinking` chat template switch (Qwen3 and other hybrid thinking models)
+    EnableThinking,
+}
+
+fn default_supports_tools() -> bool {
+    true
+}
+
+/// Model metadata used by lookups: the embedded list with the user's overrides applied
+static METADATA: OnceLock<Vec<ModelMetadata>> = OnceLock::new();
+
+fn embedded_metadata() -> Vec<ModelMetadata> {
+    serde_json::from_str(MODEL_METADATA).unwrap_or_else(|e| {
+        warn!("Failed to parse embedded model metadata: {}", e);
+        Vec::new()
+    })
+}
+
+fn metadata() -> &'static [ModelMetadata] {
+    METADATA.get_or_init(embedded_metadata)
+}
+
+/// Apply `models.metadata_path` and `models.overrides` to the model metadata
+///
+/// Call once at startup, before any lookup; until then and without overrides, only the
+/// embedded metadata is used.
+pub fn load_metadata(config: &Config) {
+    if METADATA.set(metadata_with_overrides(config)).is_err() {
+        debug!("Model metadata was already loaded");
+    }
+}
+
+/// The embedded metadata with the override file's entries applied, then the inline ones
+///
+/// An override file that can't be read or parsed is skipped with a warning.
+fn metadata_with_overrides(config: &Config) -> Vec<ModelMetadata> {
+    let mut overrides = Vec::new();
+    if let Some(path) = &config.models.metadata_path {
+        let path = config.resolve_path(path);
+        match read_overrides(&path) {
+            Ok(entries) => {
+                debug!(
+                    "Loaded {} model metadata overrides from {}",
+                    entries.len(),
+                    path.display()
+                );
+                overrides = entries;
+            }
+            Err(e) => warn!(
+                "Ignoring model metadata overrides in {}: {}",
+                path.display(),
+                e
+            ),
+        }
+    }
+    overrides.extend(config.models.overrides.iter().cloned());
+    apply_overrides(embedded_metadata(), &overrides)
+}
+
+fn read_overrides(path: &std::path::Path) -> Result<Vec<ModelOverride>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&content).map_err(|e| e.to_string())
+}
+
+/// Apply overrides in order, each to the entry its id names (by id or alias) or as a new entry
+///
+/// Changed and added entries move to the front, so their ids and aliases win over the others
+/// and a later override wins over an earlier one.
+fn apply_overrides(
+    mut metadata: Vec<ModelMetadata>,
+    overrides: &[ModelOverride],
+) -> Vec<ModelMetadata> {
+    for entry in overrides {
+        let mut merged = match position(&metadata, &entry.id) {
+            Some(index) => metadata.remove(index),
+            None => ModelMetadata {
+                id: entry.id.clone(),
+                aliases: Vec::new(),
+                supports_tools: default_supports_tools(),
+                reasoning: None,
+                context_length: None,
+                pricing_model: None,
+            },
+        };
+        for alias in &entry.aliases {
+            if !merged.aliases.contains(alias) {
+                merged.aliases.push(alias.clone());
+            }
+        }
+        if let Some(supports_tools) = entry.supports_tools {
+            merged.supports_tools = supports_tools;
+        }
+        if entry.reasoning.is_some() {
+            merged.reasoning = entry.reasoning;
+        }
+        if entry.context_length.is_some() {
+            merged.context_length = entry.context_length;
+        }
+        if entry.pricing_model.is_some() {
+            merged.pricing_model = entry.pricing_model.clone();
+        }
+        metadata.insert(0, merged);
+    }
+    metadata
+}
+
+/// Index of the first entry whose id or an alias matches `model_id` after `normalize_model_id`
+fn position(metadata: &[ModelMetadata], model_id: &str) -> Option<usize> {
+    let normalized = normalize_model_id(model_id);
+    metadata.iter().position(|m| {
+        std::iter::once(&m.id)
+            .chain(&m.aliases)
+            .any(|name| normalize_model_id(name) == normalized)
+    })
+}
+
+/// Find metadata for a model by id or alias, compared with `normalize_model_id`
+pub fn model_metadata(model_id: &str) -> Option<&'static ModelMetadata> {
+    let metadata = metadata();
+    position(metadata, model_id).map(|index| &metadata[index])
+}
+
+/// Context window from the model metadata, if an entry sets one
+pub fn context_length(model_id: &str) -> Option<u32> {
+    model_metadata(model_id).and_then(|m| m.context_length)
+}
+
+/// Where the context window a request runs with came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContextWindowSource {
+    /// The model metadata's `context_length`
+    Metadata,
+    /// The provider's `/models` list (`max_context_length` or `context_length`)
+    Provider,
+    /// The agent's `context_window`, else the global one
+    Config,
+}
+
+/// The context window a request runs with, and the configured one it replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContextWindow {
+    pub tokens: u32,
+    pub source: ContextWindowSource,
+    pub configured: u32,
+}
+
+impl ContextWindow {
+    /// Whether the model's known window and the configured one differ by more than 2x
+    pub fn mismatched(&self) -> bool {
+        let (low, high) = if self.tokens < self.configured {
+            (self.tokens, self.configured)
+        } else {
+            (self.configured, self.tokens)
+        };
+        self.source != ContextWindowSource::Config && u64::from(high) > u64::from(low) * 2
+    }
+}
+
+/// Resolve a request's context window: what the metadata, then the provider, knows about the
+/// model, else the configured one
+pub fn resolve_context_window(
+    metadata: Option<u32>,
+    provider: Option<u32>,
+    configured: u32,
+) -> ContextWindow {
+    let (tokens, source) = match (metadata, provider) {
+        (Some(tokens), _) => (tokens, ContextWindowSource::Metadata),
+        (None, Some(tokens)) => (tokens, ContextWindowSource::Provider),
+        (None, None) => (configured, ContextWindowSource::Config),
+    };
+    ContextWindow {
+        tokens,
+        source,
+        configured,
+    }
+}
+
+/// Pricing model id from the model metadata, if an entry sets one
+pub fn pricing_model(model_id: &str) -> Option<&'static str> {
+    model_metadata(model_id).and_then(|m| m.pricing_model.as_deref())
+}
+
+/// Whether tools can be sent to `model_id`; unknown models are assumed to support them
+pub fn supports_tools(model_id: &str) -> bool {
+    model_metadata(model_id).is_none_or(|m| m.supports_tools)
+}
+
+/// How reasoning is controlled for `model_id`
+///
+/// Models listed in the metadata use their entry; unknown models are recognized by family
+/// (`o1`/`o3`/`o4`/`gpt-5`/`gpt-oss` take an effort, `qwen3` takes `enable_thinking`).
+pub fn reasoning_control(model_id: &str) -> Option<ReasoningControl> {
+    if let Some(metadata) = model_metadata(model_id) {
+        return metadata.reasoning;
+    }
+
+    let normalized = normalize_model_id(model_id);
+    if ["o1", "o3", "o4", "gpt-5", "gpt-oss"]
+        .iter()
+        .any(|family| normalized.starts_with(family))
+    {
+        Some(ReasoningControl::Effort)
+    } else if normalized.starts_with("qwen3") {
+        Some(ReasoningControl::EnableThinking)
+    } else {
+        None
+    }
+}
+
+/// A model configured for an agent
+pub struct AgentModel<'a> {
+    pub agent_id: &'a str,
+    pub model: &'a str,
+    pub context_window: Option<u32>,
+}
+
+/// A provider model merged with agent configuration
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ModelEntry {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context_length: Option<u32>,
+    /// Agents configured to use this model
+    pub agents: Vec<String>,
+    /// Whether the provider currently lists this model
+    pub available: bool,
+    /// Whether the model handles function calling
+    pub supports_tools: bool,
+}
+
+/// Merge the provider's model list with the models agents are configured to use
+///
+/// Agent models match a provider id exactly first, then by `normalize_model_id`. A context length
+/// from the model metadata wins over the provider's, which wins over the agent's `context_window`.
+/// Agent models the provider doesn't list are appended with `available: false`.
+pub fn merge_model_metadata(models: &[ModelInfo], agents: &[AgentModel]) -> Vec<ModelEntry> {
+    let mut entries: Vec<ModelEntry> = models
+        .iter()
+        .map(|m| ModelEntry {
+            id: m.id.clone(),
+            context_length: context_length(&m.id).or(m.context_length),
+            agents: Vec::new(),
+            available: true,
+            supports_tools: supports_tools(&m.id),
+        })
+        .collect();
+
+    for agent in agents {
+        let normalized = normalize_model_id(agent.model);
+        let index = models
+            .iter()
+            .position(|m| m.id == agent.model)
+            .or_else(|| {
+                models
+                    .iter()
+                    .position(|m| normalize_model_id(&m.id) == normalized)
+            })
+            .or_else(|| {
+                entries
+                    .iter()
+                    .position(|e| !e.available && e.id == agent.model)
+            });
+
+        let entry = match index {
+            Some(i) => &mut entries[i],
+            None => {
+                entries.push(ModelEntry {
+                    id: agent.model.to_string(),
+                    context_length: context_length(agent.model),
+                    agents: Vec::new(),
+                    available: false,
+                    supports_tools: supports_tools(agent.model),
+                });
+                entries.last_mut().unwrap()
+            }
+        };
+        entry.agents.push(agent.agent_id.to_string());
+        if entry.context_length.is_none() {
+            entry.context_length = agent.context_window;
+        }
+    }
+
+    for entry in &mut entries {
+        entry.agents.sort();
+    }
+    entries
+}
+
+/// Entries for the models agents are configured to use, without asking the provider
+///
+/// Used in offline mode, where the configured models are assumed to be available.
+pub fn configured_model_entries(agents: &[AgentModel]) -> Vec<ModelEntry> {
+    let mut entries = merge_model_metadata(&[], agents);
+    for entry in &mut entries {
+        entry.available = true;
+    }
+    entries
+}
+
+/// Extract user-friendly connection error message
+pub fn extract_connection_error(error: &reqwest::Error) -> String {
+    if error.is_connect() {
+        "Connection refused - is the API server running?".to_string()
+    } else if error.is_timeout() {
+        "Connection timed out - check if the API server is running and accessible".to_string()
+    } else if error.is_request() {
+        format!("Request error: {}", error)
+    } else {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_models_url() {
+        assert_eq!(
+            models_url("http://127.0.0.1:1234/v1"),
+            "http://127.0.0.1:1234/v1/models"
+        );
+        assert_eq!(
+            models_url("http://127.0.0.1:1234/v1/"),
+            "http://127.0.0.1:1234/v1/models"
+        );
+    }
+
+    #[test]
+    fn test_context_length_from_provider_fields() {
+        let json = r#"{"data": [
+            {"id": "qwen3.5-4b", "max_context_length": 32768},
+            {"id": "openai/gpt-4o", "context_length": 128000},
+            {"id": "plain-model"}
+        ]}"#;
+        let response: ModelsResponse = serde_json::from_str(json).unwrap();
+
+        assert_eq!(
+            context_length_for(&response.data, "qwen3.5-4b"),
+            Some(32768)
+        );
+        assert_eq!(
+            context_length_for(&response.data, "openai/gpt-4o"),
+            Some(128000)
+        );
+        assert_eq!(context_length_for(&response.data, "plain-model"), None);
+        assert_eq!(context_length_for(&response.data, "missing"), None);
+    }
+
+    fn model(id: &str, context_length: Option<u32>) -> ModelInfo {
+        ModelInfo {
+            id: id.to_string(),
+            context_length,
+        }
+    }
+
+    #[test]
+    fn test_normalize_model_id() {
+        assert_eq!(normalize_model_id("Qwen/Qwen3.5-4B"), "qwen3.5-4b");
+        assert_eq!(normalize_model_id("llama3.2:latest"), "llama3.2");
+        assert_eq!(normalize_model_id("models/phi_4.gguf"), "phi-4");
+        assert_eq!(normalize_model_id("gpt-4o"), "gpt-4o");
+    }
+
+    #[test]
+    fn test_merge_matches_aliases_and_fuzzy_ids() {
+        let models = vec![
+            model("qwen/qwen3.5-4b", Some(32768)),
+            model("llama3.2:latest", None),
+            model("gpt-4o", Some(128000)),
+        ];
+        let agents = [
+            AgentModel {
+                agent_id: "coder",
+                model: "qwen3.5-4b",
+                context_window: Some(8192),
+            },
+            AgentModel {
+                agent_id: "assistant",
+                model: "Qwen/Qwen3.5-4B",
+                context_window: None,
+            },
+            AgentModel {
+                agent_id: "chat",
+                model: "llama3.2",
+                context_window: Some(4096),
+            },
+            AgentModel {
+                agent_id: "offline",
+                model: "mistral-7b",
+                context_window: Some(16384),
+            },
+            AgentModel {
+                agent_id: "offline-2",
+                model: "mistral-7b",
+                context_window: None,
+            },
+        ];
+
+        let merged = merge_model_metadata(&models, &agents);
+        assert_eq!(merged.len(), 4);
+
+        // Provider context length wins over the agent's configured window
+        assert_eq!(merged[0].id, "qwen/qwen3.5-4b");
+        assert_eq!(merged[0].agents, vec!["assistant", "coder"]);
+        assert_eq!(merged[0].context_length, Some(32768));
+
+        // Agent window fills in when the provider doesn't report one
+        assert_eq!(merged[1].agents, vec!["chat"]);
+        assert_eq!(merged[1].context_length, Some(4096));
+
+        assert!(merged[2].agents.is_empty());
+        assert!(merged[2].available);
+
+        // Configured models the provider doesn't list are still shown, once
+        assert_eq!(merged[3].id, "mistral-7b");
+        assert!(!merged[3].available);
+        assert_eq!(merged[3].agents, vec!["offline", "offline-2"]);
+    }
+
+    #[test]
+    fn test_configured_model_entries() {
+        let agents = [
+            AgentModel {
+                agent_id: "coder",
+                model: "qwen3.5-4b",
+                context_window: Some(8192),
+            },
+            AgentModel {
+                agent_id: "chat",
+                model: "qwen3.5-4b",
+                context_window: None,
+            },
+        ];
+        let entries = configured_model_entries(&agents);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].agents, vec!["chat", "coder"]);
+        assert_eq!(entries[0].context_length, Some(8192));
+        assert!(entries[0].available);
+    }
+
+    #[test]
+    fn test_supports_tools_from_metadata() {
+        assert!(!supports_tools("gemma-2-9b-it"));
+        assert!(!supports_tools("deepseek/DeepSeek-R1-Distill-Qwen-7B"));
+        assert!(!supports_tools("gemma2:latest"));
+        assert!(supports_tools("qwen2.5-coder-7b-instruct"));
+        assert!(supports_tools("some-unknown-model"));
+        // The full models share a name with the distills and phi-2 only after normalization
+        assert!(supports_tools("deepseek/deepseek-r1"));
+        assert!(supports_tools("microsoft/phi"));
+
+        let merged = merge_model_metadata(&[model("phi-2", None), model("gpt-4o", None)], &[]);
+        assert!(!merged[0].supports_tools);
+        assert!(merged[1].supports_tools);
+    }
+
+    #[test]
+    fn test_reasoning_control() {
+        assert_eq!(reasoning_control("o3-mini"), Some(ReasoningControl::Effort));
+        assert_eq!(
+            reasoning_control("openai/gpt-oss-20b"),
+            Some(ReasoningControl::Effort)
+        );
+        assert_eq!(
+            reasoning_control("Qwen/Qwen3-8B-GGUF"),
+            Some(ReasoningControl::EnableThinking)
+        );
+        // Listed without reasoning: the coder models don't think
+        assert_eq!(reasoning_control("qwen3-coder"), None);
+        assert_eq!(reasoning_control("gpt-4o"), None);
+    }
+
+    fn model_override(id: &str) -> ModelOverride {
+        ModelOverride {
+            id: id.to_string(),
+            aliases: Vec::new(),
+            supports_tools: None,
+            reasoning: None,
+            context_length: None,
+            pricing_model: None,
+        }
+    }
+
+    #[test]
+    fn test_overrides_merge_over_embedded_metadata() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(
+            temp.path().join("models.json"),
+            r#"[
+                {"id": "qwen3", "context_length": 40960, "pricing_model": "qwen/qwen3-8b"},
+                {"id": "gemma-2-9b", "supports_tools": true}
+            ]"#,
+        )
+        .unwrap();
+        let config = Config {
+            config_dir: Some(temp.path().to_path_buf()),
+            models: crate::config::ModelsConfig {
+                metadata_path: Some("models.json".to_string()),
+                overrides: vec![ModelOverride {
+                    context_length: Some(32768),
+                    ..model_override("Qwen3-8B")
+                }],
+            },
+            ..Default::default()
+        };
+        let metadata = metadata_with_overrides(&config);
+        let find = |id: &str| &metadata[position(&metadata, id).unwrap()];
+
+        // Inline overrides win over the file, and fields they leave out keep the embedded value
+        let qwen = find("Qwen/Qwen3-8B:latest");
+        assert_eq!(qwen.id, "qwen3-8b");
+        assert_eq!(qwen.context_length, Some(32768));
+        assert_eq!(qwen.pricing_model.as_deref(), Some("qwen/qwen3-8b"));
+        assert_eq!(qwen.reasoning, Some(ReasoningControl::EnableThinking));
+        assert!(qwen.supports_tools);
+
+        // Matched by alias; the rest of the embedded list is unchanged
+        assert!(find("gemma2").supports_tools);
+        assert!(!find("gemma-2-2b").supports_tools);
+        assert_eq!(metadata.len(), embedded_metadata().len());
+    }
+
+    #[test]
+    fn test_override_aliases_win_over_embedded_entries() {
+        let overrides = vec![ModelOverride {
+            aliases: vec!["qwen3-coder".to_string()],
+            supports_tools: Some(false),
+            context_length: Some(65536),
+            ..model_override("acme-coder-ft")
+        }];
+        let metadata = apply_overrides(embedded_metadata(), &overrides);
+        let find = |id: &str| &metadata[position(&metadata, id).unwrap()];
+
+        assert_eq!(find("qwen3-coder").id, "acme-coder-ft");
+        assert_eq!(
+            find("Acme/acme-coder-ft:latest").context_length,
+            Some(65536)
+        );
+        assert!(!find("acme_coder_ft.gguf").supports_tools);
+        // The embedded entry is still found by its own id
+        assert_eq!(
+            find("qwen3-coder-30b-a3b-instruct").id,
+            "qwen3-coder-30b-a3b-instruct"
+        );
+        assert!(find("qwen3-coder-30b-a3b-instruct").supports_tools);
+    }
+
+    #[test]
+    fn test_invalid_override_file_falls_back_to_embedded_metadata() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("models.json"), r#"{"id": "not-a-list"}"#).unwrap();
+        let mut config = Config {
+            config_dir: Some(temp.path().to_path_buf()),
+            models: crate::config::ModelsConfig {
+                metadata_path: Some("models.json".to_string()),
+                overrides: vec![ModelOverride {
+                    supports_tools: Some(false),
+                    ..model_override("my-model")
+                }],
+            },
+            ..Default::default()
+        };
+        assert!(read_overrides(&temp.path().join("models.json")).is_err());
+
+        let metadata = metadata_with_overrides(&config);
+        assert_eq!(metadata.len(), embedded_metadata().len() + 1);
+        assert!(!metadata[position(&metadata, "my-model").unwrap()].supports_tools);
+        assert!(position(&metadata, "phi-2").is_some());
+
+        config.models.metadata_path = Some("missing.json".to_string());
+        assert_eq!(
+            metadata_with_overrides(&config).len(),
+            embedded_metadata().len() + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_model_cache_serves_stale_list_on_error() {
+        let cache = ModelCache::new(Duration::from_secs(60));
+
+        // Nothing cached yet: the error is returned
+        let result = cache
+            .get(false, || async { Err("HTTP 500".to_string()) })
+            .await;
+        assert_eq!(result.unwrap_err(), "HTTP 500");
+
+        let fresh = cache
+            .get(false, || async { Ok(vec![model("a", None)]) })
+            .await
+            .unwrap();
+        assert!(!fresh.stale);
+
+        // Within the TTL the provider isn't called
+        let cached = cache
+            .get(false, || async { panic!("should use the cache") })
+            .await
+            .unwrap();
+        assert_eq!(cached.models[0].id, "a");
+        assert!(!cached.stale);
+
+        // A forced refresh that fails falls back to the last known list
+        let stale = cache
+            .get(true, || async { Err("HTTP 500".to_string()) })
+            .await
+            .unwrap();
+        assert!(stale.stale);
+        assert_eq!(stale.models[0].id, "a");
+    }
+
+    #[test]
+    fn test_context_window_prefers_what_the_model_reports() {
+        let window = resolve_context_window(Some(131072), Some(32768), 8192);
+        assert_eq!(window.tokens, 131072);
+        assert_eq!(window.source, ContextWindowSource::Metadata);
+
+        let window = resolve_context_window(None, Some(32768), 8192);
+        assert_eq!(window.tokens, 32768);
+        assert_eq!(window.source, ContextWindowSource::Provider);
+        assert_eq!(window.configured, 8192);
+
+        let window = resolve_context_window(None, None, 8192);
+        assert_eq!(window.tokens, 8192);
+        assert_eq!(window.source, ContextWindowSource::Config);
+    }
+
+    #[test]
+    fn test_context_window_of_an_embedded_model() {
+        assert_eq!(context_length("openai/gpt-oss-20b"), Some(131072));
+        assert_eq!(context_length("gemma2:latest"), Some(8192));
+
+        let window = resolve_context_window(
+            context_length("qwen3-coder-30b-a3b-instruct"),
+            Some(32768),
+            8192,
+        );
+        assert_eq!(window.tokens, 262144);
+        assert_eq!(window.source, ContextWindowSource::Metadata);
+        assert!(window.mismatched());
+    }
+
+    #[test]
+    fn test_context_window_mismatch_needs_more_than_double() {
+        assert!(resolve_context_window(None, Some(32768), 8192).mismatched());
+        assert!(resolve_context_window(Some(4096), None, 32768).mismatched());
+        assert!(!resolve_context_window(None, Some(16384), 8192).mismatched());
+        assert!(!resolve_context_window(None, Some(8192), 8192).mismatched());
+        // Nothing known about the model: nothing to disagree with
+        assert!(!resolve_context_window(None, None, 0).mismatched());
+    }
+
+    #[tokio::test]
+    async fn test_model_cache_context_length_never_fetches() {
+        let cache = ModelCache::new(Duration::ZERO);
+        assert_eq!(cache.context_length("a"), None);
+        cache
+            .get(false, || async { Ok(vec![model("a", Some(32768))]) })
+            .await
+            .unwrap();
+        // An expired list still answers
+        assert_eq!(cache.context_length("a"), Some(32768));
+        assert_eq!(cache.context_length("b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_model_cache_refetches_after_ttl() {
+        let cache = ModelCache::new(Duration::ZERO);
+        cache
+            .get(false, || async { Ok(vec![model("a", None)]) })
+            .await
+            .unwrap();
+        let refreshed = cache
+            .get(false, || async { Ok(vec![model("b", None)]) })
+            .await
+            .unwrap();
+        assert_eq!(refreshed.models[0].id, "b");
+    }
+}
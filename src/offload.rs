@@ -0,0 +1,190 @@
+//! Large tool arguments kept out of stream events and saved thinking steps
+//!
+//! A `write_file` call can carry hundreds of kilobytes of content. Sending it in the approval
+//! request, again in the completion event and storing it in every thinking step multiplies
+//! that size, so string arguments over [`OFFLOAD_THRESHOLD`] bytes are stored once in
+//! `file_contents` and replaced by a reference:
+//!
+//! ```json
+//! {"content_ref": 12, "size": 307200, "sha256": "…", "preview": "first 2 KB"}
+//! ```
+//!
+//! The tool itself still runs with the full content. When a model passes a reference back as
+//! an argument, [`resolve_arguments`] puts the content in its place; the server only resolves
+//! references stored for the same session and tool call.
+
+use log::warn;
+use serde_json::{Map, Value, json};
+use std::fmt::Display;
+
+/// String arguments longer than this many bytes are offloaded
+pub const OFFLOAD_THRESHOLD: usize = 32 * 1024;
+
+/// Bytes of the content kept inline in a reference
+const PREVIEW_BYTES: usize = 2 * 1024;
+
+/// Replace top-level string arguments longer than [`OFFLOAD_THRESHOLD`] with content refs
+///
+/// `store` saves a content and returns its id. An argument that can't be stored stays inline.
+pub fn offload_arguments<E: Display>(
+    args: &Value,
+    mut store: impl FnMut(&str) -> Result<i64, E>,
+) -> Value {
+    let Some(object) = args.as_object() else {
+        return args.clone();
+    };
+
+    let mut offloaded = Map::with_capacity(object.len());
+    for (key, value) in object {
+        let value = match value.as_str() {
+            Some(text) if text.len() > OFFLOAD_THRESHOLD => match store(text) {
+                Ok(id) => content_ref(id, text),
+                Err(e) => {
+                    warn!("Failed to offload tool argument '{}': {}", key, e);
+                    value.clone()
+                }
+            },
+            _ => value.clone(),
+        };
+        offloaded.insert(key.clone(), value);
+    }
+    Value::Object(offloaded)
+}
+
+/// The reference standing in for `content`, stored as `id`
+fn content_ref(id: i64, content: &str) -> Value {
+    let end = content.floor_char_boundary(PREVIEW_BYTES);
+    json!({
+        "content_ref": id,
+        "size": content.len(),
+        "sha256": crate::db::content_hash(content),
+        "preview": &content[..end],
+    })
+}
+
+/// Id of the stored content `value` refers to, if it is a content ref
+pub fn content_ref_id(value: &Value) -> Option<i64> {
+    value.as_object()?.get("content_ref")?.as_i64()
+}
+
+/// Whether any top-level argument is a content ref
+pub fn has_content_refs(args: &Value) -> bool {
+    args.as_object()
+        .is_some_and(|object| object.values().any(|value| content_ref_id(value).is_some()))
+}
+
+/// Put the stored content back in place of every content ref in `args`
+///
+/// `load` fetches a stored content by id. Fails when a content is gone, or when the reference
+/// has no checksum or one that doesn't match.
+pub fn resolve_arguments<E: Display>(
+    args: &Value,
+    mut load: impl FnMut(i64) -> Result<Option<String>, E>,
+) -> Result<Value, String> {
+    let Some(object) = args.as_object() else {
+        return Ok(args.clone());
+    };
+
+    let mut resolved = Map::with_capacity(object.len());
+    for (key, value) in object {
+        let value = match content_ref_id(value) {
+            Some(id) => {
+                let content = load(id)
+                    .map_err(|e| format!("Failed to load stored content {}: {}", id, e))?
+                    .ok_or_else(|| format!("Stored content {} isn't available to this call", id))?;
+                let sha256 = value["sha256"]
+                    .as_str()
+                    .ok_or_else(|| format!("Content ref {} has no sha256", id))?;
+                if sha256 != crate::db::content_hash(&content) {
+                    return Err(format!("Stored content {} doesn't match its checksum", id));
+                }
+                Value::String(content)
+            }
+            None => value.clone(),
+        };
+        resolved.insert(key.clone(), value);
+    }
+    Ok(Value::Object(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_only_arguments_over_the_threshold_are_offloaded() {
+        let at_limit = "a".repeat(OFFLOAD_THRESHOLD);
+        let over_limit = "b".repeat(OFFLOAD_THRESHOLD + 1);
+        let args = json!({"path": "big.txt", "content": over_limit, "note": at_limit});
+
+        let mut stored = Vec::new();
+        let offloaded = offload_arguments(&args, |content| {
+            stored.push(content.to_string());
+            Ok::<_, String>(7)
+        });
+
+        assert_eq!(stored, vec![over_limit.clone()]);
+        assert_eq!(offloaded["path"], "big.txt");
+        assert_eq!(offloaded["note"], at_limit.as_str());
+        assert_eq!(offloaded["content"]["content_ref"], 7);
+        assert_eq!(offloaded["content"]["size"], OFFLOAD_THRESHOLD + 1);
+        assert_eq!(
+            offloaded["content"]["sha256"],
+            crate::db::content_hash(&over_limit)
+        );
+        assert_eq!(
+            offloaded["content"]["preview"].as_str().unwrap().len(),
+            PREVIEW_BYTES
+        );
+        assert!(has_content_refs(&offloaded));
+        assert!(!has_content_refs(&args));
+    }
+
+    #[test]
+    fn test_preview_stops_at_a_char_boundary() {
+        let content = "é".repeat(OFFLOAD_THRESHOLD);
+        let offloaded = offload_arguments(&json!({"content": content}), |_| Ok::<_, String>(1));
+        let preview = offloaded["content"]["preview"].as_str().unwrap();
+        assert_eq!(preview.len(), PREVIEW_BYTES);
+        assert!(preview.chars().all(|c| c == 'é'));
+    }
+
+    #[test]
+    fn test_failed_store_keeps_the_argument_inline() {
+        let args = json!({"content": "c".repeat(OFFLOAD_THRESHOLD + 1)});
+        let offloaded = offload_arguments(&args, |_| Err("disk full"));
+        assert_eq!(offloaded, args);
+    }
+
+    #[test]
+    fn test_refs_resolve_back_to_the_stored_content() {
+        let content = "line\n".repeat(OFFLOAD_THRESHOLD);
+        let args = json!({"path": "big.txt", "content": content});
+        let mut store = HashMap::new();
+        let offloaded = offload_arguments(&args, |text| {
+            store.insert(3, text.to_string());
+            Ok::<_, String>(3)
+        });
+
+        let resolved =
+            resolve_arguments(&offloaded, |id| Ok::<_, String>(store.get(&id).cloned())).unwrap();
+        assert_eq!(resolved, args);
+
+        let missing = resolve_arguments(&offloaded, |_| Ok::<_, String>(None)).unwrap_err();
+        assert!(missing.contains("isn't available"));
+
+        let tampered =
+            resolve_arguments(&offloaded, |_| Ok::<_, String>(Some("other".to_string())));
+        assert!(tampered.unwrap_err().contains("checksum"));
+
+        let mut unchecked = offloaded.clone();
+        unchecked["content"]
+            .as_object_mut()
+            .unwrap()
+            .remove("sha256");
+        let unchecked =
+            resolve_arguments(&unchecked, |id| Ok::<_, String>(store.get(&id).cloned()));
+        assert!(unchecked.unwrap_err().contains("no sha256"));
+    }
+}
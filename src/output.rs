@@ -0,0 +1,139 @@
+//! CLI output channels and command results
+//!
+//! Answers and command results go to stdout; banners, progress, spinners and errors go to
+//! stderr, so `squid ask "..." > answer.md` captures only the answer. `--quiet` hides the
+//! stderr chatter but keeps errors.
+
+use indicatif::{ProgressBar, ProgressStyle};
+use serde::Serialize;
+use std::fmt;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::SquidError;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Suppress status output for the rest of the process (`--quiet`)
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Print a status line to stderr unless `--quiet` was given
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            eprintln!($($arg)*);
+        }
+    };
+}
+pub(crate) use status;
+
+/// A spinner on stderr; hidden with `--quiet` or when stderr isn't a terminal
+pub fn spinner(message: &str) -> ProgressBar {
+    if is_quiet() || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let spinner = ProgressBar::new_spinner();
+    spinner.set_style(
+        ProgressStyle::default_spinner()
+            .template("{spinner:.cyan} {msg}")
+            .unwrap(),
+    );
+    spinner.set_message(message.to_string());
+    spinner.enable_steady_tick(std::time::Duration::from_millis(80));
+    spinner
+}
+
+/// A progress bar on stderr for `len` items; hidden with `--quiet`
+pub fn progress_bar(len: u64, message: &str) -> ProgressBar {
+    if is_quiet() {
+        return ProgressBar::hidden();
+    }
+    let bar = ProgressBar::new(len);
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template("{msg} [{bar:30.cyan/blue}] {pos}/{len}")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    bar.set_message(message.to_string());
+    bar
+}
+
+/// Print a value as pretty JSON to stdout
+pub fn print_json<T: Serialize>(value: &T) -> CommandResult {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize response - {}", e))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Why a command failed; `main` turns it into a non-zero exit code
+#[derive(Debug)]
+pub enum CommandError {
+    /// Printed to stderr as `🦑: <message>`
+    Message(String),
+    /// A typed error from another module; its variant picks the exit code and a hint
+    Failed {
+        error: SquidError,
+        /// What was being done, printed before the error (`<context> - <error>`)
+        context: Option<String>,
+    },
+    /// The details were already printed (e.g. a diagnostics report)
+    Reported,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Message(message) => write!(f, "{}", message),
+            CommandError::Failed {
+                error,
+                context: Some(context),
+            } => write!(f, "{} - {}", context, error),
+            CommandError::Failed {
+                error,
+                context: None,
+            } => write!(f, "{}", error),
+            CommandError::Reported => write!(f, "command failed"),
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Message(message)
+    }
+}
+
+impl CommandError {
+    /// `error`, reported as `<context> - <error>`
+    pub fn failed(context: impl Into<String>, error: SquidError) -> Self {
+        CommandError::Failed {
+            error,
+            context: Some(context.into()),
+        }
+    }
+}
+
+impl From<SquidError> for CommandError {
+    fn from(error: SquidError) -> Self {
+        CommandError::Failed {
+            error,
+            context: None,
+        }
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Message(message.to_string())
+    }
+}
+
+pub type CommandResult = Result<(), CommandError>;
@@ -0,0 +1,615 @@
+//! Unified diff parsing and application for the `apply_patch` tool
+//!
+//! Hunks are located by their context and removed lines, starting at the line number in the
+//! hunk header and searching outwards, so patches still apply when earlier edits shifted the
+//! file. Hunks that can't be found are rejected and reported the way `patch` does, and a file
+//! is only written when all of its hunks apply.
+
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+
+use crate::validate::PathValidator;
+
+/// How far from the header's line number a hunk may be found
+const MAX_OFFSET: usize = 200;
+
+/// Changes to one file
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilePatch {
+    /// Path from the `---` header, `None` for `/dev/null` (new file)
+    pub old_path: Option<String>,
+    /// Path from the `+++` header, `None` for `/dev/null` (deleted file)
+    pub new_path: Option<String>,
+    pub hunks: Vec<Hunk>,
+}
+
+impl FilePatch {
+    /// The file the patch changes
+    pub fn target(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or("")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hunk {
+    /// The `@@ -a,b +c,d @@` line
+    pub header: String,
+    /// 1-based start line in the original file
+    pub old_start: usize,
+    pub lines: Vec<HunkLine>,
+    /// The new side has "\ No newline at end of file"
+    pub new_missing_newline: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum HunkLine {
+    Context(String),
+    Remove(String),
+    Add(String),
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Remove(text) => Some(text.as_str()),
+                HunkLine::Add(_) => None,
+            })
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter_map(|line| match line {
+                HunkLine::Context(text) | HunkLine::Add(text) => Some(text.as_str()),
+                HunkLine::Remove(_) => None,
+            })
+            .collect()
+    }
+
+    /// The hunk as it appeared in the diff
+    fn text(&self) -> String {
+        let mut text = format!("{}\n", self.header);
+        for line in &self.lines {
+            let (prefix, content) = match line {
+                HunkLine::Context(content) => (' ', content),
+                HunkLine::Remove(content) => ('-', content),
+                HunkLine::Add(content) => ('+', content),
+            };
+            text.push(prefix);
+            text.push_str(content);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// Parse a unified diff covering one or more files
+pub fn parse(diff: &str) -> Result<Vec<FilePatch>, String> {
+    let lines: Vec<&str> = diff.lines().collect();
+    let mut patches = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let Some(old_header) = lines[i].strip_prefix("--- ") else {
+            // `diff --git`, `index` and other preamble lines carry nothing we need
+            i += 1;
+            continue;
+        };
+        let new_header = lines
+            .get(i + 1)
+            .and_then(|line| line.strip_prefix("+++ "))
+            .ok_or_else(|| format!("Line {}: expected '+++' after '---'", i + 2))?;
+        i += 2;
+
+        let mut patch = FilePatch {
+            old_path: header_path(old_header),
+            new_path: header_path(new_header),
+            hunks: Vec::new(),
+        };
+        if patch.old_path.is_none() && patch.new_path.is_none() {
+            return Err(format!("Line {}: both file paths are /dev/null", i - 1));
+        }
+
+        while i < lines.len() && lines[i].starts_with("@@") {
+            let (hunk, next) = parse_hunk(&lines, i)?;
+            patch.hunks.push(hunk);
+            i = next;
+        }
+        if patch.hunks.is_empty() {
+            return Err(format!("No hunks for {}", patch.target()));
+        }
+        patches.push(patch);
+    }
+
+    if patches.is_empty() {
+        return Err("No file headers ('--- a/file', '+++ b/file') found in patch".to_string());
+    }
+    Ok(patches)
+}
+
+/// Path from a `---`/`+++` header without the git `a/`/`b/` prefix or a trailing timestamp
+fn header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next().unwrap_or(header).trim();
+    if path == "/dev/null" {
+        return None;
+    }
+    let path = path
+        .strip_prefix("a/")
+        .or_else(|| path.strip_prefix("b/"))
+        .unwrap_or(path);
+    Some(path.to_string())
+}
+
+/// Parse `@@ -a,b +c,d @@` into `(a, b, d)`; omitted counts default to 1
+fn parse_hunk_header(header: &str) -> Option<(usize, usize, usize)> {
+    let ranges = header.strip_prefix("@@ ")?.split(" @@").next()?;
+    let (old, new) = ranges.split_once(' ')?;
+    let range = |range: &str, sign: char| -> Option<(usize, usize)> {
+        let range = range.strip_prefix(sign)?;
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old, '-')?;
+    let (_, new_count) = range(new, '+')?;
+    Some((old_start, old_count, new_count))
+}
+
+fn parse_hunk(lines: &[&str], start: usize) -> Result<(Hunk, usize), String> {
+    let header = lines[start];
+    let (old_start, mut old_left, mut new_left) = parse_hunk_header(header)
+        .ok_or_else(|| format!("Line {}: invalid hunk header '{}'", start + 1, header))?;
+
+    let mut hunk = Hunk {
+        header: header.to_string(),
+        old_start,
+        lines: Vec::new(),
+        new_missing_newline: false,
+    };
+    let mut i = start + 1;
+    while old_left > 0 || new_left > 0 {
+        let Some(&line) = lines.get(i) else {
+            return Err(format!("Hunk '{}' ends early", header));
+        };
+        // Editors often strip the single space of empty context lines
+        let (tag, content) = match line.chars().next() {
+            Some(tag) => (tag, &line[tag.len_utf8()..]),
+            None => (' ', ""),
+        };
+        match tag {
+            ' ' if old_left > 0 && new_left > 0 => {
+                hunk.lines.push(HunkLine::Context(content.to_string()));
+                old_left -= 1;
+                new_left -= 1;
+            }
+            '-' if old_left > 0 => {
+                hunk.lines.push(HunkLine::Remove(content.to_string()));
+                old_left -= 1;
+            }
+            '+' if new_left > 0 => {
+                hunk.lines.push(HunkLine::Add(content.to_string()));
+                new_left -= 1;
+            }
+            '\\' => {}
+            _ => {
+                return Err(format!(
+                    "Line {}: unexpected line in hunk '{}' (line counts don't match the header)",
+                    i + 1,
+                    header
+                ));
+            }
+        }
+        i += 1;
+    }
+
+    // "\ No newline at end of file" follows the last line it applies to
+    if lines.get(i).is_some_and(|line| line.starts_with('\\')) {
+        hunk.new_missing_newline = !matches!(hunk.lines.last(), Some(HunkLine::Remove(_)));
+        i += 1;
+    }
+    Ok((hunk, i))
+}
+
+/// A hunk that applied
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AppliedHunk {
+    pub hunk: usize,
+    /// Lines between the header's position and where the hunk was found
+    pub offset: i64,
+}
+
+/// A hunk that couldn't be located
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct RejectedHunk {
+    pub hunk: usize,
+    pub reason: String,
+    /// The hunk text, as `patch` writes to a `.rej` file
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApplyOutcome {
+    pub content: String,
+    pub applied: Vec<AppliedHunk>,
+    pub rejected: Vec<RejectedHunk>,
+}
+
+/// Apply `patch`'s hunks to `original`
+pub fn apply(original: &str, patch: &FilePatch) -> ApplyOutcome {
+    let mut lines: Vec<String> = original.lines().map(String::from).collect();
+    let mut trailing_newline = original.is_empty() || original.ends_with('\n');
+    let mut applied = Vec::new();
+    let mut rejected = Vec::new();
+    // Shift from earlier hunks, and the first line later hunks may touch
+    let mut delta: i64 = 0;
+    let mut min_start = 0;
+
+    for (index, hunk) in patch.hunks.iter().enumerate() {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+        // An empty old side (`-0,0`) inserts after line `old_start`
+        let header_start = if old.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let expected = (header_start as i64 + delta).max(0) as usize;
+
+        match find_block(&lines, &old, expected, min_start) {
+            Some(start) => {
+                let end = start + old.len();
+                let touches_end = end == lines.len();
+                lines.splice(start..end, new.iter().map(|line| line.to_string()));
+                if touches_end {
+                    trailing_newline = !hunk.new_missing_newline;
+                }
+                applied.push(AppliedHunk {
+                    hunk: index + 1,
+                    offset: start as i64 - (header_start as i64 + delta),
+                });
+                delta += new.len() as i64 - old.len() as i64;
+                min_start = start + new.len();
+            }
+            None => rejected.push(RejectedHunk {
+                hunk: index + 1,
+                reason: format!(
+                    "Could not find the hunk's context within {} lines of line {}",
+                    MAX_OFFSET, hunk.old_start
+                ),
+                text: hunk.text(),
+            }),
+        }
+    }
+
+    let mut content = lines.join("\n");
+    if trailing_newline && !lines.is_empty() {
+        content.push('\n');
+    }
+    ApplyOutcome {
+        content,
+        applied,
+        rejected,
+    }
+}
+
+/// Find `block` in `lines` at or after `min_start`, trying `expected` first, then moving outwards
+fn find_block(
+    lines: &[String],
+    block: &[&str],
+    expected: usize,
+    min_start: usize,
+) -> Option<usize> {
+    let last_start = lines.len().checked_sub(block.len())?;
+    let matches = |start: usize| {
+        start >= min_start
+            && start <= last_start
+            && lines[start..start + block.len()]
+                .iter()
+                .zip(block)
+                .all(|(line, expected)| line == expected)
+    };
+
+    let expected = expected.min(last_start);
+    (0..=MAX_OFFSET).find_map(|offset| {
+        if matches(expected + offset) {
+            Some(expected + offset)
+        } else if offset > 0 && offset <= expected && matches(expected - offset) {
+            Some(expected - offset)
+        } else {
+            None
+        }
+    })
+}
+
+/// Every path named in `diff`'s file headers; empty when the diff doesn't parse
+pub fn paths(diff: &str) -> Vec<String> {
+    parse(diff)
+        .unwrap_or_default()
+        .into_iter()
+        .flat_map(|patch| [patch.old_path, patch.new_path])
+        .flatten()
+        .collect()
+}
+
+/// A file patch with its validated source and destination
+struct Target {
+    patch: FilePatch,
+    source: Option<PathBuf>,
+    dest: Option<PathBuf>,
+}
+
+/// Run the `apply_patch` tool: validate every target, then apply each file's hunks
+///
+/// Nothing is written when any path fails validation. Files with rejected hunks are left
+/// unchanged and reported; the other files are written unless `dry_run` is set.
+pub fn execute(diff: &str, dry_run: bool, validator: &PathValidator) -> Value {
+    let patches = match parse(diff) {
+        Ok(patches) => patches,
+        Err(e) => return json!({"error": format!("Invalid patch: {}", e)}),
+    };
+
+    let validate = |path: &Option<String>| -> Result<Option<PathBuf>, Value> {
+        path.as_deref()
+            .map(|path| {
+                validator.validate(Path::new(path)).map_err(|e| {
+                    json!({"error": format!("Path '{}' in patch is not accessible: {}", path, e)})
+                })
+            })
+            .transpose()
+    };
+    let mut targets = Vec::new();
+    for patch in patches {
+        let source = match validate(&patch.old_path) {
+            Ok(path) => path,
+            Err(error) => return error,
+        };
+        let dest = match validate(&patch.new_path) {
+            Ok(path) => path,
+            Err(error) => return error,
+        };
+        targets.push(Target {
+            patch,
+            source,
+            dest,
+        });
+    }
+
+    let files: Vec<Value> = targets
+        .iter()
+        .map(|target| apply_file(target, dry_run, validator))
+        .collect();
+
+    let failed = files.iter().filter(|f| f["status"] == "failed").count();
+    let message = match (dry_run, failed) {
+        (true, 0) => format!("Patch would apply cleanly to {} file(s)", files.len()),
+        (true, n) => format!("Patch would fail for {} of {} file(s)", n, files.len()),
+        (false, 0) => format!("Patch applied to {} file(s)", files.len()),
+        (false, n) => format!(
+            "Patch failed for {} of {} file(s); those files were left unchanged",
+            n,
+            files.len()
+        ),
+    };
+    json!({
+        "success": failed == 0,
+        "dry_run": dry_run,
+        "message": message,
+        "files": files,
+    })
+}
+
+fn apply_file(target: &Target, dry_run: bool, validator: &PathValidator) -> Value {
+    let display = target
+        .dest
+        .as_ref()
+        .or(target.source.as_ref())
+        .map(|path| path.display().to_string())
+        .unwrap_or_default();
+    let failed = |reason: String| json!({"path": display, "status": "failed", "error": reason});
+
+    let original = match &target.source {
+        Some(source) => match std::fs::read_to_string(source) {
+            Ok(content) => content,
+            Err(e) => return failed(format!("Failed to read file: {}", e)),
+        },
+        None if target.dest.as_ref().is_some_and(|dest| dest.exists()) => {
+            return failed("File already exists".to_string());
+        }
+        None => String::new(),
+    };
+
+    let outcome = apply(&original, &target.patch);
+    if !outcome.rejected.is_empty() {
+        return json!({
+            "path": display,
+            "status": "failed",
+            "hunks_applied": outcome.applied.len(),
+            "rejected": outcome.rejected,
+        });
+    }
+
+    let status = match (&target.source, &target.dest) {
+        (None, _) => "created",
+        (_, None) => "deleted",
+        (Some(source), Some(dest)) if source != dest => "renamed",
+        _ => "modified",
+    };
+    if !dry_run {
+        let mut written = match &target.dest {
+            Some(dest) => dest
+                .parent()
+                .map_or(Ok(()), std::fs::create_dir_all)
+                .map_err(|e| e.to_string())
+                .and_then(|_| {
+                    crate::safe_write::write(validator, dest, outcome.content.as_bytes())
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                }),
+            None => Ok(()),
+        };
+        if matches!(status, "deleted" | "renamed")
+            && let Some(source) = &target.source
+        {
+            written = written.and_then(|_| std::fs::remove_file(source).map_err(|e| e.to_string()));
+        }
+        if let Err(e) = written {
+            return failed(format!("Failed to write file: {}", e));
+        }
+        log::info!("apply_patch {} {}", status, display);
+    }
+
+    json!({
+        "path": display,
+        "status": status,
+        "hunks_applied": outcome.applied.len(),
+        "offsets": outcome.applied,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const ORIGINAL: &str = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\nnine\nten\n";
+
+    fn file_patch(diff: &str) -> FilePatch {
+        parse(diff).unwrap().remove(0)
+    }
+
+    #[test]
+    fn test_clean_apply_multiple_hunks() {
+        let patch = file_patch(
+            "--- a/numbers.txt\n+++ b/numbers.txt\n\
+             @@ -1,3 +1,3 @@\n one\n-two\n+TWO\n three\n\
+             @@ -8,3 +8,4 @@\n eight\n nine\n+nine and a half\n ten\n",
+        );
+        assert_eq!(patch.target(), "numbers.txt");
+
+        let outcome = apply(ORIGINAL, &patch);
+        assert!(outcome.rejected.is_empty());
+        assert_eq!(
+            outcome.content,
+            "one\nTWO\nthree\nfour\nfive\nsix\nseven\neight\nnine\nnine and a half\nten\n"
+        );
+        assert!(outcome.applied.iter().all(|hunk| hunk.offset == 0));
+    }
+
+    #[test]
+    fn test_apply_with_line_offset_drift() {
+        // Two lines were added at the top since the diff was made
+        let drifted = format!("header\nheader\n{}", ORIGINAL);
+        let patch = file_patch(
+            "--- a/numbers.txt\n+++ b/numbers.txt\n@@ -5,3 +5,3 @@\n five\n-six\n+SIX\n seven\n",
+        );
+
+        let outcome = apply(&drifted, &patch);
+        assert!(outcome.rejected.is_empty());
+        assert_eq!(outcome.applied[0].offset, 2);
+        assert!(outcome.content.contains("five\nSIX\nseven"));
+    }
+
+    #[test]
+    fn test_conflicting_hunk_is_rejected() {
+        let patch = file_patch(
+            "--- a/numbers.txt\n+++ b/numbers.txt\n\
+             @@ -1,2 +1,2 @@\n-one\n+ONE\n two\n\
+             @@ -5,3 +5,3 @@\n five\n-SIX\n+6\n seven\n",
+        );
+
+        let outcome = apply(ORIGINAL, &patch);
+        assert_eq!(outcome.applied.len(), 1);
+        assert_eq!(outcome.rejected.len(), 1);
+        assert_eq!(outcome.rejected[0].hunk, 2);
+        assert!(
+            outcome.rejected[0]
+                .text
+                .starts_with("@@ -5,3 +5,3 @@\n five\n-SIX")
+        );
+
+        // The tool leaves a file with rejected hunks untouched
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("numbers.txt"), ORIGINAL).unwrap();
+        let validator = PathValidator::for_working_dir(dir.path());
+        let diff =
+            "--- a/numbers.txt\n+++ b/numbers.txt\n@@ -5,3 +5,3 @@\n five\n-SIX\n+6\n seven\n";
+        let result = execute(diff, false, &validator);
+        assert_eq!(result["success"], false);
+        assert_eq!(result["files"][0]["status"], "failed");
+        assert_eq!(result["files"][0]["rejected"][0]["hunk"], 1);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("numbers.txt")).unwrap(),
+            ORIGINAL
+        );
+    }
+
+    #[test]
+    fn test_multi_file_patch_and_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "alpha\nbeta\n").unwrap();
+        let validator = PathValidator::for_working_dir(dir.path());
+        let diff = "diff --git a/a.txt b/a.txt\nindex 1..2 100644\n\
+                    --- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n alpha\n-beta\n+gamma\n\
+                    --- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,2 @@\n+hello\n+world\n\\ No newline at end of file\n";
+
+        let preview = execute(diff, true, &validator);
+        assert_eq!(preview["success"], true);
+        assert_eq!(preview["files"][1]["status"], "created");
+        assert!(!dir.path().join("new.txt").exists());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "alpha\nbeta\n"
+        );
+
+        let result = execute(diff, false, &validator);
+        assert_eq!(result["success"], true);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("a.txt")).unwrap(),
+            "alpha\ngamma\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("new.txt")).unwrap(),
+            "hello\nworld"
+        );
+    }
+
+    #[test]
+    fn test_path_traversal_in_headers_is_refused() {
+        let parent = tempfile::tempdir().unwrap();
+        let project = parent.path().join("project");
+        fs::create_dir(&project).unwrap();
+        fs::write(parent.path().join("secret.txt"), "keep\n").unwrap();
+        fs::write(project.join("ok.txt"), "x\n").unwrap();
+        let validator = PathValidator::for_working_dir(&project);
+
+        // A valid file first: nothing may be written when any header escapes the project
+        let diff = "--- a/ok.txt\n+++ b/ok.txt\n@@ -1 +1 @@\n-x\n+y\n\
+                    --- a/../secret.txt\n+++ b/../secret.txt\n@@ -1 +1 @@\n-keep\n+owned\n";
+        let result = execute(diff, false, &validator);
+        assert!(result["error"].as_str().unwrap().contains("../secret.txt"));
+        assert_eq!(fs::read_to_string(project.join("ok.txt")).unwrap(), "x\n");
+        assert_eq!(
+            fs::read_to_string(parent.path().join("secret.txt")).unwrap(),
+            "keep\n"
+        );
+
+        // The rename source is checked too
+        let diff = "--- a/../secret.txt\n+++ b/ok.txt\n@@ -1 +1 @@\n-x\n+y\n";
+        assert!(execute(diff, false, &validator)["error"].is_string());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("just some text").is_err());
+        assert!(parse("--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n").is_err());
+        assert!(parse("--- a/x\n@@ -1 +1 @@\n").is_err());
+    }
+}
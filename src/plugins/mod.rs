@@ -1,7 +1,6 @@
 use crate::config::Config;
 use async_openai::types::chat::ChatCompletionTools;
 use async_openai::types::chat::{ChatCompletionTool, FunctionObjectArgs};
-use log::warn;
 use serde_json::Value;
 use std::sync::Arc;
 
@@ -19,6 +18,7 @@ fn to_plugin_system_config(config: Arc<Config>) -> PluginSystemConfig {
         max_memory_mb: config.plugins.max_memory_mb,
         bundled_plugins_dir: crate::bundled::get_bundled_plugins_dir(),
         ignore_file_name: ".squidignore".to_string(),
+        allow_network: !config.network.offline,
     }
 }
 
@@ -62,11 +62,3 @@ pub async fn execute_plugin_tool(
 pub fn plugin_count() -> usize {
     squid_plugins::plugin_count()
 }
-
-pub fn reload_plugins() -> Result<usize, Box<dyn std::error::Error>> {
-    let result = squid_plugins::reload_plugins();
-    if result.is_err() {
-        warn!("Plugin manager not initialized");
-    }
-    result
-}
@@ -0,0 +1,801 @@
+//! Tool permission policy shared by the CLI and the web server
+//!
+//! [`evaluate`] decides whether a tool call runs, needs the user's approval, or is refused.
+//! Checks run in a fixed order: hard security blocks, path validation for file tools, then the
+//! agent's allow list (including plugin capabilities and granular `bash:<command>` entries), so
+//! the same call gets the same answer no matter which front end made it. Validating paths first
+//! means a traversal or ignored path is refused outright and never reaches an approval prompt.
+
+use async_openai::types::chat::ChatCompletionTools;
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::error::SquidError;
+use crate::tools::{DefaultPermission, ToolRegistry, web_path_validator};
+use crate::validate::{PathValidationError, PathValidator};
+
+/// Command fragments that are never executed, regardless of permissions or approval
+const DANGEROUS_BASH_PATTERNS: &[&str] = &[
+    "rm -rf", "rm -f", "sudo ", "chmod ", "dd ", "mkfs", "fdisk", "> /dev/", "curl", "wget",
+    "kill ", "pkill", "killall",
+];
+
+/// Where a tool call comes from; decides how file paths are validated
+#[derive(Debug, Clone, Copy)]
+pub enum Mode<'a> {
+    /// `squid ask`/`review`: paths are anchored to the project root
+    Cli,
+    /// Web UI: paths are anchored to the session's working directory, if it has one
+    Web { working_dir: Option<&'a Path> },
+}
+
+impl Mode<'_> {
+    pub fn path_validator(&self, config: &Config) -> Arc<PathValidator> {
+        match self {
+            Mode::Cli => PathValidator::cached_for_project(config.security.respect_gitignore),
+            Mode::Web { working_dir } => web_path_validator(*working_dir, config),
+        }
+    }
+}
+
+/// Outcome of [`evaluate`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyDecision {
+    /// Run without asking; `path` is the validated path for file tools or the bash `cwd`
+    Allow {
+        path: Option<PathBuf>,
+    },
+    /// Ask the user first
+    NeedsApproval {
+        path: Option<PathBuf>,
+    },
+    Deny(DenyReason),
+}
+
+/// Why a tool call didn't run, as reported in stream events and thinking steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipStatus {
+    /// Refused by the agent's permissions or path rules
+    DeniedByConfig,
+    RejectedByUser,
+    ApprovalTimeout,
+    /// A hard-blocked bash command
+    BlockedDangerous,
+}
+
+impl SkipStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SkipStatus::DeniedByConfig => "denied_by_config",
+            SkipStatus::RejectedByUser => "rejected_by_user",
+            SkipStatus::ApprovalTimeout => "approval_timeout",
+            SkipStatus::BlockedDangerous => "blocked_dangerous",
+        }
+    }
+
+    /// Reverse of [`SkipStatus::as_str`], for statuses read back from thinking steps
+    pub fn parse(status: &str) -> Option<Self> {
+        [
+            SkipStatus::DeniedByConfig,
+            SkipStatus::RejectedByUser,
+            SkipStatus::ApprovalTimeout,
+            SkipStatus::BlockedDangerous,
+        ]
+        .into_iter()
+        .find(|candidate| candidate.as_str() == status)
+    }
+}
+
+impl std::fmt::Display for SkipStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Why a tool call was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum DenyReason {
+    /// The bash command matches a hard-blocked pattern
+    DangerousCommand {
+        pattern: &'static str,
+    },
+    AgentNotFound {
+        agent_id: String,
+    },
+    PluginNotFound {
+        name: String,
+    },
+    /// The plugin needs a capability the agent wasn't granted
+    PluginCapabilityMissing {
+        name: String,
+        capability: String,
+    },
+    /// No `bash` or matching `bash:<command>` entry in the agent's allow list
+    BashNotAllowed {
+        agent_id: String,
+    },
+    ToolNotAllowed {
+        name: String,
+        agent_id: String,
+    },
+    /// A file tool's path failed validation
+    Path {
+        path: String,
+        kind: PathDenial,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathDenial {
+    /// Matched by .squidignore or .gitignore
+    Ignored,
+    /// A protected system location
+    Blacklisted,
+    /// Outside the project or working directory
+    OutsideProject,
+    Other,
+}
+
+impl DenyReason {
+    /// Explanation sent back to the model
+    pub fn message(&self) -> String {
+        match self {
+            DenyReason::DangerousCommand { pattern } => format!(
+                "Command blocked for security reasons. The command contains a dangerous pattern: '{}'. Commands like rm, sudo, chmod, dd, curl, wget, and kill operations are not allowed.",
+                pattern
+            ),
+            DenyReason::AgentNotFound { agent_id } => format!("Agent '{}' not found", agent_id),
+            DenyReason::PluginNotFound { name } => format!("Plugin '{}' not found", name),
+            DenyReason::PluginCapabilityMissing { name, capability } => format!(
+                "Plugin '{}' requires '{}' permission which is not granted to agent",
+                name, capability
+            ),
+            DenyReason::BashNotAllowed { agent_id } => {
+                format!("Bash commands not allowed for agent '{}'", agent_id)
+            }
+            DenyReason::ToolNotAllowed { name, agent_id } => {
+                format!("Tool '{}' not in allow list for agent '{}'", name, agent_id)
+            }
+            DenyReason::Path { path, kind } => match kind {
+                PathDenial::Ignored => format!(
+                    "I cannot access '{}' because it's protected by the project's .squidignore file. This is a security measure to prevent access to sensitive files.",
+                    path
+                ),
+                PathDenial::Blacklisted => format!(
+                    "I cannot access '{}' because it's a protected system file or directory. Access to this location is blocked for security reasons.",
+                    path
+                ),
+                PathDenial::OutsideProject => format!(
+                    "I cannot access '{}' because it's outside the current project directory. I can only access files within the current workspace for security reasons.",
+                    path
+                ),
+                PathDenial::Other => {
+                    format!("I cannot access '{}' due to security restrictions.", path)
+                }
+            },
+        }
+    }
+
+    pub fn skip_status(&self) -> SkipStatus {
+        match self {
+            DenyReason::DangerousCommand { .. } => SkipStatus::BlockedDangerous,
+            _ => SkipStatus::DeniedByConfig,
+        }
+    }
+
+    /// Tool result for the model: path denials read as an answer, the rest as skipped calls
+    pub fn to_tool_result(&self) -> Value {
+        match self {
+            DenyReason::Path { .. } => json!({"content": self.message()}),
+            _ => json!({"error": self.message(), "skipped": true}),
+        }
+    }
+}
+
+/// Decide how a tool call from `agent_id` is handled
+pub fn evaluate(
+    name: &str,
+    args: &Value,
+    agent_id: &str,
+    config: &Config,
+    mode: Mode<'_>,
+) -> PolicyDecision {
+    // Hard blocks can't be bypassed by configuration or approval
+    if name == "bash" {
+        let command = args["command"].as_str().unwrap_or("");
+        if let Some(pattern) = dangerous_pattern(command) {
+            warn!("Blocked dangerous bash command: {}", command);
+            return PolicyDecision::Deny(DenyReason::DangerousCommand { pattern });
+        }
+    }
+
+    let path = match validate_tool_path(name, args, &mode.path_validator(config)) {
+        Ok(path) => path,
+        Err(reason) => return PolicyDecision::Deny(reason),
+    };
+
+    let Some(permissions) = config.get_agent_permissions(agent_id) else {
+        warn!("Agent '{}' not found, denying all tools", agent_id);
+        return PolicyDecision::Deny(DenyReason::AgentNotFound {
+            agent_id: agent_id.to_string(),
+        });
+    };
+    let allows = |entry: &str| permissions.allow.iter().any(|allowed| allowed == entry);
+
+    if name.starts_with("plugin:") {
+        let Some(plugin_meta) = crate::plugins::get_plugin_metadata(name) else {
+            return PolicyDecision::Deny(DenyReason::PluginNotFound {
+                name: name.to_string(),
+            });
+        };
+        if let Some(capability) = plugin_meta
+            .security
+            .requires
+            .iter()
+            .find(|required| !allows(required))
+        {
+            return PolicyDecision::Deny(DenyReason::PluginCapabilityMissing {
+                name: name.to_string(),
+                capability: capability.clone(),
+            });
+        }
+        // Plugins that aren't explicitly allowed need approval
+        return if allows(name) || allows("plugin:*") {
+            PolicyDecision::Allow { path: None }
+        } else {
+            PolicyDecision::NeedsApproval { path: None }
+        };
+    }
+
+    // Allow-only model: anything not in the allow list is denied
+    if name == "bash" {
+        let command = args["command"].as_str().unwrap_or("");
+        if !allows("bash") && !bash_command_allowed(&permissions.allow, command) {
+            return PolicyDecision::Deny(DenyReason::BashNotAllowed {
+                agent_id: agent_id.to_string(),
+            });
+        }
+    } else if !allows(name) {
+        // Tools that default to asking, like demo_tool, wait for the user instead
+        if asks_when_not_allowed(name, config) {
+            return PolicyDecision::NeedsApproval { path };
+        }
+        return PolicyDecision::Deny(DenyReason::ToolNotAllowed {
+            name: name.to_string(),
+            agent_id: agent_id.to_string(),
+        });
+    }
+
+    info!(
+        "Tool '{}' is allowed for agent '{}', auto-approving",
+        name, agent_id
+    );
+    PolicyDecision::Allow { path }
+}
+
+/// Check that `agent_id` may be made to call `name` through `tool_choice`
+///
+/// Forcing a tool that would only be refused wastes a turn, so unknown tools, tools missing
+/// from the allow list and plugins without their capabilities are rejected up front. Plugins
+/// and tools that need approval can be forced; the call still waits for the user.
+pub fn check_forced_tool(name: &str, agent_id: &str, config: &Config) -> Result<(), SquidError> {
+    let offered = crate::tools::get_tools()
+        .iter()
+        .any(|tool| matches!(tool, ChatCompletionTools::Function(t) if t.function.name == name));
+    if !offered {
+        return Err(SquidError::ToolDenied(format!(
+            "Can't force tool '{}': no such tool",
+            name
+        )));
+    }
+
+    let Some(permissions) = config.get_agent_permissions(agent_id) else {
+        return Err(SquidError::ToolDenied(
+            DenyReason::AgentNotFound {
+                agent_id: agent_id.to_string(),
+            }
+            .message(),
+        ));
+    };
+    let allows = |entry: &str| permissions.allow.iter().any(|allowed| allowed == entry);
+
+    let denial = if name.starts_with("plugin:") {
+        crate::plugins::get_plugin_metadata(name).and_then(|plugin_meta| {
+            plugin_meta
+                .security
+                .requires
+                .iter()
+                .find(|required| !allows(required))
+                .map(|capability| DenyReason::PluginCapabilityMissing {
+                    name: name.to_string(),
+                    capability: capability.clone(),
+                })
+        })
+    } else if name == "bash" {
+        let any_command = permissions.allow.iter().any(|e| e.starts_with("bash:"));
+        (!allows("bash") && !any_command).then(|| DenyReason::BashNotAllowed {
+            agent_id: agent_id.to_string(),
+        })
+    } else {
+        (!allows(name) && !asks_when_not_allowed(name, config)).then(|| {
+            DenyReason::ToolNotAllowed {
+                name: name.to_string(),
+                agent_id: agent_id.to_string(),
+            }
+        })
+    };
+    match denial {
+        Some(reason) => Err(SquidError::ToolDenied(format!(
+            "Can't force tool '{}': {}",
+            name,
+            reason.message()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Whether a tool missing from the allow list asks for approval instead of being refused
+///
+/// True for tools that default to asking, and for `read_file` and `grep` with
+/// `security.ask_for_reads`.
+fn asks_when_not_allowed(name: &str, config: &Config) -> bool {
+    let default = ToolRegistry::builtin()
+        .get(name)
+        .map(|tool| tool.default_permission);
+    default == Some(DefaultPermission::Ask)
+        || (config.security.ask_for_reads && matches!(name, "read_file" | "grep"))
+}
+
+/// The first hard-blocked pattern in `command`
+fn dangerous_pattern(command: &str) -> Option<&'static str> {
+    DANGEROUS_BASH_PATTERNS
+        .iter()
+        .find(|pattern| command.contains(*pattern))
+        .copied()
+}
+
+/// Whether a `bash:<command>` allow entry covers `command` (exact match or followed by arguments)
+fn bash_command_allowed(allow: &[String], command: &str) -> bool {
+    let command = command.trim();
+    allow.iter().any(|entry| {
+        entry.strip_prefix("bash:").is_some_and(|allowed| {
+            command == allowed || command.starts_with(&format!("{} ", allowed))
+        })
+    })
+}
+
+/// Validate the `path` argument of file tools and the `cwd` of bash; other tools have no path
+pub fn validate_tool_path(
+    name: &str,
+    args: &Value,
+    validator: &PathValidator,
+) -> Result<Option<PathBuf>, DenyReason> {
+    // Every file named in the patch headers must be accessible
+    if name == "apply_patch" {
+        let patch = args["patch"].as_str().unwrap_or("");
+        for path in crate::patch::paths(patch) {
+            validator
+                .validate(Path::new(&path))
+                .map_err(|e| path_denial(name, &path, e))?;
+        }
+        return Ok(None);
+    }
+
+    // Bash runs in the validator's base directory unless it names another one
+    if name == "bash" {
+        let Some(cwd) = args["cwd"].as_str().filter(|cwd| !cwd.is_empty()) else {
+            return Ok(None);
+        };
+        return validator
+            .validate(Path::new(cwd))
+            .map(Some)
+            .map_err(|e| path_denial(name, cwd, e));
+    }
+
+    if !matches!(name, "read_file" | "write_file" | "grep") {
+        return Ok(None);
+    }
+
+    let path = args["path"].as_str().unwrap_or("");
+    validator
+        .validate(Path::new(path))
+        .map(Some)
+        .map_err(|e| path_denial(name, path, e))
+}
+
+fn path_denial(name: &str, path: &str, e: PathValidationError) -> DenyReason {
+    debug!("Path validation failed for {}: {}", name, e);
+    let kind = match e {
+        PathValidationError::PathIgnored(_) => PathDenial::Ignored,
+        PathValidationError::PathNotAllowed(ref msg) if msg.contains("blacklisted") => {
+            PathDenial::Blacklisted
+        }
+        PathValidationError::PathNotAllowed(ref msg) if msg.contains("not in whitelisted") => {
+            PathDenial::OutsideProject
+        }
+        _ => PathDenial::Other,
+    };
+    DenyReason::Path {
+        path: path.to_string(),
+        kind,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agent::AgentConfig;
+
+    fn config_with_allow(allow: &[&str]) -> Config {
+        let mut config = Config::default();
+        config.agents.agents.insert(
+            "test".to_string(),
+            AgentConfig {
+                name: "Test".to_string(),
+                enabled: true,
+                description: String::new(),
+                model: "test-model".to_string(),
+                prompt: None,
+                pricing_model: None,
+                context_window: None,
+                permissions: crate::agent::AgentPermissions {
+                    allow: allow.iter().map(|s| s.to_string()).collect(),
+                },
+                use_tools: true,
+                suggestions: vec![],
+            },
+        );
+        config
+    }
+
+    /// Compact form of a decision for table comparisons
+    fn outcome(decision: &PolicyDecision) -> &'static str {
+        match decision {
+            PolicyDecision::Allow { .. } => "allow",
+            PolicyDecision::NeedsApproval { .. } => "approval",
+            PolicyDecision::Deny(DenyReason::DangerousCommand { .. }) => "dangerous",
+            PolicyDecision::Deny(DenyReason::BashNotAllowed { .. }) => "bash-denied",
+            PolicyDecision::Deny(DenyReason::ToolNotAllowed { .. }) => "tool-denied",
+            PolicyDecision::Deny(DenyReason::AgentNotFound { .. }) => "no-agent",
+            PolicyDecision::Deny(DenyReason::Path { kind, .. }) => match kind {
+                PathDenial::Ignored => "path-ignored",
+                PathDenial::Blacklisted => "path-blacklisted",
+                PathDenial::OutsideProject => "path-outside",
+                PathDenial::Other => "path-other",
+            },
+            PolicyDecision::Deny(_) => "plugin-denied",
+        }
+    }
+
+    #[test]
+    fn test_check_forced_tool() {
+        let config = config_with_allow(&["read_file", "bash:ls"]);
+        assert!(check_forced_tool("read_file", "test", &config).is_ok());
+        assert!(check_forced_tool("bash", "test", &config).is_ok());
+
+        let error = check_forced_tool("write_file", "test", &config)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "Can't force tool 'write_file': Tool 'write_file' not in allow list for agent 'test'"
+        );
+        let error = check_forced_tool("launch_rockets", "test", &config)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("no such tool"));
+        let error = check_forced_tool("read_file", "missing", &config)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Agent 'missing' not found"));
+
+        let config = config_with_allow(&[]);
+        assert!(check_forced_tool("bash", "test", &config).is_err());
+        // Tools that ask by default can be forced; the call goes through approval
+        assert!(check_forced_tool("demo_tool", "test", &config).is_ok());
+    }
+
+    #[test]
+    fn test_cli_and_web_agree() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::write(dir.join("notes.md"), "notes").unwrap();
+        let notes = dir.join("notes.md").display().to_string();
+
+        // (allow list, tool, args, expected outcome)
+        let cases: Vec<(&[&str], &str, Value, &str)> = vec![
+            // Hard blocks win over any permission
+            (
+                &["bash"],
+                "bash",
+                json!({"command": "rm -rf /"}),
+                "dangerous",
+            ),
+            (
+                &["bash:sudo"],
+                "bash",
+                json!({"command": "sudo ls"}),
+                "dangerous",
+            ),
+            (
+                &["bash"],
+                "bash",
+                json!({"command": "curl example.com"}),
+                "dangerous",
+            ),
+            (
+                &["bash"],
+                "bash",
+                json!({"command": "ls | killall x"}),
+                "dangerous",
+            ),
+            // Granular bash rules
+            (&["bash"], "bash", json!({"command": "ls -la"}), "allow"),
+            (&["bash:ls"], "bash", json!({"command": "ls"}), "allow"),
+            (
+                &["bash:ls"],
+                "bash",
+                json!({"command": "  ls -la  "}),
+                "allow",
+            ),
+            (
+                &["bash:ls"],
+                "bash",
+                json!({"command": "lsof"}),
+                "bash-denied",
+            ),
+            (
+                &["bash:git status"],
+                "bash",
+                json!({"command": "git status -s"}),
+                "allow",
+            ),
+            (
+                &["bash:git status"],
+                "bash",
+                json!({"command": "git push"}),
+                "bash-denied",
+            ),
+            (&[], "bash", json!({"command": "ls"}), "bash-denied"),
+            // Allow list
+            (&["now"], "now", json!({}), "allow"),
+            (&[], "now", json!({}), "tool-denied"),
+            (&["bash"], "now", json!({}), "tool-denied"),
+            // Tools whose default posture is to ask
+            (&[], "demo_tool", json!({"message": "hi"}), "approval"),
+            (
+                &["demo_tool"],
+                "demo_tool",
+                json!({"message": "hi"}),
+                "allow",
+            ),
+            // Denied paths
+            (
+                &["read_file"],
+                "read_file",
+                json!({"path": "/etc/passwd"}),
+                "path-blacklisted",
+            ),
+            (
+                &["write_file"],
+                "write_file",
+                json!({"path": "/etc/hosts", "content": ""}),
+                "path-blacklisted",
+            ),
+            (
+                &["grep"],
+                "grep",
+                json!({"path": "/etc", "pattern": "x"}),
+                "path-blacklisted",
+            ),
+            (
+                &["read_file"],
+                "read_file",
+                json!({"path": notes}),
+                "path-outside",
+            ),
+            // Paths are checked before the allow list, so a bad path is always a path denial
+            (
+                &[],
+                "read_file",
+                json!({"path": "/etc/passwd"}),
+                "path-blacklisted",
+            ),
+            (
+                &[],
+                "read_file",
+                json!({"path": "../../../../etc/shadow"}),
+                "path-blacklisted",
+            ),
+        ];
+
+        for (allow, tool, args, expected) in &cases {
+            let config = config_with_allow(allow);
+            let cli = evaluate(tool, args, "test", &config, Mode::Cli);
+            let web = evaluate(tool, args, "test", &config, Mode::Web { working_dir: None });
+            assert_eq!(
+                outcome(&cli),
+                *expected,
+                "CLI: {} {} with {:?}",
+                tool,
+                args,
+                allow
+            );
+            assert_eq!(
+                cli, web,
+                "CLI and web differ: {} {} with {:?}",
+                tool, args, allow
+            );
+        }
+    }
+
+    #[test]
+    fn test_unknown_agent_is_denied() {
+        let config = config_with_allow(&["now"]);
+        let decision = evaluate("now", &json!({}), "missing", &config, Mode::Cli);
+        assert_eq!(outcome(&decision), "no-agent");
+    }
+
+    #[test]
+    fn test_ask_for_reads_turns_read_denials_into_prompts() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::write(dir.join("notes.md"), "notes").unwrap();
+        let mode = Mode::Web {
+            working_dir: Some(&dir),
+        };
+        let read = json!({"path": "notes.md"});
+        let search = json!({"path": ".", "pattern": "x"});
+
+        let mut config = config_with_allow(&[]);
+        assert_eq!(
+            outcome(&evaluate("read_file", &read, "test", &config, mode)),
+            "tool-denied"
+        );
+        assert!(check_forced_tool("grep", "test", &config).is_err());
+
+        config.security.ask_for_reads = true;
+        assert_eq!(
+            outcome(&evaluate("read_file", &read, "test", &config, mode)),
+            "approval"
+        );
+        assert_eq!(
+            outcome(&evaluate("grep", &search, "test", &config, mode)),
+            "approval"
+        );
+        assert!(check_forced_tool("grep", "test", &config).is_ok());
+        // Only reads ask; bad paths and other tools are still refused
+        assert_eq!(
+            outcome(&evaluate(
+                "read_file",
+                &json!({"path": "/etc/passwd"}),
+                "test",
+                &config,
+                mode
+            )),
+            "path-blacklisted"
+        );
+        assert_eq!(
+            outcome(&evaluate("now", &json!({}), "test", &config, mode)),
+            "tool-denied"
+        );
+    }
+
+    #[test]
+    fn test_web_paths_anchor_to_working_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::write(dir.join("notes.md"), "notes").unwrap();
+        let config = config_with_allow(&["read_file"]);
+
+        let decision = evaluate(
+            "read_file",
+            &json!({"path": "notes.md"}),
+            "test",
+            &config,
+            Mode::Web {
+                working_dir: Some(&dir),
+            },
+        );
+        assert_eq!(
+            decision,
+            PolicyDecision::Allow {
+                path: Some(dir.join("notes.md"))
+            }
+        );
+    }
+
+    #[test]
+    fn test_web_paths_stay_in_their_workspace() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(temp.path()).unwrap();
+        let (a, b) = (root.join("a"), root.join("b"));
+        std::fs::create_dir_all(&a).unwrap();
+        std::fs::create_dir_all(&b).unwrap();
+        std::fs::write(b.join("secret.txt"), "secret").unwrap();
+        let config = config_with_allow(&["read_file"]);
+
+        let relative = "../b/secret.txt".to_string();
+        let absolute = b.join("secret.txt").display().to_string();
+        for path in [relative, absolute] {
+            let decision = evaluate(
+                "read_file",
+                &json!({"path": path}),
+                "test",
+                &config,
+                Mode::Web {
+                    working_dir: Some(&a),
+                },
+            );
+            assert!(
+                matches!(decision, PolicyDecision::Deny(DenyReason::Path { .. })),
+                "{} must not be readable from another workspace",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_patch_checks_every_header_path() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::write(dir.join("notes.md"), "notes\n").unwrap();
+        let config = config_with_allow(&["apply_patch"]);
+        let mode = Mode::Web {
+            working_dir: Some(&dir),
+        };
+
+        let inside = "--- a/notes.md\n+++ b/notes.md\n@@ -1 +1 @@\n-notes\n+more notes\n";
+        let decision = evaluate(
+            "apply_patch",
+            &json!({"patch": inside}),
+            "test",
+            &config,
+            mode,
+        );
+        assert_eq!(decision, PolicyDecision::Allow { path: None });
+
+        let escaping = format!(
+            "{}--- a/../x.md\n+++ b/../x.md\n@@ -1 +1 @@\n-a\n+b\n",
+            inside
+        );
+        let decision = evaluate(
+            "apply_patch",
+            &json!({"patch": escaping}),
+            "test",
+            &config,
+            mode,
+        );
+        assert!(matches!(
+            decision,
+            PolicyDecision::Deny(DenyReason::Path { ref path, .. }) if path == "../x.md"
+        ));
+    }
+
+    #[test]
+    fn test_deny_results_keep_their_shape() {
+        let path = DenyReason::Path {
+            path: ".env".to_string(),
+            kind: PathDenial::Ignored,
+        };
+        assert!(
+            path.to_tool_result()["content"]
+                .as_str()
+                .unwrap()
+                .contains(".squidignore")
+        );
+
+        let bash = DenyReason::BashNotAllowed {
+            agent_id: "test".to_string(),
+        };
+        assert_eq!(
+            bash.to_tool_result(),
+            json!({"error": "Bash commands not allowed for agent 'test'", "skipped": true})
+        );
+    }
+}
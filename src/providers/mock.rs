@@ -0,0 +1,202 @@
+use async_openai::types::chat::{ChatCompletionRequestMessage, ChatCompletionTools};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use super::{ChatParams, Provider, ProviderError, ProviderEvent, ProviderStream, StopReason};
+
+/// Scripted responses replayed by [`Mock`]
+///
+/// ```json
+/// {
+///   "delay_ms": 20,
+///   "responses": [
+///     [{"tool_call": {"name": "now", "arguments": {}}}, {"stop": "tool_calls"}],
+///     [{"content": "It is noon."}, {"usage": {"input_tokens": 40, "output_tokens": 5}}]
+///   ]
+/// }
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Scenario {
+    /// Events of each request in a turn, in order; requests past the last one replay it
+    pub responses: Vec<Vec<MockEvent>>,
+    /// Pause before each event, to watch answers stream in the UI
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+/// One scripted step of a response
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MockEvent {
+    Content(String),
+    ToolCall {
+        /// Defaults to `call_<n>`, counting the calls of the response from 1
+        #[serde(default)]
+        id: Option<String>,
+        name: String,
+        #[serde(default)]
+        arguments: Value,
+    },
+    Usage {
+        input_tokens: i64,
+        output_tokens: i64,
+    },
+    Stop(StopReason),
+    /// Fail the stream with this message; later events are not sent
+    Error(String),
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read mock scenario {}: {}", path.display(), e))?;
+        let scenario: Scenario = serde_json::from_str(&text)
+            .map_err(|e| format!("Invalid mock scenario {}: {}", path.display(), e))?;
+        if scenario.responses.is_empty() {
+            return Err(format!("Mock scenario {} has no responses", path.display()));
+        }
+        Ok(scenario)
+    }
+}
+
+/// Replays a [`Scenario`] instead of calling a model, for tests and `squid serve --mock-llm`
+///
+/// A provider is created per chat turn, so every turn replays the scenario from its first
+/// response.
+pub struct Mock {
+    scenario: Result<Scenario, String>,
+    requests: AtomicUsize,
+}
+
+impl Mock {
+    /// Load the scenario at `path`; a broken file fails each request with the reason
+    pub fn load(path: &Path) -> Self {
+        Self {
+            scenario: Scenario::load(path),
+            requests: AtomicUsize::new(0),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for Mock {
+    async fn stream_chat(
+        &self,
+        _messages: &[ChatCompletionRequestMessage],
+        _tools: &[ChatCompletionTools],
+        _params: &ChatParams<'_>,
+    ) -> Result<ProviderStream, ProviderError> {
+        let scenario = self.scenario.as_ref().map_err(|e| e.clone())?;
+        let request = self.requests.fetch_add(1, Ordering::SeqCst);
+        let events = scenario.responses[request.min(scenario.responses.len() - 1)].clone();
+        let delay = Duration::from_millis(scenario.delay_ms);
+
+        Ok(Box::pin(async_stream::stream! {
+            let mut tool_calls = 0;
+            for event in events {
+                if !delay.is_zero() {
+                    tokio::time::sleep(delay).await;
+                }
+                yield match event {
+                    MockEvent::Content(text) => Ok(ProviderEvent::Content(text)),
+                    MockEvent::ToolCall { id, name, arguments } => {
+                        tool_calls += 1;
+                        let arguments = match arguments {
+                            Value::Null => "{}".to_string(),
+                            Value::String(raw) => raw,
+                            arguments => arguments.to_string(),
+                        };
+                        Ok(ProviderEvent::ToolCallDelta {
+                            index: tool_calls - 1,
+                            id: Some(id.unwrap_or_else(|| format!("call_{}", tool_calls))),
+                            name: Some(name),
+                            arguments: Some(arguments),
+                        })
+                    }
+                    MockEvent::Usage { input_tokens, output_tokens } => Ok(ProviderEvent::Usage {
+                        input_tokens,
+                        output_tokens,
+                    }),
+                    MockEvent::Stop(reason) => Ok(ProviderEvent::Stop(reason)),
+                    MockEvent::Error(message) => {
+                        yield Err(message.into());
+                        break;
+                    }
+                };
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    fn params() -> ChatParams<'static> {
+        ChatParams {
+            model: "mock",
+            reasoning: Default::default(),
+            tool_choice: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shipped_scenarios_load_and_replay_in_order() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mock");
+        for name in [
+            "plain_answer",
+            "tool_round",
+            "think_tags",
+            "mid_stream_error",
+            "directory_reads",
+            "denied_bash",
+            "think_split",
+        ] {
+            Scenario::load(&dir.join(format!("{}.json", name))).unwrap();
+        }
+
+        let mock = Mock::load(&dir.join("tool_round.json"));
+        let first: Vec<_> = mock
+            .stream_chat(&[], &[], &params())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(first[0], Ok(ProviderEvent::Content(_))));
+        assert!(matches!(
+            first[1],
+            Ok(ProviderEvent::ToolCallDelta { index: 0, ref name, .. }) if name.as_deref() == Some("now")
+        ));
+        // The answer is the last response and is replayed for any further request
+        for _ in 0..2 {
+            let events: Vec<_> = mock
+                .stream_chat(&[], &[], &params())
+                .await
+                .unwrap()
+                .collect()
+                .await;
+            assert!(matches!(events[0], Ok(ProviderEvent::Content(_))));
+        }
+
+        let failing = Mock::load(&dir.join("mid_stream_error.json"));
+        let events: Vec<_> = failing
+            .stream_chat(&[], &[], &params())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(events.last(), Some(Err(_))));
+
+        let missing = Mock::load(&dir.join("missing.json"));
+        let error = missing
+            .stream_chat(&[], &[], &params())
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("missing.json"));
+    }
+}
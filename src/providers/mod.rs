@@ -0,0 +1,542 @@
+//! Chat providers with different wire formats behind a common streaming interface
+//!
+//! Conversations are kept in async-openai's request types everywhere else in squid; each
+//! provider translates messages and tool schemas to its own API and reports the response as a
+//! stream of [`ProviderEvent`]s.
+
+mod mock;
+mod ollama;
+mod openai;
+
+pub use mock::{Mock, Scenario};
+pub use ollama::Ollama;
+pub use openai::{OpenAiCompatible, apply_reasoning, apply_tool_choice};
+
+use async_openai::types::chat::{
+    ChatCompletionMessageToolCall, ChatCompletionRequestMessage, ChatCompletionTools,
+};
+use futures::{Stream, TryStreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+
+use crate::config::{Config, ProviderKind, ReasoningConfig};
+use crate::models::{self, ReasoningControl};
+
+pub type ProviderError = Box<dyn std::error::Error + Send + Sync>;
+
+pub type ProviderStream = Pin<Box<dyn Stream<Item = Result<ProviderEvent, ProviderError>> + Send>>;
+
+/// Incremental output of a streaming chat request
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderEvent {
+    /// A piece of the assistant's text
+    Content(String),
+    /// A piece of a tool call; fields are merged with earlier deltas of the same `index`
+    ToolCallDelta {
+        index: usize,
+        id: Option<String>,
+        name: Option<String>,
+        arguments: Option<String>,
+    },
+    /// Token usage for the whole request
+    Usage {
+        input_tokens: i64,
+        output_tokens: i64,
+    },
+    /// The model finished generating
+    Stop(StopReason),
+}
+
+/// Why the model stopped generating, also recorded on assistant messages as `finish_reason`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// Natural end of the answer
+    EndTurn,
+    /// The model is waiting for tool results
+    ToolCalls,
+    /// The token limit was reached
+    Length,
+    /// The provider's content filter cut the answer off
+    ContentFilter,
+    Other,
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StopReason::EndTurn => "end_turn",
+            StopReason::ToolCalls => "tool_calls",
+            StopReason::Length => "length",
+            StopReason::ContentFilter => "content_filter",
+            StopReason::Other => "other",
+        }
+    }
+}
+
+/// Per-request parameters
+pub struct ChatParams<'a> {
+    pub model: &'a str,
+    /// Reasoning fields to send, already narrowed by [`supported_reasoning`]
+    pub reasoning: ReasoningConfig,
+    /// Whether the model may, must not, or must call a tool
+    pub tool_choice: ToolChoice,
+}
+
+/// Tool use requested for a turn: `"auto"`, `"none"` or `{"name": "read_file"}`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "ToolChoiceRepr", into = "ToolChoiceRepr")]
+pub enum ToolChoice {
+    /// The model decides
+    #[default]
+    Auto,
+    /// Answer without tools; no tool schemas are sent
+    None,
+    /// Call this tool before answering
+    Tool(String),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ToolChoiceRepr {
+    Mode(String),
+    Tool { name: String },
+}
+
+impl TryFrom<ToolChoiceRepr> for ToolChoice {
+    type Error = String;
+
+    fn try_from(repr: ToolChoiceRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ToolChoiceRepr::Mode(mode) => match mode.as_str() {
+                "auto" => Ok(ToolChoice::Auto),
+                "none" => Ok(ToolChoice::None),
+                other => Err(format!(
+                    "invalid tool_choice '{}', expected \"auto\", \"none\" or {{\"name\": \"<tool>\"}}",
+                    other
+                )),
+            },
+            ToolChoiceRepr::Tool { name } => Ok(ToolChoice::Tool(name)),
+        }
+    }
+}
+
+impl From<ToolChoice> for ToolChoiceRepr {
+    fn from(choice: ToolChoice) -> Self {
+        match choice {
+            ToolChoice::Auto => ToolChoiceRepr::Mode("auto".to_string()),
+            ToolChoice::None => ToolChoiceRepr::Mode("none".to_string()),
+            ToolChoice::Tool(name) => ToolChoiceRepr::Tool { name },
+        }
+    }
+}
+
+impl ToolChoice {
+    /// Whether tool schemas should be sent at all
+    pub fn allows_tools(&self) -> bool {
+        *self != ToolChoice::None
+    }
+
+    /// The forced tool, if any
+    pub fn forced_tool(&self) -> Option<&str> {
+        match self {
+            ToolChoice::Tool(name) => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for ToolChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ToolChoice::Auto => write!(f, "auto"),
+            ToolChoice::None => write!(f, "none"),
+            ToolChoice::Tool(name) => write!(f, "tool '{}'", name),
+        }
+    }
+}
+
+/// The part of `requested` that `model` can be sent
+///
+/// Effort goes to models steered by `reasoning_effort` and on/off to models with an
+/// `enable_thinking` switch; anything else is dropped so unknown models never get fields they
+/// might reject. Disabling reasoning on an effort model just omits the effort.
+pub fn supported_reasoning(model: &str, requested: ReasoningConfig) -> ReasoningConfig {
+    let supported = match models::reasoning_control(model) {
+        Some(ReasoningControl::Effort) => ReasoningConfig {
+            effort: requested.effort.filter(|_| !requested.is_disabled()),
+            enabled: None,
+        },
+        Some(ReasoningControl::EnableThinking) => ReasoningConfig {
+            effort: None,
+            enabled: requested.enabled,
+        },
+        None => ReasoningConfig::default(),
+    };
+    if supported != requested {
+        debug!(
+            "Reasoning settings {:?} narrowed to {:?} for model {}",
+            requested, supported, model
+        );
+    }
+    supported
+}
+
+#[async_trait::async_trait]
+pub trait Provider: Send + Sync {
+    /// Start a streaming chat completion
+    ///
+    /// `tools` uses the OpenAI function schema; an empty slice disables tool calling.
+    async fn stream_chat(
+        &self,
+        messages: &[ChatCompletionRequestMessage],
+        tools: &[ChatCompletionTools],
+        params: &ChatParams<'_>,
+    ) -> Result<ProviderStream, ProviderError>;
+}
+
+/// Create the provider selected by `provider_kind`, or the mock replaying `mock_llm`
+pub fn from_config(config: &Config) -> Box<dyn Provider> {
+    if let Some(scenario) = &config.mock_llm {
+        return Box::new(Mock::load(scenario));
+    }
+    let http_client = config.network.http_client();
+    let provider: Box<dyn Provider> = match config.provider_kind {
+        ProviderKind::OpenAi => Box::new(
+            OpenAiCompatible::new(&config.api_url, &config.get_api_key())
+                .with_http_client(http_client),
+        ),
+        ProviderKind::Ollama => {
+            Box::new(Ollama::new(&config.api_url).with_http_client(http_client))
+        }
+    };
+    if config.network.offline {
+        Box::new(Offline {
+            inner: provider,
+            api_url: config.api_url.clone(),
+        })
+    } else {
+        provider
+    }
+}
+
+/// Explain an offline-mode failure to reach the provider at `api_url`
+///
+/// Returns `None` unless `error` (or one of its sources) is a connection failure or timeout.
+pub fn offline_unreachable_error(
+    api_url: &str,
+    error: &(dyn std::error::Error + 'static),
+) -> Option<String> {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<reqwest::Error>()
+            && (error.is_connect() || error.is_timeout())
+        {
+            return Some(format!(
+                "Offline mode: the model provider at {} is unreachable. Start the local server or check api_url.",
+                api_url
+            ));
+        }
+        source = error.source();
+    }
+    None
+}
+
+/// Reports unreachable-provider errors in offline mode as such, instead of raw HTTP errors
+struct Offline {
+    inner: Box<dyn Provider>,
+    api_url: String,
+}
+
+#[async_trait::async_trait]
+impl Provider for Offline {
+    async fn stream_chat(
+        &self,
+        messages: &[ChatCompletionRequestMessage],
+        tools: &[ChatCompletionTools],
+        params: &ChatParams<'_>,
+    ) -> Result<ProviderStream, ProviderError> {
+        let explain = {
+            let api_url = self.api_url.clone();
+            move |e: ProviderError| -> ProviderError {
+                match offline_unreachable_error(&api_url, &*e) {
+                    Some(message) => message.into(),
+                    None => e,
+                }
+            }
+        };
+        // Some clients only connect once the stream is polled
+        let stream = self
+            .inner
+            .stream_chat(messages, tools, params)
+            .await
+            .map_err(explain.clone())?;
+        Ok(Box::pin(stream.map_err(explain)))
+    }
+}
+
+/// Merge a [`ProviderEvent::ToolCallDelta`] into the tool calls collected so far
+pub fn apply_tool_call_delta(
+    tool_calls: &mut Vec<ChatCompletionMessageToolCall>,
+    index: usize,
+    id: Option<String>,
+    name: Option<String>,
+    arguments: Option<String>,
+) {
+    while tool_calls.len() <= index {
+        tool_calls.push(ChatCompletionMessageToolCall {
+            id: String::new(),
+            function: Default::default(),
+        });
+    }
+
+    let tool_call = &mut tool_calls[index];
+    if let Some(id) = id {
+        tool_call.id = id;
+    }
+    if let Some(name) = name {
+        tool_call.function.name = name;
+    }
+    if let Some(arguments) = arguments {
+        tool_call.function.arguments.push_str(&arguments);
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test_support {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Serve a single HTTP response and return the base URL plus the captured request body
+    pub async fn serve_once(
+        content_type: &'static str,
+        body: String,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            respond(socket, content_type, &body).await
+        });
+
+        (url, handle)
+    }
+
+    /// Serve `bodies` to consecutive requests and return the captured request bodies
+    pub async fn serve_sequence(
+        content_type: &'static str,
+        bodies: Vec<String>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for body in bodies {
+                let (socket, _) = listener.accept().await.unwrap();
+                requests.push(respond(socket, content_type, &body).await);
+            }
+            requests
+        });
+
+        (url, handle)
+    }
+
+    /// Like [`serve_once`], but answer only after `delay`
+    pub async fn serve_slowly(
+        content_type: &'static str,
+        body: String,
+        delay: std::time::Duration,
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(delay).await;
+            respond(socket, content_type, &body).await
+        });
+
+        url
+    }
+
+    /// Read one request from `socket`, answer it with `body` and return the request body
+    async fn respond(mut socket: TcpStream, content_type: &str, body: &str) -> String {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 4096];
+        let header_end = loop {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            if let Some(pos) = request.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+        };
+        let headers = String::from_utf8_lossy(&request[..header_end]).to_lowercase();
+        let content_length = headers
+            .lines()
+            .find_map(|line| line.strip_prefix("content-length:"))
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .unwrap_or(0);
+        while request.len() < header_end + content_length {
+            let n = socket.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        );
+        socket.write_all(response.as_bytes()).await.unwrap();
+        socket.shutdown().await.ok();
+
+        String::from_utf8_lossy(&request[header_end..]).into_owned()
+    }
+
+    /// Send the headers and `first_chunk`, then keep the response open without finishing it
+    pub async fn serve_stalled(content_type: &'static str, first_chunk: String) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = socket.read(&mut buf).await;
+
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n\r\n{}",
+                content_type, first_chunk
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+        });
+
+        url
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ReasoningEffort;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    #[test]
+    fn test_tool_choice_serde() {
+        let parse = |value: serde_json::Value| serde_json::from_value::<ToolChoice>(value);
+        assert_eq!(parse(json!("auto")).unwrap(), ToolChoice::Auto);
+        assert_eq!(parse(json!("none")).unwrap(), ToolChoice::None);
+        assert_eq!(
+            parse(json!({"name": "read_file"})).unwrap(),
+            ToolChoice::Tool("read_file".to_string())
+        );
+        assert!(parse(json!("required")).is_err());
+
+        let forced = ToolChoice::Tool("grep".to_string());
+        assert_eq!(
+            serde_json::to_value(&forced).unwrap(),
+            json!({"name": "grep"})
+        );
+        assert_eq!(
+            serde_json::to_value(ToolChoice::None).unwrap(),
+            json!("none")
+        );
+        assert!(!ToolChoice::None.allows_tools());
+        assert_eq!(forced.forced_tool(), Some("grep"));
+    }
+
+    #[tokio::test]
+    async fn test_offline_unreachable_provider_fails_fast() {
+        // A port nothing listens on
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        drop(listener);
+
+        for provider_kind in [ProviderKind::OpenAi, ProviderKind::Ollama] {
+            let mut config = Config {
+                api_url: api_url.clone(),
+                provider_kind,
+                ..Default::default()
+            };
+            config.network.offline = true;
+            let started = std::time::Instant::now();
+            let result = from_config(&config)
+                .stream_chat(
+                    &[],
+                    &[],
+                    &ChatParams {
+                        model: "local-model",
+                        reasoning: ReasoningConfig::default(),
+                        tool_choice: Default::default(),
+                    },
+                )
+                .await;
+            let error = match result {
+                Ok(mut stream) => stream.next().await.unwrap().unwrap_err().to_string(),
+                Err(e) => e.to_string(),
+            };
+            assert!(error.starts_with("Offline mode:"), "{}", error);
+            assert!(error.contains(&api_url));
+            assert!(started.elapsed() < crate::config::OFFLINE_HTTP_TIMEOUT * 2);
+        }
+    }
+
+    #[test]
+    fn test_supported_reasoning_per_model_capability() {
+        let high = ReasoningConfig {
+            effort: Some(ReasoningEffort::High),
+            enabled: Some(true),
+        };
+        assert_eq!(
+            supported_reasoning("o3-mini", high),
+            ReasoningConfig {
+                effort: Some(ReasoningEffort::High),
+                enabled: None,
+            }
+        );
+        assert_eq!(
+            supported_reasoning("qwen3-8b", high),
+            ReasoningConfig {
+                effort: None,
+                enabled: Some(true),
+            }
+        );
+        assert_eq!(
+            supported_reasoning("gpt-4o", high),
+            ReasoningConfig::default()
+        );
+
+        let off = ReasoningConfig {
+            effort: Some(ReasoningEffort::Low),
+            enabled: Some(false),
+        };
+        assert_eq!(
+            supported_reasoning("o3-mini", off),
+            ReasoningConfig::default()
+        );
+        assert_eq!(supported_reasoning("qwen3-8b", off).enabled, Some(false));
+    }
+
+    #[test]
+    fn test_apply_tool_call_delta_merges_fragments() {
+        let mut calls = Vec::new();
+        apply_tool_call_delta(
+            &mut calls,
+            0,
+            Some("call_1".to_string()),
+            Some("read_file".to_string()),
+            Some("{\"path\":".to_string()),
+        );
+        apply_tool_call_delta(&mut calls, 0, None, None, Some("\"a.txt\"}".to_string()));
+        apply_tool_call_delta(&mut calls, 1, None, Some("now".to_string()), None);
+
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.arguments, "{\"path\":\"a.txt\"}");
+        assert_eq!(calls[1].function.name, "now");
+    }
+}
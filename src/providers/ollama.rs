@@ -0,0 +1,460 @@
+use async_openai::types::chat::{ChatCompletionRequestMessage, ChatCompletionTools};
+use log::debug;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+
+use super::{ChatParams, Provider, ProviderError, ProviderEvent, ProviderStream, StopReason};
+
+/// Ollama's native `/api/chat` API
+pub struct Ollama {
+    chat_url: String,
+    client: reqwest::Client,
+}
+
+impl Ollama {
+    /// `api_url` may be the server root or its OpenAI-compatible `/v1` endpoint
+    pub fn new(api_url: &str) -> Self {
+        Self {
+            chat_url: chat_url(api_url),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Send requests through `client` (e.g. one with offline timeouts)
+    pub fn with_http_client(mut self, client: reqwest::Client) -> Self {
+        self.client = client;
+        self
+    }
+}
+
+/// Resolve the `/api/chat` URL from the configured API URL
+fn chat_url(api_url: &str) -> String {
+    let base = api_url.trim_end_matches('/');
+    let base = base.strip_suffix("/v1").unwrap_or(base);
+    format!("{}/api/chat", base)
+}
+
+/// Message content as plain text; content parts are joined
+fn content_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+/// Translate OpenAI-shaped messages to Ollama's format
+///
+/// Tool call arguments become JSON objects, and tool results carry the tool's name
+/// because Ollama doesn't use tool call IDs.
+fn to_ollama_messages(messages: &[ChatCompletionRequestMessage]) -> Vec<Value> {
+    let mut tool_names: HashMap<String, String> = HashMap::new();
+
+    messages
+        .iter()
+        .filter_map(|message| serde_json::to_value(message).ok())
+        .map(|message| {
+            let role = message["role"].as_str().unwrap_or("user");
+            let mut translated = json!({
+                "role": if role == "developer" { "system" } else { role },
+                "content": content_text(&message["content"]),
+            });
+
+            if let Some(tool_calls) = message["tool_calls"].as_array() {
+                let calls: Vec<Value> = tool_calls
+                    .iter()
+                    .map(|call| {
+                        let name = call["function"]["name"].as_str().unwrap_or_default();
+                        if let Some(id) = call["id"].as_str() {
+                            tool_names.insert(id.to_string(), name.to_string());
+                        }
+                        let arguments = call["function"]["arguments"]
+                            .as_str()
+                            .and_then(|args| serde_json::from_str::<Value>(args).ok())
+                            .unwrap_or_else(|| json!({}));
+                        json!({"function": {"name": name, "arguments": arguments}})
+                    })
+                    .collect();
+                translated["tool_calls"] = Value::Array(calls);
+            }
+
+            if role == "tool"
+                && let Some(name) = message["tool_call_id"]
+                    .as_str()
+                    .and_then(|id| tool_names.get(id))
+            {
+                translated["tool_name"] = json!(name);
+            }
+
+            translated
+        })
+        .collect()
+}
+
+/// Ollama accepts OpenAI function schemas as-is; other tool types are dropped
+fn to_ollama_tools(tools: &[ChatCompletionTools]) -> Vec<Value> {
+    tools
+        .iter()
+        .filter(|tool| matches!(tool, ChatCompletionTools::Function(_)))
+        .filter_map(|tool| serde_json::to_value(tool).ok())
+        .collect()
+}
+
+/// Tracks tool calls across NDJSON lines so the final stop reason can be reported
+#[derive(Default)]
+struct LineParser {
+    tool_calls: usize,
+}
+
+impl LineParser {
+    /// Translate one NDJSON line into provider events
+    fn parse(&mut self, line: &str) -> Result<Vec<ProviderEvent>, ProviderError> {
+        let chunk: Value = serde_json::from_str(line)?;
+        if let Some(error) = chunk["error"].as_str() {
+            return Err(format!("Ollama error: {}", error).into());
+        }
+
+        let mut events = Vec::new();
+        let message = &chunk["message"];
+
+        if let Some(content) = message["content"].as_str()
+            && !content.is_empty()
+        {
+            events.push(ProviderEvent::Content(content.to_string()));
+        }
+
+        // Ollama sends complete tool calls, without IDs
+        for call in message["tool_calls"].as_array().into_iter().flatten() {
+            let index = self.tool_calls;
+            self.tool_calls += 1;
+            let arguments = match &call["function"]["arguments"] {
+                Value::String(args) => args.clone(),
+                Value::Null => "{}".to_string(),
+                args => args.to_string(),
+            };
+            events.push(ProviderEvent::ToolCallDelta {
+                index,
+                id: Some(format!("call_{}", index)),
+                name: call["function"]["name"].as_str().map(String::from),
+                arguments: Some(arguments),
+            });
+        }
+
+        if chunk["done"].as_bool() == Some(true) {
+            events.push(ProviderEvent::Usage {
+                input_tokens: chunk["prompt_eval_count"].as_i64().unwrap_or(0),
+                output_tokens: chunk["eval_count"].as_i64().unwrap_or(0),
+            });
+            let reason = if self.tool_calls > 0 {
+                StopReason::ToolCalls
+            } else {
+                match chunk["done_reason"].as_str() {
+                    Some("length") => StopReason::Length,
+                    Some("stop") | None => StopReason::EndTurn,
+                    Some(_) => StopReason::Other,
+                }
+            };
+            events.push(ProviderEvent::Stop(reason));
+        }
+
+        Ok(events)
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for Ollama {
+    async fn stream_chat(
+        &self,
+        messages: &[ChatCompletionRequestMessage],
+        tools: &[ChatCompletionTools],
+        params: &ChatParams<'_>,
+    ) -> Result<ProviderStream, ProviderError> {
+        let mut body = json!({
+            "model": params.model,
+            "messages": to_ollama_messages(messages),
+            "stream": true,
+        });
+        // Ollama has no `tool_choice`: a forced tool is offered on its own instead
+        let tools: Vec<ChatCompletionTools> = match params.tool_choice.forced_tool() {
+            Some(name) => tools
+                .iter()
+                .filter(|tool| matches!(tool, ChatCompletionTools::Function(t) if t.function.name == name))
+                .cloned()
+                .collect(),
+            None => tools.to_vec(),
+        };
+        if !tools.is_empty() {
+            body["tools"] = Value::Array(to_ollama_tools(&tools));
+        }
+        // `think` takes an effort level for gpt-oss and a boolean for other thinking models
+        if let Some(effort) = params.reasoning.effort {
+            body["think"] = json!(effort.as_str());
+        } else if let Some(enabled) = params.reasoning.enabled {
+            body["think"] = json!(enabled);
+        }
+
+        debug!("Sending streaming request to {}", self.chat_url);
+        let mut response = self.client.post(&self.chat_url).json(&body).send().await?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(format!("Ollama request failed (HTTP {}): {}", status, text.trim()).into());
+        }
+
+        Ok(Box::pin(async_stream::stream! {
+            let mut parser = LineParser::default();
+            let mut buffer: Vec<u8> = Vec::new();
+
+            loop {
+                let chunk = match response.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    Err(e) => {
+                        yield Err(Box::new(e) as ProviderError);
+                        return;
+                    }
+                };
+                buffer.extend_from_slice(&chunk);
+
+                while let Some(pos) = buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match parser.parse(line.trim()) {
+                        Ok(events) => {
+                            for event in events {
+                                yield Ok(event);
+                            }
+                        }
+                        Err(e) => {
+                            yield Err(e);
+                            return;
+                        }
+                    }
+                }
+            }
+
+            // The last line may not end with a newline
+            let rest = String::from_utf8_lossy(&buffer).trim().to_string();
+            if !rest.is_empty() {
+                match parser.parse(&rest) {
+                    Ok(events) => {
+                        for event in events {
+                            yield Ok(event);
+                        }
+                    }
+                    Err(e) => yield Err(e),
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_support::serve_once;
+    use async_openai::types::chat::{
+        ChatCompletionMessageToolCall, ChatCompletionMessageToolCalls,
+        ChatCompletionRequestAssistantMessage, ChatCompletionRequestSystemMessage,
+        ChatCompletionRequestToolMessage, ChatCompletionRequestUserMessage, FunctionCall,
+    };
+    use futures::StreamExt;
+
+    #[test]
+    fn test_chat_url() {
+        assert_eq!(
+            chat_url("http://localhost:11434/v1"),
+            "http://localhost:11434/api/chat"
+        );
+        assert_eq!(
+            chat_url("http://localhost:11434/"),
+            "http://localhost:11434/api/chat"
+        );
+    }
+
+    #[test]
+    fn test_translates_tool_calls_and_results() {
+        let messages: Vec<ChatCompletionRequestMessage> = vec![
+            ChatCompletionRequestSystemMessage::from("be brief").into(),
+            ChatCompletionRequestUserMessage::from("read a.txt").into(),
+            ChatCompletionRequestAssistantMessage {
+                tool_calls: Some(vec![ChatCompletionMessageToolCalls::Function(
+                    ChatCompletionMessageToolCall {
+                        id: "call_0".to_string(),
+                        function: FunctionCall {
+                            name: "read_file".to_string(),
+                            arguments: "{\"path\":\"a.txt\"}".to_string(),
+                        },
+                    },
+                )]),
+                ..Default::default()
+            }
+            .into(),
+            ChatCompletionRequestToolMessage {
+                content: "hello".into(),
+                tool_call_id: "call_0".to_string(),
+            }
+            .into(),
+        ];
+
+        let translated = to_ollama_messages(&messages);
+        assert_eq!(
+            translated[0],
+            json!({"role": "system", "content": "be brief"})
+        );
+        assert_eq!(
+            translated[2]["tool_calls"][0],
+            json!({"function": {"name": "read_file", "arguments": {"path": "a.txt"}}})
+        );
+        assert_eq!(
+            translated[3],
+            json!({"role": "tool", "content": "hello", "tool_name": "read_file"})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streams_ndjson_with_tool_calls() {
+        let body = [
+            json!({"model": "llama3.2", "message": {"role": "assistant", "content": "Checking"}, "done": false}),
+            json!({"model": "llama3.2", "message": {"role": "assistant", "content": "", "tool_calls": [{"function": {"name": "read_file", "arguments": {"path": "a.txt"}}}]}, "done": false}),
+            json!({"model": "llama3.2", "message": {"role": "assistant", "content": ""}, "done": true, "done_reason": "stop", "prompt_eval_count": 20, "eval_count": 7}),
+        ]
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+        let (url, request) = serve_once("application/x-ndjson", body).await;
+
+        let provider = Ollama::new(&format!("{}/v1", url));
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessage::from("hi").into()];
+        let stream = provider
+            .stream_chat(
+                &messages,
+                &crate::tools::get_tools(),
+                &ChatParams {
+                    model: "llama3.2",
+                    reasoning: Default::default(),
+                    tool_choice: Default::default(),
+                },
+            )
+            .await
+            .unwrap();
+        let events: Vec<ProviderEvent> = stream.map(|e| e.unwrap()).collect().await;
+
+        assert_eq!(
+            events,
+            vec![
+                ProviderEvent::Content("Checking".to_string()),
+                ProviderEvent::ToolCallDelta {
+                    index: 0,
+                    id: Some("call_0".to_string()),
+                    name: Some("read_file".to_string()),
+                    arguments: Some("{\"path\":\"a.txt\"}".to_string()),
+                },
+                ProviderEvent::Usage {
+                    input_tokens: 20,
+                    output_tokens: 7,
+                },
+                ProviderEvent::Stop(StopReason::ToolCalls),
+            ]
+        );
+
+        let request: Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["model"], "llama3.2");
+        assert_eq!(request["stream"], true);
+        assert_eq!(
+            request["messages"][0],
+            json!({"role": "user", "content": "hi"})
+        );
+        assert_eq!(request["tools"][0]["function"]["name"], "read_file");
+        assert!(request.get("think").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_forced_tool_is_sent_alone() {
+        let body = format!(
+            "{}\n",
+            json!({"message": {"role": "assistant", "content": "ok"}, "done": true, "done_reason": "stop"})
+        );
+        let (url, request) = serve_once("application/x-ndjson", body).await;
+
+        let provider = Ollama::new(&url);
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessage::from("hi").into()];
+        let stream = provider
+            .stream_chat(
+                &messages,
+                &crate::tools::get_tools(),
+                &ChatParams {
+                    model: "llama3.2",
+                    reasoning: Default::default(),
+                    tool_choice: crate::providers::ToolChoice::Tool("grep".to_string()),
+                },
+            )
+            .await
+            .unwrap();
+        let _: Vec<_> = stream.collect().await;
+
+        let request: Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        let tools = request["tools"].as_array().unwrap();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0]["function"]["name"], "grep");
+    }
+
+    #[tokio::test]
+    async fn test_sends_think_flag() {
+        let body = format!(
+            "{}\n",
+            json!({"message": {"role": "assistant", "content": "ok"}, "done": true, "done_reason": "stop"})
+        );
+        let (url, request) = serve_once("application/x-ndjson", body).await;
+
+        let provider = Ollama::new(&url);
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessage::from("hi").into()];
+        let stream = provider
+            .stream_chat(
+                &messages,
+                &[],
+                &ChatParams {
+                    model: "qwen3:8b",
+                    reasoning: crate::config::ReasoningConfig {
+                        effort: None,
+                        enabled: Some(false),
+                    },
+                    tool_choice: Default::default(),
+                },
+            )
+            .await
+            .unwrap();
+        let _: Vec<_> = stream.collect().await;
+
+        let request: Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["think"], false);
+    }
+
+    #[test]
+    fn test_error_line_fails_stream() {
+        let mut parser = LineParser::default();
+        let err = parser
+            .parse(r#"{"error": "model 'missing' not found"}"#)
+            .unwrap_err();
+        assert!(err.to_string().contains("model 'missing' not found"));
+
+        let events = parser
+            .parse(r#"{"message": {"content": "cut"}, "done": true, "done_reason": "length"}"#)
+            .unwrap();
+        assert_eq!(
+            events.last(),
+            Some(&ProviderEvent::Stop(StopReason::Length))
+        );
+    }
+}
@@ -0,0 +1,343 @@
+use async_openai::{
+    Client,
+    config::OpenAIConfig,
+    types::chat::{
+        ChatCompletionRequestMessage, ChatCompletionStreamOptions, ChatCompletionTools,
+        CreateChatCompletionRequestArgs, CreateChatCompletionStreamResponse, FinishReason,
+    },
+};
+use futures::StreamExt;
+use log::debug;
+use serde_json::{Value, json};
+
+use crate::config::ReasoningConfig;
+
+use super::{
+    ChatParams, Provider, ProviderError, ProviderEvent, ProviderStream, StopReason, ToolChoice,
+};
+
+/// OpenAI-compatible `/chat/completions` API (OpenAI, LM Studio, llama.cpp, vLLM, Ollama's `/v1`)
+pub struct OpenAiCompatible {
+    client: Client<OpenAIConfig>,
+}
+
+impl OpenAiCompatible {
+    pub fn new(api_url: &str, api_key: &str) -> Self {
+        let config = OpenAIConfig::new()
+            .with_api_base(api_url)
+            .with_api_key(api_key);
+        Self {
+            client: Client::with_config(config),
+        }
+    }
+
+    /// Send requests through `http_client` (e.g. one with offline timeouts)
+    pub fn with_http_client(mut self, http_client: reqwest::Client) -> Self {
+        self.client = self.client.with_http_client(http_client);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for OpenAiCompatible {
+    async fn stream_chat(
+        &self,
+        messages: &[ChatCompletionRequestMessage],
+        tools: &[ChatCompletionTools],
+        params: &ChatParams<'_>,
+    ) -> Result<ProviderStream, ProviderError> {
+        let mut request_builder = CreateChatCompletionRequestArgs::default();
+        request_builder
+            .model(params.model)
+            .messages(messages.to_vec())
+            .stream(true)
+            .stream_options(ChatCompletionStreamOptions {
+                include_usage: Some(true),
+                include_obfuscation: None,
+            });
+        if !tools.is_empty() {
+            request_builder.tools(tools.to_vec());
+        }
+        let mut request = serde_json::to_value(request_builder.build()?)?;
+        apply_reasoning(&mut request, &params.reasoning);
+        if !tools.is_empty() {
+            apply_tool_choice(&mut request, &params.tool_choice);
+        }
+
+        debug!("Sending streaming request...");
+        let stream = self
+            .client
+            .chat()
+            .create_stream_byot::<Value, CreateChatCompletionStreamResponse>(request)
+            .await?;
+
+        Ok(Box::pin(stream.flat_map(|result| {
+            let events = match result {
+                Ok(chunk) => chunk_events(chunk).into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(Box::new(e) as ProviderError)],
+            };
+            futures::stream::iter(events)
+        })))
+    }
+}
+
+/// Add reasoning controls to a `/chat/completions` request body
+///
+/// Effort maps to `reasoning_effort`; the on/off switch goes into `chat_template_kwargs`, which
+/// llama.cpp, vLLM and LM Studio pass to Qwen-style chat templates as `enable_thinking`.
+pub fn apply_reasoning(body: &mut Value, reasoning: &ReasoningConfig) {
+    if let Some(effort) = reasoning.effort {
+        body["reasoning_effort"] = json!(effort.as_str());
+    }
+    if let Some(enabled) = reasoning.enabled {
+        body["chat_template_kwargs"] = json!({ "enable_thinking": enabled });
+    }
+}
+
+/// Add `tool_choice` to a `/chat/completions` request body that has tools
+///
+/// `auto` is the API default and isn't sent, so servers without `tool_choice` support still
+/// accept ordinary requests.
+pub fn apply_tool_choice(body: &mut Value, tool_choice: &ToolChoice) {
+    match tool_choice {
+        ToolChoice::Auto => {}
+        ToolChoice::None => body["tool_choice"] = json!("none"),
+        ToolChoice::Tool(name) => {
+            body["tool_choice"] = json!({"type": "function", "function": {"name": name}})
+        }
+    }
+}
+
+/// Translate one streamed chunk into provider events
+fn chunk_events(chunk: CreateChatCompletionStreamResponse) -> Vec<ProviderEvent> {
+    let mut events = Vec::new();
+
+    if let Some(usage) = &chunk.usage {
+        debug!(
+            "Token usage - Prompt: {}, Completion: {}, Total: {}",
+            usage.prompt_tokens, usage.completion_tokens, usage.total_tokens
+        );
+        events.push(ProviderEvent::Usage {
+            input_tokens: usage.prompt_tokens as i64,
+            output_tokens: usage.completion_tokens as i64,
+        });
+    }
+
+    for choice in chunk.choices {
+        if let Some(content) = choice.delta.content {
+            events.push(ProviderEvent::Content(content));
+        }
+
+        for tool_call in choice.delta.tool_calls.unwrap_or_default() {
+            let (name, arguments) = match tool_call.function {
+                Some(function) => (function.name, function.arguments),
+                None => (None, None),
+            };
+            events.push(ProviderEvent::ToolCallDelta {
+                index: tool_call.index as usize,
+                id: tool_call.id,
+                name,
+                arguments,
+            });
+        }
+
+        if let Some(finish_reason) = choice.finish_reason {
+            events.push(ProviderEvent::Stop(match finish_reason {
+                FinishReason::Stop => StopReason::EndTurn,
+                FinishReason::ToolCalls => StopReason::ToolCalls,
+                FinishReason::Length => StopReason::Length,
+                FinishReason::ContentFilter => StopReason::ContentFilter,
+                FinishReason::FunctionCall => StopReason::Other,
+            }));
+        }
+    }
+
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::test_support::serve_once;
+    use async_openai::types::chat::ChatCompletionRequestUserMessage;
+    use serde_json::json;
+
+    fn sse(chunks: &[serde_json::Value]) -> String {
+        let mut body: String = chunks
+            .iter()
+            .map(|chunk| format!("data: {}\n\n", chunk))
+            .collect();
+        body.push_str("data: [DONE]\n\n");
+        body
+    }
+
+    fn chunk(choices: serde_json::Value, usage: serde_json::Value) -> serde_json::Value {
+        json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion.chunk",
+            "created": 1,
+            "model": "test-model",
+            "choices": choices,
+            "usage": usage
+        })
+    }
+
+    #[tokio::test]
+    async fn test_streams_content_tool_calls_and_usage() {
+        let body = sse(&[
+            chunk(
+                json!([{"index": 0, "delta": {"role": "assistant", "content": "Let me check"}}]),
+                json!(null),
+            ),
+            chunk(
+                json!([{"index": 0, "delta": {"tool_calls": [{"index": 0, "id": "call_a", "type": "function", "function": {"name": "read_file", "arguments": "{\"path\":"}}]}}]),
+                json!(null),
+            ),
+            chunk(
+                json!([{"index": 0, "delta": {"tool_calls": [{"index": 0, "function": {"arguments": "\"a.txt\"}"}}]}, "finish_reason": "tool_calls"}]),
+                json!(null),
+            ),
+            chunk(
+                json!([]),
+                json!({"prompt_tokens": 12, "completion_tokens": 5, "total_tokens": 17}),
+            ),
+        ]);
+        let (url, request) = serve_once("text/event-stream", body).await;
+
+        let provider = OpenAiCompatible::new(&url, "test-key");
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessage::from("hi").into()];
+        let stream = provider
+            .stream_chat(
+                &messages,
+                &crate::tools::get_tools(),
+                &ChatParams {
+                    model: "test-model",
+                    reasoning: ReasoningConfig::default(),
+                    tool_choice: Default::default(),
+                },
+            )
+            .await
+            .unwrap();
+        let events: Vec<ProviderEvent> = stream.map(|e| e.unwrap()).collect().await;
+
+        assert_eq!(
+            events,
+            vec![
+                ProviderEvent::Content("Let me check".to_string()),
+                ProviderEvent::ToolCallDelta {
+                    index: 0,
+                    id: Some("call_a".to_string()),
+                    name: Some("read_file".to_string()),
+                    arguments: Some("{\"path\":".to_string()),
+                },
+                ProviderEvent::ToolCallDelta {
+                    index: 0,
+                    id: None,
+                    name: None,
+                    arguments: Some("\"a.txt\"}".to_string()),
+                },
+                ProviderEvent::Stop(StopReason::ToolCalls),
+                ProviderEvent::Usage {
+                    input_tokens: 12,
+                    output_tokens: 5,
+                },
+            ]
+        );
+
+        let request: serde_json::Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["model"], "test-model");
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["tools"][0]["type"], "function");
+        assert_eq!(request["messages"][0]["content"], "hi");
+        assert!(request.get("reasoning_effort").is_none());
+        assert!(request.get("chat_template_kwargs").is_none());
+    }
+
+    #[test]
+    fn test_apply_tool_choice() {
+        let mut body = json!({});
+        apply_tool_choice(&mut body, &ToolChoice::Auto);
+        assert!(body.get("tool_choice").is_none());
+
+        apply_tool_choice(&mut body, &ToolChoice::None);
+        assert_eq!(body["tool_choice"], "none");
+
+        apply_tool_choice(&mut body, &ToolChoice::Tool("read_file".to_string()));
+        assert_eq!(
+            body["tool_choice"],
+            json!({"type": "function", "function": {"name": "read_file"}})
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sends_forced_tool_choice_only_with_tools() {
+        for (tools, expected) in [
+            (
+                crate::tools::get_tools(),
+                json!({"type": "function", "function": {"name": "read_file"}}),
+            ),
+            (Vec::new(), Value::Null),
+        ] {
+            let body = sse(&[chunk(
+                json!([{"index": 0, "delta": {"content": "ok"}, "finish_reason": "stop"}]),
+                json!(null),
+            )]);
+            let (url, request) = serve_once("text/event-stream", body).await;
+
+            let provider = OpenAiCompatible::new(&url, "test-key");
+            let messages: Vec<ChatCompletionRequestMessage> =
+                vec![ChatCompletionRequestUserMessage::from("hi").into()];
+            let stream = provider
+                .stream_chat(
+                    &messages,
+                    &tools,
+                    &ChatParams {
+                        model: "test-model",
+                        reasoning: ReasoningConfig::default(),
+                        tool_choice: ToolChoice::Tool("read_file".to_string()),
+                    },
+                )
+                .await
+                .unwrap();
+            let _: Vec<_> = stream.collect().await;
+
+            let request: Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+            assert_eq!(request["tool_choice"], expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sends_reasoning_controls() {
+        let body = sse(&[chunk(
+            json!([{"index": 0, "delta": {"content": "ok"}, "finish_reason": "stop"}]),
+            json!(null),
+        )]);
+        let (url, request) = serve_once("text/event-stream", body).await;
+
+        let provider = OpenAiCompatible::new(&url, "test-key");
+        let messages: Vec<ChatCompletionRequestMessage> =
+            vec![ChatCompletionRequestUserMessage::from("hi").into()];
+        let stream = provider
+            .stream_chat(
+                &messages,
+                &[],
+                &ChatParams {
+                    model: "qwen3-8b",
+                    reasoning: ReasoningConfig {
+                        effort: Some(crate::config::ReasoningEffort::High),
+                        enabled: Some(false),
+                    },
+                    tool_choice: Default::default(),
+                },
+            )
+            .await
+            .unwrap();
+        let _: Vec<_> = stream.collect().await;
+
+        let request: serde_json::Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["stream"], true);
+        assert_eq!(request["reasoning_effort"], "high");
+        assert_eq!(request["chat_template_kwargs"]["enable_thinking"], false);
+    }
+}
@@ -2,6 +2,7 @@ use anyhow::{Context, Result};
 use log::{debug, error, info, warn};
 use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as NotifyWatcher};
 use rig::client::EmbeddingsClient;
+use serde::Serialize;
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::{Path, PathBuf};
@@ -11,7 +12,7 @@ use std::time::Duration;
 use tiktoken_rs::cl100k_base;
 use tokio::sync::Mutex;
 
-use crate::config::RagConfig;
+use crate::config::{ProviderKind, RagConfig};
 use crate::db::Database;
 
 /// Supported document file extensions for RAG indexing
@@ -20,90 +21,194 @@ const SUPPORTED_EXTENSIONS: &[&str] = &[
     "php", "sh", "bash", "yml", "yaml", "json", "toml", "xml", "html", "css", "scss",
 ];
 
-/// RAG embedder using Rig with OpenAI-compatible API
-pub struct RagEmbedder {
+/// Computes embeddings for one provider's API
+#[async_trait::async_trait]
+trait EmbeddingBackend: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>>;
+}
+
+/// OpenAI-compatible `/embeddings` (LM Studio, OpenAI, Ollama's `/v1`, ...) via Rig
+struct OpenAiEmbeddings {
     client: rig::providers::openai::Client,
     model: String,
 }
 
+#[async_trait::async_trait]
+impl EmbeddingBackend for OpenAiEmbeddings {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let embeddings = self
+            .client
+            .embeddings(&self.model)
+            .document(text)
+            .context("Failed to create embedding document")?
+            .build()
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        // Get the first embedding from OneOrMany
+        let (_, embedding) = embeddings.first().context("No embeddings returned")?;
+        let emb = embedding
+            .iter()
+            .next()
+            .context("No embeddings in response")?;
+        Ok(emb.vec.iter().map(|&x| x as f32).collect())
+    }
+}
+
+/// Ollama's native `/api/embeddings`
+struct OllamaEmbeddings {
+    url: String,
+    model: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl OllamaEmbeddings {
+    /// `embedding_url` may be the server root or its OpenAI-compatible `/v1` endpoint
+    fn new(embedding_url: &str, model: &str, api_key: String, client: reqwest::Client) -> Self {
+        let base = embedding_url.trim_end_matches('/');
+        let base = base.strip_suffix("/v1").unwrap_or(base);
+        Self {
+            url: format!("{}/api/embeddings", base),
+            model: model.to_string(),
+            api_key,
+            client,
+        }
+    }
+
+    fn request(&self, text: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .client
+            .post(&self.url)
+            .json(&serde_json::json!({"model": self.model, "prompt": text}));
+        if self.api_key == "not-needed" {
+            request
+        } else {
+            request.bearer_auth(&self.api_key)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl EmbeddingBackend for OllamaEmbeddings {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        #[derive(serde::Deserialize)]
+        struct EmbeddingResponse {
+            embedding: Vec<f32>,
+        }
+
+        let response = self.request(text).send().await?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(anyhow::anyhow!("HTTP {}: {}", status, body.trim()));
+        }
+        let response: EmbeddingResponse = response
+            .json()
+            .await
+            .context("Unexpected response from Ollama")?;
+        if response.embedding.is_empty() {
+            return Err(anyhow::anyhow!("No embeddings returned"));
+        }
+        Ok(response.embedding)
+    }
+}
+
+/// RAG embedder for OpenAI-compatible or Ollama embedding APIs
+pub struct RagEmbedder {
+    backend: Box<dyn EmbeddingBackend>,
+    model: String,
+    url: String,
+    provider: ProviderKind,
+}
+
 impl RagEmbedder {
-    /// Create a new RAG embedder with the specified configuration
+    /// Create a new RAG embedder from the `rag` configuration, sending requests through `http_client`
     ///
-    /// # Arguments
-    /// * `embedding_url` - Base URL for the embedding service (e.g., "http://localhost:1234")
-    /// * `model` - Model name to use for embeddings
+    /// `embedding_url` is used as-is for OpenAI-compatible APIs, only removing trailing slashes:
+    ///   - http://localhost:1234/v1 (LM Studio)
+    ///   - http://localhost:11434/v1 (Ollama)
+    ///   - http://localhost:12434/engines/v1 (Docker model runner)
     ///
-    /// # Notes
-    /// This uses the rig-core 0.33.0+ builder pattern to set a custom base URL.
-    /// The builder pattern is required to properly override the OpenAI endpoint
-    /// for local services like LM Studio or Ollama.
-    pub fn new(embedding_url: &str, model: &str) -> Result<Self> {
-        // For OpenAI-compatible APIs (LM Studio, Ollama, Docker model runner, etc.)
-        // Pass the full URL as-is, only removing trailing slashes
-        // Examples:
-        //   - http://localhost:1234/v1 (LM Studio)
-        //   - http://localhost:11434/v1 (Ollama)
-        //   - http://localhost:12434/engines/v1 (Docker model runner)
-        let normalized_url = embedding_url.trim_end_matches('/');
-
-        // Create client with custom base URL using builder pattern
-        // Note: In rig-core 0.33.0+, the builder() -> api_key() -> base_url() -> build()
-        // pattern is required to properly set a custom endpoint. The full URL path
-        // (including /v1 or /engines/v1) should be provided in the base_url.
-        // The API key "not-needed" works for local services that don't require authentication.
-        let client = rig::providers::openai::Client::builder()
-            .api_key("not-needed")
-            .base_url(normalized_url)
-            .build()
-            .context("Failed to create OpenAI client")?;
+    /// With `embedding_provider: "ollama"` it may be the server root or its `/v1` endpoint.
+    pub fn new(config: &RagConfig, http_client: reqwest::Client) -> Result<Self> {
+        let url = config.embedding_url.trim_end_matches('/');
+        let api_key = config.embedding_api_key();
+
+        let backend: Box<dyn EmbeddingBackend> = match config.embedding_provider {
+            ProviderKind::OpenAi => {
+                // Note: In rig-core 0.33.0+, the builder() -> api_key() -> base_url() -> build()
+                // pattern is required to properly set a custom endpoint. The full URL path
+                // (including /v1 or /engines/v1) should be provided in the base_url.
+                let client = rig::providers::openai::Client::builder()
+                    .api_key(&api_key)
+                    .base_url(url)
+                    .http_client(http_client)
+                    .build()
+                    .context("Failed to create OpenAI client")?;
+                Box::new(OpenAiEmbeddings {
+                    client,
+                    model: config.embedding_model.clone(),
+                })
+            }
+            ProviderKind::Ollama => Box::new(OllamaEmbeddings::new(
+                url,
+                &config.embedding_model,
+                api_key,
+                http_client,
+            )),
+        };
 
         Ok(Self {
-            client,
-            model: model.to_string(),
+            backend,
+            model: config.embedding_model.clone(),
+            url: url.to_string(),
+            provider: config.embedding_provider,
         })
     }
 
+    /// Embedding model, so stored embeddings can be matched to the model that made them
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
     /// Generate embeddings for a single text
     pub async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
         debug!("Generating embedding using model: {}", self.model);
         debug!("Text length: {} characters", text.len());
 
-        let embeddings = match self
-            .client
-            .embeddings(&self.model)
-            .document(text)
-            .context("Failed to create embedding document")?
-            .build()
-            .await
-        {
-            Ok(embeddings) => embeddings,
+        match self.backend.embed(text).await {
+            Ok(embedding) => {
+                debug!("Successfully generated embeddings");
+                Ok(embedding)
+            }
             Err(e) => {
                 error!("Failed to call embedding API");
                 error!("  Error details: {:?}", e);
                 error!("  Model: {}", self.model);
-                return Err(anyhow::anyhow!(
+                Err(anyhow::anyhow!(
                     "Failed to generate embedding (model: {}, error: {}). Check if embedding service is running and the model is available",
                     self.model,
                     e
-                ));
+                ))
             }
-        };
-
-        debug!("Successfully generated embeddings");
-
-        if let Some((_, embedding)) = embeddings.first() {
-            // Get the first embedding from OneOrMany
-            let emb = embedding
-                .iter()
-                .next()
-                .context("No embeddings in response")?;
-            Ok(emb.vec.iter().map(|&x| x as f32).collect())
-        } else {
-            Err(anyhow::anyhow!("No embeddings returned"))
         }
     }
 
+    /// Send a test embedding to make sure the service is reachable and the model works
+    pub async fn check_connection(&self) -> Result<()> {
+        self.embed_text("test").await.map(|_| ()).map_err(|e| {
+            anyhow::anyhow!(
+                "Embedding service at {} ({} provider) is not working: {}",
+                self.url,
+                self.provider.as_str(),
+                e
+            )
+        })
+    }
+
     /// Generate embeddings for multiple texts in batch
+    #[cfg(test)]
     pub async fn embed_batch(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>> {
         let mut results = Vec::new();
 
@@ -124,6 +229,9 @@ pub struct DocumentChunk {
     pub tokens: usize,
 }
 
+/// Longest document title kept, in characters
+const MAX_TITLE_CHARS: usize = 120;
+
 /// Document manager for chunking and processing documents
 pub struct DocumentManager {
     chunk_size: usize,
@@ -131,12 +239,26 @@ pub struct DocumentManager {
 }
 
 impl DocumentManager {
-    /// Create a new document manager
-    pub fn new(chunk_size: usize, chunk_overlap: usize) -> Self {
-        Self {
+    /// Create a new document manager; the overlap must be smaller than the chunk size
+    pub fn new(chunk_size: usize, chunk_overlap: usize) -> Result<Self> {
+        if chunk_size == 0 || chunk_overlap == 0 {
+            return Err(anyhow::anyhow!(
+                "rag.chunk_size and rag.chunk_overlap must be greater than 0 (got {} and {})",
+                chunk_size,
+                chunk_overlap
+            ));
+        }
+        if chunk_overlap >= chunk_size {
+            return Err(anyhow::anyhow!(
+                "rag.chunk_overlap ({}) must be smaller than rag.chunk_size ({}), or chunking can't advance",
+                chunk_overlap,
+                chunk_size
+            ));
+        }
+        Ok(Self {
             chunk_size,
             chunk_overlap,
-        }
+        })
     }
 
     /// Check if a file extension is supported
@@ -168,7 +290,8 @@ impl DocumentManager {
     ///
     /// This implementation works with character positions rather than token slicing
     /// to avoid UTF-8 decoding issues when token boundaries don't align with
-    /// character boundaries.
+    /// character boundaries. Chunks end after a sentence or word where one is near, and the
+    /// overlap starts at a word, so neither cuts a word in half.
     pub fn chunk_text(&self, text: &str) -> Result<Vec<DocumentChunk>> {
         let bpe = cl100k_base().context("Failed to load tokenizer")?;
 
@@ -231,6 +354,19 @@ impl DocumentManager {
                 }
             }
 
+            // Back off to a sentence or word end in the chunk's last fifth
+            if end_char < text_chars.len() {
+                let search_from = end_char - (end_char - start_char) / 5;
+                if let Some(boundary) = chunk_end_boundary(&text_chars, search_from, end_char)
+                    && boundary > start_char
+                    && boundary < end_char
+                {
+                    end_char = boundary;
+                    chunk_text = text_chars[start_char..end_char].iter().collect();
+                    chunk_tokens = bpe.encode_ordinary(&chunk_text).len();
+                }
+            }
+
             chunks.push(DocumentChunk {
                 index: chunk_index,
                 text: chunk_text,
@@ -243,9 +379,23 @@ impl DocumentManager {
                 break;
             }
 
-            // Calculate overlap in characters
+            // Calculate overlap in characters, starting it at a word: the next one, else the
+            // one it falls in
             let overlap_chars = (self.chunk_overlap as f64 * chars_per_token) as usize;
-            start_char = end_char.saturating_sub(overlap_chars.max(1));
+            let overlap_start = end_char.saturating_sub(overlap_chars.max(1));
+            let next_start = (overlap_start..end_char)
+                .find(|&i| is_word_start(&text_chars, i))
+                .or_else(|| {
+                    (start_char + 1..overlap_start)
+                        .rev()
+                        .find(|&i| is_word_start(&text_chars, i))
+                })
+                .unwrap_or(overlap_start);
+            // Always advance by at least a token, however the chunk came out
+            let min_advance = (chars_per_token.ceil() as usize).max(1);
+            start_char = next_start
+                .max(start_char + min_advance)
+                .min(text_chars.len());
         }
 
         debug!(
@@ -257,6 +407,28 @@ impl DocumentManager {
         Ok(chunks)
     }
 
+    /// Extract a human-friendly title for a document
+    ///
+    /// Markdown uses the front-matter `title:` or the first H1; source files use the first line
+    /// of their module doc comment or docstring. Falls back to the filename.
+    pub fn extract_title(&self, path: &Path, content: &str) -> String {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        let title = match extension {
+            "md" => markdown_title(content),
+            "txt" | "json" | "yml" | "yaml" | "toml" | "xml" | "html" | "css" | "scss" => None,
+            _ => doc_comment_title(content),
+        };
+
+        match title {
+            Some(title) => title.chars().take(MAX_TITLE_CHARS).collect(),
+            None => path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_string(),
+        }
+    }
+
     /// Process a document file: read, chunk, and return chunks
     pub fn process_document(&self, path: &Path) -> Result<(String, Vec<DocumentChunk>)> {
         let content = self.read_file_content(path)?;
@@ -265,6 +437,98 @@ impl DocumentManager {
     }
 }
 
+/// Position just past the last sentence end in `chars[from..to]`, else past the last
+/// whitespace, if there is either
+fn chunk_end_boundary(chars: &[char], from: usize, to: usize) -> Option<usize> {
+    let sentence_end = (from + 1..to).rev().find(|&i| {
+        chars[i].is_whitespace()
+            && (chars[i - 1] == '\n' || matches!(chars[i - 1], '.' | '!' | '?'))
+    });
+    sentence_end
+        .or_else(|| (from..to).rev().find(|&i| chars[i].is_whitespace()))
+        .map(|i| i + 1)
+}
+
+/// Whether a word starts at `i`: a non-space after whitespace or at the start of the text
+fn is_word_start(chars: &[char], i: usize) -> bool {
+    !chars[i].is_whitespace() && (i == 0 || chars[i - 1].is_whitespace())
+}
+
+/// Front-matter `title:` or the first `# ` heading outside code fences
+fn markdown_title(content: &str) -> Option<String> {
+    let mut lines = content.lines();
+    if content.starts_with("---") && lines.next().map(str::trim_end) == Some("---") {
+        for line in lines.by_ref() {
+            if line.trim_end() == "---" {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("title:") {
+                let value = value.trim().trim_matches(|c| c == '"' || c == '\'');
+                if !value.is_empty() {
+                    return Some(value.to_string());
+                }
+            }
+        }
+    }
+
+    let mut in_fence = false;
+    for line in lines {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        } else if !in_fence && let Some(heading) = trimmed.strip_prefix("# ") {
+            let heading = heading.trim().trim_end_matches('#').trim();
+            if !heading.is_empty() {
+                return Some(heading.to_string());
+            }
+        }
+    }
+    None
+}
+
+/// First line of a leading module doc comment or docstring
+///
+/// Recognizes Rust `//!`, block comments (`/** */`, `/*! */`) and Python docstrings, after
+/// skipping a shebang and blank lines.
+fn doc_comment_title(content: &str) -> Option<String> {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with("#!"));
+    let first = lines.next()?;
+
+    let text = if first.starts_with("//!") {
+        std::iter::once(first)
+            .chain(lines)
+            .map_while(|line| line.strip_prefix("//!"))
+            .map(str::trim)
+            .find(|line| !line.is_empty())?
+    } else if let Some(rest) = first
+        .strip_prefix("/**")
+        .or_else(|| first.strip_prefix("/*!"))
+    {
+        std::iter::once(rest)
+            .chain(lines)
+            .map(|line| line.trim_end_matches("*/").trim_start_matches('*').trim())
+            .find(|line| !line.is_empty())?
+    } else if let Some(rest) = first
+        .strip_prefix("\"\"\"")
+        .or_else(|| first.strip_prefix("'''"))
+    {
+        std::iter::once(rest)
+            .chain(lines)
+            .map(|line| {
+                line.trim_end_matches("\"\"\"")
+                    .trim_end_matches("'''")
+                    .trim()
+            })
+            .find(|line| !line.is_empty())?
+    } else {
+        return None;
+    };
+    Some(text.to_string())
+}
+
 /// Vector store interface for embedding storage and retrieval
 pub trait VectorStore {
     fn insert_embedding(&self, chunk_id: i64, embedding: &[f32]) -> Result<()>;
@@ -277,7 +541,42 @@ pub struct SearchResult {
     pub chunk_id: i64,
     pub chunk_text: String,
     pub filename: String,
+    /// Document title, the filename when none was extracted
+    pub title: String,
+    /// First chunk index covered by this result
+    pub chunk_start: i64,
+    /// Last chunk index covered by this result (differs from `chunk_start` for merged blocks)
+    pub chunk_end: i64,
     pub distance: f32,
+    /// Relevance used for ordering, higher is better
+    pub score: f32,
+}
+
+impl SearchResult {
+    /// Whether this result joins several neighboring chunks
+    pub fn is_merged(&self) -> bool {
+        self.chunk_end > self.chunk_start
+    }
+
+    /// Title and filename with the chunk range for merged blocks,
+    /// e.g. `Install Guide (guide.md, chunks 2-4)`
+    ///
+    /// Documents without a title of their own are shown by filename, e.g. `guide.md (chunks 2-4)`.
+    pub fn label(&self) -> String {
+        let mut details = Vec::new();
+        if self.title != self.filename {
+            details.push(self.filename.clone());
+        }
+        if self.is_merged() {
+            details.push(format!("chunks {}-{}", self.chunk_start, self.chunk_end));
+        }
+
+        if details.is_empty() {
+            self.title.clone()
+        } else {
+            format!("{} ({})", self.title, details.join(", "))
+        }
+    }
 }
 
 /// SQLite vector store implementation
@@ -306,12 +605,18 @@ impl VectorStore for SqliteVecStore {
 
         Ok(results
             .into_iter()
-            .map(|(chunk_id, chunk_text, filename, distance)| SearchResult {
-                chunk_id,
-                chunk_text,
-                filename,
-                distance,
-            })
+            .map(
+                |(chunk_id, chunk_text, filename, chunk_index, distance, title)| SearchResult {
+                    chunk_id,
+                    chunk_text,
+                    filename,
+                    title,
+                    chunk_start: chunk_index,
+                    chunk_end: chunk_index,
+                    distance,
+                    score: vector_relevance(distance),
+                },
+            )
             .collect())
     }
 }
@@ -321,28 +626,28 @@ pub struct RagQuery {
     embedder: Arc<RagEmbedder>,
     vector_store: Arc<SqliteVecStore>,
     top_k: usize,
+    merge_adjacent: bool,
+    rerank: bool,
 }
 
 impl RagQuery {
     pub fn new(
         embedder: Arc<RagEmbedder>,
         vector_store: Arc<SqliteVecStore>,
-        top_k: usize,
+        config: &RagConfig,
     ) -> Self {
         Self {
             embedder,
             vector_store,
-            top_k,
+            top_k: config.top_k,
+            merge_adjacent: config.merge_adjacent,
+            rerank: config.rerank,
         }
     }
 
     /// Execute RAG query: embed query, retrieve context, format for LLM
     pub async fn execute(&self, query: &str) -> Result<String> {
-        let query_embedding = self.embedder.embed_text(query).await?;
-
-        let results = self
-            .vector_store
-            .query_similar(&query_embedding, self.top_k)?;
+        let results = self.execute_structured(query).await?;
 
         if results.is_empty() {
             return Ok(String::new());
@@ -354,8 +659,8 @@ impl RagQuery {
             context.push_str(&format!(
                 "## Source {}: {} (relevance: {:.3})\n\n{}\n\n",
                 idx + 1,
-                result.filename,
-                1.0 - result.distance.min(1.0),
+                result.label(),
+                result.score,
                 result.chunk_text
             ));
         }
@@ -365,9 +670,196 @@ impl RagQuery {
 
     /// Execute query and return structured results
     pub async fn execute_structured(&self, query: &str) -> Result<Vec<SearchResult>> {
+        self.execute_top_k(query, self.top_k).await
+    }
+
+    /// Execute query and return up to `top_k` structured results instead of the configured number
+    pub async fn execute_top_k(&self, query: &str, top_k: usize) -> Result<Vec<SearchResult>> {
         let query_embedding = self.embedder.embed_text(query).await?;
-        self.vector_store
-            .query_similar(&query_embedding, self.top_k)
+        self.retrieve(&query_embedding, query, top_k)
+    }
+
+    /// Fetch candidates for an embedded query and refine them into the final results
+    fn retrieve(&self, embedding: &[f32], query: &str, top_k: usize) -> Result<Vec<SearchResult>> {
+        // Over-fetch so merging and reranking have neighbors and alternatives to work with
+        let limit = if self.merge_adjacent || self.rerank {
+            top_k * CANDIDATE_MULTIPLIER
+        } else {
+            top_k
+        };
+        let candidates = self.vector_store.query_similar(embedding, limit)?;
+        Ok(refine_results(
+            candidates,
+            query,
+            top_k,
+            self.merge_adjacent,
+            self.rerank,
+        ))
+    }
+}
+
+/// How many candidates to fetch per requested result when merging or reranking
+const CANDIDATE_MULTIPLIER: usize = 3;
+
+/// Weight of the lexical score when reranking; the rest comes from vector distance
+const LEXICAL_WEIGHT: f32 = 0.3;
+
+/// Token-set similarity above which two chunks count as duplicates
+const DUPLICATE_SIMILARITY: f32 = 0.9;
+
+/// Relevance shown for a raw vector distance
+fn vector_relevance(distance: f32) -> f32 {
+    1.0 - distance.min(1.0)
+}
+
+/// Dedupe, merge and rerank vector search candidates, then keep the best `top_k`
+///
+/// `candidates` are expected in ascending distance order, as returned by the vector store.
+fn refine_results(
+    candidates: Vec<SearchResult>,
+    query: &str,
+    top_k: usize,
+    merge_adjacent: bool,
+    rerank: bool,
+) -> Vec<SearchResult> {
+    let mut results = dedupe_results(candidates);
+    if merge_adjacent {
+        results = merge_adjacent_results(results);
+    }
+
+    let lexical = if rerank {
+        lexical_scores(&results, query)
+    } else {
+        vec![0.0; results.len()]
+    };
+    for (result, lexical) in results.iter_mut().zip(lexical) {
+        let vector = vector_relevance(result.distance);
+        result.score = if rerank {
+            (1.0 - LEXICAL_WEIGHT) * vector + LEXICAL_WEIGHT * lexical
+        } else {
+            vector
+        };
+    }
+
+    results.sort_by(|a, b| {
+        b.score
+            .total_cmp(&a.score)
+            .then(a.distance.total_cmp(&b.distance))
+    });
+    results.truncate(top_k);
+    results
+}
+
+/// Lowercased alphanumeric words used for duplicate detection and lexical scoring
+fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() > 1)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Drop chunks whose text nearly repeats a closer one
+fn dedupe_results(candidates: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut kept: Vec<(SearchResult, std::collections::HashSet<String>)> = Vec::new();
+    for candidate in candidates {
+        let words: std::collections::HashSet<String> =
+            terms(&candidate.chunk_text).into_iter().collect();
+        let duplicate = kept.iter().any(|(other, other_words)| {
+            if words.is_empty() || other_words.is_empty() {
+                return candidate.chunk_text.trim() == other.chunk_text.trim();
+            }
+            let shared = words.intersection(other_words).count() as f32;
+            let total = words.union(other_words).count() as f32;
+            shared / total >= DUPLICATE_SIMILARITY
+        });
+        if !duplicate {
+            kept.push((candidate, words));
+        }
+    }
+    kept.into_iter().map(|(result, _)| result).collect()
+}
+
+/// Join results that are consecutive chunks of the same document
+fn merge_adjacent_results(results: Vec<SearchResult>) -> Vec<SearchResult> {
+    let mut sorted = results;
+    sorted.sort_by(|a, b| {
+        a.filename
+            .cmp(&b.filename)
+            .then(a.chunk_start.cmp(&b.chunk_start))
+    });
+
+    let mut merged: Vec<SearchResult> = Vec::new();
+    for result in sorted {
+        if let Some(last) = merged.last_mut()
+            && last.filename == result.filename
+            && result.chunk_start == last.chunk_end + 1
+        {
+            last.chunk_text = join_overlapping(&last.chunk_text, &result.chunk_text);
+            last.chunk_end = result.chunk_end;
+            if result.distance < last.distance {
+                last.distance = result.distance;
+                last.chunk_id = result.chunk_id;
+            }
+            continue;
+        }
+        merged.push(result);
+    }
+
+    merged.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    merged
+}
+
+/// Concatenate two neighboring chunks, dropping the overlap the chunker repeats at the seam
+fn join_overlapping(first: &str, second: &str) -> String {
+    let max = first.len().min(second.len());
+    let overlap = (1..=max)
+        .rev()
+        .filter(|&len| second.is_char_boundary(len))
+        .find(|&len| first.ends_with(&second[..len]))
+        .unwrap_or(0);
+    format!("{}{}", first, &second[overlap..])
+}
+
+/// BM25 score of every result against the query, normalized to 0..=1
+fn lexical_scores(results: &[SearchResult], query: &str) -> Vec<f32> {
+    const K1: f32 = 1.2;
+    const B: f32 = 0.75;
+
+    let mut query_terms = terms(query);
+    query_terms.sort();
+    query_terms.dedup();
+    let docs: Vec<Vec<String>> = results.iter().map(|r| terms(&r.chunk_text)).collect();
+    if query_terms.is_empty() || docs.is_empty() {
+        return vec![0.0; results.len()];
+    }
+
+    let doc_count = docs.len() as f32;
+    let avg_len = (docs.iter().map(Vec::len).sum::<usize>() as f32 / doc_count).max(1.0);
+
+    let scores: Vec<f32> = docs
+        .iter()
+        .map(|doc| {
+            let len = doc.len() as f32;
+            query_terms
+                .iter()
+                .map(|term| {
+                    let tf = doc.iter().filter(|word| *word == term).count() as f32;
+                    if tf == 0.0 {
+                        return 0.0;
+                    }
+                    let df = docs.iter().filter(|d| d.contains(term)).count() as f32;
+                    let idf = (1.0 + (doc_count - df + 0.5) / (df + 0.5)).ln();
+                    idf * tf * (K1 + 1.0) / (tf + K1 * (1.0 - B + B * len / avg_len))
+                })
+                .sum()
+        })
+        .collect();
+
+    let max = scores.iter().cloned().fold(0.0, f32::max);
+    if max > 0.0 {
+        scores.into_iter().map(|score| score / max).collect()
+    } else {
+        scores
     }
 }
 
@@ -420,15 +912,6 @@ impl DocumentWatcher {
         Ok(())
     }
 
-    /// Stop watching
-    pub fn stop(&mut self) -> Result<()> {
-        self.watcher
-            .unwatch(&self.documents_path)
-            .context("Failed to stop watching documents directory")?;
-        info!("Stopped watching: {}", self.documents_path.display());
-        Ok(())
-    }
-
     /// Process file system events (should be called in a loop)
     pub async fn process_events(&self) -> Result<()> {
         let rx = self.receiver.lock().await;
@@ -450,7 +933,7 @@ impl DocumentWatcher {
                 for path in event.paths {
                     if path.is_file() && self.is_supported_file(&path) {
                         info!("Detected change in: {}", path.display());
-                        if let Err(e) = self.indexer.index_single_file(&path).await {
+                        if let Err(e) = self.indexer.index_single_file(&path, false).await {
                             error!("Failed to index {}: {}", path.display(), e);
                         }
                     }
@@ -499,14 +982,14 @@ impl RagIndexer {
         embedder: Arc<RagEmbedder>,
         vector_store: Arc<SqliteVecStore>,
         config: &RagConfig,
-    ) -> Self {
-        Self {
+    ) -> Result<Self> {
+        Ok(Self {
             db,
             embedder,
             vector_store,
-            doc_manager: DocumentManager::new(config.chunk_size, config.chunk_overlap),
+            doc_manager: DocumentManager::new(config.chunk_size, config.chunk_overlap)?,
             embedding_url: config.embedding_url.clone(),
-        }
+        })
     }
 
     /// Scan and index all documents in a directory
@@ -544,7 +1027,7 @@ impl RagIndexer {
         info!("Found {} documents to process", stats.files_found);
 
         for path in files_to_process {
-            match self.index_single_file(&path).await {
+            match self.index_single_file(&path, false).await {
                 Ok(_) => {
                     stats.files_processed += 1;
                 }
@@ -563,7 +1046,9 @@ impl RagIndexer {
     }
 
     /// Index a single document file
-    pub async fn index_single_file(&self, path: &Path) -> Result<()> {
+    ///
+    /// Unchanged documents are skipped unless `force` is set.
+    pub async fn index_single_file(&self, path: &Path, force: bool) -> Result<()> {
         let filename = path
             .file_name()
             .and_then(|n| n.to_str())
@@ -577,9 +1062,10 @@ impl RagIndexer {
         }
 
         let content_hash = self.doc_manager.calculate_content_hash(&content);
+        let title = self.doc_manager.extract_title(path, &content);
 
         if let Some((doc_id, existing_hash, _)) = self.db.get_rag_document_by_filename(filename)? {
-            if existing_hash == content_hash {
+            if existing_hash == content_hash && !force {
                 debug!("Document {} unchanged, skipping", filename);
                 return Ok(());
             }
@@ -589,9 +1075,9 @@ impl RagIndexer {
         }
 
         let file_size = content.len() as i64;
-        let doc_id = self
-            .db
-            .upsert_rag_document(filename, &content, &content_hash, file_size)?;
+        let doc_id =
+            self.db
+                .upsert_rag_document(filename, &title, &content, &content_hash, file_size)?;
 
         for chunk in chunks {
             let chunk_id = self.db.insert_rag_chunk(
@@ -657,17 +1143,70 @@ impl RagIndexer {
         Ok(docs
             .into_iter()
             .map(
-                |(id, filename, file_size, created_at, updated_at)| DocumentInfo {
+                |(
+                    id,
+                    filename,
+                    file_size,
+                    created_at,
+                    updated_at,
+                    chunk_count,
+                    embedding_count,
+                    title,
+                )| DocumentInfo {
                     id,
                     filename,
+                    title,
                     file_size,
                     created_at,
                     updated_at,
+                    chunk_count,
+                    embedding_count,
                 },
             )
             .collect())
     }
 
+    /// List a page of chunks for an indexed document
+    ///
+    /// Returns `None` if the document is not indexed, otherwise the total chunk count and the page.
+    pub fn list_document_chunks(
+        &self,
+        filename: &str,
+        limit: usize,
+        offset: usize,
+    ) -> Result<Option<(i64, Vec<ChunkInfo>)>> {
+        let Some((doc_id, _, _)) = self.db.get_rag_document_by_filename(filename)? else {
+            return Ok(None);
+        };
+
+        let total = self.db.count_chunks_for_document(doc_id)?;
+        let chunks = self
+            .db
+            .list_chunks_for_document(doc_id, limit as i64, offset as i64)
+            .context("Failed to list chunks")?
+            .into_iter()
+            .map(|(index, tokens, text)| ChunkInfo {
+                index,
+                tokens,
+                text,
+            })
+            .collect();
+
+        Ok(Some((total, chunks)))
+    }
+
+    /// Find a document file by name inside the documents directory
+    ///
+    /// Documents are indexed by file name, so nested files are matched by their name too.
+    pub fn find_document_file(&self, documents_path: &Path, filename: &str) -> Option<PathBuf> {
+        walkdir::WalkDir::new(documents_path)
+            .follow_links(true)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_type().is_file() && e.file_name().to_str() == Some(filename))
+            .map(|e| e.into_path())
+    }
+
     /// Rebuild the entire index (clear and re-index)
     pub async fn rebuild(&self, documents_path: &Path) -> Result<IndexStats> {
         info!("Clearing existing RAG index...");
@@ -683,7 +1222,7 @@ impl RagIndexer {
 }
 
 /// Indexing statistics
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct IndexStats {
     pub files_found: usize,
     pub files_processed: usize,
@@ -693,30 +1232,81 @@ pub struct IndexStats {
 }
 
 /// Document information
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct DocumentInfo {
     pub id: i64,
     pub filename: String,
+    pub title: String,
     pub file_size: i64,
     pub created_at: i64,
     pub updated_at: i64,
+    pub chunk_count: i64,
+    pub embedding_count: i64,
+}
+
+/// Indexed documents, as printed by `squid rag list --json`
+#[derive(Debug, Clone, Serialize)]
+pub struct DocumentList {
+    pub documents: Vec<DocumentInfo>,
+    pub total: usize,
+}
+
+impl From<Vec<DocumentInfo>> for DocumentList {
+    fn from(documents: Vec<DocumentInfo>) -> Self {
+        let total = documents.len();
+        Self { documents, total }
+    }
+}
+
+/// Index totals, as printed by `squid rag stats --json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RagStats {
+    pub doc_count: i64,
+    pub chunk_count: i64,
+    pub embedding_count: i64,
+    pub avg_chunks_per_doc: f64,
+}
+
+impl RagStats {
+    pub fn new(doc_count: i64, chunk_count: i64, embedding_count: i64) -> Self {
+        let avg_chunks_per_doc = if doc_count > 0 {
+            chunk_count as f64 / doc_count as f64
+        } else {
+            0.0
+        };
+        Self {
+            doc_count,
+            chunk_count,
+            embedding_count,
+            avg_chunks_per_doc,
+        }
+    }
+}
+
+/// A stored chunk of an indexed document
+#[derive(Debug, Clone)]
+pub struct ChunkInfo {
+    pub index: i32,
+    pub tokens: i32,
+    pub text: String,
 }
 
 /// RAG system coordinator
 pub struct RagSystem {
     pub embedder: Arc<RagEmbedder>,
-    pub vector_store: Arc<SqliteVecStore>,
     pub indexer: Arc<RagIndexer>,
     pub query: Arc<RagQuery>,
 }
 
 impl RagSystem {
-    /// Initialize the RAG system
-    pub async fn new(db: Arc<Database>, config: &RagConfig) -> Result<Self> {
-        let embedder = Arc::new(
-            RagEmbedder::new(&config.embedding_url, &config.embedding_model)
-                .context("Failed to create embedder")?,
-        );
+    /// Initialize the RAG system, embedding through `http_client`
+    pub async fn new(
+        db: Arc<Database>,
+        config: &RagConfig,
+        http_client: reqwest::Client,
+    ) -> Result<Self> {
+        let embedder =
+            Arc::new(RagEmbedder::new(config, http_client).context("Failed to create embedder")?);
 
         let vector_store = Arc::new(SqliteVecStore::new(db.clone()));
 
@@ -725,17 +1315,12 @@ impl RagSystem {
             embedder.clone(),
             vector_store.clone(),
             config,
-        ));
+        )?);
 
-        let query = Arc::new(RagQuery::new(
-            embedder.clone(),
-            vector_store.clone(),
-            config.top_k,
-        ));
+        let query = Arc::new(RagQuery::new(embedder.clone(), vector_store, config));
 
         Ok(Self {
             embedder,
-            vector_store,
             indexer,
             query,
         })
@@ -756,14 +1341,14 @@ mod tests {
 
     #[test]
     fn test_document_manager_new() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         assert_eq!(manager.chunk_size, 512);
         assert_eq!(manager.chunk_overlap, 50);
     }
 
     #[test]
     fn test_document_manager_chunking() {
-        let manager = DocumentManager::new(100, 20);
+        let manager = DocumentManager::new(100, 20).unwrap();
         let text = "This is a test document. ".repeat(50);
         let chunks = manager.chunk_text(&text).unwrap();
         assert!(!chunks.is_empty());
@@ -777,7 +1362,7 @@ mod tests {
 
     #[test]
     fn test_document_manager_chunking_with_overlap() {
-        let manager = DocumentManager::new(50, 10);
+        let manager = DocumentManager::new(50, 10).unwrap();
         let text = "Word ".repeat(100); // Create text longer than chunk size
         let chunks = manager.chunk_text(&text).unwrap();
 
@@ -790,9 +1375,92 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_document_manager_rejects_degenerate_overlap() {
+        for (size, overlap) in [(100, 100), (100, 150), (0, 0), (100, 0)] {
+            let error = DocumentManager::new(size, overlap).err().unwrap();
+            assert!(error.to_string().contains("rag.chunk_"), "{}", error);
+        }
+        let config = RagConfig {
+            chunk_size: 64,
+            chunk_overlap: 64,
+            ..Default::default()
+        };
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        let embedder = Arc::new(RagEmbedder::new(&config, reqwest::Client::new()).unwrap());
+        let store = Arc::new(SqliteVecStore::new(db.clone()));
+        assert!(RagIndexer::new(db, embedder, store, &config).is_err());
+    }
+
+    /// Numbered words, so every chunk can be located in the text
+    fn numbered_text(words: usize, seed: u64) -> String {
+        let mut state = seed;
+        let mut text = String::new();
+        for i in 0..words {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            text.push_str(&format!("w{}", i));
+            text.push_str(match state % 10 {
+                0 => ". ",
+                1 => "\n",
+                _ => " ",
+            });
+        }
+        text
+    }
+
+    #[test]
+    fn test_chunking_covers_the_text_and_overlaps_as_configured() {
+        let bpe = cl100k_base().unwrap();
+        let mut state = 0x9e37_79b9_7f4a_7c15_u64;
+        for round in 0..8 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let size = 20 + (state % 150) as usize;
+            let overlap = 1 + (state >> 16) as usize % (size / 2);
+            let manager = DocumentManager::new(size, overlap).unwrap();
+            let text = numbered_text(300 + round * 40, state);
+            let chunks = manager.chunk_text(&text).unwrap();
+            let total_tokens = bpe.encode_ordinary(&text).len();
+            assert!(
+                chunks.len() <= total_tokens,
+                "size {} overlap {}",
+                size,
+                overlap
+            );
+
+            // Each chunk starts inside or right after the previous one, and the last reaches the end
+            let mut previous: Option<(usize, usize)> = None;
+            for chunk in &chunks {
+                assert!(chunk.tokens <= size);
+                let from = previous.map_or(0, |(start, _)| start + 1);
+                let start = from + text[from..].find(&chunk.text).unwrap();
+                if let Some((previous_start, previous_end)) = previous {
+                    assert!(start > previous_start && start <= previous_end);
+                    let shared = bpe.encode_ordinary(&text[start..previous_end]).len();
+                    assert!(
+                        shared.abs_diff(overlap) <= overlap / 2 + 4,
+                        "size {} overlap {}: {} tokens shared",
+                        size,
+                        overlap,
+                        shared
+                    );
+                    // The overlap starts at a word
+                    assert!(text[..start].ends_with(char::is_whitespace));
+                } else {
+                    assert_eq!(start, 0);
+                }
+                previous = Some((start, start + chunk.text.len()));
+            }
+            assert_eq!(previous.unwrap().1, text.len());
+        }
+    }
+
     #[test]
     fn test_document_manager_empty_text() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let result = manager.chunk_text("");
         assert!(
             result.is_ok(),
@@ -807,7 +1475,7 @@ mod tests {
 
     #[test]
     fn test_document_manager_small_text() {
-        let manager = DocumentManager::new(1000, 100);
+        let manager = DocumentManager::new(1000, 100).unwrap();
         let text = "Small text.";
         let chunks = manager.chunk_text(text).unwrap();
 
@@ -819,7 +1487,7 @@ mod tests {
 
     #[test]
     fn test_supported_extensions() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
 
         // Test all supported extensions
         assert!(manager.is_supported_extension(Path::new("test.md")));
@@ -842,7 +1510,7 @@ mod tests {
 
     #[test]
     fn test_content_hash() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let hash1 = manager.calculate_content_hash("test content");
         let hash2 = manager.calculate_content_hash("test content");
         let hash3 = manager.calculate_content_hash("different content");
@@ -860,7 +1528,7 @@ mod tests {
 
     #[test]
     fn test_read_file_content() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let temp_dir = TempDir::new().unwrap();
         let file_path = temp_dir.path().join("test.txt");
 
@@ -878,7 +1546,7 @@ mod tests {
 
     #[test]
     fn test_process_document() {
-        let manager = DocumentManager::new(100, 20);
+        let manager = DocumentManager::new(100, 20).unwrap();
         let temp_dir = TempDir::new().unwrap();
         let file_path = temp_dir.path().join("test.md");
 
@@ -899,19 +1567,159 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_extract_title_from_markdown_front_matter() {
+        let manager = DocumentManager::new(100, 20).unwrap();
+        let content =
+            "---\ntitle: \"Architecture Overview\"\ntags: [design]\n---\n\n# Overview\n\nText.";
+        assert_eq!(
+            manager.extract_title(Path::new("architecture.md"), content),
+            "Architecture Overview"
+        );
+
+        // Without a front-matter title the first H1 wins, ignoring headings inside code fences
+        let content =
+            "---\ntags: [notes]\n---\n```sh\n# install\n```\n## Setup\n# Release Notes #\n";
+        assert_eq!(
+            manager.extract_title(Path::new("notes.md"), content),
+            "Release Notes"
+        );
+    }
+
+    #[test]
+    fn test_extract_title_falls_back_to_filename() {
+        let manager = DocumentManager::new(100, 20).unwrap();
+        let content = "Meeting notes from March.\n\n## Attendees\n\n- Alice\n";
+        assert_eq!(
+            manager.extract_title(Path::new("docs/notes-2024-03.md"), content),
+            "notes-2024-03.md"
+        );
+        assert_eq!(
+            manager.extract_title(Path::new("config.toml"), "# Settings\nkey = 1\n"),
+            "config.toml"
+        );
+    }
+
+    #[test]
+    fn test_extract_title_from_source_doc_comments() {
+        let manager = DocumentManager::new(100, 20).unwrap();
+        let content = "//!\n//! Retrieval pipeline for indexed documents\n//!\n//! Details.\n\nuse std::fs;\n";
+        assert_eq!(
+            manager.extract_title(Path::new("rag.rs"), content),
+            "Retrieval pipeline for indexed documents"
+        );
+        assert_eq!(
+            manager.extract_title(
+                Path::new("tool.py"),
+                "#!/usr/bin/env python3\n\"\"\"Export sessions to CSV.\"\"\"\n"
+            ),
+            "Export sessions to CSV."
+        );
+        assert_eq!(
+            manager.extract_title(Path::new("main.rs"), "fn main() {}\n"),
+            "main.rs"
+        );
+    }
+
     // ========== RagEmbedder Tests ==========
     // Note: These tests verify the API structure but require a running embedding service
 
+    fn embedding_config(url: &str, provider: ProviderKind) -> RagConfig {
+        RagConfig {
+            embedding_url: url.to_string(),
+            embedding_model: "text-embedding-3-small".to_string(),
+            embedding_provider: provider,
+            embedding_api_key: Some("sk-embed".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_embedder_posts_to_embeddings_endpoint() {
+        let response = serde_json::json!({
+            "object": "list",
+            "data": [{"object": "embedding", "index": 0, "embedding": [0.5, -0.25]}],
+            "model": "text-embedding-3-small",
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        });
+        let (url, request) =
+            crate::providers::test_support::serve_once("application/json", response.to_string())
+                .await;
+
+        let embedder = RagEmbedder::new(
+            &embedding_config(&url, ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            embedder.embed_text("hello").await.unwrap(),
+            vec![0.5, -0.25]
+        );
+
+        let request: serde_json::Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["model"], "text-embedding-3-small");
+    }
+
+    #[tokio::test]
+    async fn test_ollama_embedder_uses_native_endpoint() {
+        let (url, request) = crate::providers::test_support::serve_once(
+            "application/json",
+            r#"{"embedding": [0.1, 0.2, 0.3]}"#.to_string(),
+        )
+        .await;
+
+        // The /v1 suffix of an OpenAI-style URL is dropped
+        let config = embedding_config(&format!("{}/v1/", url), ProviderKind::Ollama);
+        let embedder = RagEmbedder::new(&config, reqwest::Client::new()).unwrap();
+        assert_eq!(
+            embedder.embed_text("hello").await.unwrap(),
+            vec![0.1, 0.2, 0.3]
+        );
+
+        let request: serde_json::Value = serde_json::from_str(&request.await.unwrap()).unwrap();
+        assert_eq!(request["model"], "text-embedding-3-small");
+        assert_eq!(request["prompt"], "hello");
+
+        let ollama = OllamaEmbeddings::new(
+            &url,
+            "nomic-embed-text",
+            "sk-embed".to_string(),
+            reqwest::Client::new(),
+        );
+        let built = ollama.request("hi").build().unwrap();
+        assert_eq!(built.url().path(), "/api/embeddings");
+        assert_eq!(built.headers()["authorization"], "Bearer sk-embed");
+    }
+
+    #[tokio::test]
+    async fn test_check_connection_names_url_and_provider() {
+        // Nothing listens on port 9
+        let config = embedding_config("http://127.0.0.1:9", ProviderKind::Ollama);
+        let embedder = RagEmbedder::new(&config, reqwest::Client::new()).unwrap();
+        let error = embedder.check_connection().await.unwrap_err().to_string();
+        assert!(error.contains("http://127.0.0.1:9"));
+        assert!(error.contains("ollama provider"));
+    }
+
     #[test]
     fn test_rag_embedder_creation() {
         // Test that embedder can be created with various URL formats
-        let result1 = RagEmbedder::new("http://localhost:1234", "text-embedding-3-small");
+        let result1 = RagEmbedder::new(
+            &embedding_config("http://localhost:1234", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        );
         assert!(result1.is_ok(), "Should create embedder with http URL");
 
-        let result2 = RagEmbedder::new("http://localhost:1234/v1", "text-embedding-3-small");
+        let result2 = RagEmbedder::new(
+            &embedding_config("http://localhost:1234/v1", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        );
         assert!(result2.is_ok(), "Should create embedder with /v1 suffix");
 
-        let result3 = RagEmbedder::new("http://localhost:1234/v1/", "text-embedding-3-small");
+        let result3 = RagEmbedder::new(
+            &embedding_config("http://localhost:1234/v1/", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        );
         assert!(result3.is_ok(), "Should create embedder with /v1/ suffix");
     }
 
@@ -920,8 +1728,11 @@ mod tests {
     async fn test_rag_embedder_embed_text() {
         // This test requires a local embedding service running on port 1234
         // Start LM Studio or Ollama with an embedding model before running
-        let embedder = RagEmbedder::new("http://localhost:1234", "text-embedding-3-small")
-            .expect("Failed to create embedder");
+        let embedder = RagEmbedder::new(
+            &embedding_config("http://localhost:1234", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        )
+        .expect("Failed to create embedder");
 
         let result = embedder.embed_text("Hello, world!").await;
         assert!(result.is_ok(), "Should successfully embed text");
@@ -942,8 +1753,11 @@ mod tests {
     #[tokio::test]
     #[ignore] // Requires running embedding service
     async fn test_rag_embedder_embed_multiple_texts() {
-        let embedder = RagEmbedder::new("http://localhost:1234", "text-embedding-3-small")
-            .expect("Failed to create embedder");
+        let embedder = RagEmbedder::new(
+            &embedding_config("http://localhost:1234", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        )
+        .expect("Failed to create embedder");
 
         // Embed same text twice - should produce similar embeddings
         let embedding1 = embedder.embed_text("Test text").await.unwrap();
@@ -973,8 +1787,11 @@ mod tests {
     #[tokio::test]
     #[ignore] // Requires running embedding service
     async fn test_rag_embedder_embed_batch() {
-        let embedder = RagEmbedder::new("http://localhost:1234", "text-embedding-3-small")
-            .expect("Failed to create embedder");
+        let embedder = RagEmbedder::new(
+            &embedding_config("http://localhost:1234", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        )
+        .expect("Failed to create embedder");
 
         let texts = vec![
             "First text".to_string(),
@@ -998,8 +1815,11 @@ mod tests {
     #[tokio::test]
     #[ignore] // Requires running embedding service
     async fn test_rag_embedder_empty_text() {
-        let embedder = RagEmbedder::new("http://localhost:1234", "text-embedding-3-small")
-            .expect("Failed to create embedder");
+        let embedder = RagEmbedder::new(
+            &embedding_config("http://localhost:1234", ProviderKind::OpenAi),
+            reqwest::Client::new(),
+        )
+        .expect("Failed to create embedder");
 
         // Test with empty string - behavior may vary by provider
         let result = embedder.embed_text("").await;
@@ -1028,13 +1848,124 @@ mod tests {
             chunk_id: 1,
             chunk_text: "Test chunk".to_string(),
             filename: "test.md".to_string(),
+            title: "test.md".to_string(),
+            chunk_start: 0,
+            chunk_end: 0,
             distance: 0.5,
+            score: 0.5,
         };
 
         assert_eq!(result.chunk_id, 1);
         assert_eq!(result.chunk_text, "Test chunk");
         assert_eq!(result.filename, "test.md");
         assert_eq!(result.distance, 0.5);
+        assert_eq!(result.label(), "test.md");
+    }
+
+    #[test]
+    fn test_search_result_label_shows_title() {
+        let mut result = SearchResult {
+            chunk_id: 1,
+            chunk_text: "Layers".to_string(),
+            filename: "architecture.md".to_string(),
+            title: "Architecture Overview".to_string(),
+            chunk_start: 2,
+            chunk_end: 2,
+            distance: 0.5,
+            score: 0.5,
+        };
+        assert_eq!(result.label(), "Architecture Overview (architecture.md)");
+
+        result.chunk_end = 3;
+        assert_eq!(
+            result.label(),
+            "Architecture Overview (architecture.md, chunks 2-3)"
+        );
+    }
+
+    /// Index chunks into an in-memory database with embeddings at the given distances
+    /// from the zero vector
+    fn synthetic_store(chunks: &[(&str, i64, &str, f32)]) -> SqliteVecStore {
+        let db = Arc::new(Database::new(":memory:").unwrap());
+        for (filename, index, text, distance) in chunks {
+            let doc_id = db
+                .upsert_rag_document(filename, filename, "content", filename, 100)
+                .unwrap();
+            let chunk_id = db
+                .insert_rag_chunk(doc_id, *index as i32, text, 10)
+                .unwrap();
+            let mut embedding = [0.0f32; 768];
+            embedding[0] = *distance;
+            db.insert_rag_embedding(chunk_id, &embedding).unwrap();
+        }
+        SqliteVecStore::new(db)
+    }
+
+    #[test]
+    fn test_refine_merges_adjacent_chunks() {
+        let store = synthetic_store(&[
+            ("guide.md", 0, "Install squid with cargo. ", 0.2),
+            ("guide.md", 1, "with cargo. Then run squid init.", 0.1),
+            ("guide.md", 3, "Unrelated appendix.", 0.3),
+            ("notes.md", 2, "Install notes for squid.", 0.4),
+        ]);
+        let candidates = store.query_similar(&[0.0; 768], 10).unwrap();
+
+        let results = refine_results(candidates, "install", 5, true, false);
+        assert_eq!(results.len(), 3);
+
+        let merged = &results[0];
+        assert_eq!(merged.filename, "guide.md");
+        assert_eq!((merged.chunk_start, merged.chunk_end), (0, 1));
+        assert!(merged.is_merged());
+        assert_eq!(merged.label(), "guide.md (chunks 0-1)");
+        assert_eq!(
+            merged.chunk_text,
+            "Install squid with cargo. Then run squid init."
+        );
+        assert!((merged.distance - 0.1).abs() < 1e-6);
+
+        // Chunk 3 isn't adjacent to 1, and chunks of other files are never joined
+        assert!(!results[1].is_merged());
+        assert_eq!(results[1].chunk_start, 3);
+        assert_eq!(results[2].filename, "notes.md");
+    }
+
+    #[test]
+    fn test_refine_drops_duplicate_chunks() {
+        let store = synthetic_store(&[
+            ("a.md", 0, "Copyright 2024 the squid authors", 0.1),
+            ("b.md", 0, "copyright 2024  The squid authors", 0.2),
+            ("c.md", 0, "Something else entirely", 0.3),
+        ]);
+        let candidates = store.query_similar(&[0.0; 768], 10).unwrap();
+
+        let results = refine_results(candidates, "copyright", 5, false, false);
+        let files: Vec<&str> = results.iter().map(|r| r.filename.as_str()).collect();
+        assert_eq!(files, vec!["a.md", "c.md"]);
+    }
+
+    #[test]
+    fn test_rerank_prefers_lexical_match() {
+        let store = synthetic_store(&[
+            (
+                "vector.md",
+                0,
+                "General overview of the project layout",
+                0.10,
+            ),
+            ("lexical.md", 0, "Configure the embedding_url for RAG", 0.12),
+        ]);
+        let query = "how do I set embedding_url";
+
+        let candidates = store.query_similar(&[0.0; 768], 10).unwrap();
+        let plain = refine_results(candidates.clone(), query, 1, false, false);
+        assert_eq!(plain[0].filename, "vector.md");
+
+        let reranked = refine_results(candidates, query, 1, false, true);
+        assert_eq!(reranked.len(), 1);
+        assert_eq!(reranked[0].filename, "lexical.md");
+        assert!(reranked[0].score > vector_relevance(0.10));
     }
 
     // ========== SUPPORTED_EXTENSIONS Tests ==========
@@ -1056,7 +1987,7 @@ mod tests {
 
     #[test]
     fn test_chunk_indices_are_sequential() {
-        let manager = DocumentManager::new(50, 10);
+        let manager = DocumentManager::new(50, 10).unwrap();
         let text = "Word ".repeat(100);
         let chunks = manager.chunk_text(&text).unwrap();
 
@@ -1067,7 +1998,7 @@ mod tests {
 
     #[test]
     fn test_chunk_token_counts_are_positive() {
-        let manager = DocumentManager::new(100, 20);
+        let manager = DocumentManager::new(100, 20).unwrap();
         let text = "This is a test. ".repeat(20);
         let chunks = manager.chunk_text(&text).unwrap();
 
@@ -1081,7 +2012,7 @@ mod tests {
 
     #[test]
     fn test_document_manager_with_special_characters() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let text = "Special chars: !@#$%^&*()_+-=[]{}|;':\",./<>?\n\t\r";
         let chunks = manager.chunk_text(text).unwrap();
 
@@ -1091,7 +2022,7 @@ mod tests {
 
     #[test]
     fn test_document_manager_with_unicode() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let text = "Unicode: 你好世界 🌍 émojis ñ café";
         let chunks = manager.chunk_text(text).unwrap();
 
@@ -1102,7 +2033,7 @@ mod tests {
 
     #[test]
     fn test_hash_consistency_across_calls() {
-        let manager = DocumentManager::new(512, 50);
+        let manager = DocumentManager::new(512, 50).unwrap();
         let content = "Consistent content";
 
         // Generate hash multiple times
@@ -1115,4 +2046,76 @@ mod tests {
             assert_eq!(hash, &hashes[0]);
         }
     }
+
+    // ========== CLI JSON Output Tests ==========
+
+    #[test]
+    fn test_index_stats_json_shape() {
+        let stats = IndexStats {
+            files_found: 3,
+            files_processed: 2,
+            files_failed: 1,
+            total_chunks: 10,
+            total_embeddings: 10,
+        };
+        assert_eq!(
+            serde_json::to_value(&stats).unwrap(),
+            serde_json::json!({
+                "files_found": 3,
+                "files_processed": 2,
+                "files_failed": 1,
+                "total_chunks": 10,
+                "total_embeddings": 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_document_list_json_shape() {
+        let list = DocumentList::from(vec![DocumentInfo {
+            id: 7,
+            filename: "guide.md".to_string(),
+            title: "Install Guide".to_string(),
+            file_size: 1024,
+            created_at: 1700000000,
+            updated_at: 1700000100,
+            chunk_count: 4,
+            embedding_count: 4,
+        }]);
+        assert_eq!(
+            serde_json::to_value(&list).unwrap(),
+            serde_json::json!({
+                "documents": [{
+                    "id": 7,
+                    "filename": "guide.md",
+                    "title": "Install Guide",
+                    "file_size": 1024,
+                    "created_at": 1700000000,
+                    "updated_at": 1700000100,
+                    "chunk_count": 4,
+                    "embedding_count": 4
+                }],
+                "total": 1
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(DocumentList::from(vec![])).unwrap(),
+            serde_json::json!({ "documents": [], "total": 0 })
+        );
+    }
+
+    #[test]
+    fn test_rag_stats_json_shape() {
+        assert_eq!(
+            serde_json::to_value(RagStats::new(2, 5, 5)).unwrap(),
+            serde_json::json!({
+                "doc_count": 2,
+                "chunk_count": 5,
+                "embedding_count": 5,
+                "avg_chunks_per_doc": 2.5
+            })
+        );
+        // An empty index doesn't divide by zero
+        assert_eq!(RagStats::new(0, 0, 0).avg_chunks_per_doc, 0.0);
+    }
 }
@@ -0,0 +1,307 @@
+//! Per-IP rate limiting for the expensive API endpoints
+//!
+//! Each client IP gets a token bucket per endpoint group: `burst` requests can be made at once,
+//! after which tokens refill at the configured per-minute rate. Requests without a token get
+//! `429 Too Many Requests` with a `Retry-After` header.
+
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::{Error, HttpResponse, http::header, web};
+use log::warn;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+/// Buckets are pruned once this many clients are tracked
+const MAX_TRACKED_BUCKETS: usize = 4096;
+/// Share of [`MAX_TRACKED_BUCKETS`] kept when the least recently used buckets are evicted
+const EVICT_TO: usize = MAX_TRACKED_BUCKETS * 3 / 4;
+
+/// Endpoint groups with separate limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Group {
+    Chat,
+    Rag,
+}
+
+impl Group {
+    /// Every endpoint that runs a model turn counts as chat
+    fn for_path(path: &str) -> Option<Self> {
+        let segments: Vec<&str> = path.split('/').collect();
+        match segments.as_slice() {
+            ["", "api", "chat"]
+            | ["", "api", "sessions", "from-template"]
+            | ["", "api", "sessions", _, "continue" | "compact"]
+            | ["", "api", "schedules", _, "run"] => Some(Self::Chat),
+            ["", "api", "rag", _, ..] => Some(Self::Rag),
+            _ => None,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+/// Shared limiter state; clones use the same buckets
+#[derive(Clone)]
+pub struct RateLimiter {
+    enabled: bool,
+    burst: f64,
+    chat_per_minute: u32,
+    rag_per_minute: u32,
+    buckets: Arc<Mutex<HashMap<(Group, IpAddr), Bucket>>>,
+}
+
+impl RateLimiter {
+    /// `allow_network` decides whether limits apply when `enabled` isn't set explicitly
+    pub fn new(config: &RateLimitConfig, allow_network: bool) -> Self {
+        Self {
+            enabled: config.is_enabled(allow_network),
+            burst: config.burst.max(1) as f64,
+            chat_per_minute: config.chat_per_minute,
+            rag_per_minute: config.rag_per_minute,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Tokens added per second for `group`; 0 disables the limit for that group
+    fn refill_rate(&self, group: Group) -> f64 {
+        let per_minute = match group {
+            Group::Chat => self.chat_per_minute,
+            Group::Rag => self.rag_per_minute,
+        };
+        per_minute as f64 / 60.0
+    }
+
+    /// Take a token for `ip`, or return how long to wait for the next one
+    fn check(&self, group: Group, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let rate = self.refill_rate(group);
+        if rate <= 0.0 {
+            return Ok(());
+        }
+
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_BUCKETS && !buckets.contains_key(&(group, ip)) {
+            // Full buckets carry no state worth keeping
+            let burst = self.burst;
+            buckets.retain(|(group, _), bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated_at);
+                bucket.tokens + elapsed.as_secs_f64() * self.refill_rate(*group) < burst
+            });
+            // Many clients draining at once: forget the least recently seen, which only gives
+            // them a fresh burst
+            if buckets.len() >= MAX_TRACKED_BUCKETS {
+                let mut by_age: Vec<((Group, IpAddr), Instant)> = buckets
+                    .iter()
+                    .map(|(key, bucket)| (*key, bucket.updated_at))
+                    .collect();
+                by_age.sort_by_key(|(_, updated_at)| *updated_at);
+                for (key, _) in by_age.iter().take(buckets.len() - EVICT_TO) {
+                    buckets.remove(key);
+                }
+            }
+        }
+
+        let bucket = buckets.entry((group, ip)).or_insert(Bucket {
+            tokens: self.burst,
+            updated_at: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(self.burst);
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+/// Middleware applying [`RateLimiter`] (from app data) to chat endpoints and `/api/rag/*`
+pub async fn limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, Error> {
+    let group = Group::for_path(req.path());
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let ip = req.peer_addr().map(|addr| addr.ip());
+
+    if let (Some(group), Some(limiter), Some(ip)) = (group, limiter, ip)
+        && limiter.is_enabled()
+        && let Err(wait) = limiter.check(group, ip, Instant::now())
+    {
+        // Round up so clients never retry before a token is available
+        let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+        warn!(
+            "Rate limit exceeded for {} on {} (retry after {}s)",
+            ip,
+            req.path(),
+            retry_after
+        );
+        let response = HttpResponse::TooManyRequests()
+            .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+            .json(json!({
+                "error": "Too many requests",
+                "retry_after": retry_after
+            }));
+        return Ok(req.into_response(response));
+    }
+
+    next.call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::App;
+    use actix_web::test::{TestRequest, call_service, init_service};
+
+    fn limiter(chat_per_minute: u32, burst: u32) -> RateLimiter {
+        let config = RateLimitConfig {
+            enabled: Some(true),
+            chat_per_minute,
+            burst,
+            ..Default::default()
+        };
+        RateLimiter::new(&config, false)
+    }
+
+    #[test]
+    fn test_disabled_by_default_for_localhost() {
+        let config = RateLimitConfig::default();
+        assert!(!RateLimiter::new(&config, false).is_enabled());
+        assert!(RateLimiter::new(&config, true).is_enabled());
+    }
+
+    #[test]
+    fn test_bucket_refills_at_configured_rate() {
+        let limiter = limiter(60, 2);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(limiter.check(Group::Chat, ip, start).is_ok());
+        assert!(limiter.check(Group::Chat, ip, start).is_ok());
+        let wait = limiter.check(Group::Chat, ip, start).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(1));
+
+        // Other clients and groups have their own buckets
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        assert!(limiter.check(Group::Chat, other, start).is_ok());
+        assert!(limiter.check(Group::Rag, ip, start).is_ok());
+
+        let later = start + Duration::from_secs(1);
+        assert!(limiter.check(Group::Chat, ip, later).is_ok());
+        assert!(limiter.check(Group::Chat, ip, later).is_err());
+    }
+
+    #[test]
+    fn test_tracked_clients_stay_bounded() {
+        let limiter = limiter(1, 1);
+        let start = Instant::now();
+        // Each client spends its only token, so no bucket is full enough to prune
+        for n in 0..(MAX_TRACKED_BUCKETS as u32 + 1000) {
+            let ip = IpAddr::from(std::net::Ipv4Addr::from(0x0a00_0000 + n));
+            let now = start + Duration::from_millis(n as u64);
+            assert!(limiter.check(Group::Chat, ip, now).is_ok());
+            assert!(limiter.buckets.lock().unwrap().len() <= MAX_TRACKED_BUCKETS);
+        }
+
+        // The most recent clients are still limited
+        let last = IpAddr::from(std::net::Ipv4Addr::from(
+            0x0a00_0000 + MAX_TRACKED_BUCKETS as u32 + 999,
+        ));
+        let now = start + Duration::from_millis(MAX_TRACKED_BUCKETS as u64 + 999);
+        assert!(limiter.check(Group::Chat, last, now).is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_text_generating_endpoints_share_the_chat_limit() {
+        let paths = [
+            "/api/chat",
+            "/api/sessions/from-template",
+            "/api/sessions/s1/continue",
+            "/api/sessions/s1/compact",
+            "/api/schedules/nightly/run",
+        ];
+        let mut app = App::new()
+            .app_data(web::Data::new(limiter(60, 1)))
+            .wrap(actix_web::middleware::from_fn(limit));
+        for path in paths {
+            app = app.route(path, web::post().to(HttpResponse::Ok));
+        }
+        let app = init_service(app).await;
+
+        for (n, path) in paths.iter().enumerate() {
+            assert_eq!(Group::for_path(path), Some(Group::Chat));
+            // Each client gets one request, so a path missing from the group would get a 200
+            let peer = format!("192.168.1.{}:5000", n + 1).parse().unwrap();
+            for expected in [200, 429] {
+                let req = TestRequest::post().uri(path).peer_addr(peer).to_request();
+                assert_eq!(call_service(&app, req).await.status(), expected, "{}", path);
+            }
+        }
+
+        assert_eq!(Group::for_path("/api/sessions/s1"), None);
+        assert_eq!(Group::for_path("/api/rag/query"), Some(Group::Rag));
+        assert_eq!(Group::for_path("/api/rag"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_burst_gets_429_then_recovers() {
+        // 600 per minute refills one token every 100ms
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(limiter(600, 3)))
+                .wrap(actix_web::middleware::from_fn(limit))
+                .route("/api/chat", web::post().to(HttpResponse::Ok))
+                .route("/api/sessions", web::get().to(HttpResponse::Ok)),
+        )
+        .await;
+        let peer = "192.168.1.20:5000".parse().unwrap();
+
+        for _ in 0..3 {
+            let req = TestRequest::post()
+                .uri("/api/chat")
+                .peer_addr(peer)
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), 200);
+        }
+
+        let req = TestRequest::post()
+            .uri("/api/chat")
+            .peer_addr(peer)
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "1");
+
+        // Other endpoints aren't limited
+        let req = TestRequest::get()
+            .uri("/api/sessions")
+            .peer_addr(peer)
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 200);
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let req = TestRequest::post()
+            .uri("/api/chat")
+            .peer_addr(peer)
+            .to_request();
+        assert_eq!(call_service(&app, req).await.status(), 200);
+    }
+}
@@ -0,0 +1,420 @@
+//! Replay of stored sessions as chat stream events
+//!
+//! `GET /api/sessions/{id}/stream` sends a saved conversation as the events a live chat would
+//! have produced, so playback (and debugging event handling) goes through the same client code.
+//! Each assistant message becomes `turn`, `sources`, then its reasoning, content and tool steps in
+//! `step_order`, with the content split where it was streamed before each tool call. The stream
+//! ends with the session's stored token usage and `done`. Warning steps aren't replayed because
+//! their codes aren't stored.
+
+use actix_web::{HttpResponse, web};
+use serde::Deserialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::api::{self, Source, StreamEvent};
+use crate::config::Config;
+use crate::policy::SkipStatus;
+use crate::session::{ChatMessage, ChatSession, SessionManager};
+use crate::stream_events;
+
+/// Longest content piece per event when simulating streaming
+const REPLAY_CHUNK_CHARS: usize = 24;
+
+/// Longest delay between replayed events, in milliseconds
+const MAX_REPLAY_DELAY_MS: u64 = 5_000;
+
+#[derive(Debug, Deserialize)]
+pub struct ReplayQuery {
+    /// Delay between content chunks in milliseconds; `0` sends each piece whole, as fast as possible
+    #[serde(default)]
+    pub speed: u64,
+    /// Replay only the assistant message at this index of the session's messages
+    pub message_index: Option<usize>,
+}
+
+/// Why a session can't be replayed as asked
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    /// `message_index` is past the end of the session
+    NoSuchMessage(usize),
+    /// `message_index` points at a message the model didn't write
+    NotAnAnswer(usize),
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::NoSuchMessage(index) => write!(f, "No message at index {}", index),
+            ReplayError::NotAnAnswer(index) => {
+                write!(f, "Message {} is not an assistant message", index)
+            }
+        }
+    }
+}
+
+/// The events replaying `session`, or only its answer at `message_index`
+///
+/// With `chunked`, content is split into short pieces like a streaming model sends it.
+pub fn replay_events(
+    session: &ChatSession,
+    message_index: Option<usize>,
+    chunked: bool,
+) -> Result<Vec<StreamEvent>, ReplayError> {
+    let indexes: Vec<usize> = match message_index {
+        Some(index) => match session.messages.get(index) {
+            None => return Err(ReplayError::NoSuchMessage(index)),
+            Some(message) if message.role != "assistant" => {
+                return Err(ReplayError::NotAnAnswer(index));
+            }
+            Some(_) => vec![index],
+        },
+        None => (0..session.messages.len())
+            .filter(|&index| session.messages[index].role == "assistant")
+            .collect(),
+    };
+
+    let mut events = vec![StreamEvent::Session {
+        session_id: session.id.clone(),
+    }];
+    for &index in &indexes {
+        let prompt = index
+            .checked_sub(1)
+            .and_then(|previous| session.messages.get(previous))
+            .filter(|message| message.role == "user")
+            .map(|message| message.content.clone());
+        events.push(StreamEvent::Turn {
+            message_index: index,
+            prompt,
+        });
+        answer_events(&session.messages[index], chunked, &mut events);
+    }
+
+    let usage = &session.token_usage;
+    events.push(StreamEvent::Usage {
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        reasoning_tokens: usage.reasoning_tokens,
+        cache_tokens: usage.cache_tokens,
+        estimated: false,
+    });
+    // The replayed stream ends like the last answer in it did
+    let last_answer = indexes.last().map(|&index| &session.messages[index]);
+    events.push(StreamEvent::Done {
+        finish_reason: last_answer.and_then(|message| message.finish_reason),
+        message_id: last_answer.and_then(|message| message.id),
+    });
+    Ok(events)
+}
+
+/// Events of one assistant message, in the order its thinking steps were stored
+fn answer_events(message: &ChatMessage, chunked: bool, events: &mut Vec<StreamEvent>) {
+    if !message.sources.is_empty() {
+        events.push(StreamEvent::Sources {
+            sources: message.sources.iter().map(Source::from).collect(),
+        });
+    }
+
+    // Content before each tool call is stored raw, the message content sanitized and trimmed;
+    // locating each piece keeps the replayed content identical to the stored one
+    let content = message.content.as_str();
+    let mut cursor = 0;
+    for step in message.thinking_steps.iter().flatten() {
+        match step.step_type.as_str() {
+            "reasoning" => {
+                if let Some(text) = step.content.as_ref().filter(|text| !text.is_empty()) {
+                    events.push(StreamEvent::Reasoning { text: text.clone() });
+                }
+            }
+            "tool" => {
+                if let Some(delta) = step.content_delta_before_tool.as_deref() {
+                    let delta = api::sanitize_assistant_content(delta);
+                    let delta = delta.trim();
+                    if !delta.is_empty()
+                        && let Some(position) = content[cursor..].find(delta)
+                    {
+                        let end = cursor + position + delta.len();
+                        push_content(&content[cursor..end], chunked, events);
+                        cursor = end;
+                    }
+                }
+                events.push(StreamEvent::ToolInvocationCompleted {
+                    name: step.tool_name.clone().unwrap_or_default(),
+                    arguments: step
+                        .tool_arguments
+                        .clone()
+                        .unwrap_or_else(|| serde_json::json!({})),
+                    result: step.tool_result.clone(),
+                    error: step.tool_error.clone(),
+                    status: step.status.as_deref().and_then(SkipStatus::parse),
+                });
+            }
+            _ => {}
+        }
+    }
+    push_content(&content[cursor..], chunked, events);
+}
+
+/// Content events for `text`, split into pieces of about `REPLAY_CHUNK_CHARS` when `chunked`
+fn push_content(text: &str, chunked: bool, events: &mut Vec<StreamEvent>) {
+    if text.is_empty() {
+        return;
+    }
+    if !chunked {
+        events.push(StreamEvent::Content {
+            text: text.to_string(),
+        });
+        return;
+    }
+
+    let mut piece = String::new();
+    for word in text.split_inclusive(char::is_whitespace) {
+        piece.push_str(word);
+        if piece.chars().count() >= REPLAY_CHUNK_CHARS {
+            events.push(StreamEvent::Content {
+                text: std::mem::take(&mut piece),
+            });
+        }
+    }
+    if !piece.is_empty() {
+        events.push(StreamEvent::Content { text: piece });
+    }
+}
+
+/// Replay a stored session as a chat event stream
+pub async fn replay_session(
+    session_id: web::Path<String>,
+    query: web::Query<ReplayQuery>,
+    app_config: web::Data<Arc<Config>>,
+    session_manager: web::Data<Arc<SessionManager>>,
+) -> HttpResponse {
+    let Some(session) = session_manager.get_session(&session_id) else {
+        return HttpResponse::NotFound()
+            .json(serde_json::json!({ "error": format!("Session '{}' not found", session_id) }));
+    };
+    let events = match replay_events(&session, query.message_index, query.speed > 0) {
+        Ok(events) => events,
+        Err(e @ ReplayError::NoSuchMessage(_)) => {
+            return HttpResponse::NotFound().json(serde_json::json!({ "error": e.to_string() }));
+        }
+        Err(e) => {
+            return HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }));
+        }
+    };
+
+    let delay = Duration::from_millis(query.speed.min(MAX_REPLAY_DELAY_MS));
+    let max_event_bytes = app_config.server.sse_max_event_bytes;
+    let stream = async_stream::stream! {
+        for (index, event) in events.into_iter().enumerate() {
+            if !delay.is_zero()
+                && matches!(
+                    event,
+                    StreamEvent::Content { .. }
+                        | StreamEvent::Reasoning { .. }
+                        | StreamEvent::ToolInvocationCompleted { .. }
+                )
+            {
+                tokio::time::sleep(delay).await;
+            }
+            let frame = stream_events::chunked_frames(&event.to_json(), index as u64 + 1, max_event_bytes);
+            yield Ok::<_, actix_web::Error>(frame);
+        }
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(Box::pin(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::session::{Source as SessionSource, SourceOrigin, ThinkingStep, TokenUsageUpdate};
+    use serde_json::{Value, json};
+
+    fn step(step_order: i32, step_type: &str) -> ThinkingStep {
+        ThinkingStep {
+            id: None,
+            step_type: step_type.to_string(),
+            step_order,
+            content: None,
+            tool_name: None,
+            tool_arguments: None,
+            tool_result: None,
+            tool_error: None,
+            content_delta_before_tool: None,
+            status: None,
+        }
+    }
+
+    fn reasoning(step_order: i32, text: &str) -> ThinkingStep {
+        ThinkingStep {
+            content: Some(text.to_string()),
+            ..step(step_order, "reasoning")
+        }
+    }
+
+    fn tool(step_order: i32, name: &str, delta: Option<&str>) -> ThinkingStep {
+        ThinkingStep {
+            tool_name: Some(name.to_string()),
+            tool_arguments: Some(json!({"path": format!("{}.txt", name)})),
+            tool_result: Some(format!("{} result", name)),
+            content_delta_before_tool: delta.map(String::from),
+            status: None,
+            ..step(step_order, "tool")
+        }
+    }
+
+    /// A saved session with two turns; the second interleaves reasoning, content and tools
+    fn fixture(manager: &SessionManager) -> String {
+        let id = manager.create_session();
+        manager
+            .add_user_message(&id, "Hi".to_string(), vec![])
+            .unwrap();
+        manager
+            .add_assistant_message(&id, "Hello!".to_string(), vec![], None, None)
+            .unwrap();
+        manager
+            .add_user_message(&id, "Compare the files".to_string(), vec![])
+            .unwrap();
+        manager
+            .add_assistant_message(
+                &id,
+                "Let me read a. Now b. They differ in one line: only b ends with a newline."
+                    .to_string(),
+                vec![SessionSource::new("notes.md", "notes", SourceOrigin::Rag)],
+                Some(vec![
+                    reasoning(0, "Read both files"),
+                    tool(
+                        1,
+                        "read_a",
+                        Some("<think>Read both files</think>Let me read a."),
+                    ),
+                    reasoning(2, "a is short"),
+                    tool(3, "read_b", Some(" Now b.")),
+                    tool(4, "read_c", None),
+                ]),
+                None,
+            )
+            .unwrap();
+        manager
+            .update_token_usage(
+                &id,
+                "default",
+                TokenUsageUpdate {
+                    input_tokens: 120,
+                    output_tokens: 30,
+                    reasoning_tokens: 5,
+                    cache_tokens: 0,
+                    context_window: 8192,
+                },
+            )
+            .unwrap();
+        id
+    }
+
+    fn json_events(events: &[StreamEvent]) -> Vec<Value> {
+        events
+            .iter()
+            .map(|event| serde_json::from_str(&event.to_json()).unwrap())
+            .collect()
+    }
+
+    /// `type` of each event, with the text or tool name that identifies it
+    fn outline(events: &[Value]) -> Vec<String> {
+        events
+            .iter()
+            .map(|event| {
+                let kind = event["type"].as_str().unwrap();
+                match kind {
+                    "content" | "reasoning" => {
+                        format!("{}:{}", kind, event["text"].as_str().unwrap())
+                    }
+                    "tool_invocation_completed" => {
+                        format!("tool:{}", event["name"].as_str().unwrap())
+                    }
+                    "turn" => format!("turn:{}", event["message_index"]),
+                    _ => kind.to_string(),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_replay_follows_stored_step_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let db_path = dir.path().join("squid.db");
+        let id = fixture(&SessionManager::new(
+            Database::new(db_path.to_str().unwrap()).unwrap(),
+        ));
+        // Load with a fresh manager so steps come back from the database in stored order
+        let manager = SessionManager::new(Database::new(db_path.to_str().unwrap()).unwrap());
+        let session = manager.get_session(&id).unwrap();
+
+        let events = json_events(&replay_events(&session, None, false).unwrap());
+        assert_eq!(
+            outline(&events),
+            vec![
+                "session",
+                "turn:1",
+                "content:Hello!",
+                "turn:3",
+                "sources",
+                "reasoning:Read both files",
+                "content:Let me read a.",
+                "tool:read_a",
+                "reasoning:a is short",
+                "content: Now b.",
+                "tool:read_b",
+                "tool:read_c",
+                "content: They differ in one line: only b ends with a newline.",
+                "usage",
+                "done",
+            ]
+        );
+        assert_eq!(events[0]["session_id"], id.as_str());
+        assert_eq!(events[3]["prompt"], "Compare the files");
+        assert_eq!(events[7]["arguments"], json!({"path": "read_a.txt"}));
+        assert_eq!(events[7]["result"], "read_a result");
+        assert_eq!(events[13]["input_tokens"], 120);
+        assert_eq!(events[13]["estimated"], false);
+    }
+
+    #[test]
+    fn test_replay_single_turn_and_chunked_content() {
+        let manager = SessionManager::new(Database::new(":memory:").unwrap());
+        let id = fixture(&manager);
+        let session = manager.get_session(&id).unwrap();
+
+        let events = json_events(&replay_events(&session, Some(3), true).unwrap());
+        let outline = outline(&events);
+        assert_eq!(&outline[..3], ["session", "turn:3", "sources"]);
+        assert!(!outline.contains(&"content:Hello!".to_string()));
+
+        let content: String = events
+            .iter()
+            .filter(|event| event["type"] == "content")
+            .map(|event| event["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(content, session.messages[3].content);
+        assert!(
+            events
+                .iter()
+                .filter(|event| event["type"] == "content")
+                .count()
+                > 3
+        );
+
+        assert_eq!(
+            replay_events(&session, Some(2), false).unwrap_err(),
+            ReplayError::NotAnAnswer(2)
+        );
+        assert_eq!(
+            replay_events(&session, Some(9), false).unwrap_err(),
+            ReplayError::NoSuchMessage(9)
+        );
+    }
+}
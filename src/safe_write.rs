@@ -0,0 +1,157 @@
+//! Crash-safe file writes for the file tools
+//!
+//! The content goes to a temporary file next to the target, is synced to disk and then renamed
+//! over the target, so a crash leaves either the old or the new file and never half of one. The
+//! path is validated again right before writing, with symlinks resolved, so the file actually
+//! written is the one checked: a link leading out of the workspace, or one swapped in after the
+//! call was approved, is refused. An overwritten file keeps its permissions.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::validate::PathValidator;
+
+/// Replace the file at `path` with `content`, returning the resolved path that was written
+pub fn write(validator: &PathValidator, path: &Path, content: &[u8]) -> Result<PathBuf> {
+    let target = validator.validate(path)?;
+    let (Some(dir), Some(name)) = (target.parent(), target.file_name()) else {
+        return Err(std::io::Error::other(format!("Not a file path: {}", target.display())).into());
+    };
+    let permissions = fs::metadata(&target)
+        .ok()
+        .map(|metadata| metadata.permissions());
+
+    let (temp_path, mut temp) = create_temp(dir, &name.to_string_lossy())?;
+    let written = temp
+        .write_all(content)
+        .and_then(|_| match permissions {
+            Some(permissions) => temp.set_permissions(permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| temp.sync_all())
+        .and_then(|_| fs::rename(&temp_path, &target));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+
+    // Make the rename itself durable; not every platform can sync a directory
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+    Ok(target)
+}
+
+/// A new, uniquely named file in `dir` for the content of `name`
+fn create_temp(dir: &Path, name: &str) -> std::io::Result<(PathBuf, File)> {
+    let mut attempt = 0;
+    loop {
+        let path = dir.join(format!(
+            ".{}.{}-{}.squid-tmp",
+            name,
+            std::process::id(),
+            attempt
+        ));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists && attempt < 100 => {
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace() -> (tempfile::TempDir, PathBuf, PathValidator) {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap().join("project");
+        fs::create_dir(&root).unwrap();
+        let validator = PathValidator::for_working_dir(&root);
+        (dir, root, validator)
+    }
+
+    fn entries(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_write_replaces_the_file_without_leftovers() {
+        let (_dir, root, validator) = workspace();
+        fs::write(root.join("notes.md"), "old").unwrap();
+
+        let written = write(&validator, &root.join("notes.md"), b"new").unwrap();
+        assert_eq!(written, root.join("notes.md"));
+        assert_eq!(fs::read_to_string(root.join("notes.md")).unwrap(), "new");
+        write(&validator, &root.join("new.md"), b"created").unwrap();
+        assert_eq!(entries(&root), ["new.md", "notes.md"]);
+
+        // A missing directory is an error, not created on the way
+        assert!(write(&validator, &root.join("missing/file.md"), b"x").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_out_of_the_workspace_are_refused() {
+        use std::os::unix::fs::symlink;
+
+        let (dir, root, validator) = workspace();
+        let outside = dir.path().canonicalize().unwrap().join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), "keep").unwrap();
+        symlink(&outside, root.join("linked_dir")).unwrap();
+        symlink(outside.join("secret.txt"), root.join("link.txt")).unwrap();
+        symlink(outside.join("planted.txt"), root.join("dangling.txt")).unwrap();
+
+        for path in ["linked_dir/new.txt", "link.txt", "dangling.txt"] {
+            assert!(
+                write(&validator, &root.join(path), b"escaped").is_err(),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(entries(&outside), ["secret.txt"]);
+        assert_eq!(
+            fs::read_to_string(outside.join("secret.txt")).unwrap(),
+            "keep"
+        );
+
+        // A link inside the workspace writes its target and stays a link
+        fs::write(root.join("target.txt"), "old").unwrap();
+        symlink(root.join("target.txt"), root.join("alias.txt")).unwrap();
+        let written = write(&validator, &root.join("alias.txt"), b"new").unwrap();
+        assert_eq!(written, root.join("target.txt"));
+        assert_eq!(fs::read_to_string(root.join("target.txt")).unwrap(), "new");
+        assert!(
+            fs::symlink_metadata(root.join("alias.txt"))
+                .unwrap()
+                .file_type()
+                .is_symlink()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_overwrite_keeps_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_dir, root, validator) = workspace();
+        let script = root.join("run.sh");
+        fs::write(&script, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write(&validator, &script, b"#!/bin/sh\necho hi\n").unwrap();
+        let mode = fs::metadata(&script).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+    }
+}
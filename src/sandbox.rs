@@ -0,0 +1,578 @@
+//! Restricted execution of bash tool commands
+//!
+//! With `tools.bash.sandbox = "restricted"` commands get a minimal environment (PATH, LANG,
+//! TERM and a temporary HOME), a hard wall-clock limit, and one of these backends, picked by
+//! what works on the machine:
+//!
+//! - `bwrap`: the whole filesystem is mounted read-only, with a private `/tmp` and PID namespace
+//! - `unshare`: the working directory is bind-mounted read-only in a private mount namespace
+//! - fallback: commands that look like they write files are refused, the rest run with a
+//!   non-writable HOME and, when `prlimit` is available, can't write files at all
+//!
+//! Network access is not restricted by any backend.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::OnceLock;
+
+use log::info;
+use serde::Serialize;
+
+use crate::config::SandboxMode;
+
+/// Host environment variables sandboxed commands get, when they're set
+const RESTRICTED_ENV: &[&str] = &["PATH", "LANG", "TERM"];
+
+/// Open file limit applied by `prlimit` in the fallback backend
+const FALLBACK_MAX_OPEN_FILES: u32 = 256;
+
+/// Programs that create, change or delete files
+const WRITING_COMMANDS: &[&str] = &[
+    "rm", "rmdir", "mv", "cp", "mkdir", "touch", "chmod", "chown", "chgrp", "ln", "dd", "truncate",
+    "tee", "install", "shred", "unlink", "rsync", "patch", "sudo",
+];
+
+/// Subcommands of common tools that change the working tree or install things
+const WRITING_SUBCOMMANDS: &[(&str, &[&str])] = &[
+    (
+        "git",
+        &[
+            "add",
+            "am",
+            "apply",
+            "checkout",
+            "cherry-pick",
+            "clean",
+            "clone",
+            "commit",
+            "fetch",
+            "init",
+            "merge",
+            "mv",
+            "pull",
+            "push",
+            "rebase",
+            "reset",
+            "restore",
+            "revert",
+            "rm",
+            "stash",
+            "switch",
+            "tag",
+        ],
+    ),
+    (
+        "cargo",
+        &[
+            "add", "build", "clean", "fix", "fmt", "init", "install", "new", "publish", "remove",
+            "run", "test", "update",
+        ],
+    ),
+    ("npm", &["ci", "i", "install", "uninstall", "update", "run"]),
+    ("pnpm", &["add", "i", "install", "remove", "update", "run"]),
+    ("yarn", &["add", "install", "remove", "upgrade", "run"]),
+    ("pip", &["install", "uninstall"]),
+    ("pip3", &["install", "uninstall"]),
+];
+
+/// How restricted commands are isolated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SandboxBackend {
+    Bwrap,
+    Unshare,
+    Fallback,
+}
+
+impl SandboxBackend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SandboxBackend::Bwrap => "bwrap",
+            SandboxBackend::Unshare => "unshare",
+            SandboxBackend::Fallback => "fallback",
+        }
+    }
+}
+
+/// Sandboxing tools that work on this machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SandboxCapabilities {
+    pub bwrap: bool,
+    pub unshare: bool,
+    pub prlimit: bool,
+    /// Backend restricted commands use
+    pub backend: SandboxBackend,
+}
+
+impl SandboxCapabilities {
+    pub fn new(bwrap: bool, unshare: bool, prlimit: bool) -> Self {
+        let backend = if bwrap {
+            SandboxBackend::Bwrap
+        } else if unshare {
+            SandboxBackend::Unshare
+        } else {
+            SandboxBackend::Fallback
+        };
+        Self {
+            bwrap,
+            unshare,
+            prlimit,
+            backend,
+        }
+    }
+
+    /// Probe each backend by running `true` in it
+    ///
+    /// Installed tools don't always work (e.g. user namespaces disabled in containers), so a
+    /// backend only counts when the probe succeeds.
+    pub fn detect() -> Self {
+        let dir = std::env::temp_dir();
+        let probe = |backend| {
+            let (program, args) = wrapper(backend, "true", &dir, false, 1);
+            runs(&program, &args)
+        };
+        let prlimit = runs(&OsString::from("prlimit"), &[OsString::from("--version")]);
+        Self::new(
+            probe(SandboxBackend::Bwrap),
+            probe(SandboxBackend::Unshare),
+            prlimit,
+        )
+    }
+}
+
+/// Whether `program` exits successfully
+fn runs(program: &OsString, args: &[OsString]) -> bool {
+    std::process::Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+static CAPABILITIES: OnceLock<SandboxCapabilities> = OnceLock::new();
+
+/// Capabilities of this machine, detected on first use
+pub fn capabilities() -> &'static SandboxCapabilities {
+    CAPABILITIES.get_or_init(|| {
+        let capabilities = SandboxCapabilities::detect();
+        info!(
+            "Bash sandbox backend: {} (bwrap: {}, unshare: {}, prlimit: {})",
+            capabilities.backend.as_str(),
+            capabilities.bwrap,
+            capabilities.unshare,
+            capabilities.prlimit
+        );
+        capabilities
+    })
+}
+
+/// Sandbox mode and capabilities, as reported by the health endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct SandboxStatus {
+    pub mode: SandboxMode,
+    #[serde(flatten)]
+    pub capabilities: SandboxCapabilities,
+}
+
+/// Program and arguments that run `command` in `cwd` through `backend`
+fn wrapper(
+    backend: SandboxBackend,
+    command: &str,
+    cwd: &Path,
+    prlimit: bool,
+    time_limit_secs: u64,
+) -> (OsString, Vec<OsString>) {
+    let mut args: Vec<OsString> = Vec::new();
+    let program = match backend {
+        SandboxBackend::Bwrap => {
+            for arg in [
+                "--ro-bind",
+                "/",
+                "/",
+                "--dev",
+                "/dev",
+                "--proc",
+                "/proc",
+                "--tmpfs",
+                "/tmp",
+            ] {
+                args.push(arg.into());
+            }
+            // The private /tmp hides working directories below it, so bind it again
+            args.extend(["--ro-bind".into(), cwd.into(), cwd.into()]);
+            for arg in [
+                "--unshare-pid",
+                "--unshare-ipc",
+                "--unshare-uts",
+                "--die-with-parent",
+                "--new-session",
+            ] {
+                args.push(arg.into());
+            }
+            args.extend(["--chdir".into(), cwd.into(), "--".into()]);
+            "bwrap"
+        }
+        SandboxBackend::Unshare => {
+            for arg in [
+                "--user",
+                "--map-root-user",
+                "--mount",
+                "--pid",
+                "--fork",
+                "--kill-child",
+            ] {
+                args.push(arg.into());
+            }
+            "unshare"
+        }
+        SandboxBackend::Fallback if prlimit => {
+            args.push("--fsize=0".into());
+            args.push("--core=0".into());
+            args.push(format!("--nofile={}", FALLBACK_MAX_OPEN_FILES).into());
+            args.push(format!("--cpu={}", time_limit_secs).into());
+            args.push("--".into());
+            "prlimit"
+        }
+        SandboxBackend::Fallback => "sh",
+    };
+
+    if backend == SandboxBackend::Unshare {
+        // Make the working directory read-only inside the new mount namespace, then run
+        args.extend([
+            "sh".into(),
+            "-c".into(),
+            r#"mount --bind "$1" "$1" && mount -o remount,bind,ro "$1" && cd "$1" && exec sh -c "$2""#
+                .into(),
+            "sh".into(),
+            cwd.into(),
+            command.into(),
+        ]);
+    } else {
+        if program != "sh" {
+            args.push("sh".into());
+        }
+        args.extend(["-c".into(), command.into()]);
+    }
+    (program.into(), args)
+}
+
+/// A command prepared to run in the sandbox
+pub struct RestrictedCommand {
+    pub command: tokio::process::Command,
+    /// Temporary HOME, removed when dropped, so it must outlive the command
+    pub home: Option<tempfile::TempDir>,
+}
+
+/// Minimal environment for sandboxed commands
+pub fn restricted_env(home: &Path) -> Vec<(String, OsString)> {
+    RESTRICTED_ENV
+        .iter()
+        .filter_map(|name| std::env::var_os(name).map(|value| (name.to_string(), value)))
+        .chain(std::iter::once(("HOME".to_string(), home.into())))
+        .collect()
+}
+
+/// Prepare `command` to run in `cwd` with the restricted sandbox
+///
+/// Fails when the fallback backend refuses a command that looks like it writes files.
+pub fn restricted_command(
+    command: &str,
+    cwd: &Path,
+    capabilities: &SandboxCapabilities,
+    time_limit_secs: u64,
+) -> Result<RestrictedCommand, String> {
+    let backend = capabilities.backend;
+    if backend == SandboxBackend::Fallback
+        && let Some(reason) = write_reason(command)
+    {
+        return Err(format!(
+            "Refused by the bash sandbox: {}. Neither bwrap nor unshare is available to run it \
+             with a read-only working directory",
+            reason
+        ));
+    }
+
+    let (home_dir, home): (PathBuf, _) = match backend {
+        // bwrap gives the command a private, empty /tmp
+        SandboxBackend::Bwrap => (PathBuf::from("/tmp"), None),
+        SandboxBackend::Unshare | SandboxBackend::Fallback => {
+            let dir = tempfile::Builder::new()
+                .prefix("squid-sandbox-home-")
+                .tempdir()
+                .map_err(|e| format!("Failed to create sandbox HOME: {}", e))?;
+            if backend == SandboxBackend::Fallback {
+                make_read_only(dir.path())?;
+            }
+            (dir.path().to_path_buf(), Some(dir))
+        }
+    };
+
+    let (program, args) = wrapper(
+        backend,
+        command,
+        cwd,
+        capabilities.prlimit,
+        time_limit_secs.max(1),
+    );
+    let mut cmd = tokio::process::Command::new(program);
+    cmd.args(args)
+        .env_clear()
+        .envs(restricted_env(&home_dir))
+        .current_dir(cwd);
+    Ok(RestrictedCommand { command: cmd, home })
+}
+
+#[cfg(unix)]
+fn make_read_only(dir: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o555))
+        .map_err(|e| format!("Failed to protect sandbox HOME: {}", e))
+}
+
+#[cfg(not(unix))]
+fn make_read_only(dir: &Path) -> Result<(), String> {
+    let mut permissions = std::fs::metadata(dir)
+        .map_err(|e| format!("Failed to protect sandbox HOME: {}", e))?
+        .permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(dir, permissions)
+        .map_err(|e| format!("Failed to protect sandbox HOME: {}", e))
+}
+
+/// Why `command` looks like it writes files, if it does
+///
+/// A heuristic for the fallback backend: redirections to files, programs that modify files, and
+/// subcommands such as `git commit` or `cargo build`. Quoting is only roughly respected.
+pub fn write_reason(command: &str) -> Option<String> {
+    if let Some(target) = redirect_target(command) {
+        return Some(format!("redirects output to `{}`", target));
+    }
+
+    for segment in command.split(['|', ';', '&', '\n', '(', ')', '`']) {
+        let mut words = segment
+            .split_whitespace()
+            .skip_while(|word| word.contains('=') || matches!(*word, "env" | "nohup" | "time"));
+        let Some(program) = words.next() else {
+            continue;
+        };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let rest: Vec<&str> = words.collect();
+
+        if WRITING_COMMANDS.contains(&program) {
+            return Some(format!("`{}` modifies files", program));
+        }
+        if matches!(program, "sed" | "perl")
+            && rest
+                .iter()
+                .any(|arg| arg.starts_with("-i") || *arg == "--in-place")
+        {
+            return Some(format!("`{} -i` edits files in place", program));
+        }
+        if let Some((_, subcommands)) = WRITING_SUBCOMMANDS
+            .iter()
+            .find(|(name, _)| *name == program)
+            && let Some(subcommand) = rest.iter().find(|arg| !arg.starts_with('-'))
+            && subcommands.contains(subcommand)
+        {
+            return Some(format!("`{} {}` modifies files", program, subcommand));
+        }
+    }
+    None
+}
+
+/// The first file that `command` redirects output to, ignoring `/dev/null` and `>&` duplication
+fn redirect_target(command: &str) -> Option<String> {
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '>') => {
+                if chars.peek() == Some(&'>') {
+                    chars.next();
+                }
+                if chars.peek() == Some(&'&') {
+                    continue;
+                }
+                while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                    chars.next();
+                }
+                let target: String = chars
+                    .by_ref()
+                    .take_while(|c| !c.is_whitespace() && !matches!(c, ';' | '|' | '&'))
+                    .collect();
+                if !target.is_empty() && target != "/dev/null" {
+                    return Some(target);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// How a bash command will run, for approval prompts
+pub fn describe(mode: SandboxMode, time_limit_secs: u64, command: &str) -> String {
+    describe_with(mode, capabilities(), time_limit_secs, command)
+}
+
+fn describe_with(
+    mode: SandboxMode,
+    capabilities: &SandboxCapabilities,
+    time_limit_secs: u64,
+    command: &str,
+) -> String {
+    if mode == SandboxMode::None {
+        return "Not sandboxed: runs with your environment and permissions".to_string();
+    }
+    let limit = time_limit_secs.max(1);
+    match capabilities.backend {
+        SandboxBackend::Bwrap => format!(
+            "Sandboxed (bwrap): read-only filesystem, minimal environment, {}s limit",
+            limit
+        ),
+        SandboxBackend::Unshare => format!(
+            "Sandboxed (unshare): read-only working directory, minimal environment, {}s limit",
+            limit
+        ),
+        SandboxBackend::Fallback => match write_reason(command) {
+            Some(reason) => format!(
+                "Will be refused by the sandbox: {} (no bwrap or unshare available)",
+                reason
+            ),
+            None if capabilities.prlimit => format!(
+                "Partly sandboxed (prlimit): file writes blocked, minimal environment, {}s limit",
+                limit
+            ),
+            None => format!(
+                "Partly sandboxed: minimal environment, read-only HOME, {}s limit",
+                limit
+            ),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FALLBACK: SandboxCapabilities = SandboxCapabilities {
+        bwrap: false,
+        unshare: false,
+        prlimit: false,
+        backend: SandboxBackend::Fallback,
+    };
+
+    async fn run(command: &str, cwd: &Path) -> Result<String, String> {
+        let RestrictedCommand { mut command, home } =
+            restricted_command(command, cwd, &FALLBACK, 5)?;
+        let output = command.output().await.unwrap();
+        drop(home);
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    #[tokio::test]
+    async fn test_restricted_env_is_stripped() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let output = run("env", dir.path()).await.unwrap();
+        let names: Vec<&str> = output
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name))
+            .filter(|name| *name != "PWD" && *name != "SHLVL" && *name != "_")
+            .collect();
+        assert!(
+            names
+                .iter()
+                .all(|name| ["PATH", "LANG", "TERM", "HOME"].contains(name)),
+            "unexpected variables: {:?}",
+            names
+        );
+
+        let home = output
+            .lines()
+            .find_map(|line| line.strip_prefix("HOME="))
+            .unwrap();
+        assert_ne!(Some(home.into()), std::env::var_os("HOME"));
+        assert!(home.contains("squid-sandbox-home-"));
+        // The temporary HOME is gone once the command is dropped
+        assert!(!Path::new(home).exists());
+    }
+
+    #[tokio::test]
+    async fn test_fallback_refuses_writes_and_runs_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "hello").unwrap();
+
+        let err = run("echo hi > out.txt", dir.path()).await.unwrap_err();
+        assert!(err.contains("redirects output to `out.txt`"), "{}", err);
+        assert!(!dir.path().join("out.txt").exists());
+
+        let err = run("ls && rm notes.txt", dir.path()).await.unwrap_err();
+        assert!(err.contains("`rm` modifies files"), "{}", err);
+        assert!(dir.path().join("notes.txt").exists());
+
+        let output = run("cat notes.txt 2>/dev/null && pwd", dir.path())
+            .await
+            .unwrap();
+        assert!(output.starts_with("hello"));
+        assert!(output.contains(&dir.path().display().to_string()));
+    }
+
+    #[test]
+    fn test_write_reason_heuristic() {
+        for (command, reason) in [
+            ("echo x >> log.txt", "redirects output to `log.txt`"),
+            ("cargo build 2>&1 | tail", "`cargo build` modifies files"),
+            ("FOO=1 /bin/mkdir -p out", "`mkdir` modifies files"),
+            ("git commit -m wip", "`git commit` modifies files"),
+            ("sed -i 's/a/b/' file", "`sed -i` edits files in place"),
+            ("ls | tee listing", "`tee` modifies files"),
+        ] {
+            assert_eq!(
+                write_reason(command).as_deref(),
+                Some(reason),
+                "{}",
+                command
+            );
+        }
+
+        for command in [
+            "ls -la",
+            "git status",
+            "git log --oneline > /dev/null",
+            "grep -r 'a > b' src",
+            "cargo --version 2>&1",
+            "cat Cargo.toml | head",
+        ] {
+            assert_eq!(write_reason(command), None, "{}", command);
+        }
+    }
+
+    #[test]
+    fn test_describe_states_whether_sandboxed() {
+        assert!(describe_with(SandboxMode::None, &FALLBACK, 60, "ls").starts_with("Not sandboxed"));
+        assert_eq!(
+            describe_with(
+                SandboxMode::Restricted,
+                &SandboxCapabilities::new(true, true, true),
+                30,
+                "rm -rf build"
+            ),
+            "Sandboxed (bwrap): read-only filesystem, minimal environment, 30s limit"
+        );
+        assert!(
+            describe_with(SandboxMode::Restricted, &FALLBACK, 30, "rm -rf build")
+                .starts_with("Will be refused by the sandbox: `rm` modifies files")
+        );
+        assert_eq!(
+            describe_with(SandboxMode::Restricted, &FALLBACK, 30, "ls"),
+            "Partly sandboxed: minimal environment, read-only HOME, 30s limit"
+        );
+    }
+}
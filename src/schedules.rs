@@ -0,0 +1,712 @@
+//! Prompts run on a schedule by `squid serve`
+//!
+//! Each entry in `schedules` becomes a cron job. A run sends its prompt through the same chat
+//! pipeline as the Web UI and saves the answer as a normal session tagged with the schedule's
+//! name. Nobody is around to approve tools, so calls that would need approval are refused; list
+//! the tools a schedule needs in its `tools` override instead.
+
+use actix_web::{HttpResponse, web};
+use futures::StreamExt;
+use log::{error, info, warn};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::api;
+use crate::chat_turn::{LocalTools, TurnTranscript};
+use crate::config::{Config, ScheduleConfig, ScheduleSessionMode};
+use crate::session::{self, SessionManager};
+
+/// Outcome of the latest run of a schedule
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleRun {
+    pub started_at: i64,
+    pub finished_at: i64,
+    /// `completed` or `failed`
+    pub status: String,
+    pub session_id: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ScheduleState {
+    running: bool,
+    last_run: Option<ScheduleRun>,
+}
+
+/// Marks a schedule as no longer running when dropped, so a run that is cancelled or panics
+/// doesn't block the schedule until restart
+struct RunningGuard<'a> {
+    states: &'a Mutex<HashMap<String, ScheduleState>>,
+    name: &'a str,
+}
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        let mut states = self
+            .states
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(state) = states.get_mut(self.name) {
+            state.running = false;
+        }
+    }
+}
+
+/// Why a schedule didn't run
+#[derive(Debug, PartialEq)]
+pub enum RunError {
+    Unknown(String),
+    /// The previous run of the schedule hasn't finished
+    AlreadyRunning(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Unknown(name) => write!(f, "Unknown schedule '{}'", name),
+            RunError::AlreadyRunning(name) => {
+                write!(f, "Schedule '{}' is already running", name)
+            }
+            RunError::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Runs configured schedules and remembers how each last run went
+pub struct ScheduleRunner {
+    config: Arc<Config>,
+    session_manager: Arc<SessionManager>,
+    states: Mutex<HashMap<String, ScheduleState>>,
+    /// Cron job of each schedule, once started
+    jobs: Mutex<HashMap<String, uuid::Uuid>>,
+    scheduler: tokio::sync::Mutex<Option<tokio_cron_scheduler::JobScheduler>>,
+}
+
+impl ScheduleRunner {
+    pub fn new(config: Arc<Config>, session_manager: Arc<SessionManager>) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            session_manager,
+            states: Mutex::new(HashMap::new()),
+            jobs: Mutex::new(HashMap::new()),
+            scheduler: tokio::sync::Mutex::new(None),
+        })
+    }
+
+    /// Add a cron job for every schedule and start them
+    ///
+    /// Schedules with an invalid cron expression are skipped with an error in the log.
+    pub async fn start(self: &Arc<Self>) -> Result<(), Box<dyn std::error::Error>> {
+        let scheduler = tokio_cron_scheduler::JobScheduler::new().await?;
+        for schedule in &self.config.schedules {
+            let runner = Arc::clone(self);
+            let name = schedule.name.clone();
+            let job = tokio_cron_scheduler::Job::new_async_tz(
+                schedule.cron.as_str(),
+                chrono::Local,
+                move |_uuid, _scheduler| {
+                    let runner = Arc::clone(&runner);
+                    let name = name.clone();
+                    Box::pin(async move {
+                        match runner.run(&name).await {
+                            Ok(session_id) => {
+                                info!("Schedule '{}' saved its run to {}", name, session_id)
+                            }
+                            Err(RunError::AlreadyRunning(_)) => {
+                                warn!(
+                                    "Skipping schedule '{}': the previous run is still going",
+                                    name
+                                )
+                            }
+                            Err(e) => error!("Schedule '{}' failed: {}", name, e),
+                        }
+                    })
+                },
+            );
+            match job {
+                Ok(job) => {
+                    let id = scheduler.add(job).await?;
+                    self.jobs.lock().unwrap().insert(schedule.name.clone(), id);
+                    info!("Scheduled '{}' ({})", schedule.name, schedule.cron);
+                }
+                Err(e) => error!(
+                    "Failed to parse cron expression '{}' for schedule '{}': {}. Expected format: 'sec min hour day month dayofweek'",
+                    schedule.cron, schedule.name, e
+                ),
+            }
+        }
+        scheduler.start().await?;
+        *self.scheduler.lock().await = Some(scheduler);
+        Ok(())
+    }
+
+    /// Run schedule `name` now and return the session its answer was saved to
+    ///
+    /// A schedule runs at most once at a time; a run while one is going returns
+    /// [`RunError::AlreadyRunning`].
+    pub async fn run(&self, name: &str) -> Result<String, RunError> {
+        let schedule = self
+            .config
+            .get_schedule(name)
+            .ok_or_else(|| RunError::Unknown(name.to_string()))?;
+        {
+            let mut states = self.states.lock().unwrap();
+            let state = states.entry(name.to_string()).or_default();
+            if state.running {
+                return Err(RunError::AlreadyRunning(name.to_string()));
+            }
+            state.running = true;
+        }
+        let _running = RunningGuard {
+            states: &self.states,
+            name,
+        };
+
+        let started_at = chrono::Utc::now().timestamp();
+        let mut session_id = None;
+        let result = self.execute(schedule, &mut session_id).await;
+
+        let run = ScheduleRun {
+            started_at,
+            finished_at: chrono::Utc::now().timestamp(),
+            status: if result.is_ok() {
+                "completed"
+            } else {
+                "failed"
+            }
+            .to_string(),
+            session_id: session_id.clone(),
+            error: result.as_ref().err().cloned(),
+        };
+        self.states
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_default()
+            .last_run = Some(run);
+
+        result.map_err(RunError::Failed)
+    }
+
+    /// Send the schedule's prompt and save the answer, recording the session in `session_id`
+    async fn execute(
+        &self,
+        schedule: &ScheduleConfig,
+        session_id: &mut Option<String>,
+    ) -> Result<String, String> {
+        let prompt = prompt(&self.config, schedule)?;
+        let agent_id = schedule
+            .agent
+            .clone()
+            .unwrap_or_else(|| self.config.agents.default_agent.clone());
+        let config = config_for(&self.config, schedule, &agent_id)?;
+        let agent = config
+            .get_agent(&agent_id)
+            .ok_or_else(|| format!("Agent '{}' not found", agent_id))?;
+        let model_id = agent.model.clone();
+        let context_window = config.agent_context_window(agent);
+        let use_tools = agent.use_tools;
+
+        let id = match schedule.session {
+            ScheduleSessionMode::Append => {
+                self.session_manager.latest_schedule_session(&schedule.name)
+            }
+            ScheduleSessionMode::New => None,
+        }
+        .unwrap_or_else(|| {
+            let title = format!(
+                "{} ({})",
+                schedule.name,
+                chrono::Local::now().format("%Y-%m-%d %H:%M")
+            );
+            self.session_manager
+                .create_schedule_session(&schedule.name, &agent_id, title)
+        });
+        *session_id = Some(id.clone());
+        let _turn_lock = self
+            .session_manager
+            .begin_turn(&id, &uuid::Uuid::new_v4().to_string())
+            .map_err(|e| e.to_string())?;
+
+        self.session_manager
+            .add_user_message(&id, prompt.clone(), Vec::new())
+            .map_err(|e| e.to_string())?;
+
+        // Only the reasoning fields this model understands are sent, as in the web chat
+        let reasoning =
+            crate::providers::supported_reasoning(&model_id, config.generation.reasoning);
+        let executor = LocalTools {
+            config: &config,
+            rag: None,
+        };
+        let turn = api::TurnRequest {
+            session_id: &id,
+            question: &prompt,
+            files: &[],
+            system_prompt: None,
+            agent_id: &agent_id,
+            rag_sources: None,
+            use_tools,
+            force_reattach: false,
+            capture_requests: config.debug.capture_requests,
+            reasoning,
+            tool_choice: Default::default(),
+            context_window,
+        };
+        let services = api::TurnServices {
+            app_config: &config,
+            session_manager: &self.session_manager,
+            approval_map: None,
+            rag_system: None,
+            executor: &executor,
+        };
+        let stream = api::create_chat_stream(turn, services)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // Saved the way the web chat saves its answers
+        let mut transcript = TurnTranscript::new(Vec::new(), !reasoning.is_disabled());
+        let mut failure = None;
+        let mut stream = std::pin::pin!(stream);
+        while let Some(event) = stream.next().await {
+            match event {
+                Ok(event) => {
+                    transcript.observe(event);
+                }
+                Err(e) => {
+                    failure = Some(e.to_string());
+                    break;
+                }
+            }
+        }
+
+        let owner = api::TurnOwner {
+            session_id: &id,
+            agent_id: &agent_id,
+            model_id: &model_id,
+            system_prompt: None,
+            context_window,
+        };
+        let metadata = session::MessageMetadata::for_answer(&model_id, &config.api_url, reasoning)
+            .map(|metadata| session::MessageMetadata {
+                error: failure.clone(),
+                ..metadata
+            });
+        api::save_turn(&self.session_manager, &owner, &mut transcript, metadata).await;
+        api::broadcast_session_update_for_session(&self.session_manager, &id);
+
+        match failure {
+            Some(error) => Err(error),
+            None => Ok(id.clone()),
+        }
+    }
+
+    /// Whether schedule `name` is running, and how its last run went
+    fn state(&self, name: &str) -> (bool, Option<ScheduleRun>) {
+        let states = self.states.lock().unwrap();
+        states
+            .get(name)
+            .map(|state| (state.running, state.last_run.clone()))
+            .unwrap_or_default()
+    }
+
+    /// When the cron job of schedule `name` fires next
+    async fn next_run(&self, name: &str) -> Option<i64> {
+        let id = *self.jobs.lock().unwrap().get(name)?;
+        let mut scheduler = self.scheduler.lock().await.clone()?;
+        let next = scheduler.next_tick_for_job(id).await.ok()??;
+        Some(next.timestamp())
+    }
+}
+
+/// The schedule's inline prompt, or the contents of its prompt file
+fn prompt(config: &Config, schedule: &ScheduleConfig) -> Result<String, String> {
+    let prompt = match (&schedule.prompt, &schedule.prompt_file) {
+        (Some(prompt), _) => prompt.clone(),
+        (None, Some(file)) => {
+            let path = config.resolve_path(file);
+            std::fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read prompt file {}: {}", path.display(), e))?
+        }
+        (None, None) => {
+            return Err(format!(
+                "Schedule '{}' has neither `prompt` nor `prompt_file`",
+                schedule.name
+            ));
+        }
+    };
+    if prompt.trim().is_empty() {
+        return Err(format!("Schedule '{}' has an empty prompt", schedule.name));
+    }
+    Ok(prompt)
+}
+
+/// The config a run uses: the schedule's `tools` replace the agent's allow list
+fn config_for(
+    config: &Arc<Config>,
+    schedule: &ScheduleConfig,
+    agent_id: &str,
+) -> Result<Arc<Config>, String> {
+    let Some(tools) = &schedule.tools else {
+        return Ok(Arc::clone(config));
+    };
+    let mut config = Config::clone(config);
+    let agent = config
+        .agents
+        .agents
+        .get_mut(agent_id)
+        .ok_or_else(|| format!("Agent '{}' not found", agent_id))?;
+    agent.permissions.allow = tools.clone();
+    Ok(Arc::new(config))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduleInfo {
+    pub name: String,
+    pub cron: String,
+    pub agent: String,
+    pub session: ScheduleSessionMode,
+    pub running: bool,
+    /// Unix timestamp of the next scheduled run
+    pub next_run: Option<i64>,
+    pub last_run: Option<ScheduleRun>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SchedulesResponse {
+    pub schedules: Vec<ScheduleInfo>,
+}
+
+/// List configured schedules with the outcome of their last run
+pub async fn list_schedules(runner: web::Data<Arc<ScheduleRunner>>) -> HttpResponse {
+    let mut schedules = Vec::new();
+    for schedule in &runner.config.schedules {
+        let (running, last_run) = runner.state(&schedule.name);
+        schedules.push(ScheduleInfo {
+            name: schedule.name.clone(),
+            cron: schedule.cron.clone(),
+            agent: schedule
+                .agent
+                .clone()
+                .unwrap_or_else(|| runner.config.agents.default_agent.clone()),
+            session: schedule.session,
+            running,
+            next_run: runner.next_run(&schedule.name).await,
+            last_run,
+        });
+    }
+    HttpResponse::Ok().json(SchedulesResponse { schedules })
+}
+
+/// Run a schedule now, responding once the answer is saved
+pub async fn run_schedule(
+    name: web::Path<String>,
+    runner: web::Data<Arc<ScheduleRunner>>,
+) -> HttpResponse {
+    match runner.run(&name).await {
+        Ok(session_id) => HttpResponse::Ok().json(serde_json::json!({ "session_id": session_id })),
+        Err(e @ RunError::Unknown(_)) => {
+            HttpResponse::NotFound().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        Err(e @ RunError::AlreadyRunning(_)) => {
+            HttpResponse::Conflict().json(serde_json::json!({ "error": e.to_string() }))
+        }
+        Err(e) => {
+            let session_id = runner.state(&name).1.and_then(|run| run.session_id);
+            HttpResponse::InternalServerError()
+                .json(serde_json::json!({ "error": e.to_string(), "session_id": session_id }))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers;
+    use serde_json::json;
+
+    fn answer(text: &str) -> String {
+        format!(
+            "data: {}\n\ndata: [DONE]\n\n",
+            json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 1,
+                "model": "test-model",
+                "choices": [{"index": 0, "delta": {"role": "assistant", "content": text}, "finish_reason": "stop"}]
+            })
+        )
+    }
+
+    fn runner_for(api_url: String, schedule: ScheduleConfig) -> Arc<ScheduleRunner> {
+        runner_with(test_config(api_url, schedule))
+    }
+
+    fn runner_with(config: Config) -> Arc<ScheduleRunner> {
+        let session_manager = Arc::new(SessionManager::new(
+            crate::db::Database::new(":memory:").unwrap(),
+        ));
+        ScheduleRunner::new(Arc::new(config), session_manager)
+    }
+
+    fn test_config(api_url: String, schedule: ScheduleConfig) -> Config {
+        let mut config = Config {
+            api_url,
+            schedules: vec![schedule],
+            ..Default::default()
+        };
+        config.agents.default_agent = "assistant".to_string();
+        config.agents.agents.insert(
+            "assistant".to_string(),
+            crate::agent::AgentConfig {
+                name: "Assistant".to_string(),
+                enabled: true,
+                description: String::new(),
+                model: "test-model".to_string(),
+                prompt: None,
+                pricing_model: None,
+                context_window: None,
+                permissions: Default::default(),
+                use_tools: false,
+                suggestions: vec![],
+            },
+        );
+        config
+    }
+
+    fn schedule(name: &str, cron: &str, session: ScheduleSessionMode) -> ScheduleConfig {
+        ScheduleConfig {
+            name: name.to_string(),
+            cron: cron.to_string(),
+            prompt: Some("Summarize yesterday's commits".to_string()),
+            prompt_file: None,
+            agent: None,
+            tools: Some(vec!["read_file".to_string()]),
+            session,
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_manual_runs_append_to_the_schedule_session() {
+        use actix_web::{App, test as actix_test};
+
+        let (url, _requests) = providers::test_support::serve_sequence(
+            "text/event-stream",
+            vec![answer("Three commits"), answer("Two commits")],
+        )
+        .await;
+        let runner = runner_for(
+            url,
+            schedule("digest", "0 0 8 * * *", ScheduleSessionMode::Append),
+        );
+        let session_manager = runner.session_manager.clone();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(runner))
+                .route("/api/schedules", web::get().to(list_schedules))
+                .route("/api/schedules/{name}/run", web::post().to(run_schedule)),
+        )
+        .await;
+
+        let mut session_ids = Vec::new();
+        for _ in 0..2 {
+            let req = actix_test::TestRequest::post()
+                .uri("/api/schedules/digest/run")
+                .to_request();
+            let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+            session_ids.push(body["session_id"].as_str().unwrap().to_string());
+        }
+        assert_eq!(session_ids[0], session_ids[1]);
+
+        let session = session_manager.get_session(&session_ids[0]).unwrap();
+        assert_eq!(session.schedule(), Some("digest"));
+        assert_eq!(session.agent_id.as_deref(), Some("assistant"));
+        let contents: Vec<_> = session
+            .messages
+            .iter()
+            .map(|message| message.content.as_str())
+            .collect();
+        assert_eq!(
+            contents,
+            vec![
+                "Summarize yesterday's commits",
+                "Three commits",
+                "Summarize yesterday's commits",
+                "Two commits"
+            ]
+        );
+
+        let req = actix_test::TestRequest::get()
+            .uri("/api/schedules")
+            .to_request();
+        let body: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        let listed = &body["schedules"][0];
+        assert_eq!(listed["name"], "digest");
+        assert_eq!(listed["running"], false);
+        assert_eq!(listed["last_run"]["status"], "completed");
+        assert_eq!(listed["last_run"]["session_id"], session_ids[0].as_str());
+
+        let req = actix_test::TestRequest::post()
+            .uri("/api/schedules/missing/run")
+            .to_request();
+        let resp = actix_test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_run_saves_the_answer_like_the_web_chat() {
+        let chunk = |choices: serde_json::Value, usage: serde_json::Value| {
+            format!(
+                "data: {}\n\n",
+                json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion.chunk",
+                    "created": 1,
+                    "model": "test-model",
+                    "choices": choices,
+                    "usage": usage
+                })
+            )
+        };
+        let mut body = chunk(
+            json!([{"index": 0, "delta": {"content": "<think>plan</think>"}}]),
+            json!(null),
+        );
+        // Enough content events for live usage estimates to be sent
+        for _ in 0..crate::chat_turn::LIVE_USAGE_EVERY_EVENTS + 5 {
+            body.push_str(&chunk(
+                json!([{"index": 0, "delta": {"content": "word "}}]),
+                json!(null),
+            ));
+        }
+        body.push_str(&chunk(
+            json!([{"index": 0, "delta": {}, "finish_reason": "stop"}]),
+            json!(null),
+        ));
+        body.push_str(&chunk(
+            json!([]),
+            json!({"prompt_tokens": 11, "completion_tokens": 7, "total_tokens": 18}),
+        ));
+        body.push_str("data: [DONE]\n\n");
+        let (url, _requests) =
+            providers::test_support::serve_sequence("text/event-stream", vec![body]).await;
+
+        let mut config = test_config(
+            url,
+            schedule("digest", "0 0 8 * * *", ScheduleSessionMode::New),
+        );
+        config.server.live_usage_estimates = true;
+        let runner = runner_with(config);
+        let session_id = runner.run("digest").await.unwrap();
+
+        let session = runner.session_manager.get_session(&session_id).unwrap();
+        // Only the reported usage counts, not the running estimates
+        assert_eq!(session.token_usage.input_tokens, 11);
+        assert_eq!(session.token_usage.output_tokens, 7);
+
+        let answer = session.messages.last().unwrap();
+        assert!(!answer.content.contains("<think>"));
+        let steps = answer.thinking_steps.as_ref().unwrap();
+        assert_eq!(steps[0].step_type, "reasoning");
+        assert_eq!(steps[0].content.as_deref(), Some("plan"));
+    }
+
+    #[tokio::test]
+    async fn test_cron_trigger_creates_a_session() {
+        let (url, _requests) = providers::test_support::serve_sequence(
+            "text/event-stream",
+            vec![answer("Tick"), answer("Tick"), answer("Tick")],
+        )
+        .await;
+        // Every second
+        let runner = runner_for(
+            url,
+            schedule("tick", "* * * * * *", ScheduleSessionMode::New),
+        );
+        runner.start().await.unwrap();
+        assert!(runner.next_run("tick").await.is_some());
+
+        let mut session_id = None;
+        for _ in 0..50 {
+            session_id = runner.state("tick").1.and_then(|run| run.session_id);
+            if session_id.is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        }
+        let session = runner
+            .session_manager
+            .get_session(&session_id.expect("the schedule never ran"))
+            .unwrap();
+        assert_eq!(session.schedule(), Some("tick"));
+        assert!(session.title.unwrap().starts_with("tick ("));
+    }
+
+    #[tokio::test]
+    async fn test_overlapping_runs_are_refused() {
+        let url = providers::test_support::serve_stalled(
+            "text/event-stream",
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n".to_string(),
+        )
+        .await;
+        let runner = runner_for(
+            url,
+            schedule("slow", "0 0 8 * * *", ScheduleSessionMode::New),
+        );
+
+        let first = tokio::spawn({
+            let runner = Arc::clone(&runner);
+            async move { runner.run("slow").await }
+        });
+        while !runner.state("slow").0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        assert_eq!(
+            runner.run("slow").await,
+            Err(RunError::AlreadyRunning("slow".to_string()))
+        );
+        first.abort();
+    }
+
+    #[tokio::test]
+    async fn test_dropped_run_releases_the_schedule() {
+        let url = providers::test_support::serve_stalled(
+            "text/event-stream",
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"m\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n".to_string(),
+        )
+        .await;
+        let runner = runner_for(
+            url,
+            schedule("slow", "0 0 8 * * *", ScheduleSessionMode::New),
+        );
+
+        // Like a client disconnecting or a timeout while the model is still answering
+        let mut run = Box::pin(runner.run("slow"));
+        assert!(futures::poll!(&mut run).is_pending());
+        assert!(runner.state("slow").0);
+        drop(run);
+        assert!(!runner.state("slow").0);
+    }
+
+    #[test]
+    fn test_tools_override_replaces_the_allow_list() {
+        let runner = runner_for(
+            "http://127.0.0.1:1".to_string(),
+            schedule("digest", "0 0 8 * * *", ScheduleSessionMode::New),
+        );
+        let schedule = runner.config.get_schedule("digest").unwrap();
+        let config = config_for(&runner.config, schedule, "assistant").unwrap();
+        assert_eq!(
+            config.get_agent_permissions("assistant").unwrap().allow,
+            vec!["read_file".to_string()]
+        );
+        assert!(
+            config_for(&runner.config, schedule, "missing")
+                .unwrap_err()
+                .contains("not found")
+        );
+    }
+}
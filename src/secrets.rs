@@ -0,0 +1,195 @@
+//! API key storage outside of `squid.config.json`
+//!
+//! The key lives either in the project's `.env` file (read at startup by dotenvy) or, when
+//! squid is built with the `keyring` feature, in the OS keychain.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Service name used for keychain entries
+#[cfg_attr(not(feature = "keyring"), allow(dead_code))]
+const KEYCHAIN_SERVICE: &str = "squid";
+#[cfg_attr(not(feature = "keyring"), allow(dead_code))]
+const KEYCHAIN_USER: &str = "api_key";
+
+/// Where `squid config set-key` and `squid init` put the API key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStorage {
+    DotEnv,
+    Keychain,
+}
+
+/// Whether this build can store keys in the OS keychain
+pub fn keychain_available() -> bool {
+    cfg!(feature = "keyring")
+}
+
+/// Read the API key from the OS keychain (looked up once per process)
+#[cfg(feature = "keyring")]
+pub fn keychain_api_key() -> Option<String> {
+    static KEY: std::sync::OnceLock<Option<String>> = std::sync::OnceLock::new();
+    KEY.get_or_init(|| {
+        let entry = keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER).ok()?;
+        match entry.get_password() {
+            Ok(key) => Some(key),
+            Err(keyring::Error::NoEntry) => None,
+            Err(e) => {
+                log::debug!("Failed to read API key from keychain: {}", e);
+                None
+            }
+        }
+    })
+    .clone()
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn keychain_api_key() -> Option<String> {
+    None
+}
+
+/// Save the API key in the OS keychain
+#[cfg(feature = "keyring")]
+pub fn store_in_keychain(key: &str) -> Result<(), String> {
+    keyring::Entry::new(KEYCHAIN_SERVICE, KEYCHAIN_USER)
+        .and_then(|entry| entry.set_password(key))
+        .map_err(|e| format!("Failed to store API key in keychain: {}", e))
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn store_in_keychain(_key: &str) -> Result<(), String> {
+    Err("This build of squid has no keychain support (rebuild with `--features keyring`)".into())
+}
+
+/// Write `API_KEY=<key>` to `dir/.env`, replacing an existing `API_KEY` line and keeping the rest
+pub fn write_env_key(dir: &Path, key: &str) -> io::Result<PathBuf> {
+    let env_path = dir.join(".env");
+    let existing = match fs::read_to_string(&env_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let key_line = format!("API_KEY={}", key);
+    let mut replaced = false;
+    let mut lines: Vec<String> = Vec::new();
+    for line in existing.lines() {
+        let name = line.trim_start().trim_start_matches("export ");
+        if name.starts_with("API_KEY=") {
+            // Keep only the first definition so dotenvy can't pick up a stale one
+            if !replaced {
+                lines.push(key_line.clone());
+                replaced = true;
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+    }
+    if !replaced {
+        lines.push(key_line);
+    }
+
+    fs::write(&env_path, lines.join("\n") + "\n")?;
+    restrict_permissions(&env_path);
+    Ok(env_path)
+}
+
+/// Make sure `.env` is listed in `.gitignore` and `.squidignore`
+///
+/// Existing files get the entry appended; a missing `.gitignore` is only created inside a git
+/// repository. Returns the files that were changed.
+pub fn ensure_env_ignored(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for (name, create) in [
+        (".gitignore", dir.join(".git").exists()),
+        (".squidignore", false),
+    ] {
+        let path = dir.join(name);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && create => String::new(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        let listed = content
+            .lines()
+            .map(str::trim)
+            .any(|line| matches!(line, ".env" | "/.env" | ".env*"));
+        if listed {
+            continue;
+        }
+
+        let mut updated = content;
+        if !updated.is_empty() && !updated.ends_with('\n') {
+            updated.push('\n');
+        }
+        updated.push_str(".env\n");
+        fs::write(&path, updated)?;
+        changed.push(path);
+    }
+    Ok(changed)
+}
+
+#[cfg(unix)]
+fn restrict_permissions(path: &Path) {
+    use std::os::unix::fs::PermissionsExt;
+    if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(0o600)) {
+        log::warn!("Failed to restrict permissions on {:?}: {}", path, e);
+    }
+}
+
+#[cfg(not(unix))]
+fn restrict_permissions(_path: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_env_key_merges_existing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let env_path = dir.path().join(".env");
+        fs::write(
+            &env_path,
+            "API_URL=http://localhost:1234/v1\nAPI_KEY=old\n# comment\nexport API_KEY=older\n",
+        )
+        .unwrap();
+
+        write_env_key(dir.path(), "sk-new").unwrap();
+        assert_eq!(
+            fs::read_to_string(&env_path).unwrap(),
+            "API_URL=http://localhost:1234/v1\nAPI_KEY=sk-new\n# comment\n"
+        );
+
+        // A missing file is created
+        let empty = tempfile::tempdir().unwrap();
+        let created = write_env_key(empty.path(), "sk-1").unwrap();
+        assert_eq!(fs::read_to_string(created).unwrap(), "API_KEY=sk-1\n");
+    }
+
+    #[test]
+    fn test_ensure_env_ignored_adds_entry_once() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::write(dir.path().join(".squidignore"), "target/").unwrap();
+
+        let changed = ensure_env_ignored(dir.path()).unwrap();
+        assert_eq!(changed.len(), 2);
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".gitignore")).unwrap(),
+            ".env\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join(".squidignore")).unwrap(),
+            "target/\n.env\n"
+        );
+
+        assert!(ensure_env_ignored(dir.path()).unwrap().is_empty());
+
+        // Outside a git repository no .gitignore is created
+        let plain = tempfile::tempdir().unwrap();
+        assert!(ensure_env_ignored(plain.path()).unwrap().is_empty());
+        assert!(!plain.path().join(".gitignore").exists());
+    }
+}
@@ -1,11 +1,18 @@
 use actix_cors::Cors;
+use actix_web::error::JsonPayloadError;
 use actix_web::{App, HttpResponse, HttpServer, middleware, web};
 use log::{error, info, warn};
 use rust_embed::RustEmbed;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use crate::{api, audio, config, db, jobs, jobs_api, rag, session, workspace};
+use crate::output::CommandResult;
+use crate::rate_limit::{self, RateLimiter};
+use crate::shutdown::{self, Shutdown};
+use crate::{
+    api, audio, compact, config, db, jobs, jobs_api, logger, rag, replay, schedules, session,
+    session_templates, webhooks, workspace,
+};
 
 #[derive(RustEmbed)]
 #[folder = "static/"]
@@ -23,11 +30,69 @@ pub struct BundledPlugins;
 #[folder = "agents/"]
 pub struct BundledAgents;
 
-async fn serve_index() -> HttpResponse {
-    serve_static(web::Path::from("index.html".to_string())).await
+/// Where the Web UI is served from: an optional directory on disk, backed by the embedded build
+#[derive(Clone, Default)]
+pub struct StaticAssets {
+    /// Canonicalized `--static-dir`/`server.static_dir`; files missing here fall back to embedded
+    dir: Option<PathBuf>,
+}
+
+impl StaticAssets {
+    /// Serve from `dir` when set; it must exist and be a directory
+    pub fn new(dir: Option<&Path>) -> std::io::Result<Self> {
+        let dir = match dir {
+            Some(dir) => {
+                let dir = dir.canonicalize()?;
+                if !dir.is_dir() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{} is not a directory", dir.display()),
+                    ));
+                }
+                Some(dir)
+            }
+            None => None,
+        };
+        Ok(Self { dir })
+    }
+
+    /// Whether a custom frontend directory is in use
+    pub fn is_external(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        self.dir.as_deref()
+    }
+
+    /// Resolve a request path inside the static directory, rejecting anything outside it
+    fn disk_path(&self, path: &str) -> Option<PathBuf> {
+        let root = self.dir.as_ref()?;
+        // Canonicalizing resolves `..` and symlinks, so the prefix check catches traversal
+        let candidate = root
+            .join(path.trim_start_matches('/'))
+            .canonicalize()
+            .ok()?;
+        (candidate.starts_with(root) && candidate.is_file()).then_some(candidate)
+    }
+
+    /// File contents for `path`, from disk first and then from the embedded assets
+    async fn get(&self, path: &str) -> Option<Vec<u8>> {
+        if let Some(file) = self.disk_path(path) {
+            match tokio::fs::read(&file).await {
+                Ok(data) => return Some(data),
+                Err(e) => warn!("Failed to read static file {}: {}", file.display(), e),
+            }
+        }
+        Assets::get(path).map(|content| content.data.into_owned())
+    }
+}
+
+async fn serve_index(assets: web::Data<StaticAssets>) -> HttpResponse {
+    serve_static(web::Path::from("index.html".to_string()), assets).await
 }
 
-async fn serve_static(path: web::Path<String>) -> HttpResponse {
+async fn serve_static(path: web::Path<String>, assets: web::Data<StaticAssets>) -> HttpResponse {
     let path = path.into_inner();
     let path = if path.is_empty() || path == "/" {
         "index.html"
@@ -35,13 +100,13 @@ async fn serve_static(path: web::Path<String>) -> HttpResponse {
         path.as_str()
     };
 
-    match Assets::get(path) {
+    match assets.get(path).await {
         Some(content) => {
             let mime_type = mime_guess::from_path(path).first_or_octet_stream();
 
             HttpResponse::Ok()
                 .content_type(mime_type.as_ref())
-                .body(content.data.into_owned())
+                .body(content)
         }
         None => {
             // For SPA routing: if the file doesn't exist and doesn't have an extension,
@@ -49,10 +114,8 @@ async fn serve_static(path: web::Path<String>) -> HttpResponse {
             if !path.contains('.') && path != "index.html" {
                 // This is likely a frontend route like /jobs/6, /agents/foo, etc.
                 // Serve index.html to let React Router handle it
-                if let Some(index) = Assets::get("index.html") {
-                    return HttpResponse::Ok()
-                        .content_type("text/html")
-                        .body(index.data.into_owned());
+                if let Some(index) = assets.get("index.html").await {
+                    return HttpResponse::Ok().content_type("text/html").body(index);
                 }
             }
             HttpResponse::NotFound().body("404 - Not Found")
@@ -60,16 +123,106 @@ async fn serve_static(path: web::Path<String>) -> HttpResponse {
     }
 }
 
+/// Rebuild the damaged database at `path` (`squid serve --repair`) and open the result
+fn repair_database(path: &Path) -> Result<db::Database, String> {
+    println!(
+        "🦑: The database {} is damaged; repairing...",
+        path.display()
+    );
+    let report = crate::db_repair::repair(path)
+        .map_err(|e| format!("Failed to repair database {} - {}", path.display(), e))?;
+    println!("🦑: Moved the damaged file to {}", report.backup.display());
+    println!(
+        "🦑: Recovered {} session(s) and {} message(s)",
+        report.sessions, report.messages
+    );
+    for (table, error) in &report.damaged {
+        println!("    {} could not be read completely: {}", table, error);
+    }
+    db::Database::new(path).map_err(|e| {
+        format!(
+            "Failed to open the repaired database {} - {}",
+            path.display(),
+            e
+        )
+    })
+}
+
+/// JSON body limit for all endpoints; oversized bodies get 413 with a JSON error
+fn json_config(limit: usize) -> web::JsonConfig {
+    web::JsonConfig::default()
+        .limit(limit)
+        .error_handler(move |err, _req| match err {
+            JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+                let response = HttpResponse::PayloadTooLarge().json(serde_json::json!({
+                    "error": format!("Request body exceeds the {} byte limit", limit)
+                }));
+                actix_web::error::InternalError::from_response(err, response).into()
+            }
+            err => err.into(),
+        })
+}
+
 pub async fn start_server(
     port: u16,
     db: Option<PathBuf>,
     dir: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    repair: bool,
     mut app_config: config::Config,
-) {
+) -> CommandResult {
     info!("Starting Squid Web UI on port {}", port);
 
+    // Resolve the custom frontend before changing directory: CLI paths are relative to the
+    // current directory, config paths to the project root
+    let static_dir = static_dir.or_else(|| {
+        app_config
+            .server
+            .static_dir
+            .as_deref()
+            .map(|dir| app_config.resolve_path(dir))
+    });
+    let static_assets = match StaticAssets::new(static_dir.as_deref()) {
+        Ok(assets) => assets,
+        Err(e) => {
+            let dir = static_dir.unwrap_or_default();
+            error!("Invalid static directory {}: {}", dir.display(), e);
+            return Err(format!("Invalid static directory {} - {}", dir.display(), e).into());
+        }
+    };
+    if let Some(dir) = static_assets.dir() {
+        info!("Serving Web UI from {}", dir.display());
+        println!(
+            "🦑: Serving Web UI from {} (embedded build as fallback)",
+            dir.display()
+        );
+    }
+
+    // Checked up front and kept absolute, the server changes directory below
+    if let Some(scenario) = app_config.mock_llm.take() {
+        let scenario = std::path::absolute(&scenario).unwrap_or(scenario);
+        if let Err(e) = crate::providers::Scenario::load(&scenario) {
+            error!("{}", e);
+            return Err(e.into());
+        }
+        warn!("Answering chats from mock scenario {}", scenario.display());
+        println!(
+            "🦑: Answering chats from mock scenario {} instead of the model",
+            scenario.display()
+        );
+        app_config.mock_llm = Some(scenario);
+    }
+
     // CLI --dir parameter overrides config working_dir
+    // (relative to the current directory, unlike config paths which anchor to the project root)
     if let Some(work_dir) = dir {
+        let work_dir = if work_dir.is_relative() {
+            std::env::current_dir()
+                .map(|cwd| cwd.join(&work_dir))
+                .unwrap_or(work_dir)
+        } else {
+            work_dir
+        };
         let work_dir_str = work_dir.to_string_lossy().to_string();
         info!(
             "CLI --dir parameter overrides config working_dir: {}",
@@ -79,7 +232,7 @@ pub async fn start_server(
     }
 
     // Ensure working directory exists and change to it
-    let working_dir_path = PathBuf::from(&app_config.working_dir);
+    let working_dir_path = app_config.resolve_path(&app_config.working_dir);
 
     // Create working directory if it doesn't exist
     if !working_dir_path.exists() {
@@ -89,11 +242,11 @@ pub async fn start_server(
                 "Failed to create working directory {:?}: {}",
                 working_dir_path, e
             );
-            println!(
-                "🦑: Failed to create working directory {:?} - {}",
+            return Err(format!(
+                "Failed to create working directory {:?} - {}",
                 working_dir_path, e
-            );
-            return;
+            )
+            .into());
         }
         println!("🦑: Created working directory: {:?}", working_dir_path);
     }
@@ -105,11 +258,11 @@ pub async fn start_server(
                 "Failed to change to working directory {:?}: {}",
                 working_dir_path, e
             );
-            println!(
-                "🦑: Failed to change to working directory {:?} - {}",
+            return Err(format!(
+                "Failed to change to working directory {:?} - {}",
                 working_dir_path, e
-            );
-            return;
+            )
+            .into());
         }
         info!("Changed working directory to: {:?}", working_dir_path);
         println!("🦑: Working directory set to: {:?}", working_dir_path);
@@ -130,6 +283,16 @@ pub async fn start_server(
 
     let app_config = Arc::new(app_config);
 
+    // Detect the bash sandbox backends up front, for the health endpoint and the first command
+    let sandbox = crate::sandbox::capabilities();
+    if app_config.tools.bash.sandbox == crate::config::SandboxMode::Restricted
+        && sandbox.backend == crate::sandbox::SandboxBackend::Fallback
+    {
+        warn!(
+            "Bash sandbox: neither bwrap nor unshare works here; commands that look like they write files will be refused"
+        );
+    }
+
     // Initialize database
     let db_path = &app_config.database_path;
     info!("Initializing database at: {}", db_path);
@@ -140,16 +303,27 @@ pub async fn start_server(
         }
         Err(e) => {
             error!("Failed to initialize database: {}", e);
-            println!("🦑: Failed to initialize database - {}", e);
-            println!("    Database path: {}", db_path);
-            println!(
-                "    Make sure the directory is writable and the database file is not corrupted."
-            );
-            return;
+            match crate::db_repair::diagnose(&e, Path::new(db_path)) {
+                Some(crate::db_repair::OpenProblem::Corrupt) if repair => {
+                    repair_database(Path::new(db_path))?
+                }
+                Some(problem) => {
+                    return Err(crate::db_repair::describe(&problem, Path::new(db_path)).into());
+                }
+                None => {
+                    return Err(format!(
+                        "Failed to initialize database - {}\n    Database path: {}\n    Make sure the directory is writable and the database file is not corrupted.",
+                        e, db_path
+                    )
+                    .into());
+                }
+            }
         }
     };
 
     let session_manager = Arc::new(session::SessionManager::new(database));
+    // Requests that were streaming when the server stopped will never finish
+    session_manager.clear_in_flight_idempotency_keys();
 
     // Extract bundled plugins and agents (for cargo install distributions)
     crate::bundled::init_bundled_assets();
@@ -175,14 +349,32 @@ pub async fn start_server(
         info!("Initializing RAG system...");
         info!("RAG Configuration:");
         info!("  Embedding URL: {}", app_config.rag.embedding_url);
+        info!(
+            "  Embedding Provider: {}",
+            app_config.rag.embedding_provider.as_str()
+        );
         info!("  Embedding Model: {}", app_config.rag.embedding_model);
         info!("  Documents Path: {}", app_config.rag.documents_path);
         match db::Database::new(db_path) {
-            Ok(db) => match rag::RagSystem::new(Arc::new(db), &app_config.rag).await {
-                Ok(system) => {
-                    info!("RAG system initialized successfully");
-                    Some(Arc::new(system))
-                }
+            Ok(db) => match rag::RagSystem::new(
+                Arc::new(db),
+                &app_config.rag,
+                app_config.network.http_client(),
+            )
+            .await
+            {
+                Ok(system) => match system.embedder.check_connection().await {
+                    Ok(()) => {
+                        info!("RAG system initialized successfully");
+                        Some(Arc::new(system))
+                    }
+                    Err(e) => {
+                        warn!("Failed to initialize RAG system: {}", e);
+                        println!("🦑: RAG initialization failed - {}", e);
+                        println!("    RAG features will be disabled");
+                        None
+                    }
+                },
                 Err(e) => {
                     warn!("Failed to initialize RAG system: {}", e);
                     println!("🦑: RAG initialization failed - {}", e);
@@ -201,9 +393,27 @@ pub async fn start_server(
         None
     };
 
+    // Shared by chat streams and background tasks so Ctrl+C/SIGTERM can wind them down
+    let shutdown = Shutdown::new();
+
+    let notifier = if app_config.network.offline {
+        if !app_config.notifications.webhooks.is_empty() {
+            info!("Webhook notifications disabled in offline mode");
+        }
+        webhooks::Notifier::new(&Default::default())
+    } else {
+        webhooks::Notifier::new(&app_config.notifications)
+    };
+    if !app_config.network.offline && !app_config.notifications.webhooks.is_empty() {
+        info!(
+            "Webhook notifications enabled ({} webhook(s))",
+            app_config.notifications.webhooks.len()
+        );
+    }
+
     // Start document watcher if RAG is enabled
     if let Some(ref rag) = rag_system {
-        let documents_path = std::path::PathBuf::from(&app_config.rag.documents_path);
+        let documents_path = app_config.documents_dir();
         match rag.create_watcher(documents_path.clone()) {
             Ok(mut watcher) => {
                 match watcher.start() {
@@ -215,15 +425,22 @@ pub async fn start_server(
                         );
 
                         // Spawn background task to process file system events
+                        let watcher_shutdown = shutdown.clone();
                         tokio::spawn(async move {
                             let mut interval =
                                 tokio::time::interval(tokio::time::Duration::from_secs(1));
                             loop {
-                                interval.tick().await;
+                                tokio::select! {
+                                    _ = interval.tick() => {}
+                                    _ = watcher_shutdown.wait() => break,
+                                }
                                 if let Err(e) = watcher.process_events().await {
                                     log::error!("Error processing document watcher events: {}", e);
                                 }
                             }
+                            // Dropping the watcher stops file system notifications
+                            drop(watcher);
+                            info!("Document watcher stopped");
                         });
                     }
                     Err(e) => {
@@ -242,17 +459,26 @@ pub async fn start_server(
     let approval_map: api::ApprovalStateMap =
         Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new()));
 
+    // Recent chat stream events, for clients reconnecting after a dropped connection
+    let event_buffers = crate::stream_events::StreamEventBuffers::new();
+    let model_cache = crate::models::ModelCache::new(std::time::Duration::from_secs(
+        app_config.server.models_cache_ttl_seconds,
+    ));
+
     // Spawn approval cleanup task to remove expired approvals
     let approval_map_cleanup = approval_map.clone();
+    let approval_timeout = app_config.tools.approval_timeout();
+    let approval_cleanup_interval = app_config.tools.approval_cleanup_interval();
+    let approval_shutdown = shutdown.clone();
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(60));
+        let mut interval = tokio::time::interval(approval_cleanup_interval);
         loop {
-            interval.tick().await;
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = approval_shutdown.wait() => break,
+            }
             let mut approvals = approval_map_cleanup.lock().await;
-            let now = std::time::Instant::now();
-            let initial_count = approvals.len();
-            approvals.retain(|_, state| now.duration_since(state.created_at).as_secs() < 300);
-            let removed = initial_count - approvals.len();
+            let removed = api::expire_approvals(&mut approvals, approval_timeout);
             if removed > 0 {
                 log::debug!(
                     "Cleaned up {} expired approval(s), {} remaining",
@@ -263,6 +489,83 @@ pub async fn start_server(
         }
     });
 
+    // Spawn log retention task to remove old log entries (batched, off the async runtime)
+    if app_config.logging.retention_days > 0 {
+        let log_db_path = db_path.clone();
+        let retention_days = app_config.logging.retention_days;
+        info!("Log retention enabled: {} days", retention_days);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                let path = log_db_path.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    logger::cleanup_old_logs(&path, retention_days * 24 * 60 * 60)
+                })
+                .await;
+                match result {
+                    Ok(Ok(removed)) => info!(
+                        "Log retention removed {} log entries older than {} days",
+                        removed, retention_days
+                    ),
+                    Ok(Err(e)) => error!("Failed to clean up old logs: {}", e),
+                    Err(e) => error!("Log retention task failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Spawn session retention task to remove abandoned sessions
+    if let Some(max_age_seconds) = app_config.sessions.retention_seconds() {
+        let retention_manager = session_manager.clone();
+        let retention_days = app_config.sessions.retention_days;
+        info!("Session retention enabled: {} days", retention_days);
+        let retention_shutdown = shutdown.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = retention_shutdown.wait() => break,
+                }
+                let manager = retention_manager.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    manager.cleanup_old_sessions(max_age_seconds)
+                })
+                .await;
+                match result {
+                    Ok(removed) => info!(
+                        "Session retention removed {} session(s) not updated in {} days",
+                        removed, retention_days
+                    ),
+                    Err(e) => error!("Session retention task failed: {}", e),
+                }
+            }
+        });
+    }
+
+    // Spawn request trace retention task; traces can be captured per request even when
+    // debug.capture_requests is off, so this runs regardless
+    if let Some(max_age_seconds) = app_config.debug.trace_retention_seconds() {
+        let retention_manager = session_manager.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+            loop {
+                interval.tick().await;
+                let manager = retention_manager.clone();
+                let result = tokio::task::spawn_blocking(move || {
+                    manager.cleanup_old_request_traces(max_age_seconds)
+                })
+                .await;
+                match result {
+                    Ok(0) => {}
+                    Ok(removed) => info!("Removed {} expired request trace(s)", removed),
+                    Err(e) => error!("Request trace retention task failed: {}", e),
+                }
+            }
+        });
+    }
+
     // Initialize background job scheduler if enabled
     let job_scheduler = if app_config.jobs.enabled {
         // Initialize global DB path for jobs API
@@ -308,6 +611,22 @@ pub async fn start_server(
         None
     };
 
+    // Run configured prompts on their schedules
+    let schedule_runner =
+        schedules::ScheduleRunner::new(app_config.clone(), session_manager.clone());
+    if !app_config.schedules.is_empty() {
+        match schedule_runner.start().await {
+            Ok(()) => println!(
+                "🦑: {} scheduled prompt(s) active",
+                app_config.schedules.len()
+            ),
+            Err(e) => {
+                warn!("Failed to start schedules: {}", e);
+                println!("🦑: Warning - Schedules could not be started: {}", e);
+            }
+        }
+    }
+
     println!("🦑: Starting Squid Web UI...");
     if app_config.server.allow_network {
         println!(
@@ -323,6 +642,23 @@ pub async fn start_server(
     println!("📡 API endpoint: http://{}/api/chat", bind_address);
     println!("Press Ctrl+C to stop the server\n");
 
+    let rate_limiter = RateLimiter::new(
+        &app_config.server.rate_limit,
+        app_config.server.allow_network,
+    );
+    if rate_limiter.is_enabled() {
+        info!(
+            "Rate limiting enabled: {} chat/min, {} rag/min per IP (burst {})",
+            app_config.server.rate_limit.chat_per_minute,
+            app_config.server.rate_limit.rag_per_minute,
+            app_config.server.rate_limit.burst
+        );
+    }
+    let max_json_bytes = app_config.server.json_body_limit();
+
+    let shutdown_grace = std::time::Duration::from_secs(app_config.server.shutdown_grace_seconds);
+    let signal_shutdown = shutdown.clone();
+
     let server = HttpServer::new(move || {
         // Configure CORS to allow development mode (Vite dev server)
         let cors = Cors::default()
@@ -335,7 +671,16 @@ pub async fn start_server(
             .app_data(web::Data::new(app_config.clone()))
             .app_data(web::Data::new(session_manager.clone()))
             .app_data(web::Data::new(approval_map.clone()))
+            .app_data(web::Data::new(event_buffers.clone()))
+            .app_data(web::Data::new(model_cache.clone()))
+            .app_data(web::Data::new(shutdown.clone()))
+            .app_data(web::Data::new(notifier.clone()))
+            .app_data(web::Data::new(static_assets.clone()))
             .app_data(web::Data::new(rag_system.clone()))
+            .app_data(web::Data::new(schedule_runner.clone()))
+            .app_data(web::Data::new(rate_limiter.clone()))
+            .app_data(json_config(max_json_bytes))
+            .wrap(middleware::from_fn(rate_limit::limit))
             .wrap(cors)
             .wrap(middleware::Logger::default())
             .service(
@@ -343,7 +688,43 @@ pub async fn start_server(
                     .route("/chat", web::post().to(api::chat_stream))
                     .route("/sessions", web::get().to(api::list_sessions))
                     .route("/sessions/events", web::get().to(api::session_events))
+                    .route(
+                        "/sessions/bulk-delete",
+                        web::post().to(api::bulk_delete_sessions),
+                    )
+                    .route(
+                        "/sessions/from-template",
+                        web::post().to(session_templates::create_from_template),
+                    )
                     .route("/sessions/{session_id}", web::get().to(api::get_session))
+                    .route(
+                        "/sessions/{session_id}/events",
+                        web::get().to(api::get_missed_events),
+                    )
+                    .route(
+                        "/sessions/{session_id}/stream",
+                        web::get().to(replay::replay_session),
+                    )
+                    .route(
+                        "/sessions/{session_id}/traces",
+                        web::get().to(api::get_session_traces),
+                    )
+                    .route(
+                        "/sessions/{session_id}/compact",
+                        web::post().to(compact::compact_session),
+                    )
+                    .route(
+                        "/sessions/{session_id}/continue",
+                        web::post().to(api::continue_session),
+                    )
+                    .route(
+                        "/sessions/{session_id}/cancel",
+                        web::post().to(api::cancel_turn),
+                    )
+                    .route(
+                        "/sessions/{session_id}/steps/{step_id}",
+                        web::get().to(api::get_thinking_step),
+                    )
                     .route(
                         "/sessions/{session_id}",
                         web::patch().to(api::update_session),
@@ -352,9 +733,13 @@ pub async fn start_server(
                         "/sessions/{session_id}",
                         web::delete().to(api::delete_session),
                     )
+                    .route("/contents/{content_id}", web::get().to(api::get_content))
                     .route("/logs", web::get().to(api::get_logs))
+                    .route("/logs/export", web::get().to(api::export_logs))
                     .route("/agents", web::get().to(api::get_agents))
+                    .route("/models", web::get().to(api::get_models))
                     .route("/agents/stats", web::get().to(api::get_agent_stats))
+                    .route("/stats/tools", web::get().to(api::get_tool_stats))
                     .route(
                         "/agents/{agent_id}/stats",
                         web::get().to(api::get_agent_stats_by_id),
@@ -364,6 +749,8 @@ pub async fn start_server(
                         web::get().to(api::get_agent_content),
                     )
                     .route("/config", web::get().to(api::get_config))
+                    .route("/version", web::get().to(api::get_version))
+                    .route("/health", web::get().to(api::get_health))
                     .route("/tool-approval", web::post().to(api::handle_tool_approval))
                     .route("/transcribe", web::post().to(audio::transcribe_audio))
                     // Job management routes (must be before workspace catch-all)
@@ -384,6 +771,20 @@ pub async fn start_server(
                         "/executions/{id}",
                         web::get().to(jobs_api::get_job_execution),
                     )
+                    .route("/schedules", web::get().to(schedules::list_schedules))
+                    .route(
+                        "/schedules/{name}/run",
+                        web::post().to(schedules::run_schedule),
+                    )
+                    .route(
+                        "/templates",
+                        web::get().to(session_templates::list_templates),
+                    )
+                    .route("/workspaces", web::get().to(workspace::list_workspaces))
+                    .route(
+                        "/workspace/validate",
+                        web::get().to(workspace::validate_workspace_path),
+                    )
                     .route(
                         "/workspace/files",
                         web::get().to(workspace::get_workspace_files),
@@ -394,6 +795,14 @@ pub async fn start_server(
                     )
                     .route("/rag/query", web::post().to(api::rag_query))
                     .route("/rag/documents", web::get().to(api::rag_list_documents))
+                    .route(
+                        "/rag/documents/{filename}/chunks",
+                        web::get().to(api::rag_document_chunks),
+                    )
+                    .route(
+                        "/rag/documents/{filename}/reindex",
+                        web::post().to(api::rag_reindex_document),
+                    )
                     .route(
                         "/rag/documents/{filename:.*}",
                         web::delete().to(api::rag_delete_document),
@@ -413,19 +822,113 @@ pub async fn start_server(
 
         app
     })
+    // Signals are handled below so streams can be told to finish before workers stop
+    .disable_signals()
+    .shutdown_timeout(shutdown_grace.as_secs())
     .bind(&bind_address);
 
     match server {
         Ok(server) => {
-            if let Err(e) = server.run().await {
+            let server = server.run();
+            let handle = server.handle();
+
+            tokio::spawn(async move {
+                let signal = shutdown::wait_for_signal().await;
+                info!("Received {}, shutting down", signal);
+                println!(
+                    "\n🦑: Shutting down (waiting up to {}s for active requests)...",
+                    shutdown_grace.as_secs()
+                );
+                // Active chat streams save partial answers and send Done, then the server
+                // stops accepting connections and waits for in-flight requests
+                signal_shutdown.trigger();
+                handle.stop(true).await;
+            });
+
+            let result = server.await;
+            log::logger().flush();
+            if let Err(e) = result {
                 error!("Server error: {}", e);
-                println!("🦑: Server error - {}", e);
+                return Err(format!("Server error - {}", e).into());
             }
+            println!("🦑: Server stopped");
+            Ok(())
         }
         Err(e) => {
             error!("Failed to bind to {}: {}", bind_address, e);
-            println!("🦑: Failed to start server on {} - {}", bind_address, e);
-            println!("The port might already be in use. Try a different port with --port <PORT>");
+            Err(format!(
+                "Failed to start server on {} - {}\nThe port might already be in use. Try a different port with --port <PORT>",
+                bind_address, e
+            )
+            .into())
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_static_dir_overrides_and_falls_back_to_embedded() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(temp.path().join("index.html"), "<html>custom</html>").unwrap();
+        let assets = StaticAssets::new(Some(temp.path())).unwrap();
+
+        assert!(assets.is_external());
+        assert_eq!(
+            assets.get("index.html").await.unwrap(),
+            b"<html>custom</html>".to_vec()
+        );
+        // Files missing on disk come from the embedded build
+        if let Some(embedded) = Assets::iter().find(|name| name != "index.html") {
+            assert_eq!(
+                assets.get(&embedded).await,
+                Assets::get(&embedded).map(|c| c.data.into_owned())
+            );
+        }
+    }
+
+    #[test]
+    fn test_static_dir_rejects_traversal() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("ui");
+        std::fs::create_dir(&root).unwrap();
+        std::fs::write(root.join("app.js"), "ok").unwrap();
+        std::fs::write(temp.path().join("secret.txt"), "secret").unwrap();
+        let assets = StaticAssets::new(Some(&root)).unwrap();
+
+        assert!(assets.disk_path("app.js").is_some());
+        assert!(assets.disk_path("../secret.txt").is_none());
+        assert!(assets.disk_path("/../secret.txt").is_none());
+        assert!(assets.disk_path("%2e%2e/secret.txt").is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_oversized_json_body_is_rejected() {
+        async fn echo(body: web::Json<serde_json::Value>) -> HttpResponse {
+            HttpResponse::Ok().json(body.into_inner())
+        }
+        let app = actix_web::test::init_service(
+            App::new()
+                .app_data(json_config(64))
+                .route("/echo", web::post().to(echo)),
+        )
+        .await;
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({"message": "hi"}))
+            .to_request();
+        assert_eq!(actix_web::test::call_service(&app, req).await.status(), 200);
+
+        let req = actix_web::test::TestRequest::post()
+            .uri("/echo")
+            .set_json(serde_json::json!({"message": "x".repeat(100)}))
+            .to_request();
+        let resp = actix_web::test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 413);
+        let body: serde_json::Value = actix_web::test::read_body_json(resp).await;
+        assert!(body["error"].as_str().unwrap().contains("64 byte limit"));
+    }
+}
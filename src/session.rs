@@ -1,36 +1,34 @@
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 use uuid::Uuid;
 
 use crate::db::Database;
+use crate::error::{Result, SquidError};
+use crate::trace::{RequestTrace, ResponseSummary};
 
 /// Represents a file attachment in a chat message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileAttachment {
     pub filename: String,
     pub content: String,
-}
-
-/// Represents a tool invocation (execution) result
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ToolInvocation {
-    pub name: String,
-    pub arguments: Value,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub result: Option<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    /// Send the content with line numbers so findings can cite exact lines
+    #[serde(default)]
+    pub for_review: bool,
 }
 
 /// Represents a single step in the thinking process
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ThinkingStep {
-    pub step_type: String, // "reasoning" or "tool"
+    /// Row id in `thinking_steps`, once saved
+    #[serde(skip)]
+    pub id: Option<i64>,
+    pub step_type: String, // "reasoning", "tool" or "warning"
     pub step_order: i32,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content: Option<String>, // for reasoning steps
+    pub content: Option<String>, // for reasoning and warning steps
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -40,18 +38,141 @@ pub struct ThinkingStep {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub content_before_tool: Option<String>, // Content accumulated before this tool
+    pub content_delta_before_tool: Option<String>, // Content produced since the previous tool step
+    /// Why a tool step didn't run, such as `denied_by_config`; `None` for tools that ran
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<String>,
+}
+
+impl ThinkingStep {
+    /// A notice recorded with the answer, such as a tool limit that was hit
+    pub fn warning(step_order: i32, message: &str) -> Self {
+        Self {
+            id: None,
+            step_type: "warning".to_string(),
+            step_order,
+            content: Some(message.to_string()),
+            tool_name: None,
+            tool_arguments: None,
+            tool_result: None,
+            tool_error: None,
+            content_delta_before_tool: None,
+            status: None,
+        }
+    }
+}
+
+/// Splits streamed assistant content into the pieces that precede each tool step.
+///
+/// Each call returns only the text produced since the previous non-empty delta, so
+/// concatenating all deltas yields the content streamed before the last tool call.
+#[derive(Debug, Default)]
+pub struct ContentDeltaTracker {
+    offset: usize,
+}
+
+impl ContentDeltaTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the content added since the last delta, or `None` if only whitespace was added
+    pub fn take_delta(&mut self, accumulated: &str) -> Option<String> {
+        // Leading whitespace is dropped, matching the trimmed message content
+        let start = if self.offset == 0 {
+            accumulated.len() - accumulated.trim_start().len()
+        } else {
+            self.offset
+        };
+        let delta = accumulated.get(start..)?;
+        if delta.trim().is_empty() {
+            return None;
+        }
+        self.offset = accumulated.len();
+        Some(delta.to_string())
+    }
 }
 
 /// Represents a message in the chat history
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
+    /// Database id, `None` until the message is saved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    /// Message this one follows; reserved for branching conversations
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_message_id: Option<i64>,
     pub role: String, // "user" or "assistant"
     pub content: String,
     pub sources: Vec<Source>,
     pub timestamp: i64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thinking_steps: Option<Vec<ThinkingStep>>, // Ordered chain of thought
+    /// Generation settings that produced an assistant message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<MessageMetadata>,
+    /// Why the model stopped generating an assistant message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<crate::providers::StopReason>,
+}
+
+/// Per-message details stored alongside the content as JSON
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MessageMetadata {
+    /// Reasoning settings actually sent to the model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<crate::config::ReasoningConfig>,
+    /// Version of squid that generated the answer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub squid_version: Option<String>,
+    /// Host (and port) of the provider `api_url`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_host: Option<String>,
+    /// Model id the answer was requested from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Written by compaction in place of this many earlier messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summary_of: Option<usize>,
+    /// Error that cut the answer short; the content is what arrived before it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time spent searching the documents for the question, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retrieval_ms: Option<u64>,
+    /// Approximate tokens each part of the context took when the question was sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_breakdown: Option<Vec<crate::tokens::ContextCategory>>,
+}
+
+impl MessageMetadata {
+    /// Metadata for an answer generated by `model` at `api_url` with `reasoning`
+    pub fn for_answer(
+        model: &str,
+        api_url: &str,
+        reasoning: crate::config::ReasoningConfig,
+    ) -> Option<Self> {
+        Some(Self {
+            reasoning: (!reasoning.is_empty()).then_some(reasoning),
+            squid_version: Some(crate::config::Config::app_version()),
+            provider_host: provider_host(api_url),
+            model: Some(model.to_string()),
+            summary_of: None,
+            error: None,
+            retrieval_ms: None,
+            context_breakdown: None,
+        })
+    }
+}
+
+/// `host[:port]` of a provider URL, without credentials or path
+pub fn provider_host(api_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(api_url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
 }
 
 /// Represents a source (file attachment) to be displayed with a message
@@ -59,6 +180,84 @@ pub struct ChatMessage {
 pub struct Source {
     pub title: String,
     pub content: String,
+    #[serde(default)]
+    pub origin: SourceOrigin,
+}
+
+impl Source {
+    pub fn new(title: impl Into<String>, content: impl Into<String>, origin: SourceOrigin) -> Self {
+        Self {
+            title: title.into(),
+            content: content.into(),
+            origin,
+        }
+    }
+}
+
+/// Where a source came from, stored in `sources.origin`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceOrigin {
+    /// A file the user attached
+    #[default]
+    Attachment,
+    /// A chunk retrieved from the RAG index
+    Rag,
+    /// Content returned by a tool
+    Tool,
+    /// A project file attached by `context.auto_include`
+    Auto,
+}
+
+impl SourceOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceOrigin::Attachment => "attachment",
+            SourceOrigin::Rag => "rag",
+            SourceOrigin::Tool => "tool",
+            SourceOrigin::Auto => "auto",
+        }
+    }
+
+    /// Parse a stored value, treating unknown values as attachments
+    pub fn from_db(value: &str) -> Self {
+        match value {
+            "rag" => SourceOrigin::Rag,
+            "tool" => SourceOrigin::Tool,
+            "auto" => SourceOrigin::Auto,
+            _ => SourceOrigin::Attachment,
+        }
+    }
+}
+
+/// Drop sources repeating an earlier one's title and content, keeping the first
+pub fn dedupe_sources(sources: &mut Vec<Source>) {
+    let mut seen = std::collections::HashSet::new();
+    sources.retain(|source| {
+        seen.insert((
+            source.title.clone(),
+            crate::db::content_hash(&source.content),
+        ))
+    });
+}
+
+/// Sources of one turn: the attachments, then RAG chunks that don't repeat an attachment
+///
+/// A chunk with the same content as an attached file is the same source, so it is shown once
+/// under the attachment's title.
+pub fn merge_turn_sources(attachments: &[Source], rag: &[Source]) -> Vec<Source> {
+    let attached: std::collections::HashSet<String> = attachments
+        .iter()
+        .map(|source| crate::db::content_hash(&source.content))
+        .collect();
+    let mut sources = attachments.to_vec();
+    sources.extend(
+        rag.iter()
+            .filter(|source| !attached.contains(&crate::db::content_hash(&source.content)))
+            .cloned(),
+    );
+    dedupe_sources(&mut sources);
+    sources
 }
 
 /// Token usage tracking for a session
@@ -92,16 +291,6 @@ impl TokenUsage {
             self.context_utilization = 0.0;
         }
     }
-
-    /// Check if context usage is approaching the limit (>80%)
-    pub fn is_approaching_limit(&self) -> bool {
-        self.context_utilization > 0.8
-    }
-
-    /// Check if context usage has exceeded the limit
-    pub fn is_over_limit(&self) -> bool {
-        self.context_utilization > 1.0
-    }
 }
 
 /// Represents a chat session with history and context
@@ -116,9 +305,88 @@ pub struct ChatSession {
     pub token_usage: TokenUsage,
     pub cost_usd: f64,
     pub is_readonly: bool,
+    /// Directory tools run in; `None` uses the server's startup directory
+    #[serde(default)]
+    pub working_dir: Option<String>,
+    /// Name of the configured workspace the session belongs to
+    #[serde(default)]
+    pub workspace: Option<String>,
+    /// Session-level details stored as JSON in `sessions.metadata`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<SessionMetadata>,
+    /// Models that answered in this session, in order of first use
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub models_used: Vec<String>,
+    /// Chat requests for any other model are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked_model: Option<String>,
+    /// Who started the session over the web API, for auditing shared servers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<Origin>,
+}
+
+/// Client that sent the first chat request of a session
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Origin {
+    /// Remote address, truncated to its network with `privacy.redact_ips`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+    /// Value of the `X-Squid-User` header; set by the client, so not proof of identity
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+impl Origin {
+    /// `None` when nothing about the client is known
+    pub fn into_option(self) -> Option<Self> {
+        (self != Self::default()).then_some(self)
+    }
+}
+
+/// Per-session details stored alongside the session as JSON
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionMetadata {
+    /// Where the conversation came from, for sessions created by `squid sessions import`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported_from: Option<ImportSource>,
+    /// Name of the schedule whose runs are saved in this session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// Name of the conversation template the session was started from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// System prompt of the template, used for every turn of the session
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_prompt: Option<String>,
+}
+
+/// The export a session was imported from; re-imports skip conversations already recorded
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImportSource {
+    /// Export format (`chatgpt`, `claude` or `squid`)
+    pub format: String,
+    /// Conversation id in the source export
+    pub conversation_id: String,
 }
 
 impl ChatSession {
+    /// Name of the schedule that created this session
+    pub fn schedule(&self) -> Option<&str> {
+        self.metadata.as_ref()?.schedule.as_deref()
+    }
+
+    /// Name of the conversation template this session was started from
+    pub fn template(&self) -> Option<&str> {
+        self.metadata.as_ref()?.template.as_deref()
+    }
+
+    /// System prompt the session was started with, which replaces the agent's
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.metadata.as_ref()?.system_prompt.as_deref()
+    }
+
     /// Create a new chat session
     pub fn new() -> Self {
         let now = chrono::Utc::now().timestamp();
@@ -132,6 +400,12 @@ impl ChatSession {
             token_usage: TokenUsage::default(),
             cost_usd: 0.0,
             is_readonly: false,
+            working_dir: None,
+            workspace: None,
+            metadata: None,
+            models_used: Vec::new(),
+            locked_model: None,
+            origin: None,
         }
     }
 
@@ -150,13 +424,39 @@ impl ChatSession {
         self.token_usage.update_utilization();
     }
 
-    /// Set the model used for this session
-    pub fn set_model(&mut self, model_id: String) {
-        if self.agent_id.is_none() {
-            self.agent_id = Some(model_id);
+    /// Record the agent that answered the latest turn
+    pub fn set_agent(&mut self, agent_id: String) {
+        self.agent_id = Some(agent_id);
+    }
+
+    /// Record that `model` answered in this session
+    pub fn note_model(&mut self, model: &str) {
+        if !self.models_used.iter().any(|used| used == model) {
+            self.models_used.push(model.to_string());
+        }
+    }
+
+    /// Refuse `model` when the session is locked to another one
+    pub fn check_model_lock(&self, model: &str) -> Result<()> {
+        match self.locked_model.as_deref() {
+            Some(locked) if locked != model => Err(SquidError::ModelLocked {
+                locked: locked.to_string(),
+                requested: model.to_string(),
+            }),
+            _ => Ok(()),
         }
     }
 
+    /// Model that wrote the latest answer, if known
+    pub fn last_model(&self) -> Option<&str> {
+        self.messages
+            .iter()
+            .rev()
+            .filter(|message| message.role == "assistant")
+            .find_map(|message| message.metadata.as_ref()?.model.as_deref())
+            .or_else(|| self.models_used.last().map(String::as_str))
+    }
+
     /// Set the context window size for this session
     pub fn set_context_window(&mut self, context_window: u32) {
         self.token_usage.context_window = context_window;
@@ -164,50 +464,31 @@ impl ChatSession {
     }
 
     /// Add a message to the session
-    pub fn add_message(&mut self, role: String, content: String, sources: Vec<Source>) {
+    pub fn add_message(&mut self, role: String, content: String, mut sources: Vec<Source>) {
+        dedupe_sources(&mut sources);
         let now = chrono::Utc::now().timestamp();
+        let parent_message_id = self.messages.last().and_then(|msg| msg.id);
         self.messages.push(ChatMessage {
+            id: None,
+            parent_message_id,
             role,
             content,
             sources,
             timestamp: now,
             thinking_steps: None, // Will be set separately when available
+            metadata: None,
+            finish_reason: None,
         });
         self.updated_at = now;
     }
 
-    /// Get the full conversation history for the LLM
-    pub fn get_conversation_context(&self) -> Vec<(String, String)> {
-        self.messages
-            .iter()
-            .map(|msg| (msg.role.clone(), msg.content.clone()))
-            .collect()
-    }
-
-    /// Get sources from the last user message
-    pub fn get_last_user_sources(&self) -> Vec<Source> {
-        self.messages
-            .iter()
-            .rev()
-            .find(|msg| msg.role == "user")
-            .map(|msg| msg.sources.clone())
-            .unwrap_or_default()
-    }
-
     /// Generate a title from the first user message
     /// Returns a truncated version (max 100 chars) of the first user message
     fn generate_title(&self) -> Option<String> {
         self.messages
             .iter()
             .find(|msg| msg.role == "user")
-            .map(|msg| {
-                let content = msg.content.trim();
-                if content.len() > 100 {
-                    format!("{}...", &content[..97])
-                } else {
-                    content.to_string()
-                }
-            })
+            .map(|msg| crate::text::truncate(msg.content.trim(), 100))
     }
 
     /// Update the session title if it hasn't been set yet
@@ -224,11 +505,38 @@ impl Default for ChatSession {
     }
 }
 
+/// What is holding a session's lock
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SessionActivity {
+    /// A chat turn is in progress, with its request id
+    Turn(String),
+    /// The session's messages are being replaced by a summary
+    Compacting,
+}
+
+/// Held while a chat turn or compaction runs on a session, released on drop
+pub struct SessionLock {
+    activity: Arc<Mutex<HashMap<String, SessionActivity>>>,
+    session_id: String,
+}
+
+impl Drop for SessionLock {
+    fn drop(&mut self) {
+        self.activity.lock().unwrap().remove(&self.session_id);
+    }
+}
+
+/// A directory a session may read without asking, as (tool, directory)
+type DirectoryGrant = (String, PathBuf);
+
 /// Session manager to handle multiple chat sessions
 /// Uses write-through cache with SQLite persistence
 pub struct SessionManager {
     sessions: Arc<RwLock<HashMap<String, ChatSession>>>,
     db: Arc<Database>,
+    activity: Arc<Mutex<HashMap<String, SessionActivity>>>,
+    directory_grants: Arc<Mutex<HashMap<String, Vec<DirectoryGrant>>>>,
+    context_window_warned: Arc<Mutex<HashSet<String>>>,
 }
 
 impl SessionManager {
@@ -237,6 +545,106 @@ impl SessionManager {
         Self {
             sessions: Arc::new(RwLock::new(HashMap::new())),
             db: Arc::new(db),
+            activity: Arc::new(Mutex::new(HashMap::new())),
+            directory_grants: Arc::new(Mutex::new(HashMap::new())),
+            context_window_warned: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Lock a session for the chat turn `turn_id`; fails while another turn or a compaction runs
+    ///
+    /// Turns of one session share its event buffer, so they can't overlap.
+    pub fn begin_turn(&self, session_id: &str, turn_id: &str) -> Result<SessionLock> {
+        let mut activity = self.activity.lock().unwrap();
+        match activity.get(session_id) {
+            Some(SessionActivity::Compacting) => Err(SquidError::SessionBusy(
+                "Session is being compacted, try again shortly".to_string(),
+            )),
+            Some(SessionActivity::Turn(_)) => Err(SquidError::SessionBusy(
+                "A chat turn is already in progress on this session".to_string(),
+            )),
+            None => {
+                activity.insert(
+                    session_id.to_string(),
+                    SessionActivity::Turn(turn_id.to_string()),
+                );
+                Ok(self.lock_for(session_id))
+            }
+        }
+    }
+
+    /// Lock a session for compaction; fails while any chat turn or compaction is running on it
+    pub fn begin_compaction(&self, session_id: &str) -> Result<SessionLock> {
+        let mut activity = self.activity.lock().unwrap();
+        match activity.get(session_id) {
+            Some(SessionActivity::Compacting) => Err(SquidError::SessionBusy(
+                "Session is already being compacted".to_string(),
+            )),
+            Some(SessionActivity::Turn(_)) => Err(SquidError::SessionBusy(
+                "A chat turn is in progress on this session".to_string(),
+            )),
+            None => {
+                activity.insert(session_id.to_string(), SessionActivity::Compacting);
+                Ok(self.lock_for(session_id))
+            }
+        }
+    }
+
+    /// Whether a chat turn is running on the session
+    pub fn is_turn_running(&self, session_id: &str) -> bool {
+        self.running_turn(session_id).is_some()
+    }
+
+    /// Request id of the chat turn running on the session
+    pub fn running_turn(&self, session_id: &str) -> Option<String> {
+        match self.activity.lock().unwrap().get(session_id) {
+            Some(SessionActivity::Turn(turn_id)) => Some(turn_id.clone()),
+            _ => None,
+        }
+    }
+
+    /// Let `tool` run anywhere under `dir` without approval until the server stops
+    ///
+    /// Grants live in memory only, like the rule `read_file:src/**` scoped to one session.
+    pub fn allow_directory(&self, session_id: &str, tool: &str, dir: &Path) {
+        let mut grants = self.directory_grants.lock().unwrap();
+        let session_grants = grants.entry(session_id.to_string()).or_default();
+        if !session_grants
+            .iter()
+            .any(|(granted_tool, granted)| granted_tool == tool && dir.starts_with(granted))
+        {
+            session_grants.push((tool.to_string(), dir.to_path_buf()));
+        }
+    }
+
+    /// Whether a directory grant of this session covers `tool` on `path`
+    pub fn is_directory_allowed(&self, session_id: &str, tool: &str, path: &Path) -> bool {
+        self.directory_grants
+            .lock()
+            .unwrap()
+            .get(session_id)
+            .is_some_and(|grants| {
+                grants
+                    .iter()
+                    .any(|(granted_tool, dir)| granted_tool == tool && path.starts_with(dir))
+            })
+    }
+
+    /// Whether the context window mismatch should be reported for this session
+    ///
+    /// True only the first time it's asked for a session; like directory grants, this is kept
+    /// in memory and starts over when the server restarts.
+    pub fn warn_context_window_once(&self, session_id: &str) -> bool {
+        self.context_window_warned
+            .lock()
+            .unwrap()
+            .insert(session_id.to_string())
+    }
+
+    fn lock_for(&self, session_id: &str) -> SessionLock {
+        SessionLock {
+            activity: self.activity.clone(),
+            session_id: session_id.to_string(),
         }
     }
 
@@ -257,6 +665,83 @@ impl SessionManager {
         session_id
     }
 
+    /// Create an empty session with a client-chosen `id`, which must be a lowercase hyphenated UUID
+    pub fn create_session_with_id(&self, id: &str) -> Result<String, String> {
+        match Uuid::parse_str(id) {
+            Ok(uuid) if uuid.hyphenated().to_string() == id => {}
+            _ => return Err(format!("Session id '{}' is not a UUID", id)),
+        }
+        let session = ChatSession {
+            id: id.to_string(),
+            ..ChatSession::new()
+        };
+        if let Err(e) = self.db.save_session(&session) {
+            log::error!("Failed to save session to database: {}", e);
+        }
+
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.insert(id.to_string(), session);
+        Ok(id.to_string())
+    }
+
+    /// Create a session for runs of schedule `name`, answered by `agent_id`
+    pub fn create_schedule_session(&self, name: &str, agent_id: &str, title: String) -> String {
+        let mut session = ChatSession::new();
+        session.title = Some(title);
+        session.agent_id = Some(agent_id.to_string());
+        session.metadata = Some(SessionMetadata {
+            schedule: Some(name.to_string()),
+            ..Default::default()
+        });
+        let session_id = session.id.clone();
+
+        if let Err(e) = self.db.save_session(&session) {
+            log::error!("Failed to save session to database: {}", e);
+        }
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session_id.clone(), session);
+
+        session_id
+    }
+
+    /// Create a session started from template `name`, answered by `agent_id`
+    pub fn create_template_session(
+        &self,
+        name: &str,
+        agent_id: &str,
+        system_prompt: Option<String>,
+    ) -> String {
+        let mut session = ChatSession::new();
+        session.agent_id = Some(agent_id.to_string());
+        session.metadata = Some(SessionMetadata {
+            template: Some(name.to_string()),
+            system_prompt,
+            ..Default::default()
+        });
+        let session_id = session.id.clone();
+
+        if let Err(e) = self.db.save_session(&session) {
+            log::error!("Failed to save session to database: {}", e);
+        }
+        self.sessions
+            .write()
+            .unwrap()
+            .insert(session_id.clone(), session);
+
+        session_id
+    }
+
+    /// The session schedule `name` last wrote to, if any
+    pub fn latest_schedule_session(&self, name: &str) -> Option<String> {
+        self.db
+            .latest_schedule_session(name)
+            .map_err(|e| log::error!("Failed to look up sessions of schedule '{}': {}", name, e))
+            .ok()
+            .flatten()
+    }
+
     /// Get a session by ID
     /// First checks memory cache, then falls back to database
     pub fn get_session(&self, session_id: &str) -> Option<ChatSession> {
@@ -302,20 +787,30 @@ impl SessionManager {
         session_id: &str,
         content: String,
         files: Vec<FileAttachment>,
-    ) -> Result<Vec<Source>, String> {
+    ) -> Result<Vec<Source>> {
+        self.add_user_message_with_sources(session_id, content, files, Vec::new())
+    }
+
+    /// Add a user message whose sources start with `sources`, followed by its attached files
+    pub fn add_user_message_with_sources(
+        &self,
+        session_id: &str,
+        content: String,
+        files: Vec<FileAttachment>,
+        mut sources: Vec<Source>,
+    ) -> Result<Vec<Source>> {
         // Get or load session
         let mut session = self
             .get_session(session_id)
-            .ok_or_else(|| "Session not found".to_string())?;
+            .ok_or_else(|| SquidError::SessionNotFound(session_id.to_string()))?;
 
         // Convert file attachments to sources
-        let sources: Vec<Source> = files
-            .iter()
-            .map(|file| Source {
-                title: file.filename.clone(),
-                content: file.content.clone(),
-            })
-            .collect();
+        sources.extend(
+            files
+                .iter()
+                .map(|file| Source::new(&file.filename, &file.content, SourceOrigin::Attachment)),
+        );
+        dedupe_sources(&mut sources);
 
         // Add message to session (users don't have thinking steps)
         session.add_message("user".to_string(), content, sources.clone());
@@ -329,10 +824,12 @@ impl SessionManager {
             .last()
             .ok_or_else(|| "Failed to add message".to_string())?;
 
-        // Save message to database
-        if let Err(e) = self.db.save_message(session_id, message) {
+        // Save message to database and keep the cached copy in sync with its id
+        let message_id = self.db.save_message(session_id, message).inspect_err(|e| {
             log::error!("Failed to save message to database: {}", e);
-            return Err(format!("Failed to save message: {}", e));
+        })?;
+        if let Some(message) = session.messages.last_mut() {
+            message.id = Some(message_id);
         }
 
         // Update session (saves to DB and cache)
@@ -342,11 +839,11 @@ impl SessionManager {
     }
 
     /// Update session title
-    pub fn update_session_title(&self, session_id: &str, title: String) -> Result<(), String> {
+    pub fn update_session_title(&self, session_id: &str, title: String) -> Result<()> {
         // Update in database
         if let Err(e) = self.db.update_session_title(session_id, &title) {
             log::error!("Failed to update session title in database: {}", e);
-            return Err(format!("Failed to update session title: {}", e));
+            return Err(e);
         }
 
         // Update cache if session is loaded
@@ -358,25 +855,154 @@ impl SessionManager {
         Ok(())
     }
 
-    /// Add an assistant message to a session
+    /// Update session working directory
+    pub fn update_session_working_dir(
+        &self,
+        session_id: &str,
+        working_dir: Option<String>,
+    ) -> Result<()> {
+        if let Err(e) = self
+            .db
+            .update_session_working_dir(session_id, working_dir.as_deref())
+        {
+            log::error!(
+                "Failed to update session working directory in database: {}",
+                e
+            );
+            return Err(e);
+        }
+
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.working_dir = working_dir;
+        }
+
+        Ok(())
+    }
+
+    /// Lock a session to `model`, or remove the lock with `None`
+    pub fn update_session_locked_model(
+        &self,
+        session_id: &str,
+        model: Option<String>,
+    ) -> Result<()> {
+        if let Err(e) = self
+            .db
+            .update_session_locked_model(session_id, model.as_deref())
+        {
+            log::error!("Failed to update session model lock in database: {}", e);
+            return Err(e);
+        }
+
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.locked_model = model;
+        }
+
+        Ok(())
+    }
+
+    /// Record who started `session_id`, unless an origin is already recorded
+    pub fn record_origin(&self, session_id: &str, origin: Origin) -> Result<()> {
+        let Some(mut session) = self.get_session(session_id) else {
+            return Err(SquidError::SessionNotFound(session_id.to_string()));
+        };
+        if session.origin.is_none() {
+            session.origin = origin.into_option();
+            if session.origin.is_some() {
+                self.update_session(session);
+            }
+        }
+        Ok(())
+    }
+
+    /// Arguments of tool call `tool_call_id`, with large values stored and replaced by refs
+    ///
+    /// See [`crate::offload`]; used for stream events and saved thinking steps.
+    pub fn offload_tool_arguments(
+        &self,
+        session_id: &str,
+        tool_call_id: &str,
+        args: &serde_json::Value,
+    ) -> serde_json::Value {
+        crate::offload::offload_arguments(args, |content| {
+            self.db
+                .store_tool_content(session_id, tool_call_id, content)
+        })
+    }
+
+    /// Arguments of tool call `tool_call_id` with content refs replaced by the stored contents
+    ///
+    /// Only refs stored for this session and tool call resolve, so a model can't pull in
+    /// another session's content.
+    pub fn resolve_tool_arguments(
+        &self,
+        session_id: &str,
+        tool_call_id: &str,
+        args: &serde_json::Value,
+    ) -> std::result::Result<serde_json::Value, String> {
+        crate::offload::resolve_arguments(args, |id| {
+            self.db.get_tool_content(session_id, tool_call_id, id)
+        })
+    }
+
+    /// A stored file or tool argument content
+    pub fn get_file_content(&self, content_id: i64) -> Result<Option<String>> {
+        self.db.get_file_content(content_id)
+    }
+
+    /// Bind a session to a workspace whose root is `working_dir`
+    pub fn update_session_workspace(
+        &self,
+        session_id: &str,
+        workspace: String,
+        working_dir: String,
+    ) -> Result<()> {
+        if let Err(e) = self
+            .db
+            .update_session_workspace(session_id, &workspace, &working_dir)
+        {
+            log::error!("Failed to update session workspace in database: {}", e);
+            return Err(e);
+        }
+
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(session) = sessions.get_mut(session_id) {
+            session.workspace = Some(workspace);
+            session.working_dir = Some(working_dir);
+        }
+
+        Ok(())
+    }
+
+    /// Add an assistant message to a session, returning its database id
     pub fn add_assistant_message(
         &self,
         session_id: &str,
         content: String,
         sources: Vec<Source>,
         thinking_steps: Option<Vec<ThinkingStep>>,
-    ) -> Result<(), String> {
+        metadata: Option<MessageMetadata>,
+    ) -> Result<i64> {
         // Get or load session
         let mut session = self
             .get_session(session_id)
-            .ok_or_else(|| "Session not found".to_string())?;
+            .ok_or_else(|| SquidError::SessionNotFound(session_id.to_string()))?;
 
         // Add message to session
         session.add_message("assistant".to_string(), content, sources);
 
+        if let Some(model) = metadata
+            .as_ref()
+            .and_then(|metadata| metadata.model.as_deref())
+        {
+            session.note_model(model);
+        }
+
         // Get the last message and set thinking steps
         if let Some(message) = session.messages.last_mut() {
             message.thinking_steps = thinking_steps;
+            message.metadata = metadata;
         }
 
         let message = session
@@ -384,16 +1010,48 @@ impl SessionManager {
             .last()
             .ok_or_else(|| "Failed to add message".to_string())?;
 
-        // Save message to database
-        if let Err(e) = self.db.save_message(session_id, message) {
+        // Save message to database and keep the cached copy in sync with its id
+        let message_id = self.db.save_message(session_id, message).inspect_err(|e| {
             log::error!("Failed to save message to database: {}", e);
-            return Err(format!("Failed to save message: {}", e));
+        })?;
+        if let Some(message) = session.messages.last_mut() {
+            message.id = Some(message_id);
+            if let Some(steps) = message.thinking_steps.as_mut() {
+                match self.db.thinking_step_ids(message_id) {
+                    Ok(ids) => {
+                        for (step, id) in steps.iter_mut().zip(ids) {
+                            step.id = Some(id);
+                        }
+                    }
+                    Err(e) => log::error!("Failed to load thinking step ids: {}", e),
+                }
+            }
         }
 
         // Update session (saves to DB and cache)
         self.update_session(session);
 
-        Ok(())
+        Ok(message_id)
+    }
+
+    /// Replace `message_ids` with a compaction `summary`, returning its id and what was removed
+    pub fn replace_with_summary(
+        &self,
+        session_id: &str,
+        message_ids: &[i64],
+        summary: &ChatMessage,
+    ) -> Result<(i64, crate::db::CompactionStats)> {
+        let result = self
+            .db
+            .replace_with_summary(session_id, message_ids, summary)
+            .inspect_err(|e| {
+                log::error!("Failed to compact session {}: {}", session_id, e);
+            })?;
+
+        // The cached copy still holds the removed messages; reload it on next access
+        self.sessions.write().unwrap().remove(session_id);
+
+        Ok(result)
     }
 
     /// Delete a session
@@ -404,10 +1062,28 @@ impl SessionManager {
         // Remove from cache
         let mut sessions = self.sessions.write().unwrap();
         let cache_deleted = sessions.remove(session_id).is_some();
+        self.directory_grants.lock().unwrap().remove(session_id);
+        self.context_window_warned
+            .lock()
+            .unwrap()
+            .remove(session_id);
 
         db_deleted || cache_deleted
     }
 
+    /// Delete every session matching `filter` and return the deleted ids
+    pub fn delete_sessions(&self, filter: &crate::db::SessionFilter) -> Result<Vec<String>> {
+        let ids = self.db.delete_sessions(filter).inspect_err(|e| {
+            log::error!("Failed to delete sessions: {}", e);
+        })?;
+
+        let mut sessions = self.sessions.write().unwrap();
+        for id in &ids {
+            sessions.remove(id);
+        }
+        Ok(ids)
+    }
+
     /// Get all session IDs from database
     pub fn list_sessions(&self) -> Vec<String> {
         match self.db.list_sessions() {
@@ -420,16 +1096,24 @@ impl SessionManager {
     }
 
     /// Clean up old sessions (older than specified seconds)
-    pub fn cleanup_old_sessions(&self, max_age_seconds: i64) {
+    ///
+    /// Returns the number of sessions deleted from the database.
+    pub fn cleanup_old_sessions(&self, max_age_seconds: i64) -> usize {
         // Clean up database
-        if let Err(e) = self.db.cleanup_old_sessions(max_age_seconds) {
-            log::error!("Failed to cleanup old sessions from database: {}", e);
-        }
+        let deleted = match self.db.cleanup_old_sessions(max_age_seconds) {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                log::error!("Failed to cleanup old sessions from database: {}", e);
+                0
+            }
+        };
 
         // Clean up cache
         let now = chrono::Utc::now().timestamp();
         let mut sessions = self.sessions.write().unwrap();
         sessions.retain(|_, session| (now - session.updated_at) < max_age_seconds);
+
+        deleted
     }
 
     /// Update token usage for a session
@@ -438,14 +1122,14 @@ impl SessionManager {
         session_id: &str,
         agent_id: &str,
         usage: TokenUsageUpdate,
-    ) -> Result<(), String> {
+    ) -> Result<()> {
         // Get or load session
         let mut session = self
             .get_session(session_id)
-            .ok_or_else(|| "Session not found".to_string())?;
+            .ok_or_else(|| SquidError::SessionNotFound(session_id.to_string()))?;
 
-        // Set agent if not already set
-        session.set_model(agent_id.to_string());
+        // The agent of the latest turn, so the session continues with it
+        session.set_agent(agent_id.to_string());
 
         // Set context window
         session.set_context_window(usage.context_window);
@@ -480,17 +1164,185 @@ impl SessionManager {
     pub fn get_agent_token_stats(
         &self,
         agent_id: &str,
-    ) -> Result<Option<crate::db::AgentTokenStatsRow>, String> {
-        self.db
-            .get_agent_token_stats(agent_id)
-            .map_err(|e| format!("Failed to get agent token stats: {}", e))
+    ) -> Result<Option<crate::db::AgentTokenStatsRow>> {
+        self.db.get_agent_token_stats(agent_id)
     }
 
     /// Get token stats for all agents
-    pub fn get_all_agent_token_stats(&self) -> Result<Vec<crate::db::AgentTokenStatsRow>, String> {
+    pub fn get_all_agent_token_stats(&self) -> Result<Vec<crate::db::AgentTokenStatsRow>> {
+        self.db.get_all_agent_token_stats()
+    }
+
+    /// Tool usage counts for steps recorded since `since`
+    pub fn get_tool_stats(&self, since: i64) -> Result<Vec<crate::db::ToolStatsRow>> {
+        self.db.get_tool_stats(since)
+    }
+
+    /// Bash usage by base command for steps recorded since `since`
+    pub fn get_bash_command_stats(&self, since: i64) -> Result<Vec<crate::db::ToolStatsRow>> {
+        self.db.get_bash_command_stats(since)
+    }
+
+    /// Store a captured provider request
+    pub fn save_request_trace(&self, trace: &RequestTrace) -> Result<()> {
+        self.db.save_request_trace(trace)
+    }
+
+    /// Record the response summary of a captured request
+    pub fn finish_request_trace(&self, trace_id: &str, summary: &ResponseSummary) {
+        let response = serde_json::to_value(summary).unwrap_or_default();
+        if let Err(e) = self.db.update_request_trace_response(trace_id, &response) {
+            log::error!("Failed to update request trace: {}", e);
+        }
+    }
+
+    /// Record why the model stopped generating a saved assistant message
+    pub fn set_finish_reason(
+        &self,
+        session_id: &str,
+        message_id: i64,
+        finish_reason: crate::providers::StopReason,
+    ) -> Result<()> {
+        self.db
+            .set_message_finish_reason(message_id, finish_reason)?;
+        let mut sessions = self.sessions.write().unwrap();
+        if let Some(message) = sessions.get_mut(session_id).and_then(|session| {
+            session
+                .messages
+                .iter_mut()
+                .find(|message| message.id == Some(message_id))
+        }) {
+            message.finish_reason = Some(finish_reason);
+        }
+        Ok(())
+    }
+
+    /// Id of the session's newest saved message
+    pub fn last_message_id(&self, session_id: &str) -> Option<i64> {
+        let sessions = self.sessions.read().unwrap();
+        sessions
+            .get(session_id)
+            .and_then(|session| session.messages.last())
+            .and_then(|message| message.id)
+    }
+
+    /// The last message of every session that has one, read without loading the histories
+    pub fn last_messages(&self) -> HashMap<String, crate::db::LastMessage> {
+        self.db.last_messages().unwrap_or_else(|e| {
+            log::error!("Failed to load last messages: {}", e);
+            HashMap::new()
+        })
+    }
+
+    /// Claim an idempotency key for a chat request; see [`Database::claim_idempotency_key`]
+    pub fn claim_idempotency_key(
+        &self,
+        key: &str,
+        session_id: &str,
+        request_id: &str,
+        ttl_seconds: i64,
+    ) -> Result<Option<crate::db::IdempotencyRecord>> {
+        self.db
+            .claim_idempotency_key(key, session_id, request_id, ttl_seconds)
+    }
+
+    /// The request that used an idempotency key within `ttl_seconds`
+    pub fn idempotency_key(
+        &self,
+        key: &str,
+        ttl_seconds: i64,
+    ) -> Option<crate::db::IdempotencyRecord> {
         self.db
-            .get_all_agent_token_stats()
-            .map_err(|e| format!("Failed to get all agent token stats: {}", e))
+            .get_idempotency_key(key, ttl_seconds)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to look up idempotency key: {}", e);
+                None
+            })
+    }
+
+    /// Record the messages a request with an idempotency key saved and the tokens it used
+    pub fn complete_idempotency_key(
+        &self,
+        key: &str,
+        user_message_id: Option<i64>,
+        assistant_message_id: i64,
+        usage: &crate::db::TurnUsage,
+    ) {
+        if let Err(e) =
+            self.db
+                .complete_idempotency_key(key, user_message_id, assistant_message_id, usage)
+        {
+            log::error!("Failed to complete idempotency key: {}", e);
+        }
+    }
+
+    /// Forget the keys of requests a previous server run left streaming
+    pub fn clear_in_flight_idempotency_keys(&self) {
+        match self.db.clear_in_flight_idempotency_keys() {
+            Ok(0) => {}
+            Ok(cleared) => log::info!(
+                "Released {} idempotency key(s) of interrupted requests",
+                cleared
+            ),
+            Err(e) => log::error!("Failed to release interrupted idempotency keys: {}", e),
+        }
+    }
+
+    /// Forget an idempotency key whose request saved no answer
+    pub fn release_idempotency_key(&self, key: &str) {
+        if let Err(e) = self.db.release_idempotency_key(key) {
+            log::error!("Failed to release idempotency key: {}", e);
+        }
+    }
+
+    /// Cached embeddings of `message_ids` made with `model`; see [`Database::message_embeddings`]
+    pub fn message_embeddings(&self, message_ids: &[i64], model: &str) -> HashMap<i64, Vec<f32>> {
+        self.db
+            .message_embeddings(message_ids, model)
+            .unwrap_or_else(|e| {
+                log::error!("Failed to load message embeddings: {}", e);
+                Default::default()
+            })
+    }
+
+    pub fn save_message_embedding(&self, message_id: i64, model: &str, embedding: &[f32]) {
+        if let Err(e) = self.db.save_message_embedding(message_id, model, embedding) {
+            log::error!("Failed to save message embedding: {}", e);
+        }
+    }
+
+    /// Link captured requests to the assistant message they produced
+    pub fn link_request_traces(&self, trace_ids: &[String], message_id: i64) {
+        if let Err(e) = self.db.link_request_traces(trace_ids, message_id) {
+            log::error!("Failed to link request traces: {}", e);
+        }
+    }
+
+    /// A thinking step of a session with its full tool result
+    pub fn get_thinking_step(
+        &self,
+        session_id: &str,
+        step_id: i64,
+    ) -> Result<Option<ThinkingStep>> {
+        self.db.get_thinking_step(session_id, step_id)
+    }
+
+    /// Captured requests of a session, oldest first
+    pub fn list_request_traces(&self, session_id: &str) -> Result<Vec<RequestTrace>> {
+        self.db.list_request_traces(session_id)
+    }
+
+    /// Delete captured requests older than `max_age_seconds`
+    ///
+    /// Returns the number of traces deleted.
+    pub fn cleanup_old_request_traces(&self, max_age_seconds: i64) -> usize {
+        match self.db.cleanup_old_request_traces(max_age_seconds) {
+            Ok(deleted) => deleted,
+            Err(e) => {
+                log::error!("Failed to clean up old request traces: {}", e);
+                0
+            }
+        }
     }
 }
 
@@ -520,6 +1372,7 @@ mod tests {
         let files = vec![FileAttachment {
             filename: "test.txt".to_string(),
             content: "test content".to_string(),
+            for_review: false,
         }];
 
         let sources = manager
@@ -529,13 +1382,143 @@ mod tests {
         assert_eq!(sources.len(), 1);
 
         manager
-            .add_assistant_message(&session_id, "Hi there!".to_string(), sources, None)
+            .add_assistant_message(&session_id, "Hi there!".to_string(), sources, None, None)
             .unwrap();
 
         let session = manager.get_session(&session_id).unwrap();
         assert_eq!(session.messages.len(), 2);
     }
 
+    #[test]
+    fn test_file_attached_twice_is_one_source() {
+        let db = crate::db::Database::new(":memory:").unwrap();
+        let manager = SessionManager::new(db);
+        let session_id = manager.create_session();
+
+        let file = FileAttachment {
+            filename: "notes.md".to_string(),
+            content: "Notes".to_string(),
+            for_review: false,
+        };
+        let sources = manager
+            .add_user_message(&session_id, "Hi".to_string(), vec![file.clone(), file])
+            .unwrap();
+        assert_eq!(sources.len(), 1);
+
+        manager.sessions.write().unwrap().clear();
+        let session = manager.get_session(&session_id).unwrap();
+        assert_eq!(session.messages[0].sources.len(), 1);
+        assert_eq!(
+            session.messages[0].sources[0].origin,
+            SourceOrigin::Attachment
+        );
+    }
+
+    #[test]
+    fn test_rag_chunk_matching_an_attachment_keeps_its_title() {
+        let attachments = vec![Source::new(
+            "notes.md",
+            "Short file",
+            SourceOrigin::Attachment,
+        )];
+        let rag = vec![
+            Source::new("docs/notes.md", "Short file", SourceOrigin::Rag),
+            Source::new("guide.md (chunks 2-4)", "Other text", SourceOrigin::Rag),
+            Source::new("guide.md (chunks 2-4)", "Other text", SourceOrigin::Rag),
+        ];
+
+        let sources = merge_turn_sources(&attachments, &rag);
+        let labels: Vec<_> = sources
+            .iter()
+            .map(|source| (source.title.as_str(), source.origin))
+            .collect();
+        assert_eq!(
+            labels,
+            vec![
+                ("notes.md", SourceOrigin::Attachment),
+                ("guide.md (chunks 2-4)", SourceOrigin::Rag),
+            ]
+        );
+        assert_eq!(serde_json::to_value(&sources[1]).unwrap()["origin"], "rag");
+    }
+
+    #[test]
+    fn test_saved_messages_get_database_ids() {
+        let db = crate::db::Database::new(":memory:").unwrap();
+        let manager = SessionManager::new(db);
+        let session_id = manager.create_session();
+
+        manager
+            .add_user_message(&session_id, "Hello".to_string(), vec![])
+            .unwrap();
+        manager
+            .add_assistant_message(&session_id, "Hi there!".to_string(), vec![], None, None)
+            .unwrap();
+
+        let cached = manager.get_session(&session_id).unwrap();
+        let user_id = cached.messages[0].id.expect("user message id");
+        let assistant_id = cached.messages[1].id.expect("assistant message id");
+        assert_eq!(cached.messages[0].parent_message_id, None);
+        assert_eq!(cached.messages[1].parent_message_id, Some(user_id));
+
+        // The cache matches what a fresh load from the database returns
+        let stored = manager.db.load_session(&session_id).unwrap().unwrap();
+        assert_eq!(stored.messages[0].id, Some(user_id));
+        assert_eq!(stored.messages[1].id, Some(assistant_id));
+        assert_eq!(stored.messages[1].parent_message_id, Some(user_id));
+    }
+
+    #[test]
+    fn test_request_traces_link_to_session_and_message() {
+        let db = crate::db::Database::new(":memory:").unwrap();
+        let manager = SessionManager::new(db);
+        let session_id = manager.create_session();
+        manager
+            .add_user_message(&session_id, "Hello".to_string(), vec![])
+            .unwrap();
+
+        let config = crate::config::Config::default();
+        let trace = RequestTrace::capture(
+            &session_id,
+            &[],
+            &[],
+            &crate::providers::ChatParams {
+                model: "qwen",
+                reasoning: Default::default(),
+                tool_choice: Default::default(),
+            },
+            &config,
+        );
+        manager.save_request_trace(&trace).unwrap();
+        manager.finish_request_trace(
+            &trace.id,
+            &ResponseSummary {
+                stop_reason: Some("end_turn".to_string()),
+                content_chars: 9,
+                ..Default::default()
+            },
+        );
+
+        let message_id = manager
+            .add_assistant_message(&session_id, "Hi there!".to_string(), vec![], None, None)
+            .unwrap();
+        manager.link_request_traces(std::slice::from_ref(&trace.id), message_id);
+
+        let traces = manager.list_request_traces(&session_id).unwrap();
+        assert_eq!(traces.len(), 1);
+        assert_eq!(traces[0].id, trace.id);
+        assert_eq!(traces[0].message_id, Some(message_id));
+        assert_eq!(traces[0].request["params"]["model"], "qwen");
+        assert_eq!(
+            traces[0].response.as_ref().unwrap()["stop_reason"],
+            "end_turn"
+        );
+
+        // Traces go away with their session
+        assert!(manager.delete_session(&session_id));
+        assert!(manager.list_request_traces(&session_id).unwrap().is_empty());
+    }
+
     #[test]
     fn test_delete_session() {
         let db = crate::db::Database::new(":memory:").unwrap();
@@ -567,7 +1550,7 @@ mod tests {
 
         // Add assistant response
         manager
-            .add_assistant_message(&session_id, "First answer".to_string(), vec![], None)
+            .add_assistant_message(&session_id, "First answer".to_string(), vec![], None, None)
             .unwrap();
 
         // Verify both messages exist
@@ -582,7 +1565,7 @@ mod tests {
             .unwrap();
 
         manager
-            .add_assistant_message(&session_id, "Second answer".to_string(), vec![], None)
+            .add_assistant_message(&session_id, "Second answer".to_string(), vec![], None, None)
             .unwrap();
 
         // Verify all 4 messages persist
@@ -609,7 +1592,7 @@ mod tests {
                 .unwrap();
 
             manager
-                .add_assistant_message(&session_id, format!("Answer {}", i), vec![], None)
+                .add_assistant_message(&session_id, format!("Answer {}", i), vec![], None, None)
                 .unwrap();
         }
 
@@ -656,7 +1639,7 @@ mod tests {
             .unwrap();
 
         manager
-            .add_assistant_message(&session_id, "Test response".to_string(), vec![], None)
+            .add_assistant_message(&session_id, "Test response".to_string(), vec![], None, None)
             .unwrap();
 
         // Update token usage multiple times (simulates streaming updates)
@@ -716,6 +1699,7 @@ mod tests {
                 "Rust is a systems programming language".to_string(),
                 vec![],
                 None,
+                None,
             )
             .unwrap();
 
@@ -748,7 +1732,7 @@ mod tests {
             .unwrap();
 
         manager
-            .add_assistant_message(&session_id, "Answer 1".to_string(), vec![], None)
+            .add_assistant_message(&session_id, "Answer 1".to_string(), vec![], None, None)
             .unwrap();
 
         // Get updated timestamp
@@ -842,4 +1826,47 @@ mod tests {
         // Title should be truncated to 100 chars
         assert_eq!(session2.title, Some(format!("{}...", &long_message[..97])));
     }
+
+    #[test]
+    fn test_directory_grants_are_per_session_and_tool() {
+        let db = Database::new(":memory:").unwrap();
+        let manager = SessionManager::new(db);
+        let session_id = manager.create_session();
+        let other_id = manager.create_session();
+
+        manager.allow_directory(&session_id, "read_file", Path::new("/work/src"));
+        assert!(manager.is_directory_allowed(
+            &session_id,
+            "read_file",
+            Path::new("/work/src/main.rs")
+        ));
+        assert!(manager.is_directory_allowed(
+            &session_id,
+            "read_file",
+            Path::new("/work/src/api/mod.rs")
+        ));
+        assert!(!manager.is_directory_allowed(
+            &session_id,
+            "read_file",
+            Path::new("/work/srcs/a.rs")
+        ));
+        assert!(!manager.is_directory_allowed(
+            &session_id,
+            "read_file",
+            Path::new("/work/README.md")
+        ));
+        assert!(!manager.is_directory_allowed(&session_id, "grep", Path::new("/work/src")));
+        assert!(!manager.is_directory_allowed(
+            &other_id,
+            "read_file",
+            Path::new("/work/src/main.rs")
+        ));
+
+        manager.delete_session(&session_id);
+        assert!(!manager.is_directory_allowed(
+            &session_id,
+            "read_file",
+            Path::new("/work/src/main.rs")
+        ));
+    }
 }
@@ -0,0 +1,571 @@
+//! Conversation templates: sessions that start with a system prompt, a message and files
+//!
+//! Templates are configured under `templates`. Starting one fills the `{{name}}` placeholders of
+//! `initial_user_message` from the variables given (then `prompt_vars` and the built-in
+//! variables), creates a session that keeps the template's system prompt for all of its turns,
+//! and attaches the project files matching the template's `auto_include` globs, with those of
+//! `context.auto_include`, to the first message as `auto` sources.
+
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::api;
+use crate::auto_include::{self, AutoIncluded};
+use crate::config::{Config, ContextConfig, TemplateConfig};
+use crate::error::SquidError;
+use crate::llm;
+use crate::session::{self, SessionManager, SourceOrigin};
+use crate::template::TemplateRenderer;
+use crate::validate::PathValidator;
+
+/// A template as listed by `GET /api/templates`
+#[derive(Debug, Serialize)]
+pub struct TemplateInfo {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    pub agent: String,
+    /// Placeholders of the first message that need a value when the template is started
+    pub variables: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub auto_include: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplatesResponse {
+    pub templates: Vec<TemplateInfo>,
+}
+
+/// A template resolved for a new session
+#[derive(Debug)]
+pub struct Prepared {
+    pub agent_id: String,
+    pub system_prompt: Option<String>,
+    /// The first user message with its placeholders filled
+    pub message: String,
+}
+
+/// A session seeded from a template without running it
+#[derive(Debug, Serialize)]
+pub struct Seeded {
+    pub session_id: String,
+    /// Files left out to stay within `context.budget`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+/// Agent that answers sessions started from `template`
+fn agent_id(config: &Config, template: &TemplateConfig) -> String {
+    template
+        .agent
+        .clone()
+        .unwrap_or_else(|| config.agents.default_agent.clone())
+}
+
+/// What `GET /api/templates` shows for `template`
+pub fn info(config: &Config, template: &TemplateConfig) -> TemplateInfo {
+    let agent = agent_id(config, template);
+    let model = config
+        .get_agent(&agent)
+        .map(|agent| agent.model.as_str())
+        .unwrap_or_default();
+    TemplateInfo {
+        name: template.name.clone(),
+        description: template.description.clone(),
+        variables: TemplateRenderer::for_prompt(config, model)
+            .undefined_variables(&template.initial_user_message),
+        agent,
+        auto_include: template.auto_include.clone(),
+    }
+}
+
+/// Resolve the agent, system prompt and first message of `template`
+///
+/// Fails when the agent or bundled prompt doesn't exist, or when `vars` lacks a value for a
+/// placeholder of the message.
+pub fn prepare(
+    config: &Config,
+    template: &TemplateConfig,
+    vars: &HashMap<String, String>,
+) -> Result<Prepared, SquidError> {
+    let agent_id = agent_id(config, template);
+    let agent = config.get_agent(&agent_id).ok_or_else(|| {
+        SquidError::Config(format!(
+            "Template '{}' uses agent '{}', which doesn't exist",
+            template.name, agent_id
+        ))
+    })?;
+
+    let system_prompt = match (&template.system_prompt, &template.prompt_name) {
+        (Some(prompt), _) => Some(prompt.clone()),
+        (None, Some(name)) => {
+            let prompt = llm::bundled_prompt(name).ok_or_else(|| {
+                SquidError::Config(format!(
+                    "Template '{}' uses unknown prompt '{}'; bundled prompts are: {}",
+                    template.name,
+                    name,
+                    llm::BUNDLED_PROMPTS
+                        .iter()
+                        .map(|(name, _)| *name)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            })?;
+            Some(llm::combine_prompts(prompt))
+        }
+        (None, None) => None,
+    };
+
+    let message = TemplateRenderer::for_prompt(config, &agent.model).render_with_vars(
+        &format!("Template '{}'", template.name),
+        &template.initial_user_message,
+        vars,
+    )?;
+    if message.trim().is_empty() {
+        return Err(SquidError::Config(format!(
+            "Template '{}' has an empty initial_user_message",
+            template.name
+        )));
+    }
+
+    Ok(Prepared {
+        agent_id,
+        system_prompt,
+        message,
+    })
+}
+
+/// Files attached to the first message of a new session, from `context.auto_include` and the
+/// `auto_include` globs of the template it started from
+pub fn auto_included(
+    config: &Config,
+    template: Option<&TemplateConfig>,
+    model: &str,
+    context_window: u32,
+) -> AutoIncluded {
+    let Some(template) = template.filter(|template| !template.auto_include.is_empty()) else {
+        return auto_include::load(config, model, context_window);
+    };
+    let settings = ContextConfig {
+        auto_include: config
+            .context
+            .auto_include
+            .iter()
+            .chain(&template.auto_include)
+            .cloned()
+            .collect(),
+        ..config.context.clone()
+    };
+    let root = config.project_root();
+    let validator = PathValidator::cached_for_working_dir(&root, config.security.respect_gitignore);
+    auto_include::load_from(&root, &settings, &validator, model, context_window)
+}
+
+/// Create a session from `template` holding its first message, without answering it
+pub fn seed(
+    config: &Config,
+    session_manager: &SessionManager,
+    template: &TemplateConfig,
+    vars: &HashMap<String, String>,
+) -> Result<Seeded, SquidError> {
+    let prepared = prepare(config, template, vars)?;
+    let agent = config
+        .get_agent(&prepared.agent_id)
+        .ok_or_else(|| SquidError::Config(format!("Agent '{}' not found", prepared.agent_id)))?;
+    let included = auto_included(
+        config,
+        Some(template),
+        &agent.model,
+        config.agent_context_window(agent),
+    );
+
+    let session_id = session_manager.create_template_session(
+        &template.name,
+        &prepared.agent_id,
+        prepared.system_prompt,
+    );
+    let sources = included
+        .files
+        .iter()
+        .map(|(name, content)| session::Source::new(name, content, SourceOrigin::Auto))
+        .collect();
+    session_manager.add_user_message_with_sources(
+        &session_id,
+        prepared.message,
+        Vec::new(),
+        sources,
+    )?;
+    info!(
+        "Started session {} from template '{}'",
+        session_id, template.name
+    );
+    Ok(Seeded {
+        session_id,
+        warnings: included.warnings,
+    })
+}
+
+/// Parse a `name=value` variable given on the command line
+pub fn parse_var(var: &str) -> Result<(String, String), String> {
+    match var.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("Expected NAME=VALUE, got '{}'", var)),
+    }
+}
+
+/// List the configured conversation templates
+pub async fn list_templates(app_config: web::Data<Arc<Config>>) -> HttpResponse {
+    let templates = app_config
+        .templates
+        .iter()
+        .map(|template| info(&app_config, template))
+        .collect();
+    HttpResponse::Ok().json(TemplatesResponse { templates })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FromTemplateRequest {
+    pub template: String,
+    /// Values for the placeholders of the template's first message
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    /// Answer the first message right away, streaming like `/api/chat`
+    #[serde(default)]
+    pub run: bool,
+}
+
+/// Start a session from a template
+///
+/// With `run` the first message is sent like a `/api/chat` request and the answer streamed;
+/// otherwise the seeded session's id is returned.
+pub async fn create_from_template(
+    req: HttpRequest,
+    body: web::Json<FromTemplateRequest>,
+    state: api::ChatState,
+) -> Result<HttpResponse, Error> {
+    let app_config = &state.app_config;
+    let session_manager = &state.session_manager;
+    let Some(template) = app_config.get_template(&body.template) else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Template '{}' not found", body.template)
+        })));
+    };
+
+    if !body.run {
+        return Ok(
+            match seed(app_config, session_manager, template, &body.vars) {
+                Ok(seeded) => {
+                    api::broadcast_session_update_for_session(session_manager, &seeded.session_id);
+                    HttpResponse::Created().json(seeded)
+                }
+                Err(e @ SquidError::Config(_)) => {
+                    HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }))
+                }
+                Err(e) => HttpResponse::InternalServerError()
+                    .json(serde_json::json!({ "error": e.to_string() })),
+            },
+        );
+    }
+
+    // The chat request adds the message and attaches the template's files to the empty session
+    let prepared = match prepare(app_config, template, &body.vars) {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            return Ok(
+                HttpResponse::BadRequest().json(serde_json::json!({ "error": e.to_string() }))
+            );
+        }
+    };
+    let session_id = session_manager.create_template_session(
+        &template.name,
+        &prepared.agent_id,
+        prepared.system_prompt,
+    );
+    let request = api::ChatRequest {
+        message: prepared.message,
+        session_id: Some(session_id),
+        create_if_missing: None,
+        files: Vec::new(),
+        system_prompt: None,
+        agent_id: prepared.agent_id,
+        use_rag: None,
+        use_tools: None,
+        force_reattach: None,
+        debug: None,
+        reasoning: None,
+        tool_choice: None,
+        workspace: None,
+        client_message_id: None,
+    };
+    api::chat_stream(req, web::Json(request), state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+    use crate::rag::RagSystem;
+    use crate::shutdown::Shutdown;
+    use crate::stream_events::StreamEventBuffers;
+    use crate::webhooks;
+    use serde_json::{Value, json};
+
+    fn config(root: &std::path::Path, scenario: &str) -> Config {
+        let mut config = Config {
+            config_dir: Some(root.to_path_buf()),
+            mock_llm: Some(
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                    .join(format!("tests/fixtures/mock/{}.json", scenario)),
+            ),
+            templates: vec![TemplateConfig {
+                name: "bug-triage".to_string(),
+                description: Some("Triage a reported bug".to_string()),
+                system_prompt: Some("You triage bugs for {{project}}.".to_string()),
+                prompt_name: None,
+                initial_user_message: "Triage issue #{{issue}} ({{severity}}).".to_string(),
+                auto_include: vec!["docs/*.md".to_string()],
+                agent: Some("test".to_string()),
+            }],
+            ..Default::default()
+        };
+        config.agents.agents.insert(
+            "test".to_string(),
+            crate::agent::AgentConfig {
+                name: "Test".to_string(),
+                enabled: true,
+                description: String::new(),
+                model: "test-model".to_string(),
+                prompt: Some("You are the test agent.".to_string()),
+                pricing_model: None,
+                context_window: None,
+                permissions: Default::default(),
+                use_tools: false,
+                suggestions: vec![],
+            },
+        );
+        config
+    }
+
+    fn project() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/triage.md"), "Label by severity\n").unwrap();
+        std::fs::write(dir.path().join("docs/notes.txt"), "Not markdown\n").unwrap();
+        dir
+    }
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_placeholders_are_filled_from_the_variables() {
+        let dir = project();
+        let mut config = config(dir.path(), "plain_answer");
+        config
+            .prompt_vars
+            .insert("severity".to_string(), "unknown".to_string());
+        let template = config.templates[0].clone();
+
+        // Given variables win over prompt_vars
+        let prepared = prepare(
+            &config,
+            &template,
+            &vars(&[("issue", "123"), ("severity", "high")]),
+        )
+        .unwrap();
+        assert_eq!(prepared.message, "Triage issue #123 (high).");
+        assert_eq!(prepared.agent_id, "test");
+        assert_eq!(
+            prepared.system_prompt.as_deref(),
+            Some("You triage bugs for {{project}}.")
+        );
+
+        let prepared = prepare(&config, &template, &vars(&[("issue", "7")])).unwrap();
+        assert_eq!(prepared.message, "Triage issue #7 (unknown).");
+
+        let bundled = TemplateConfig {
+            system_prompt: None,
+            prompt_name: Some("review-rust".to_string()),
+            ..template
+        };
+        let prepared = prepare(&config, &bundled, &vars(&[("issue", "7")])).unwrap();
+        assert!(
+            prepared
+                .system_prompt
+                .unwrap()
+                .contains(llm::bundled_prompt("review-rust").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_missing_variables_are_all_reported() {
+        let dir = project();
+        let config = config(dir.path(), "plain_answer");
+        let template = &config.templates[0];
+
+        let error = prepare(&config, template, &HashMap::new())
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Template 'bug-triage'"), "{}", error);
+        assert!(error.contains("issue, severity"), "{}", error);
+        assert_eq!(info(&config, template).variables, ["issue", "severity"]);
+
+        let unknown = TemplateConfig {
+            prompt_name: Some("review-cobol".to_string()),
+            system_prompt: None,
+            ..template.clone()
+        };
+        let error = prepare(
+            &config,
+            &unknown,
+            &vars(&[("issue", "1"), ("severity", "low")]),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("unknown prompt 'review-cobol'"), "{}", error);
+    }
+
+    #[test]
+    fn test_seeded_session_holds_the_message_and_files() {
+        let dir = project();
+        let config = config(dir.path(), "plain_answer");
+        let session_manager = SessionManager::new(Database::new(":memory:").unwrap());
+
+        let seeded = seed(
+            &config,
+            &session_manager,
+            &config.templates[0],
+            &vars(&[("issue", "123"), ("severity", "high")]),
+        )
+        .unwrap();
+
+        let session = session_manager.get_session(&seeded.session_id).unwrap();
+        assert_eq!(session.agent_id.as_deref(), Some("test"));
+        assert_eq!(session.template(), Some("bug-triage"));
+        assert_eq!(
+            session.system_prompt(),
+            Some("You triage bugs for {{project}}.")
+        );
+        assert_eq!(session.messages.len(), 1);
+        let message = &session.messages[0];
+        assert_eq!(message.role, "user");
+        assert_eq!(message.content, "Triage issue #123 (high).");
+        let sources: Vec<(&str, SourceOrigin)> = message
+            .sources
+            .iter()
+            .map(|source| (source.title.as_str(), source.origin))
+            .collect();
+        assert_eq!(sources, [("docs/triage.md", SourceOrigin::Auto)]);
+    }
+
+    #[actix_web::test]
+    async fn test_running_a_template_answers_with_its_system_prompt() {
+        use crate::api::ApprovalStateMap;
+        use actix_web::{App, test as actix_test};
+
+        let dir = project();
+        let mut config = config(dir.path(), "plain_answer");
+        config
+            .prompt_vars
+            .insert("project".to_string(), "squid".to_string());
+        config.debug.capture_requests = true;
+        let session_manager = Arc::new(SessionManager::new(Database::new(":memory:").unwrap()));
+        let approval_map: ApprovalStateMap = Default::default();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(web::Data::new(Arc::new(config)))
+                .app_data(web::Data::new(session_manager.clone()))
+                .app_data(web::Data::new(approval_map))
+                .app_data(web::Data::new(None::<Arc<RagSystem>>))
+                .app_data(web::Data::new(StreamEventBuffers::new()))
+                .app_data(web::Data::new(Shutdown::new()))
+                .app_data(web::Data::new(webhooks::Notifier::new(&Default::default())))
+                .route("/api/templates", web::get().to(list_templates))
+                .route(
+                    "/api/sessions/from-template",
+                    web::post().to(create_from_template),
+                ),
+        )
+        .await;
+
+        let listed: Value = actix_test::call_and_read_body_json(
+            &app,
+            actix_test::TestRequest::get()
+                .uri("/api/templates")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(listed["templates"][0]["name"], "bug-triage");
+        assert_eq!(
+            listed["templates"][0]["variables"],
+            json!(["issue", "severity"])
+        );
+
+        let response = actix_test::call_service(
+            &app,
+            actix_test::TestRequest::post()
+                .uri("/api/sessions/from-template")
+                .set_json(json!({"template": "bug-triage", "vars": {"issue": "1"}}))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(response.status(), 400);
+        assert!(session_manager.list_sessions().is_empty());
+
+        let body = actix_test::call_and_read_body(
+            &app,
+            actix_test::TestRequest::post()
+                .uri("/api/sessions/from-template")
+                .set_json(json!({
+                    "template": "bug-triage",
+                    "vars": {"issue": "9", "severity": "low"},
+                    "run": true,
+                }))
+                .to_request(),
+        )
+        .await;
+        let events: Vec<Value> = String::from_utf8_lossy(&body)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str(data).ok())
+            .collect();
+        assert_eq!(events[0]["type"], "session");
+        assert_eq!(events.last().unwrap()["type"], "done");
+
+        let session_id = events[0]["session_id"].as_str().unwrap();
+        let session = session_manager.get_session(session_id).unwrap();
+        let roles: Vec<&str> = session.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["user", "assistant"]);
+        assert_eq!(session.messages[0].content, "Triage issue #9 (low).");
+        assert_eq!(session.messages[0].sources[0].title, "docs/triage.md");
+
+        // The template's prompt, rendered for the turn, replaced the agent's
+        let traces = session_manager.list_request_traces(session_id).unwrap();
+        let request = traces[0].request.to_string();
+        assert!(
+            request.contains("You triage bugs for squid."),
+            "{}",
+            request
+        );
+        assert!(!request.contains("You are the test agent."));
+    }
+
+    #[test]
+    fn test_var_arguments_need_a_name() {
+        assert_eq!(
+            parse_var("issue=a=b").unwrap(),
+            ("issue".to_string(), "a=b".to_string())
+        );
+        assert!(parse_var("=1").is_err());
+        assert!(parse_var("issue").is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Coordinated shutdown for `squid serve`
+//!
+//! Ctrl+C/SIGTERM flips a watch channel that chat streams and background tasks observe, so they
+//! can finish cleanly (persist partial answers, send a final event) instead of being dropped.
+
+use futures::{Stream, StreamExt};
+use tokio::sync::watch;
+
+/// Shared shutdown flag; clones observe the same signal
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: std::sync::Arc<watch::Sender<bool>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Self {
+            sender: std::sync::Arc::new(sender),
+        }
+    }
+
+    /// Signal every observer to shut down
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Resolve once shutdown has been triggered (immediately if it already was)
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Result of waiting for the next stream item while watching for shutdown
+pub enum Next<T> {
+    Item(T),
+    End,
+    Shutdown,
+}
+
+/// Wait for the next item of `stream`, giving up as soon as shutdown is triggered
+pub async fn next_or_shutdown<S>(stream: &mut S, shutdown: &Shutdown) -> Next<S::Item>
+where
+    S: Stream + Unpin,
+{
+    tokio::select! {
+        biased;
+        _ = shutdown.wait() => Next::Shutdown,
+        item = stream.next() => match item {
+            Some(item) => Next::Item(item),
+            None => Next::End,
+        },
+    }
+}
+
+/// Wait for Ctrl+C or SIGTERM and return the signal's name
+pub async fn wait_for_signal() -> &'static str {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => "SIGINT",
+                    _ = sigterm.recv() => "SIGTERM",
+                }
+            }
+            Err(e) => {
+                log::warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                "SIGINT"
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        "Ctrl+C"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_next_or_shutdown_interrupts_pending_stream() {
+        let shutdown = Shutdown::new();
+        let mut stream = futures::stream::iter(vec![1]).chain(futures::stream::pending());
+
+        assert!(matches!(
+            next_or_shutdown(&mut stream, &shutdown).await,
+            Next::Item(1)
+        ));
+
+        let trigger = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            trigger.trigger();
+        });
+        let next = tokio::time::timeout(
+            Duration::from_secs(5),
+            next_or_shutdown(&mut stream, &shutdown),
+        )
+        .await
+        .expect("shutdown should interrupt the stream");
+        assert!(matches!(next, Next::Shutdown));
+    }
+}
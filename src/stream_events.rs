@@ -0,0 +1,555 @@
+use actix_web::web::Bytes;
+use futures::future::{AbortHandle, Abortable};
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a chat stream may stay silent before a heartbeat is sent
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// SSE comment frame used as a heartbeat (ignored by clients, keeps proxies from timing out)
+pub const HEARTBEAT_FRAME: &str = ": ping\n\n";
+
+/// Maximum number of events kept per stream for replay
+const MAX_BUFFERED_EVENTS: usize = 1000;
+
+/// How long a finished stream's events stay available for reconnecting clients
+const BUFFER_RETENTION: Duration = Duration::from_secs(300);
+
+/// Recent events of one chat stream
+struct EventBuffer {
+    /// Request id of the turn streaming into the buffer
+    turn_id: String,
+    next_seq: u64,
+    events: VecDeque<Value>,
+    finished_at: Option<Instant>,
+    /// Stops the task running the turn, for streams started with [`spawn_recorded`]
+    abort: Option<AbortHandle>,
+}
+
+impl EventBuffer {
+    fn new(turn_id: &str, abort: Option<AbortHandle>) -> Self {
+        Self {
+            turn_id: turn_id.to_string(),
+            next_seq: 1,
+            events: VecDeque::new(),
+            finished_at: None,
+            abort,
+        }
+    }
+}
+
+/// Short-lived, in-memory ring buffers of chat stream events, keyed by session ID
+///
+/// Lets a client that lost its connection fetch the events it missed.
+#[derive(Clone, Default)]
+pub struct StreamEventBuffers {
+    buffers: Arc<Mutex<HashMap<String, EventBuffer>>>,
+}
+
+/// Events recorded after a given sequence number
+#[derive(Debug, serde::Serialize)]
+pub struct MissedEvents {
+    pub session_id: String,
+    pub events: Vec<Value>,
+    pub finished: bool,
+}
+
+impl StreamEventBuffers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start a new buffer for a turn of a session, replacing any previous stream's events
+    fn start(&self, session_id: &str, turn_id: &str, abort: Option<AbortHandle>) {
+        let mut buffers = self.buffers.lock().unwrap();
+        buffers.retain(|_, b| {
+            b.finished_at
+                .is_none_or(|finished| finished.elapsed() < BUFFER_RETENTION)
+        });
+        buffers.insert(session_id.to_string(), EventBuffer::new(turn_id, abort));
+    }
+
+    /// Assign the next sequence number to an event and remember it
+    pub fn record(&self, session_id: &str, mut event: Value) -> Value {
+        let mut buffers = self.buffers.lock().unwrap();
+        let buffer = buffers
+            .entry(session_id.to_string())
+            .or_insert_with(|| EventBuffer::new("", None));
+
+        if let Value::Object(map) = &mut event {
+            map.insert("seq".to_string(), Value::from(buffer.next_seq));
+        }
+        buffer.next_seq += 1;
+
+        buffer.events.push_back(event.clone());
+        if buffer.events.len() > MAX_BUFFERED_EVENTS {
+            buffer.events.pop_front();
+        }
+        event
+    }
+
+    /// Mark a turn's stream as finished so its buffer can expire
+    ///
+    /// A buffer a later turn of the session started is left alone.
+    pub fn finish(&self, session_id: &str, turn_id: &str) {
+        if let Some(buffer) = self.buffers.lock().unwrap().get_mut(session_id)
+            && buffer.turn_id == turn_id
+        {
+            buffer.finished_at = Some(Instant::now());
+            buffer.abort = None;
+        }
+    }
+
+    /// Stop the turn streaming into a session's buffer, returning its request id
+    ///
+    /// The task is dropped at its next await, so the answer is never saved. `None` when no
+    /// turn started with [`spawn_recorded`] is running on the session.
+    pub fn cancel(&self, session_id: &str) -> Option<String> {
+        let buffers = self.buffers.lock().unwrap();
+        let buffer = buffers.get(session_id)?;
+        if buffer.finished_at.is_some() {
+            return None;
+        }
+        buffer.abort.as_ref()?.abort();
+        Some(buffer.turn_id.clone())
+    }
+
+    /// Get events with a sequence number greater than `after_seq`
+    pub fn events_after(&self, session_id: &str, after_seq: u64) -> Option<MissedEvents> {
+        let buffers = self.buffers.lock().unwrap();
+        let buffer = buffers.get(session_id)?;
+        if buffer
+            .finished_at
+            .is_some_and(|finished| finished.elapsed() >= BUFFER_RETENTION)
+        {
+            return None;
+        }
+
+        let events = buffer
+            .events
+            .iter()
+            .filter(|e| e.get("seq").and_then(Value::as_u64).unwrap_or(0) > after_seq)
+            .cloned()
+            .collect();
+
+        Some(MissedEvents {
+            session_id: session_id.to_string(),
+            events,
+            finished: buffer.finished_at.is_some(),
+        })
+    }
+}
+
+/// Marks the stream as finished when it ends or is dropped
+struct FinishGuard {
+    buffers: StreamEventBuffers,
+    session_id: String,
+    turn_id: String,
+}
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        self.buffers.finish(&self.session_id, &self.turn_id);
+    }
+}
+
+/// Add a `seq` number to every `data:` frame and record it for replay in the turn's buffer,
+/// which the caller has started
+///
+/// Events whose JSON is longer than `max_event_bytes` are sent as [`chunked_frames`]; `0` sends
+/// every event whole.
+fn sequence_events<S, E>(
+    stream: S,
+    session_id: String,
+    turn_id: String,
+    buffers: StreamEventBuffers,
+    max_event_bytes: usize,
+) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    async_stream::stream! {
+        let _guard = FinishGuard {
+            buffers: buffers.clone(),
+            session_id: session_id.clone(),
+            turn_id,
+        };
+
+        let mut stream = Box::pin(stream);
+        while let Some(item) = stream.next().await {
+            let frame = match item {
+                Ok(frame) => frame,
+                Err(e) => {
+                    yield Err(e);
+                    continue;
+                }
+            };
+
+            let payload = std::str::from_utf8(&frame)
+                .ok()
+                .and_then(|s| s.strip_prefix("data: "))
+                .and_then(|s| serde_json::from_str::<Value>(s.trim_end()).ok());
+
+            match payload {
+                Some(event) => {
+                    let event = buffers.record(&session_id, event);
+                    let seq = event["seq"].as_u64().unwrap_or_default();
+                    yield Ok(chunked_frames(&event.to_string(), seq, max_event_bytes));
+                }
+                None => yield Ok(frame),
+            }
+        }
+    }
+}
+
+/// Run a chat stream to the end in its own task, recording every event for replay
+///
+/// The returned stream follows the events as they are recorded. Dropping it, as actix does
+/// when the client disconnects, stops the following but not the turn: the task keeps
+/// generating and saves the answer, and a reconnecting client fetches what it missed from
+/// `buffers`. [`StreamEventBuffers::cancel`] stops the task. Every event gets a `seq` number.
+pub fn spawn_recorded<S, E>(
+    stream: S,
+    session_id: String,
+    turn_id: String,
+    buffers: StreamEventBuffers,
+    max_event_bytes: usize,
+) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    let (abort, registration) = AbortHandle::new_pair();
+    buffers.start(&session_id, &turn_id, Some(abort));
+
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut frames = Box::pin(sequence_events(
+        stream,
+        session_id,
+        turn_id,
+        buffers,
+        max_event_bytes,
+    ));
+    let run = async move {
+        while let Some(frame) = frames.next().await {
+            // Nobody may be listening anymore; the turn still runs to the end
+            let _ = sender.send(frame);
+        }
+    };
+    tokio::spawn(Abortable::new(run, registration));
+    tokio_stream::wrappers::UnboundedReceiverStream::new(receiver)
+}
+
+/// SSE frames for one event's JSON, split when its `data:` line is longer than `max_bytes`
+///
+/// Some proxies drop lines over a length limit, which would silently cut a stream carrying a
+/// multi-megabyte tool result. A long event is sent as `chunked` events instead, each line at
+/// most `max_bytes` long once its slice of the JSON is escaped (but holding at least one
+/// character); clients join the `data` of chunks `0..total` sharing an `id` and parse the
+/// result as the original event.
+pub fn chunked_frames(json: &str, id: u64, max_bytes: usize) -> Bytes {
+    if max_bytes == 0 || "data: ".len() + json.len() <= max_bytes {
+        return Bytes::from(format!("data: {}\n\n", json));
+    }
+
+    // The envelope with the widest index and total a split can have
+    let envelope = serde_json::json!({
+        "type": "chunked",
+        "id": id,
+        "index": json.len(),
+        "total": json.len(),
+        "data": "",
+    });
+    let budget = max_bytes.saturating_sub("data: ".len() + envelope.to_string().len());
+
+    // Slices are escaped again inside `data`, so quotes and backslashes count twice
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    for (index, c) in json.char_indices() {
+        let len = crate::text::json_escaped_len(c);
+        if used + len > budget && index > start {
+            parts.push(&json[start..index]);
+            start = index;
+            used = 0;
+        }
+        used += len;
+    }
+    parts.push(&json[start..]);
+
+    let total = parts.len();
+    let mut frames = String::with_capacity(json.len() + total * 64);
+    for (index, data) in parts.into_iter().enumerate() {
+        let chunk = serde_json::json!({
+            "type": "chunked",
+            "id": id,
+            "index": index,
+            "total": total,
+            "data": data,
+        });
+        frames.push_str(&format!("data: {}\n\n", chunk));
+    }
+    Bytes::from(frames)
+}
+
+/// Emit a heartbeat frame whenever the stream is silent for `interval`
+pub fn with_heartbeat<S, E>(stream: S, interval: Duration) -> impl Stream<Item = Result<Bytes, E>>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+{
+    async_stream::stream! {
+        let mut stream = Box::pin(stream);
+        loop {
+            match tokio::time::timeout(interval, stream.next()).await {
+                Ok(Some(item)) => yield item,
+                Ok(None) => break,
+                Err(_) => yield Ok(Bytes::from_static(HEARTBEAT_FRAME.as_bytes())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+
+    fn frame(json: &str) -> Result<Bytes, Infallible> {
+        Ok(Bytes::from(format!("data: {}\n\n", json)))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_emitted_while_stream_is_paused() {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut frames = Box::pin(
+            with_heartbeat(
+                tokio_stream::wrappers::UnboundedReceiverStream::new(rx),
+                HEARTBEAT_INTERVAL,
+            )
+            .map(|f| String::from_utf8(f.unwrap().to_vec()).unwrap()),
+        );
+
+        tx.send(frame(r#"{"type":"session","session_id":"s1"}"#))
+            .unwrap();
+        assert!(frames.next().await.unwrap().contains("session"));
+
+        // Simulate a long tool execution with no output: the paused clock
+        // advances straight to each heartbeat deadline
+        let start = tokio::time::Instant::now();
+        assert_eq!(frames.next().await.unwrap(), HEARTBEAT_FRAME);
+        assert_eq!(start.elapsed(), HEARTBEAT_INTERVAL);
+        assert_eq!(frames.next().await.unwrap(), HEARTBEAT_FRAME);
+        assert_eq!(start.elapsed(), HEARTBEAT_INTERVAL * 2);
+
+        // Output shortly before the deadline resets the timer
+        tokio::time::advance(HEARTBEAT_INTERVAL - Duration::from_secs(1)).await;
+        tx.send(frame(r#"{"type":"content","text":"a"}"#)).unwrap();
+        assert!(frames.next().await.unwrap().contains("content"));
+        let resumed = tokio::time::Instant::now();
+        assert_eq!(frames.next().await.unwrap(), HEARTBEAT_FRAME);
+        assert_eq!(resumed.elapsed(), HEARTBEAT_INTERVAL);
+
+        tx.send(frame(r#"{"type":"done"}"#)).unwrap();
+        drop(tx);
+        assert!(frames.next().await.unwrap().contains("done"));
+        assert!(frames.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_no_heartbeat_when_stream_is_busy() {
+        let mock = futures::stream::iter(vec![
+            frame(r#"{"type":"content","text":"a"}"#),
+            frame(r#"{"type":"done"}"#),
+        ]);
+
+        let start = tokio::time::Instant::now();
+        let frames: Vec<_> = with_heartbeat(mock, HEARTBEAT_INTERVAL).collect().await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_sequence_numbers_and_replay() {
+        let buffers = StreamEventBuffers::new();
+        let mock = futures::stream::iter(vec![
+            frame(r#"{"type":"session","session_id":"s1"}"#),
+            frame(r#"{"type":"content","text":"Hello"}"#),
+            frame(r#"{"type":"done"}"#),
+        ]);
+
+        buffers.start("s1", "t1", None);
+        let frames: Vec<Value> =
+            sequence_events(mock, "s1".to_string(), "t1".to_string(), buffers.clone(), 0)
+                .map(|f| {
+                    let text = String::from_utf8(f.unwrap().to_vec()).unwrap();
+                    serde_json::from_str(text.strip_prefix("data: ").unwrap().trim_end()).unwrap()
+                })
+                .collect()
+                .await;
+
+        let seqs: Vec<u64> = frames.iter().map(|f| f["seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs, vec![1, 2, 3]);
+
+        let missed = buffers.events_after("s1", 1).unwrap();
+        assert!(missed.finished);
+        assert_eq!(missed.events.len(), 2);
+        assert_eq!(missed.events[0]["text"], "Hello");
+        assert_eq!(missed.events[1]["type"], "done");
+
+        assert!(buffers.events_after("unknown", 0).is_none());
+    }
+
+    /// Parse SSE frames the way the Web UI does, joining `chunked` events
+    fn parse_sse(body: &str) -> Vec<Value> {
+        let mut events = Vec::new();
+        let mut chunks: HashMap<u64, Vec<Option<String>>> = HashMap::new();
+        for line in body.split('\n') {
+            let Some(data) = line.strip_prefix("data: ") else {
+                assert!(
+                    line.is_empty() || line.starts_with(':'),
+                    "stray line {:?}",
+                    line
+                );
+                continue;
+            };
+            let event: Value = serde_json::from_str(data).unwrap();
+            if event["type"] != "chunked" {
+                events.push(event);
+                continue;
+            }
+            let total = event["total"].as_u64().unwrap() as usize;
+            let parts = chunks
+                .entry(event["id"].as_u64().unwrap())
+                .or_insert_with(|| vec![None; total]);
+            parts[event["index"].as_u64().unwrap() as usize] =
+                Some(event["data"].as_str().unwrap().to_string());
+            if parts.iter().all(Option::is_some) {
+                let json: String = parts.iter().flatten().map(String::as_str).collect();
+                events.push(serde_json::from_str(&json).unwrap());
+            }
+        }
+        events
+    }
+
+    fn round_trip(event: &Value, max_bytes: usize) -> (String, Value) {
+        let body = chunked_frames(&event.to_string(), 7, max_bytes);
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let mut events = parse_sse(&body);
+        assert_eq!(events.len(), 1);
+        (body, events.remove(0))
+    }
+
+    #[test]
+    fn test_newline_heavy_payloads_stay_on_one_line() {
+        let text = "line one\nline two\r\n\n\n\tindented\n".repeat(200);
+        let event = serde_json::json!({"type": "tool_invocation_completed", "result": text});
+
+        let (body, parsed) = round_trip(&event, 0);
+        assert_eq!(body.matches('\n').count(), 2, "one frame, one blank line");
+        assert_eq!(parsed, event);
+
+        let (body, parsed) = round_trip(&event, 500);
+        assert!(body.lines().filter(|line| !line.is_empty()).count() > 1);
+        assert_eq!(parsed, event);
+    }
+
+    #[test]
+    fn test_unicode_payloads_split_on_character_boundaries() {
+        let text = "héllo wörld 🦑 日本語テキスト e\u{301} ".repeat(100);
+        let event = serde_json::json!({"type": "content", "text": text});
+
+        // Odd sizes land inside multi-byte characters
+        for max_bytes in [1, 3, 5, 97] {
+            let (_, parsed) = round_trip(&event, max_bytes);
+            assert_eq!(parsed, event, "max_bytes {}", max_bytes);
+        }
+    }
+
+    #[test]
+    fn test_oversized_events_are_chunked_within_the_limit() {
+        let content = "fn main() { println!(\"hi\"); }\n".repeat(100_000);
+        let event = serde_json::json!({
+            "type": "tool_invocation_completed",
+            "name": "write_file",
+            "arguments": {"path": "big.rs", "content": content},
+        });
+        let max_bytes = 64 * 1024;
+
+        let (body, parsed) = round_trip(&event, max_bytes);
+        assert_eq!(parsed, event);
+        let lines: Vec<&str> = body.lines().filter(|line| !line.is_empty()).collect();
+        assert!(lines.len() > 40);
+        for line in &lines {
+            assert!(line.len() <= max_bytes, "line of {} bytes", line.len());
+            let frame: Value = serde_json::from_str(line.strip_prefix("data: ").unwrap()).unwrap();
+            assert_eq!(frame["type"], "chunked");
+            assert_eq!(frame["id"], 7);
+        }
+
+        // Quotes and backslashes are escaped twice once sliced, yet lines stay within the limit
+        let quoted = r#"say "\"" and \\ "#.repeat(20_000);
+        let event = serde_json::json!({"type": "content", "text": quoted});
+        let (body, parsed) = round_trip(&event, 4096);
+        assert_eq!(parsed, event);
+        let lines: Vec<&str> = body.lines().filter(|line| !line.is_empty()).collect();
+        assert!(lines.len() > 1);
+        for line in lines {
+            assert!(line.len() <= 4096, "line of {} bytes", line.len());
+        }
+
+        // Small events are sent whole
+        let small = serde_json::json!({"type": "done"});
+        let body = chunked_frames(&small.to_string(), 1, max_bytes);
+        assert_eq!(&body[..], b"data: {\"type\":\"done\"}\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_sequenced_events_are_chunked_and_replayed_whole() {
+        let buffers = StreamEventBuffers::new();
+        let long = "x".repeat(1000);
+        let mock = futures::stream::iter(vec![
+            frame(r#"{"type":"session","session_id":"s1"}"#),
+            frame(&serde_json::json!({"type": "content", "text": long}).to_string()),
+        ]);
+
+        buffers.start("s1", "t1", None);
+        let body: Vec<u8> = sequence_events(
+            mock,
+            "s1".to_string(),
+            "t1".to_string(),
+            buffers.clone(),
+            100,
+        )
+        .map(|f| f.unwrap().to_vec())
+        .collect::<Vec<_>>()
+        .await
+        .concat();
+        let events = parse_sse(&String::from_utf8(body).unwrap());
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1]["seq"], 2);
+        assert_eq!(events[1]["text"], long.as_str());
+
+        let missed = buffers.events_after("s1", 1).unwrap();
+        assert_eq!(missed.events[0]["text"], long.as_str());
+    }
+
+    #[test]
+    fn test_ring_buffer_drops_oldest_events() {
+        let buffers = StreamEventBuffers::new();
+        buffers.start("s1", "t1", None);
+        for i in 0..(MAX_BUFFERED_EVENTS + 5) {
+            buffers.record(
+                "s1",
+                serde_json::json!({ "type": "content", "text": i.to_string() }),
+            );
+        }
+
+        let missed = buffers.events_after("s1", 0).unwrap();
+        assert_eq!(missed.events.len(), MAX_BUFFERED_EVENTS);
+        assert_eq!(missed.events[0]["seq"], 6);
+        assert!(!missed.finished);
+    }
+}
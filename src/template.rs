@@ -17,10 +17,20 @@
 
 use chrono::Local;
 use log::debug;
+use std::collections::HashMap;
 use sysinfo::System;
 use tera::{Context, Tera};
 use uuid::Uuid;
 
+use crate::config::Config;
+use crate::error::SquidError;
+
+/// Written before `{{` to keep it as text, e.g. `\{{ not a variable }}`
+const ESCAPED_OPEN: &str = "\\{{";
+
+/// Most undefined variables collected before giving up
+const MAX_MISSING_VARIABLES: usize = 50;
+
 // Load persona content for template variable
 const PERSONA: &str = include_str!("./assets/persona.md");
 
@@ -43,6 +53,20 @@ impl TemplateRenderer {
         Self { tera, context }
     }
 
+    /// A renderer for system prompts answered by `model`
+    ///
+    /// Adds `{{project_root}}`, `{{model}}` and the config's `prompt_vars`, which take precedence
+    /// over the built-in variables.
+    pub fn for_prompt(config: &Config, model: &str) -> Self {
+        let mut renderer = Self::new();
+        renderer.insert("project_root", &config.project_root().display().to_string());
+        renderer.insert("model", &model);
+        for (name, value) in &config.prompt_vars {
+            renderer.insert(name, value);
+        }
+        renderer
+    }
+
     /// Builds the default context with secure, privacy-safe variables
     fn build_default_context() -> Context {
         let mut context = Context::new();
@@ -108,6 +132,90 @@ impl TemplateRenderer {
         tera.render(&template_name, &self.context)
     }
 
+    /// Render a prompt, failing with every variable it uses that isn't defined
+    ///
+    /// `what` names the prompt in the error, e.g. "System prompt" or "SQUID.md". A `{{` written
+    /// as `\{{` is kept as text.
+    pub fn render_prompt(&self, what: &str, template: &str) -> Result<String, SquidError> {
+        match self.render_checked(what, template, &self.context)? {
+            Ok(rendered) => Ok(rendered),
+            Err(missing) => Err(SquidError::Config(format!(
+                "{} uses undefined variables: {}. Define them under `prompt_vars` in squid.config.json (e.g. `squid config set prompt_vars.{} \"...\"`), or write `\\{{{{` for literal braces",
+                what,
+                missing.join(", "),
+                missing[0]
+            ))),
+        }
+    }
+
+    /// Render a message whose placeholders are filled from `vars` as well as the context
+    ///
+    /// Fails with every placeholder that is in neither, naming `what` in the error.
+    pub fn render_with_vars(
+        &self,
+        what: &str,
+        template: &str,
+        vars: &HashMap<String, String>,
+    ) -> Result<String, SquidError> {
+        let mut context = self.context.clone();
+        for (name, value) in vars {
+            context.insert(name, value);
+        }
+        match self.render_checked(what, template, &context)? {
+            Ok(rendered) => Ok(rendered),
+            Err(missing) => Err(SquidError::Config(format!(
+                "{} needs values for: {}. Pass them as variables (e.g. `--var {}=...`)",
+                what,
+                missing.join(", "),
+                missing[0]
+            ))),
+        }
+    }
+
+    /// Placeholders in `template` the context doesn't define, in order of use
+    pub fn undefined_variables(&self, template: &str) -> Vec<String> {
+        match self.render_checked("Template", template, &self.context) {
+            Ok(Err(missing)) => missing,
+            _ => Vec::new(),
+        }
+    }
+
+    /// Render `template` with `context`, or list the variables it uses that aren't defined
+    fn render_checked(
+        &self,
+        what: &str,
+        template: &str,
+        context: &Context,
+    ) -> Result<std::result::Result<String, Vec<String>>, SquidError> {
+        let template = template.replace(ESCAPED_OPEN, "{% raw %}{{{% endraw %}");
+
+        // Tera stops at the first undefined variable, so define each one found and try again
+        let mut context = context.clone();
+        let mut missing = Vec::new();
+        loop {
+            let error = match self.render_string_with_context(&template, &context) {
+                Ok(rendered) if missing.is_empty() => return Ok(Ok(rendered)),
+                Ok(_) => break,
+                Err(error) => error,
+            };
+            match undefined_variable(&error) {
+                Some(name) if missing.len() < MAX_MISSING_VARIABLES && !missing.contains(&name) => {
+                    context.insert(&name, "");
+                    missing.push(name);
+                }
+                _ if !missing.is_empty() => break,
+                _ => {
+                    return Err(SquidError::Config(format!(
+                        "{} is not a valid template: {}",
+                        what,
+                        error_chain(&error)
+                    )));
+                }
+            }
+        }
+        Ok(Err(missing))
+    }
+
     /// Renders a template string with custom context variables (replaces defaults)
     pub fn render_string_with_context(
         &self,
@@ -123,22 +231,38 @@ impl TemplateRenderer {
         tera.render(&template_name, custom_context)
     }
 
-    /// Gets the current context (useful for debugging)
-    pub fn get_context(&self) -> &Context {
-        &self.context
-    }
-
     /// Adds or updates a context variable
     pub fn insert<T: serde::Serialize>(&mut self, key: &str, value: &T) {
         self.context.insert(key, value);
     }
 
     /// Removes a context variable
+    #[cfg(test)]
     pub fn remove(&mut self, key: &str) {
         self.context.remove(key);
     }
 }
 
+/// The name in a Tera "Variable `name` not found in context" error
+fn undefined_variable(error: &tera::Error) -> Option<String> {
+    let chain = error_chain(error);
+    let rest = &chain[chain.find("Variable `")? + "Variable `".len()..];
+    let (name, rest) = rest.split_once('`')?;
+    rest.starts_with(" not found in context")
+        .then(|| name.to_string())
+}
+
+/// An error with its sources, which is where Tera puts the useful part
+fn error_chain(error: &tera::Error) -> String {
+    let mut messages = vec![error.to_string()];
+    let mut source = std::error::Error::source(error);
+    while let Some(error) = source {
+        messages.push(error.to_string());
+        source = error.source();
+    }
+    messages.join(": ")
+}
+
 impl Default for TemplateRenderer {
     fn default() -> Self {
         Self::new()
@@ -284,6 +408,87 @@ mod tests {
         assert!(!result.contains("{{"));
     }
 
+    #[test]
+    fn test_prompt_variables_and_builtins() {
+        let mut config = Config::default();
+        config
+            .prompt_vars
+            .insert("team".to_string(), "Platform".to_string());
+        config
+            .prompt_vars
+            .insert("test_framework".to_string(), "nextest".to_string());
+        let renderer = TemplateRenderer::for_prompt(&config, "qwen3-coder");
+
+        let result = renderer
+            .render_prompt(
+                "System prompt",
+                "Team {{team}} tests with {{ test_framework }} on {{model}} in {{project_root}}, {{date}}.",
+            )
+            .unwrap();
+        let expected = format!(
+            "Team Platform tests with nextest on qwen3-coder in {}, {}.",
+            config.project_root().display(),
+            Local::now().format("%Y-%m-%d")
+        );
+        assert_eq!(result, expected);
+
+        // Config variables override built-ins
+        config
+            .prompt_vars
+            .insert("model".to_string(), "house model".to_string());
+        let renderer = TemplateRenderer::for_prompt(&config, "qwen3-coder");
+        assert_eq!(
+            renderer
+                .render_prompt("System prompt", "{{model}}")
+                .unwrap(),
+            "house model"
+        );
+    }
+
+    #[test]
+    fn test_escaped_braces_stay_literal() {
+        let renderer = TemplateRenderer::for_prompt(&Config::default(), "m");
+        let result = renderer
+            .render_prompt(
+                "System prompt",
+                "Write \\{{ name }} in Handlebars templates; the model is {{model}}.",
+            )
+            .unwrap();
+        assert_eq!(
+            result,
+            "Write {{ name }} in Handlebars templates; the model is m."
+        );
+    }
+
+    #[test]
+    fn test_undefined_prompt_variables_are_all_listed() {
+        let renderer = TemplateRenderer::for_prompt(&Config::default(), "m");
+        let error = renderer
+            .render_prompt(
+                "Agent prompt",
+                "{{company}} uses {{standards_doc}}; again {{company}} on {{os}}.",
+            )
+            .unwrap_err();
+        assert!(matches!(error, SquidError::Config(_)));
+        let message = error.to_string();
+        assert!(
+            message.starts_with("Agent prompt uses undefined variables: company, standards_doc."),
+            "{}",
+            message
+        );
+        assert!(message.contains("squid config set prompt_vars.company"));
+
+        // Other template mistakes are reported as such
+        let error = renderer
+            .render_prompt("System prompt", "{% if %}")
+            .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("System prompt is not a valid template")
+        );
+    }
+
     #[test]
     fn test_persona_variable() {
         let renderer = TemplateRenderer::new();
@@ -0,0 +1,101 @@
+//! Text helpers for user-provided content
+
+use unicode_segmentation::UnicodeSegmentation;
+
+const ELLIPSIS: &str = "...";
+
+/// Bytes `c` takes inside a JSON string as serde_json writes it
+pub fn json_escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\n' | '\r' | '\t' | '\u{8}' | '\u{c}' => 2,
+        c if (c as u32) < 0x20 => 6,
+        c => c.len_utf8(),
+    }
+}
+
+/// Shorten `text` to at most `max_len` characters, including a trailing "..."
+///
+/// Lengths count grapheme clusters, so multibyte characters, emoji sequences and combining
+/// marks are never split. The cut moves back to the last word boundary when that keeps at
+/// least half of the allowed length.
+pub fn truncate(text: &str, max_len: usize) -> String {
+    if text.graphemes(true).nth(max_len).is_none() {
+        return text.to_string();
+    }
+
+    let budget = max_len.saturating_sub(ELLIPSIS.len());
+    let end = text
+        .grapheme_indices(true)
+        .nth(budget)
+        .map_or(text.len(), |(i, _)| i);
+    let mut kept = &text[..end];
+
+    // Prefer cutting between words, unless that throws away most of the text
+    let cut_mid_word = !text[end..].starts_with(char::is_whitespace);
+    if cut_mid_word
+        && let Some(space) = kept.rfind(char::is_whitespace)
+        && kept[..space].graphemes(true).count() >= budget / 2
+    {
+        kept = &kept[..space];
+    }
+
+    format!("{}{}", kept.trim_end(), ELLIPSIS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn len(text: &str) -> usize {
+        text.graphemes(true).count()
+    }
+
+    #[test]
+    fn test_short_text_is_unchanged() {
+        assert_eq!(truncate("hello", 10), "hello");
+        assert_eq!(truncate("привіт", 6), "привіт");
+        assert_eq!(truncate("", 5), "");
+    }
+
+    #[test]
+    fn test_cuts_at_word_boundary() {
+        assert_eq!(truncate("the quick brown fox jumps", 15), "the quick...");
+        // Exactly at a space: nothing to move back over
+        assert_eq!(truncate("alpha beta gamma", 13), "alpha beta...");
+        // A single long word is cut hard
+        assert_eq!(
+            truncate(&"a".repeat(150), 100),
+            format!("{}...", "a".repeat(97))
+        );
+    }
+
+    #[test]
+    fn test_multibyte_text_straddling_the_limit() {
+        let cyrillic = "Привіт світ, як справи сьогодні у тебе";
+        let emoji = "🦑🦑🦑 squid 👩‍👩‍👧‍👦 family 🇺🇦 flag é́ accents";
+        let cjk = "你好世界你好世界你好世界你好世界";
+
+        for text in [cyrillic, emoji, cjk] {
+            for max_len in 0..=len(text) + 2 {
+                let result = truncate(text, max_len);
+                if len(text) <= max_len {
+                    assert_eq!(result, text);
+                } else {
+                    assert!(result.ends_with(ELLIPSIS), "{:?} at {}", result, max_len);
+                    assert!(len(&result) <= max_len.max(ELLIPSIS.len()));
+                    let kept = result.trim_end_matches(ELLIPSIS);
+                    assert!(text.starts_with(kept), "{:?} at {}", result, max_len);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_grapheme_clusters_stay_whole() {
+        // Family emoji is one grapheme made of several code points
+        let family = "👩‍👩‍👧‍👦";
+        let text = format!("{}{}", family.repeat(5), "x".repeat(10));
+        let result = truncate(&text, 6);
+        assert_eq!(result, format!("{}...", family.repeat(3)));
+    }
+}
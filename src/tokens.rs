@@ -5,6 +5,7 @@
 
 use async_openai::types::chat::ChatCompletionRequestMessage;
 use log::debug;
+use serde::{Deserialize, Serialize};
 
 /// Convert async_openai message to text for token counting
 ///
@@ -61,7 +62,7 @@ fn message_to_text(msg: &ChatCompletionRequestMessage) -> String {
 /// A tuple of (input_tokens, output_tokens). Output tokens is always 0 for this function
 /// since it only counts the input context.
 pub fn estimate_tokens(model: &str, messages: &[ChatCompletionRequestMessage]) -> (i64, i64) {
-    match tiktoken_rs::get_bpe_from_model(model) {
+    match tiktoken_rs::bpe_for_model(model) {
         Ok(bpe) => {
             let mut total_tokens = 0;
 
@@ -111,7 +112,7 @@ pub fn estimate_tokens(model: &str, messages: &[ChatCompletionRequestMessage]) -
 ///
 /// The estimated number of tokens
 pub fn estimate_message_tokens(model: &str, content: &str) -> i64 {
-    match tiktoken_rs::get_bpe_from_model(model) {
+    match tiktoken_rs::bpe_for_model(model) {
         Ok(bpe) => {
             let tokens = bpe.encode_with_special_tokens(content);
             tokens.len() as i64
@@ -152,6 +153,113 @@ fn estimate_tokens_fallback(messages: &[ChatCompletionRequestMessage]) -> (i64,
     (estimated_tokens, 0)
 }
 
+/// Estimated tokens of one part of a request's context
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextCategory {
+    pub name: String,
+    pub tokens: i64,
+}
+
+/// Tallies what a request's context is made of while its messages are assembled
+///
+/// Parts are estimated as they're added, with the tokenizer [`estimate_tokens`] uses. What the
+/// parts don't carry (message framing, JSON escaping, reply priming) ends up in `formatting`,
+/// so the categories add up to the request's estimate.
+pub struct ContextTally {
+    bpe: Option<&'static tiktoken_rs::CoreBPE>,
+    categories: Vec<ContextCategory>,
+    /// Tokens sent beside the messages, like tool definitions
+    beside_messages: i64,
+}
+
+impl ContextTally {
+    pub fn new(model: &str) -> Self {
+        Self {
+            bpe: tiktoken_rs::bpe_for_model(model).ok(),
+            categories: Vec::new(),
+            beside_messages: 0,
+        }
+    }
+
+    fn count(&self, text: &str) -> i64 {
+        match &self.bpe {
+            Some(bpe) => bpe.encode_with_special_tokens(text).len() as i64,
+            None => (text.len() / 4) as i64,
+        }
+    }
+
+    fn add_tokens(&mut self, name: &str, tokens: i64) {
+        if tokens == 0 {
+            return;
+        }
+        match self.categories.iter_mut().find(|c| c.name == name) {
+            Some(category) => category.tokens += tokens,
+            None => self.categories.push(ContextCategory {
+                name: name.to_string(),
+                tokens,
+            }),
+        }
+    }
+
+    /// Count `text`, part of a message, under `name`
+    pub fn add(&mut self, name: &str, text: &str) {
+        let tokens = self.count(text);
+        self.add_tokens(name, tokens);
+    }
+
+    /// Count `text`, sent beside the messages, under `name`
+    pub fn add_beside_messages(&mut self, name: &str, text: &str) {
+        let tokens = self.count(text);
+        self.beside_messages += tokens;
+        self.add_tokens(name, tokens);
+    }
+
+    /// Count whole messages under `name`, as [`estimate_tokens`] counts them
+    pub fn add_messages(&mut self, name: &str, messages: &[ChatCompletionRequestMessage]) {
+        let tokens = messages
+            .iter()
+            .map(|msg| 3 + self.count(&message_to_text(msg)))
+            .sum();
+        self.add_tokens(name, tokens);
+    }
+
+    /// The categories of a request sending `messages`, with `formatting` as the rest of its estimate
+    pub fn finish(mut self, messages: &[ChatCompletionRequestMessage]) -> Vec<ContextCategory> {
+        let total = match &self.bpe {
+            Some(_) => {
+                messages
+                    .iter()
+                    .map(|msg| 3 + self.count(&message_to_text(msg)))
+                    .sum::<i64>()
+                    + 3
+            }
+            None => estimate_tokens_fallback(messages).0,
+        } + self.beside_messages;
+        let counted: i64 = self.categories.iter().map(|c| c.tokens).sum();
+        self.add_tokens("formatting", (total - counted).max(0));
+        self.categories
+    }
+}
+
+/// The categories as an aligned table with each one's share, largest first
+pub fn format_breakdown(categories: &[ContextCategory]) -> String {
+    let total: i64 = categories.iter().map(|c| c.tokens).sum();
+    let mut sorted: Vec<&ContextCategory> = categories.iter().collect();
+    sorted.sort_by_key(|c| std::cmp::Reverse(c.tokens));
+    let width = sorted.iter().map(|c| c.name.len()).max().unwrap_or(0);
+    let mut table = String::new();
+    for category in sorted {
+        table.push_str(&format!(
+            "  {:<width$}  {:>7}  {:>3}%\n",
+            category.name,
+            category.tokens,
+            (category.tokens * 100 + total / 2) / total.max(1),
+        ));
+    }
+    table.push_str(&format!("  {:<width$}  {:>7}\n", "total", total));
+    table
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -293,4 +401,78 @@ mod tests {
         assert!(gpt4o_tokens > 0);
         // They might be slightly different due to different encodings
     }
+
+    fn breakdown_messages() -> Vec<ChatCompletionRequestMessage> {
+        vec![
+            ChatCompletionRequestMessage::System(ChatCompletionRequestSystemMessage {
+                content: "You are a helpful assistant.\n\n# Project\nUse \"tabs\".".into(),
+                name: None,
+            }),
+            ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                content: ChatCompletionRequestUserMessageContent::Text("Earlier".to_string()),
+                name: None,
+            }),
+            ChatCompletionRequestMessage::User(ChatCompletionRequestUserMessage {
+                content: ChatCompletionRequestUserMessageContent::Text(
+                    "```main.rs\nfn main() {}\n```\nUser query: What does it do?".to_string(),
+                ),
+                name: None,
+            }),
+        ]
+    }
+
+    #[test]
+    fn test_context_breakdown_adds_up_to_the_estimate() {
+        let messages = breakdown_messages();
+        let tools = r#"[{"type":"function","function":{"name":"now"}}]"#;
+        for model in ["gpt-4", "some-local-model"] {
+            let mut tally = ContextTally::new(model);
+            tally.add("system_prompt", "You are a helpful assistant.");
+            tally.add("project_instructions", "\n\n# Project\nUse \"tabs\".");
+            tally.add_messages("history", &messages[1..2]);
+            tally.add("attachments", "```main.rs\nfn main() {}\n```\n");
+            tally.add("question", "User query: What does it do?");
+            tally.add_beside_messages("tools", tools);
+            let categories = tally.finish(&messages);
+
+            let total = estimate_tokens(model, &messages).0 + ContextTally::new(model).count(tools);
+            let sum: i64 = categories.iter().map(|c| c.tokens).sum();
+            // Each part is tokenized on its own, so boundaries may shift a token or two
+            assert!(
+                (sum - total).abs() <= categories.len() as i64,
+                "{} vs {}",
+                sum,
+                total
+            );
+
+            let names: Vec<&str> = categories.iter().map(|c| c.name.as_str()).collect();
+            assert_eq!(
+                names,
+                [
+                    "system_prompt",
+                    "project_instructions",
+                    "history",
+                    "attachments",
+                    "question",
+                    "tools",
+                    "formatting"
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn test_empty_parts_are_left_out_and_repeats_merge() {
+        let mut tally = ContextTally::new("gpt-4");
+        tally.add("environment", "");
+        tally.add("attachments", "one file");
+        tally.add("attachments", "another file");
+        let categories = tally.finish(&[]);
+        assert_eq!(categories[0].name, "attachments");
+        assert!(categories.iter().all(|c| c.name != "environment"));
+
+        let table = format_breakdown(&categories);
+        assert!(table.contains("attachments"));
+        assert!(table.lines().last().unwrap().contains("total"));
+    }
 }
@@ -4,145 +4,372 @@ use console::style;
 use inquire::Select;
 use log::{debug, error, info, warn};
 use regex::Regex;
+use serde::Serialize;
 use serde_json::json;
-use std::process::{Command, Stdio};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::time::timeout;
 use walkdir::WalkDir;
 
-use crate::config::Config;
+use crate::config::{BashConfig, Config, PermissionChange, SandboxMode};
+use crate::error::SquidError;
+use crate::policy::{self, PolicyDecision, SkipStatus};
+use crate::rag::RagSystem;
+use crate::session::{Source, SourceOrigin};
 use crate::validate::PathValidator;
 
-/// Get the list of available tools for the LLM
-pub fn get_tools() -> Vec<ChatCompletionTools> {
-    let mut tools = vec![
+/// What a tool can do to the machine, shown with approval prompts so they can be told apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskLevel {
+    ReadOnly,
+    WritesFiles,
+    ExecutesCommands,
+    Network,
+}
+
+impl RiskLevel {
+    /// Risk of a plugin tool, from the access its `plugin.json` declares
+    pub fn for_plugin(name: &str) -> Self {
+        match crate::plugins::get_plugin_metadata(name) {
+            Some(plugin) if plugin.security.network => RiskLevel::Network,
+            Some(plugin) if plugin.security.file_write => RiskLevel::WritesFiles,
+            _ => RiskLevel::ReadOnly,
+        }
+    }
+}
+
+/// How a call is handled when the agent's allow list doesn't mention the tool
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultPermission {
+    Deny,
+    /// Ask the user, like a plugin that isn't allowed explicitly
+    Ask,
+}
+
+/// Everything squid knows about a built-in tool
+pub struct ToolSpec {
+    pub name: &'static str,
+    /// Sent to the model with the parameter schema
+    pub description: &'static str,
+    /// Short description shown to the user
+    pub summary: &'static str,
+    pub risk: RiskLevel,
+    pub default_permission: DefaultPermission,
+    /// What approving the call allows, completing "Can I ...?"
+    pub action: &'static str,
+    /// `(label, argument)` pairs listed under the approval question when present
+    pub details: &'static [(&'static str, &'static str)],
+    parameters: fn() -> serde_json::Value,
+}
+
+impl ToolSpec {
+    /// JSON schema of the tool's arguments
+    pub fn parameters(&self) -> serde_json::Value {
+        (self.parameters)()
+    }
+
+    /// OpenAI function definition sent to the model
+    pub fn schema(&self) -> ChatCompletionTools {
         ChatCompletionTools::Function(ChatCompletionTool {
             function: FunctionObjectArgs::default()
-                .name("read_file")
-                .description("Read the contents of a file from the filesystem")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "path": {
-                            "type": "string",
-                            "description": "The path to the file to read"
-                        }
-                    },
-                    "required": ["path"]
-                }))
+                .name(self.name)
+                .description(self.description)
+                .parameters(self.parameters())
                 .build()
-                .expect("Failed to build read_file function"),
-        }),
-        ChatCompletionTools::Function(ChatCompletionTool {
-            function: FunctionObjectArgs::default()
-                .name("write_file")
-                .description("Write content to a file on the filesystem")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "path": {
-                            "type": "string",
-                            "description": "The path where the file should be written"
-                        },
-                        "content": {
-                            "type": "string",
-                            "description": "The content to write to the file"
-                        }
+                .unwrap_or_else(|e| panic!("Failed to build {} function: {}", self.name, e)),
+        })
+    }
+}
+
+/// The built-in tools; adding a tool is one entry here plus its implementation
+pub struct ToolRegistry {
+    tools: &'static [ToolSpec],
+}
+
+static REGISTRY: ToolRegistry = ToolRegistry {
+    tools: BUILTIN_TOOLS,
+};
+
+impl ToolRegistry {
+    pub fn builtin() -> &'static ToolRegistry {
+        &REGISTRY
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'static ToolSpec> {
+        self.tools.iter().find(|tool| tool.name == name)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'static, ToolSpec> {
+        self.tools.iter()
+    }
+
+    /// Function definitions of the built-in tools, in registry order
+    pub fn schemas(&self) -> Vec<ChatCompletionTools> {
+        self.iter().map(ToolSpec::schema).collect()
+    }
+}
+
+const BUILTIN_TOOLS: &[ToolSpec] = &[
+    ToolSpec {
+        name: "read_file",
+        description: "Read the contents of a file from the filesystem",
+        summary: "Read the contents of a file from the filesystem",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Deny,
+        action: "read this file",
+        details: &[("📄 File", "path")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "The path to the file to read"
+                    }
+                },
+                "required": ["path"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "write_file",
+        description: "Write content to a file on the filesystem",
+        summary: "Write content to a file on the filesystem",
+        risk: RiskLevel::WritesFiles,
+        default_permission: DefaultPermission::Deny,
+        action: "write to this file",
+        details: &[("📄 File", "path")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "The path where the file should be written"
                     },
-                    "required": ["path", "content"]
-                }))
-                .build()
-                .expect("Failed to build write_file function"),
-        }),
-        ChatCompletionTools::Function(ChatCompletionTool {
-            function: FunctionObjectArgs::default()
-                .name("grep")
-                .description("Search for a pattern in files using regex. Searches recursively from a given directory or in a specific file.")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "pattern": {
-                            "type": "string",
-                            "description": "The regex pattern to search for"
-                        },
-                        "path": {
-                            "type": "string",
-                            "description": "The file or directory path to search in. If a directory, searches recursively."
-                        },
-                        "case_sensitive": {
-                            "type": "boolean",
-                            "description": "Whether the search should be case-sensitive (default: false)"
-                        },
-                        "max_results": {
-                            "type": "integer",
-                            "description": "Maximum number of results to return (default: 50)"
-                        }
+                    "content": {
+                        "type": "string",
+                        "description": "The content to write to the file"
+                    }
+                },
+                "required": ["path", "content"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "apply_patch",
+        description: "Apply a unified diff to one or more files. Prefer this over write_file for targeted edits. Each file needs '--- a/path' and '+++ b/path' headers (use /dev/null to create or delete a file) followed by @@ hunks with a few lines of context. Hunks are matched by their context even if line numbers drifted; a file is only changed when all of its hunks apply, and rejected hunks are reported.",
+        summary: "Apply a unified diff to one or more files",
+        risk: RiskLevel::WritesFiles,
+        default_permission: DefaultPermission::Deny,
+        action: "apply this patch",
+        details: &[],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "patch": {
+                        "type": "string",
+                        "description": "The unified diff to apply"
                     },
-                    "required": ["pattern", "path"]
-                }))
-                .build()
-                .expect("Failed to build grep function"),
-        }),
-
-        ChatCompletionTools::Function(ChatCompletionTool {
-            function: FunctionObjectArgs::default()
-                .name("bash")
-                .description("Execute a bash command. Only use this for safe, non-destructive commands like ls, git status, cat, etc. Dangerous commands (rm, sudo, chmod, dd, curl, wget, kill) are automatically blocked.")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "command": {
-                            "type": "string",
-                            "description": "The bash command to execute (e.g., 'ls -la', 'git status', 'cat file.txt')"
-                        },
-                        "timeout": {
-                            "type": "integer",
-                            "description": "Maximum execution time in seconds (default: 10, max: 60)",
-                            "minimum": 1,
-                            "maximum": 60
-                        }
+                    "dry_run": {
+                        "type": "boolean",
+                        "description": "Report what would change without writing any files (default: false)"
+                    }
+                },
+                "required": ["patch"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "grep",
+        description: "Search for a pattern in files using regex. Searches recursively from a given directory or in a specific file.",
+        summary: "Search for a pattern in files using regex",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Deny,
+        action: "search for this pattern",
+        details: &[("🔍 Pattern", "pattern"), ("📂 Path", "path")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "The regex pattern to search for"
                     },
-                    "required": ["command"]
-                }))
-                .build()
-                .expect("Failed to build bash function"),
-        }),
-        ChatCompletionTools::Function(ChatCompletionTool {
-            function: FunctionObjectArgs::default()
-                .name("now")
-                .description("Get the current date and time. Returns the current timestamp in RFC 3339 format along with timezone information.")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {},
-                    "required": []
-                }))
-                .build()
-                .expect("Failed to build now function"),
-        }),
-        ChatCompletionTools::Function(ChatCompletionTool {
-            function: FunctionObjectArgs::default()
-                .name("demo_tool")
-                .description("A demo tool for testing the approval workflow. Returns a simple message with the provided input. This tool is safe and only used for testing - it doesn't modify anything.")
-                .parameters(json!({
-                    "type": "object",
-                    "properties": {
-                        "message": {
-                            "type": "string",
-                            "description": "A message to echo back"
-                        },
-                        "delay_seconds": {
-                            "type": "integer",
-                            "description": "Optional delay in seconds before returning (default: 0, max: 5)",
-                            "minimum": 0,
-                            "maximum": 5
-                        }
+                    "path": {
+                        "type": "string",
+                        "description": "The file or directory path to search in. If a directory, searches recursively."
                     },
-                    "required": ["message"]
-                }))
-                .build()
-                .expect("Failed to build demo_tool function"),
-        }),
-    ];
+                    "case_sensitive": {
+                        "type": "boolean",
+                        "description": "Whether the search should be case-sensitive (default: false)"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of results to return (default: 50, at most 200 unless configured otherwise)"
+                    }
+                },
+                "required": ["pattern", "path"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "find_files",
+        description: "Find files in the workspace by fuzzy name matching. Every word of the query must appear in order in the file path, e.g. 'sess mgr' finds 'src/session_manager.rs'. Returns the best matches with their sizes, best first. Use this to locate a file before reading it.",
+        summary: "Find files in the workspace by fuzzy name matching",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Deny,
+        action: "look for files",
+        details: &[("🔍 Query", "query")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "Part of the file name or path, or a fuzzy pattern like 'sess mgr'"
+                    },
+                    "extension": {
+                        "type": "string",
+                        "description": "Only return files with this extension, e.g. 'rs' or '.ts'"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of files to return (default: 20, at most 200 unless configured otherwise)"
+                    }
+                },
+                "required": ["query"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "search_docs",
+        description: "Search the indexed documents (the RAG index) for passages relevant to a query. Returns the best matching chunks with their file names and relevance scores, best first. Use this when the question may be answered by the project's documentation.",
+        summary: "Search the indexed documents for relevant passages",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Deny,
+        action: "search the documents",
+        details: &[("🔍 Query", "query")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "query": {
+                        "type": "string",
+                        "description": "What to look for, in natural language"
+                    },
+                    "top_k": {
+                        "type": "integer",
+                        "description": "Number of chunks to return (default: the configured rag.top_k, max: 20)",
+                        "minimum": 1,
+                        "maximum": 20
+                    }
+                },
+                "required": ["query"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "bash",
+        description: "Execute a bash command. Only use this for safe, non-destructive commands like ls, git status, cat, etc. Dangerous commands (rm, sudo, chmod, dd, curl, wget, kill) are automatically blocked.",
+        summary: "Execute a bash command (safe, read-only commands only)",
+        risk: RiskLevel::ExecutesCommands,
+        default_permission: DefaultPermission::Deny,
+        action: "execute this bash command",
+        details: &[
+            ("💻 Command", "command"),
+            ("📂 Directory", "cwd"),
+            ("⏱️  Timeout (seconds)", "timeout"),
+        ],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "The bash command to execute (e.g., 'ls -la', 'git status', 'cat file.txt')"
+                    },
+                    "timeout": {
+                        "type": "integer",
+                        "description": "Maximum execution time in seconds (default: 10, max: 60)",
+                        "minimum": 1,
+                        "maximum": 60
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Directory to run the command in, relative to the working directory (default: the working directory)"
+                    }
+                },
+                "required": ["command"]
+            })
+        },
+    },
+    ToolSpec {
+        name: "now",
+        description: "Get the current date and time, optionally in another timezone, shifted by an offset, or with a custom format. Use the offset instead of doing date arithmetic yourself. Returns the date in RFC 3339 format, the formatted string, the timezone and the Unix timestamp.",
+        summary: "Get the current date and time, optionally in a timezone, shifted by an offset, or formatted",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Deny,
+        action: "check the current time",
+        details: &[("🌍 Timezone", "timezone")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "timezone": {
+                        "type": "string",
+                        "description": "IANA timezone name, e.g. 'Europe/Kyiv' or 'America/New_York'. Defaults to the local timezone"
+                    },
+                    "format": {
+                        "type": "string",
+                        "description": "strftime format string, e.g. '%A, %Y-%m-%d %H:%M'. Defaults to RFC 3339"
+                    },
+                    "offset": {
+                        "type": "string",
+                        "description": "Offset applied before formatting, e.g. '+3d', '-2h', '+1w2d'. Units: s, m, h, d, w. Days and weeks keep the wall-clock time across DST changes"
+                    }
+                },
+                "required": []
+            })
+        },
+    },
+    ToolSpec {
+        name: "demo_tool",
+        description: "A demo tool for testing the approval workflow. Returns a simple message with the provided input. This tool is safe and only used for testing - it doesn't modify anything.",
+        summary: "A demo tool for testing the approval workflow (safe, read-only)",
+        risk: RiskLevel::ReadOnly,
+        default_permission: DefaultPermission::Ask,
+        action: "run the demo tool",
+        details: &[("💬 Message", "message")],
+        parameters: || {
+            json!({
+                "type": "object",
+                "properties": {
+                    "message": {
+                        "type": "string",
+                        "description": "A message to echo back"
+                    },
+                    "delay_seconds": {
+                        "type": "integer",
+                        "description": "Optional delay in seconds before returning (default: 0, max: 5)",
+                        "minimum": 0,
+                        "maximum": 5
+                    }
+                },
+                "required": ["message"]
+            })
+        },
+    },
+];
+
+/// Get the list of available tools for the LLM
+pub fn get_tools() -> Vec<ChatCompletionTools> {
+    let mut tools = ToolRegistry::builtin().schemas();
 
     // Add dynamically loaded plugin tools
     if let Ok(plugin_tools) = crate::plugins::get_plugin_tools() {
@@ -152,13 +379,239 @@ pub fn get_tools() -> Vec<ChatCompletionTools> {
     tools
 }
 
+/// Human-readable description of a tool, for approval prompts
+pub fn tool_description(name: &str) -> String {
+    if let Some(tool) = ToolRegistry::builtin().get(name) {
+        return tool.summary.to_string();
+    }
+    match crate::plugins::get_plugin_metadata(name) {
+        Some(plugin) => plugin.description,
+        None => format!("Execute tool: {}", name),
+    }
+}
+
+/// Risk level of a built-in or plugin tool; unknown tools are assumed to run commands
+pub fn tool_risk(name: &str) -> RiskLevel {
+    match ToolRegistry::builtin().get(name) {
+        Some(tool) => tool.risk,
+        None if name.starts_with("plugin:") => RiskLevel::for_plugin(name),
+        None => RiskLevel::ExecutesCommands,
+    }
+}
+
+/// Directory an "allow for this directory" approval of a read would cover
+///
+/// `read_file` offers the file's directory and `grep` the directory it searches. Other tools
+/// can't be allowed by directory.
+pub fn grant_directory(name: &str, path: &Path) -> Option<PathBuf> {
+    match name {
+        "grep" if path.is_dir() => Some(path.to_path_buf()),
+        "read_file" | "grep" => path.parent().map(Path::to_path_buf),
+        _ => None,
+    }
+}
+
+/// Directories allowed for reads during this CLI run, as (tool, directory) pairs
+static CLI_DIRECTORY_GRANTS: std::sync::Mutex<Vec<(String, PathBuf)>> =
+    std::sync::Mutex::new(Vec::new());
+
+fn cli_directory_allowed(name: &str, path: &Path) -> bool {
+    CLI_DIRECTORY_GRANTS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|(tool, dir)| tool == name && path.starts_with(dir))
+}
+
+/// One-line CLI notice for a tool call that didn't run
+fn print_skip_notice(name: &str, status: SkipStatus, detail: &str) {
+    eprintln!(
+        "{} {} skipped ({}): {}",
+        style("⊘").yellow(),
+        style(name).cyan(),
+        status,
+        detail
+    );
+}
+
+/// The CLI approval question for a call, with the arguments the user should check
+fn approval_question(name: &str, args: &serde_json::Value) -> String {
+    let Some(tool) = ToolRegistry::builtin().get(name) else {
+        return format!("Can I execute: {}?", style(name).yellow());
+    };
+    let mut message = format!("Can I {}?", style(tool.action).yellow());
+    for (label, argument) in tool.details {
+        let value = match &args[*argument] {
+            serde_json::Value::Null => continue,
+            serde_json::Value::String(value) => value.clone(),
+            value => value.to_string(),
+        };
+        message.push_str(&format!("\n  {}: {}", label, style(value).green()));
+    }
+    message
+}
+
+/// Added to the system prompt when tools were requested but the model can't call them
+const TOOLS_UNAVAILABLE_NOTE: &str = "Tools are not available with this model. \
+Do not try to call functions; answer from the conversation and any attached files.";
+
+/// Tool definitions to send with a request to `model_id`
+///
+/// Empty when tools weren't `requested` or the model doesn't support function calling. In the
+/// latter case `system_message` gets a note so the model doesn't try to call them anyway.
+pub fn tools_for_model(
+    model_id: &str,
+    requested: bool,
+    system_message: &mut String,
+) -> Vec<ChatCompletionTools> {
+    if !requested {
+        return Vec::new();
+    }
+    if !crate::models::supports_tools(model_id) {
+        info!("Model '{}' doesn't support tools, sending none", model_id);
+        system_message.push_str("\n\n");
+        system_message.push_str(TOOLS_UNAVAILABLE_NOTE);
+        return Vec::new();
+    }
+    get_tools()
+}
+
+/// Drop `search_docs` from `tools` when there is no document index to search
+pub fn retain_doc_search(tools: &mut Vec<ChatCompletionTools>, available: bool) {
+    if !available {
+        tools.retain(
+            |tool| !matches!(tool, ChatCompletionTools::Function(t) if t.function.name == "search_docs"),
+        );
+    }
+}
+
+/// Append documentation of the tools being sent to `system_message`
+///
+/// Generated from the same definitions as the request's tool schema, so the prompt never
+/// describes a tool the model can't call. Extra prose comes from `tools.notes_file`.
+pub fn append_tool_docs(
+    system_message: &mut String,
+    tools: &[ChatCompletionTools],
+    config: &Config,
+) {
+    if tools.is_empty() {
+        return;
+    }
+    let notes = config
+        .tools
+        .notes_file
+        .as_deref()
+        .map(|file| load_tool_notes(&config.resolve_path(file)))
+        .unwrap_or_default();
+    system_message.push_str("\n\n");
+    system_message.push_str(&tool_docs(tools, &notes));
+}
+
+/// Markdown section describing `tools`, with each tool's entry in `notes` appended to it
+pub fn tool_docs(
+    tools: &[ChatCompletionTools],
+    notes: &std::collections::HashMap<String, String>,
+) -> String {
+    let mut docs = String::from(
+        "## Available Tools\n\nOnly these tools can be called, with the parameters listed.\n",
+    );
+    for tool in tools {
+        let ChatCompletionTools::Function(tool) = tool else {
+            continue;
+        };
+        let function = &tool.function;
+        docs.push_str(&format!("\n### `{}`\n\n", function.name));
+        if let Some(description) = function.description.as_deref() {
+            docs.push_str(description);
+            docs.push('\n');
+        }
+
+        let parameters = function.parameters.as_ref();
+        let required: Vec<&str> = parameters
+            .and_then(|p| p["required"].as_array())
+            .map(|names| names.iter().filter_map(|name| name.as_str()).collect())
+            .unwrap_or_default();
+        if let Some(properties) = parameters.and_then(|p| p["properties"].as_object())
+            && !properties.is_empty()
+        {
+            docs.push_str("\nParameters:\n");
+            for (name, schema) in properties {
+                docs.push_str(&format!(
+                    "- `{}` ({}, {})",
+                    name,
+                    parameter_type(schema),
+                    if required.contains(&name.as_str()) {
+                        "required"
+                    } else {
+                        "optional"
+                    }
+                ));
+                if let Some(description) = schema["description"].as_str() {
+                    docs.push_str(&format!(": {}", description));
+                }
+                docs.push('\n');
+            }
+        }
+
+        if let Some(note) = notes.get(&function.name) {
+            docs.push('\n');
+            docs.push_str(note);
+            docs.push('\n');
+        }
+    }
+    docs
+}
+
+/// Short type of a JSON schema property, e.g. `string` or `array of string`
+fn parameter_type(schema: &serde_json::Value) -> String {
+    match schema["type"].as_str() {
+        Some("array") => match schema["items"]["type"].as_str() {
+            Some(item) => format!("array of {}", item),
+            None => "array".to_string(),
+        },
+        Some(kind) => kind.to_string(),
+        None => "any".to_string(),
+    }
+}
+
+/// Per-tool notes from a markdown file, keyed by the `## <tool name>` heading they follow
+fn load_tool_notes(path: &std::path::Path) -> std::collections::HashMap<String, String> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => parse_tool_notes(&content),
+        Err(e) => {
+            warn!("Failed to read tool notes {:?}: {}", path, e);
+            std::collections::HashMap::new()
+        }
+    }
+}
+
+fn parse_tool_notes(content: &str) -> std::collections::HashMap<String, String> {
+    let mut notes = std::collections::HashMap::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+    for line in content.lines() {
+        if let Some(name) = line.strip_prefix("## ") {
+            if let Some((name, lines)) = current.take() {
+                notes.insert(name, lines.join("\n").trim().to_string());
+            }
+            current = Some((name.trim().trim_matches('`').to_string(), Vec::new()));
+        } else if let Some((_, lines)) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    if let Some((name, lines)) = current {
+        notes.insert(name, lines.join("\n").trim().to_string());
+    }
+    notes.retain(|_, note| !note.is_empty());
+    notes
+}
+
 // Helper function to search in a single file
 fn search_file(
     path: &std::path::Path,
     regex: &Regex,
     max_results: usize,
     results: &mut Vec<serde_json::Value>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), SquidError> {
     if results.len() >= max_results {
         return Ok(());
     }
@@ -182,37 +635,294 @@ fn search_file(
     Ok(())
 }
 
-// Execute grep search
-async fn execute_bash(command: &str, timeout_secs: u64) -> Result<String, String> {
-    let output = timeout(
-        Duration::from_secs(timeout_secs),
-        tokio::task::spawn_blocking({
-            let command = command.to_string();
-            move || {
-                Command::new("sh")
-                    .arg("-c")
-                    .arg(&command)
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped())
-                    .output()
+// Forward grep matches to the tool output as they are found
+fn report_matches(matches: &[serde_json::Value], output: Option<&mut ToolOutput>) {
+    if let Some(output) = output {
+        for m in matches {
+            output.line(
+                OutputStream::Stdout,
+                &format!(
+                    "{}:{} — {}",
+                    m["file"].as_str().unwrap_or("?"),
+                    m["line"].as_i64().unwrap_or(0),
+                    m["content"].as_str().unwrap_or("").trim()
+                ),
+            );
+        }
+    }
+}
+
+/// Upper bound on tool output forwarded while a tool runs; the final result is unaffected
+const MAX_STREAMED_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Which output stream a line came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// A line of output from a running tool
+#[derive(Debug, Clone, PartialEq)]
+pub struct ToolOutputChunk {
+    pub stream: OutputStream,
+    pub text: String,
+}
+
+enum OutputSink {
+    Channel(tokio::sync::mpsc::UnboundedSender<ToolOutputChunk>),
+    /// CLI: print lines under the tool banner as they arrive
+    Print,
+}
+
+/// Forwards a tool's output as it is produced, up to [`MAX_STREAMED_OUTPUT_BYTES`]
+pub struct ToolOutput {
+    sink: OutputSink,
+    streamed_bytes: usize,
+}
+
+impl ToolOutput {
+    /// Send lines to a channel, e.g. to turn them into stream events
+    pub fn channel(sender: tokio::sync::mpsc::UnboundedSender<ToolOutputChunk>) -> Self {
+        Self {
+            sink: OutputSink::Channel(sender),
+            streamed_bytes: 0,
+        }
+    }
+
+    /// Print lines to the terminal
+    pub fn print() -> Self {
+        Self {
+            sink: OutputSink::Print,
+            streamed_bytes: 0,
+        }
+    }
+
+    fn line(&mut self, stream: OutputStream, text: &str) {
+        if self.streamed_bytes >= MAX_STREAMED_OUTPUT_BYTES {
+            return;
+        }
+        self.streamed_bytes += text.len() + 1;
+        let text = if self.streamed_bytes >= MAX_STREAMED_OUTPUT_BYTES {
+            format!(
+                "{}\n… (output limit reached, the rest is in the final result)",
+                text
+            )
+        } else {
+            text.to_string()
+        };
+
+        match &self.sink {
+            OutputSink::Channel(sender) => {
+                // The listener may be gone (client disconnected); the tool keeps running
+                let _ = sender.send(ToolOutputChunk { stream, text });
+            }
+            OutputSink::Print => match stream {
+                OutputStream::Stdout => eprintln!("  {} {}", style("│").dim(), text),
+                OutputStream::Stderr => {
+                    eprintln!("  {} {}", style("│").dim(), style(text).yellow())
+                }
+            },
+        }
+    }
+}
+
+/// Environment variables every bash command gets, when they're set
+const BASH_ENV_WHITELIST: &[&str] = &["PATH", "HOME", "LANG", "TERM"];
+
+/// Whether an environment variable name looks like it holds a credential
+fn is_secret_env_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    ["_KEY", "_TOKEN", "_SECRET"]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// Environment for bash commands: the whitelist plus `passthrough`, minus anything secret-looking
+///
+/// Values are read with `var`, which is `std::env::var_os` outside tests.
+fn bash_env(
+    passthrough: &[String],
+    var: impl Fn(&str) -> Option<std::ffi::OsString>,
+) -> Vec<(String, std::ffi::OsString)> {
+    let mut names: Vec<&str> = BASH_ENV_WHITELIST.to_vec();
+    for name in passthrough {
+        if !names.contains(&name.as_str()) {
+            names.push(name);
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| !is_secret_env_name(name))
+        .filter_map(|name| var(name).map(|value| (name.to_string(), value)))
+        .collect()
+}
+
+/// Directory a bash command runs in: the validated `cwd` argument or the validator's base directory
+fn bash_cwd(
+    validated: Option<std::path::PathBuf>,
+    validator: &PathValidator,
+) -> Result<std::path::PathBuf, String> {
+    let dir = validated.unwrap_or_else(|| validator.base_dir());
+    if dir.is_dir() {
+        Ok(dir)
+    } else {
+        Err(format!(
+            "Working directory '{}' is not a directory",
+            dir.display()
+        ))
+    }
+}
+
+/// Run a bash tool call in `cwd`, reporting the directory alongside the result
+async fn run_bash_tool(
+    command: &str,
+    timeout_secs: u64,
+    cwd: Result<std::path::PathBuf, String>,
+    bash: &BashConfig,
+    output: Option<ToolOutput>,
+) -> serde_json::Value {
+    let cwd = match cwd {
+        Ok(cwd) => cwd,
+        Err(e) => {
+            warn!("Bash command not run: {}: {}", command, e);
+            return json!({"error": format!("Command failed: {}", e)});
+        }
+    };
+    let cwd_display = cwd.display().to_string();
+    match execute_bash(command, timeout_secs, Some(&cwd), bash, output).await {
+        Ok(output) => {
+            info!(
+                "Bash command executed successfully in {}: {}",
+                cwd_display, command
+            );
+            json!({"content": format!("Command executed successfully:\n\n{}", output), "cwd": cwd_display})
+        }
+        Err(e) => {
+            warn!("Bash command failed in {}: {}: {}", cwd_display, command, e);
+            json!({"error": format!("Command failed: {}", e), "cwd": cwd_display})
+        }
+    }
+}
+
+// Execute bash command, reporting output lines as they arrive
+//
+// The command only sees the whitelisted environment, so API keys and tokens of the
+// squid process don't leak into it. In the restricted sandbox it also runs isolated and
+// under the sandbox time limit (see `crate::sandbox`).
+async fn execute_bash(
+    command: &str,
+    timeout_secs: u64,
+    cwd: Option<&std::path::Path>,
+    bash: &BashConfig,
+    mut output: Option<ToolOutput>,
+) -> Result<String, String> {
+    // Temporary HOME of a sandboxed command, removed once the command is done
+    let mut _sandbox_home = None;
+    let mut sandbox_limit = None;
+    let mut cmd = match bash.sandbox {
+        SandboxMode::None => {
+            let mut cmd = tokio::process::Command::new("sh");
+            cmd.arg("-c")
+                .arg(command)
+                .env_clear()
+                .envs(bash_env(&bash.env_passthrough, |name| {
+                    std::env::var_os(name)
+                }));
+            if let Some(cwd) = cwd {
+                cmd.current_dir(cwd);
             }
-        }),
+            cmd
+        }
+        SandboxMode::Restricted => {
+            let cwd = match cwd {
+                Some(cwd) => cwd.to_path_buf(),
+                None => std::env::current_dir()
+                    .map_err(|e| format!("Failed to resolve working directory: {}", e))?,
+            };
+            let limit = bash.sandbox_time_limit_secs.max(1);
+            let sandboxed = crate::sandbox::restricted_command(
+                command,
+                &cwd,
+                crate::sandbox::capabilities(),
+                limit,
+            )?;
+            _sandbox_home = sandboxed.home;
+            sandbox_limit = Some(limit).filter(|limit| *limit < timeout_secs);
+            sandboxed.command
+        }
+    };
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Dropping the child on timeout kills the command
+        .kill_on_drop(true);
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| format!("Failed to spawn command: {}", e))?;
+
+    let mut stdout_lines =
+        BufReader::new(child.stdout.take().expect("stdout is piped")).split(b'\n');
+    let mut stderr_lines =
+        BufReader::new(child.stderr.take().expect("stderr is piped")).split(b'\n');
+
+    let run = async {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let (mut stdout_open, mut stderr_open) = (true, true);
+
+        while stdout_open || stderr_open {
+            let (stream, line) = tokio::select! {
+                line = stdout_lines.next_segment(), if stdout_open => (OutputStream::Stdout, line),
+                line = stderr_lines.next_segment(), if stderr_open => (OutputStream::Stderr, line),
+            };
+            match line {
+                Ok(Some(line)) => {
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end_matches('\r');
+                    if let Some(output) = output.as_mut() {
+                        output.line(stream, line);
+                    }
+                    let buffer = match stream {
+                        OutputStream::Stdout => &mut stdout,
+                        OutputStream::Stderr => &mut stderr,
+                    };
+                    buffer.push_str(line);
+                    buffer.push('\n');
+                }
+                Ok(None) | Err(_) => match stream {
+                    OutputStream::Stdout => stdout_open = false,
+                    OutputStream::Stderr => stderr_open = false,
+                },
+            }
+        }
+
+        child.wait().await.map(|status| (status, stdout, stderr))
+    };
+
+    let (status, stdout, stderr) = timeout(
+        Duration::from_secs(sandbox_limit.unwrap_or(timeout_secs)),
+        run,
     )
     .await
-    .map_err(|_| format!("Command timed out after {} seconds", timeout_secs))?
-    .map_err(|e| format!("Failed to spawn command: {}", e))?
+    .map_err(|_| match sandbox_limit {
+        Some(limit) => format!(
+            "Command exceeded the sandbox time limit of {} seconds",
+            limit
+        ),
+        None => format!("Command timed out after {} seconds", timeout_secs),
+    })?
     .map_err(|e| format!("Failed to execute command: {}", e))?;
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
+    if !status.success() {
         return Err(format!(
             "Command failed with exit code {}: {}",
-            output.status.code().unwrap_or(-1),
+            status.code().unwrap_or(-1),
             stderr.trim()
         ));
     }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
     Ok(stdout.trim().to_string())
 }
 
@@ -221,32 +931,30 @@ fn execute_grep(
     path: &str,
     case_sensitive: bool,
     max_results: usize,
-) -> Result<Vec<serde_json::Value>, Box<dyn std::error::Error>> {
+    validator: &PathValidator,
+    mut output: Option<&mut ToolOutput>,
+) -> Result<Vec<serde_json::Value>, SquidError> {
     let regex = if case_sensitive {
-        Regex::new(pattern)?
+        Regex::new(pattern)
     } else {
-        Regex::new(&format!("(?i){}", pattern))?
-    };
+        Regex::new(&format!("(?i){}", pattern))
+    }
+    .map_err(|e| SquidError::Other(e.to_string()))?;
 
     let mut results = Vec::new();
     let search_path = std::path::Path::new(path);
 
-    // Initialize path validator with ignore patterns
-    let ignore_patterns = PathValidator::load_ignore_patterns();
-    let validator = PathValidator::with_ignore_file(if ignore_patterns.is_empty() {
-        None
-    } else {
-        Some(ignore_patterns)
-    });
-
     if search_path.is_file() {
         // Search in a single file
         search_file(search_path, &regex, max_results, &mut results)?;
+        report_matches(&results, output.as_deref_mut());
     } else if search_path.is_dir() {
         // Search recursively in directory
         for entry in WalkDir::new(search_path)
             .follow_links(false)
             .into_iter()
+            // Don't descend into ignored directories such as target/ or node_modules/
+            .filter_entry(|e| e.depth() == 0 || !validator.is_ignored(e.path()))
             .filter_map(|e| e.ok())
         {
             let entry_path = entry.path();
@@ -311,10 +1019,12 @@ fn execute_grep(
             }
 
             // Try to search the file
+            let reported = results.len();
             if let Err(e) = search_file(entry_path, &regex, max_results, &mut results) {
                 debug!("Skipping file {} due to error: {}", entry_path.display(), e);
                 continue;
             }
+            report_matches(&results[reported..], output.as_deref_mut());
 
             if results.len() >= max_results {
                 break;
@@ -325,6 +1035,228 @@ fn execute_grep(
     Ok(results)
 }
 
+/// Score `candidate` against a fuzzy `query`, or `None` when it doesn't match
+///
+/// Every whitespace-separated term must appear in order in the path, ignoring case.
+/// Consecutive characters, word starts and characters in the file name score higher,
+/// and a term naming the whole file (or its stem) gets a bonus, so `session.rs` ranks
+/// above a path that only spells "session" across several directories.
+fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let path: Vec<char> = candidate.to_lowercase().chars().collect();
+    let file_start = path.iter().rposition(|c| *c == '/').map_or(0, |i| i + 1);
+    let file_name: String = path[file_start..].iter().collect();
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name.as_str(), |(stem, _)| stem);
+
+    let mut total = 0;
+    let mut terms = 0;
+    for term in query.split_whitespace() {
+        let term: Vec<char> = term.to_lowercase().chars().collect();
+        // Try every start of the first character and keep the best greedy match
+        let best = (0..path.len())
+            .filter(|&start| path[start] == term[0])
+            .filter_map(|start| score_term(&term, &path, start, file_start))
+            .max()?;
+        let term: String = term.into_iter().collect();
+        let exact = if term == file_name || term == stem {
+            20
+        } else {
+            0
+        };
+        total += best + exact;
+        terms += 1;
+    }
+    (terms > 0).then_some(total)
+}
+
+/// Greedy subsequence match of `term` in `path` anchored at `start`
+fn score_term(term: &[char], path: &[char], start: usize, file_start: usize) -> Option<i64> {
+    let mut score = 0;
+    let mut previous: Option<usize> = None;
+    let mut position = start;
+    for &wanted in term {
+        let found = position + path[position..].iter().position(|c| *c == wanted)?;
+        score += 1;
+        match previous {
+            Some(prev) if found == prev + 1 => score += 5,
+            Some(prev) => score -= (found - prev - 1).min(5) as i64,
+            None => {}
+        }
+        if found == 0 || matches!(path[found - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 3;
+        }
+        if found >= file_start {
+            score += 2;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+/// Fuzzy-match workspace files against `query`, best first, as (relative path, size)
+///
+/// Walks the validator's base directory without descending into ignored directories
+/// and skips any file the validator rejects.
+fn find_files(
+    query: &str,
+    extension: Option<&str>,
+    max_results: usize,
+    validator: &PathValidator,
+) -> Vec<(String, u64)> {
+    let root = validator.base_dir();
+    let extension = extension
+        .map(|ext| ext.trim_start_matches('.').to_lowercase())
+        .filter(|ext| !ext.is_empty());
+
+    let mut matches: Vec<(i64, String, u64)> = WalkDir::new(&root)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !validator.is_ignored(e.path()))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| {
+            extension.as_ref().is_none_or(|wanted| {
+                e.path()
+                    .extension()
+                    .is_some_and(|ext| ext.to_string_lossy().to_lowercase() == *wanted)
+            })
+        })
+        .filter(|e| validator.validate(e.path()).is_ok())
+        .filter_map(|e| {
+            let relative = e.path().strip_prefix(&root).unwrap_or(e.path());
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            let score = fuzzy_score(query, &relative)?;
+            let size = e.metadata().map(|m| m.len()).unwrap_or(0);
+            Some((score, relative, size))
+        })
+        .collect();
+
+    // Best score first; shorter paths win ties
+    matches.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(a.1.len().cmp(&b.1.len()))
+            .then(a.1.cmp(&b.1))
+    });
+    matches
+        .into_iter()
+        .take(max_results)
+        .map(|(_, path, size)| (path, size))
+        .collect()
+}
+
+/// Run the `find_files` tool and format the matches for the model
+fn find_files_tool(
+    args: &serde_json::Value,
+    max_results: usize,
+    validator: &PathValidator,
+) -> serde_json::Value {
+    let query = args["query"].as_str().unwrap_or("").trim();
+    if query.is_empty() {
+        return json!({"error": "find_files needs a non-empty query"});
+    }
+    let extension = args["extension"].as_str();
+
+    let files = find_files(query, extension, max_results, validator);
+    info!("find_files found {} files for '{}'", files.len(), query);
+    if files.is_empty() {
+        return json!({"message": format!("No files match '{}'", query)});
+    }
+
+    let mut formatted = format!(
+        "Found {} file{} matching '{}':\n\n",
+        files.len(),
+        if files.len() == 1 { "" } else { "s" },
+        query
+    );
+    for (path, size) in &files {
+        formatted.push_str(&format!("  - {} ({} bytes)\n", path, size));
+    }
+    json!({"content": formatted})
+}
+
+/// Most chunks a single `search_docs` call returns
+const MAX_SEARCH_DOCS_RESULTS: u64 = 20;
+
+/// Run the `search_docs` tool against the document index
+async fn search_docs(
+    rag: Option<&RagSystem>,
+    args: &serde_json::Value,
+    config: &Config,
+) -> serde_json::Value {
+    let Some(rag) = rag else {
+        return json!({"error": "Document search is not available: RAG is disabled or failed to start"});
+    };
+    let query = args["query"].as_str().unwrap_or("").trim();
+    if query.is_empty() {
+        return json!({"error": "search_docs needs a non-empty query"});
+    }
+    let top_k = args["top_k"]
+        .as_u64()
+        .map(|top_k| top_k.clamp(1, MAX_SEARCH_DOCS_RESULTS) as usize)
+        .unwrap_or(config.rag.top_k);
+
+    match rag.query.execute_top_k(query, top_k).await {
+        Ok(results) if results.is_empty() => {
+            json!({"message": format!("No indexed documents match '{}'", query), "results": []})
+        }
+        Ok(results) => {
+            info!("search_docs found {} chunks for '{}'", results.len(), query);
+            let results: Vec<serde_json::Value> = results
+                .iter()
+                .enumerate()
+                .map(|(idx, result)| {
+                    json!({
+                        "rank": idx + 1,
+                        "filename": result.filename,
+                        "title": result.title,
+                        "label": result.label(),
+                        "score": result.score,
+                        "text": result.chunk_text,
+                    })
+                })
+                .collect();
+            json!({"query": query, "results": results})
+        }
+        Err(e) => {
+            warn!("search_docs failed for '{}': {}", query, e);
+            json!({"error": format!("Document search failed: {}", e)})
+        }
+    }
+}
+
+/// Sources for the chunks a `search_docs` call returned, so the answer keeps their provenance
+pub fn doc_sources(name: &str, result: &serde_json::Value) -> Vec<Source> {
+    if name != "search_docs" {
+        return Vec::new();
+    }
+    result["results"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|chunk| {
+            Some(Source::new(
+                chunk["label"].as_str()?,
+                chunk["text"].as_str()?,
+                SourceOrigin::Rag,
+            ))
+        })
+        .collect()
+}
+
+/// RAG system for CLI tool calls, started on first use when `rag.enabled` is set
+async fn cli_rag_system(config: &Config) -> Option<Arc<RagSystem>> {
+    static RAG_SYSTEM: tokio::sync::OnceCell<Option<Arc<RagSystem>>> =
+        tokio::sync::OnceCell::const_new();
+    RAG_SYSTEM
+        .get_or_init(|| {
+            crate::llm::initialize_rag_if_needed(config.rag.enabled, false, false, config)
+        })
+        .await
+        .clone()
+}
+
 /// Permission choices for tool execution
 #[derive(Debug, Clone, Copy, PartialEq)]
 enum PermissionChoice {
@@ -332,6 +1264,7 @@ enum PermissionChoice {
     No,
     Always,
     Never,
+    Directory,
 }
 
 impl std::fmt::Display for PermissionChoice {
@@ -341,137 +1274,379 @@ impl std::fmt::Display for PermissionChoice {
             PermissionChoice::No => write!(f, "No (skip)"),
             PermissionChoice::Always => write!(f, "Always (add to allow list)"),
             PermissionChoice::Never => write!(f, "Never (add to deny list)"),
+            PermissionChoice::Directory => {
+                write!(f, "Allow read access to this directory for this session")
+            }
         }
     }
 }
 
-/// Tool permission status
-#[derive(Debug, Clone, PartialEq)]
-pub enum ToolPermissionStatus {
-    Allowed,
-    Denied { reason: String },
-    NeedsApproval,
+/// A whole `now` offset after its sign, e.g. `2h30m`
+static TIME_OFFSET: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(?:\d+[smhdw])+$").expect("valid regex"));
+/// One amount and unit of a `now` offset
+static TIME_OFFSET_SEGMENT: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(\d+)([smhdw])").expect("valid regex"));
+
+/// Parse a `now` tool offset like `+3d`, `-2h` or `+1w2d` into (calendar days, seconds)
+fn parse_time_offset(offset: &str) -> Result<(i64, i64), String> {
+    let invalid = || {
+        format!(
+            "Invalid offset '{}'. Use a sign followed by amounts with units s, m, h, d or w, e.g. '+3d' or '-2h30m'",
+            offset
+        )
+    };
+
+    let trimmed = offset.trim();
+    let (sign, rest) = match trimmed.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+
+    if !TIME_OFFSET.is_match(rest) {
+        return Err(invalid());
+    }
+
+    let mut days = 0i64;
+    let mut seconds = 0i64;
+    for caps in TIME_OFFSET_SEGMENT.captures_iter(rest) {
+        let value: i64 = caps[1].parse().map_err(|_| invalid())?;
+        let (target, multiplier) = match &caps[2] {
+            "s" => (&mut seconds, 1),
+            "m" => (&mut seconds, 60),
+            "h" => (&mut seconds, 3600),
+            "d" => (&mut days, 1),
+            _ => (&mut days, 7),
+        };
+        *target = value
+            .checked_mul(multiplier)
+            .and_then(|v| target.checked_add(v))
+            .ok_or_else(invalid)?;
+    }
+
+    Ok((sign * days, sign * seconds))
+}
+
+/// Shift a date by calendar days (keeping the wall-clock time where possible), then by seconds
+fn shift_time<Tz: chrono::TimeZone>(
+    time: chrono::DateTime<Tz>,
+    days: i64,
+    seconds: i64,
+) -> Option<chrono::DateTime<Tz>> {
+    let time = if days != 0 {
+        let local = time
+            .naive_local()
+            .checked_add_signed(chrono::Duration::try_days(days)?)?;
+        // Fall back to an absolute shift when the wall-clock time doesn't exist (DST gap)
+        match time.timezone().from_local_datetime(&local).earliest() {
+            Some(shifted) => shifted,
+            None => time.checked_add_signed(chrono::Duration::try_days(days)?)?,
+        }
+    } else {
+        time
+    };
+    time.checked_add_signed(chrono::Duration::try_seconds(seconds)?)
 }
 
-/// Check tool permission status based on agent configuration and security rules
-/// This function performs mandatory security checks and consults agent-specific allow/deny lists
-pub fn check_tool_permission(
+/// Build the `now` tool response for a point in time, applying the offset and format
+fn format_current_time<Tz: chrono::TimeZone>(
+    time: chrono::DateTime<Tz>,
+    timezone: &str,
+    args: &serde_json::Value,
+) -> serde_json::Value
+where
+    Tz::Offset: std::fmt::Display,
+{
+    let (days, seconds) = match args["offset"].as_str().filter(|o| !o.trim().is_empty()) {
+        Some(offset) => match parse_time_offset(offset) {
+            Ok(parsed) => parsed,
+            Err(e) => return json!({"error": e, "parameter": "offset"}),
+        },
+        None => (0, 0),
+    };
+    let Some(time) = shift_time(time, days, seconds) else {
+        return json!({"error": "Offset is out of range", "parameter": "offset"});
+    };
+
+    let rfc3339 = time.to_rfc3339();
+    let formatted = match args["format"].as_str().filter(|f| !f.is_empty()) {
+        Some(format) => {
+            let items: Vec<_> = chrono::format::StrftimeItems::new(format).collect();
+            if items.contains(&chrono::format::Item::Error) {
+                return json!({
+                    "error": format!("Invalid format string '{}'", format),
+                    "parameter": "format"
+                });
+            }
+            time.format_with_items(items.into_iter()).to_string()
+        }
+        None => rfc3339.clone(),
+    };
+
+    json!({
+        "content": format!(
+            "Date and time: {}\nFormatted: {}\nTimezone: {} ({})\nUnix timestamp: {}",
+            rfc3339,
+            formatted,
+            timezone,
+            time.format("%Z"),
+            time.timestamp()
+        ),
+        "datetime": rfc3339,
+        "formatted": formatted,
+        "timezone": timezone,
+        "timestamp": time.timestamp()
+    })
+}
+
+/// Execute the `now` tool relative to `base`
+fn current_time(base: chrono::DateTime<Utc>, args: &serde_json::Value) -> serde_json::Value {
+    match args["timezone"]
+        .as_str()
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+    {
+        None => format_current_time(base.with_timezone(&chrono::Local), "local", args),
+        Some(name) => match name.parse::<chrono_tz::Tz>() {
+            Ok(tz) => format_current_time(base.with_timezone(&tz), tz.name(), args),
+            Err(_) => json!({
+                "error": format!(
+                    "Unknown timezone '{}'. Use an IANA name such as 'Europe/Kyiv' or 'UTC'",
+                    name
+                ),
+                "parameter": "timezone"
+            }),
+        },
+    }
+}
+
+/// Maximum number of diff lines included in an approval preview
+const MAX_PREVIEW_DIFF_LINES: usize = 300;
+
+/// Reviewable summary of what a tool call will do, shown before asking for approval
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ToolPreview {
+    pub summary: String,
+    /// Resolved absolute path after validation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_exists: Option<bool>,
+    /// Unified diff of the change (write_file and apply_patch)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<String>,
+    /// Whether and how the bash command will be sandboxed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<String>,
+    /// Whether the diff was cut off at `MAX_PREVIEW_DIFF_LINES`
+    pub truncated: bool,
+}
+
+impl ToolPreview {
+    fn new(summary: String) -> Self {
+        Self {
+            summary,
+            path: None,
+            path_exists: None,
+            diff: None,
+            command: None,
+            cwd: None,
+            sandbox: None,
+            truncated: false,
+        }
+    }
+}
+
+/// Build the approval preview for a tool call
+///
+/// Shared by the CLI prompt and the web UI approval request so both show the same information.
+pub fn build_tool_preview(
     name: &str,
     args: &serde_json::Value,
-    agent_id: &str,
-    config: &Config,
-) -> ToolPermissionStatus {
-    // MANDATORY SECURITY CHECK: Block dangerous bash commands BEFORE any permission checks
-    // This cannot be bypassed by configuration or user approval
-    if name == "bash" {
-        let command = args["command"].as_str().unwrap_or("");
-
-        let dangerous_patterns = [
-            "rm -rf", "rm -f", "sudo ", "chmod ", "dd ", "mkfs", "fdisk", "> /dev/", "curl",
-            "wget", "kill ", "pkill", "killall",
-        ];
+    validator: &PathValidator,
+    bash: &BashConfig,
+) -> Option<ToolPreview> {
+    match name {
+        "read_file" | "write_file" | "grep" => {
+            let raw_path = args["path"].as_str().unwrap_or("");
+            let path = match validator.validate(std::path::Path::new(raw_path)) {
+                Ok(p) => p,
+                Err(e) => {
+                    return Some(ToolPreview::new(format!(
+                        "Path '{}' is not accessible: {}",
+                        raw_path, e
+                    )));
+                }
+            };
+            let exists = path.exists();
+            let display_path = path.display().to_string();
 
-        for pattern in &dangerous_patterns {
-            if command.contains(pattern) {
-                warn!("Blocked dangerous bash command: {}", command);
-                return ToolPermissionStatus::Denied {
-                    reason: format!(
-                        "Command blocked for security reasons. The command contains a dangerous pattern: '{}'. Commands like rm, sudo, chmod, dd, curl, wget, and kill operations are not allowed.",
-                        pattern
-                    ),
-                };
-            }
+            let mut preview = match name {
+                "write_file" => {
+                    let new_content = args["content"].as_str().unwrap_or("");
+                    if exists {
+                        let old_content = std::fs::read_to_string(&path).unwrap_or_default();
+                        let (diff, truncated, added, removed) =
+                            unified_diff(&display_path, &old_content, new_content);
+                        let mut preview = ToolPreview::new(if added == 0 && removed == 0 {
+                            format!("No changes to {}", display_path)
+                        } else {
+                            format!("Overwrite {} (+{} -{} lines)", display_path, added, removed)
+                        });
+                        preview.diff = Some(diff);
+                        preview.truncated = truncated;
+                        preview
+                    } else {
+                        ToolPreview::new(format!(
+                            "New file, {} lines: {}",
+                            new_content.lines().count(),
+                            display_path
+                        ))
+                    }
+                }
+                "grep" => ToolPreview::new(format!(
+                    "Search {} for '{}'",
+                    display_path,
+                    args["pattern"].as_str().unwrap_or("")
+                )),
+                _ if exists => ToolPreview::new(format!("Read {}", display_path)),
+                _ => ToolPreview::new(format!("File does not exist: {}", display_path)),
+            };
+            preview.path = Some(display_path);
+            preview.path_exists = Some(exists);
+            Some(preview)
         }
-    }
-
-    // Get agent-specific permissions
-    let permissions = match config.get_agent_permissions(agent_id) {
-        Some(p) => p,
-        None => {
-            warn!("Agent '{}' not found, denying all tools", agent_id);
-            return ToolPermissionStatus::Denied {
-                reason: format!("Agent '{}' not found", agent_id),
+        "apply_patch" => {
+            // Show the diff exactly as the model wrote it
+            let diff = args["patch"].as_str().unwrap_or("").to_string();
+            let files = crate::patch::parse(&diff)
+                .map(|patches| patches.len())
+                .unwrap_or(0);
+            let action = if args["dry_run"].as_bool().unwrap_or(false) {
+                "Dry run: patch"
+            } else {
+                "Patch"
             };
+            let mut preview = ToolPreview::new(format!("{} {} file(s)", action, files));
+            preview.diff = Some(diff);
+            Some(preview)
         }
-    };
-
-    // Check plugin permissions
-    if name.starts_with("plugin:") {
-        // Check if plugin exists
-        if let Some(plugin_meta) = crate::plugins::get_plugin_metadata(name) {
-            // Verify agent has permission for plugin's required capabilities
-            for required in &plugin_meta.security.requires {
-                if !permissions.allow.contains(required) {
-                    return ToolPermissionStatus::Denied {
-                        reason: format!(
-                            "Plugin '{}' requires '{}' permission which is not granted to agent",
-                            name, required
-                        ),
-                    };
-                }
-            }
-
-            // Check if plugin is explicitly allowed or wildcard allowed
-            if permissions.allow.contains(&name.to_string())
-                || permissions.allow.contains(&"plugin:*".to_string())
-            {
-                return ToolPermissionStatus::Allowed;
+        "bash" => {
+            let command = args["command"].as_str().unwrap_or("").to_string();
+            let cwd = match args["cwd"].as_str().filter(|cwd| !cwd.is_empty()) {
+                Some(raw_cwd) => match validator.validate(std::path::Path::new(raw_cwd)) {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        return Some(ToolPreview::new(format!(
+                            "Directory '{}' is not accessible: {}",
+                            raw_cwd, e
+                        )));
+                    }
+                },
+                None => validator.base_dir(),
             }
-
-            // Otherwise needs approval (deny by default)
-            return ToolPermissionStatus::NeedsApproval;
-        } else {
-            return ToolPermissionStatus::Denied {
-                reason: format!("Plugin '{}' not found", name),
-            };
+            .display()
+            .to_string();
+            let mut preview = ToolPreview::new(format!("Run `{}` in {}", command, cwd));
+            preview.sandbox = Some(crate::sandbox::describe(
+                bash.sandbox,
+                bash.sandbox_time_limit_secs,
+                &command,
+            ));
+            preview.command = Some(command);
+            preview.cwd = Some(cwd);
+            Some(preview)
         }
+        _ => None,
     }
+}
 
-    // Check if tool is allowed (with granular bash command support)
-    // Allow-only model: if not in allow list, it's denied
-    let auto_allowed = if name == "bash" {
-        let command = args["command"].as_str().unwrap_or("");
-
-        // Check if "bash" is in allow list (allows all bash commands)
-        if permissions.allow.contains(&"bash".to_string()) {
-            return ToolPermissionStatus::Allowed;
+/// Create a unified diff capped at `MAX_PREVIEW_DIFF_LINES`
+///
+/// Returns the diff text, whether it was truncated, and the added/removed line counts.
+fn unified_diff(path: &str, old: &str, new: &str) -> (String, bool, usize, usize) {
+    let diff = similar::TextDiff::from_lines(old, new);
+
+    let (mut added, mut removed) = (0, 0);
+    for change in diff.iter_all_changes() {
+        match change.tag() {
+            similar::ChangeTag::Insert => added += 1,
+            similar::ChangeTag::Delete => removed += 1,
+            similar::ChangeTag::Equal => {}
         }
+    }
 
-        // Check for granular bash permissions
-        let command_trimmed = command.trim();
-        let has_granular_permission = permissions.allow.iter().any(|perm| {
-            if let Some(bash_cmd) = perm.strip_prefix("bash:") {
-                command_trimmed == bash_cmd
-                    || command_trimmed.starts_with(&format!("{} ", bash_cmd))
-            } else {
-                false
-            }
-        });
+    let text = diff
+        .unified_diff()
+        .context_radius(3)
+        .header(path, path)
+        .to_string();
 
-        if has_granular_permission {
-            return ToolPermissionStatus::Allowed;
-        }
+    let total_lines = text.lines().count();
+    if total_lines <= MAX_PREVIEW_DIFF_LINES {
+        return (text, false, added, removed);
+    }
 
-        // Bash not in allow list - denied by default
-        return ToolPermissionStatus::Denied {
-            reason: format!("Bash commands not allowed for agent '{}'", agent_id),
-        };
-    } else {
-        permissions.allow.contains(&name.to_string())
-    };
+    let mut truncated: String = text
+        .lines()
+        .take(MAX_PREVIEW_DIFF_LINES)
+        .map(|l| format!("{}\n", l))
+        .collect();
+    truncated.push_str(&format!(
+        "... ({} more diff lines not shown)\n",
+        total_lines - MAX_PREVIEW_DIFF_LINES
+    ));
+    (truncated, true, added, removed)
+}
 
-    if auto_allowed {
-        info!(
-            "Tool '{}' is allowed for agent '{}', auto-approving",
-            name, agent_id
-        );
-        ToolPermissionStatus::Allowed
-    } else {
-        // Tool not in allow list - denied by default
-        ToolPermissionStatus::Denied {
-            reason: format!("Tool '{}' not in allow list for agent '{}'", name, agent_id),
+/// Render a tool preview for the terminal approval prompt
+fn format_tool_preview(preview: &ToolPreview) -> String {
+    let mut lines = vec![format!("  🔎 {}", style(&preview.summary).cyan())];
+    if let Some(sandbox) = &preview.sandbox {
+        lines.push(format!("  🛡️  {}", style(sandbox).dim()));
+    }
+    if let Some(diff) = &preview.diff {
+        for line in diff.lines() {
+            let styled = if line.starts_with("+++") || line.starts_with("---") {
+                style(line).bold()
+            } else if line.starts_with('+') {
+                style(line).green()
+            } else if line.starts_with('-') {
+                style(line).red()
+            } else if line.starts_with("@@") {
+                style(line).cyan()
+            } else {
+                style(line).dim()
+            };
+            lines.push(format!("    {}", styled));
         }
     }
+    lines.join("\n")
+}
+
+/// Path validator used for tools executed from the web UI
+///
+/// Anchored to the session's working directory when it has one inside the configured
+/// workspaces, otherwise to the first configured workspace, or to the server's current
+/// directory and the project's .squidignore when no workspaces are configured. The
+/// validator is shared across tool calls until an ignore file changes.
+pub fn web_path_validator(
+    working_dir: Option<&std::path::Path>,
+    config: &Config,
+) -> Arc<PathValidator> {
+    let respect_gitignore = config.security.respect_gitignore;
+    let root = working_dir
+        .filter(|dir| crate::workspace::within_workspaces(config, dir))
+        .map(std::path::Path::to_path_buf)
+        .or_else(|| crate::workspace::default_root(config));
+    match root {
+        Some(dir) => PathValidator::cached_for_working_dir(&dir, respect_gitignore),
+        None => PathValidator::cached_for_current_dir(respect_gitignore),
+    }
 }
 
 /// Execute a tool without CLI prompts (for web UI)
@@ -479,7 +1654,10 @@ pub fn check_tool_permission(
 pub async fn execute_tool_direct(
     name: &str,
     args: &serde_json::Value,
-    _config: &Config,
+    config: &Config,
+    working_dir: Option<&std::path::Path>,
+    rag: Option<&RagSystem>,
+    output: Option<ToolOutput>,
 ) -> serde_json::Value {
     // Check if this is a plugin tool
     if crate::plugins::is_plugin_tool(name) {
@@ -493,52 +1671,11 @@ pub async fn execute_tool_direct(
     }
 
     // Validate paths for file operations
-    let ignore_patterns = PathValidator::load_ignore_patterns();
-    let validator = PathValidator::with_ignore_file(if ignore_patterns.is_empty() {
-        None
-    } else {
-        Some(ignore_patterns)
-    });
+    let validator = web_path_validator(working_dir, config);
 
-    let validated_path = match name {
-        "read_file" | "write_file" | "grep" => {
-            let path = args["path"].as_str().unwrap_or("");
-            match validator.validate(std::path::Path::new(path)) {
-                Ok(p) => Some(p),
-                Err(e) => {
-                    debug!("Path validation failed for {}: {}", name, e);
-                    let friendly_message = match e {
-                        crate::validate::PathValidationError::PathIgnored(_) => {
-                            format!(
-                                "I cannot access '{}' because it's protected by the project's .squidignore file.",
-                                path
-                            )
-                        }
-                        crate::validate::PathValidationError::PathNotAllowed(ref msg)
-                            if msg.contains("blacklisted") =>
-                        {
-                            format!(
-                                "I cannot access '{}' because it's a protected system file or directory.",
-                                path
-                            )
-                        }
-                        crate::validate::PathValidationError::PathNotAllowed(ref msg)
-                            if msg.contains("not whitelisted") =>
-                        {
-                            format!(
-                                "I cannot access '{}' because it's outside the current project directory.",
-                                path
-                            )
-                        }
-                        _ => {
-                            format!("I cannot access '{}' due to security restrictions.", path)
-                        }
-                    };
-                    return json!({"content": friendly_message});
-                }
-            }
-        }
-        _ => None,
+    let validated_path = match crate::policy::validate_tool_path(name, args, &validator) {
+        Ok(path) => path,
+        Err(reason) => return reason.to_tool_result(),
     };
 
     // Execute the tool
@@ -563,7 +1700,7 @@ pub async fn execute_tool_direct(
         "write_file" => {
             let validated_path = validated_path.unwrap();
             let content = args["content"].as_str().unwrap_or("");
-            match std::fs::write(&validated_path, content) {
+            match crate::safe_write::write(&validator, &validated_path, content.as_bytes()) {
                 Ok(_) => {
                     info!(
                         "Successfully wrote file: {} ({} bytes)",
@@ -578,18 +1715,38 @@ pub async fn execute_tool_direct(
                 }
             }
         }
+        "apply_patch" => {
+            let patch = args["patch"].as_str().unwrap_or("");
+            let dry_run = args["dry_run"].as_bool().unwrap_or(false);
+            crate::patch::execute(patch, dry_run, &validator)
+        }
         "grep" => {
             let validated_path = validated_path.unwrap();
             let pattern = args["pattern"].as_str().unwrap_or("");
             let case_sensitive = args["case_sensitive"].as_bool().unwrap_or(false);
-            let max_results = args["max_results"].as_i64().unwrap_or(50) as usize;
-
-            match execute_grep(
-                pattern,
-                validated_path.to_str().unwrap_or(""),
-                case_sensitive,
-                max_results,
-            ) {
+            let max_results = config.tools.search_limit(args, 50);
+
+            // Walk the tree off the async runtime so matches can be forwarded as they're found
+            let search = tokio::task::spawn_blocking({
+                let pattern = pattern.to_string();
+                let path = validated_path.to_string_lossy().to_string();
+                let mut output = output;
+                move || {
+                    execute_grep(
+                        &pattern,
+                        &path,
+                        case_sensitive,
+                        max_results,
+                        &validator,
+                        output.as_mut(),
+                    )
+                    .map_err(|e| e.to_string())
+                }
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Search task failed: {}", e)));
+
+            match search {
                 Ok(results) => {
                     info!(
                         "Grep found {} results for pattern '{}' in {}",
@@ -632,31 +1789,24 @@ pub async fn execute_tool_direct(
                 }
             }
         }
+        "search_docs" => search_docs(rag, args, config).await,
+        "find_files" => {
+            let args = args.clone();
+            let max_results = config.tools.search_limit(&args, 20);
+            let validator = Arc::clone(&validator);
+            tokio::task::spawn_blocking(move || find_files_tool(&args, max_results, &validator))
+                .await
+                .unwrap_or_else(|e| json!({"error": format!("find_files failed: {}", e)}))
+        }
         "bash" => {
             let command = args["command"].as_str().unwrap_or("");
             let timeout_secs = args["timeout"].as_u64().unwrap_or(10);
-            match execute_bash(command, timeout_secs).await {
-                Ok(output) => {
-                    info!("Bash command executed successfully: {}", command);
-                    json!({"content": format!("Command executed successfully:\n\n{}", output)})
-                }
-                Err(e) => {
-                    warn!("Bash command failed: {}: {}", command, e);
-                    json!({"error": format!("Command failed: {}", e)})
-                }
-            }
+            let cwd = bash_cwd(validated_path, &validator);
+            run_bash_tool(command, timeout_secs, cwd, &config.tools.bash, output).await
         }
         "now" => {
-            let now = chrono::Local::now();
-            info!("Current time tool called: {}", now.to_rfc3339());
-            json!({
-                "content": format!(
-                    "Current date and time: {}\nLocal timezone: {}\nUnix timestamp: {}",
-                    now.to_rfc3339(),
-                    now.format("%Z"),
-                    now.timestamp()
-                )
-            })
+            info!("Current time tool called with args: {}", args);
+            current_time(Utc::now(), args)
         }
         "demo_tool" => {
             let message = args["message"].as_str().unwrap_or("No message provided");
@@ -700,146 +1850,50 @@ pub async fn call_tool(
         }
     };
 
-    // Check if this is a plugin tool
-    if crate::plugins::is_plugin_tool(name) {
-        // Plugin tools are executed directly without CLI approval prompts
-        // (Web UI will handle approval via the standard approval flow)
-        match crate::plugins::execute_plugin_tool(name, &args).await {
-            Ok(result) => return result,
-            Err(e) => {
-                error!("Plugin execution failed: {}", e);
-                return json!({"error": format!("Plugin execution failed: {}", e)});
-            }
-        }
-    }
-
     // Use provided agent_id or default agent
     let agent_id_str = agent_id.unwrap_or(&config.agents.default_agent);
 
-    // Check permission status using the extracted function
-    match check_tool_permission(name, &args, agent_id_str, config) {
-        ToolPermissionStatus::Denied { reason } => {
-            return json!({"error": reason, "skipped": true});
-        }
-        ToolPermissionStatus::Allowed => {
-            // Continue with execution (skip user prompt)
-        }
-        ToolPermissionStatus::NeedsApproval => {
-            // Will ask for user approval below
-        }
-    }
-
-    // Initialize path validator with ignore patterns from .squidignore
-    let ignore_patterns = PathValidator::load_ignore_patterns();
-    let validator = PathValidator::with_ignore_file(if ignore_patterns.is_empty() {
-        None
-    } else {
-        Some(ignore_patterns)
-    });
-
-    // Validate paths BEFORE asking for user approval
-    let validated_path = match name {
-        "read_file" | "write_file" | "grep" => {
-            let path = args["path"].as_str().unwrap_or("");
-            match validator.validate(std::path::Path::new(path)) {
-                Ok(p) => Some(p),
-                Err(e) => {
-                    debug!("Path validation failed for {}: {}", name, e);
-                    let friendly_message = match e {
-                        crate::validate::PathValidationError::PathIgnored(_) => {
-                            format!(
-                                "I cannot access '{}' because it's protected by the project's .squidignore file. This is a security measure to prevent access to sensitive files.",
-                                path
-                            )
-                        }
-                        crate::validate::PathValidationError::PathNotAllowed(ref msg)
-                            if msg.contains("blacklisted") =>
-                        {
-                            format!(
-                                "I cannot access '{}' because it's a protected system file or directory. Access to this location is blocked for security reasons.",
-                                path
-                            )
-                        }
-                        crate::validate::PathValidationError::PathNotAllowed(ref msg)
-                            if msg.contains("not whitelisted") =>
-                        {
-                            format!(
-                                "I cannot access '{}' because it's outside the current project directory. I can only access files within the current workspace for security reasons.",
-                                path
-                            )
-                        }
-                        _ => {
-                            format!("I cannot access '{}' due to security restrictions.", path)
-                        }
-                    };
-                    return json!({"content": friendly_message});
-                }
+    // Same policy as the web UI: hard blocks, path validation, then the allow list
+    let (validated_path, needs_approval) =
+        match policy::evaluate(name, &args, agent_id_str, config, policy::Mode::Cli) {
+            PolicyDecision::Deny(reason) => {
+                print_skip_notice(name, reason.skip_status(), &reason.message());
+                return reason.to_tool_result();
             }
-        }
-        _ => None,
-    };
+            PolicyDecision::Allow { path } => (path, false),
+            PolicyDecision::NeedsApproval { path } => (path, true),
+        };
+
+    // Initialize path validator anchored to the project root and its ignore files
+    let validator = policy::Mode::Cli.path_validator(config);
 
-    // Ask for user approval if not auto-allowed (checked above by check_tool_permission)
-    // This section only runs if ToolPermissionStatus::NeedsApproval was returned
-    let permission_status = check_tool_permission(name, &args, agent_id_str, config);
-    let permission = if matches!(permission_status, ToolPermissionStatus::Allowed) {
+    let directory = validated_path
+        .as_deref()
+        .and_then(|path| grant_directory(name, path));
+    let permission = if !needs_approval {
         PermissionChoice::Yes
+    } else if validated_path
+        .as_deref()
+        .is_some_and(|path| cli_directory_allowed(name, path))
+    {
+        PermissionChoice::Directory
     } else {
-        // Build approval message with styled formatting
-        let approval_message = match name {
-            "read_file" => {
-                let path = args["path"].as_str().unwrap_or("unknown");
-                format!(
-                    "Can I {}?\n  📄 File: {}",
-                    style("read this file").yellow(),
-                    style(path).green()
-                )
-            }
-            "write_file" => {
-                let path = args["path"].as_str().unwrap_or("unknown");
-                let content = args["content"].as_str().unwrap_or("");
-                let preview = if content.len() > 100 {
-                    format!("{}... ({} bytes total)", &content[..100], content.len())
-                } else {
-                    content.to_string()
-                };
-                format!(
-                    "Can I {}?\n  📄 File: {}\n  📝 Content preview:\n{}",
-                    style("write to this file").yellow(),
-                    style(path).green(),
-                    style(&preview).dim()
-                )
-            }
-            "grep" => {
-                let pattern = args["pattern"].as_str().unwrap_or("unknown");
-                let path = args["path"].as_str().unwrap_or("unknown");
-                format!(
-                    "Can I {}?\n  🔍 Pattern: {}\n  📂 Path: {}",
-                    style("search for this pattern").yellow(),
-                    style(pattern).magenta(),
-                    style(path).green()
-                )
-            }
-
-            "bash" => {
-                let command = args["command"].as_str().unwrap_or("unknown");
-                let timeout_secs = args["timeout"].as_u64().unwrap_or(10);
-                format!(
-                    "Can I {}?\n  💻 Command: {}\n  ⏱️  Timeout: {} seconds",
-                    style("execute this bash command").yellow(),
-                    style(command).magenta(),
-                    style(timeout_secs).cyan()
-                )
-            }
-            _ => format!("Can I execute: {}?", style(name).yellow()),
+        let approval_message = approval_question(name, &args);
+        let approval_message = match build_tool_preview(name, &args, &validator, &config.tools.bash)
+        {
+            Some(preview) => format!("{}\n{}", approval_message, format_tool_preview(&preview)),
+            None => approval_message,
         };
 
-        let options = vec![
+        let mut options = vec![
             PermissionChoice::Yes,
             PermissionChoice::No,
             PermissionChoice::Always,
             PermissionChoice::Never,
         ];
+        if directory.is_some() {
+            options.push(PermissionChoice::Directory);
+        }
 
         match Select::new(&approval_message, options)
             .with_help_message(&format!(
@@ -881,18 +1935,26 @@ pub async fn call_tool(
                         );
                         // Load current config, modify it, and save
                         let mut updated_config = Config::load();
-                        if let Err(e) =
-                            updated_config.allow_tool_for_agent(agent_id_str, &tool_to_save)
-                        {
-                            error!("Failed to update config with allow list: {}", e);
-                            eprintln!("{} Failed to save permission: {}", style("✗").red(), e);
-                        } else {
-                            eprintln!(
-                                "{} Permission '{}' added to allow list for agent '{}' in squid.config.json",
+                        updated_config.load_agents();
+                        match updated_config.persist_tool_permission(
+                            agent_id_str,
+                            &tool_to_save,
+                            PermissionChange::Allow,
+                        ) {
+                            Err(e) => {
+                                error!("Failed to update config with allow list: {}", e);
+                                eprintln!("{} Failed to save permission: {}", style("✗").red(), e);
+                            }
+                            Ok(config_path) => eprintln!(
+                                "{} Permission '{}' added to allow list for agent '{}' in {}",
                                 style("✓").green(),
                                 style(&tool_to_save).cyan(),
-                                style(agent_id_str).cyan()
-                            );
+                                style(agent_id_str).cyan(),
+                                config_path
+                                    .file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                            ),
                         }
                     }
                     PermissionChoice::Never => {
@@ -924,18 +1986,35 @@ pub async fn call_tool(
                         );
                         // Load current config, modify it, and save
                         let mut updated_config = Config::load();
-                        if let Err(e) =
-                            updated_config.deny_tool_for_agent(agent_id_str, &tool_to_save)
-                        {
-                            error!("Failed to update config with deny list: {}", e);
-                            eprintln!("{} Failed to save permission: {}", style("✗").red(), e);
-                        } else {
-                            eprintln!(
-                                "{} Permission '{}' added to deny list for agent '{}' in squid.config.json",
+                        updated_config.load_agents();
+                        match updated_config.persist_tool_permission(
+                            agent_id_str,
+                            &tool_to_save,
+                            PermissionChange::Deny,
+                        ) {
+                            Err(e) => {
+                                error!("Failed to update config with deny list: {}", e);
+                                eprintln!("{} Failed to save permission: {}", style("✗").red(), e);
+                            }
+                            Ok(config_path) => eprintln!(
+                                "{} Permission '{}' added to deny list for agent '{}' in {}",
                                 style("✓").green(),
                                 style(&tool_to_save).cyan(),
-                                style(agent_id_str).cyan()
-                            );
+                                style(agent_id_str).cyan(),
+                                config_path
+                                    .file_name()
+                                    .unwrap_or_default()
+                                    .to_string_lossy()
+                            ),
+                        }
+                    }
+                    PermissionChoice::Directory => {
+                        if let Some(dir) = &directory {
+                            info!("Allowing {} in {} for this session", name, dir.display());
+                            CLI_DIRECTORY_GRANTS
+                                .lock()
+                                .unwrap()
+                                .push((name.to_string(), dir.clone()));
                         }
                     }
                     _ => {}
@@ -951,7 +2030,17 @@ pub async fn call_tool(
 
     // Execute tool based on permission
     match permission {
-        PermissionChoice::Yes | PermissionChoice::Always => {
+        PermissionChoice::Yes | PermissionChoice::Always | PermissionChoice::Directory => {
+            if crate::plugins::is_plugin_tool(name) {
+                return match crate::plugins::execute_plugin_tool(name, &args).await {
+                    Ok(result) => result,
+                    Err(e) => {
+                        error!("Plugin execution failed: {}", e);
+                        json!({"error": format!("Plugin execution failed: {}", e)})
+                    }
+                };
+            }
+
             // User approved, proceed with tool execution
             match name {
                 "read_file" => {
@@ -976,7 +2065,8 @@ pub async fn call_tool(
                     let validated_path = validated_path.unwrap();
                     let content = args["content"].as_str().unwrap_or("");
 
-                    match std::fs::write(&validated_path, content) {
+                    match crate::safe_write::write(&validator, &validated_path, content.as_bytes())
+                    {
                         Ok(_) => {
                             info!(
                                 "Successfully wrote file: {} ({} bytes)",
@@ -991,17 +2081,24 @@ pub async fn call_tool(
                         }
                     }
                 }
+                "apply_patch" => {
+                    let patch = args["patch"].as_str().unwrap_or("");
+                    let dry_run = args["dry_run"].as_bool().unwrap_or(false);
+                    crate::patch::execute(patch, dry_run, &validator)
+                }
                 "grep" => {
                     let validated_path = validated_path.unwrap();
                     let pattern = args["pattern"].as_str().unwrap_or("");
                     let case_sensitive = args["case_sensitive"].as_bool().unwrap_or(false);
-                    let max_results = args["max_results"].as_i64().unwrap_or(50) as usize;
+                    let max_results = config.tools.search_limit(&args, 50);
 
                     match execute_grep(
                         pattern,
                         validated_path.to_str().unwrap_or(""),
                         case_sensitive,
                         max_results,
+                        &validator,
+                        None,
                     ) {
                         Ok(results) => {
                             info!(
@@ -1056,35 +2153,32 @@ pub async fn call_tool(
                         }
                     }
                 }
+                "find_files" => {
+                    find_files_tool(&args, config.tools.search_limit(&args, 20), &validator)
+                }
+                "search_docs" => {
+                    let rag = cli_rag_system(config).await;
+                    search_docs(rag.as_deref(), &args, config).await
+                }
 
                 "bash" => {
                     let command = args["command"].as_str().unwrap_or("");
                     let timeout_secs = args["timeout"].as_u64().unwrap_or(10);
 
-                    // Note: Dangerous command check already performed at the top of call_tool()
+                    // Note: Dangerous commands were already rejected by policy::evaluate()
                     // Execute the command
-                    match execute_bash(command, timeout_secs).await {
-                        Ok(output) => {
-                            info!("Bash command executed successfully: {}", command);
-                            json!({"content": format!("Command executed successfully:\n\n{}", output)})
-                        }
-                        Err(e) => {
-                            warn!("Bash command failed: {}: {}", command, e);
-                            json!({"error": format!("Command failed: {}", e)})
-                        }
-                    }
+                    run_bash_tool(
+                        command,
+                        timeout_secs,
+                        bash_cwd(validated_path, &validator),
+                        &config.tools.bash,
+                        Some(ToolOutput::print()),
+                    )
+                    .await
                 }
                 "now" => {
-                    let now = chrono::Local::now();
-                    info!("Current time tool called: {}", now.to_rfc3339());
-                    json!({
-                        "content": format!(
-                            "Current date and time: {}\nLocal timezone: {}\nUnix timestamp: {}",
-                            now.to_rfc3339(),
-                            now.format("%Z"),
-                            now.timestamp()
-                        )
-                    })
+                    info!("Current time tool called with args: {}", args);
+                    current_time(Utc::now(), &args)
                 }
                 "demo_tool" => {
                     let message = args["message"].as_str().unwrap_or("No message provided");
@@ -1117,7 +2211,742 @@ pub async fn call_tool(
         PermissionChoice::No | PermissionChoice::Never => {
             // User declined
             info!("Tool execution declined by user: {}", name);
+            print_skip_notice(name, SkipStatus::RejectedByUser, "declined at the prompt");
             json!({"error": "Tool execution declined by user", "skipped": true})
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registered_tools_are_complete() {
+        let registry = ToolRegistry::builtin();
+        let mut names = std::collections::HashSet::new();
+        for tool in registry.iter() {
+            assert!(names.insert(tool.name), "{} registered twice", tool.name);
+            assert!(
+                !tool.description.is_empty(),
+                "{} has no description",
+                tool.name
+            );
+            assert!(!tool.summary.is_empty(), "{} has no summary", tool.name);
+            assert!(
+                !tool.action.is_empty(),
+                "{} has no approval action",
+                tool.name
+            );
+            assert_eq!(tool.parameters()["type"], "object", "{} schema", tool.name);
+            let properties = tool.parameters()["properties"].clone();
+            for (_, argument) in tool.details {
+                assert!(
+                    properties.get(argument).is_some(),
+                    "{} shows unknown argument {}",
+                    tool.name,
+                    argument
+                );
+            }
+            assert_eq!(tool_description(tool.name), tool.summary);
+            assert_eq!(tool_risk(tool.name), tool.risk);
+        }
+        assert_eq!(tool_description("unknown"), "Execute tool: unknown");
+        assert_eq!(tool_risk("unknown"), RiskLevel::ExecutesCommands);
+    }
+
+    #[test]
+    fn test_get_tools_matches_the_registry() {
+        let builtin: Vec<serde_json::Value> = get_tools()
+            .iter()
+            .map(|tool| serde_json::to_value(tool).unwrap())
+            .filter(|tool| {
+                !tool["function"]["name"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("plugin:")
+            })
+            .collect();
+        let registered: Vec<serde_json::Value> = ToolRegistry::builtin()
+            .schemas()
+            .iter()
+            .map(|tool| serde_json::to_value(tool).unwrap())
+            .collect();
+        assert_eq!(builtin, registered);
+        assert_eq!(registered.len(), ToolRegistry::builtin().iter().count());
+    }
+
+    #[test]
+    fn test_approval_question_lists_present_arguments() {
+        let question = approval_question("bash", &json!({"command": "ls -la"}));
+        assert!(question.contains("execute this bash command"));
+        assert!(question.contains("ls -la"));
+        assert!(!question.contains("Timeout"));
+        assert!(approval_question("plugin:x", &json!({})).contains("plugin:x"));
+    }
+
+    #[test]
+    fn test_tools_for_model_skips_unsupported_models() {
+        let mut system_message = "You are helpful.".to_string();
+        let tools = tools_for_model("qwen2.5-coder-7b-instruct", true, &mut system_message);
+        assert!(!tools.is_empty());
+        assert_eq!(system_message, "You are helpful.");
+
+        // Matched through an alias after normalization (provider prefix, tag)
+        let tools = tools_for_model("google/gemma-2-9b:latest", true, &mut system_message);
+        assert!(tools.is_empty());
+        assert!(system_message.ends_with(TOOLS_UNAVAILABLE_NOTE));
+
+        // Unknown models are assumed to support tools
+        let mut system_message = String::new();
+        assert!(!tools_for_model("my-finetune", true, &mut system_message).is_empty());
+        // The full R1 isn't the 7B distill
+        assert!(!tools_for_model("deepseek/deepseek-r1", true, &mut system_message).is_empty());
+
+        // Nothing to note when tools weren't requested
+        assert!(tools_for_model("phi-2", false, &mut system_message).is_empty());
+        assert!(system_message.is_empty());
+    }
+
+    #[test]
+    fn test_unified_diff_counts_changes() {
+        let old = "fn main() {\n    println!(\"hi\");\n}\n";
+        let new = "fn main() {\n    println!(\"hello\");\n}\n";
+        let (diff, truncated, added, removed) = unified_diff("src/main.rs", old, new);
+
+        assert!(!truncated);
+        assert_eq!((added, removed), (1, 1));
+        assert!(diff.contains("--- src/main.rs"));
+        assert!(diff.contains("-    println!(\"hi\");"));
+        assert!(diff.contains("+    println!(\"hello\");"));
+    }
+
+    #[test]
+    fn test_unified_diff_truncates_large_changes() {
+        let new: String = (0..1000).map(|i| format!("line {}\n", i)).collect();
+        let (diff, truncated, added, removed) = unified_diff("big.txt", "", &new);
+
+        assert!(truncated);
+        assert_eq!((added, removed), (1000, 0));
+        assert_eq!(diff.lines().count(), MAX_PREVIEW_DIFF_LINES + 1);
+        assert!(diff.ends_with("more diff lines not shown)\n"));
+    }
+
+    #[test]
+    fn test_bash_preview_includes_command_and_cwd() {
+        let validator = PathValidator::new();
+        let preview = build_tool_preview(
+            "bash",
+            &json!({"command": "git status"}),
+            &validator,
+            &BashConfig::default(),
+        )
+        .unwrap();
+
+        assert_eq!(preview.command.as_deref(), Some("git status"));
+        let cwd = std::env::current_dir().unwrap().display().to_string();
+        assert_eq!(preview.cwd.as_deref(), Some(cwd.as_str()));
+        assert!(preview.sandbox.unwrap().starts_with("Not sandboxed"));
+    }
+
+    #[test]
+    fn test_fuzzy_score_prefers_exact_names_over_scattered_matches() {
+        let exact = fuzzy_score("session", "src/session.rs").unwrap();
+        let scattered = fuzzy_score("session", "docs/s_e_s_s_i_o_n.md").unwrap();
+        assert!(exact > scattered, "{} <= {}", exact, scattered);
+
+        let prefix = fuzzy_score("sess mgr", "src/session_manager.rs").unwrap();
+        let spread = fuzzy_score("sess mgr", "src/sessions/messages/grid.rs").unwrap();
+        assert!(prefix > spread, "{} <= {}", prefix, spread);
+
+        assert!(fuzzy_score("sess mgr", "src/main.rs").is_none());
+        assert!(fuzzy_score("   ", "src/main.rs").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_find_files_ranks_matches_and_skips_ignored_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("build/cache")).unwrap();
+        std::fs::write(dir.join("src/session_manager.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("src/sessions.ts"), "").unwrap();
+        std::fs::write(dir.join("build/cache/session_manager.rs"), "").unwrap();
+        std::fs::write(dir.join(".squidignore"), "**/build/**\n").unwrap();
+        let validator = PathValidator::for_working_dir(&dir);
+
+        let files = find_files("sess mgr", None, 10, &validator);
+        assert_eq!(files, vec![("src/session_manager.rs".to_string(), 12)]);
+
+        let files = find_files("session", Some(".ts"), 10, &validator);
+        assert_eq!(files, vec![("src/sessions.ts".to_string(), 0)]);
+
+        let result = execute_tool_direct(
+            "find_files",
+            &json!({"query": "session", "max_results": 1}),
+            &Config::default(),
+            Some(&dir),
+            None,
+            None,
+        )
+        .await;
+        let content = result["content"].as_str().unwrap();
+        assert!(content.contains("Found 1 file matching 'session'"));
+        assert!(content.contains("src/session"));
+        assert!(!content.contains("build/"));
+    }
+
+    #[tokio::test]
+    async fn test_search_docs_returns_ranked_chunks_as_sources() {
+        let db = Arc::new(crate::db::Database::new(":memory:").unwrap());
+        let mut install = vec![0.0f32; 768];
+        install[0] = 1.0;
+        let mut notes = vec![0.0f32; 768];
+        notes[1] = 1.0;
+        for (filename, text, embedding) in [
+            (
+                "guide.md",
+                "Install squid with cargo install squid-rs",
+                &install,
+            ),
+            ("notes.md", "Notes from the weekly planning meeting", &notes),
+        ] {
+            let doc = db
+                .upsert_rag_document(filename, filename, text, filename, text.len() as i64)
+                .unwrap();
+            let chunk = db.insert_rag_chunk(doc, 0, text, 8).unwrap();
+            db.insert_rag_embedding(chunk, embedding).unwrap();
+        }
+
+        // The query embeds next to the install guide
+        let response = json!({
+            "object": "list",
+            "data": [{"object": "embedding", "index": 0, "embedding": install}],
+            "model": "text-embedding-3-small",
+            "usage": {"prompt_tokens": 3, "total_tokens": 3}
+        });
+        let (url, _request) =
+            crate::providers::test_support::serve_once("application/json", response.to_string())
+                .await;
+        let mut config = Config::default();
+        config.rag.embedding_url = url;
+        config.rag.embedding_provider = crate::config::ProviderKind::OpenAi;
+        let rag = RagSystem::new(db, &config.rag, reqwest::Client::new())
+            .await
+            .unwrap();
+
+        let result = execute_tool_direct(
+            "search_docs",
+            &json!({"query": "how do I install it?", "top_k": 1}),
+            &config,
+            None,
+            Some(&rag),
+            None,
+        )
+        .await;
+        assert_eq!(result["query"], "how do I install it?");
+        let results = result["results"].as_array().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["rank"], 1);
+        assert_eq!(results[0]["filename"], "guide.md");
+        assert_eq!(results[0]["label"], "guide.md");
+        assert_eq!(
+            results[0]["text"],
+            "Install squid with cargo install squid-rs"
+        );
+        assert!(results[0]["score"].as_f64().unwrap() > 0.9);
+
+        let sources = doc_sources("search_docs", &result);
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].title, "guide.md");
+        assert_eq!(sources[0].origin, SourceOrigin::Rag);
+        assert!(doc_sources("grep", &result).is_empty());
+
+        let unavailable = execute_tool_direct(
+            "search_docs",
+            &json!({"query": "install"}),
+            &config,
+            None,
+            None,
+            None,
+        )
+        .await;
+        assert!(
+            unavailable["error"]
+                .as_str()
+                .unwrap()
+                .contains("not available")
+        );
+    }
+
+    #[test]
+    fn test_doc_search_is_dropped_without_an_index() {
+        let named = |tools: &[ChatCompletionTools]| {
+            tools.iter().any(
+                |tool| matches!(tool, ChatCompletionTools::Function(t) if t.function.name == "search_docs"),
+            )
+        };
+        let mut tools = get_tools();
+        retain_doc_search(&mut tools, true);
+        assert!(named(&tools));
+        retain_doc_search(&mut tools, false);
+        assert!(!named(&tools));
+    }
+
+    #[test]
+    fn test_tool_docs_match_the_tools_sent() {
+        let tools: Vec<_> = get_tools()
+            .into_iter()
+            .filter(|tool| {
+                matches!(tool, ChatCompletionTools::Function(t)
+                    if ["read_file", "write_file"].contains(&t.function.name.as_str()))
+            })
+            .collect();
+        let notes = parse_tool_notes(
+            "Notes for squid\n\n## `write_file`\n\nAlways write the whole file.\n\n## bash\n\nUse `cargo`.\n",
+        );
+
+        assert_eq!(
+            tool_docs(&tools, &notes),
+            "## Available Tools
+
+Only these tools can be called, with the parameters listed.
+
+### `read_file`
+
+Read the contents of a file from the filesystem
+
+Parameters:
+- `path` (string, required): The path to the file to read
+
+### `write_file`
+
+Write content to a file on the filesystem
+
+Parameters:
+- `content` (string, required): The content to write to the file
+- `path` (string, required): The path where the file should be written
+
+Always write the whole file.
+"
+        );
+    }
+
+    #[test]
+    fn test_tool_docs_follow_the_filtered_tool_set() {
+        let temp = tempfile::tempdir().unwrap();
+        std::fs::write(
+            temp.path().join("tools.md"),
+            "## find_files\n\nPrefer this over bash `find`.\n",
+        )
+        .unwrap();
+        let config = Config {
+            config_dir: Some(temp.path().to_path_buf()),
+            tools: crate::config::ToolsConfig {
+                notes_file: Some("tools.md".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let mut tools = get_tools();
+        retain_doc_search(&mut tools, false);
+        let mut system_message = "Prompt".to_string();
+        append_tool_docs(&mut system_message, &tools, &config);
+        assert!(system_message.starts_with("Prompt\n\n## Available Tools"));
+        assert!(system_message.contains("### `grep`"));
+        assert!(system_message.contains("- `max_results` (integer, optional)"));
+        assert!(!system_message.contains("search_docs"));
+        assert!(system_message.contains("Prefer this over bash `find`."));
+
+        let mut system_message = "Prompt".to_string();
+        append_tool_docs(&mut system_message, &[], &config);
+        assert_eq!(system_message, "Prompt");
+    }
+
+    #[tokio::test]
+    async fn test_session_working_dir_used_for_tools() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::write(dir.join("notes.md"), "session notes").unwrap();
+        let config = Config::default();
+
+        let result = execute_tool_direct(
+            "read_file",
+            &json!({"path": "notes.md"}),
+            &config,
+            Some(&dir),
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(result["content"], "session notes");
+
+        let output = execute_bash("pwd", 5, Some(&dir), &config.tools.bash, None)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::canonicalize(output).unwrap(), dir);
+
+        let preview = build_tool_preview(
+            "bash",
+            &json!({"command": "ls"}),
+            &web_path_validator(Some(&dir), &config),
+            &config.tools.bash,
+        )
+        .unwrap();
+        assert_eq!(preview.cwd, Some(dir.display().to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_workspace_stay_inside_configured_workspaces() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = std::fs::canonicalize(temp.path()).unwrap();
+        let workspace = root.join("project");
+        std::fs::create_dir(&workspace).unwrap();
+        std::fs::write(workspace.join("notes.md"), "inside").unwrap();
+        std::fs::write(root.join("outside.md"), "outside").unwrap();
+        let config = Config {
+            workspaces: vec![crate::config::WorkspaceConfig {
+                name: "project".to_string(),
+                path: workspace.display().to_string(),
+            }],
+            ..Default::default()
+        };
+
+        // Without a working directory, relative paths resolve against the first workspace
+        let result = execute_tool_direct(
+            "read_file",
+            &json!({"path": "notes.md"}),
+            &config,
+            None,
+            None,
+            None,
+        )
+        .await;
+        assert_eq!(result["content"], "inside");
+
+        // Nothing outside the workspaces is reachable, also from a working directory outside them
+        let outside = root.join("outside.md").display().to_string();
+        for working_dir in [None, Some(root.as_path())] {
+            let result = execute_tool_direct(
+                "read_file",
+                &json!({ "path": outside }),
+                &config,
+                working_dir,
+                None,
+                None,
+            )
+            .await;
+            assert!(
+                result["content"]
+                    .as_str()
+                    .unwrap()
+                    .starts_with("I cannot access")
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bash_cwd_argument_is_validated() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = std::fs::canonicalize(temp.path()).unwrap();
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        let config = Config::default();
+
+        let result = execute_tool_direct(
+            "bash",
+            &json!({"command": "pwd", "cwd": "sub"}),
+            &config,
+            Some(&dir),
+            None,
+            None,
+        )
+        .await;
+        let sub = dir.join("sub").display().to_string();
+        assert_eq!(result["cwd"], sub.as_str());
+        assert!(result["content"].as_str().unwrap().ends_with(&sub));
+
+        let preview = build_tool_preview(
+            "bash",
+            &json!({"command": "ls", "cwd": "sub"}),
+            &web_path_validator(Some(&dir), &config),
+            &config.tools.bash,
+        )
+        .unwrap();
+        assert_eq!(preview.cwd, Some(sub));
+
+        let escaping = execute_tool_direct(
+            "bash",
+            &json!({"command": "pwd", "cwd": "/etc"}),
+            &config,
+            Some(&dir),
+            None,
+            None,
+        )
+        .await;
+        assert!(
+            escaping["content"]
+                .as_str()
+                .unwrap()
+                .starts_with("I cannot access '/etc'")
+        );
+        assert!(escaping["cwd"].is_null());
+    }
+
+    /// A variable of the test process that bash commands don't get unless passed through
+    fn process_only_env_var() -> String {
+        std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .find(|name| !BASH_ENV_WHITELIST.contains(&name.as_str()) && !is_secret_env_name(name))
+            .expect("the test process has variables besides the whitelist")
+    }
+
+    /// Names of the variables printed by `env`, without the ones the shell sets itself
+    fn env_names(output: &str) -> Vec<&str> {
+        output
+            .lines()
+            .filter_map(|line| line.split_once('=').map(|(name, _)| name))
+            .filter(|name| !["PWD", "SHLVL", "_"].contains(name))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_bash_does_not_see_secret_env_vars() {
+        // The process environment isn't changed: set_var races with tests reading it
+        let env = |name: &str| match name {
+            "PATH" => Some("/usr/bin".into()),
+            "SQUID_TEST_BASH_API_KEY" => Some("hunter2".into()),
+            "SQUID_TEST_BASH_VISIBLE" => Some("shown".into()),
+            _ => None,
+        };
+        let passthrough = vec![
+            "SQUID_TEST_BASH_API_KEY".to_string(),
+            "SQUID_TEST_BASH_VISIBLE".to_string(),
+        ];
+        let names: Vec<String> = bash_env(&passthrough, env)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["PATH", "SQUID_TEST_BASH_VISIBLE"]);
+
+        // Commands only see the whitelist and what is passed through
+        let visible = process_only_env_var();
+        let bash = BashConfig {
+            env_passthrough: vec![visible.clone()],
+            ..BashConfig::default()
+        };
+        let output = execute_bash("env", 5, None, &bash, None).await.unwrap();
+        assert!(env_names(&output).contains(&visible.as_str()));
+
+        let output = execute_bash("env", 5, None, &BashConfig::default(), None)
+            .await
+            .unwrap();
+        let names = env_names(&output);
+        assert!(
+            names.iter().all(|name| BASH_ENV_WHITELIST.contains(name)),
+            "unexpected variables: {:?}",
+            names
+        );
+    }
+
+    #[tokio::test]
+    async fn test_restricted_bash_ignores_passthrough_and_enforces_time_limit() {
+        let temp = tempfile::tempdir().unwrap();
+        let passed = process_only_env_var();
+        let bash = BashConfig {
+            env_passthrough: vec![passed.clone()],
+            sandbox: SandboxMode::Restricted,
+            sandbox_time_limit_secs: 1,
+        };
+
+        let output = execute_bash("env", 5, Some(temp.path()), &bash, None)
+            .await
+            .unwrap();
+        assert!(!env_names(&output).contains(&passed.as_str()));
+
+        let err = execute_bash("sleep 5", 10, Some(temp.path()), &bash, None)
+            .await
+            .unwrap_err();
+        assert_eq!(err, "Command exceeded the sandbox time limit of 1 seconds");
+    }
+
+    #[test]
+    fn test_secret_env_names() {
+        assert!(is_secret_env_name("OPENAI_API_KEY"));
+        assert!(is_secret_env_name("github_token"));
+        assert!(is_secret_env_name("CLIENT_SECRET"));
+        assert!(!is_secret_env_name("KEYBOARD"));
+        assert!(!is_secret_env_name("PATH"));
+    }
+
+    #[tokio::test]
+    async fn test_bash_output_streams_before_completion() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let execution = tokio::spawn(async move {
+            execute_bash(
+                "for i in 1 2 3; do echo line$i; sleep 0.3; done; echo oops >&2",
+                10,
+                None,
+                &BashConfig::default(),
+                Some(ToolOutput::channel(sender)),
+            )
+            .await
+        });
+
+        let first = tokio::time::timeout(std::time::Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.text, "line1");
+        assert_eq!(first.stream, OutputStream::Stdout);
+        assert!(!execution.is_finished());
+
+        let output = execution.await.unwrap().unwrap();
+        assert_eq!(output, "line1\nline2\nline3");
+
+        let mut rest = Vec::new();
+        while let Ok(chunk) = receiver.try_recv() {
+            rest.push(chunk);
+        }
+        assert_eq!(
+            rest,
+            vec![
+                ToolOutputChunk {
+                    stream: OutputStream::Stdout,
+                    text: "line2".to_string()
+                },
+                ToolOutputChunk {
+                    stream: OutputStream::Stdout,
+                    text: "line3".to_string()
+                },
+                ToolOutputChunk {
+                    stream: OutputStream::Stderr,
+                    text: "oops".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_streamed_output_is_capped() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut output = ToolOutput::channel(sender);
+        let line = "x".repeat(1023);
+        for _ in 0..100 {
+            output.line(OutputStream::Stdout, &line);
+        }
+
+        let mut chunks = Vec::new();
+        while let Ok(chunk) = receiver.try_recv() {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks.len(), MAX_STREAMED_OUTPUT_BYTES / 1024);
+        assert!(chunks.last().unwrap().text.contains("output limit reached"));
+    }
+
+    #[test]
+    fn test_preview_reports_inaccessible_path() {
+        let validator = PathValidator::new();
+        let preview = build_tool_preview(
+            "write_file",
+            &json!({"path": "/etc/passwd", "content": "x"}),
+            &validator,
+            &BashConfig::default(),
+        )
+        .unwrap();
+
+        assert!(preview.summary.contains("not accessible"));
+        assert!(preview.diff.is_none());
+        assert!(preview.path.is_none());
+    }
+
+    #[test]
+    fn test_no_preview_for_other_tools() {
+        assert!(
+            build_tool_preview(
+                "now",
+                &json!({}),
+                &PathValidator::new(),
+                &BashConfig::default()
+            )
+            .is_none()
+        );
+    }
+
+    fn utc(s: &str) -> chrono::DateTime<Utc> {
+        chrono::DateTime::parse_from_rfc3339(s)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_time_offset() {
+        assert_eq!(parse_time_offset("+3d"), Ok((3, 0)));
+        assert_eq!(parse_time_offset("-2h"), Ok((0, -7200)));
+        assert_eq!(parse_time_offset("1w2d"), Ok((9, 0)));
+        assert_eq!(parse_time_offset("-1d2h30m15s"), Ok((-1, -9015)));
+        assert!(parse_time_offset("3 days").is_err());
+        assert!(parse_time_offset("+").is_err());
+        assert!(parse_time_offset("+2x").is_err());
+        assert!(parse_time_offset("+99999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_now_day_offset_keeps_wall_clock_across_dst() {
+        // Europe/Kyiv switches from EET (+02) to EEST (+03) on 2026-03-29
+        let base = utc("2026-03-28T10:00:00Z");
+        let result = current_time(
+            base,
+            &json!({"timezone": "Europe/Kyiv", "offset": "+1d", "format": "%Y-%m-%d %H:%M %Z"}),
+        );
+
+        assert_eq!(result["datetime"], "2026-03-29T12:00:00+03:00");
+        assert_eq!(result["formatted"], "2026-03-29 12:00 EEST");
+        assert_eq!(result["timezone"], "Europe/Kyiv");
+        // Only 23 hours pass because of the DST switch
+        assert_eq!(
+            result["timestamp"].as_i64().unwrap() - base.timestamp(),
+            23 * 3600
+        );
+    }
+
+    #[test]
+    fn test_now_offset_into_dst_gap_falls_back_to_absolute_shift() {
+        // 02:30 on 2026-03-08 doesn't exist in New York (clocks jump from 02:00 to 03:00)
+        let base = utc("2026-03-07T07:30:00Z");
+        let result = current_time(
+            base,
+            &json!({"timezone": "America/New_York", "offset": "+1d"}),
+        );
+        assert_eq!(result["datetime"], "2026-03-08T03:30:00-04:00");
+
+        let hours = current_time(
+            base,
+            &json!({"timezone": "America/New_York", "offset": "-2h"}),
+        );
+        assert_eq!(hours["datetime"], "2026-03-07T00:30:00-05:00");
+    }
+
+    #[test]
+    fn test_now_invalid_arguments_return_structured_errors() {
+        let base = utc("2026-01-01T00:00:00Z");
+
+        let result = current_time(base, &json!({"timezone": "Mars/Olympus"}));
+        assert_eq!(result["parameter"], "timezone");
+        assert!(result["error"].as_str().unwrap().contains("Mars/Olympus"));
+
+        let result = current_time(base, &json!({"timezone": "UTC", "format": "%Q"}));
+        assert_eq!(result["parameter"], "format");
+
+        let result = current_time(base, &json!({"timezone": "UTC", "offset": "soon"}));
+        assert_eq!(result["parameter"], "offset");
+    }
+
+    #[test]
+    fn test_now_defaults_to_rfc3339() {
+        let result = current_time(utc("2026-01-01T00:00:00Z"), &json!({"timezone": "UTC"}));
+        assert_eq!(result["datetime"], "2026-01-01T00:00:00+00:00");
+        assert_eq!(result["formatted"], result["datetime"]);
+        assert_eq!(result["timestamp"], 1767225600);
+    }
+}
@@ -0,0 +1,323 @@
+//! Opt-in capture of provider requests for debugging (prompt inspector)
+//!
+//! When `debug.capture_requests` is on (or a chat request sets `"debug": true`), every request
+//! sent to the model is stored in `request_traces` with the exact messages, tool schemas and
+//! parameters, plus a summary of the response. The API key is never stored.
+
+use async_openai::types::chat::{ChatCompletionRequestMessage, ChatCompletionTools};
+use serde::Serialize;
+use serde_json::{Value, json};
+
+use crate::config::Config;
+use crate::providers::{ChatParams, ToolChoice};
+use crate::text::json_escaped_len;
+
+/// Replacement for redacted values
+const REDACTED: &str = "[REDACTED]";
+
+/// Object keys whose values are always redacted
+const SENSITIVE_KEYS: &[&str] = &[
+    "api_key",
+    "apikey",
+    "authorization",
+    "password",
+    "secret",
+    "access_token",
+];
+
+/// A stored provider request with a summary of its response
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestTrace {
+    pub id: String,
+    pub session_id: String,
+    /// Assistant message produced by the request, once it has been saved
+    pub message_id: Option<i64>,
+    pub model: String,
+    pub request: Value,
+    pub response: Option<Value>,
+    /// Long strings in `request` were shortened to fit `debug.max_trace_bytes`
+    pub truncated: bool,
+    pub created_at: i64,
+}
+
+/// Key facts about a provider response
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ResponseSummary {
+    pub stop_reason: Option<String>,
+    pub input_tokens: Option<i64>,
+    pub output_tokens: Option<i64>,
+    /// Names of the tools the model asked to call
+    pub tool_calls: Vec<String>,
+    pub content_chars: usize,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl RequestTrace {
+    /// Record an outgoing request: redacted and capped to `debug.max_trace_bytes`
+    pub fn capture(
+        session_id: &str,
+        messages: &[ChatCompletionRequestMessage],
+        tools: &[ChatCompletionTools],
+        params: &ChatParams<'_>,
+        config: &Config,
+    ) -> Self {
+        let mut request = json!({
+            "provider": config.provider_kind,
+            "api_url": config.api_url,
+            "params": {
+                "model": params.model,
+            },
+            "messages": messages,
+            "tools": tools,
+        });
+        if !params.reasoning.is_empty() {
+            request["params"]["reasoning"] = json!(params.reasoning);
+        }
+        if params.tool_choice != ToolChoice::Auto {
+            request["params"]["tool_choice"] = json!(params.tool_choice);
+        }
+
+        let api_key = config.get_api_key();
+        let mut secrets = vec![api_key.as_str()];
+        secrets.extend(config.api_key.as_deref());
+        redact(&mut request, &secrets);
+        let truncated = cap_size(&mut request, config.debug.max_trace_bytes);
+
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            session_id: session_id.to_string(),
+            message_id: None,
+            model: params.model.to_string(),
+            request,
+            response: None,
+            truncated,
+            created_at: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Remove credentials from a JSON value
+///
+/// Values of sensitive keys are replaced, and any string containing one of `secrets` has it
+/// masked. Secrets shorter than 8 characters and the `not-needed` placeholder key are ignored
+/// to avoid masking ordinary words.
+pub fn redact(value: &mut Value, secrets: &[&str]) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SENSITIVE_KEYS.contains(&key.to_lowercase().as_str()) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value, secrets);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                redact(item, secrets);
+            }
+        }
+        Value::String(text) => {
+            let secrets = secrets
+                .iter()
+                .filter(|secret| secret.len() >= 8 && **secret != "not-needed");
+            for secret in secrets {
+                if text.contains(secret) {
+                    *text = text.replace(secret, REDACTED);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Shorten the longest strings until the serialized value fits in `max_bytes`
+///
+/// Each string is cut at most once, longest first, to the length that removes the remaining
+/// excess with room for the marker; a string with no room left becomes the marker alone.
+/// Returns whether anything was shortened. A value whose structure alone exceeds the cap
+/// stays over it.
+pub fn cap_size(value: &mut Value, max_bytes: usize) -> bool {
+    let size = serde_json::to_string(value).map(|s| s.len()).unwrap_or(0);
+    if size <= max_bytes {
+        return false;
+    }
+
+    let mut strings = Vec::new();
+    collect_strings(value, &mut strings);
+    strings.sort_by_key(|text| std::cmp::Reverse(text.len()));
+
+    let mut excess = size - max_bytes;
+    let mut truncated = false;
+    for text in strings {
+        if excess == 0 {
+            break;
+        }
+        let encoded: usize = text.chars().map(json_escaped_len).sum();
+        // Upper bound: the marker never counts more removed bytes than the string has
+        let marker_len = truncation_marker(text.len()).len();
+        if encoded <= marker_len {
+            continue;
+        }
+
+        let budget = encoded.saturating_sub(excess + marker_len);
+        let mut kept = 0;
+        let mut cut = 0;
+        for (index, c) in text.char_indices() {
+            kept += json_escaped_len(c);
+            if kept > budget {
+                break;
+            }
+            cut = index + c.len_utf8();
+        }
+
+        let marker = truncation_marker(text.len() - cut);
+        let kept_encoded: usize = text[..cut].chars().map(json_escaped_len).sum();
+        excess = excess.saturating_sub(encoded - kept_encoded - marker.len());
+        text.truncate(cut);
+        text.push_str(&marker);
+        truncated = true;
+    }
+    truncated
+}
+
+fn truncation_marker(removed: usize) -> String {
+    format!("… [truncated {} bytes]", removed)
+}
+
+fn collect_strings<'a>(value: &'a mut Value, out: &mut Vec<&'a mut String>) {
+    match value {
+        Value::String(text) => out.push(text),
+        Value::Array(items) => items.iter_mut().for_each(|item| collect_strings(item, out)),
+        Value::Object(map) => map.values_mut().for_each(|item| collect_strings(item, out)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_openai::types::chat::ChatCompletionRequestUserMessage;
+
+    #[test]
+    fn test_capture_never_stores_api_key() {
+        let config = Config {
+            api_key: Some("sk-test-0123456789".to_string()),
+            ..Config::default()
+        };
+        let messages: Vec<ChatCompletionRequestMessage> = vec![
+            ChatCompletionRequestUserMessage {
+                content: "my key is sk-test-0123456789, keep it safe".into(),
+                ..Default::default()
+            }
+            .into(),
+        ];
+
+        let trace = RequestTrace::capture(
+            "session-1",
+            &messages,
+            &[],
+            &ChatParams {
+                model: "qwen",
+                reasoning: Default::default(),
+                tool_choice: Default::default(),
+            },
+            &config,
+        );
+
+        let stored = trace.request.to_string();
+        assert!(!stored.contains("sk-test-0123456789"));
+        assert!(stored.contains("my key is [REDACTED], keep it safe"));
+        assert_eq!(trace.request["params"]["model"], "qwen");
+        assert_eq!(trace.model, "qwen");
+        assert!(!trace.truncated);
+        assert!(trace.request["params"].get("tool_choice").is_none());
+
+        let forced = RequestTrace::capture(
+            "session-1",
+            &messages,
+            &[],
+            &ChatParams {
+                model: "qwen",
+                reasoning: Default::default(),
+                tool_choice: ToolChoice::Tool("read_file".to_string()),
+            },
+            &config,
+        );
+        assert_eq!(
+            forced.request["params"]["tool_choice"],
+            json!({"name": "read_file"})
+        );
+
+        let mut headers = json!({"headers": {"Authorization": "Bearer abc"}, "n": 1});
+        redact(&mut headers, &[]);
+        assert_eq!(headers["headers"]["Authorization"], REDACTED);
+        assert_eq!(headers["n"], 1);
+    }
+
+    #[test]
+    fn test_cap_size_shortens_longest_strings() {
+        let mut value = json!({
+            "messages": [
+                {"role": "system", "content": "x".repeat(10_000)},
+                {"role": "user", "content": "short question"},
+            ]
+        });
+
+        assert!(cap_size(&mut value, 2_000));
+        assert!(serde_json::to_string(&value).unwrap().len() <= 2_000);
+        assert_eq!(value["messages"][1]["content"], "short question");
+        assert!(
+            value["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .ends_with("bytes]")
+        );
+
+        let mut small = json!({"content": "ok"});
+        assert!(!cap_size(&mut small, 2_000));
+    }
+
+    #[test]
+    fn test_cap_size_strings_just_over_the_cap() {
+        let mut value = json!({"content": "x".repeat(1_000)});
+        let size = serde_json::to_string(&value).unwrap().len();
+
+        assert!(cap_size(&mut value, size - 1));
+        let capped = serde_json::to_string(&value).unwrap();
+        assert!(capped.len() < size);
+        // Only as much as needed is removed
+        assert!(capped.len() >= size - 1 - truncation_marker(1_000).len());
+        assert!(value["content"].as_str().unwrap().ends_with("bytes]"));
+    }
+
+    #[test]
+    fn test_cap_size_many_mid_sized_strings() {
+        let messages: Vec<Value> = (0..50)
+            .map(|i| {
+                let content = format!("{} \"quoted\" \\path {}", i, "y".repeat(280));
+                json!({"role": "user", "content": content})
+            })
+            .collect();
+        let mut value = json!({ "messages": messages });
+
+        assert!(cap_size(&mut value, 4_000));
+        assert!(serde_json::to_string(&value).unwrap().len() <= 4_000);
+    }
+
+    #[test]
+    fn test_cap_size_tiny_caps() {
+        let mut value = json!({"content": "é".repeat(500)});
+        assert!(cap_size(&mut value, 60));
+        let content = value["content"].as_str().unwrap();
+        assert!(content.starts_with('é') && content.ends_with("bytes]"));
+        assert!(serde_json::to_string(&value).unwrap().len() <= 60);
+
+        // A cap smaller than the marker leaves just the marker
+        let mut value = json!({"content": "x".repeat(1_000)});
+        assert!(cap_size(&mut value, 10));
+        assert_eq!(value["content"], truncation_marker(1_000));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::config::Config;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use log::{debug, warn};
 use regex::Regex;
+use serde::Serialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Instant, SystemTime};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -10,14 +16,334 @@ pub enum PathValidationError {
     PathNotAllowed(String),
     #[error("Permission denied: {0}")]
     PermissionDenied(String),
-    #[error("Path is ignored by .squidignore: {0}")]
+    #[error("Path is ignored by .squidignore or .gitignore: {0}")]
     PathIgnored(String),
+    #[error("Not a directory: {0}")]
+    NotADirectory(String),
 }
 
 pub struct PathValidator {
     whitelist: Vec<PathBuf>,
     blacklist: Vec<PathBuf>,
-    ignore_patterns: Vec<String>,
+    ignore_rules: Vec<IgnoreRule>,
+    base_dir: Option<PathBuf>,
+    gitignore: Option<GitignoreRules>,
+}
+
+/// A .squidignore pattern compiled once when the validator is built
+struct IgnoreRule {
+    pattern: String,
+    negated: bool,
+    /// Patterns without a `/` match the file name only, like .gitignore
+    file_name_only: bool,
+    regex: Regex,
+    /// The .squidignore file and line the pattern was read from
+    origin: Option<(PathBuf, usize)>,
+}
+
+/// The checks [`PathValidator::validate`] made for a path, in order
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    /// The path as given
+    pub path: PathBuf,
+    /// The absolute path the checks ran on, once it could be resolved
+    pub resolved: Option<PathBuf>,
+    /// Checks performed; they stop at the first one that rejects the path
+    pub checks: Vec<ValidationCheck>,
+    pub allowed: bool,
+}
+
+/// One check made while validating a path
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationCheck {
+    pub rule: ValidationRule,
+    pub passed: bool,
+    pub detail: String,
+    /// The pattern or directory that decided the check
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// Ignore file the pattern came from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<PathBuf>,
+    /// Line of the pattern in `source` (.squidignore only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+}
+
+/// What a [`ValidationCheck`] looked at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValidationRule {
+    /// The path could be resolved on disk
+    Resolve,
+    /// Not inside a sensitive system directory
+    Blacklist,
+    /// Inside the workspace root
+    Workspace,
+    /// A symlink leads outside the workspace, or the link is ignored while its target isn't
+    Symlink,
+    Squidignore,
+    Gitignore,
+}
+
+impl ValidationRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationRule::Resolve => "resolve",
+            ValidationRule::Blacklist => "blacklist",
+            ValidationRule::Workspace => "workspace",
+            ValidationRule::Symlink => "symlink",
+            ValidationRule::Squidignore => "squidignore",
+            ValidationRule::Gitignore => "gitignore",
+        }
+    }
+}
+
+impl ValidationCheck {
+    fn new(rule: ValidationRule, passed: bool, detail: impl Into<String>) -> Self {
+        Self {
+            rule,
+            passed,
+            detail: detail.into(),
+            pattern: None,
+            source: None,
+            line: None,
+        }
+    }
+
+    fn with_pattern(mut self, pattern: impl Into<String>) -> Self {
+        self.pattern = Some(pattern.into());
+        self
+    }
+
+    fn with_ignore_rule(self, rule: &IgnoreRule) -> Self {
+        let (source, line) = match &rule.origin {
+            Some((source, line)) => (Some(source.clone()), Some(*line)),
+            None => (None, None),
+        };
+        Self {
+            source,
+            line,
+            ..self.with_pattern(&rule.pattern)
+        }
+    }
+}
+
+impl ValidationReport {
+    /// The check that rejected the path, if any
+    pub fn denied_by(&self) -> Option<&ValidationCheck> {
+        self.checks.iter().find(|check| !check.passed)
+    }
+
+    /// Add a failed check and return the finished report
+    fn deny(mut self, check: ValidationCheck) -> Self {
+        self.checks.push(check);
+        self.allowed = false;
+        self
+    }
+
+    fn into_result(self) -> Result<PathBuf, PathValidationError> {
+        let resolved = self.resolved.unwrap_or_else(|| self.path.clone());
+        let Some(check) = self.checks.into_iter().find(|check| !check.passed) else {
+            return Ok(resolved);
+        };
+        Err(match check.rule {
+            ValidationRule::Resolve => PathValidationError::PermissionDenied(check.detail),
+            ValidationRule::Blacklist | ValidationRule::Workspace | ValidationRule::Symlink => {
+                PathValidationError::PathNotAllowed(check.detail)
+            }
+            ValidationRule::Squidignore | ValidationRule::Gitignore => {
+                PathValidationError::PathIgnored(resolved.display().to_string())
+            }
+        })
+    }
+}
+
+impl IgnoreRule {
+    fn new(pattern: &str) -> Option<Self> {
+        let (negated, glob) = match pattern.strip_prefix('!') {
+            Some(glob) => (true, glob),
+            None => (false, pattern),
+        };
+        match Regex::new(&PathValidator::glob_to_regex(glob)) {
+            Ok(regex) => Some(Self {
+                pattern: pattern.to_string(),
+                negated,
+                file_name_only: !glob.contains('/') && !glob.starts_with("**"),
+                regex,
+                origin: None,
+            }),
+            Err(_) => {
+                warn!("Invalid pattern: {}", pattern);
+                None
+            }
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        if self.file_name_only {
+            Path::new(path)
+                .file_name()
+                .is_some_and(|name| self.regex.is_match(&name.to_string_lossy()))
+        } else {
+            self.regex.is_match(path)
+        }
+    }
+}
+
+/// Validators kept in [`VALIDATOR_CACHE`]; one per working directory a server has seen
+const MAX_CACHED_VALIDATORS: usize = 64;
+
+/// Validators shared between tool calls, see [`PathValidator::cached`]
+static VALIDATOR_CACHE: LazyLock<Mutex<HashMap<CacheKey, CachedValidator>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct CacheKey {
+    root: PathBuf,
+    ignore_root: PathBuf,
+    base_dir: Option<PathBuf>,
+    respect_gitignore: bool,
+}
+
+struct CachedValidator {
+    /// Modification times of the ignore files the validator was built from
+    mtimes: Vec<Option<SystemTime>>,
+    validator: Arc<PathValidator>,
+    last_used: Instant,
+}
+
+/// Add `entry` to `cache`, first dropping the least recently used entry when `limit` is reached
+fn insert_bounded(
+    cache: &mut HashMap<CacheKey, CachedValidator>,
+    key: CacheKey,
+    entry: CachedValidator,
+    limit: usize,
+) {
+    if cache.len() >= limit
+        && !cache.contains_key(&key)
+        && let Some(oldest) = cache
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone())
+    {
+        cache.remove(&oldest);
+    }
+    cache.insert(key, entry);
+}
+
+impl CacheKey {
+    fn ignore_file_mtimes(&self) -> Vec<Option<SystemTime>> {
+        let mut files = vec![self.ignore_root.join(".squidignore")];
+        if self.respect_gitignore {
+            files.push(self.root.join(".gitignore"));
+            files.push(self.root.join(".git").join("info").join("exclude"));
+        }
+        files
+            .iter()
+            .map(|file| fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// .gitignore rules for a directory tree
+///
+/// Covers `.git/info/exclude` and every `.gitignore` from the repository root down,
+/// loading nested files lazily as paths in their directories are checked.
+struct GitignoreRules {
+    /// Repository root, or the validator root when it isn't inside a git repository
+    top: PathBuf,
+    exclude: Option<Gitignore>,
+    per_dir: Mutex<HashMap<PathBuf, Option<Gitignore>>>,
+}
+
+impl GitignoreRules {
+    fn new(root: &Path) -> Self {
+        let root = fs::canonicalize(root).unwrap_or_else(|_| root.to_path_buf());
+        let top = root
+            .ancestors()
+            .find(|dir| dir.join(".git").exists())
+            .unwrap_or(&root)
+            .to_path_buf();
+        let exclude = Self::load(&top, &top.join(".git").join("info").join("exclude"));
+
+        Self {
+            top,
+            exclude,
+            per_dir: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn load(dir: &Path, file: &Path) -> Option<Gitignore> {
+        if !file.is_file() {
+            return None;
+        }
+        let mut builder = GitignoreBuilder::new(dir);
+        if let Some(e) = builder.add(file) {
+            warn!("Failed to parse {}: {}", file.display(), e);
+        }
+        match builder.build() {
+            Ok(gitignore) if !gitignore.is_empty() => Some(gitignore),
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Failed to load {}: {}", file.display(), e);
+                None
+            }
+        }
+    }
+
+    /// The rule deciding `path`, or `None` if no rule matches
+    fn matched(&self, path: &Path, is_dir: bool) -> Option<GitignoreMatch> {
+        if !path.starts_with(&self.top) || path == self.top {
+            return None;
+        }
+
+        // Deeper .gitignore files take precedence, as in git
+        let mut per_dir = self.per_dir.lock().unwrap();
+        for dir in path.ancestors().skip(1) {
+            let gitignore = per_dir
+                .entry(dir.to_path_buf())
+                .or_insert_with(|| Self::load(dir, &dir.join(".gitignore")));
+            if let Some(gitignore) = gitignore {
+                let m = gitignore.matched_path_or_any_parents(path, is_dir);
+                if !m.is_none() {
+                    return Some(GitignoreMatch::new(&m));
+                }
+            }
+            if dir == self.top {
+                break;
+            }
+        }
+
+        self.exclude.as_ref().and_then(|exclude| {
+            let m = exclude.matched_path_or_any_parents(path, is_dir);
+            (!m.is_none()).then(|| GitignoreMatch::new(&m))
+        })
+    }
+}
+
+/// A .gitignore rule that matched a path
+struct GitignoreMatch {
+    /// `false` when the path was re-included with `!pattern`
+    ignored: bool,
+    pattern: String,
+    source: Option<PathBuf>,
+}
+
+impl GitignoreMatch {
+    fn new(m: &ignore::Match<&ignore::gitignore::Glob>) -> Self {
+        Self {
+            ignored: m.is_ignore(),
+            pattern: m
+                .inner()
+                .map(|glob| glob.original().to_string())
+                .unwrap_or_default(),
+            source: m
+                .inner()
+                .and_then(|glob| glob.from())
+                .map(Path::to_path_buf),
+        }
+    }
 }
 
 impl PathValidator {
@@ -29,10 +355,149 @@ impl PathValidator {
     /// Create a new PathValidator with optional custom ignore patterns
     pub fn with_ignore_file(ignore_patterns: Option<Vec<String>>) -> Self {
         // Default whitelist: current directory and subdirectories
-        let whitelist = vec![
-            PathBuf::from("."),
-            std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
-        ];
+        let current_dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+        Self::with_root(&current_dir, ignore_patterns)
+    }
+
+    /// Create a PathValidator for a session working directory
+    ///
+    /// Relative paths resolve against `dir` instead of the process working directory,
+    /// and the directory's own .squidignore applies.
+    pub fn for_working_dir(dir: &Path) -> Self {
+        let mut validator = Self::with_squidignore(dir, dir);
+        validator.base_dir = Some(dir.to_path_buf());
+        validator
+    }
+
+    /// Validator for `root` using `ignore_root/.squidignore`, remembering where each pattern came from
+    fn with_squidignore(root: &Path, ignore_root: &Path) -> Self {
+        let mut validator = Self::with_root(root, None);
+        let ignore_file = ignore_root.join(".squidignore");
+        validator.ignore_rules = Self::read_ignore_file(&ignore_file)
+            .into_iter()
+            .filter_map(|(line, pattern)| {
+                IgnoreRule::new(&pattern).map(|rule| IgnoreRule {
+                    origin: Some((ignore_file.clone(), line)),
+                    ..rule
+                })
+            })
+            .collect();
+        validator
+    }
+
+    /// Shared validator anchored to the project root, using the project's .squidignore
+    ///
+    /// Rebuilt only when an ignore file changes.
+    pub fn cached_for_project(respect_gitignore: bool) -> Arc<Self> {
+        let root = Config::find_project_root();
+        Self::cached(CacheKey {
+            root: root.clone(),
+            ignore_root: root,
+            base_dir: None,
+            respect_gitignore,
+        })
+    }
+
+    /// Shared [`PathValidator::for_working_dir`] validator, rebuilt only when an ignore file changes
+    pub fn cached_for_working_dir(dir: &Path, respect_gitignore: bool) -> Arc<Self> {
+        Self::cached(CacheKey {
+            root: dir.to_path_buf(),
+            ignore_root: dir.to_path_buf(),
+            base_dir: Some(dir.to_path_buf()),
+            respect_gitignore,
+        })
+    }
+
+    /// Shared validator for the current directory with the project's .squidignore
+    pub fn cached_for_current_dir(respect_gitignore: bool) -> Arc<Self> {
+        Self::cached(CacheKey {
+            root: std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")),
+            ignore_root: Config::find_project_root(),
+            base_dir: None,
+            respect_gitignore,
+        })
+    }
+
+    /// Look up a validator in the process-wide cache, building it on first use
+    ///
+    /// The entry is rebuilt when `.squidignore` (or, with `respect_gitignore`, the root
+    /// `.gitignore` or `.git/info/exclude`) has a different modification time than when
+    /// it was built. Nested `.gitignore` files are read once per cached validator.
+    fn cached(key: CacheKey) -> Arc<Self> {
+        let mtimes = key.ignore_file_mtimes();
+        let mut cache = VALIDATOR_CACHE.lock().unwrap();
+        if let Some(entry) = cache.get_mut(&key)
+            && entry.mtimes == mtimes
+        {
+            entry.last_used = Instant::now();
+            return entry.validator.clone();
+        }
+
+        debug!("Building path validator for {}", key.root.display());
+        let validator = match &key.base_dir {
+            Some(dir) => Self::for_working_dir(dir),
+            None => Self::with_squidignore(&key.root, &key.ignore_root),
+        };
+        let validator = Arc::new(validator.respect_gitignore(key.respect_gitignore));
+        insert_bounded(
+            &mut cache,
+            key,
+            CachedValidator {
+                mtimes,
+                validator: validator.clone(),
+                last_used: Instant::now(),
+            },
+            MAX_CACHED_VALIDATORS,
+        );
+        validator
+    }
+
+    /// Also honor .gitignore files and .git/info/exclude under the validator root
+    ///
+    /// `.squidignore` keeps precedence, so its `!pattern` entries can re-include gitignored paths.
+    pub fn respect_gitignore(mut self, enabled: bool) -> Self {
+        self.gitignore = if enabled {
+            self.whitelist.first().map(|root| GitignoreRules::new(root))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Check that `dir` can be used as a session working directory
+    ///
+    /// The directory must exist and must not be inside a blacklisted system path.
+    pub fn validate_working_dir(dir: &Path) -> Result<PathBuf, PathValidationError> {
+        let canonical = fs::canonicalize(dir).map_err(|e| {
+            PathValidationError::PermissionDenied(format!("{}: {}", dir.display(), e))
+        })?;
+
+        if !canonical.is_dir() {
+            return Err(PathValidationError::NotADirectory(
+                canonical.display().to_string(),
+            ));
+        }
+
+        if Self::new().is_blacklisted(&canonical) {
+            return Err(PathValidationError::PathNotAllowed(format!(
+                "Path is in blacklisted directory: {}",
+                canonical.display()
+            )));
+        }
+
+        Ok(canonical)
+    }
+
+    /// Directory that relative paths resolve against
+    pub fn base_dir(&self) -> PathBuf {
+        self.base_dir
+            .clone()
+            .unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_| PathBuf::from(".")))
+    }
+
+    /// Create a new PathValidator that whitelists `root` and its subdirectories
+    pub fn with_root(root: &Path, ignore_patterns: Option<Vec<String>>) -> Self {
+        let whitelist = vec![root.to_path_buf()];
 
         // Default blacklist: sensitive system paths
         let mut blacklist = vec![
@@ -64,37 +529,43 @@ impl PathValidator {
             blacklist.push(PathBuf::from("C:\\Program Files (x86)"));
         }
 
-        let ignore_patterns = ignore_patterns.unwrap_or_default();
+        let ignore_rules: Vec<IgnoreRule> = ignore_patterns
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|pattern| IgnoreRule::new(pattern))
+            .collect();
 
         debug!(
             "PathValidator initialized with {} whitelist entries, {} blacklist entries, {} ignore patterns",
             whitelist.len(),
             blacklist.len(),
-            ignore_patterns.len()
+            ignore_rules.len()
         );
 
         Self {
             whitelist,
             blacklist,
-            ignore_patterns,
+            ignore_rules,
+            base_dir: None,
+            gitignore: None,
         }
     }
 
-    /// Load ignore patterns from .squidignore file if it exists
-    pub fn load_ignore_patterns() -> Vec<String> {
-        let ignore_file = PathBuf::from(".squidignore");
+    /// Patterns in an ignore file with their 1-based line numbers
+    fn read_ignore_file(ignore_file: &Path) -> Vec<(usize, String)> {
         if !ignore_file.exists() {
             debug!("No .squidignore file found");
             return Vec::new();
         }
 
-        match fs::read_to_string(&ignore_file) {
+        match fs::read_to_string(ignore_file) {
             Ok(content) => {
-                let patterns: Vec<String> = content
+                let patterns: Vec<(usize, String)> = content
                     .lines()
-                    .map(|line| line.trim())
-                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
-                    .map(String::from)
+                    .enumerate()
+                    .map(|(index, line)| (index + 1, line.trim()))
+                    .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+                    .map(|(line, pattern)| (line, pattern.to_string()))
                     .collect();
 
                 debug!("Loaded {} patterns from .squidignore", patterns.len());
@@ -110,75 +581,234 @@ impl PathValidator {
     /// Validates a path against whitelist, blacklist, and ignore rules
     pub fn validate(&self, path: &Path) -> Result<PathBuf, PathValidationError> {
         debug!("Validating path: {}", path.display());
+        let result = self.explain(path).into_result();
+        if let Ok(canonical_path) = &result {
+            debug!("Path validation successful: {}", canonical_path.display());
+        }
+        result
+    }
+
+    /// Run the checks behind [`PathValidator::validate`] and report which rule decided
+    pub fn explain(&self, path: &Path) -> ValidationReport {
+        let report = ValidationReport {
+            path: path.to_path_buf(),
+            resolved: None,
+            checks: Vec::new(),
+            allowed: false,
+        };
+
+        // Anchor relative paths to the working directory, if one was set
+        let anchored;
+        let path = match &self.base_dir {
+            Some(base) if path.is_relative() => {
+                anchored = base.join(path);
+                anchored.as_path()
+            }
+            _ => path,
+        };
 
-        // Try to canonicalize, but don't fail if path doesn't exist yet (for write operations)
-        let canonical_path = if path.exists() {
-            fs::canonicalize(path).map_err(|e| {
-                PathValidationError::PermissionDenied(format!("{}: {}", path.display(), e))
-            })?
+        // The path as written, with . and .. resolved but symlinks left alone
+        let absolute = if path.is_absolute() {
+            path.to_path_buf()
         } else {
-            // For non-existent paths, resolve relative to current directory
-            let absolute = if path.is_absolute() {
-                path.to_path_buf()
-            } else {
-                std::env::current_dir()
-                    .unwrap_or_else(|_| PathBuf::from("."))
-                    .join(path)
-            };
+            std::env::current_dir()
+                .unwrap_or_else(|_| PathBuf::from("."))
+                .join(path)
+        };
+        let lexical_path = Self::normalize_path(&absolute);
 
-            // Clean the path (remove . and ..)
-            Self::normalize_path(&absolute)
+        // Resolve symlinks, also in the existing part of a path that doesn't exist yet (for
+        // write operations), so the target is checked rather than the link; dangling links fail
+        let canonical_path = match Self::resolve(path, &lexical_path) {
+            Ok(canonical_path) => canonical_path,
+            Err(e) => {
+                return report.deny(ValidationCheck::new(
+                    ValidationRule::Resolve,
+                    false,
+                    format!("{}: {}", path.display(), e),
+                ));
+            }
         };
 
         debug!("Canonical path: {}", canonical_path.display());
+        let mut report = ValidationReport {
+            resolved: Some(canonical_path.clone()),
+            ..report
+        };
 
         // Check blacklist first
-        for blocked in &self.blacklist {
-            let blocked_canonical = if blocked.exists() {
-                fs::canonicalize(blocked).unwrap_or_else(|_| blocked.clone())
-            } else {
-                blocked.clone()
-            };
+        if let Some(blocked) = self.blacklisted_by(&canonical_path) {
+            return report.deny(
+                ValidationCheck::new(
+                    ValidationRule::Blacklist,
+                    false,
+                    format!(
+                        "Path is in blacklisted directory: {}",
+                        canonical_path.display()
+                    ),
+                )
+                .with_pattern(blocked.display().to_string()),
+            );
+        }
+        report.checks.push(ValidationCheck::new(
+            ValidationRule::Blacklist,
+            true,
+            "Not in a blacklisted system directory",
+        ));
 
-            if canonical_path.starts_with(&blocked_canonical) {
-                return Err(PathValidationError::PathNotAllowed(format!(
-                    "Path is in blacklisted directory: {}",
-                    canonical_path.display()
-                )));
+        // Check whitelist
+        match self.whitelisted_by(&canonical_path) {
+            Some(root) => report.checks.push(
+                ValidationCheck::new(ValidationRule::Workspace, true, "Inside the workspace")
+                    .with_pattern(root.display().to_string()),
+            ),
+            // Inside the workspace as written: a symlink along the way leads out of it
+            None if self.whitelisted_by(&lexical_path).is_some() => {
+                return report.deny(ValidationCheck::new(
+                    ValidationRule::Symlink,
+                    false,
+                    format!(
+                        "Path is not in whitelisted directory: {} (symlink target of {})",
+                        canonical_path.display(),
+                        lexical_path.display()
+                    ),
+                ));
+            }
+            None => {
+                return report.deny(ValidationCheck::new(
+                    ValidationRule::Workspace,
+                    false,
+                    format!(
+                        "Path is not in whitelisted directory: {}",
+                        canonical_path.display()
+                    ),
+                ));
             }
         }
 
-        // Check whitelist
-        let mut is_whitelisted = false;
-        for allowed in &self.whitelist {
-            let allowed_canonical = if allowed.exists() {
-                fs::canonicalize(allowed).unwrap_or_else(|_| allowed.clone())
-            } else {
-                allowed.clone()
-            };
+        // A link must be allowed itself, not only its target
+        if lexical_path != canonical_path && self.is_ignored(&lexical_path) {
+            return report.deny(ValidationCheck::new(
+                ValidationRule::Symlink,
+                false,
+                format!(
+                    "Path is ignored as written: {} (symlink to {})",
+                    lexical_path.display(),
+                    canonical_path.display()
+                ),
+            ));
+        }
 
-            if canonical_path.starts_with(&allowed_canonical) {
-                is_whitelisted = true;
-                break;
+        // Check ignore patterns; .squidignore takes precedence over .gitignore
+        match self.squidignore_rule(&canonical_path) {
+            Some(rule) if !rule.negated => {
+                return report.deny(
+                    ValidationCheck::new(
+                        ValidationRule::Squidignore,
+                        false,
+                        "Matched a .squidignore pattern",
+                    )
+                    .with_ignore_rule(rule),
+                );
+            }
+            Some(rule) => {
+                report.checks.push(
+                    ValidationCheck::new(
+                        ValidationRule::Squidignore,
+                        true,
+                        "Re-included by a .squidignore pattern",
+                    )
+                    .with_ignore_rule(rule),
+                );
+                report.allowed = true;
+                return report;
             }
+            None => report.checks.push(ValidationCheck::new(
+                ValidationRule::Squidignore,
+                true,
+                "No .squidignore pattern matches",
+            )),
         }
 
-        if !is_whitelisted {
-            return Err(PathValidationError::PathNotAllowed(format!(
-                "Path is not in whitelisted directory: {}",
-                canonical_path.display()
-            )));
+        if let Some(gitignore) = &self.gitignore {
+            let check = match gitignore.matched(&canonical_path, canonical_path.is_dir()) {
+                Some(m) => {
+                    let detail = if m.ignored {
+                        debug!("Path {} matched .gitignore", canonical_path.display());
+                        "Matched a .gitignore pattern"
+                    } else {
+                        "Re-included by a .gitignore pattern"
+                    };
+                    ValidationCheck {
+                        source: m.source,
+                        ..ValidationCheck::new(ValidationRule::Gitignore, !m.ignored, detail)
+                            .with_pattern(m.pattern)
+                    }
+                }
+                None => ValidationCheck::new(
+                    ValidationRule::Gitignore,
+                    true,
+                    "No .gitignore pattern matches",
+                ),
+            };
+            if !check.passed {
+                return report.deny(check);
+            }
+            report.checks.push(check);
         }
 
-        // Check ignore patterns
-        if self.is_ignored(&canonical_path) {
-            return Err(PathValidationError::PathIgnored(
-                canonical_path.display().to_string(),
-            ));
-        }
+        report.allowed = true;
+        report
+    }
+
+    /// Check if a canonical path is inside a blacklisted directory
+    fn is_blacklisted(&self, canonical_path: &Path) -> bool {
+        self.blacklisted_by(canonical_path).is_some()
+    }
 
-        debug!("Path validation successful: {}", canonical_path.display());
-        Ok(canonical_path)
+    /// The blacklisted directory containing `canonical_path`
+    fn blacklisted_by(&self, canonical_path: &Path) -> Option<&PathBuf> {
+        self.blacklist.iter().find(|blocked| {
+            let blocked_canonical = if blocked.exists() {
+                fs::canonicalize(blocked).unwrap_or_else(|_| (*blocked).clone())
+            } else {
+                (*blocked).clone()
+            };
+            canonical_path.starts_with(&blocked_canonical)
+        })
+    }
+
+    /// The whitelisted directory containing `path`
+    fn whitelisted_by(&self, path: &Path) -> Option<&PathBuf> {
+        self.whitelist.iter().find(|allowed| {
+            let allowed_canonical = if allowed.exists() {
+                fs::canonicalize(allowed).unwrap_or_else(|_| (*allowed).clone())
+            } else {
+                (*allowed).clone()
+            };
+            path.starts_with(&allowed_canonical)
+        })
+    }
+
+    /// `path` with every symlink resolved; a missing tail is appended to its resolved parent
+    fn resolve(path: &Path, lexical_path: &Path) -> std::io::Result<PathBuf> {
+        if fs::symlink_metadata(path).is_ok() {
+            return fs::canonicalize(path);
+        }
+        let mut existing = lexical_path;
+        let mut missing = Vec::new();
+        while fs::symlink_metadata(existing).is_err() {
+            match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name);
+                    existing = parent;
+                }
+                _ => return Ok(lexical_path.to_path_buf()),
+            }
+        }
+        let mut resolved = fs::canonicalize(existing)?;
+        resolved.extend(missing.iter().rev());
+        Ok(resolved)
     }
 
     /// Normalize a path by resolving . and .. components
@@ -196,44 +826,41 @@ impl PathValidator {
         components.iter().collect()
     }
 
-    /// Check if a path matches any ignore pattern
-    fn is_ignored(&self, path: &Path) -> bool {
-        let path_str = path.to_string_lossy();
-
-        for pattern in &self.ignore_patterns {
-            if self.matches_pattern(&path_str, pattern) {
-                debug!("Path {} matched ignore pattern: {}", path_str, pattern);
-                return true;
-            }
+    /// Check if a path is excluded by .squidignore or, when enabled, .gitignore
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        if let Some(rule) = self.squidignore_rule(path) {
+            return !rule.negated;
         }
 
-        false
-    }
-
-    /// Check if a path matches a glob-like pattern
-    fn matches_pattern(&self, path: &str, pattern: &str) -> bool {
-        // If pattern has no path separator, match against filename only
-        // This allows ".env" to match "/path/to/.env" like .gitignore does
-        if !pattern.contains('/') && !pattern.starts_with("**") {
-            if let Some(filename) = std::path::Path::new(path).file_name() {
-                let filename_str = filename.to_string_lossy();
-                let regex_pattern = Self::glob_to_regex(pattern);
-                if let Ok(regex) = Regex::new(&regex_pattern) {
-                    return regex.is_match(&filename_str);
+        match &self.gitignore {
+            Some(gitignore) => {
+                let ignored = gitignore
+                    .matched(path, path.is_dir())
+                    .is_some_and(|m| m.ignored);
+                if ignored {
+                    debug!("Path {} matched .gitignore", path.display());
                 }
+                ignored
             }
-            return false;
+            None => false,
         }
+    }
 
-        // For patterns with paths, match against full path
-        let regex_pattern = Self::glob_to_regex(pattern);
+    /// Match a path against .squidignore patterns; the last matching pattern wins
+    ///
+    /// The returned rule is negated when a `!pattern` re-includes the path.
+    fn squidignore_rule(&self, path: &Path) -> Option<&IgnoreRule> {
+        let path_str = path.to_string_lossy();
+        let mut result = None;
 
-        if let Ok(regex) = Regex::new(&regex_pattern) {
-            regex.is_match(path)
-        } else {
-            warn!("Invalid pattern: {}", pattern);
-            false
+        for rule in &self.ignore_rules {
+            if rule.matches(&path_str) {
+                debug!("Path {} matched ignore pattern: {}", path_str, rule.pattern);
+                result = Some(rule);
+            }
         }
+
+        result
     }
 
     /// Convert a simple glob pattern to regex
@@ -302,11 +929,12 @@ mod tests {
 
     #[test]
     fn test_matches_pattern() {
-        let validator = PathValidator::new();
+        let matches = |path: &str, pattern: &str| IgnoreRule::new(pattern).unwrap().matches(path);
 
-        assert!(validator.matches_pattern("test.txt", "*.txt"));
-        assert!(validator.matches_pattern("src/main.rs", "**/*.rs"));
-        assert!(!validator.matches_pattern("test.rs", "*.txt"));
+        assert!(matches("test.txt", "*.txt"));
+        assert!(matches("src/main.rs", "**/*.rs"));
+        assert!(!matches("test.rs", "*.txt"));
+        assert!(IgnoreRule::new("!keep.log").unwrap().negated);
     }
 
     #[test]
@@ -359,4 +987,287 @@ mod tests {
         assert!(validator.is_ignored(Path::new("/home/user/project/.env")));
         assert!(validator.is_ignored(Path::new("./subdir/.env")));
     }
+
+    #[test]
+    fn test_project_squidignore_from_subdirectory() {
+        let temp = tempfile::tempdir().unwrap();
+        let project = fs::canonicalize(temp.path()).unwrap();
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(
+            project.join("squid.config.json"),
+            r#"{"api_url": "http://127.0.0.1:1234/v1"}"#,
+        )
+        .unwrap();
+        fs::write(project.join(".squidignore"), "# secrets\n*.secret\n").unwrap();
+
+        let root = Config::find_project_root_from(&project.join("src"));
+        assert_eq!(root, project);
+
+        let patterns = PathValidator::read_ignore_file(&root.join(".squidignore"));
+        assert_eq!(patterns, vec![(2, "*.secret".to_string())]);
+
+        let validator = PathValidator::with_squidignore(&root, &root);
+        assert_eq!(validator.whitelist, vec![project.clone()]);
+        assert!(!validator.is_ignored(&project.join("README.md")));
+        assert!(validator.is_ignored(&project.join("api.secret")));
+    }
+
+    #[test]
+    fn test_working_dir_anchors_relative_paths() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(temp.path()).unwrap();
+        fs::write(dir.join("notes.md"), "hello").unwrap();
+        fs::write(dir.join(".squidignore"), "*.secret\n").unwrap();
+        fs::write(dir.join("api.secret"), "key").unwrap();
+
+        let validator = PathValidator::for_working_dir(&dir);
+        assert_eq!(validator.base_dir(), dir);
+        assert_eq!(
+            validator.validate(Path::new("notes.md")).unwrap(),
+            dir.join("notes.md")
+        );
+        // Files that don't exist yet resolve against the working dir too
+        assert_eq!(
+            validator.validate(Path::new("new/file.txt")).unwrap(),
+            dir.join("new/file.txt")
+        );
+        assert!(matches!(
+            validator.validate(Path::new("api.secret")),
+            Err(PathValidationError::PathIgnored(_))
+        ));
+        assert!(validator.validate(Path::new("../outside.txt")).is_err());
+    }
+
+    #[test]
+    fn test_explain_attributes_each_rejection() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(temp.path()).unwrap();
+        let workspace = dir.join("workspace");
+        let outside = dir.join("outside");
+        fs::create_dir_all(workspace.join("logs")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(workspace.join(".squidignore"), "# secrets\n\n*.env\n").unwrap();
+        fs::write(workspace.join(".gitignore"), "logs/\n").unwrap();
+        fs::write(workspace.join("main.rs"), "fn main() {}").unwrap();
+        fs::write(workspace.join(".env"), "KEY=1").unwrap();
+        fs::write(workspace.join("logs").join("app.log"), "log").unwrap();
+        fs::write(outside.join("notes.md"), "notes").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(outside.join("notes.md"), workspace.join("notes.md")).unwrap();
+
+        let validator = PathValidator::for_working_dir(&workspace).respect_gitignore(true);
+        let rules = |report: &ValidationReport| -> Vec<ValidationRule> {
+            report.checks.iter().map(|check| check.rule).collect()
+        };
+
+        let report = validator.explain(Path::new("main.rs"));
+        assert!(report.allowed);
+        assert!(report.denied_by().is_none());
+        assert_eq!(report.resolved, Some(workspace.join("main.rs")));
+        assert_eq!(
+            rules(&report),
+            vec![
+                ValidationRule::Blacklist,
+                ValidationRule::Workspace,
+                ValidationRule::Squidignore,
+                ValidationRule::Gitignore
+            ]
+        );
+
+        let report = validator.explain(Path::new(".env"));
+        let denied = report.denied_by().unwrap();
+        assert!(!report.allowed);
+        assert_eq!(denied.rule, ValidationRule::Squidignore);
+        assert_eq!(denied.pattern.as_deref(), Some("*.env"));
+        assert_eq!(denied.source, Some(workspace.join(".squidignore")));
+        assert_eq!(denied.line, Some(3));
+        assert!(matches!(
+            validator.validate(Path::new(".env")),
+            Err(PathValidationError::PathIgnored(_))
+        ));
+
+        let report = validator.explain(Path::new("logs/app.log"));
+        let denied = report.denied_by().unwrap();
+        assert_eq!(denied.rule, ValidationRule::Gitignore);
+        assert_eq!(denied.pattern.as_deref(), Some("logs/"));
+        assert_eq!(denied.source, Some(workspace.join(".gitignore")));
+
+        let report = validator.explain(&outside.join("notes.md"));
+        assert_eq!(report.denied_by().unwrap().rule, ValidationRule::Workspace);
+        assert_eq!(
+            rules(&report),
+            vec![ValidationRule::Blacklist, ValidationRule::Workspace]
+        );
+
+        #[cfg(unix)]
+        {
+            let report = validator.explain(Path::new("notes.md"));
+            assert_eq!(report.denied_by().unwrap().rule, ValidationRule::Symlink);
+            assert!(matches!(
+                validator.validate(Path::new("notes.md")),
+                Err(PathValidationError::PathNotAllowed(_))
+            ));
+        }
+
+        let report = validator.explain(Path::new("/etc/passwd"));
+        let denied = report.denied_by().unwrap();
+        assert_eq!(denied.rule, ValidationRule::Blacklist);
+        assert_eq!(denied.pattern.as_deref(), Some("/etc"));
+        assert_eq!(rules(&report), vec![ValidationRule::Blacklist]);
+    }
+
+    #[test]
+    fn test_validate_working_dir() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(temp.path()).unwrap();
+        fs::write(dir.join("file.txt"), "x").unwrap();
+
+        assert_eq!(PathValidator::validate_working_dir(&dir).unwrap(), dir);
+        assert!(matches!(
+            PathValidator::validate_working_dir(&dir.join("file.txt")),
+            Err(PathValidationError::NotADirectory(_))
+        ));
+        assert!(PathValidator::validate_working_dir(&dir.join("missing")).is_err());
+        assert!(matches!(
+            PathValidator::validate_working_dir(Path::new("/etc")),
+            Err(PathValidationError::PathNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn test_nested_gitignore_and_negation() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        fs::create_dir_all(root.join(".git/info")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join("sub/node_modules/pkg")).unwrap();
+        fs::write(root.join(".git/info/exclude"), "*.local\n").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n!keep.log\n").unwrap();
+        fs::write(
+            root.join("sub/.gitignore"),
+            "node_modules/\n*.tmp\n!important.log\n",
+        )
+        .unwrap();
+
+        let validator = PathValidator::with_root(&root, None).respect_gitignore(true);
+        assert!(validator.is_ignored(&root.join("target")));
+        assert!(validator.is_ignored(&root.join("target/debug/squid")));
+        assert!(validator.is_ignored(&root.join("app.log")));
+        assert!(!validator.is_ignored(&root.join("keep.log")));
+        assert!(validator.is_ignored(&root.join("notes.local")));
+        assert!(!validator.is_ignored(&root.join("src/main.rs")));
+
+        // Nested .gitignore applies only below its directory and overrides its parents
+        assert!(validator.is_ignored(&root.join("sub/node_modules/pkg/index.js")));
+        assert!(validator.is_ignored(&root.join("sub/cache.tmp")));
+        assert!(!validator.is_ignored(&root.join("cache.tmp")));
+        assert!(!validator.is_ignored(&root.join("sub/important.log")));
+        assert!(validator.is_ignored(&root.join("sub/other.log")));
+
+        let validator = PathValidator::with_root(&root, None).respect_gitignore(false);
+        assert!(!validator.is_ignored(&root.join("target/debug/squid")));
+        assert!(!validator.is_ignored(&root.join("app.log")));
+    }
+
+    #[test]
+    fn test_squidignore_takes_precedence_over_gitignore() {
+        let temp = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(temp.path()).unwrap();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::write(root.join(".gitignore"), "build/\n*.log\n").unwrap();
+        fs::write(
+            root.join(".squidignore"),
+            "*.secret\n!important.log\n!**/build/report.txt\n",
+        )
+        .unwrap();
+
+        let validator = PathValidator::for_working_dir(&root).respect_gitignore(true);
+        assert!(validator.validate(Path::new("important.log")).is_ok());
+        assert!(validator.validate(Path::new("build/report.txt")).is_ok());
+        assert!(matches!(
+            validator.validate(Path::new("debug.log")),
+            Err(PathValidationError::PathIgnored(_))
+        ));
+        assert!(matches!(
+            validator.validate(Path::new("build/app.js")),
+            Err(PathValidationError::PathIgnored(_))
+        ));
+        assert!(matches!(
+            validator.validate(Path::new("api.secret")),
+            Err(PathValidationError::PathIgnored(_))
+        ));
+    }
+
+    #[test]
+    fn test_squidignore_last_matching_pattern_wins() {
+        let patterns = vec!["*.log".to_string(), "!keep.log".to_string()];
+        let validator = PathValidator::with_ignore_file(Some(patterns));
+        assert!(validator.is_ignored(Path::new("/project/app.log")));
+        assert!(!validator.is_ignored(Path::new("/project/keep.log")));
+
+        let patterns = vec!["!keep.log".to_string(), "*.log".to_string()];
+        let validator = PathValidator::with_ignore_file(Some(patterns));
+        assert!(validator.is_ignored(Path::new("/project/keep.log")));
+    }
+
+    #[test]
+    fn test_cached_validator_picks_up_squidignore_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = fs::canonicalize(temp.path()).unwrap();
+        let ignore_file = dir.join(".squidignore");
+        fs::write(&ignore_file, "*.secret\n").unwrap();
+
+        let validator = PathValidator::cached_for_working_dir(&dir, false);
+        assert!(validator.is_ignored(&dir.join("api.secret")));
+        assert!(!validator.is_ignored(&dir.join("notes.md")));
+        assert!(Arc::ptr_eq(
+            &validator,
+            &PathValidator::cached_for_working_dir(&dir, false)
+        ));
+
+        fs::write(&ignore_file, "*.md\n").unwrap();
+        // Set the mtime explicitly; the write may land within the filesystem's timestamp granularity
+        fs::File::options()
+            .write(true)
+            .open(&ignore_file)
+            .unwrap()
+            .set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+
+        let validator = PathValidator::cached_for_working_dir(&dir, false);
+        assert!(!validator.is_ignored(&dir.join("api.secret")));
+        assert!(validator.is_ignored(&dir.join("notes.md")));
+    }
+
+    #[test]
+    fn test_validator_cache_evicts_the_least_recently_used() {
+        let key = |n: usize| CacheKey {
+            root: PathBuf::from(format!("/project/{}", n)),
+            ignore_root: PathBuf::from("/project"),
+            base_dir: None,
+            respect_gitignore: false,
+        };
+        let entry = |last_used: Instant| CachedValidator {
+            mtimes: Vec::new(),
+            validator: Arc::new(PathValidator::new()),
+            last_used,
+        };
+        let start = Instant::now();
+        let mut cache = HashMap::new();
+        for n in 0..3 {
+            insert_bounded(&mut cache, key(n), entry(start), 3);
+        }
+        // Entry 0 was used most recently, so entry 1 goes first
+        cache.get_mut(&key(0)).unwrap().last_used = start + std::time::Duration::from_secs(2);
+        cache.get_mut(&key(2)).unwrap().last_used = start + std::time::Duration::from_secs(1);
+
+        insert_bounded(&mut cache, key(3), entry(start), 3);
+        assert_eq!(cache.len(), 3);
+        assert!(!cache.contains_key(&key(1)));
+        assert!(cache.contains_key(&key(0)) && cache.contains_key(&key(3)));
+
+        // Replacing an entry never evicts another
+        insert_bounded(&mut cache, key(3), entry(start), 3);
+        assert_eq!(cache.len(), 3);
+    }
 }
@@ -0,0 +1,278 @@
+//! Webhook notifications for chat events
+//!
+//! Configured under `notifications.webhooks`, each webhook gets a JSON POST when a tool
+//! approval is waiting, a chat turn completes, or a turn fails. Deliveries run in the
+//! background with a short timeout and a single retry; a failing webhook is logged and never
+//! affects the chat. Webhook URLs often embed tokens, so only their host is logged.
+
+use log::{debug, warn};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{NotificationEvent, NotificationsConfig, WebhookConfig};
+
+/// How long a single delivery may take
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Deliveries are retried at most once
+const MAX_ATTEMPTS: usize = 2;
+/// Summaries are cut to this many characters
+const MAX_SUMMARY_CHARS: usize = 200;
+
+/// The JSON body posted to a webhook
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub session_id: String,
+    pub summary: String,
+    /// One-line message for chat services that display a `text` field (e.g. Slack)
+    pub text: String,
+    pub timestamp: i64,
+}
+
+impl Notification {
+    pub fn new(event: NotificationEvent, session_id: &str, summary: &str) -> Self {
+        let summary = crate::text::truncate(summary.trim(), MAX_SUMMARY_CHARS);
+        Self {
+            event,
+            session_id: session_id.to_string(),
+            text: format!("🦑 {}: {}", event.as_str(), summary),
+            summary,
+            timestamp: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Sends a notification to a URL; mocked in tests
+#[async_trait::async_trait]
+pub trait WebhookTransport: Send + Sync {
+    async fn post(&self, url: &str, notification: &Notification) -> Result<(), String>;
+}
+
+/// Posts notifications as JSON over HTTP
+pub struct HttpTransport {
+    client: reqwest::Client,
+}
+
+impl HttpTransport {
+    pub fn new() -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        Self { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl WebhookTransport for HttpTransport {
+    async fn post(&self, url: &str, notification: &Notification) -> Result<(), String> {
+        let response = self
+            .client
+            .post(url)
+            .json(notification)
+            .send()
+            .await
+            // reqwest errors include the URL, which may contain a token
+            .map_err(|e| e.without_url().to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("HTTP {}", response.status()));
+        }
+        Ok(())
+    }
+}
+
+/// Delivers chat events to the configured webhooks
+#[derive(Clone)]
+pub struct Notifier {
+    webhooks: Arc<Vec<WebhookConfig>>,
+    transport: Arc<dyn WebhookTransport>,
+}
+
+impl Notifier {
+    pub fn new(config: &NotificationsConfig) -> Self {
+        Self::with_transport(config, Arc::new(HttpTransport::new()))
+    }
+
+    pub fn with_transport(
+        config: &NotificationsConfig,
+        transport: Arc<dyn WebhookTransport>,
+    ) -> Self {
+        Self {
+            webhooks: Arc::new(config.webhooks.clone()),
+            transport,
+        }
+    }
+
+    /// Send a notification in the background without waiting for delivery
+    pub fn notify(&self, event: NotificationEvent, session_id: &str, summary: &str) {
+        if !self.webhooks.iter().any(|webhook| webhook.wants(event)) {
+            return;
+        }
+        let notifier = self.clone();
+        let notification = Notification::new(event, session_id, summary);
+        tokio::spawn(async move { notifier.send(&notification).await });
+    }
+
+    /// Deliver a notification to every webhook subscribed to its event
+    pub async fn send(&self, notification: &Notification) {
+        let deliveries = self
+            .webhooks
+            .iter()
+            .filter(|webhook| webhook.wants(notification.event))
+            .map(|webhook| self.deliver(&webhook.url, notification));
+        futures::future::join_all(deliveries).await;
+    }
+
+    async fn deliver(&self, url: &str, notification: &Notification) {
+        for attempt in 1..=MAX_ATTEMPTS {
+            match self.transport.post(url, notification).await {
+                Ok(()) => {
+                    debug!(
+                        "Sent {} webhook to {}",
+                        notification.event.as_str(),
+                        redact_url(url)
+                    );
+                    return;
+                }
+                Err(e) if attempt < MAX_ATTEMPTS => {
+                    debug!("Webhook to {} failed, retrying: {}", redact_url(url), e);
+                }
+                Err(e) => warn!("Webhook to {} failed: {}", redact_url(url), e),
+            }
+        }
+    }
+}
+
+/// Keep only the scheme and host of a webhook URL for logging
+fn redact_url(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(parsed) => format!(
+            "{}://{}/…",
+            parsed.scheme(),
+            parsed.host_str().unwrap_or("unknown")
+        ),
+        Err(_) => "[invalid webhook url]".to_string(),
+    }
+}
+
+#[cfg(test)]
+pub mod test_support {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Records notifications instead of sending them; the first `failures` posts fail
+    #[derive(Default)]
+    pub struct MockTransport {
+        pub sent: Mutex<Vec<(String, Notification)>>,
+        pub failures: Mutex<usize>,
+    }
+
+    #[async_trait::async_trait]
+    impl WebhookTransport for MockTransport {
+        async fn post(&self, url: &str, notification: &Notification) -> Result<(), String> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("connection refused".to_string());
+            }
+            self.sent
+                .lock()
+                .unwrap()
+                .push((url.to_string(), notification.clone()));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_support::MockTransport;
+    use super::*;
+
+    fn webhook(url: &str, events: &[NotificationEvent]) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            events: events.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_send_delivers_approval_and_completion_payloads() {
+        let config = NotificationsConfig {
+            webhooks: vec![
+                webhook("https://ntfy.sh/squid-secret", &[]),
+                webhook(
+                    "https://hooks.slack.com/services/T0/B0/token",
+                    &[NotificationEvent::ApprovalRequested],
+                ),
+            ],
+        };
+        let transport = Arc::new(MockTransport::default());
+        let notifier = Notifier::with_transport(&config, transport.clone());
+
+        notifier
+            .send(&Notification::new(
+                NotificationEvent::ApprovalRequested,
+                "s1",
+                "Tool 'bash' is waiting for approval",
+            ))
+            .await;
+        notifier
+            .send(&Notification::new(
+                NotificationEvent::TurnCompleted,
+                "s1",
+                "  Done  ",
+            ))
+            .await;
+
+        let sent = transport.sent.lock().unwrap().clone();
+        let urls: Vec<&str> = sent.iter().map(|(url, _)| url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://ntfy.sh/squid-secret",
+                "https://hooks.slack.com/services/T0/B0/token",
+                "https://ntfy.sh/squid-secret",
+            ]
+        );
+
+        let approval = serde_json::to_value(&sent[0].1).unwrap();
+        assert_eq!(approval["event"], "approval_requested");
+        assert_eq!(approval["session_id"], "s1");
+        assert_eq!(approval["summary"], "Tool 'bash' is waiting for approval");
+        assert!(approval["timestamp"].is_i64());
+
+        let completion = serde_json::to_value(&sent[2].1).unwrap();
+        assert_eq!(completion["event"], "turn_completed");
+        assert_eq!(completion["summary"], "Done");
+        assert_eq!(completion["text"], "🦑 turn_completed: Done");
+    }
+
+    #[tokio::test]
+    async fn test_send_retries_once() {
+        let config = NotificationsConfig {
+            webhooks: vec![webhook("https://example.com/hook", &[])],
+        };
+        let transport = Arc::new(MockTransport::default());
+        let notifier = Notifier::with_transport(&config, transport.clone());
+        let notification = Notification::new(NotificationEvent::Error, "s1", "Boom");
+
+        *transport.failures.lock().unwrap() = 1;
+        notifier.send(&notification).await;
+        assert_eq!(transport.sent.lock().unwrap().len(), 1);
+
+        *transport.failures.lock().unwrap() = 2;
+        notifier.send(&notification).await;
+        assert_eq!(transport.sent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_redact_url_hides_path_and_query() {
+        assert_eq!(
+            redact_url("https://hooks.slack.com/services/T0/B0/token?x=1"),
+            "https://hooks.slack.com/…"
+        );
+        assert_eq!(redact_url("not a url"), "[invalid webhook url]");
+    }
+}
@@ -1,6 +1,13 @@
 use actix_web::{Error, HttpResponse, web};
 use log::debug;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::{self, Config};
+use crate::llm;
+use crate::session::SessionManager;
+use crate::validate::PathValidator;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileNode {
@@ -16,26 +23,325 @@ pub struct WorkspaceFilesResponse {
     pub files: Vec<FileNode>,
 }
 
-/// Get workspace files structure
-pub async fn get_workspace_files() -> Result<HttpResponse, Error> {
-    debug!("Fetching workspace files");
+#[derive(Debug, Deserialize)]
+pub struct WorkspaceQuery {
+    /// Browse this session's working directory instead of the server's
+    pub session_id: Option<String>,
+    /// Browse a configured workspace; ignored for sessions with a working directory
+    pub workspace: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkspaceInfo {
+    pub name: String,
+    /// Resolved directory of the workspace
+    pub path: String,
+    /// Whether the directory exists
+    pub available: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkspacesResponse {
+    pub workspaces: Vec<WorkspaceInfo>,
+}
+
+/// Canonical root of the named workspace
+pub fn resolve_workspace(config: &Config, name: &str) -> Result<PathBuf, String> {
+    let workspace = config
+        .get_workspace(name)
+        .ok_or_else(|| format!("Unknown workspace '{}'", name))?;
+    PathValidator::validate_working_dir(&config.workspace_path(workspace))
+        .map_err(|e| format!("Workspace '{}' is unavailable: {}", name, e))
+}
+
+/// Whether `dir` lies inside a configured workspace; always true when none are configured
+pub fn within_workspaces(config: &Config, dir: &Path) -> bool {
+    config.workspaces.is_empty()
+        || config.workspaces.iter().any(|workspace| {
+            resolve_workspace(config, &workspace.name).is_ok_and(|root| dir.starts_with(root))
+        })
+}
+
+/// Root used when a request names no workspace while workspaces are configured: the first one
+///
+/// `None` when no workspaces are configured and the server's current directory applies.
+pub fn default_root(config: &Config) -> Option<PathBuf> {
+    let first = config.workspaces.first()?;
+    Some(resolve_workspace(config, &first.name).unwrap_or_else(|_| config.workspace_path(first)))
+}
+
+/// Bind a session to a workspace, pointing its working directory at the workspace root
+///
+/// Sessions already bound to the workspace keep their working directory; a session bound to
+/// another workspace can't be moved.
+pub fn bind_session(
+    session_manager: &SessionManager,
+    session_id: &str,
+    config: &Config,
+    name: &str,
+) -> Result<(), String> {
+    let root = resolve_workspace(config, name)?;
+    let session = session_manager
+        .get_session(session_id)
+        .ok_or_else(|| "Session not found".to_string())?;
+    match session.workspace.as_deref() {
+        Some(current) if current == name => Ok(()),
+        Some(current) => Err(format!(
+            "Session belongs to workspace '{}', not '{}'",
+            current, name
+        )),
+        None => session_manager
+            .update_session_workspace(
+                session_id,
+                name.to_string(),
+                root.to_string_lossy().to_string(),
+            )
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// List configured workspaces
+pub async fn list_workspaces(app_config: web::Data<Arc<config::Config>>) -> HttpResponse {
+    let workspaces = app_config
+        .workspaces
+        .iter()
+        .map(|workspace| {
+            let resolved = resolve_workspace(&app_config, &workspace.name);
+            WorkspaceInfo {
+                name: workspace.name.clone(),
+                path: match &resolved {
+                    Ok(root) => root.to_string_lossy().to_string(),
+                    Err(_) => app_config
+                        .workspace_path(workspace)
+                        .to_string_lossy()
+                        .to_string(),
+                },
+                available: resolved.is_ok(),
+            }
+        })
+        .collect();
+    HttpResponse::Ok().json(WorkspacesResponse { workspaces })
+}
+
+/// Optional slice of a file to return
+///
+/// Lines are 1-based and inclusive; byte ranges snap to character boundaries.
+#[derive(Debug, Default, Deserialize)]
+pub struct FileRangeQuery {
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+    pub offset: Option<usize>,
+    pub length: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkspaceFileResponse {
+    pub path: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Language detected from the file name or extension
+    pub language: Option<&'static str>,
+    pub binary: bool,
+    /// Returned text; omitted for binary files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_lines: Option<usize>,
+    /// First and last returned line (1-based), set for line ranges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_line: Option<usize>,
+    /// Byte offset of the returned content, set for byte ranges
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub offset: Option<usize>,
+    /// Whether `content` is only part of the file
+    pub truncated: bool,
+}
+
+/// Number of leading bytes checked for NUL when detecting binary files
+const BINARY_SNIFF_BYTES: usize = 8192;
+
+fn is_binary(bytes: &[u8]) -> bool {
+    bytes[..bytes.len().min(BINARY_SNIFF_BYTES)].contains(&0)
+}
+
+/// Build the response for `bytes`, applying the requested range
+fn file_response(
+    path: &str,
+    bytes: &[u8],
+    range: &FileRangeQuery,
+) -> Result<WorkspaceFileResponse, String> {
+    let mut response = WorkspaceFileResponse {
+        path: path.to_string(),
+        size: bytes.len() as u64,
+        language: llm::detect_language(std::path::Path::new(path)),
+        binary: false,
+        content: None,
+        total_lines: None,
+        start_line: None,
+        end_line: None,
+        offset: None,
+        truncated: false,
+    };
+
+    let by_lines = range.start_line.is_some() || range.end_line.is_some();
+    let by_bytes = range.offset.is_some() || range.length.is_some();
+    if by_lines && by_bytes {
+        return Err("Use either start_line/end_line or offset/length, not both".to_string());
+    }
+
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) if !is_binary(bytes) => text,
+        _ => {
+            response.binary = true;
+            return Ok(response);
+        }
+    };
+    response.total_lines = Some(text.lines().count());
+
+    let (start, end) = if by_lines {
+        let first = range.start_line.unwrap_or(1);
+        if first == 0 {
+            return Err("start_line is 1-based".to_string());
+        }
+        let last = range.end_line.unwrap_or(usize::MAX);
+        if last < first {
+            return Err("end_line must not be before start_line".to_string());
+        }
+
+        // Byte offset where each line starts, plus the end of the file
+        let mut line_starts: Vec<usize> = text
+            .split_inclusive('\n')
+            .scan(0, |pos, line| {
+                let start = *pos;
+                *pos += line.len();
+                Some(start)
+            })
+            .collect();
+        line_starts.push(text.len());
+
+        // Past the end of the file the range is empty and `end_line` ends up before `start_line`
+        let total = line_starts.len() - 1;
+        let last = last.min(total);
+        response.start_line = Some(first);
+        response.end_line = Some(last);
+        let start = line_starts[(first - 1).min(total)];
+        (start, line_starts[last].max(start))
+    } else if by_bytes {
+        let start = text.floor_char_boundary(range.offset.unwrap_or(0));
+        let end = match range.length {
+            Some(length) => text.floor_char_boundary(start.saturating_add(length)),
+            None => text.len(),
+        };
+        response.offset = Some(start);
+        (start, end)
+    } else {
+        (0, text.len())
+    };
+
+    response.truncated = start > 0 || end < text.len();
+    response.content = Some(text[start..end].to_string());
+    Ok(response)
+}
+
+/// Resolve the root to browse: the session's working directory, the requested workspace, the
+/// first configured workspace, or the server's current directory when none are configured
+fn workspace_root(
+    query: &WorkspaceQuery,
+    session_manager: &SessionManager,
+    config: &Config,
+) -> Result<PathBuf, Error> {
+    if let Some(session_id) = &query.session_id {
+        let session = session_manager
+            .get_session(session_id)
+            .ok_or_else(|| actix_web::error::ErrorNotFound("Session not found"))?;
+        if let (Some(current), Some(requested)) = (&session.workspace, &query.workspace)
+            && current != requested
+        {
+            return Err(actix_web::error::ErrorForbidden(format!(
+                "Session belongs to workspace '{}'",
+                current
+            )));
+        }
+        if let Some(dir) = session.working_dir {
+            let dir = PathBuf::from(dir);
+            if !within_workspaces(config, &dir) {
+                return Err(actix_web::error::ErrorForbidden(
+                    "Session working directory is outside every configured workspace",
+                ));
+            }
+            return Ok(dir);
+        }
+    }
+
+    if let Some(name) = &query.workspace {
+        return resolve_workspace(config, name).map_err(|e| {
+            if config.get_workspace(name).is_none() {
+                actix_web::error::ErrorNotFound(e)
+            } else {
+                actix_web::error::ErrorInternalServerError(e)
+            }
+        });
+    }
+
+    if let Some(root) = default_root(config) {
+        return Ok(root);
+    }
 
-    // Get current working directory
     let cwd = std::env::current_dir().map_err(|e| {
         actix_web::error::ErrorInternalServerError(format!(
             "Failed to get current directory: {}",
             e
         ))
     })?;
+    cwd.canonicalize().map_err(|e| {
+        actix_web::error::ErrorInternalServerError(format!(
+            "Failed to resolve current directory: {}",
+            e
+        ))
+    })
+}
+
+/// Get workspace files structure
+pub async fn get_workspace_files(
+    query: web::Query<WorkspaceQuery>,
+    session_manager: web::Data<Arc<SessionManager>>,
+    app_config: web::Data<Arc<config::Config>>,
+) -> Result<HttpResponse, Error> {
+    debug!("Fetching workspace files");
+
+    let cwd = workspace_root(&query, &session_manager, &app_config)?;
+    let validator =
+        PathValidator::cached_for_working_dir(&cwd, app_config.security.respect_gitignore);
 
     // Build file tree
-    let files = build_file_tree(&cwd).map_err(|e| {
+    let files = build_file_tree(&cwd, &validator).map_err(|e| {
         actix_web::error::ErrorInternalServerError(format!("Failed to build file tree: {}", e))
     })?;
 
     Ok(HttpResponse::Ok().json(WorkspaceFilesResponse { files }))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ValidatePathQuery {
+    /// Path to check, relative to the workspace root or absolute
+    pub path: String,
+}
+
+/// Explain whether tools may access a path, and which rule decided
+pub async fn validate_workspace_path(
+    query: web::Query<WorkspaceQuery>,
+    target: web::Query<ValidatePathQuery>,
+    session_manager: web::Data<Arc<SessionManager>>,
+    app_config: web::Data<Arc<config::Config>>,
+) -> Result<HttpResponse, Error> {
+    let cwd = workspace_root(&query, &session_manager, &app_config)?;
+    let validator =
+        PathValidator::cached_for_working_dir(&cwd, app_config.security.respect_gitignore);
+    Ok(HttpResponse::Ok().json(validator.explain(Path::new(&target.path))))
+}
+
 /// Check if a file is a supported code/text file based on extension
 fn is_supported_file(path: &std::path::Path) -> bool {
     // Extensions to include (code and documentation files)
@@ -59,18 +365,18 @@ fn is_supported_file(path: &std::path::Path) -> bool {
     }
 }
 
-/// Get content of a single workspace file
-pub async fn get_workspace_file(path: web::Path<String>) -> Result<HttpResponse, Error> {
+/// Get content of a single workspace file, or a range of it
+pub async fn get_workspace_file(
+    path: web::Path<String>,
+    query: web::Query<WorkspaceQuery>,
+    range: web::Query<FileRangeQuery>,
+    session_manager: web::Data<Arc<SessionManager>>,
+    app_config: web::Data<Arc<config::Config>>,
+) -> Result<HttpResponse, Error> {
     let file_path = path.into_inner();
     debug!("Fetching workspace file: {}", file_path);
 
-    // Get current working directory
-    let cwd = std::env::current_dir().map_err(|e| {
-        actix_web::error::ErrorInternalServerError(format!(
-            "Failed to get current directory: {}",
-            e
-        ))
-    })?;
+    let cwd = workspace_root(&query, &session_manager, &app_config)?;
 
     // Construct full path
     let full_path = cwd.join(&file_path);
@@ -99,18 +405,19 @@ pub async fn get_workspace_file(path: web::Path<String>) -> Result<HttpResponse,
     }
 
     // Read file content
-    let content = std::fs::read_to_string(&canonical_path).map_err(|e| {
+    let bytes = std::fs::read(&canonical_path).map_err(|e| {
         actix_web::error::ErrorInternalServerError(format!("Failed to read file: {}", e))
     })?;
 
-    Ok(HttpResponse::Ok()
-        .content_type("text/plain; charset=utf-8")
-        .body(content))
+    let response =
+        file_response(&file_path, &bytes, &range).map_err(actix_web::error::ErrorBadRequest)?;
+    Ok(HttpResponse::Ok().json(response))
 }
 
 /// Build a hierarchical file tree for a directory
 fn build_file_tree(
     root_path: &std::path::Path,
+    validator: &PathValidator,
 ) -> Result<Vec<FileNode>, Box<dyn std::error::Error>> {
     use std::collections::HashMap;
     use walkdir::WalkDir;
@@ -165,6 +472,11 @@ fn build_file_tree(
                 return false;
             }
 
+            // Skip paths excluded by .squidignore or .gitignore
+            if e.depth() > 0 && validator.is_ignored(e.path()) {
+                return false;
+            }
+
             // Skip excluded directories
             if e.file_type().is_dir() {
                 !excluded_dirs.iter().any(|&excluded| file_name == excluded)
@@ -300,3 +612,110 @@ fn build_file_tree(
 
     Ok(root_nodes)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(start_line: Option<usize>, end_line: Option<usize>) -> FileRangeQuery {
+        FileRangeQuery {
+            start_line,
+            end_line,
+            ..Default::default()
+        }
+    }
+
+    fn bytes(offset: Option<usize>, length: Option<usize>) -> FileRangeQuery {
+        FileRangeQuery {
+            offset,
+            length,
+            ..Default::default()
+        }
+    }
+
+    const TEXT: &[u8] = b"one\ntwo\nthree\nfour";
+
+    #[test]
+    fn test_whole_file_with_metadata() {
+        let response = file_response("src/main.rs", TEXT, &FileRangeQuery::default()).unwrap();
+        assert_eq!(response.content.as_deref(), Some("one\ntwo\nthree\nfour"));
+        assert_eq!(response.total_lines, Some(4));
+        assert_eq!(response.size, TEXT.len() as u64);
+        assert_eq!(response.language, Some("rust"));
+        assert!(!response.truncated);
+        assert!(!response.binary);
+    }
+
+    #[test]
+    fn test_line_range_boundaries() {
+        let response = file_response("a.txt", TEXT, &lines(Some(2), Some(3))).unwrap();
+        assert_eq!(response.content.as_deref(), Some("two\nthree\n"));
+        assert_eq!((response.start_line, response.end_line), (Some(2), Some(3)));
+        assert!(response.truncated);
+
+        // Open-ended and past-the-end ranges are clamped to the file
+        let response = file_response("a.txt", TEXT, &lines(Some(3), None)).unwrap();
+        assert_eq!(response.content.as_deref(), Some("three\nfour"));
+        let response = file_response("a.txt", TEXT, &lines(Some(1), Some(99))).unwrap();
+        assert_eq!(response.content.as_deref(), Some("one\ntwo\nthree\nfour"));
+        assert_eq!(response.end_line, Some(4));
+        assert!(!response.truncated);
+    }
+
+    #[test]
+    fn test_empty_and_invalid_ranges() {
+        let response = file_response("a.txt", TEXT, &lines(Some(10), Some(12))).unwrap();
+        assert_eq!(response.content.as_deref(), Some(""));
+        assert_eq!(
+            (response.start_line, response.end_line),
+            (Some(10), Some(4))
+        );
+        assert_eq!(response.total_lines, Some(4));
+        assert!(response.truncated);
+
+        let response = file_response("a.txt", TEXT, &bytes(Some(4), Some(0))).unwrap();
+        assert_eq!(response.content.as_deref(), Some(""));
+
+        let response = file_response("a.txt", b"", &FileRangeQuery::default()).unwrap();
+        assert_eq!(response.content.as_deref(), Some(""));
+        assert_eq!(response.total_lines, Some(0));
+
+        assert!(file_response("a.txt", TEXT, &lines(Some(0), None)).is_err());
+        assert!(file_response("a.txt", TEXT, &lines(Some(3), Some(2))).is_err());
+        let mixed = FileRangeQuery {
+            start_line: Some(1),
+            offset: Some(0),
+            ..Default::default()
+        };
+        assert!(file_response("a.txt", TEXT, &mixed).is_err());
+    }
+
+    #[test]
+    fn test_byte_range_snaps_to_char_boundaries() {
+        let response = file_response("a.txt", TEXT, &bytes(Some(4), Some(3))).unwrap();
+        assert_eq!(response.content.as_deref(), Some("two"));
+        assert_eq!(response.offset, Some(4));
+
+        // "é" is two bytes; a range ending inside it stops before it
+        let text = "caf\u{e9} ol\u{e9}".as_bytes();
+        let response = file_response("a.txt", text, &bytes(Some(0), Some(4))).unwrap();
+        assert_eq!(response.content.as_deref(), Some("caf"));
+        let response = file_response("a.txt", text, &bytes(Some(4), None)).unwrap();
+        assert_eq!(response.offset, Some(3));
+        assert_eq!(response.content.as_deref(), Some("\u{e9} ol\u{e9}"));
+    }
+
+    #[test]
+    fn test_binary_file_detection() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        let response = file_response("logo.png", png, &FileRangeQuery::default()).unwrap();
+        assert!(response.binary);
+        assert!(response.content.is_none());
+        assert!(response.total_lines.is_none());
+        assert_eq!(response.size, png.len() as u64);
+
+        // Invalid UTF-8 without NUL bytes is treated as binary as well
+        let response = file_response("a.txt", b"\xff\xfe text", &lines(Some(1), None)).unwrap();
+        assert!(response.binary);
+    }
+}
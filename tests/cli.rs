@@ -0,0 +1,166 @@
+//! Exit codes of the `squid` binary on common failure paths
+
+use assert_cmd::Command;
+use std::path::Path;
+
+/// A `squid` command isolated from the developer's environment and config
+fn squid(dir: &Path) -> Command {
+    let mut cmd = Command::cargo_bin("squid").unwrap();
+    cmd.current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env_remove("API_URL")
+        .env_remove("API_KEY")
+        .env_remove("SQUID_API_KEY");
+    cmd
+}
+
+/// Write a config and agent pointing at a port nothing listens on
+fn write_config(dir: &Path) {
+    std::fs::write(
+        dir.join("squid.config.json"),
+        format!(
+            r#"{{"version": "{}", "api_url": "http://127.0.0.1:9/v1", "rag": {{"enabled": false}}}}"#,
+            env!("CARGO_PKG_VERSION")
+        ),
+    )
+    .unwrap();
+    std::fs::create_dir(dir.join("agents")).unwrap();
+    std::fs::write(
+        dir.join("agents/general-assistant.md"),
+        "---\nname: General\nmodel: test-model\n---\nYou are a test assistant.\n",
+    )
+    .unwrap();
+}
+
+#[test]
+fn ask_without_config_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    let output = squid(dir.path()).args(["ask", "hello"]).output().unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("squid init"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn review_missing_file_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    let output = squid(dir.path())
+        .args(["review", "missing.rs"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("I can't"));
+}
+
+#[test]
+fn invalid_log_age_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    squid(dir.path())
+        .args(["logs", "cleanup", "--older-than", "bogus"])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn provider_error_fails_quietly() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    let output = squid(dir.path())
+        .args(["ask", "--quiet", "--no-stream", "hello"])
+        .output()
+        .unwrap();
+
+    // EX_UNAVAILABLE: the provider couldn't be reached
+    assert_eq!(output.status.code(), Some(69));
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Failed to get a response"));
+}
+
+#[test]
+fn rag_json_error_goes_to_stdout() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    let output = squid(dir.path())
+        .args(["rag", "stats", "--json"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    let body: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(body["error"].as_str().unwrap().contains("RAG is disabled"));
+}
+
+#[test]
+fn show_missing_session_fails() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    let output = squid(dir.path())
+        .args(["sessions", "show", "no-such-session"])
+        .output()
+        .unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not found"));
+}
+
+#[test]
+fn config_set_round_trip_and_unknown_key() {
+    let dir = tempfile::tempdir().unwrap();
+    write_config(dir.path());
+    let set = squid(dir.path())
+        .args(["config", "set", "rag.chunk_size", "256"])
+        .output()
+        .unwrap();
+    assert_eq!(set.status.code(), Some(0));
+
+    let get = squid(dir.path())
+        .args(["config", "get", "rag.chunk_size"])
+        .output()
+        .unwrap();
+    assert_eq!(String::from_utf8_lossy(&get.stdout).trim(), "256");
+
+    let unknown = squid(dir.path())
+        .args(["config", "set", "rag.chunk_sise", "256"])
+        .output()
+        .unwrap();
+    assert_eq!(unknown.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("Unknown config key"));
+}
+
+#[test]
+fn global_config_applies_outside_projects() {
+    let dir = tempfile::tempdir().unwrap();
+    let global = dir.path().join("config/squid");
+    std::fs::create_dir_all(&global).unwrap();
+    std::fs::write(
+        global.join("config.json"),
+        r#"{"api_url": "http://global.invalid/v1"}"#,
+    )
+    .unwrap();
+    let get = |key: &str| {
+        let output = squid(dir.path())
+            .args(["config", "get", key])
+            .output()
+            .unwrap();
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    };
+
+    assert_eq!(get("api_url"), "http://global.invalid/v1");
+    assert_eq!(
+        get("database_path"),
+        dir.path().join("data/squid/squid.db").display().to_string()
+    );
+
+    write_config(dir.path());
+    assert_eq!(get("api_url"), "http://127.0.0.1:9/v1");
+    assert_eq!(
+        get("database_path"),
+        dir.path().join("squid.db").display().to_string()
+    );
+}
@@ -9,6 +9,7 @@ import { AppSidebar } from './components/app/app-sidebar';
 import { FilesSidebar } from './components/app/files-sidebar';
 import { DocumentManager } from './components/app/document-manager';
 import { AgentStatsCard } from './components/app/agent-stats';
+import { ToolStatsCard } from './components/app/tool-stats';
 import { JobCreateDialog } from './components/app/job-create-dialog';
 import { SidebarInset, SidebarProvider, SidebarTrigger } from '@/components/ui/sidebar';
 import { Separator } from '@/components/ui/separator';
@@ -229,7 +230,15 @@ function AppContent() {
               <Route path="/new" element={<ChatBot key="new-chat" />} />
               <Route path="/chat/:id" element={<ChatBot key={activeSessionId || 'loading'} />} />
               <Route path="/logs" element={<Logs />} />
-              <Route path="/agent-stats" element={<AgentStatsCard apiUrl="" />} />
+              <Route
+                path="/agent-stats"
+                element={
+                  <>
+                    <ToolStatsCard apiUrl="" />
+                    <AgentStatsCard apiUrl="" />
+                  </>
+                }
+              />
               <Route path="/agents/:id" element={<AgentViewer />} />
               <Route path="/jobs/:id" element={<JobDetails />} />
               <Route path="/workspace/files/*" element={<FileViewer />} />
@@ -66,7 +66,7 @@ import { Tool, ToolContent, ToolHeader, ToolInput, ToolOutput } from '@/componen
 import type { BundledLanguage } from 'shiki';
 import { useCallback, useEffect, useMemo, useRef, useState } from 'react';
 import { toast } from 'sonner';
-import { BrainIcon, WrenchIcon, Sparkles, Bot } from 'lucide-react';
+import { BrainIcon, WrenchIcon, Sparkles, Bot, AlertTriangleIcon } from 'lucide-react';
 import { playNotificationSound } from '@/lib/notification-sound';
 
 // App components
@@ -81,6 +81,9 @@ import { useSessionStore } from '@/stores/session-store';
 import { useAgentStore } from '@/stores/agent-store';
 import { useChatStore } from '@/stores/chat-store';
 import { useConfigStore } from '@/stores/config-store';
+import { CONTINUE_PROMPT } from '@/lib/chat-api';
+import { Badge } from '@/components/ui/badge';
+import { Button } from '@/components/ui/button';
 
 const AttachmentItem = ({
   attachment,
@@ -145,6 +148,7 @@ const Chatbot = () => {
     messages,
     status,
     streamingMessageId,
+    isRetrieving,
     useRag,
     useTools,
     addUserMessage,
@@ -171,6 +175,7 @@ const Chatbot = () => {
 
   // Whether the currently selected agent supports tools (defaults to true if not specified)
   const agentSupportsTools = useMemo(() => selectedAgentData?.use_tools !== false, [selectedAgentData]);
+  const modelSupportsTools = selectedAgentData?.model_supports_tools !== false;
 
   // Check if current session is readonly (created by a job)
   const isSessionReadonly = useMemo(() => {
@@ -523,7 +528,7 @@ const Chatbot = () => {
       <div className="flex-1 min-h-0 flex flex-col overflow-hidden">
         <Conversation key={activeSessionId || 'new'} initial="instant" resize="smooth">
           <ConversationContent>
-            {messages.map(({ versions, ...message }) => (
+            {messages.map(({ versions, ...message }, messageIndex) => (
               <MessageBranch defaultBranch={0} key={message.key}>
                 <MessageBranchContent>
                   {versions.map((version) => {
@@ -613,6 +618,17 @@ const Chatbot = () => {
                                           <div className="text-sm whitespace-pre-wrap">{step.content}</div>
                                         </ChainOfThoughtStep>
                                       );
+                                    } else if (step.type === 'warning') {
+                                      return (
+                                        <ChainOfThoughtStep
+                                          key={`warning-${idx}`}
+                                          icon={AlertTriangleIcon}
+                                          label="Warning"
+                                          status="complete"
+                                        >
+                                          <div className="text-sm text-amber-600 dark:text-amber-400">{step.content}</div>
+                                        </ChainOfThoughtStep>
+                                      );
                                     } else {
                                       return (
                                         <ChainOfThoughtStep
@@ -646,7 +662,12 @@ const Chatbot = () => {
                                                 </div>
                                               </div>
                                             )}
-                                            {!step.result && !step.error && step.status === 'pending' && (
+                                            {!step.result && !step.error && step.status === 'pending' && step.liveOutput && (
+                                              <pre className="bg-muted p-2 rounded text-xs overflow-x-auto whitespace-pre-wrap">
+                                                {step.liveOutput}
+                                              </pre>
+                                            )}
+                                            {!step.result && !step.error && step.status === 'pending' && !step.liveOutput && (
                                               <div className="text-xs text-muted-foreground">
                                                 Waiting for tool execution...
                                               </div>
@@ -679,12 +700,12 @@ const Chatbot = () => {
                               </ChainOfThought>
                             );
                           })()}
-                          {/* Render content and tools in order using split markers (for loaded sessions with contentBeforeTool) */}
+                          {/* Render content and tools in order using split markers (for loaded sessions with contentDeltaBeforeTool) */}
                           {message.from === 'assistant' &&
                             !message.thinkingSteps?.some((s) => s.type === 'reasoning') &&
                             message.thinkingSteps &&
                             message.thinkingSteps.some(
-                              (s) => s.type === 'tool' && (s.contentBeforeTool !== undefined || s.result || s.error)
+                              (s) => s.type === 'tool' && (s.contentDeltaBeforeTool !== undefined || s.result || s.error)
                             ) ? (
                             <>
                               {(() => {
@@ -692,26 +713,38 @@ const Chatbot = () => {
                                 const elements: React.ReactNode[] = [];
 
                                 message.thinkingSteps!.forEach((step, idx) => {
-                                  if (step.type === 'tool') {
+                                  if (step.type === 'warning') {
+                                    elements.push(
+                                      <div
+                                        key={`warning-${idx}`}
+                                        className="mt-4 flex items-start gap-2 text-sm text-amber-600 dark:text-amber-400"
+                                      >
+                                        <AlertTriangleIcon className="size-4 mt-0.5 shrink-0" />
+                                        <span>{step.content}</span>
+                                      </div>
+                                    );
+                                  } else if (step.type === 'tool') {
                                     // Find corresponding tool approval if exists
                                     const approval = message.toolApprovals?.find((a) => a.tool_name === step.name);
                                     const decision = approval ? toolApprovalDecisions.get(approval.approval_id) : null;
 
                                     // Show content before this tool
-                                    // During streaming, use approval.contentBeforeApproval
-                                    // After loading, use step.contentBeforeTool
-                                    const contentBefore = approval?.contentBeforeApproval ?? step.contentBeforeTool;
-                                    if (contentBefore) {
-                                      // contentBefore is cumulative, so extract only the incremental part
-                                      const incrementalContent = contentBefore.substring(contentPosition);
-                                      if (incrementalContent.trim()) {
-                                        elements.push(
-                                          <MessageContent key={`content-before-${idx}`}>
-                                            <MessageResponse>{incrementalContent}</MessageResponse>
-                                          </MessageContent>
-                                        );
-                                      }
-                                      contentPosition = contentBefore.length;
+                                    // During streaming, approval.contentBeforeApproval is cumulative
+                                    // After loading, step.contentDeltaBeforeTool holds only the new content
+                                    let incrementalContent: string | undefined;
+                                    if (approval?.contentBeforeApproval !== undefined) {
+                                      incrementalContent = approval.contentBeforeApproval.substring(contentPosition);
+                                      contentPosition = approval.contentBeforeApproval.length;
+                                    } else if (step.contentDeltaBeforeTool) {
+                                      incrementalContent = step.contentDeltaBeforeTool;
+                                      contentPosition += step.contentDeltaBeforeTool.length;
+                                    }
+                                    if (incrementalContent?.trim()) {
+                                      elements.push(
+                                        <MessageContent key={`content-before-${idx}`}>
+                                          <MessageResponse>{incrementalContent}</MessageResponse>
+                                        </MessageContent>
+                                      );
                                     }
 
                                     // Show tool approval or result
@@ -733,7 +766,8 @@ const Chatbot = () => {
                                     } else if (step.result || step.error) {
                                       // No approval exists but we have a result (loaded from session)
                                       // Show the tool execution details using Tool component
-                                      const isRejected = step.error?.includes('rejected by user');
+                                      // Steps saved before skip statuses were recorded only have the error text
+                                      const isRejected = !!step.skipStatus || step.error?.includes('rejected by user');
                                       const toolState = isRejected
                                         ? 'output-denied'
                                         : step.status === 'error' || step.error
@@ -870,7 +904,10 @@ const Chatbot = () => {
                                   !version.content &&
                                   status === 'streaming' &&
                                   !message.thinkingSteps ? (
-                                  <ThinkingShimmer className="text-muted-foreground" />
+                                  <ThinkingShimmer
+                                    className="text-muted-foreground"
+                                    message={isRetrieving ? 'Searching documents...' : undefined}
+                                  />
                                 ) : (
                                   <MessageResponse preserveWhitespace={message.from === 'user'}>
                                     {version.content}
@@ -900,6 +937,29 @@ const Chatbot = () => {
                               })}
                             </>
                           )}
+                          {message.from === 'assistant' &&
+                            (message.finishReason === 'length' || message.finishReason === 'content_filter') && (
+                              <div className="mt-2 flex items-center gap-2">
+                                <Badge variant="outline" className="text-amber-600 dark:text-amber-400">
+                                  <AlertTriangleIcon className="size-3" />
+                                  {message.finishReason === 'length'
+                                    ? 'Response truncated (length)'
+                                    : 'Response stopped by content filter'}
+                                </Badge>
+                                {message.finishReason === 'length' &&
+                                  messageIndex === messages.length - 1 &&
+                                  !isSessionReadonly && (
+                                    <Button
+                                      size="sm"
+                                      variant="ghost"
+                                      disabled={status !== 'ready'}
+                                      onClick={() => handleSuggestionClick(CONTINUE_PROMPT)}
+                                    >
+                                      Continue
+                                    </Button>
+                                  )}
+                              </div>
+                            )}
                         </div>
                       </Message>
                     );
@@ -978,14 +1038,17 @@ const Chatbot = () => {
                 )}
                 {agentSupportsTools && (
                   <PromptInputButton
+                    disabled={!modelSupportsTools}
                     onClick={handleToolsToggle}
                     tooltip={{
-                      content: useTools
-                        ? 'Tools enabled - AI can use tools to help answer your questions'
-                        : 'Enable Tools to allow AI to use tools',
+                      content: !modelSupportsTools
+                        ? "This agent's model doesn't support tools"
+                        : useTools
+                          ? 'Tools enabled - AI can use tools to help answer your questions'
+                          : 'Enable Tools to allow AI to use tools',
                       side: 'top',
                     }}
-                    variant={useTools ? 'default' : 'ghost'}
+                    variant={useTools && modelSupportsTools ? 'default' : 'ghost'}
                   >
                     <WrenchIcon size={16} />
                     <span>Tools</span>
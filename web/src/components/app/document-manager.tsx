@@ -18,6 +18,7 @@ import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/com
 interface DocumentSummary {
   id: number;
   filename: string;
+  title: string;
   file_size: number;
   updated_at: number;
 }
@@ -330,8 +331,9 @@ export function DocumentManager({ apiUrl = '', onDocumentChange }: DocumentManag
                 <div className="flex items-center gap-3 flex-1 min-w-0">
                   <FileText className="h-4 w-4 text-muted-foreground shrink-0" />
                   <div className="flex-1 min-w-0">
-                    <div className="font-medium truncate">{doc.filename}</div>
+                    <div className="font-medium truncate">{doc.title || doc.filename}</div>
                     <div className="text-xs text-muted-foreground truncate">
+                      {doc.title && doc.title !== doc.filename && `${doc.filename} • `}
                       {formatFileSize(doc.file_size)} • Updated {formatDate(doc.updated_at)}
                     </div>
                   </div>
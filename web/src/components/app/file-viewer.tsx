@@ -94,6 +94,17 @@ const getLanguageFromFilename = (filename: string): BundledLanguage => {
   return languageMap[ext] || 'text';
 };
 
+// Response of GET /api/workspace/files/{path}
+interface WorkspaceFile {
+  path: string;
+  size: number;
+  language: string | null;
+  binary: boolean;
+  content?: string;
+  total_lines?: number;
+  truncated: boolean;
+}
+
 export function FileViewer() {
   const { '*': filePath } = useParams();
   const navigate = useNavigate();
@@ -141,8 +152,11 @@ export function FileViewer() {
         if (!response.ok) {
           throw new Error('Failed to fetch file content');
         }
-        const text = await response.text();
-        setContent(text);
+        const file: WorkspaceFile = await response.json();
+        if (file.binary) {
+          throw new Error(`Binary file (${file.size} bytes) cannot be displayed`);
+        }
+        setContent(file.content ?? '');
       } catch (err) {
         setError(err instanceof Error ? err.message : 'Unknown error');
       } finally {
@@ -185,7 +199,7 @@ export function FileViewer() {
       const fileAttachment = {
         id: `file-${Date.now()}`,
         type: 'file' as const,
-        url: `/api/workspace/files/${encodeURIComponent(filePath)}`,
+        url: `data:text/plain;charset=utf-8,${encodeURIComponent(content)}`,
         filename: fileName,
         mediaType: 'text/plain',
         size: content.length,
@@ -53,6 +53,9 @@ const makeSession = (overrides: Partial<SessionListItem> = {}): SessionListItem
   },
   cost_usd: 0.01,
   is_readonly: false,
+  last_message_role: "assistant",
+  last_message_at: 1700000600,
+  status: "idle",
   ...overrides,
 });
 
@@ -123,6 +126,21 @@ describe("SessionList", () => {
     expect(await screen.findByText("42 messages")).toBeInTheDocument();
   });
 
+  it("marks sessions that need attention", async () => {
+    mockListSessions.mockResolvedValue({
+      sessions: [
+        makeSession({ session_id: "a", title: "Waiting", status: "awaiting_approval" }),
+        makeSession({ session_id: "b", title: "Broken", status: "errored", last_message_role: "assistant" }),
+        makeSession({ session_id: "c", title: "Quiet", status: "idle" }),
+      ],
+      total: 3,
+    });
+    renderComponent();
+    expect(await screen.findByText("Needs approval")).toBeInTheDocument();
+    expect(screen.getByText("Failed")).toBeInTheDocument();
+    expect(screen.queryByText("Answering…")).not.toBeInTheDocument();
+  });
+
   it("renders formatted date", async () => {
     const now = new Date();
     const oneHourAgo = Math.floor((now.getTime() - 60 * 60 * 1000) / 1000);
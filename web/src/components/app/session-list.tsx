@@ -1,4 +1,4 @@
-import { deleteSession, listSessions, updateSessionTitle, type SessionListItem } from '@/lib/chat-api';
+import { deleteSession, listSessions, updateSessionTitle, type SessionListItem, type SessionStatus } from '@/lib/chat-api';
 import { Button } from '@/components/ui/button';
 import { ScrollArea } from '@/components/ui/scroll-area';
 import { Separator } from '@/components/ui/separator';
@@ -15,6 +15,13 @@ import { MessageSquare, Trash2, Plus, Pencil } from 'lucide-react';
 import { useCallback, useEffect, useState } from 'react';
 import { toast } from 'sonner';
 
+// Sessions that need a look; idle ones show nothing
+const STATUS_LABELS: Partial<Record<SessionStatus, { label: string; className: string }>> = {
+  awaiting_approval: { label: 'Needs approval', className: 'text-amber-600 dark:text-amber-400' },
+  errored: { label: 'Failed', className: 'text-destructive' },
+  streaming: { label: 'Answering…', className: 'text-primary' },
+};
+
 interface SessionListProps {
   currentSessionId: string | null;
   onSessionSelect: (sessionId: string) => void;
@@ -210,6 +217,22 @@ export function SessionList({
                           <span>{session.message_count} messages</span>
                           <span>•</span>
                           <span>{formatDate(session.updated_at)}</span>
+                          {(session.models_used?.length ?? 0) > 1 && (
+                            <>
+                              <span>•</span>
+                              <span title={session.models_used?.join(', ')}>
+                                {session.models_used?.length} models
+                              </span>
+                            </>
+                          )}
+                          {STATUS_LABELS[session.status] && (
+                            <>
+                              <span>•</span>
+                              <span className={STATUS_LABELS[session.status]?.className}>
+                                {STATUS_LABELS[session.status]?.label}
+                              </span>
+                            </>
+                          )}
                         </div>
                       </div>
                     </div>
@@ -106,6 +106,11 @@ describe('ThinkingShimmer', () => {
     expect(message).toBeTruthy();
   });
 
+  it('shows a given message instead of a random one', () => {
+    render(<ThinkingShimmer message="Searching documents..." />);
+    expect(screen.getByText('Searching documents...')).toBeInTheDocument();
+  });
+
   // ── Message variety coverage ───────────────────────────────────────────────
 
   it('eventually renders all predefined messages across many instances', () => {
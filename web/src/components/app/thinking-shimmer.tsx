@@ -24,6 +24,7 @@ const getRandomThinkingMessage = (): string => {
 
 interface ThinkingShimmerProps {
   className?: string;
+  message?: string; // Shown instead of a random thinking message
 }
 
 /**
@@ -31,10 +32,11 @@ interface ThinkingShimmerProps {
  * The message is selected once per component instance to provide variety
  * while maintaining consistency during the component's lifetime.
  */
-export const ThinkingShimmer = ({ className }: ThinkingShimmerProps) => {
+export const ThinkingShimmer = ({ className, message: fixedMessage }: ThinkingShimmerProps) => {
   const brainRef = useRef<BrainIconHandle>(null);
   // Memoize the message so it doesn't change on re-renders
-  const message = useMemo(() => getRandomThinkingMessage(), []);
+  const randomMessage = useMemo(() => getRandomThinkingMessage(), []);
+  const message = fixedMessage ?? randomMessage;
 
   // Start brain animation on mount
   useEffect(() => {
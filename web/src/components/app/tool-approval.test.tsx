@@ -61,6 +61,11 @@ describe('ToolApprovalComponent', () => {
     expect(screen.getByText('Write content to a file')).toBeInTheDocument();
   });
 
+  it('labels the request with the tool risk level', () => {
+    renderComponent(makeApproval({ risk: 'writes_files' }));
+    expect(screen.getByText('Writes files')).toBeInTheDocument();
+  });
+
   it('renders "Tool Execution Request" heading', () => {
     renderComponent(makeApproval());
     expect(screen.getByText('Tool Execution Request')).toBeInTheDocument();
@@ -91,6 +96,19 @@ describe('ToolApprovalComponent', () => {
     expect(screen.getByText(/x{100}\.\.\. \(150 chars\)/)).toBeInTheDocument();
   });
 
+  it('shows the preview and size of offloaded arguments', () => {
+    const approval = makeApproval({
+      tool_args: {
+        content: { content_ref: 12, size: 40000, sha256: 'abc', preview: 'y'.repeat(2048) },
+      },
+    });
+    renderComponent(approval);
+
+    expect(
+      screen.getByText(/y{100}\.\.\. \(40000 bytes, stored content #12\)/),
+    ).toBeInTheDocument();
+  });
+
   it('handles empty tool args gracefully', () => {
     const approval = makeApproval({ tool_args: {} });
     renderComponent(approval);
@@ -183,6 +201,20 @@ describe('ToolApprovalComponent', () => {
     expect(defaultProps.onReject).toHaveBeenCalledWith(false);
   });
 
+  it('offers to allow the directory only for reads that carry one', async () => {
+    const user = userEvent.setup();
+    const { unmount } = renderComponent(makeApproval());
+    expect(screen.queryByText('Allow this directory')).not.toBeInTheDocument();
+    unmount();
+
+    renderComponent(
+      makeApproval({ tool_name: 'read_file', tool_args: { path: 'src/main.rs' }, directory: '/project/src' })
+    );
+    await user.click(screen.getByText('Allow this directory'));
+
+    expect(defaultProps.onApprove).toHaveBeenCalledWith(false, 'directory');
+  });
+
   // ── Always options ─────────────────────────────────────────────────────────
 
   it('shows "Always Reject" and "Always Approve" buttons when "Always..." is clicked', async () => {
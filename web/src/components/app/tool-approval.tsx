@@ -1,4 +1,4 @@
-import { useCallback, useMemo, useState } from 'react';
+import { useCallback, useEffect, useMemo, useState } from 'react';
 import { CheckIcon, XIcon } from 'lucide-react';
 import {
   Confirmation,
@@ -9,6 +9,15 @@ import {
   ConfirmationAction,
 } from '@/components/ai-elements/confirmation';
 import type { ToolApproval } from '@/stores/chat-store';
+import type { ToolRisk } from '@/lib/chat-api';
+
+/** Label and color of each risk level, from harmless to most dangerous */
+const RISK_STYLES: Record<ToolRisk, { label: string; className: string }> = {
+  read_only: { label: 'Read-only', className: 'text-green-700 bg-green-100 dark:text-green-300 dark:bg-green-950' },
+  writes_files: { label: 'Writes files', className: 'text-amber-700 bg-amber-100 dark:text-amber-300 dark:bg-amber-950' },
+  network: { label: 'Network access', className: 'text-blue-700 bg-blue-100 dark:text-blue-300 dark:bg-blue-950' },
+  executes_commands: { label: 'Runs commands', className: 'text-red-700 bg-red-100 dark:text-red-300 dark:bg-red-950' },
+};
 
 interface ToolApprovalProps {
   approval: ToolApproval;
@@ -18,6 +27,43 @@ interface ToolApprovalProps {
   isRejected?: boolean;
 }
 
+/** Seconds until the approval expires, updated every second while pending */
+const useSecondsRemaining = (expiresAt: number | undefined, active: boolean) => {
+  const compute = useCallback(
+    () => (expiresAt === undefined ? undefined : Math.max(0, Math.ceil(expiresAt - Date.now() / 1000))),
+    [expiresAt],
+  );
+  const [remaining, setRemaining] = useState(compute);
+
+  useEffect(() => {
+    setRemaining(compute());
+    if (!active || expiresAt === undefined) return;
+    const timer = setInterval(() => setRemaining(compute()), 1000);
+    return () => clearInterval(timer);
+  }, [active, expiresAt, compute]);
+
+  return remaining;
+};
+
+const formatRemaining = (seconds: number) => {
+  const minutes = Math.floor(seconds / 60);
+  const rest = seconds % 60;
+  return minutes > 0 ? `${minutes}:${String(rest).padStart(2, '0')}` : `${rest}s`;
+};
+
+interface ContentRef {
+  content_ref: number;
+  size: number;
+  preview: string;
+}
+
+// Large arguments arrive as references to content stored on the server
+const isContentRef = (value: unknown): value is ContentRef =>
+  typeof value === 'object' &&
+  value !== null &&
+  typeof (value as ContentRef).content_ref === 'number' &&
+  typeof (value as ContentRef).preview === 'string';
+
 export const ToolApprovalComponent = ({
   approval,
   onApprove,
@@ -26,6 +72,7 @@ export const ToolApprovalComponent = ({
   isRejected,
 }: ToolApprovalProps) => {
   const [showAlwaysOptions, setShowAlwaysOptions] = useState(false);
+  const secondsRemaining = useSecondsRemaining(approval.expires_at, !isApproved && !isRejected);
 
   // Determine the state for the Confirmation component
   const state = useMemo(() => {
@@ -68,6 +115,11 @@ export const ToolApprovalComponent = ({
     setShowAlwaysOptions(false);
   }, [onApprove, approval.tool_name]);
 
+  const handleDirectoryApprove = useCallback(() => {
+    onApprove(false, 'directory');
+    setShowAlwaysOptions(false);
+  }, [onApprove]);
+
   const handleAlwaysReject = useCallback(() => {
     onReject(true);
     setShowAlwaysOptions(false);
@@ -86,9 +138,12 @@ export const ToolApprovalComponent = ({
       <div className="mt-2 space-y-1">
         {entries.map(([key, value]) => {
           let displayValue = String(value);
-          
-          // Truncate long values
-          if (displayValue.length > 100) {
+
+          // Offloaded content: show the inline preview and the full size
+          if (isContentRef(value)) {
+            displayValue = `${value.preview.substring(0, 100)}... (${value.size} bytes, stored content #${value.content_ref})`;
+          } else if (displayValue.length > 100) {
+            // Truncate long values
             displayValue = `${displayValue.substring(0, 100)}... (${displayValue.length} chars)`;
           }
 
@@ -103,6 +158,36 @@ export const ToolApprovalComponent = ({
     );
   }, [approval.tool_args]);
 
+  // Server-side preview: diff for writes, resolved path for reads, command and cwd for bash
+  const renderPreview = useCallback(() => {
+    const preview = approval.preview;
+    if (!preview) return null;
+
+    return (
+      <div className="mt-2 space-y-1">
+        <div className="text-sm font-medium">{preview.summary}</div>
+        {preview.sandbox && <div className="text-xs text-muted-foreground">{preview.sandbox}</div>}
+        {preview.diff && (
+          <pre className="max-h-96 overflow-auto rounded-md border bg-muted p-2 font-mono text-xs">
+            {preview.diff.split('\n').map((line, idx) => {
+              let className = 'text-muted-foreground';
+              if (line.startsWith('+++') || line.startsWith('---')) className = 'font-semibold';
+              else if (line.startsWith('+')) className = 'text-green-600 dark:text-green-400';
+              else if (line.startsWith('-')) className = 'text-red-600 dark:text-red-400';
+              else if (line.startsWith('@@')) className = 'text-blue-600 dark:text-blue-400';
+              return (
+                <div key={idx} className={className}>
+                  {line || ' '}
+                </div>
+              );
+            })}
+          </pre>
+        )}
+        {preview.truncated && <div className="text-xs text-muted-foreground">Diff truncated</div>}
+      </div>
+    );
+  }, [approval.preview]);
+
   return (
     <Confirmation approval={confirmationApproval} state={state} className="my-2">
       <ConfirmationRequest>
@@ -110,11 +195,23 @@ export const ToolApprovalComponent = ({
           <div className="font-semibold">Tool Execution Request</div>
           <div className="text-sm">
             The assistant wants to use <span className="font-mono font-semibold">{approval.tool_name}</span>
+            {approval.risk && (
+              <span className={`ml-2 rounded px-1.5 py-0.5 text-xs font-medium ${RISK_STYLES[approval.risk].className}`}>
+                {RISK_STYLES[approval.risk].label}
+              </span>
+            )}
           </div>
           {approval.tool_description && (
             <div className="text-sm text-muted-foreground">{approval.tool_description}</div>
           )}
-          {formatToolArgs()}
+          {approval.preview ? renderPreview() : formatToolArgs()}
+          {secondsRemaining !== undefined && (
+            <div className="text-xs text-muted-foreground" aria-live="polite">
+              {secondsRemaining > 0
+                ? `Expires in ${formatRemaining(secondsRemaining)}`
+                : 'Request expired - the tool will not run'}
+            </div>
+          )}
         </div>
       </ConfirmationRequest>
 
@@ -140,6 +237,15 @@ export const ToolApprovalComponent = ({
           <ConfirmationAction variant="default" onClick={handleApprove}>
             Approve
           </ConfirmationAction>
+          {approval.directory && (
+            <ConfirmationAction
+              variant="outline"
+              onClick={handleDirectoryApprove}
+              title={`Later reads under ${approval.directory} run without asking until this session ends`}
+            >
+              Allow this directory
+            </ConfirmationAction>
+          )}
           <ConfirmationAction variant="secondary" onClick={toggleAlwaysOptions} className="ml-auto">
             {showAlwaysOptions ? 'Hide' : 'Always...'}
           </ConfirmationAction>
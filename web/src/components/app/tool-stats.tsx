@@ -0,0 +1,80 @@
+import { useEffect, useState } from 'react';
+import { Card, CardContent, CardDescription, CardHeader, CardTitle } from '@/components/ui/card';
+import { fetchToolStats, type ToolStats, type ToolStatsResponse } from '@/lib/chat-api';
+import { Wrench } from 'lucide-react';
+
+interface ToolStatsCardProps {
+  apiUrl: string;
+  days?: number;
+}
+
+const ToolStatsTable = ({ rows }: { rows: ToolStats[] }) => (
+  <table className="w-full text-sm">
+    <thead>
+      <tr className="text-left text-muted-foreground">
+        <th className="font-medium py-1">Name</th>
+        <th className="font-medium py-1 text-right">Calls</th>
+        <th className="font-medium py-1 text-right">OK</th>
+        <th className="font-medium py-1 text-right">Errors</th>
+        <th className="font-medium py-1 text-right">Denied</th>
+        <th className="font-medium py-1 text-right">Avg result</th>
+      </tr>
+    </thead>
+    <tbody>
+      {rows.map((row) => (
+        <tr key={row.name} className="border-t">
+          <td className="py-1 font-mono">{row.name}</td>
+          <td className="py-1 text-right">{row.invocations}</td>
+          <td className="py-1 text-right">{row.successes}</td>
+          <td className="py-1 text-right">{row.errors}</td>
+          <td className="py-1 text-right">{row.denied}</td>
+          <td className="py-1 text-right">{Math.round(row.avg_result_bytes)} B</td>
+        </tr>
+      ))}
+    </tbody>
+  </table>
+);
+
+export const ToolStatsCard = ({ apiUrl, days = 30 }: ToolStatsCardProps) => {
+  const [stats, setStats] = useState<ToolStatsResponse | null>(null);
+  const [error, setError] = useState<string | null>(null);
+
+  useEffect(() => {
+    fetchToolStats(apiUrl, days)
+      .then((data) => {
+        setStats(data);
+        setError(null);
+      })
+      .catch((err) => {
+        setError(err instanceof Error ? err.message : 'Failed to load tool statistics');
+      });
+  }, [apiUrl, days]);
+
+  return (
+    <Card className="mb-4">
+      <CardHeader>
+        <CardTitle className="text-lg flex items-center gap-2">
+          <Wrench className="h-5 w-5" />
+          Most used tools
+        </CardTitle>
+        <CardDescription>Tool calls in the last {days} days</CardDescription>
+      </CardHeader>
+      <CardContent>
+        {error && <div className="text-center text-red-500">{error}</div>}
+        {!error && !stats && (
+          <div className="text-center text-muted-foreground">Loading tool statistics...</div>
+        )}
+        {stats && stats.tools.length === 0 && (
+          <div className="text-center text-muted-foreground">No tool calls yet.</div>
+        )}
+        {stats && stats.tools.length > 0 && <ToolStatsTable rows={stats.tools} />}
+        {stats && stats.bash_commands.length > 0 && (
+          <div className="mt-4 pt-4 border-t">
+            <div className="text-sm text-muted-foreground mb-2">Bash commands</div>
+            <ToolStatsTable rows={stats.bash_commands} />
+          </div>
+        )}
+      </CardContent>
+    </Card>
+  );
+};
@@ -8,20 +8,88 @@
 export interface FileAttachment {
   filename: string;
   content: string;
+  for_review?: boolean; // Send with line numbers so findings cite exact lines
 }
 
 export interface ChatMessage {
   message: string;
   session_id?: string;
+  create_if_missing?: boolean; // Create the session under session_id (a lowercase UUID) instead of failing with 404
   files?: FileAttachment[];
   system_prompt?: string;
   agent_id: string;
   use_rag?: boolean;
   use_tools?: boolean;
+  force_reattach?: boolean; // Resend files even if identical content was sent earlier in the session
+  debug?: boolean; // Capture the provider requests of this turn for the prompt inspector
+  reasoning?: ReasoningSettings; // Falls back to generation.reasoning in the server config
+  tool_choice?: ToolChoice; // Defaults to 'auto'
+  workspace?: string; // Configured workspace the session runs in
+  client_message_id?: string; // Idempotency key: a retry with the same id replays the saved answer
 }
 
+export type ToolChoice = 'auto' | 'none' | { name: string };
+
+export interface ReasoningSettings {
+  effort?: 'low' | 'medium' | 'high';
+  enabled?: boolean;
+}
+
+export interface MessageMetadata {
+  reasoning?: ReasoningSettings; // Reasoning settings sent to the model for this answer
+  squid_version?: string; // squid version that generated the answer
+  provider_host?: string; // Host and port of the provider api_url
+  model?: string; // Model id the answer was requested from
+}
+
+// Machine-readable reason of a failed request or an `error` stream event (see docs/API.md)
+export type ErrorCode =
+  | 'server_shutting_down'
+  | 'config_error'
+  | 'database_error'
+  | 'provider_unavailable'
+  | 'provider_error'
+  | 'tool_denied'
+  | 'path_not_allowed'
+  | 'rag_error'
+  | 'session_not_found'
+  | 'session_busy'
+  | 'model_locked'
+  | 'internal_error';
+
+// Body of a failed API request
+export interface ApiErrorBody {
+  error: string;
+  code?: ErrorCode | string; // Endpoint-specific codes such as 'attachments_too_large' also appear
+  retriable?: boolean; // Sending the same request again may succeed
+}
+
+// A failed API request, with the reason the server gave
+export class ApiError extends Error {
+  status: number;
+  code?: ApiErrorBody['code'];
+  retriable?: boolean;
+
+  constructor(status: number, body: Partial<ApiErrorBody>) {
+    super(body.error || `HTTP error! status: ${status}`);
+    this.name = 'ApiError';
+    this.status = status;
+    this.code = body.code;
+    this.retriable = body.retriable;
+  }
+}
+
+// Why the model stopped generating an answer; 'length' means it was cut off at the token limit
+export type FinishReason = 'end_turn' | 'tool_calls' | 'length' | 'content_filter' | 'other';
+
+// Sent as the next user message to continue an answer cut off at the token limit
+// (same text as `POST /api/sessions/{id}/continue`)
+export const CONTINUE_PROMPT = 'Continue exactly where your previous answer was cut off, without repeating it.';
+
 export type StreamEventType =
   | 'session'
+  | 'retrieval_started'
+  | 'retrieval_failed'
   | 'sources'
   | 'content'
   | 'reasoning'
@@ -29,14 +97,29 @@ export type StreamEventType =
   | 'tool_result'
   | 'tool_approval_request'
   | 'tool_approval_response'
+  | 'tool_output_chunk'
   | 'tool_invocation_completed'
   | 'usage'
+  | 'context_breakdown'
+  | 'trace_id'
+  | 'turn'
+  | 'warning'
   | 'error'
+  | 'chunked'
   | 'done';
 
+export type SourceOrigin = 'attachment' | 'rag' | 'tool' | 'auto'; // 'auto': context.auto_include
+
 export interface Source {
   title: string;
   content: string;
+  origin: SourceOrigin;
+}
+
+// Approximate tokens one part of the context takes, e.g. 'history' or 'attachments'
+export interface ContextCategory {
+  name: string;
+  tokens: number;
 }
 
 export interface TokenUsage {
@@ -49,73 +132,152 @@ export interface TokenUsage {
   context_utilization: number;
 }
 
+// What a tool can do, sent with approval requests so prompts can be colored by it
+export type ToolRisk = 'read_only' | 'writes_files' | 'executes_commands' | 'network';
+
+// Why a tool call didn't run
+export type SkipStatus = 'denied_by_config' | 'rejected_by_user' | 'approval_timeout' | 'blocked_dangerous';
+
+export interface ToolPreview {
+  summary: string;
+  path?: string; // Resolved absolute path after validation
+  path_exists?: boolean;
+  diff?: string; // Unified diff (write_file only)
+  command?: string;
+  cwd?: string;
+  sandbox?: string; // Whether and how a bash command will be sandboxed
+  truncated: boolean;
+}
+
 export interface StreamEvent {
   type: StreamEventType;
+  seq?: number; // Monotonically increasing per stream, used to resume after a dropped connection
   session_id?: string;
+  query?: string; // Retrieval started: the question the documents are searched for
   sources?: Source[];
   text?: string;
   name?: string;
+  tool_call_id?: string;
+  stream?: 'stdout' | 'stderr'; // Output stream of a tool_output_chunk line
   arguments?: string;
   result?: string;
   error?: string;
+  status?: SkipStatus; // Tool invocation that didn't run
   approval_id?: string;
   tool_name?: string;
   tool_args?: Record<string, unknown>;
   tool_description?: string;
+  risk?: ToolRisk; // Approval request: what the tool can do
+  preview?: ToolPreview;
+  resolved_path?: string; // Canonical absolute path a file tool will touch
+  directory?: string; // Directory a read approval can allow for the rest of the session
+  expires_at?: number; // Unix timestamp (seconds) when a pending approval expires
+  timeout_secs?: number;
   approved?: boolean;
+  timed_out?: boolean; // Approval expired without a response
   input_tokens?: number;
   output_tokens?: number;
   reasoning_tokens?: number;
   cache_tokens?: number;
+  estimated?: boolean; // Running output estimate, replaced by the next reported usage
+  trace_id?: string; // Captured provider request, see fetchSessionTraces
+  categories?: ContextCategory[]; // Context breakdown, sent before the model is asked
   message?: string;
+  code?: ErrorCode | string; // Machine-readable error or warning reason, e.g. 'server_shutting_down'
+  id?: number; // Chunked: which event the chunk belongs to
+  index?: number; // Chunked: position of this chunk, from 0
+  total?: number; // Chunked: number of chunks in the event
+  data?: string; // Chunked: a slice of the event's JSON
+  message_index?: number; // Turn: index of the replayed assistant message
+  prompt?: string; // Turn: user message the replayed answer responds to
+  finish_reason?: FinishReason; // Done: why the model stopped generating
+  message_id?: number; // Done: id of the saved answer
+}
+
+/**
+ * Collect a `chunked` event's slice; returns the original event's JSON once all slices arrived
+ */
+export function joinChunkedEvent(pending: Map<number, string[]>, chunk: StreamEvent): string | null {
+  const { id = 0, index = 0, total = 1, data = '' } = chunk;
+  const parts = pending.get(id) ?? new Array<string>(total);
+  parts[index] = data;
+  pending.set(id, parts);
+  for (let i = 0; i < total; i++) {
+    if (parts[i] === undefined) return null;
+  }
+  pending.delete(id);
+  return parts.join('');
 }
 
 export interface StreamHandlers {
   onSession?: (sessionId: string) => void;
+  onRetrievalStarted?: (query: string) => void;
+  onRetrievalFailed?: (message: string) => void; // The answer continues without document context
   onSources?: (sources: Source[]) => void;
   onContent: (text: string) => void;
   onReasoning?: (text: string) => void;
   onToolCall?: (name: string, args: string) => void;
   onToolResult?: (name: string, result: string) => void;
+  onToolOutputChunk?: (chunk: { tool_call_id: string; name: string; stream: 'stdout' | 'stderr'; text: string }) => void;
   onToolInvocationCompleted?: (tool: {
     name: string;
     arguments: Record<string, unknown>;
     result?: string;
     error?: string;
+    status?: SkipStatus;
   }) => void;
   onToolApprovalRequest?: (approval: {
     approval_id: string;
     tool_name: string;
     tool_args: Record<string, unknown>;
     tool_description: string;
+    risk?: ToolRisk;
+    preview?: ToolPreview;
+    resolved_path?: string;
+    directory?: string;
+    expires_at?: number;
   }) => void;
-  onToolApprovalResponse?: (approval_id: string, approved: boolean) => void;
+  onToolApprovalResponse?: (approval_id: string, approved: boolean, timed_out?: boolean) => void;
   onUsage?: (usage: {
     input_tokens: number;
     output_tokens: number;
     reasoning_tokens: number;
     cache_tokens: number;
+    estimated: boolean;
   }) => void;
-  onError?: (error: string) => void;
-  onDone?: () => void;
+  onContextBreakdown?: (categories: ContextCategory[]) => void;
+  onTraceId?: (traceId: string) => void;
+  onWarning?: (message: string, code?: string) => void;
+  onError?: (error: string, code?: ErrorCode | string) => void;
+  onDone?: (finishReason?: FinishReason) => void;
   signal?: AbortSignal;
 }
 
 export interface SessionMessage {
+  id?: number;
+  parent_message_id?: number;
   role: string;
   content: string;
   sources: Source[];
   timestamp: number;
-  thinking_steps?: Array<{
-    step_type: string;
-    step_order: number;
-    content?: string;
-    tool_name?: string;
-    tool_arguments?: Record<string, unknown>;
-    tool_result?: string;
-    tool_error?: string;
-    content_before_tool?: string;
-  }>;
+  thinking_steps?: SessionThinkingStep[];
+  metadata?: MessageMetadata;
+  finish_reason?: FinishReason;
+}
+
+export interface SessionThinkingStep {
+  step_id?: number; // Fetch the full tool result with fetchThinkingStep
+  step_type: string;
+  step_order: number;
+  content?: string;
+  tool_name?: string;
+  tool_arguments?: Record<string, unknown>;
+  tool_result?: string;
+  tool_error?: string;
+  content_delta_before_tool?: string;
+  status?: SkipStatus; // Why the tool didn't run
+  result_size?: number; // Size of the full tool result in bytes
+  result_truncated?: boolean; // tool_result is only a preview
 }
 
 export interface SessionData {
@@ -127,6 +289,10 @@ export interface SessionData {
   agent_id: string | null;
   token_usage: TokenUsage;
   cost_usd: number;
+  working_dir?: string | null;
+  workspace?: string | null;
+  models_used?: string[]; // Models that answered, in order of first use
+  locked_model?: string | null; // Chat requests for other models are refused
 }
 
 export interface SessionListItem {
@@ -140,8 +306,19 @@ export interface SessionListItem {
   token_usage: TokenUsage;
   cost_usd: number;
   is_readonly: boolean;
+  working_dir?: string | null;
+  workspace?: string | null;
+  schedule?: string | null; // Scheduled prompt whose runs created the session
+  models_used?: string[]; // Models that answered, in order of first use
+  locked_model?: string | null;
+  last_message_role: 'user' | 'assistant' | null;
+  last_message_at: number | null;
+  status: SessionStatus;
 }
 
+// What a listed session is waiting on
+export type SessionStatus = 'idle' | 'awaiting_approval' | 'errored' | 'streaming';
+
 export interface SessionListResponse {
   sessions: SessionListItem[];
   total: number;
@@ -181,13 +358,19 @@ export interface SessionListResponse {
 export async function streamChat(apiUrl: string, message: ChatMessage, handlers: StreamHandlers): Promise<void> {
   const {
     onSession,
+    onRetrievalStarted,
+    onRetrievalFailed,
     onSources,
     onContent,
     onReasoning,
     onToolCall,
     onToolResult,
+    onToolOutputChunk,
     onToolInvocationCompleted,
     onUsage,
+    onContextBreakdown,
+    onTraceId,
+    onWarning,
     onError,
     onDone,
     signal,
@@ -206,7 +389,11 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
     });
 
     if (!response.ok) {
-      throw new Error(`HTTP error! status: ${response.status}`);
+      if (response.status === 429) {
+        const retryAfter = response.headers.get('Retry-After');
+        throw new Error(`Too many requests. Try again in ${retryAfter ?? 'a few'} seconds.`);
+      }
+      throw new ApiError(response.status, await response.json().catch(() => ({})));
     }
 
     const reader = response.body?.getReader();
@@ -217,6 +404,8 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
     }
 
     let buffer = '';
+    // Slices of events the server split into `chunked` events, by event id
+    const chunkedEvents = new Map<number, string[]>();
 
     while (true) {
       const { done, value } = await reader.read();
@@ -233,7 +422,12 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
         if (line.startsWith('data: ')) {
           const data = line.slice(6);
           try {
-            const event: StreamEvent = JSON.parse(data);
+            let event: StreamEvent = JSON.parse(data);
+            if (event.type === 'chunked') {
+              const joined = joinChunkedEvent(chunkedEvents, event);
+              if (joined === null) continue;
+              event = JSON.parse(joined);
+            }
 
             switch (event.type) {
               case 'session':
@@ -242,6 +436,16 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
                 }
                 break;
 
+              case 'retrieval_started':
+                onRetrievalStarted?.(event.query ?? '');
+                break;
+
+              case 'retrieval_failed':
+                if (onRetrievalFailed && event.message) {
+                  onRetrievalFailed(event.message);
+                }
+                break;
+
               case 'sources':
                 if (onSources && event.sources) {
                   onSources(event.sources);
@@ -272,6 +476,17 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
                 }
                 break;
 
+              case 'tool_output_chunk':
+                if (onToolOutputChunk && event.tool_call_id && event.name && event.text !== undefined) {
+                  onToolOutputChunk({
+                    tool_call_id: event.tool_call_id,
+                    name: event.name,
+                    stream: event.stream ?? 'stdout',
+                    text: event.text,
+                  });
+                }
+                break;
+
               case 'tool_invocation_completed':
                 if (onToolInvocationCompleted && event.name && event.arguments) {
                   // Parse arguments if it's a string
@@ -287,6 +502,7 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
                     arguments: parsedArgs,
                     result: event.result,
                     error: event.error,
+                    status: event.status,
                   });
                 }
                 break;
@@ -304,13 +520,18 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
                     tool_name: event.tool_name,
                     tool_args: event.tool_args,
                     tool_description: event.tool_description,
+                    risk: event.risk,
+                    preview: event.preview,
+                    resolved_path: event.resolved_path,
+                    directory: event.directory,
+                    expires_at: event.expires_at,
                   });
                 }
                 break;
 
               case 'tool_approval_response':
                 if (handlers.onToolApprovalResponse && event.approval_id && event.approved !== undefined) {
-                  handlers.onToolApprovalResponse(event.approval_id, event.approved);
+                  handlers.onToolApprovalResponse(event.approval_id, event.approved, event.timed_out);
                 }
                 break;
 
@@ -321,19 +542,38 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
                     output_tokens: event.output_tokens,
                     reasoning_tokens: event.reasoning_tokens || 0,
                     cache_tokens: event.cache_tokens || 0,
+                    estimated: event.estimated ?? false,
                   });
                 }
                 break;
 
+              case 'context_breakdown':
+                if (onContextBreakdown && event.categories) {
+                  onContextBreakdown(event.categories);
+                }
+                break;
+
+              case 'trace_id':
+                if (onTraceId && event.trace_id) {
+                  onTraceId(event.trace_id);
+                }
+                break;
+
+              case 'warning':
+                if (onWarning && event.message) {
+                  onWarning(event.message, event.code);
+                }
+                break;
+
               case 'error':
                 if (onError && event.message) {
-                  onError(event.message);
+                  onError(event.message, event.code);
                 }
                 break;
 
               case 'done':
                 if (onDone) {
-                  onDone();
+                  onDone(event.finish_reason);
                 }
                 return;
             }
@@ -345,7 +585,10 @@ export async function streamChat(apiUrl: string, message: ChatMessage, handlers:
     }
   } catch (error) {
     if (onError) {
-      onError(error instanceof Error ? error.message : String(error));
+      onError(
+        error instanceof Error ? error.message : String(error),
+        error instanceof ApiError ? error.code : undefined
+      );
     }
     throw error;
   }
@@ -388,9 +631,9 @@ export function useChatStream(apiUrl: string) {
           setIsStreaming(false);
           handlers.onDone?.();
         },
-        onError: (error) => {
+        onError: (error, code) => {
           setIsStreaming(false);
-          handlers.onError?.(error);
+          handlers.onError?.(error, code);
         },
       });
     } catch (error) {
@@ -402,6 +645,136 @@ export function useChatStream(apiUrl: string) {
   return { sendMessage, isStreaming };
 }
 
+export interface MissedEventsResponse {
+  session_id: string;
+  events: StreamEvent[];
+  finished: boolean;
+}
+
+/**
+ * Fetch chat stream events missed after a dropped connection
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param sessionId - The session ID of the interrupted stream
+ * @param afterSeq - The last `seq` number the client received
+ * @returns Promise with the missed events or null if the stream is no longer buffered
+ */
+export async function fetchMissedEvents(
+  apiUrl: string,
+  sessionId: string,
+  afterSeq: number
+): Promise<MissedEventsResponse | null> {
+  try {
+    const path = `/api/sessions/${sessionId}/events?after_seq=${afterSeq}`;
+    const response = await fetch(apiUrl ? `${apiUrl}${path}` : path);
+
+    if (!response.ok) {
+      if (response.status === 404) {
+        return null;
+      }
+      throw new Error(`HTTP error! status: ${response.status}`);
+    }
+
+    return await response.json();
+  } catch (error) {
+    console.error('Failed to fetch missed events:', error);
+    return null;
+  }
+}
+
+/**
+ * Stop the chat turn running on a session
+ *
+ * Aborting the fetch only disconnects; the server keeps the turn running until it is cancelled.
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param sessionId - The session whose turn to stop
+ * @returns Promise with boolean indicating whether a turn was stopped
+ */
+export async function cancelChatTurn(apiUrl: string, sessionId: string): Promise<boolean> {
+  try {
+    const path = `/api/sessions/${sessionId}/cancel`;
+    const response = await fetch(apiUrl ? `${apiUrl}${path}` : path, {
+      method: 'POST',
+    });
+
+    // 404: the turn already ended
+    return response.ok;
+  } catch (error) {
+    console.error('Failed to cancel chat turn:', error);
+    return false;
+  }
+}
+
+export interface RequestTrace {
+  id: string;
+  session_id: string;
+  message_id: number | null; // Assistant message produced by the request
+  model: string;
+  request: {
+    provider: string;
+    api_url: string;
+    params: Record<string, unknown>;
+    messages: Array<Record<string, unknown>>;
+    tools: Array<Record<string, unknown>>;
+  };
+  response: {
+    stop_reason: string | null;
+    input_tokens: number | null;
+    output_tokens: number | null;
+    tool_calls: string[];
+    content_chars: number;
+    duration_ms: number;
+    error?: string;
+  } | null;
+  truncated: boolean; // Long strings were shortened to fit debug.max_trace_bytes
+  created_at: number;
+}
+
+/**
+ * Fetch the provider requests captured for a session (prompt inspector)
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param sessionId - The session to inspect
+ * @returns Promise with the traces, oldest first
+ */
+export async function fetchSessionTraces(apiUrl: string, sessionId: string): Promise<RequestTrace[]> {
+  const path = `/api/sessions/${sessionId}/traces`;
+  const response = await fetch(apiUrl ? `${apiUrl}${path}` : path);
+
+  if (!response.ok) {
+    throw new Error(`HTTP error! status: ${response.status}`);
+  }
+
+  const data: { traces: RequestTrace[] } = await response.json();
+  return data.traces;
+}
+
+/**
+ * Fetch one thinking step with its full tool result
+ *
+ * Session responses only include a preview of long tool results (`result_truncated`).
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param sessionId - The session the step belongs to
+ * @param stepId - The step's `step_id`
+ * @returns Promise with the complete step
+ */
+export async function fetchThinkingStep(
+  apiUrl: string,
+  sessionId: string,
+  stepId: number,
+): Promise<SessionThinkingStep> {
+  const path = `/api/sessions/${sessionId}/steps/${stepId}`;
+  const response = await fetch(apiUrl ? `${apiUrl}${path}` : path);
+
+  if (!response.ok) {
+    throw new Error(`HTTP error! status: ${response.status}`);
+  }
+
+  return response.json();
+}
+
 /**
  * Load a session's history from the API
  *
@@ -502,6 +875,41 @@ export async function deleteSession(apiUrl: string, sessionId: string): Promise<
   }
 }
 
+/** Sessions to remove with bulkDeleteSessions; a session must match every field that is set */
+export interface SessionDeleteFilter {
+  ids?: string[];
+  older_than_days?: number;
+  title_contains?: string;
+  untitled_only?: boolean;
+  max_messages?: number;
+  /** Required to delete every session when no ids or filters are set */
+  confirm_all?: boolean;
+}
+
+/**
+ * Delete sessions by id or by filter
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param filter - Ids and/or conditions the deleted sessions must match
+ * @returns The ids of the deleted sessions
+ */
+export async function bulkDeleteSessions(apiUrl: string, filter: SessionDeleteFilter): Promise<string[]> {
+  const endpoint = apiUrl ? `${apiUrl}/api/sessions/bulk-delete` : '/api/sessions/bulk-delete';
+  const response = await fetch(endpoint, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(filter),
+  });
+
+  if (!response.ok) {
+    const body = await response.json().catch(() => ({}));
+    throw new Error(body.error || `HTTP error! status: ${response.status}`);
+  }
+
+  const result: { deleted: number; session_ids: string[] } = await response.json();
+  return result.session_ids;
+}
+
 /**
  * Update a session's title
  *
@@ -543,6 +951,36 @@ export async function updateSessionTitle(apiUrl: string, sessionId: string, titl
   }
 }
 
+/**
+ * Sets the directory tools run in for a session
+ *
+ * @param apiUrl - Base API URL (empty string for same origin)
+ * @param sessionId - The session ID to update
+ * @param workingDir - Directory path, or an empty string to reset to the server's startup directory
+ * @returns An error message if the directory was rejected, or null on success
+ */
+export async function updateSessionWorkingDir(
+  apiUrl: string,
+  sessionId: string,
+  workingDir: string
+): Promise<string | null> {
+  const endpoint = apiUrl ? `${apiUrl}/api/sessions/${sessionId}` : `/api/sessions/${sessionId}`;
+  const response = await fetch(endpoint, {
+    method: 'PATCH',
+    headers: {
+      'Content-Type': 'application/json',
+    },
+    body: JSON.stringify({ working_dir: workingDir }),
+  });
+
+  if (!response.ok) {
+    const data = await response.json().catch(() => ({}));
+    return data.error || `HTTP error! status: ${response.status}`;
+  }
+
+  return null;
+}
+
 export interface AgentInfo {
   id: string;
   name: string;
@@ -550,6 +988,8 @@ export interface AgentInfo {
   model: string;
   enabled: boolean;
   use_tools: boolean;
+  /** False when the agent's model can't call tools; tools are not sent to it */
+  model_supports_tools?: boolean;
   permissions: {
     allow: string[];
     deny: string[];
@@ -605,6 +1045,39 @@ export async function fetchAgents(apiUrl: string): Promise<AgentsResponse> {
   return data;
 }
 
+export interface ModelEntry {
+  id: string;
+  context_length?: number;
+  agents: string[];
+  available: boolean;
+  supports_tools: boolean;
+}
+
+export interface ModelsResponse {
+  models: ModelEntry[];
+  stale: boolean;
+}
+
+/**
+ * Fetch provider models merged with agent configuration
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param refresh - Bypass the server-side cache
+ * @returns Promise with models; `stale` is true when the provider was unreachable
+ */
+export async function fetchModels(apiUrl: string, refresh = false): Promise<ModelsResponse> {
+  const path = refresh ? '/api/models?refresh=true' : '/api/models';
+  const endpoint = apiUrl ? `${apiUrl}${path}` : path;
+  const response = await fetch(endpoint);
+
+  if (!response.ok) {
+    throw new Error(`Failed to fetch models: HTTP ${response.status}`);
+  }
+
+  const data: ModelsResponse = await response.json();
+  return data;
+}
+
 /**
  * Fetch token statistics for all agents
  *
@@ -664,6 +1137,43 @@ export async function fetchAgentStats(apiUrl: string, agentId: string): Promise<
   }
 }
 
+export interface ToolStats {
+  /** Tool name, or the base command for bash breakdowns */
+  name: string;
+  invocations: number;
+  successes: number;
+  errors: number;
+  denied: number;
+  avg_result_bytes: number;
+}
+
+export interface ToolStatsResponse {
+  days: number;
+  since: number;
+  tools: ToolStats[];
+  bash_commands: ToolStats[];
+}
+
+/**
+ * Fetch per-tool invocation, error and denial counts
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @param days - How many days back to count
+ * @returns Promise with tool statistics, most used first
+ */
+export async function fetchToolStats(apiUrl: string, days = 30): Promise<ToolStatsResponse> {
+  const path = `/api/stats/tools?days=${days}`;
+  const endpoint = apiUrl ? `${apiUrl}${path}` : path;
+  const response = await fetch(endpoint);
+
+  if (!response.ok) {
+    throw new Error(`Failed to fetch tool statistics: HTTP ${response.status}`);
+  }
+
+  const data: ToolStatsResponse = await response.json();
+  return data;
+}
+
 export interface AgentContentResponse {
   id: string;
   name: string;
@@ -694,6 +1204,8 @@ export interface ConfigResponse {
   rag_enabled: boolean;
   web_sounds: boolean;
   audio_enabled: boolean;
+  /** A SQUID.md or AGENTS.md from the project root is added to system prompts */
+  project_instructions: boolean;
 }
 
 /**
@@ -720,14 +1232,44 @@ export async function fetchConfig(apiUrl: string): Promise<ConfigResponse> {
   return data;
 }
 
+export interface VersionResponse {
+  version: string;
+  ui: 'embedded' | 'external'; // external: served from --static-dir / server.static_dir
+  static_dir?: string;
+}
+
+/**
+ * Fetch the server version and where the Web UI is served from
+ *
+ * @param apiUrl - The base URL of the Squid API. Use empty string '' for relative path (same origin)
+ * @returns Promise with version information
+ */
+export async function fetchVersion(apiUrl: string): Promise<VersionResponse> {
+  const endpoint = apiUrl ? `${apiUrl}/api/version` : '/api/version';
+  const response = await fetch(endpoint);
+
+  if (!response.ok) {
+    throw new Error(`Failed to fetch version: HTTP ${response.status}`);
+  }
+
+  const data: VersionResponse = await response.json();
+  return data;
+}
+
 /**
  * RAG-related API functions
  */
 
 export interface RagSource {
   filename: string;
+  /** Document title, the filename when the document has none */
+  title: string;
   text: string;
   relevance: number;
+  /** Chunk index range covered by `text`; equal unless adjacent chunks were merged */
+  chunk_start: number;
+  chunk_end: number;
+  merged: boolean;
 }
 
 export interface RagQueryResponse {
@@ -738,8 +1280,25 @@ export interface RagQueryResponse {
 export interface DocumentSummary {
   id: number;
   filename: string;
+  title: string;
   file_size: number;
   updated_at: number;
+  chunk_count: number;
+  embedding_count: number;
+}
+
+export interface DocumentChunk {
+  index: number;
+  tokens: number;
+  text: string;
+}
+
+export interface DocumentChunksResponse {
+  filename: string;
+  chunks: DocumentChunk[];
+  total: number;
+  page: number;
+  page_size: number;
 }
 
 export interface DocumentListResponse {
@@ -825,6 +1384,53 @@ export async function deleteRagDocument(apiUrl: string, filename: string): Promi
   return data;
 }
 
+/**
+ * List how a document was chunked
+ *
+ * @param apiUrl - The base URL of the Squid API
+ * @param filename - The indexed filename
+ * @param page - Page number (starting at 1)
+ * @param pageSize - Number of chunks per page
+ * @returns Promise with a page of chunks
+ */
+export async function getRagDocumentChunks(
+  apiUrl: string,
+  filename: string,
+  page = 1,
+  pageSize = 50
+): Promise<DocumentChunksResponse> {
+  const path = `/api/rag/documents/${encodeURIComponent(filename)}/chunks?page=${page}&page_size=${pageSize}`;
+  const response = await fetch(apiUrl ? `${apiUrl}${path}` : path);
+
+  if (!response.ok) {
+    throw new Error(`Failed to get document chunks: HTTP ${response.status}`);
+  }
+
+  const data: DocumentChunksResponse = await response.json();
+  return data;
+}
+
+/**
+ * Re-index a document, even if its content has not changed
+ *
+ * @param apiUrl - The base URL of the Squid API
+ * @param filename - The filename to re-index
+ * @returns Promise with success status
+ */
+export async function reindexRagDocument(apiUrl: string, filename: string): Promise<RagResponse> {
+  const path = `/api/rag/documents/${encodeURIComponent(filename)}/reindex`;
+  const response = await fetch(apiUrl ? `${apiUrl}${path}` : path, {
+    method: 'POST',
+  });
+
+  if (!response.ok) {
+    throw new Error(`Failed to re-index document: HTTP ${response.status}`);
+  }
+
+  const data: RagResponse = await response.json();
+  return data;
+}
+
 /**
  * Get RAG statistics
  *
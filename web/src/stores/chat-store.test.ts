@@ -1,6 +1,6 @@
 import { describe, it, expect, vi, beforeEach, afterEach } from 'vitest';
 import { useChatStore, type MessageType, type ToolApproval } from './chat-store';
-import { streamChat, loadSession, sendToolApproval } from '@/lib/chat-api';
+import { streamChat, cancelChatTurn, loadSession, sendToolApproval } from '@/lib/chat-api';
 import type { StreamHandlers, TokenUsage, AgentInfo, SessionData } from '@/lib/chat-api';
 import { toast } from 'sonner';
 import { useSessionStore } from './session-store';
@@ -8,6 +8,7 @@ import { useAgentStore } from './agent-store';
 
 vi.mock('@/lib/chat-api', () => ({
   streamChat: vi.fn(),
+  cancelChatTurn: vi.fn(),
   loadSession: vi.fn(),
   sendToolApproval: vi.fn(),
 }));
@@ -516,9 +517,17 @@ describe('useChatStore', () => {
       expect(abortController).toBeNull();
     });
 
+    it('cancels the turn of the active session on the server', () => {
+      mockSessionState.activeSessionId = 'session-1';
+      useChatStore.setState({ abortController: new AbortController() });
+      useChatStore.getState().stopStreaming();
+      expect(cancelChatTurn).toHaveBeenCalledWith('', 'session-1');
+    });
+
     it('does nothing when there is no active abort controller', () => {
       expect(() => useChatStore.getState().stopStreaming()).not.toThrow();
       expect(useChatStore.getState().status).toBe('ready');
+      expect(cancelChatTurn).not.toHaveBeenCalled();
     });
   });
 
@@ -752,6 +761,26 @@ describe('useChatStore', () => {
       expect(mockSessionState.setActiveSession).toHaveBeenCalledWith('new-session-id');
     });
 
+    // ── onUsage ──────────────────────────────────────────────────────────────
+
+    it('replaces live usage estimates with the reported usage', async () => {
+      mockAgentState.updateTokenUsage.mockImplementation((usage: Partial<TokenUsage>) => {
+        mockAgentState.tokenUsage = { ...mockAgentState.tokenUsage, ...usage };
+      });
+      const usage = { input_tokens: 0, reasoning_tokens: 0, cache_tokens: 0 };
+      mockStream((h) => {
+        h.onUsage?.({ ...usage, output_tokens: 10, estimated: true });
+        h.onUsage?.({ ...usage, output_tokens: 25, estimated: true });
+        expect(mockAgentState.tokenUsage.output_tokens).toBe(25);
+        h.onUsage?.({ ...usage, input_tokens: 11, output_tokens: 7, estimated: false });
+      });
+      await useChatStore.getState().streamResponse(MSG_ID, 'Hello');
+
+      expect(mockAgentState.tokenUsage.output_tokens).toBe(7);
+      expect(mockAgentState.tokenUsage.input_tokens).toBe(11);
+      expect(mockAgentState.tokenUsage.total_tokens).toBe(18);
+    });
+
     // ── onContent (plain text) ───────────────────────────────────────────────
 
     it('updates the message content with plain streamed text', async () => {
@@ -798,7 +827,7 @@ describe('useChatStore', () => {
     // ── onSources ────────────────────────────────────────────────────────────
 
     it('attaches sources to the matching message with href="#"', async () => {
-      mockStream((h) => h.onSources?.([{ title: 'Doc A', content: 'body' }]));
+      mockStream((h) => h.onSources?.([{ title: 'Doc A', content: 'body', origin: 'rag' }]));
       await useChatStore.getState().streamResponse(MSG_ID, 'Hello');
       const sources = useChatStore.getState().messages[0].sources;
       expect(sources).toHaveLength(1);
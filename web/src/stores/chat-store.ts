@@ -1,16 +1,35 @@
 import { create } from 'zustand';
 import { persist } from 'zustand/middleware';
 import type { FileUIPart } from 'ai';
-import { streamChat, loadSession, sendToolApproval, type Source } from '@/lib/chat-api';
+import {
+  streamChat,
+  cancelChatTurn,
+  loadSession,
+  sendToolApproval,
+  type FinishReason,
+  type SkipStatus,
+  type Source,
+  type ToolPreview,
+  type ToolRisk,
+} from '@/lib/chat-api';
 import { toast } from 'sonner';
 import { useSessionStore } from './session-store';
 import { useAgentStore } from './agent-store';
 
+/** Source title as shown in the UI, marking files the server attached automatically */
+function sourceTitle(source: Source): string {
+  return source.origin === 'auto' ? `${source.title} (auto-included)` : source.title;
+}
+
 export interface ToolApproval {
   approval_id: string;
   tool_name: string;
   tool_args: Record<string, unknown>;
   tool_description: string;
+  risk?: ToolRisk; // What the tool can do, used to color the prompt
+  preview?: ToolPreview; // Server-side summary (diff, resolved path, command)
+  directory?: string; // Set for reads that can be allowed for the whole directory
+  expires_at?: number; // Unix timestamp (seconds) when the server stops waiting
   message_id: string; // Associated message ID
   contentBeforeApproval?: string; // Content before the approval request
 }
@@ -34,10 +53,18 @@ export interface ToolStep {
   parameters: Record<string, unknown>;
   result: string | undefined;
   error: string | undefined;
-  contentBeforeTool?: string;
+  skipStatus?: SkipStatus; // Set when the tool was denied, rejected or timed out instead of run
+  contentDeltaBeforeTool?: string; // Content produced since the previous tool step
+  toolCallId?: string;
+  liveOutput?: string; // Output received while the tool is still running
+}
+
+export interface WarningStep {
+  type: 'warning';
+  content: string;
 }
 
-export type ThinkingStep = ReasoningStep | ToolStep;
+export type ThinkingStep = ReasoningStep | ToolStep | WarningStep;
 
 export interface MessageType {
   key: string;
@@ -50,6 +77,8 @@ export interface MessageType {
   // Chain-of-thought steps
   thinkingSteps?: ThinkingStep[];
   toolApprovals?: ToolApproval[];
+  // Why the model stopped; 'length' marks a truncated answer that can be continued
+  finishReason?: FinishReason;
 }
 
 interface ChatStore {
@@ -60,6 +89,7 @@ interface ChatStore {
   streamingContentRef: string;
   streamingReasoningRef: string;
   isReasoningStreaming: boolean;
+  isRetrieving: boolean; // Documents are being searched for the streaming answer
   abortController: AbortController | null;
   useWebSearch: boolean;
   useRag: boolean;
@@ -96,6 +126,7 @@ export const useChatStore = create<ChatStore>()(
       streamingContentRef: '',
       streamingReasoningRef: '',
       isReasoningStreaming: false,
+      isRetrieving: false,
       abortController: null,
       useWebSearch: false,
       useRag: false,
@@ -105,6 +136,8 @@ export const useChatStore = create<ChatStore>()(
 
       // Add user message and trigger streaming
       addUserMessage: (content: string, files?: FileUIPart[]) => {
+        // Sent as the idempotency key so a resubmitted request doesn't generate twice
+        const clientMessageId = `${Date.now()}-${Math.random().toString(36).slice(2)}`;
         const userMessage: MessageType = {
           from: 'user',
           key: `user-${Date.now()}`,
@@ -183,6 +216,7 @@ export const useChatStore = create<ChatStore>()(
           streamingContentRef: '',
           streamingReasoningRef: '',
           isReasoningStreaming: false,
+          isRetrieving: false,
         });
 
         const sessionStore = useSessionStore.getState();
@@ -219,6 +253,9 @@ export const useChatStore = create<ChatStore>()(
             }
           }
 
+          // Output tokens of the latest live estimate, replaced by the next usage event
+          let liveEstimate = 0;
+
           await streamChat(
             '',
             {
@@ -228,21 +265,41 @@ export const useChatStore = create<ChatStore>()(
               agent_id: agentStore.selectedAgent,
               use_rag: get().useRag || undefined,
               use_tools: get().useTools || undefined,
+              client_message_id: clientMessageId,
             },
             {
               signal: abortController.signal,
               onSession: (newSessionId) => {
                 sessionStore.setActiveSession(newSessionId);
               },
+              onRetrievalStarted: () => {
+                set({ isRetrieving: true });
+              },
+              onRetrievalFailed: (message) => {
+                const warningStep: ThinkingStep = {
+                  type: 'warning',
+                  content: `Document search failed, answering without documents: ${message}`,
+                };
+                set((state) => ({
+                  isRetrieving: false,
+                  messages: state.messages.map((msg) => {
+                    if (msg.versions.some((v) => v.id === messageId)) {
+                      return { ...msg, thinkingSteps: [...(msg.thinkingSteps || []), warningStep] };
+                    }
+                    return msg;
+                  }),
+                }));
+              },
               onSources: (sources: Source[]) => {
                 set((state) => ({
+                  isRetrieving: false,
                   messages: state.messages.map((msg) => {
                     if (msg.versions.some((v) => v.id === messageId)) {
                       return {
                         ...msg,
                         sources: sources.map((s) => ({
                           href: '#',
-                          title: s.title,
+                          title: sourceTitle(s),
                           content: s.content,
                         })),
                       };
@@ -254,7 +311,7 @@ export const useChatStore = create<ChatStore>()(
               onContent: (text) => {
                 const state = get();
                 const fullContent = state.streamingContentRef + text;
-                set({ streamingContentRef: fullContent });
+                set({ streamingContentRef: fullContent, isRetrieving: false });
 
                 // Parse out ALL <think> tags and build thinking steps
                 // Keep each <think> block as a SEPARATE step (don't merge)
@@ -397,33 +454,74 @@ export const useChatStore = create<ChatStore>()(
                 }));
               },
               onUsage: (usage) => {
+                const { tokenUsage } = useAgentStore.getState();
+                const outputTokens = usage.output_tokens - liveEstimate;
+                liveEstimate = usage.estimated ? usage.output_tokens : 0;
                 agentStore.updateTokenUsage({
                   total_tokens:
-                    agentStore.tokenUsage.total_tokens +
+                    tokenUsage.total_tokens +
                     usage.input_tokens +
-                    usage.output_tokens +
+                    outputTokens +
                     usage.reasoning_tokens +
                     usage.cache_tokens,
-                  input_tokens: agentStore.tokenUsage.input_tokens + usage.input_tokens,
-                  output_tokens: agentStore.tokenUsage.output_tokens + usage.output_tokens,
-                  reasoning_tokens: agentStore.tokenUsage.reasoning_tokens + usage.reasoning_tokens,
-                  cache_tokens: agentStore.tokenUsage.cache_tokens + usage.cache_tokens,
+                  input_tokens: tokenUsage.input_tokens + usage.input_tokens,
+                  output_tokens: tokenUsage.output_tokens + outputTokens,
+                  reasoning_tokens: tokenUsage.reasoning_tokens + usage.reasoning_tokens,
+                  cache_tokens: tokenUsage.cache_tokens + usage.cache_tokens,
                 });
               },
+              onToolOutputChunk: (chunk) => {
+                // Show output of a running tool in a pending step until it completes
+                set((state) => ({
+                  messages: state.messages.map((msg) => {
+                    if (msg.versions.some((v) => v.id === messageId)) {
+                      const currentSteps = msg.thinkingSteps || [];
+                      const lastStep = currentSteps[currentSteps.length - 1];
+                      if (lastStep?.type === 'tool' && lastStep.toolCallId === chunk.tool_call_id) {
+                        const updatedStep: ThinkingStep = {
+                          ...lastStep,
+                          liveOutput: `${lastStep.liveOutput ?? ''}${chunk.text}\n`,
+                        };
+                        return { ...msg, thinkingSteps: [...currentSteps.slice(0, -1), updatedStep] };
+                      }
+
+                      const runningStep: ThinkingStep = {
+                        type: 'tool',
+                        name: chunk.name,
+                        description: '',
+                        status: 'pending',
+                        parameters: {},
+                        result: undefined,
+                        error: undefined,
+                        toolCallId: chunk.tool_call_id,
+                        liveOutput: `${chunk.text}\n`,
+                      };
+                      return { ...msg, thinkingSteps: [...currentSteps, runningStep] };
+                    }
+                    return msg;
+                  }),
+                }));
+              },
               onToolInvocationCompleted: (tool) => {
                 // Add tool step to thinking steps immediately when it completes
                 set((state) => ({
                   messages: state.messages.map((msg) => {
                     if (msg.versions.some((v) => v.id === messageId)) {
-                      const currentSteps = msg.thinkingSteps || [];
+                      const lastStep = msg.thinkingSteps?.[msg.thinkingSteps.length - 1];
+                      // Replace the running step that showed this tool's live output
+                      const currentSteps =
+                        lastStep?.type === 'tool' && lastStep.status === 'pending' && lastStep.name === tool.name
+                          ? msg.thinkingSteps!.slice(0, -1)
+                          : msg.thinkingSteps || [];
                       const newToolStep: ThinkingStep = {
                         type: 'tool',
                         name: tool.name,
                         description: '',
-                        status: tool.error ? 'error' : 'completed',
+                        status: tool.status ? 'skipped' : tool.error ? 'error' : 'completed',
                         parameters: tool.arguments,
                         result: tool.result,
                         error: tool.error,
+                        skipStatus: tool.status,
                       };
 
                       return {
@@ -435,6 +533,18 @@ export const useChatStore = create<ChatStore>()(
                   }),
                 }));
               },
+              onWarning: (warning) => {
+                // Show the cut-off inline, where the model's next steps would have been
+                const warningStep: ThinkingStep = { type: 'warning', content: warning };
+                set((state) => ({
+                  messages: state.messages.map((msg) => {
+                    if (msg.versions.some((v) => v.id === messageId)) {
+                      return { ...msg, thinkingSteps: [...(msg.thinkingSteps || []), warningStep] };
+                    }
+                    return msg;
+                  }),
+                }));
+              },
               onToolApprovalRequest: (approval) => {
                 // Capture the current content at the time of approval request
                 const currentContent = get().streamingContentRef;
@@ -446,7 +556,15 @@ export const useChatStore = create<ChatStore>()(
                   contentBeforeApproval: currentContent,
                 });
               },
-              onToolApprovalResponse: (approval_id) => {
+              onToolApprovalResponse: (approval_id, _approved, timed_out) => {
+                if (timed_out) {
+                  const approval = get().pendingApprovals.get(approval_id);
+                  toast.warning('Tool approval expired', {
+                    description: approval
+                      ? `${approval.tool_name} was not run because no decision was made in time.`
+                      : 'The tool was not run because no decision was made in time.',
+                  });
+                }
                 // Clear the approval from pending
                 get().clearApproval(approval_id);
               },
@@ -462,7 +580,12 @@ export const useChatStore = create<ChatStore>()(
                   abortController: null,
                 });
               },
-              onDone: async () => {
+              onDone: async (finishReason) => {
+                set((state) => ({
+                  messages: state.messages.map((msg) =>
+                    msg.versions.some((v) => v.id === messageId) ? { ...msg, finishReason } : msg
+                  ),
+                }));
                 set({
                   streamingContentRef: '',
                   streamingReasoningRef: '',
@@ -516,6 +639,11 @@ export const useChatStore = create<ChatStore>()(
         const { abortController } = get();
         if (abortController) {
           abortController.abort();
+          // The server runs the turn on after a disconnect, so stop it there too
+          const sessionId = useSessionStore.getState().activeSessionId;
+          if (sessionId) {
+            void cancelChatTurn('', sessionId);
+          }
           set({
             abortController: null,
             status: 'ready',
@@ -555,11 +683,17 @@ export const useChatStore = create<ChatStore>()(
                 type: 'tool',
                 name: step.tool_name || '',
                 description: '',
-                status: step.tool_error ? 'error' : 'completed',
+                status: step.status ? 'skipped' : step.tool_error ? 'error' : 'completed',
                 parameters: typeof step.tool_arguments === 'object' ? step.tool_arguments : {},
                 result: step.tool_result,
                 error: step.tool_error,
-                contentBeforeTool: step.content_before_tool,
+                skipStatus: step.status,
+                contentDeltaBeforeTool: step.content_delta_before_tool,
+              });
+            } else if (step.step_type === 'warning') {
+              thinkingSteps.push({
+                type: 'warning',
+                content: step.content || '',
               });
             }
           });
@@ -571,7 +705,7 @@ export const useChatStore = create<ChatStore>()(
               msg.sources.length > 0
                 ? msg.sources.map((s) => ({
                     href: '#',
-                    title: s.title,
+                    title: sourceTitle(s),
                     content: s.content,
                   }))
                 : undefined,
@@ -582,6 +716,7 @@ export const useChatStore = create<ChatStore>()(
               },
             ],
             thinkingSteps: thinkingSteps.length > 0 ? thinkingSteps : undefined,
+            finishReason: msg.finish_reason,
           });
         }
 
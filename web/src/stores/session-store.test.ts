@@ -3,6 +3,7 @@ import { useSessionStore, type ChatSession } from './session-store';
 import {
   listSessions,
   deleteSession as apiDeleteSession,
+  bulkDeleteSessions as apiBulkDeleteSessions,
   updateSessionTitle as apiUpdateSessionTitle,
 } from '@/lib/chat-api';
 import type { SessionListItem } from '@/lib/chat-api';
@@ -11,6 +12,7 @@ import { toast } from 'sonner';
 vi.mock('@/lib/chat-api', () => ({
   listSessions: vi.fn(),
   deleteSession: vi.fn(),
+  bulkDeleteSessions: vi.fn(),
   updateSessionTitle: vi.fn(),
 }));
 
@@ -47,6 +49,9 @@ const makeApiSession = (overrides: Partial<SessionListItem> = {}): SessionListIt
   token_usage: TOKEN_USAGE,
   cost_usd: 0,
   is_readonly: false,
+  last_message_role: 'assistant',
+  last_message_at: UPDATED_AT_MS,
+  status: 'idle',
   ...overrides,
 });
 
@@ -402,6 +407,34 @@ describe('useSessionStore', () => {
 
   // ── deleteSession ──────────────────────────────────────────────────────────
 
+  describe('deleteSessions', () => {
+    beforeEach(() => {
+      useSessionStore.setState({ sessions: [SESSION_A, SESSION_B], activeSessionId: 'session-a' });
+    });
+
+    it('removes the deleted sessions and reports how many were deleted', async () => {
+      vi.mocked(apiBulkDeleteSessions).mockResolvedValueOnce(['session-a']);
+
+      const deleted = await useSessionStore.getState().deleteSessions({ untitled_only: true });
+
+      expect(deleted).toBe(1);
+      expect(vi.mocked(apiBulkDeleteSessions)).toHaveBeenCalledWith('', { untitled_only: true });
+      expect(useSessionStore.getState().sessions.map((s) => s.id)).toEqual(['session-b']);
+      expect(useSessionStore.getState().activeSessionId).toBeNull();
+      expect(vi.mocked(toast.success)).toHaveBeenCalledWith('Deleted 1 session');
+    });
+
+    it('keeps the sessions and shows an error when the request fails', async () => {
+      vi.mocked(apiBulkDeleteSessions).mockRejectedValueOnce(new Error('No ids or filters given'));
+
+      const deleted = await useSessionStore.getState().deleteSessions({});
+
+      expect(deleted).toBe(0);
+      expect(useSessionStore.getState().sessions).toHaveLength(2);
+      expect(vi.mocked(toast.error)).toHaveBeenCalledWith('Failed to delete sessions');
+    });
+  });
+
   describe('deleteSession', () => {
     beforeEach(() => {
       useSessionStore.setState({ sessions: [SESSION_A, SESSION_B], activeSessionId: null });
@@ -1,5 +1,5 @@
 import { create } from 'zustand';
-import { listSessions, deleteSession as apiDeleteSession, updateSessionTitle as apiUpdateSessionTitle, subscribeToSessionUpdates, type SessionListItem } from '@/lib/chat-api';
+import { listSessions, deleteSession as apiDeleteSession, bulkDeleteSessions as apiBulkDeleteSessions, updateSessionTitle as apiUpdateSessionTitle, subscribeToSessionUpdates, type SessionDeleteFilter, type SessionListItem } from '@/lib/chat-api';
 import { toast } from 'sonner';
 
 export interface ChatSession {
@@ -26,6 +26,7 @@ interface SessionStore {
   startNewChat: () => void;
   refreshSessions: () => Promise<void>;
   deleteSession: (sessionId: string) => Promise<boolean>;
+  deleteSessions: (filter: SessionDeleteFilter) => Promise<number>;
   updateSessionTitle: (sessionId: string, title: string) => Promise<boolean>;
   updateSession: (session: SessionListItem) => void;
   removeSession: (sessionId: string) => void;
@@ -119,6 +120,29 @@ export const useSessionStore = create<SessionStore>((set, get) => ({
     }
   },
 
+  // Delete sessions by id or filter
+  deleteSessions: async (filter: SessionDeleteFilter) => {
+    try {
+      const deletedIds = await apiBulkDeleteSessions('', filter);
+      const deleted = new Set(deletedIds);
+      set((state) => ({
+        sessions: state.sessions.filter((s) => !deleted.has(s.id)),
+      }));
+
+      const { activeSessionId } = get();
+      if (activeSessionId && deleted.has(activeSessionId)) {
+        get().startNewChat();
+      }
+
+      toast.success(`Deleted ${deletedIds.length} session${deletedIds.length === 1 ? '' : 's'}`);
+      return deletedIds.length;
+    } catch (error) {
+      console.error('Failed to delete sessions:', error);
+      toast.error('Failed to delete sessions');
+      return 0;
+    }
+  },
+
   // Update session title
   updateSessionTitle: async (sessionId: string, title: string) => {
     try {